@@ -1,6 +1,9 @@
 CREATE TABLE metadata_analysis.total_package_downloads AS
 
+-- packages that haven't been backfilled yet still have legacy weekly counts
 with unnested_counts as (
+  select package_id, count as counter from download_counts_daily
+  union all
   select package_id, (unnest(download_counts)).counter from download_metrics
 )
 
@@ -1,55 +1,54 @@
-CREATE OR REPLACE FUNCTION metadata_analysis.non_beta_semver(semver) RETURNS semver AS $$
-SELECT ROW(($1).major, ($1).minor, ($1).bug, NULL, NULL)::semver $$ LANGUAGE SQL IMMUTABLE;
-
-
-
-
-CREATE OR REPLACE FUNCTION metadata_analysis.less_than_semver(semver_struct, semver_struct, bool) RETURNS bool AS $$
+-- Whether a release (no prerelease or build) satisfies a comparator. Comparators can still have a
+-- prerelease, e.g. npm turns `< 1.2` into `< 1.2.0-0`. No release is between 1.2.0-0 and 1.2.0,
+-- so against releases, `< 1.2.0-0` is the same as `< 1.2.0` and `> 1.2.0-0` as `>= 1.2.0`.
+CREATE OR REPLACE FUNCTION metadata_analysis.release_satisfies_comparator(semver, version_comparator_struct) RETURNS bool AS $$
 SELECT CASE
-        WHEN $1 IS NULL
-        OR $2 IS NULL THEN TRUE
-        WHEN $3 THEN $1 <= $2
-        ELSE $1 < $2
-    END $$ LANGUAGE SQL IMMUTABLE;
-
+        WHEN ($2).operator = '*' THEN TRUE
+        WHEN (($2).semver).prerelease IS NULL THEN CASE
+            ($2).operator
+            WHEN '=' THEN v = b
+            WHEN '>' THEN v > b
+            WHEN '>=' THEN v >= b
+            WHEN '<' THEN v < b
+            WHEN '<=' THEN v <= b
+        END
+        ELSE CASE
+            ($2).operator
+            WHEN '=' THEN FALSE
+            WHEN '>' THEN v >= b
+            WHEN '>=' THEN v >= b
+            WHEN '<' THEN v < b
+            WHEN '<=' THEN v < b
+        END
+    END
+FROM (
+        SELECT ROW(($1).major, ($1).minor, ($1).bug) AS v,
+            ROW((($2).semver).major, (($2).semver).minor, (($2).semver).bug) AS b
+    ) AS parts $$ LANGUAGE SQL IMMUTABLE;
 
-CREATE OR REPLACE FUNCTION metadata_analysis.matches_range_non_beta(semver, semver_struct, bool, semver_struct, bool) RETURNS bool AS $$
-SELECT metadata_analysis.less_than_semver($2, $1, $3)
-    AND metadata_analysis.less_than_semver($1, $4, $5)
-END $$ LANGUAGE SQL IMMUTABLE;
 
-            
-    
-CREATE OR REPLACE FUNCTION metadata_analysis.matches_range(semver, semver_struct, bool, semver_struct, bool) RETURNS bool AS $$
-SELECT CASE
-        WHEN NOT $2 IS NULL
-        AND (
-            ($2).prerelease IS NOT NULL
-            OR ($2).build IS NOT NULL
-        )
-        AND NOT $4 IS NULL
-        AND (
-            ($4).prerelease IS NOT NULL
-            OR ($4).build IS NOT NULL
-        ) THEN metadata_analysis.matches_range_non_beta(
-            $1,
-            metadata_analysis.non_beta_semver($2),
-            TRUE,
-            metadata_analysis.non_beta_semver($4),
-            FALSE
-        )
-        WHEN NOT $2 IS NULL
-        AND (
-            ($2).prerelease IS NOT NULL
-            OR ($2).build IS NOT NULL
-        ) THEN metadata_analysis.matches_range_non_beta($1, metadata_analysis.non_beta_semver($2), TRUE, $4, $5)
-        WHEN NOT $4 IS NULL
-        AND (
-            ($4).prerelease IS NOT NULL
-            OR ($4).build IS NOT NULL
-        ) THEN metadata_analysis.matches_range_non_beta($1, $2, $3, metadata_analysis.non_beta_semver($4), FALSE)
-        ELSE metadata_analysis.matches_range_non_beta($1, $2, $3, $4, $5)
-    END $$ LANGUAGE SQL IMMUTABLE;
+-- Whether a release satisfies any of the disjuncts of a constraint, i.e. all comparators in it.
+CREATE OR REPLACE FUNCTION metadata_analysis.release_satisfies_constraint(semver, constraint_disjuncts) RETURNS bool AS $$
+DECLARE
+    disjunct constraint_conjuncts_struct;
+    comparator version_comparator_struct;
+    satisfied bool;
+BEGIN
+    FOREACH disjunct IN ARRAY $2::constraint_conjuncts_struct[] LOOP
+        satisfied := TRUE;
+        FOREACH comparator IN ARRAY (disjunct).conjuncts::version_comparator_struct[] LOOP
+            IF NOT metadata_analysis.release_satisfies_comparator($1, comparator) THEN
+                satisfied := FALSE;
+                EXIT;
+            END IF;
+        END LOOP;
+        IF satisfied THEN
+            RETURN TRUE;
+        END IF;
+    END LOOP;
+    RETURN FALSE;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
 
 
 CREATE TABLE metadata_analysis.vulnerable_versions AS
@@ -60,15 +59,12 @@ from versions vers
     and (vers.semver).prerelease IS NULL
     and (vers.semver).build IS NULL
     inner join vulnerabilities vuln on vuln.package_name = pkg.name
-    and metadata_analysis.matches_range(
+    and metadata_analysis.release_satisfies_constraint(
         vers.semver,
-        vuln.vulnerable_version_lower_bound,
-        vuln.vulnerable_version_lower_bound_inclusive,
-        vuln.vulnerable_version_upper_bound,
-        vuln.vulnerable_version_upper_bound_inclusive
+        vuln.vulnerable_version_constraint
     );
 
 
 GRANT SELECT ON metadata_analysis.vulnerable_versions TO data_analyzer;
 GRANT ALL ON metadata_analysis.vulnerable_versions TO pinckney;
-GRANT ALL ON metadata_analysis.vulnerable_versions TO federico;
\ No newline at end of file
+GRANT ALL ON metadata_analysis.vulnerable_versions TO federico;
@@ -19,6 +19,7 @@ use tokio::{
     sync::Semaphore,
     task::JoinHandle,
 };
+use tracing::Instrument;
 
 fn spawn_keep_alive_loop(file_id: u32) -> JoinHandle<()> {
     tokio::task::spawn(async move {
@@ -28,7 +29,7 @@ fn spawn_keep_alive_loop(file_id: u32) -> JoinHandle<()> {
         let client = reqwest::Client::new();
         loop {
             tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-            eprintln!("Sending keep-alive request");
+            tracing::debug!(file_id, "Sending keep-alive request");
 
             let res = client
                 .post(format!("{}/blob/keep_alive_lock", blob_api_url))
@@ -62,9 +63,10 @@ async fn check_req_failed(resp: reqwest::Response) -> Result<reqwest::Response,
         // try to parse into a BlobError, or send HttpServerError
         let mut err_map = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&text)
             .map_err(|_| ClientError::SerdeJsonError(format!("Failed to parse error: {}", text)))?;
-        eprintln!(
-            "Failed request. Got: {}",
-            err_map.get("error").unwrap_or(&serde_json::Value::Null)
+        tracing::warn!(
+            %status,
+            error = %err_map.get("error").unwrap_or(&serde_json::Value::Null),
+            "Request failed"
         );
         if let Some(err) = err_map.remove("error") {
             let blob_err = serde_json::from_value::<blob_idx_server::errors::BlobError>(err)?;
@@ -87,7 +89,7 @@ async fn read_slice(tarball_url_key: String) -> Result<BlobStorageSlice, ClientE
         .map_err(|e| ClientError::ReqwestError(format!("Failed to build client: {}", e)))?;
 
     // lookup request
-    eprintln!("Sending lookup request for {}", tarball_url_key);
+    tracing::debug!(blob_key = %tarball_url_key, "Sending lookup request");
     let body = serde_json::to_vec(&LookupRequest {
         key: tarball_url_key,
     })?;
@@ -114,7 +116,7 @@ pub async fn compute_run_bin(
     args: Vec<String>,
 ) -> Result<HashMap<String, TarballResult>, ClientError> {
     if args.len() != 4 {
-        eprintln!(
+        tracing::error!(
             "Usage: {} compute <binary path> <tarball keys, separated by spaces>",
             args[0]
         );
@@ -133,6 +135,7 @@ pub async fn compute_run_bin(
         let atomic_idx = Arc::new(AtomicUsize::new(0));
         for tarball_url_key in tarball_url_keys.clone() {
             let atomic_idx = atomic_idx.clone();
+            let span = tracing::info_span!("blob", blob_key = %tarball_url_key);
             let handle = tokio::task::spawn(async move {
                 let slice_path = read_and_send(
                     tarball_url_key.clone(),
@@ -142,6 +145,7 @@ pub async fn compute_run_bin(
                         atomic_idx.fetch_add(1, Ordering::SeqCst)
                     ),
                 )
+                .instrument(span)
                 .await?;
                 Ok((tarball_url_key, slice_path))
             });
@@ -208,7 +212,7 @@ pub async fn compute_run_bin(
             .output()
             .await
             .ok();
-        eprintln!("Deleting {}", p.to_str().unwrap());
+        tracing::debug!(dir = %p.display(), "Deleting the temp directory");
         tokio::fs::remove_dir_all(p).await.ok();
     }
 
@@ -219,7 +223,7 @@ pub async fn compute_run_bin_multi(
     args: Vec<String>,
 ) -> Result<HashMap<String, TarballResult>, ClientError> {
     if args.len() != 4 {
-        eprintln!(
+        tracing::error!(
             "Usage: {} compute <binary path> <tarball keys, separated by spaces>",
             args[0]
         );
@@ -245,6 +249,7 @@ pub async fn compute_run_bin_multi(
                 let mut slice_paths = Vec::new();
                 let mut original_tarball_urls = Vec::new();
                 for tarball_url_key in tarball_url_keys {
+                    let span = tracing::info_span!("blob", blob_key = %tarball_url_key);
                     let slice_path = read_and_send(
                         tarball_url_key.clone(),
                         &format!(
@@ -253,6 +258,7 @@ pub async fn compute_run_bin_multi(
                             atomic_idx.fetch_add(1, Ordering::SeqCst)
                         ),
                     )
+                    .instrument(span)
                     .await?;
                     slice_paths.push(slice_path);
                     original_tarball_urls.push(tarball_url_key);
@@ -262,14 +268,14 @@ pub async fn compute_run_bin_multi(
             handles.push(handle);
         }
 
-        eprintln!("Waiting for all slices to be read and sent");
+        tracing::debug!("Waiting for all slices to be read and sent");
 
         for handle in handles {
             let (slice_paths, tarball_urls) = handle.await.unwrap()?;
             slice_map.insert(slice_paths, tarball_urls);
         }
 
-        eprintln!("All slices read and sent");
+        tracing::debug!("All slices read and sent");
 
         // check that the binary exists
         if !std::path::Path::new(&binary).exists() {
@@ -300,7 +306,7 @@ pub async fn compute_run_bin_multi(
         let mut res_map = HashMap::new();
         for (original_tarball_url, handle) in handle_map {
             let res = handle.await.unwrap()?;
-            eprintln!("Got result for {}", original_tarball_url);
+            tracing::debug!(blob_keys = %original_tarball_url, "Got result");
             res_map.insert(original_tarball_url, res);
         }
         Ok(res_map)
@@ -327,16 +333,20 @@ pub async fn compute_run_bin_multi(
             .output()
             .await
             .ok();
-        eprintln!("Deleting {}", p.to_str().unwrap());
+        tracing::debug!(dir = %p.display(), "Deleting the temp directory");
         tokio::fs::remove_dir_all(p).await.ok();
     }
 
     res
 }
 
-async fn read_and_send(tarball_key: String, tmp_dir_root: &str) -> Result<String, ClientError> {
+/// Looks up the blob with the given key, and opens a reader over its bytes
+/// in the blob storage directory.
+pub async fn open_blob(
+    tarball_key: String,
+) -> Result<(BlobStorageSlice, tokio::io::Take<tokio::fs::File>), ClientError> {
     let blob_storage_dir = std::env::var("BLOB_STORAGE_DIR").expect("BLOB_STORAGE_DIR must be set");
-    let slice = read_slice(tarball_key.to_string()).await?;
+    let slice = read_slice(tarball_key).await?;
 
     let mut file =
         tokio::fs::File::open(format!("{}/{}", blob_storage_dir, slice.file_name)).await?;
@@ -344,6 +354,13 @@ async fn read_and_send(tarball_key: String, tmp_dir_root: &str) -> Result<String
     file.seek(std::io::SeekFrom::Start(slice.byte_offset))
         .await?;
 
+    let num_bytes = slice.num_bytes;
+    Ok((slice, file.take(num_bytes)))
+}
+
+async fn read_and_send(tarball_key: String, tmp_dir_root: &str) -> Result<String, ClientError> {
+    let (slice, mut file) = open_blob(tarball_key).await?;
+
     // read slice.num_bytes from file. make into base64.
     let mut buf = vec![0; slice.num_bytes as usize];
     file.read_exact(&mut buf).await?;
@@ -364,7 +381,7 @@ async fn read_and_send(tarball_key: String, tmp_dir_root: &str) -> Result<String
         temp_dir_path.join(format!("blob-file-{}-{}", pid, slurm_job_id))
     };
 
-    eprintln!("Writing to temp file: {}", temp_file_path.display());
+    tracing::debug!(path = %temp_file_path.display(), "Writing to temp file");
 
     // write to temp file
     let mut file = tokio::fs::File::create(&temp_file_path).await?;
@@ -375,18 +392,21 @@ async fn read_and_send(tarball_key: String, tmp_dir_root: &str) -> Result<String
 
 pub async fn read_and_send_main(args: Vec<String>) -> Result<String, ClientError> {
     if args.len() != 3 {
-        eprintln!("Usage: {} read <tarball url key>", args[0]);
+        tracing::error!("Usage: {} read <tarball url key>", args[0]);
         std::process::exit(1);
     }
 
     let tarball_url_key = &args[2];
     let tmp_dir_root = format!("/scratch/{}", std::env::var("USER").unwrap());
-    read_and_send(tarball_url_key.to_string(), &tmp_dir_root).await
+    let span = tracing::info_span!("blob", blob_key = %tarball_url_key);
+    read_and_send(tarball_url_key.to_string(), &tmp_dir_root)
+        .instrument(span)
+        .await
 }
 
 pub async fn cp_main(args: Vec<String>) -> Result<(), ClientError> {
     if args.len() != 4 {
-        eprintln!("Usage: {} cp <tarball url key> <destination path>", args[0]);
+        tracing::error!("Usage: {} cp <tarball url key> <destination path>", args[0]);
         std::process::exit(1);
     }
 
@@ -410,7 +430,7 @@ pub async fn cp_main(args: Vec<String>) -> Result<(), ClientError> {
 
 pub async fn download_and_write(args: Vec<String>) -> Result<(), ClientError> {
     if args.len() != 4 {
-        eprintln!(
+        tracing::error!(
             "Usage: {} write <discovery node id> <tarball urls, separated by spaces>",
             args[0]
         );
@@ -434,7 +454,7 @@ pub async fn download_and_write(args: Vec<String>) -> Result<(), ClientError> {
         let client = client.clone();
         handles.push(tokio::task::spawn(async move {
             let _permit = sem.acquire().await.unwrap();
-            eprintln!("Downloading {}", url);
+            tracing::info!(%url, "Downloading");
             let mut resp = match client.get(&url).send().await {
                 Ok(r) => r,
                 Err(_) => return Err((url, std::num::NonZeroU16::new(1337).unwrap())),
@@ -602,7 +622,7 @@ async fn store_into_blob(
 
 pub async fn store_from_local(args: Vec<String>) -> Result<(), ClientError> {
     if args.len() != 4 {
-        eprintln!(
+        tracing::error!(
             "Usage: {} store <discovery node id> <tarball filepaths, separated by spaces>",
             args[0]
         );
@@ -14,6 +14,7 @@ async fn main() {
     // The .secret.env has higher priority than .env, so we load it first
     dotenvy::from_filename(".secret.env").expect("failed to load .secret.env. Please create it");
     dotenvy::dotenv().ok();
+    let _tracing = utils::init_tracing_stderr("blob_idx_client");
 
     let args: Vec<String> = std::env::args().collect();
     // args[1] is either "write" or "read"
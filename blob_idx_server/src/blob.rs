@@ -239,7 +239,7 @@ impl BlobStorage {
                 tokio::select! {
                     _ = timer.tick() => { // 10 min timer expired
                         if let Some((_, file_lock)) = locked_files.remove(&file_id) {
-                            println!("cleaning up lock for file {}", file_id);
+                            tracing::info!(file_id, "Cleaning up expired lock");
                             file_lock.notify_unlock.notify_waiters();
                             keep_alive_ch.close();
                             return;
@@ -251,7 +251,7 @@ impl BlobStorage {
                     }
                     Some(()) = keep_alive_ch.recv() => {
                         // reset the timer
-                        println!("resetting timer for file {}", file_id);
+                        tracing::debug!(file_id, "Resetting lock timer");
                         timer.reset();
                     }
                 }
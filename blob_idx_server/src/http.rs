@@ -4,12 +4,15 @@ use std::{
     str::FromStr,
     sync::Arc,
     task::{Context, Poll},
+    time::Instant,
 };
 
 use futures::Future;
 use hyper::{service::Service, Body, Request, Response, Server};
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tracing::Instrument;
 
 use crate::{
     blob::{BlobStorage, BlobStorageConfig},
@@ -23,6 +26,7 @@ pub struct HTTP {
     host: String,
     port: String,
     api_key: String,
+    metrics_logger: MetricsLogger,
 }
 
 impl HTTP {
@@ -31,9 +35,16 @@ impl HTTP {
             host,
             port,
             api_key,
+            metrics_logger: MetricsLogger::null(),
         }
     }
 
+    /// Logs an event for every request. By default nothing is logged.
+    pub fn with_metrics_logger(mut self, metrics_logger: MetricsLogger) -> Self {
+        self.metrics_logger = metrics_logger;
+        self
+    }
+
     pub async fn start(
         self,
         blob_config: BlobStorageConfig,
@@ -55,9 +66,10 @@ impl HTTP {
             blob,
             job_manager,
             api_key: self.api_key,
+            metrics_logger: self.metrics_logger,
         });
 
-        println!("Listening on http://{addr}");
+        tracing::info!("Listening on http://{addr}");
 
         server.with_graceful_shutdown(shutdown_signal).await?;
         Ok(())
@@ -69,6 +81,7 @@ struct Svc {
     blob_store: Arc<BlobStorage>,
     job_manager: Option<Arc<JobManager>>,
     api_key: String,
+    metrics_logger: MetricsLogger,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -170,6 +183,10 @@ impl Service<Request<Body>> for Svc {
         let blob_store = self.blob_store.clone();
         let job_manager = self.job_manager.clone();
         let api_key = self.api_key.clone();
+        let mut metrics_logger = self.metrics_logger.clone();
+        let route = format!("{} {}", req.method(), req.uri().path());
+        let start = Instant::now();
+        let span = tracing::info_span!("request", route = %route);
         // routes:
         //  - POST:
         //     - /blob/create_and_lock
@@ -188,70 +205,93 @@ impl Service<Request<Body>> for Svc {
         //     - /blob/lookup
         //       - body: { "key": "some_key" }
         //       - returns: BlobStorageSlice or error
-        Box::pin(async move {
-            let thunk = async move {
-                // get the body
-                let body = hyper::body::to_bytes(req.body_mut()).await?;
-                let body = String::from_utf8(body.to_vec()).expect("invalid utf8");
-
-                // get auth header
-                let auth_header = req.headers().get("Authorization");
-
-                // check api key equals to the one in the header
-                if auth_header.is_none()
-                    || auth_header.unwrap().to_str().is_err()
-                    || auth_header.unwrap().to_str().unwrap() != api_key
-                {
-                    return Err(HTTPError::InvalidKey);
-                }
-
-                // get the method
-                let method = req.method().to_string();
-                // get the path
-                let path = req.uri().path().to_string();
-                let path = path.trim_start_matches('/').to_string();
-                match method.as_str() {
-                    "POST" => match path.as_str() {
-                        "blob/create_and_lock" => {
-                            routes::blob::create_and_lock(blob_store, try_from_str(&body)?).await
-                        }
-                        "blob/create_unlock" => {
-                            routes::blob::create_unlock(blob_store, try_from_str(&body)?).await
-                        }
-                        "blob/keep_alive_lock" => {
-                            routes::blob::keep_alive_lock(blob_store, try_from_str(&body)?).await
-                        }
-                        "job/submit" => match job_manager {
-                            Some(man) => routes::job::submit_job(man, try_from_str(&body)?).await,
-                            None => Err(HTTPError::Job(JobError::NoJobManager)),
+        Box::pin(
+            async move {
+                let thunk = async move {
+                    // get the body
+                    let body = hyper::body::to_bytes(req.body_mut()).await?;
+                    let body = String::from_utf8(body.to_vec()).expect("invalid utf8");
+
+                    // get auth header
+                    let auth_header = req.headers().get("Authorization");
+
+                    // check api key equals to the one in the header
+                    if auth_header.is_none()
+                        || auth_header.unwrap().to_str().is_err()
+                        || auth_header.unwrap().to_str().unwrap() != api_key
+                    {
+                        return Err(HTTPError::InvalidKey);
+                    }
+
+                    // get the method
+                    let method = req.method().to_string();
+                    // get the path
+                    let path = req.uri().path().to_string();
+                    let path = path.trim_start_matches('/').to_string();
+                    match method.as_str() {
+                        "POST" => match path.as_str() {
+                            "blob/create_and_lock" => {
+                                routes::blob::create_and_lock(blob_store, try_from_str(&body)?)
+                                    .await
+                            }
+                            "blob/create_unlock" => {
+                                routes::blob::create_unlock(blob_store, try_from_str(&body)?).await
+                            }
+                            "blob/keep_alive_lock" => {
+                                routes::blob::keep_alive_lock(blob_store, try_from_str(&body)?)
+                                    .await
+                            }
+                            "job/submit" => match job_manager {
+                                Some(man) => {
+                                    routes::job::submit_job(man, try_from_str(&body)?).await
+                                }
+                                None => Err(HTTPError::Job(JobError::NoJobManager)),
+                            },
+                            p => Err(HTTPError::InvalidPath(p.to_string())),
                         },
-                        p => Err(HTTPError::InvalidPath(p.to_string())),
-                    },
-                    "GET" => match path.as_str() {
-                        "blob/lookup" => {
-                            routes::blob::lookup(blob_store, try_from_str(&body)?).await
-                        }
-                        p => Err(HTTPError::InvalidPath(p.to_string())),
-                    },
-                    _ => Err(HTTPError::InvalidMethod(method)),
-                }
-            };
-            match thunk.await {
-                Ok(s) => mk_res(s),
-                Err(HTTPError::Blob(e)) => {
-                    let json_val = serde_json::to_value(e).unwrap();
-                    mk_error(json!({ "error": json_val }).to_string(), 400)
-                }
-                Err(HTTPError::Job(JobError::ClientError(e))) => {
-                    let json_val = serde_json::to_value(e).unwrap();
-                    mk_error(json!({ "error": json_val }).to_string(), 400)
+                        "GET" => match path.as_str() {
+                            "blob/lookup" => {
+                                routes::blob::lookup(blob_store, try_from_str(&body)?).await
+                            }
+                            p => Err(HTTPError::InvalidPath(p.to_string())),
+                        },
+                        _ => Err(HTTPError::InvalidMethod(method)),
+                    }
+                };
+                let res = thunk.await;
+
+                let kind = match res {
+                    Ok(_) => EventKind::Progress,
+                    Err(_) => EventKind::Error,
+                };
+                let mut event = MetricsEvent::new("blob_idx_server", kind)
+                    .count("requests", 1)
+                    .duration("duration", start.elapsed())
+                    .text("route", route);
+                if let Err(e) = &res {
+                    tracing::error!(error = %e, "Request failed");
+                    event = event.text("message", e.to_string());
                 }
-                Err(HTTPError::Job(e)) => {
-                    mk_error(json!({"error": e.to_string()}).to_string(), 400)
+                metrics_logger.log_event(event);
+
+                match res {
+                    Ok(s) => mk_res(s),
+                    Err(HTTPError::Blob(e)) => {
+                        let json_val = serde_json::to_value(e).unwrap();
+                        mk_error(json!({ "error": json_val }).to_string(), 400)
+                    }
+                    Err(HTTPError::Job(JobError::ClientError(e))) => {
+                        let json_val = serde_json::to_value(e).unwrap();
+                        mk_error(json!({ "error": json_val }).to_string(), 400)
+                    }
+                    Err(HTTPError::Job(e)) => {
+                        mk_error(json!({"error": e.to_string()}).to_string(), 400)
+                    }
+                    Err(e) => mk_error(json!({"error": e.to_string()}).to_string(), 500),
                 }
-                Err(e) => mk_error(json!({"error": e.to_string()}).to_string(), 500),
             }
-        })
+            .instrument(span),
+        )
     }
 }
 
@@ -314,6 +354,7 @@ mod routes {
             blob: Arc<BlobStorage>,
             body: LookupRequest,
         ) -> Result<String, HTTPError> {
+            tracing::debug!(blob_key = %body.key, "Looking up blob");
             let res = blob.lookup(body.key).await?;
             Ok(serde_json::to_string(&res)?)
         }
@@ -322,6 +363,7 @@ mod routes {
             blob: Arc<BlobStorage>,
             body: KeepAliveLockRequest,
         ) -> Result<String, HTTPError> {
+            tracing::debug!(file_id = body.file_id, "Keeping lock alive");
             blob.keep_alive_lock(body.file_id).await?;
             Ok("".to_string())
         }
@@ -330,6 +372,7 @@ mod routes {
             blob: Arc<BlobStorage>,
             body: CreateUnlockRequest,
         ) -> Result<String, HTTPError> {
+            tracing::debug!(file_id = body.file_id, node_id = %body.node_id, "Unlocking file");
             blob.create_unlock(body.file_id, body.node_id).await?;
             Ok("".to_string())
         }
@@ -351,6 +394,7 @@ struct MakeSvc {
     blob: Arc<BlobStorage>,
     job_manager: Option<Arc<JobManager>>,
     api_key: String,
+    metrics_logger: MetricsLogger,
 }
 
 impl From<MakeSvc> for Svc {
@@ -359,6 +403,7 @@ impl From<MakeSvc> for Svc {
             blob_store: m.blob,
             job_manager: m.job_manager,
             api_key: m.api_key,
+            metrics_logger: m.metrics_logger,
         }
     }
 }
@@ -2,9 +2,9 @@ use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
+use tracing::{debug, field::Empty, Instrument, Span};
 
 use crate::{
-    debug,
     errors::{ClientError, JobError},
     job::worker::WorkerStatus,
     ssh::SshFactory,
@@ -72,7 +72,7 @@ impl JobManager {
             .await
             .expect("populate worker pool failed");
 
-        println!("Job manager initialized");
+        tracing::info!("Job manager initialized");
 
         Self {
             xfer_pool,
@@ -81,9 +81,11 @@ impl JobManager {
     }
 
     /// Submits a download and write job to the discovery cluster.
+    #[tracing::instrument(skip_all, fields(num_urls = urls.len(), job_id = Empty))]
     pub async fn submit_download_job(&self, urls: Vec<String>) -> Result<(), JobError> {
-        debug!("Submitting download job with {} urls", urls.len());
+        debug!("Submitting download job");
         let worker = self.xfer_pool.get_worker().await?;
+        Span::current().record("job_id", worker.job_id);
 
         let (node_id, ssh) = match &*worker.status {
             WorkerStatus::Running {
@@ -119,9 +121,11 @@ impl JobManager {
     /// Submits a read job to the discovery cluster. Returns the data in base64 format.
     /// This should not be used for computation, just for situational retrieval
     /// of data.
+    #[tracing::instrument(skip(self), fields(job_id = Empty))]
     pub async fn submit_read_job(&self, key: String) -> Result<String, JobError> {
-        debug!("Submitting read job with key {}", key);
+        debug!("Submitting read job");
         let worker = self.xfer_pool.get_worker().await?;
+        Span::current().record("job_id", worker.job_id);
         let ssh = worker.get_ssh_session();
 
         let cmd = format!(
@@ -158,35 +162,40 @@ impl JobManager {
         let mut handles: Vec<JoinHandle<Result<ClientResponse, JobError>>> = Vec::new();
 
         for chunk in &tarball_chunks {
-            debug!("Submitting compute job with {} tarballs", chunk.len());
             let wp_comp = self.compute_pool.clone();
             let binary = binary.clone();
             let tbs = chunk.join(" ");
-            handles.push(tokio::task::spawn(async move {
-                let worker = wp_comp.get_worker().await?;
-                let ssh = worker.get_ssh_session();
-                let cmd = format!(
-                    "cd $HOME/npm-follower/blob_idx_client && ./run.sh compute {} \"{}\"",
-                    binary, tbs
-                );
-                debug!("Running command:\n{}", cmd);
-                let out = match tokio::time::timeout(
-                    std::time::Duration::from_secs(timeout),
-                    ssh.run_command(&cmd),
-                )
-                .await
-                {
-                    Ok(res) => res?,
-                    Err(_) => {
-                        wp_comp.replace_worker(&worker).await?;
-                        return Ok(ClientResponse::Error(ClientError::Timeout));
-                    }
-                };
-                debug!("Output:\n{}", out);
-                let response: ClientResponse = serde_json::from_str(&out)
-                    .map_err(|_| JobError::ClientOutputNotParsable(out))?;
-                Ok(response)
-            }));
+            let span =
+                tracing::info_span!("compute_job", num_tarballs = chunk.len(), job_id = Empty);
+            handles.push(tokio::task::spawn(
+                async move {
+                    let worker = wp_comp.get_worker().await?;
+                    Span::current().record("job_id", worker.job_id);
+                    let ssh = worker.get_ssh_session();
+                    let cmd = format!(
+                        "cd $HOME/npm-follower/blob_idx_client && ./run.sh compute {} \"{}\"",
+                        binary, tbs
+                    );
+                    debug!("Running command:\n{}", cmd);
+                    let out = match tokio::time::timeout(
+                        std::time::Duration::from_secs(timeout),
+                        ssh.run_command(&cmd),
+                    )
+                    .await
+                    {
+                        Ok(res) => res?,
+                        Err(_) => {
+                            wp_comp.replace_worker(&worker).await?;
+                            return Ok(ClientResponse::Error(ClientError::Timeout));
+                        }
+                    };
+                    debug!("Output:\n{}", out);
+                    let response: ClientResponse = serde_json::from_str(&out)
+                        .map_err(|_| JobError::ClientOutputNotParsable(out))?;
+                    Ok(response)
+                }
+                .instrument(span),
+            ));
         }
 
         let mut responses = Vec::new();
@@ -212,7 +221,6 @@ impl JobManager {
         let mut handles: Vec<JoinHandle<Result<ClientResponse, JobError>>> = Vec::new();
 
         for chunk in &tarball_chunks {
-            debug!("Submitting compute job with {} tarballs", chunk.len());
             let wp_comp = self.compute_pool.clone();
             let binary = binary.clone();
             let tbs = chunk
@@ -220,34 +228,40 @@ impl JobManager {
                 .map(|sub| sub.join("&"))
                 .collect::<Vec<String>>()
                 .join(" ");
-            handles.push(tokio::task::spawn(async move {
-                let worker = wp_comp.get_worker().await?;
-                let ssh = worker.get_ssh_session();
-                let cmd = format!(
-                    "cd $HOME/npm-follower/blob_idx_client && ./run.sh compute_multi {} \"{}\"",
-                    binary, tbs
-                );
-                debug!("Running command:\n{}", cmd);
-
-                let out = match tokio::time::timeout(
-                    std::time::Duration::from_secs(timeout),
-                    ssh.run_command(&cmd),
-                )
-                .await
-                {
-                    Ok(res) => res?,
-                    Err(_) => {
-                        println!("Worker timed out! Replacing...");
-                        wp_comp.replace_worker(&worker).await?;
-                        return Ok(ClientResponse::Error(ClientError::Timeout));
-                    }
-                };
-
-                debug!("Output:\n{}", out);
-                let response: ClientResponse = serde_json::from_str(&out)
-                    .map_err(|_| JobError::ClientOutputNotParsable(out))?;
-                Ok(response)
-            }));
+            let span =
+                tracing::info_span!("compute_job", num_tarballs = chunk.len(), job_id = Empty);
+            handles.push(tokio::task::spawn(
+                async move {
+                    let worker = wp_comp.get_worker().await?;
+                    Span::current().record("job_id", worker.job_id);
+                    let ssh = worker.get_ssh_session();
+                    let cmd = format!(
+                        "cd $HOME/npm-follower/blob_idx_client && ./run.sh compute_multi {} \"{}\"",
+                        binary, tbs
+                    );
+                    debug!("Running command:\n{}", cmd);
+
+                    let out = match tokio::time::timeout(
+                        std::time::Duration::from_secs(timeout),
+                        ssh.run_command(&cmd),
+                    )
+                    .await
+                    {
+                        Ok(res) => res?,
+                        Err(_) => {
+                            tracing::warn!("Worker timed out! Replacing...");
+                            wp_comp.replace_worker(&worker).await?;
+                            return Ok(ClientResponse::Error(ClientError::Timeout));
+                        }
+                    };
+
+                    debug!("Output:\n{}", out);
+                    let response: ClientResponse = serde_json::from_str(&out)
+                        .map_err(|_| JobError::ClientOutputNotParsable(out))?;
+                    Ok(response)
+                }
+                .instrument(span),
+            ));
         }
 
         let mut responses = Vec::new();
@@ -260,12 +274,11 @@ impl JobManager {
 
     /// Stores the files in the given filepaths (that reside on the discovery cluster) into
     /// the blob index. The filepaths should be the full path to the file on the discovery cluster.
+    #[tracing::instrument(skip_all, fields(num_files = filepaths.len(), job_id = Empty))]
     pub async fn submit_store_tarballs(&self, filepaths: Vec<String>) -> Result<(), JobError> {
-        debug!(
-            "Submitting store tarballs job with {} filepaths",
-            filepaths.len()
-        );
+        debug!("Submitting store tarballs job");
         let worker = self.xfer_pool.get_worker().await?;
+        Span::current().record("job_id", worker.job_id);
         let (node_id, ssh) = match &*worker.status {
             WorkerStatus::Running {
                 node_id,
@@ -9,12 +9,13 @@ use tokio::sync::{
 };
 
 use crate::{
-    debug,
     errors::JobError,
     job::worker::WorkerStatus,
     ssh::{Ssh, SshFactory},
 };
 
+use tracing::debug;
+
 use super::worker::Worker;
 
 /// A resource pool data structure that is used to query available worker jobs.
@@ -1,10 +1,13 @@
 use blob_idx_server::{blob, http::HTTP, job::JobManagerConfig, ssh::SshSessionFactory};
+use metrics_logging::MetricsLogger;
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
+    let _tracing = utils::init_tracing("blob_idx_server");
     let api_key = std::env::var("BLOB_API_KEY").expect("API_KEY must be set");
-    let http = HTTP::new("127.0.0.1".to_string(), "8080".to_string(), api_key);
+    let http = HTTP::new("127.0.0.1".to_string(), "8080".to_string(), api_key)
+        .with_metrics_logger(MetricsLogger::from_env_or_exit());
     let discovery_ssh = std::env::var("DISCOVERY_SSH").expect("DISCOVERY_SSH must be set");
     let (_tx, mut shutdown_signal) = tokio::sync::mpsc::channel::<()>(1);
 
@@ -1,6 +1,7 @@
 use tokio::sync::Mutex;
 
-use crate::{debug, errors::JobError};
+use crate::errors::JobError;
+use tracing::debug;
 
 #[async_trait::async_trait]
 pub trait Ssh: Send + Sync {
@@ -3,27 +3,38 @@ use changes_stream2::ChangeEvent;
 use changes_stream2::{ChangesStream, Event};
 use chrono::Utc;
 use futures_util::stream::StreamExt;
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
 use postgres_db::change_log;
 use postgres_db::connection::DbConnection;
+use postgres_db::notifications::{self, CHANGE_LOG_CHANNEL};
 use std::fs::File;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use utils::check_no_concurrent_processes;
 
+const COMPONENT: &str = "changes_fetcher";
+
+/// Progress is logged for this many changes at a time, or this often, whichever comes first,
+/// so that catching up doesn't log an event per change.
+const PROGRESS_EVERY_CHANGES: u64 = 1000;
+const PROGRESS_EVERY: Duration = Duration::from_secs(10);
+
 #[tokio::main]
 async fn main() {
+    let _tracing = utils::init_tracing("changes_fetcher");
     check_no_concurrent_processes("changes_fetcher");
 
     let mut conn = DbConnection::connect();
+    let mut metrics_logger = MetricsLogger::from_env_or_exit();
 
     loop {
-        listen_for_npm_changes_forever(&mut conn).await;
-        println!("NPM changes streamer ended. Sleeping for 300 seconds before restarting...");
+        listen_for_npm_changes_forever(&mut conn, &mut metrics_logger).await;
+        tracing::warn!("NPM changes streamer ended. Sleeping for 300 seconds before restarting...");
         task::sleep(Duration::from_secs(300)).await;
     }
 }
 
-async fn listen_for_npm_changes_forever(conn: &mut DbConnection) {
+async fn listen_for_npm_changes_forever(conn: &mut DbConnection, logger: &mut MetricsLogger) {
     let since_when = change_log::query_latest_change_seq(conn);
 
     let db_url = "https://replicate.npmjs.com";
@@ -43,13 +54,13 @@ async fn listen_for_npm_changes_forever(conn: &mut DbConnection) {
         .as_u64()
         .unwrap();
 
-    println!("Current last seq on NPM is: {}", end_sequence);
-    println!(
-        "Starting replication for range: ({}, forever)",
-        since_when
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "start-of-time".to_owned())
+    tracing::info!(npm_seq = end_sequence, "Got the current last seq on NPM");
+    logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::SessionStart)
+            .gauge("seq", since_when.unwrap_or(0))
+            .gauge("npm_seq", end_sequence as i64),
     );
+    tracing::info!(since = since_when, "Starting replication");
 
     let changes_url = match since_when {
         Some(since_when_num) => format!("https://replicate.npmjs.com/_changes?feed=continuous&style=main_only&include_docs=true&since={}", since_when_num),
@@ -58,33 +69,106 @@ async fn listen_for_npm_changes_forever(conn: &mut DbConnection) {
 
     let mut changes = match ChangesStream::new(changes_url).await {
         Ok(c) => c,
-        Err(_) => return,
+        Err(err) => {
+            tracing::error!(error = ?err, "Failed to connect to the changes stream");
+            logger.log_event(
+                MetricsEvent::new(COMPONENT, EventKind::Error)
+                    .text("message", format!("{:?}", err)),
+            );
+            return;
+        }
     };
+    let mut progress = Progress::new(end_sequence as i64);
     while let Some(event) = changes.next().await {
         match event {
             Ok(Event::Change(change_json)) => {
-                println!("inserting change seq: {}", change_json.seq);
-                process_change_event(conn, change_json);
+                let seq = process_change_event(conn, change_json);
+                progress.record(logger, seq);
             }
             Ok(Event::Finished(finished)) => {
-                println!("Finished: {}", finished.last_seq);
+                tracing::info!(last_seq = %finished.last_seq, "Changes stream finished");
                 break;
             }
             Err(err) => {
-                println!("Error: {:?}", err);
+                tracing::error!(error = ?err, "Changes stream failed");
+                logger.log_event(
+                    MetricsEvent::new(COMPONENT, EventKind::Error)
+                        .text("message", format!("{:?}", err)),
+                );
                 break;
             }
         }
     }
+    progress.flush(logger);
+    logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::SessionEnd)
+            .count("changes", progress.session_changes)
+            .gauge("seq", progress.last_seq),
+    );
+}
+
+/// Batches up the progress events of a session.
+struct Progress {
+    /// The latest seq npm is known to have, for the lag. The changes stream doesn't say, so this
+    /// is the seq from when the session started, or the latest change if that's newer.
+    npm_seq: i64,
+    last_seq: i64,
+    session_changes: u64,
+    unlogged_changes: u64,
+    last_logged: Instant,
+}
+
+impl Progress {
+    fn new(npm_seq: i64) -> Progress {
+        Progress {
+            npm_seq,
+            last_seq: 0,
+            session_changes: 0,
+            unlogged_changes: 0,
+            last_logged: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, logger: &mut MetricsLogger, seq: i64) {
+        self.npm_seq = self.npm_seq.max(seq);
+        self.last_seq = seq;
+        self.session_changes += 1;
+        self.unlogged_changes += 1;
+        if self.unlogged_changes >= PROGRESS_EVERY_CHANGES
+            || self.last_logged.elapsed() >= PROGRESS_EVERY
+        {
+            self.flush(logger);
+        }
+    }
+
+    fn flush(&mut self, logger: &mut MetricsLogger) {
+        if self.unlogged_changes == 0 {
+            return;
+        }
+        logger.log_event(
+            MetricsEvent::new(COMPONENT, EventKind::Progress)
+                .count("changes", self.unlogged_changes)
+                .gauge("seq", self.last_seq)
+                .gauge("seq_lag", self.npm_seq - self.last_seq)
+                .duration("duration", self.last_logged.elapsed()),
+        );
+        self.unlogged_changes = 0;
+        self.last_logged = Instant::now();
+    }
 }
 
-pub fn process_change_event(conn: &mut DbConnection, change: ChangeEvent) {
+/// Inserts the change, returning its seq.
+pub fn process_change_event(conn: &mut DbConnection, change: ChangeEvent) -> i64 {
     let now = Utc::now();
     let seq = change.seq.as_i64().unwrap();
+    let _span = tracing::info_span!("change", seq, package = %change.id).entered();
+    tracing::info!("Inserting change");
     let change_json =
         serde_json::to_value(&change).expect("Failed to serialize ChangeEvent to a Value");
 
     change_log::insert_change(conn, seq, change_json, now);
+    notifications::notify(conn, CHANGE_LOG_CHANNEL, &seq.to_string());
+    seq
 }
 
 fn _insert_saved_log_file(conn: &mut DbConnection) {
@@ -21,6 +21,7 @@ use tokio::{
     },
     task::JoinHandle,
 };
+use tracing::Instrument;
 
 #[derive(Serialize, Deserialize, QueryableByName, Debug, Clone)]
 struct QRes {
@@ -58,6 +59,7 @@ const NUM_LOCAL_WORKERS: usize = 3;
 
 #[tokio::main]
 async fn main() {
+    let _tracing = utils::init_tracing("diff_analysis");
     utils::check_no_concurrent_processes("diff_analysis");
     dotenvy::dotenv().ok();
     let mut conn: DbConnection = DbConnection::connect();
@@ -83,10 +85,10 @@ async fn main() {
         let chunk = chunk.to_vec();
         total += chunk.len();
         data_tx.send(chunk).await.unwrap();
-        println!("[MANAGER] Progress: {}/{}", total, total_count[0].count);
+        tracing::info!("Progress: {}/{}", total, total_count[0].count);
     }
 
-    println!("[MANAGER] DONE! Waiting for workers to finish...");
+    tracing::info!("DONE! Waiting for workers to finish...");
     drop(data_tx);
 
     for worker in chunk_workers {
@@ -136,8 +138,7 @@ fn spawn_diff_worker(
             };
 
             let client = reqwest::Client::new();
-            let time = chrono::Local::now();
-            println!("[{} - {}] Submitting job", worker_id, time);
+            tracing::info!(num_diffs = chunk.len(), "Submitting job");
             let http_resp = client
                 .post(&format!("{}/job/submit", blob_api_url))
                 .header("Authorization", &blob_api_key)
@@ -159,7 +160,7 @@ fn spawn_diff_worker(
                             let to_id = id_lookup[&tb_new];
                             if !dedup.insert((from_id, to_id)) {
                                 // this may happen for some reason?
-                                eprintln!("[{}] Duplicate diff: {} {}", worker_id, from_id, to_id);
+                                tracing::warn!(from_id, to_id, "Duplicate diff");
                                 continue;
                             }
                             let job_result = if res.exit_code == 0 && !res.stdout.is_empty() {
@@ -189,14 +190,14 @@ fn spawn_diff_worker(
                         }
                     }
                     ClientResponse::Error(e) => {
-                        eprintln!("[{}] Client Error: {}", worker_id, e);
+                        tracing::error!("Client Error: {}", e);
                     }
                 };
             }
             db_tx.send(diffs).await.unwrap();
         }
     };
-    tokio::task::spawn(thunk)
+    tokio::task::spawn(thunk.instrument(tracing::info_span!("diff_worker", worker_id)))
 }
 
 fn spawn_db_worker(
@@ -205,7 +206,7 @@ fn spawn_db_worker(
 ) -> JoinHandle<()> {
     let thunk = async move {
         while let Some(diffs) = db_rx.recv().await {
-            println!("[DB] Inserting {} diffs", diffs.len());
+            tracing::info!("Inserting {} diffs", diffs.len());
             if !diffs.is_empty() {
                 conn.run_psql_transaction(|mut c| {
                     delete_rows_after_compute(&diffs, &mut c);
@@ -214,10 +215,10 @@ fn spawn_db_worker(
                 })
                 .expect("Failed to insert diffs");
             }
-            println!("[DB] Done");
+            tracing::info!("Done");
         }
     };
-    tokio::task::spawn(thunk)
+    tokio::task::spawn(thunk.instrument(tracing::info_span!("db_worker")))
 }
 
 fn delete_rows_after_compute(diffs: &[DiffAnalysis], conn: &mut DbConnectionInTransaction) {
@@ -235,7 +236,7 @@ fn delete_rows_after_compute(diffs: &[DiffAnalysis], conn: &mut DbConnectionInTr
         }
     }
 
-    println!("[DB] Found {} errors in this batch", err_count);
+    tracing::info!("Found {} errors in this batch", err_count);
 
     // we may have all Errs, in which case we don't need to delete anything
     if !pairs.is_empty() {
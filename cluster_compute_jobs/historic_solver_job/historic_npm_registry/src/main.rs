@@ -1,23 +1,35 @@
 use std::str::FromStr;
 use std::sync::Arc;
 
+use blob_idx_server::errors::BlobError;
+use blob_idx_server::errors::ClientError;
 use chrono::DateTime;
 use chrono::Utc;
 use headers::ContentLength;
 use headers::ContentType;
 use headers::HeaderMapExt;
+use historic_solver_job::packument_requests::packument_from_reconstruction;
 use historic_solver_job::packument_requests::parse_packument;
 use historic_solver_job::packument_requests::restrict_time;
+use historic_solver_job::packument_requests::LocalPackumentResult;
 use historic_solver_job::packument_requests::NpmCache;
 use historic_solver_job::packument_requests::ParsedPackument;
 use historic_solver_job::MaxConcurrencyClient;
 use mime::Mime;
 use moka::future::Cache;
+use percent_encoding::utf8_percent_encode;
+use percent_encoding::NON_ALPHANUMERIC;
+use postgres_db::connection::async_pool::DbConnection;
+use postgres_db::diff_log;
+use postgres_db::diff_log::ReconstructionPoint;
+use postgres_db::download_tarball;
 use reqwest_middleware::ClientBuilder;
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
+use tokio_util::io::ReaderStream;
+use warp::host::Authority;
 use warp::http::StatusCode;
 use warp::hyper::body::Bytes;
 use warp::hyper::Body;
@@ -27,6 +39,58 @@ use warp::Filter;
 use warp::Rejection;
 use warp::Reply;
 
+/// Serves packuments out of our own diff log, so that we don't depend on npm
+/// still having the same data (e.g. for deleted versions).
+#[derive(Clone)]
+struct LocalBackend {
+    db: DbConnection,
+    /// Only requests for a fixed time are cached, since "now" keeps moving.
+    cache: Cache<(String, DateTime<Utc>), Arc<LocalPackumentResult>>,
+}
+
+fn lookup_package_in_diff_log(
+    t: Option<DateTime<Utc>>,
+    full_name: &str,
+    conn: &mut impl postgres_db::connection::QueryRunner,
+) -> LocalPackumentResult {
+    let at = ReconstructionPoint::Time(t.unwrap_or_else(Utc::now));
+    match diff_log::reconstruct(full_name, at, conn) {
+        Some(reconstructed) => packument_from_reconstruction(full_name, &reconstructed),
+        None => {
+            if diff_log::query_first_seq_for_package(full_name, conn).is_some() {
+                // We know the package, it just didn't exist yet at time t
+                LocalPackumentResult::DoesNotExist
+            } else {
+                LocalPackumentResult::NoLocalData
+            }
+        }
+    }
+}
+
+async fn request_package_locally(
+    maybe_t: Option<DateTime<Utc>>,
+    full_name: &str,
+    local: &LocalBackend,
+) -> Arc<LocalPackumentResult> {
+    let key = maybe_t.map(|t| (full_name.to_owned(), t));
+
+    if let Some(cache_hit) = key.as_ref().and_then(|k| local.cache.get(k)) {
+        return cache_hit;
+    }
+
+    let result = Arc::new(
+        local
+            .db
+            .run_blocking(|conn| lookup_package_in_diff_log(maybe_t, full_name, conn))
+            .await,
+    );
+
+    if let Some(k) = key {
+        local.cache.insert(k, result.clone()).await;
+    }
+    result
+}
+
 async fn request_package_from_npm(
     full_name: &str,
     client: MaxConcurrencyClient,
@@ -50,8 +114,18 @@ async fn lookup_package(
     full_name: &str,
     client: MaxConcurrencyClient,
     cache: NpmCache,
+    local: Option<LocalBackend>,
 ) -> Option<ParsedPackument<String>> {
     // println!("looking up: {}", full_name);
+    if let Some(local) = local {
+        match &*request_package_locally(maybe_t, full_name, &local).await {
+            LocalPackumentResult::Packument(p) => return restrict_time(p, maybe_t, full_name),
+            LocalPackumentResult::DoesNotExist => return None,
+            // Fall back to npm, e.g. if we haven't processed the package yet
+            LocalPackumentResult::NoLocalData => {}
+        }
+    }
+
     if let Some(cache_hit) = cache.get(full_name) {
         cache_hit.and_then(|x| restrict_time(&x, maybe_t, full_name))
     } else {
@@ -65,6 +139,23 @@ async fn lookup_package(
     }
 }
 
+/// Points the tarball urls at ourselves, so that `npm install` gets them from
+/// blob storage (see `handle_tarball_from_blob`) rather than from npm.
+fn rewrite_tarball_urls(packument: &mut ParsedPackument<String>, registry_base: &str) {
+    for version in packument.versions.values_mut() {
+        if let Some(Value::String(url)) = version
+            .get_mut("dist")
+            .and_then(|dist| dist.get_mut("tarball"))
+        {
+            *url = format!(
+                "{}/-/tarball/{}",
+                registry_base,
+                utf8_percent_encode(url, NON_ALPHANUMERIC)
+            );
+        }
+    }
+}
+
 fn serialize_datetime(dt: DateTime<Utc>) -> Value {
     Value::String(dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
 }
@@ -116,6 +207,8 @@ async fn handle_request(
     name: String,
     client: MaxConcurrencyClient,
     cache: NpmCache,
+    local: Option<LocalBackend>,
+    host: Option<Authority>,
 ) -> warp::reply::Json {
     // println!("handle_request");
 
@@ -150,7 +243,16 @@ async fn handle_request(
         panic!("BAD DATE: {}, full_name = {}", t_str, full_name)
     };
 
-    let matching_versions = lookup_package(parsed_t, &full_name, client, cache).await;
+    let tarballs_from_blob = local.is_some();
+    let mut matching_versions = lookup_package(parsed_t, &full_name, client, cache, local).await;
+
+    if let (true, Some(host), Some(packument)) =
+        (tarballs_from_blob, host, matching_versions.as_mut())
+    {
+        let registry_base = format!("http://{}/{}", host, t_str_url_encoded);
+        rewrite_tarball_urls(packument, &registry_base);
+    }
+
     warp::reply::json(&serialize_packument_in_npm_format(
         &full_name,
         matching_versions,
@@ -159,13 +261,84 @@ async fn handle_request(
 
 // Custom rejection handler that maps rejections into responses.
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, std::convert::Infallible> {
-    eprintln!("unhandled rejection: {:?}", err);
+    tracing::error!("unhandled rejection: {:?}", err);
     Ok(reply::with_status(
         "INTERNAL_SERVER_ERROR",
         StatusCode::INTERNAL_SERVER_ERROR,
     ))
 }
 
+fn tarball_not_found(tarball_url: &str, reason: &str) -> Response {
+    reply::with_status(
+        reply::json(&json!({
+            "error": "Tarball not mirrored",
+            "reason": reason,
+            "tarball": tarball_url
+        })),
+        StatusCode::NOT_FOUND,
+    )
+    .into_response()
+}
+
+/// Streams a tarball that we mirrored into blob storage. `tarball_url_encoded` is the
+/// original (npm) url of the tarball, as written by `rewrite_tarball_urls`.
+async fn handle_tarball_from_blob(
+    tarball_url_encoded: String,
+    local: Option<LocalBackend>,
+) -> Response {
+    let tarball_url = percent_encoding::percent_decode(tarball_url_encoded.as_bytes())
+        .decode_utf8()
+        .unwrap()
+        .into_owned();
+
+    let local = match local {
+        Some(l) => l,
+        None => {
+            return tarball_not_found(
+                &tarball_url,
+                "blob storage is only used with the local backend",
+            )
+        }
+    };
+
+    let blob_key = local
+        .db
+        .run_blocking(|conn| download_tarball::get_tarball_by_url(conn, &tarball_url))
+        .await
+        .and_then(|tb| tb.blob_storage_key);
+
+    let blob_key = match blob_key {
+        Some(k) => k,
+        None => return tarball_not_found(&tarball_url, "never transferred to blob storage"),
+    };
+
+    match blob_idx_client::open_blob(blob_key).await {
+        Ok((slice, reader)) => {
+            let mut resp = Response::new(Body::wrap_stream(ReaderStream::new(reader)));
+
+            resp.headers_mut()
+                .typed_insert(ContentLength(slice.num_bytes));
+            resp.headers_mut().typed_insert(ContentType::from(
+                "application/gzip".parse::<Mime>().unwrap(),
+            ));
+
+            resp
+        }
+        Err(ClientError::BlobError(BlobError::DoesNotExist(_))) => {
+            tarball_not_found(&tarball_url, "missing from blob storage")
+        }
+        Err(e) => {
+            tracing::error!(
+                tarball_url = %tarball_url,
+                "failed to read tarball from blob storage: {:?}",
+                e
+            );
+            reply::with_status("INTERNAL_SERVER_ERROR", StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response()
+        }
+    }
+}
+
 fn handle_tarball_empty() -> Response {
     let empty_tarball_bytes = Bytes::from_static(include_bytes!("../empty-package.tar"));
     let len = empty_tarball_bytes.len();
@@ -181,7 +354,20 @@ fn handle_tarball_empty() -> Response {
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    let _tracing = utils::init_tracing("historic_npm_registry");
+
+    let args: Vec<String> = std::env::args().collect();
+    let backend = args.get(1).map(|s| s.as_str()).unwrap_or("npm");
+    let local = match backend {
+        "local" => Some(LocalBackend {
+            db: DbConnection::connect().await,
+            cache: Cache::new(32_768),
+        }),
+        "npm" => None,
+        _ => panic!("Usage: {} [npm|local]", args[0]),
+    };
+    let local2 = local.clone();
+    let local3 = local.clone();
 
     let retry_policy = ExponentialBackoff::builder().build_with_max_retries(6);
     let req_client = ClientBuilder::new(reqwest::Client::new())
@@ -197,10 +383,12 @@ async fn main() {
         .and(warp::path::param::<String>())
         .and(warp::any().map(move || req_client.clone()))
         .and(warp::any().map(move || cache.clone()))
+        .and(warp::any().map(move || local.clone()))
+        .and(warp::host::optional())
         .and(warp::path::end())
         .then(
-            |t_str_url: String, name, req_client_inner, cache| async move {
-                handle_request(t_str_url, None, name, req_client_inner, cache).await
+            |t_str_url: String, name, req_client_inner, cache, local, host| async move {
+                handle_request(t_str_url, None, name, req_client_inner, cache, local, host).await
             },
         );
 
@@ -209,10 +397,21 @@ async fn main() {
         .and(warp::path::param::<String>())
         .and(warp::any().map(move || req_client2.clone()))
         .and(warp::any().map(move || cache2.clone()))
+        .and(warp::any().map(move || local2.clone()))
+        .and(warp::host::optional())
         .and(warp::path::end())
         .then(
-            |t_str_url: String, scope, name, req_client_inner, cache| async move {
-                handle_request(t_str_url, Some(scope), name, req_client_inner, cache).await
+            |t_str_url: String, scope, name, req_client_inner, cache, local, host| async move {
+                handle_request(
+                    t_str_url,
+                    Some(scope),
+                    name,
+                    req_client_inner,
+                    cache,
+                    local,
+                    host,
+                )
+                .await
             },
         );
 
@@ -228,6 +427,13 @@ async fn main() {
 
     // let file_semaphore
 
+    let tarball_from_blob = warp::path!(String / "-" / "tarball" / String)
+        .and(warp::path::end())
+        .and(warp::any().map(move || local3.clone()))
+        .then(|_time, tarball_url_encoded, local| async move {
+            handle_tarball_from_blob(tarball_url_encoded, local).await
+        });
+
     let tarball_redirect = warp::path!(String / String / "-" / String)
         .and(warp::path::end())
         .map(|_time, _name, _tarball_name| ())
@@ -272,6 +478,7 @@ async fn main() {
         empty_advisories
             .or(root)
             .or(css)
+            .or(tarball_from_blob)
             .or(non_scoped)
             .or(scoped)
             .or(tarball_redirect)
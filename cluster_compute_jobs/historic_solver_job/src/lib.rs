@@ -333,6 +333,9 @@ pub mod packument_requests {
 
     use chrono::{DateTime, Utc};
     use moka::future::Cache;
+    use postgres_db::custom_types::Semver;
+    use postgres_db::diff_log::ReconstructedPackument;
+    use postgres_db::packument::{PackageOnlyPackument, Spec, VersionOnlyPackument};
     use serde_json::{Map, Value};
 
     #[derive(Clone)]
@@ -396,6 +399,113 @@ pub mod packument_requests {
         })
     }
 
+    #[derive(Clone)]
+    pub enum LocalPackumentResult {
+        Packument(ParsedPackument<()>),
+        /// We know about the package, but it did not exist (or was deleted / unpublished) at the requested time.
+        DoesNotExist,
+        /// We have no usable data about the package, so the caller should fall back to npm.
+        NoLocalData,
+    }
+
+    /// Keys of a version blob that are kept when serving packuments, see `parse_packument`.
+    const KEPT_VERSION_KEYS: &[&str] = &[
+        "dependencies",
+        "devDependencies",
+        "optionalDependencies",
+        "peerDependencies",
+        "peerDependenciesMeta",
+        "overrides",
+        "bundleDependencies",
+    ];
+
+    /// Builds a packument in the same shape as `parse_packument`, but from a packument
+    /// reconstructed out of our diff log instead of one downloaded from npm.
+    pub fn packument_from_reconstruction(
+        package_name: &str,
+        reconstructed: &ReconstructedPackument,
+    ) -> LocalPackumentResult {
+        let (created_time, modified_time) = match &reconstructed.package {
+            PackageOnlyPackument::Normal {
+                created, modified, ..
+            } => (*created, *modified),
+            PackageOnlyPackument::Unpublished { .. } | PackageOnlyPackument::Deleted => {
+                return LocalPackumentResult::DoesNotExist
+            }
+            PackageOnlyPackument::MissingData => return LocalPackumentResult::NoLocalData,
+        };
+
+        // Same as parse_packument, we remove all betas
+        let non_betas = reconstructed
+            .versions
+            .iter()
+            .filter(|(v, _)| v.prerelease.is_empty() && v.build.is_empty());
+
+        let mut sorted_times: Vec<(String, DateTime<Utc>)> = non_betas
+            .clone()
+            .map(|(v, data)| (v.to_string(), data.time))
+            .collect();
+        sorted_times.sort_by_key(|(_, dt)| *dt);
+
+        let versions: Map<String, Value> = non_betas
+            .map(|(v, data)| (v.to_string(), version_blob(package_name, v, data)))
+            .collect();
+
+        LocalPackumentResult::Packument(ParsedPackument {
+            latest_tag: (),
+            versions,
+            sorted_times,
+            modified_time,
+            created_time,
+        })
+    }
+
+    fn version_blob(package_name: &str, v: &Semver, data: &VersionOnlyPackument) -> Value {
+        let mut blob = Map::new();
+
+        // Weird dependency values (e.g. arrays) are kept in the extra metadata by the diff log builder
+        for key in KEPT_VERSION_KEYS {
+            if let Some(x) = data.extra_metadata.get(*key) {
+                blob.insert(key.to_string(), x.clone());
+            }
+        }
+
+        for (key, deps) in [
+            ("dependencies", &data.prod_dependencies),
+            ("devDependencies", &data.dev_dependencies),
+            ("peerDependencies", &data.peer_dependencies),
+            ("optionalDependencies", &data.optional_dependencies),
+        ] {
+            if !deps.is_empty() {
+                blob.insert(key.to_owned(), dependencies_blob(deps));
+            }
+        }
+
+        blob.insert("name".to_owned(), Value::String(package_name.to_owned()));
+        blob.insert("version".to_owned(), Value::String(v.to_string()));
+        blob.insert(
+            "_id".to_owned(),
+            Value::String(format!("{}@{}", package_name, v)),
+        );
+
+        let mut dist = Map::new();
+        dist.insert(
+            "tarball".to_owned(),
+            Value::String(data.dist.tarball_url.clone()),
+        );
+        blob.insert("dist".to_owned(), Value::Object(dist));
+
+        Value::Object(blob)
+    }
+
+    fn dependencies_blob(deps: &[(String, Spec)]) -> Value {
+        Value::Object(
+            deps.iter()
+                .map(|(name, spec)| (name.clone(), spec.raw.clone()))
+                .collect(),
+        )
+    }
+
     pub fn parse_datetime(x: &str) -> DateTime<Utc> {
         let dt = DateTime::parse_from_rfc3339(x)
             .or_else(|_| DateTime::parse_from_rfc3339(&format!("{}Z", x)))
@@ -1,22 +1,26 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use historic_solver_job::{
     async_pool::{handle_get_jobs, handle_submit_result},
     Job, JobResult, MaxConcurrencyClient,
 };
 use lazy_static::lazy_static;
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
 use postgres_db::connection::async_pool::DbConnection;
 use reqwest_middleware::ClientBuilder;
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use tokio::sync::mpsc;
 use tokio::sync::RwLock;
+use tracing::Instrument;
 
 mod run_solve_job;
 
 const JOBS_PER_THREAD: i64 = 8;
 
+const COMPONENT: &str = "historic_solver_job";
+
 #[derive(Debug)]
 pub struct Configuration {
     num_threads: i64,
@@ -24,6 +28,15 @@ pub struct Configuration {
     node_name: String,
     npm_config_cache: String,
     max_job_time: Duration,
+    solver_backend: SolverBackend,
+}
+
+/// How dependencies are solved: by running `npm install` against the historic registry, or
+/// in-process with `npm_resolver` over the relational tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolverBackend {
+    Npm,
+    Native,
 }
 
 lazy_static! {
@@ -34,6 +47,7 @@ lazy_static! {
 trait RunnableJob {
     async fn run(
         self,
+        db: DbConnection,
         req_client: MaxConcurrencyClient,
         subprocess_semaphore: Arc<tokio::sync::Semaphore>,
         nuke_process_semaphore: Arc<RwLock<()>>,
@@ -44,12 +58,14 @@ trait RunnableJob {
 impl RunnableJob for Job {
     async fn run(
         self,
+        db: DbConnection,
         req_client: MaxConcurrencyClient,
         subprocess_semaphore: Arc<tokio::sync::Semaphore>,
         nuke_process_semaphore: Arc<RwLock<()>>,
     ) -> JobResult {
         run_solve_job::run_solve_job(
             self,
+            db,
             req_client,
             subprocess_semaphore,
             nuke_process_semaphore,
@@ -60,11 +76,19 @@ impl RunnableJob for Job {
 
 #[tokio::main]
 async fn main() {
+    let _tracing = utils::init_tracing("historic_solver_job");
     let (result_tx, mut result_rx) = mpsc::unbounded_channel();
 
     let schedule_more_jobs_if_fewer_than = JOBS_PER_THREAD * CONFIG.num_threads / 10;
     let start_time = Utc::now();
 
+    let mut metrics_logger = MetricsLogger::from_env_or_exit();
+    metrics_logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::SessionStart)
+            .gauge("num_threads", CONFIG.num_threads)
+            .text("node_name", CONFIG.node_name.clone()),
+    );
+
     let active_jobs: Arc<RwLock<i64>> = Arc::new(tokio::sync::RwLock::new(0));
     let active_jobs2 = active_jobs.clone();
 
@@ -73,7 +97,7 @@ async fn main() {
 
         loop {
             interval.tick().await;
-            println!("queue size: {}", active_jobs2.read().await);
+            tracing::info!(queue_size = *active_jobs2.read().await, "Queue size");
         }
     });
 
@@ -86,7 +110,7 @@ async fn main() {
         loop {
             interval.tick().await;
             let permit = nuke_cache_lock2.write().await;
-            println!("Clearing npm cache!");
+            tracing::info!("Clearing npm cache!");
 
             // TODO: nuke cache
             std::fs::remove_dir_all(&CONFIG.npm_config_cache).unwrap();
@@ -118,13 +142,18 @@ async fn main() {
         )
         .await;
 
+        let mut num_jobs = 0;
         if *active_jobs.read().await == 0 {
-            println!("We got no initial jobs to run, exiting.");
+            tracing::info!("We got no initial jobs to run, exiting.");
+            log_session_end(&mut metrics_logger, num_jobs, start_time);
             return;
         }
 
         while let Some(result) = result_rx.recv().await {
+            let job_start = std::time::Instant::now();
+            let result_category = result.result_category.clone();
             write_result_to_postgres(result, &db).await;
+            num_jobs += 1;
 
             let new_active_jobs = {
                 let mut active_jobs_lock = active_jobs.write().await;
@@ -132,6 +161,15 @@ async fn main() {
                 *active_jobs_lock
             };
 
+            metrics_logger.log_event(
+                MetricsEvent::new(COMPONENT, EventKind::Progress)
+                    .count("jobs", 1)
+                    .count("ok_jobs", (result_category == "Ok") as u64)
+                    .gauge("queue_size", new_active_jobs)
+                    .duration("write_duration", job_start.elapsed())
+                    .text("result_category", result_category),
+            );
+
             let now = Utc::now();
             let dt = now - start_time;
 
@@ -148,7 +186,8 @@ async fn main() {
             }
 
             if *active_jobs.read().await == 0 {
-                println!("No jobs left to run, exiting");
+                tracing::info!("No jobs left to run, exiting");
+                log_session_end(&mut metrics_logger, num_jobs, start_time);
                 return;
             }
         }
@@ -157,6 +196,17 @@ async fn main() {
     .unwrap();
 }
 
+fn log_session_end(metrics_logger: &mut MetricsLogger, num_jobs: u64, start_time: DateTime<Utc>) {
+    metrics_logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::SessionEnd)
+            .count("jobs", num_jobs)
+            .duration(
+                "duration",
+                (Utc::now() - start_time).to_std().unwrap_or_default(),
+            ),
+    );
+}
+
 async fn grab_and_run_job_batch(
     active_jobs: &RwLock<i64>,
     result_tx: &mpsc::UnboundedSender<JobResult>,
@@ -169,7 +219,7 @@ async fn grab_and_run_job_batch(
 
     {
         let mut active_jobs = active_jobs.write().await;
-        println!(
+        tracing::info!(
             "Fetched new jobs. Queue size {} -> {}",
             *active_jobs,
             *active_jobs + jobs.len() as i64
@@ -179,16 +229,29 @@ async fn grab_and_run_job_batch(
     }
 
     for job in jobs {
+        let db = db.clone();
         let result_tx = result_tx.clone();
         let req_client = req_client.clone();
         let subprocess_semaphore = subprocess_semaphore.clone();
         let nuke_cache_lock = nuke_cache_lock.clone();
-        tokio::task::spawn(async move {
-            let job_result = job
-                .run(req_client, subprocess_semaphore, nuke_cache_lock)
-                .await;
-            result_tx.send(job_result).unwrap();
-        });
+        let span = tracing::info_span!(
+            "solve_job",
+            update_from_id = job.update_from_id,
+            update_to_id = job.update_to_id,
+            downstream_package_id = job.downstream_package_id,
+            update_package = %job.update_package_name,
+            downstream_package = %job.downstream_package_name,
+        );
+        tokio::task::spawn(
+            async move {
+                let job_result = job
+                    .run(db, req_client, subprocess_semaphore, nuke_cache_lock)
+                    .await;
+                tracing::info!(result_category = %job_result.result_category, "Job done");
+                result_tx.send(job_result).unwrap();
+            }
+            .instrument(span),
+        );
     }
 }
 
@@ -240,5 +303,10 @@ fn load_config() -> Configuration {
         node_name: env::var("NODE_NAME").expect("NODE_NAME"),
         npm_config_cache: env::var("npm_config_cache").expect("npm_config_cache"),
         max_job_time: Duration::seconds(desired_secs),
+        solver_backend: match env::var("SOLVER_BACKEND").as_deref() {
+            Err(_) | Ok("npm") => SolverBackend::Npm,
+            Ok("native") => SolverBackend::Native,
+            Ok(other) => panic!("invalid SOLVER_BACKEND: {}", other),
+        },
     }
 }
@@ -7,6 +7,7 @@ use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use super::SolverBackend;
 use super::CONFIG;
 use chrono::DateTime;
 use chrono::Duration;
@@ -20,6 +21,8 @@ use historic_solver_job::ResultError;
 use historic_solver_job::SolveResult;
 use historic_solver_job::{Job, JobResult};
 use lazy_static::lazy_static;
+use npm_resolver::{DbRegistry, ResolveOptions, RootManifest};
+use postgres_db::connection::async_pool::DbConnection;
 use postgres_db::custom_types::Semver;
 use serde_json::Value;
 use std::process::Stdio;
@@ -43,6 +46,7 @@ lazy_static! {
 
 pub(crate) async fn run_solve_job(
     job: Job,
+    db: DbConnection,
     req_client: MaxConcurrencyClient,
     subprocess_semaphore: Arc<tokio::sync::Semaphore>,
     nuke_cache_lock: Arc<RwLock<()>>,
@@ -58,6 +62,7 @@ pub(crate) async fn run_solve_job(
 
     match run_solve_job_result(
         job,
+        &db,
         req_client,
         subprocess_semaphore,
         nuke_cache_lock,
@@ -90,6 +95,7 @@ pub(crate) async fn run_solve_job(
 
 async fn run_solve_job_result(
     job: Job,
+    db: &DbConnection,
     req_client: MaxConcurrencyClient,
     subprocess_semaphore: Arc<tokio::sync::Semaphore>,
     nuke_cache_lock: Arc<RwLock<()>>,
@@ -123,6 +129,7 @@ async fn run_solve_job_result(
         job.update_to_time - *EPSILON,
         &new_tmp_dir,
         &job.downstream_package_name,
+        db,
         subprocess_semaphore.as_ref(),
         nuke_cache_lock.as_ref(),
         (stdout_res, stderr_res),
@@ -155,6 +162,7 @@ async fn run_solve_job_result(
             dt,
             &new_tmp_dir,
             &job.downstream_package_name,
+            db,
             subprocess_semaphore.as_ref(),
             nuke_cache_lock.as_ref(),
             (stdout_res, stderr_res),
@@ -212,6 +220,7 @@ async fn solve_dependencies(
     dt: DateTime<Utc>,
     temp_dir: &TempDir,
     solve_package_name: &str,
+    db: &DbConnection,
     subprocess_semaphore: &tokio::sync::Semaphore,
     nuke_cache_lock: &RwLock<()>,
     stdout_stderr_res: (&mut Vec<u8>, &mut Vec<u8>),
@@ -227,6 +236,7 @@ async fn solve_dependencies(
         package_json_at_time,
         dt,
         &solve_dir,
+        db,
         subprocess_semaphore,
         nuke_cache_lock,
         stdout_stderr_res,
@@ -243,10 +253,93 @@ async fn solve_dependencies_impl(
     package_json: Value,
     dt: DateTime<Utc>,
     solve_dir: &PathBuf,
+    db: &DbConnection,
     subprocess_semaphore: &tokio::sync::Semaphore,
     nuke_cache_lock: &RwLock<()>,
     stdout_stderr_res: (&mut Vec<u8>, &mut Vec<u8>),
 ) -> Result<SolveSolutionMetrics, ResultError> {
+    let mut lock_json = match CONFIG.solver_backend {
+        SolverBackend::Npm => {
+            solve_with_npm(
+                package_json,
+                dt,
+                solve_dir,
+                subprocess_semaphore,
+                nuke_cache_lock,
+                stdout_stderr_res,
+            )
+            .await?
+        }
+        SolverBackend::Native => solve_natively(package_json, dt, db, stdout_stderr_res.1).await?,
+    };
+
+    let lock_json_copy = lock_json.clone();
+
+    let deps = lock_json
+        .as_object_mut()
+        .unwrap()
+        .remove("packages")
+        .unwrap();
+
+    let mut solution = SolveSolutionMetrics::new(downstream_v, dt, lock_json_copy);
+
+    for (dep_path, dep_info) in deps.as_object().unwrap().iter() {
+        if dep_path.is_empty() {
+            continue;
+        }
+
+        let dep_info = dep_info.as_object().unwrap();
+        if dep_info.contains_key("link") {
+            continue;
+        }
+
+        if !dep_path.contains("node_modules/") {
+            continue;
+        }
+
+        let dep_name_start_idx = dep_path.rfind("node_modules/").unwrap() + 13;
+        let dep_name = &dep_path[dep_name_start_idx..];
+
+        let version = dep_info.get("version").unwrap().as_str().unwrap();
+        let version = semver_spec_serialization::parse_semver(version).unwrap();
+        solution.push_dep(dep_name.to_string(), version);
+    }
+
+    Ok(solution)
+}
+
+/// Resolves in-process over the relational tables as of `dt`, instead of running npm.
+async fn solve_natively(
+    package_json: Value,
+    dt: DateTime<Utc>,
+    db: &DbConnection,
+    stderr_res: &mut Vec<u8>,
+) -> Result<Value, ResultError> {
+    let root = RootManifest::from_package_json(package_json.as_object().unwrap());
+
+    let res = db
+        .run_blocking(|conn| {
+            let mut registry = DbRegistry::new(conn, dt);
+            npm_resolver::resolve(root, &mut registry, &ResolveOptions::default())
+                .map(|resolution| resolution.to_package_lock())
+        })
+        .await;
+
+    res.map_err(|err| {
+        writeln!(stderr_res, "{}", err).unwrap();
+        ResultError::SolveError
+    })
+}
+
+/// Runs `npm install` against the historic registry as of `dt`, and returns the package lock.
+async fn solve_with_npm(
+    package_json: Value,
+    dt: DateTime<Utc>,
+    solve_dir: &PathBuf,
+    subprocess_semaphore: &tokio::sync::Semaphore,
+    nuke_cache_lock: &RwLock<()>,
+    stdout_stderr_res: (&mut Vec<u8>, &mut Vec<u8>),
+) -> Result<Value, ResultError> {
     let (stdout_res, stderr_res) = stdout_stderr_res;
 
     // println!("solve_dependencies_impl");
@@ -303,44 +396,12 @@ async fn solve_dependencies_impl(
         .unwrap()
         .read_to_string(&mut s)
         .unwrap();
-    let mut lock_json: Value = serde_json::from_str(&s).unwrap();
+    let lock_json: Value = serde_json::from_str(&s).unwrap();
 
     drop(subprocess_permit);
     drop(cache_permit);
 
-    let lock_json_copy = lock_json.clone();
-
-    let deps = lock_json
-        .as_object_mut()
-        .unwrap()
-        .remove("packages")
-        .unwrap();
-
-    let mut solution = SolveSolutionMetrics::new(downstream_v, dt, lock_json_copy);
-
-    for (dep_path, dep_info) in deps.as_object().unwrap().iter() {
-        if dep_path.is_empty() {
-            continue;
-        }
-
-        let dep_info = dep_info.as_object().unwrap();
-        if dep_info.contains_key("link") {
-            continue;
-        }
-
-        if !dep_path.contains("node_modules/") {
-            continue;
-        }
-
-        let dep_name_start_idx = dep_path.rfind("node_modules/").unwrap() + 13;
-        let dep_name = &dep_path[dep_name_start_idx..];
-
-        let version = dep_info.get("version").unwrap().as_str().unwrap();
-        let version = semver_spec_serialization::parse_semver(version).unwrap();
-        solution.push_dep(dep_name.to_string(), version);
-    }
-
-    Ok(solution)
+    Ok(lock_json)
 }
 
 fn make_solve_dir(in_dir: &TempDir) -> PathBuf {
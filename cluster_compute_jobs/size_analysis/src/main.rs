@@ -19,6 +19,7 @@ use tokio::{
     },
     task::JoinHandle,
 };
+use tracing::Instrument;
 
 #[derive(Serialize, Deserialize, QueryableByName, Debug, Clone)]
 struct QRes {
@@ -53,6 +54,7 @@ async fn main() {
     let num_workers: usize = args[1].parse().unwrap();
     let chunk_size: usize = args[2].parse().unwrap();
 
+    let _tracing = utils::init_tracing("size_analysis");
     utils::check_no_concurrent_processes("size_analysis");
     dotenvy::dotenv().ok();
     let mut conn: DbConnection = DbConnection::connect();
@@ -78,17 +80,17 @@ async fn main() {
     let db_worker = spawn_db_worker(db_rx, DbConnection::connect());
 
     let total_count: Vec<QCount> = conn.load(diesel::sql_query(COUNT_QUERY)).unwrap();
-    println!("[MANAGER] Total count: {}", total_count[0].count);
+    tracing::info!("Total count: {}", total_count[0].count);
 
     let mut total = 0;
     for chunk in res.chunks(chunk_size) {
         let chunk = chunk.to_vec();
         total += chunk.len();
         data_tx.send(chunk).await.unwrap();
-        println!("[MANAGER] Progress: {}/{}", total, total_count[0].count);
+        tracing::info!("Progress: {}/{}", total, total_count[0].count);
     }
 
-    println!("[MANAGER] DONE! Waiting for workers to finish...");
+    tracing::info!("DONE! Waiting for workers to finish...");
     drop(data_tx);
 
     for worker in chunk_workers {
@@ -138,8 +140,7 @@ fn spawn_compute_worker(
             };
 
             let client = reqwest::Client::new();
-            let time = chrono::Local::now();
-            println!("[{worker_id} - {time}] Submitting job");
+            tracing::info!(num_tarballs = chunk.len(), "Submitting job");
             let http_resp = client
                 .post(&format!("{blob_api_url}/job/submit"))
                 .header("Authorization", &blob_api_key)
@@ -158,7 +159,7 @@ fn spawn_compute_worker(
                         for (tb, res) in res {
                             if !dedup.insert(tb.clone()) {
                                 // this may happen for some reason?
-                                eprintln!("[{worker_id}] Duplicate tb: {tb}");
+                                tracing::warn!(blob_key = %tb, "Duplicate tarball");
                                 continue;
                             }
 
@@ -169,7 +170,7 @@ fn spawn_compute_worker(
                                         let tarball_url = match lookup.get(&tb) {
                                             Some(u) => u,
                                             None => {
-                                                eprintln!("[{worker_id}] Failed to find tarball url for {tb}");
+                                                tracing::error!(blob_key = %tb, "Failed to find tarball url");
                                                 continue;
                                             }
                                         };
@@ -177,18 +178,19 @@ fn spawn_compute_worker(
                                         results.push(res)
                                     }
                                     Err(_) => {
-                                        eprintln!(
-                                            "[{worker_id}] Failed to deserialize stdout: {stdout:?}"
+                                        tracing::error!(
+                                            blob_key = %tb,
+                                            "Failed to deserialize stdout: {stdout:?}"
                                         );
                                     }
                                 }
                             } else {
-                                eprintln!("[{worker_id}] Failed to run size analysis: {res:?}");
+                                tracing::error!(blob_key = %tb, "Failed to run size analysis: {res:?}");
                             };
                         }
                     }
                     ClientResponse::Error(e) => {
-                        eprintln!("[{worker_id}] Client Error: {e}");
+                        tracing::error!("Client Error: {e}");
                     }
                 };
             }
@@ -196,7 +198,7 @@ fn spawn_compute_worker(
             db_tx.send(results).await.unwrap();
         }
     };
-    tokio::task::spawn(thunk)
+    tokio::task::spawn(thunk.instrument(tracing::info_span!("compute_worker", worker_id)))
 }
 
 fn spawn_db_worker(
@@ -205,7 +207,7 @@ fn spawn_db_worker(
 ) -> JoinHandle<()> {
     let thunk = async move {
         while let Some(results) = db_rx.recv().await {
-            println!("[DB] Inserting {} results", results.len());
+            tracing::info!("Inserting {} results", results.len());
             if !results.is_empty() {
                 conn.run_psql_transaction(|mut c| {
                     delete_rows_after_compute(&results, &mut c);
@@ -238,10 +240,10 @@ fn spawn_db_worker(
                 })
                 .expect("Failed to insert results");
             }
-            println!("[DB] Done");
+            tracing::info!("Done");
         }
     };
-    tokio::task::spawn(thunk)
+    tokio::task::spawn(thunk.instrument(tracing::info_span!("db_worker")))
 }
 
 fn delete_rows_after_compute(
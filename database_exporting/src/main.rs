@@ -27,6 +27,7 @@ fn load_hf_token() -> String {
 }
 
 fn main() -> io::Result<()> {
+    let _tracing = utils::init_tracing("database_exporting");
     let user = get_user_by_uid(get_current_uid()).unwrap();
     if user.name().to_string_lossy() != "postgres" {
         return Err(io::Error::new(
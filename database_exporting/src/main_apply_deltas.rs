@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use database_exporting::parquet_export::delta::apply_deltas;
+use database_exporting::parquet_export::DEFAULT_ROWS_PER_FILE;
+
+const USAGE: &str = "<snapshot dir> <delta dir>... --out <export dir> [--rows-per-file <n>]";
+
+/// Applies a chain of delta exports, oldest first, to a full Parquet export, and writes the
+/// result as a new full export to `<export dir>`.
+fn main() {
+    let _tracing = utils::init_tracing("apply_parquet_deltas");
+
+    let args: Vec<String> = std::env::args().collect();
+    let program = &args[0];
+
+    let mut dirs: Vec<PathBuf> = vec![];
+    let mut out_dir = None;
+    let mut rows_per_file = DEFAULT_ROWS_PER_FILE;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--out" => {
+                out_dir = Some(PathBuf::from(
+                    rest.next().unwrap_or_else(|| exit_with_usage(program)),
+                ))
+            }
+            "--rows-per-file" => {
+                rows_per_file = rest
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .unwrap_or_else(|| exit_with_usage(program));
+            }
+            _ if !arg.starts_with("--") => dirs.push(PathBuf::from(arg)),
+            _ => exit_with_usage(program),
+        }
+    }
+    let out_dir = out_dir.unwrap_or_else(|| exit_with_usage(program));
+    let Some((snapshot_dir, delta_dirs)) = dirs.split_first() else {
+        exit_with_usage(program)
+    };
+
+    let manifest = match apply_deltas(snapshot_dir, delta_dirs, &out_dir, rows_per_file) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "Applied {} deltas, up to seq {}, to {}",
+        delta_dirs.len(),
+        manifest
+            .relational_processed_seq
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| "?".to_string()),
+        out_dir.display()
+    );
+    for table in &manifest.tables {
+        println!(
+            "{:<24} {:>12} rows in {} files",
+            table.name,
+            table.num_rows,
+            table.files.len()
+        );
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
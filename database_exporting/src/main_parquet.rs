@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use database_exporting::manifest::Manifest;
+use database_exporting::parquet_export::delta::export_delta;
+use database_exporting::parquet_export::{export_dataset, DEFAULT_ROWS_PER_FILE};
+use postgres_db::connection::DbConnection;
+
+const USAGE: &str = "<export dir> [--rows-per-file <n>] [--since <base export dir>]";
+
+/// Exports `packages`, `versions`, `dependencies`, `ghsa`, `vulnerabilities` and the download
+/// metrics to Parquet files in `<export dir>`, with a `manifest.json` describing them.
+/// With `--since`, only exports what changed since the export in `<base export dir>`.
+fn main() {
+    let _tracing = utils::init_tracing("export_parquet");
+
+    let args: Vec<String> = std::env::args().collect();
+    let program = &args[0];
+
+    let mut export_dir = None;
+    let mut rows_per_file = DEFAULT_ROWS_PER_FILE;
+    let mut base_dir = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--rows-per-file" => {
+                rows_per_file = rest
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .unwrap_or_else(|| exit_with_usage(program));
+            }
+            "--since" => {
+                base_dir = Some(PathBuf::from(
+                    rest.next().unwrap_or_else(|| exit_with_usage(program)),
+                ))
+            }
+            _ if export_dir.is_none() && !arg.starts_with("--") => {
+                export_dir = Some(PathBuf::from(arg))
+            }
+            _ => exit_with_usage(program),
+        }
+    }
+    let export_dir = export_dir.unwrap_or_else(|| exit_with_usage(program));
+
+    let mut conn = DbConnection::connect();
+    let manifest = match base_dir {
+        Some(base_dir) => {
+            let base = Manifest::read(&base_dir);
+            export_delta(&mut conn, &base, &export_dir, rows_per_file)
+        }
+        None => export_dataset(&mut conn, &export_dir, rows_per_file),
+    };
+
+    if let Some(base) = &manifest.base {
+        println!(
+            "Exported the changes after seq {} since the export at {}",
+            base.relational_processed_seq, base.exported_at
+        );
+    }
+    println!(
+        "Exported {} tables at seq {} to {}",
+        manifest.tables.len(),
+        manifest
+            .relational_processed_seq
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| "?".to_string()),
+        export_dir.display()
+    );
+    for table in &manifest.tables {
+        println!(
+            "{:<24} {:>12} rows in {} files",
+            table.name,
+            table.num_rows,
+            table.files.len()
+        );
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
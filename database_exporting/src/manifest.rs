@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The name of the manifest file in the directory of an export.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Bumped whenever the schema of an exported table changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Describes a Parquet export: the tables in it, their files, row counts and schemas.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// The last seq of the change log that the relational tables include, if any.
+    pub relational_processed_seq: Option<i64>,
+    /// How many times the relational tables had been rewound when they were exported. A delta of
+    /// this export is refused if they were rewound to before its seq since.
+    #[serde(default)]
+    pub num_relational_rewinds: usize,
+    /// Set if this is a delta export: the export it is a delta of.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub base: Option<BaseManifest>,
+    pub tables: Vec<TableManifest>,
+}
+
+/// Identifies the export a delta applies to, which is either a full export or the previous delta.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BaseManifest {
+    pub exported_at: DateTime<Utc>,
+    pub relational_processed_seq: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TableManifest {
+    pub name: String,
+    pub num_rows: u64,
+    /// The column the files are partitioned by in Hive style, e.g. `year=2022/part-00000.parquet`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub partitioned_by: Option<String>,
+    /// Only in delta exports: the column identifying rows, whose rows replace the rows of the base
+    /// with the same value. Tables without it replace the whole table of the base, and tables
+    /// missing from a delta are unchanged.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub upsert_key: Option<String>,
+    pub files: Vec<FileManifest>,
+    pub schema: Vec<FieldManifest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileManifest {
+    /// Relative to the directory of the export.
+    pub path: String,
+    pub num_rows: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FieldManifest {
+    pub name: String,
+    /// The Arrow type, e.g. `list<struct<major: int64, ...>>`.
+    #[serde(rename = "type")]
+    pub data_type: String,
+    pub nullable: bool,
+    /// `json` for text columns holding JSON, which was `jsonb` in Postgres.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub encoding: Option<String>,
+}
+
+impl Manifest {
+    pub fn read(export_dir: &Path) -> Manifest {
+        let path = export_dir.join(MANIFEST_FILE_NAME);
+        let file = File::open(&path)
+            .unwrap_or_else(|e| panic!("Failed to open {}: {}", path.display(), e));
+        serde_json::from_reader(BufReader::new(file))
+            .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    pub fn write(&self, export_dir: &Path) {
+        let path = export_dir.join(MANIFEST_FILE_NAME);
+        let file = File::create(&path)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", path.display(), e));
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableManifest> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+}
+
+pub fn schema_manifest(schema: &Schema) -> Vec<FieldManifest> {
+    schema
+        .fields()
+        .iter()
+        .map(|field| FieldManifest {
+            name: field.name().clone(),
+            data_type: type_name(field.data_type()),
+            nullable: field.is_nullable(),
+            encoding: field.metadata().get("encoding").cloned(),
+        })
+        .collect()
+}
+
+fn type_name(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Boolean => "bool".to_string(),
+        DataType::Int32 => "int32".to_string(),
+        DataType::Int64 => "int64".to_string(),
+        DataType::Float32 => "float32".to_string(),
+        DataType::Utf8 => "string".to_string(),
+        DataType::Date32 => "date32".to_string(),
+        DataType::Timestamp(TimeUnit::Microsecond, Some(tz)) => format!("timestamp[us, {}]", tz),
+        DataType::List(item) => format!("list<{}>", field_type_name(item)),
+        DataType::Struct(fields) => format!(
+            "struct<{}>",
+            fields
+                .iter()
+                .map(|f| format!("{}: {}", f.name(), field_type_name(f)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        other => other.to_string(),
+    }
+}
+
+fn field_type_name(field: &Field) -> String {
+    if field.is_nullable() {
+        type_name(field.data_type())
+    } else {
+        format!("{} not null", type_name(field.data_type()))
+    }
+}
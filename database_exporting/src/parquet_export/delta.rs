@@ -0,0 +1,605 @@
+//! Delta exports, which only contain what changed in the relational tables since a previous
+//! export, and applying a chain of them to a full export.
+//!
+//! A delta covers the changes after the seq of its base export, up to its own seq. It contains the
+//! current rows of every package with diff entries in that range, all of their versions, and the
+//! dependencies of those versions or on those packages. These replace the rows with the same id
+//! in the base. Rows are only deleted from the relational tables by rewinding them, so if they
+//! were rewound to before the base's seq since the base export, a new full export is needed. `ghsa` and
+//! `vulnerabilities` are small and not keyed by seq, so deltas contain all of them. The download
+//! metrics are not in deltas, and are only updated by a full export.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use arrow::array::{Array, BooleanArray, Int64Array};
+use arrow::compute::filter_record_batch;
+use arrow::record_batch::RecordBatch;
+use chrono::Utc;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use postgres_db::connection::{DbConnection, QueryRunner};
+use postgres_db::{dependencies, diff_log, ghsa, internal_state, packages, versions};
+
+use super::rows::*;
+use super::{export_by_id, export_ghsa, schema, table_manifest, PartWriter, PAGE_SIZE};
+use crate::manifest::{BaseManifest, Manifest, TableManifest, FORMAT_VERSION, MANIFEST_FILE_NAME};
+
+/// Exports the changes since the export described by `base` to `export_dir`, and writes the
+/// manifest. Like `export_dataset`, everything is read in one repeatable read transaction.
+pub fn export_delta(
+    conn: &mut DbConnection,
+    base: &Manifest,
+    export_dir: &Path,
+    rows_per_file: usize,
+) -> Manifest {
+    let base_seq = base
+        .relational_processed_seq
+        .expect("The base export has no relational_processed_seq");
+    fs::create_dir_all(export_dir)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {}", export_dir.display(), e));
+    let exported_at = Utc::now();
+
+    let manifest = conn
+        .run_psql_transaction(|mut conn| {
+            conn.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")?;
+            let relational_processed_seq =
+                internal_state::query_relational_processed_seq(&mut conn)
+                    .expect("The relational tables are empty");
+            let rewinds = internal_state::query_relational_rewinds(&mut conn);
+            // The lowest seq they were rewound to since the base export
+            let low_water_mark = rewinds
+                .iter()
+                .skip(base.num_relational_rewinds)
+                .copied()
+                .chain([relational_processed_seq])
+                .min()
+                .unwrap();
+            assert!(
+                low_water_mark >= base_seq,
+                "The relational tables were rewound to seq {} since the base export at seq {}, \
+                 so a new full export is needed",
+                low_water_mark,
+                base_seq
+            );
+
+            let mut tables = export_changed_packages(
+                &mut conn,
+                export_dir,
+                rows_per_file,
+                base_seq,
+                relational_processed_seq,
+            );
+            tables.push(export_ghsa(&mut conn, export_dir, rows_per_file));
+            tables.push(export_by_id::<_, _, VulnerabilityRow>(
+                &mut conn,
+                export_dir,
+                rows_per_file,
+                "vulnerabilities",
+                schema::vulnerabilities(),
+                ghsa::query_vulnerabilities_after_id,
+                |(id, _)| *id,
+            ));
+
+            let manifest = Manifest {
+                format_version: FORMAT_VERSION,
+                exported_at,
+                relational_processed_seq: Some(relational_processed_seq),
+                num_relational_rewinds: rewinds.len(),
+                base: Some(BaseManifest {
+                    exported_at: base.exported_at,
+                    relational_processed_seq: base_seq,
+                }),
+                tables,
+            };
+            // read only, so there's nothing to commit
+            Ok((manifest, false))
+        })
+        .expect("Failed to export the delta");
+
+    manifest.write(export_dir);
+    manifest
+}
+
+/// Exports the packages with diff entries after `after_seq` up to `up_to_seq`, their versions,
+/// and the dependencies of those versions or on those packages.
+fn export_changed_packages<R: QueryRunner>(
+    conn: &mut R,
+    export_dir: &Path,
+    rows_per_file: usize,
+    after_seq: i64,
+    up_to_seq: i64,
+) -> Vec<TableManifest> {
+    let (packages_schema, versions_schema, dependencies_schema) = (
+        schema::packages(),
+        schema::versions(),
+        schema::dependencies(),
+    );
+    let mut packages_writer = PartWriter::new(
+        export_dir,
+        "packages".to_string(),
+        packages_schema.clone(),
+        rows_per_file,
+    );
+    let mut versions_writer = PartWriter::new(
+        export_dir,
+        "versions".to_string(),
+        versions_schema.clone(),
+        rows_per_file,
+    );
+    let mut dependencies_writer = PartWriter::new(
+        export_dir,
+        "dependencies".to_string(),
+        dependencies_schema.clone(),
+        rows_per_file,
+    );
+
+    let package_names =
+        diff_log::query_packages_with_diff_entries_in_seq_range(after_seq, up_to_seq, conn);
+    tracing::info!(
+        after_seq,
+        up_to_seq,
+        num_packages = package_names.len(),
+        "Exporting changed packages"
+    );
+
+    // A dependency can be shared by many of the changed versions.
+    let mut exported_dependencies = HashSet::new();
+    for names in package_names.chunks(PAGE_SIZE as usize) {
+        let page = packages::get_packages_by_names(conn, names);
+        let package_ids: Vec<i64> = page.iter().map(|p| p.id).collect();
+        let page_versions = versions::get_versions_by_package_ids(conn, &package_ids);
+
+        let dependency_ids: Vec<i64> = page_versions
+            .iter()
+            .flat_map(|v| {
+                v.prod_dependencies
+                    .iter()
+                    .chain(&v.dev_dependencies)
+                    .chain(&v.peer_dependencies)
+                    .chain(&v.optional_dependencies)
+            })
+            .copied()
+            .filter(|id| exported_dependencies.insert(*id))
+            .collect();
+        let mut page_dependencies = dependencies::get_dependencies_by_ids(conn, &dependency_ids);
+        // Dependencies on a package get its id once it is created.
+        page_dependencies.extend(
+            dependencies::get_dependencies_by_dst_names(conn, names)
+                .into_iter()
+                .filter(|d| exported_dependencies.insert(d.id)),
+        );
+
+        let rows: Vec<PackageRow> = page.into_iter().map(Into::into).collect();
+        packages_writer.write(&rows);
+        let rows: Vec<VersionRow> = page_versions.into_iter().map(Into::into).collect();
+        versions_writer.write(&rows);
+        let rows: Vec<DependencyRow> = page_dependencies.into_iter().map(Into::into).collect();
+        dependencies_writer.write(&rows);
+    }
+
+    [
+        table_manifest("packages", &packages_schema, None, packages_writer.finish()),
+        table_manifest("versions", &versions_schema, None, versions_writer.finish()),
+        table_manifest(
+            "dependencies",
+            &dependencies_schema,
+            None,
+            dependencies_writer.finish(),
+        ),
+    ]
+    .into_iter()
+    .map(|table| TableManifest {
+        upsert_key: Some("id".to_string()),
+        ..table
+    })
+    .collect()
+}
+
+/// Checks that each of `deltas` is a delta of the export before it, starting with `snapshot`.
+pub fn check_delta_chain(snapshot: &Manifest, deltas: &[Manifest]) -> Result<(), String> {
+    if snapshot.base.is_some() {
+        return Err("The snapshot is a delta, not a full export".to_string());
+    }
+    let mut previous = snapshot;
+    for (i, delta) in deltas.iter().enumerate() {
+        let Some(base) = &delta.base else {
+            return Err(format!("Delta {} is a full export, not a delta", i + 1));
+        };
+        if delta.format_version != snapshot.format_version {
+            return Err(format!(
+                "Delta {} has format version {}, but the snapshot has format version {}",
+                i + 1,
+                delta.format_version,
+                snapshot.format_version
+            ));
+        }
+        if base.exported_at != previous.exported_at
+            || Some(base.relational_processed_seq) != previous.relational_processed_seq
+        {
+            return Err(format!(
+                "Delta {} is a delta of the export at {} (seq {}), but follows the export at {} (seq {:?})",
+                i + 1,
+                base.exported_at,
+                base.relational_processed_seq,
+                previous.exported_at,
+                previous.relational_processed_seq
+            ));
+        }
+        previous = delta;
+    }
+    Ok(())
+}
+
+/// Applies the chain of deltas in `delta_dirs` to the full export in `snapshot_dir`, writing a
+/// new full export to `out_dir`, as if it were exported at the same time as the last delta.
+/// Tables which are the same as in an input are copied, and upserted tables are rewritten, with
+/// rows not in order of their key.
+pub fn apply_deltas(
+    snapshot_dir: &Path,
+    delta_dirs: &[PathBuf],
+    out_dir: &Path,
+    rows_per_file: usize,
+) -> Result<Manifest, String> {
+    if out_dir.join(MANIFEST_FILE_NAME).exists() {
+        return Err(format!("{} already contains an export", out_dir.display()));
+    }
+    let snapshot = Manifest::read(snapshot_dir);
+    let deltas: Vec<Manifest> = delta_dirs.iter().map(|dir| Manifest::read(dir)).collect();
+    check_delta_chain(&snapshot, &deltas)?;
+    fs::create_dir_all(out_dir)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {}", out_dir.display(), e));
+
+    // Newest first
+    let mut exports: Vec<(&Path, &Manifest)> = delta_dirs
+        .iter()
+        .map(PathBuf::as_path)
+        .zip(&deltas)
+        .rev()
+        .collect();
+    exports.push((snapshot_dir, &snapshot));
+
+    let mut table_names: Vec<&str> = vec![];
+    for (_, manifest) in exports.iter().rev() {
+        for table in &manifest.tables {
+            if !table_names.contains(&table.name.as_str()) {
+                table_names.push(&table.name);
+            }
+        }
+    }
+
+    let tables = table_names
+        .into_iter()
+        .map(|name| apply_table(name, &exports, out_dir, rows_per_file))
+        .collect();
+
+    let last = deltas.last().unwrap_or(&snapshot);
+    let manifest = Manifest {
+        format_version: snapshot.format_version,
+        exported_at: last.exported_at,
+        relational_processed_seq: last.relational_processed_seq,
+        num_relational_rewinds: last.num_relational_rewinds,
+        base: None,
+        tables,
+    };
+    manifest.write(out_dir);
+    Ok(manifest)
+}
+
+/// Writes the table to `out_dir` from `exports`, newest first: the newest export with the whole
+/// table, and the upserts after it.
+fn apply_table(
+    name: &str,
+    exports: &[(&Path, &Manifest)],
+    out_dir: &Path,
+    rows_per_file: usize,
+) -> TableManifest {
+    let mut sources = vec![];
+    for (dir, manifest) in exports {
+        if let Some(table) = manifest.table(name) {
+            sources.push((*dir, table));
+            if table.upsert_key.is_none() {
+                break;
+            }
+        }
+    }
+
+    match sources.as_slice() {
+        [(dir, table)] if table.upsert_key.is_none() => copy_table(dir, table, out_dir),
+        _ => upsert_table(name, &sources, out_dir, rows_per_file),
+    }
+}
+
+fn copy_table(dir: &Path, table: &TableManifest, out_dir: &Path) -> TableManifest {
+    for file in &table.files {
+        let (from, to) = (dir.join(&file.path), out_dir.join(&file.path));
+        let parent = to.parent().unwrap();
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", parent.display(), e));
+        fs::copy(&from, &to).unwrap_or_else(|e| {
+            panic!(
+                "Failed to copy {} to {}: {}",
+                from.display(),
+                to.display(),
+                e
+            )
+        });
+    }
+    tracing::info!(
+        table = %table.name,
+        num_rows = table.num_rows,
+        "Copied table"
+    );
+    table.clone()
+}
+
+/// Writes the rows of `sources`, newest first, skipping rows whose key is in a newer source.
+fn upsert_table(
+    name: &str,
+    sources: &[(&Path, &TableManifest)],
+    out_dir: &Path,
+    rows_per_file: usize,
+) -> TableManifest {
+    let (newest_dir, newest) = sources[0];
+    let key = newest
+        .upsert_key
+        .as_deref()
+        .unwrap_or_else(|| panic!("Table {} has no upsert key", name));
+    let schema =
+        ParquetRecordBatchReaderBuilder::try_new(open(&newest_dir.join(&newest.files[0].path)))
+            .unwrap_or_else(|e| panic!("Failed to read table {}: {}", name, e))
+            .schema()
+            .clone();
+    let mut writer = PartWriter::new(out_dir, name.to_string(), schema.clone(), rows_per_file);
+
+    let mut newer_keys: HashSet<i64> = HashSet::new();
+    for (dir, table) in sources {
+        let mut keys = vec![];
+        for file in &table.files {
+            let path = dir.join(&file.path);
+            let reader = ParquetRecordBatchReaderBuilder::try_new(open(&path))
+                .and_then(|builder| builder.build())
+                .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+            for batch in reader {
+                let batch: RecordBatch =
+                    batch.unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+                let batch_keys = batch
+                    .column_by_name(key)
+                    .and_then(|c| c.as_any().downcast_ref::<Int64Array>())
+                    .unwrap_or_else(|| panic!("{} has no int64 column {}", path.display(), key));
+                let keep: BooleanArray = batch_keys
+                    .values()
+                    .iter()
+                    .map(|k| Some(!newer_keys.contains(k)))
+                    .collect();
+                if table.upsert_key.is_some() {
+                    keys.extend(batch_keys.values().iter().copied());
+                }
+                writer.write_batch(&filter_record_batch(&batch, &keep).expect("Invalid filter"));
+            }
+        }
+        newer_keys.extend(keys);
+    }
+
+    table_manifest(name, &schema, None, writer.finish())
+}
+
+fn open(path: &Path) -> File {
+    File::open(path).unwrap_or_else(|e| panic!("Failed to open {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use arrow::array::{Array, Int64Array};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use postgres_db::download_metrics::DailyDownloadCount;
+
+    use std::panic::{self, AssertUnwindSafe};
+
+    use postgres_db::connection::testing::using_test_db;
+    use postgres_db::internal_state;
+    use postgres_db::rewind::rewind_relational_tables;
+
+    use super::super::rows::DailyDownloadCountRow;
+    use super::super::{export_dataset, schema, table_manifest, PartWriter};
+    use super::{apply_deltas, check_delta_chain, export_delta, open};
+    use crate::manifest::{BaseManifest, Manifest, TableManifest, FORMAT_VERSION};
+
+    fn manifest(
+        exported_at: i64,
+        seq: i64,
+        base: Option<&Manifest>,
+        tables: Vec<TableManifest>,
+    ) -> Manifest {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            exported_at: Utc.timestamp_opt(exported_at, 0).unwrap(),
+            relational_processed_seq: Some(seq),
+            num_relational_rewinds: 0,
+            base: base.map(|b| BaseManifest {
+                exported_at: b.exported_at,
+                relational_processed_seq: b.relational_processed_seq.unwrap(),
+            }),
+            tables,
+        }
+    }
+
+    /// Writes a table of download counts on 2022-01-01, keyed by package id if `upsert`.
+    fn write_table(dir: &Path, name: &str, upsert: bool, counts: &[(i64, i64)]) -> TableManifest {
+        let schema = schema::download_counts_daily();
+        let mut writer = PartWriter::new(dir, name.to_string(), schema.clone(), 2);
+        let rows: Vec<DailyDownloadCountRow> = counts
+            .iter()
+            .map(|(package_id, count)| {
+                DailyDownloadCount {
+                    package_id: *package_id,
+                    day: NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(),
+                    count: *count,
+                }
+                .into()
+            })
+            .collect();
+        writer.write(&rows);
+        TableManifest {
+            upsert_key: upsert.then(|| "package_id".to_string()),
+            ..table_manifest(name, &schema, None, writer.finish())
+        }
+    }
+
+    fn read_counts(dir: &Path, table: &TableManifest) -> BTreeMap<i64, i64> {
+        let mut counts = BTreeMap::new();
+        for file in &table.files {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(open(&dir.join(&file.path)))
+                .unwrap()
+                .build()
+                .unwrap();
+            for batch in reader {
+                let batch = batch.unwrap();
+                let column = |i: usize| {
+                    batch
+                        .column(i)
+                        .as_any()
+                        .downcast_ref::<Int64Array>()
+                        .unwrap()
+                        .clone()
+                };
+                let (package_ids, day_counts) = (column(0), column(2));
+                for i in 0..batch.num_rows() {
+                    let previous = counts.insert(package_ids.value(i), day_counts.value(i));
+                    assert_eq!(previous, None);
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn test_check_delta_chain() {
+        let snapshot = manifest(1000, 10, None, vec![]);
+        let delta1 = manifest(2000, 20, Some(&snapshot), vec![]);
+        let delta2 = manifest(3000, 30, Some(&delta1), vec![]);
+
+        assert_eq!(check_delta_chain(&snapshot, &[]), Ok(()));
+        assert_eq!(
+            check_delta_chain(&snapshot, &[delta1.clone(), delta2.clone()]),
+            Ok(())
+        );
+        assert!(check_delta_chain(&snapshot, &[delta2.clone()]).is_err());
+        assert!(check_delta_chain(&snapshot, &[delta2.clone(), delta1.clone()]).is_err());
+        assert!(check_delta_chain(&delta1, &[delta2.clone()]).is_err());
+        assert!(check_delta_chain(&snapshot, &[delta1.clone(), snapshot.clone()]).is_err());
+
+        // A delta of a different export at the same seq
+        let other_snapshot = manifest(1500, 10, None, vec![]);
+        assert!(check_delta_chain(&other_snapshot, &[delta1]).is_err());
+    }
+
+    #[test]
+    fn test_apply_deltas() {
+        let (snapshot_dir, delta1_dir, delta2_dir, out_dir) = (
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+        );
+
+        let snapshot = manifest(
+            1000,
+            10,
+            None,
+            vec![
+                write_table(
+                    snapshot_dir.path(),
+                    "upserted",
+                    false,
+                    &[(1, 10), (2, 20), (3, 30)],
+                ),
+                write_table(snapshot_dir.path(), "replaced", false, &[(1, 10)]),
+                write_table(snapshot_dir.path(), "unchanged", false, &[(1, 10)]),
+            ],
+        );
+        snapshot.write(snapshot_dir.path());
+        let delta1 = manifest(
+            2000,
+            20,
+            Some(&snapshot),
+            vec![
+                write_table(delta1_dir.path(), "upserted", true, &[(2, 21), (4, 41)]),
+                write_table(delta1_dir.path(), "replaced", false, &[(2, 21)]),
+            ],
+        );
+        delta1.write(delta1_dir.path());
+        let delta2 = manifest(
+            3000,
+            30,
+            Some(&delta1),
+            vec![
+                write_table(delta2_dir.path(), "upserted", true, &[(2, 22)]),
+                write_table(delta2_dir.path(), "replaced", false, &[(3, 32)]),
+            ],
+        );
+        delta2.write(delta2_dir.path());
+
+        // Out of order
+        let delta_dirs = vec![delta2_dir.path().into(), delta1_dir.path().into()];
+        assert!(apply_deltas(snapshot_dir.path(), &delta_dirs, out_dir.path(), 2).is_err());
+
+        let delta_dirs = vec![delta1_dir.path().into(), delta2_dir.path().into()];
+        let applied = apply_deltas(snapshot_dir.path(), &delta_dirs, out_dir.path(), 2).unwrap();
+        assert_eq!(applied, Manifest::read(out_dir.path()));
+        assert_eq!(applied.exported_at, delta2.exported_at);
+        assert_eq!(applied.relational_processed_seq, Some(30));
+        assert_eq!(applied.base, None);
+
+        let names: Vec<&str> = applied.tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["upserted", "replaced", "unchanged"]);
+        let counts = |name: &str| read_counts(out_dir.path(), applied.table(name).unwrap());
+        assert_eq!(
+            counts("upserted"),
+            BTreeMap::from([(1, 10), (2, 22), (3, 30), (4, 41)])
+        );
+        assert_eq!(applied.table("upserted").unwrap().num_rows, 4);
+        assert_eq!(applied.table("upserted").unwrap().upsert_key, None);
+        assert_eq!(counts("replaced"), BTreeMap::from([(3, 32)]));
+        assert_eq!(counts("unchanged"), BTreeMap::from([(1, 10)]));
+
+        // The result can be the snapshot for later deltas
+        let delta3 = manifest(4000, 40, Some(&delta2), vec![]);
+        assert_eq!(check_delta_chain(&applied, &[delta3]), Ok(()));
+
+        // Won't overwrite an export
+        assert!(apply_deltas(snapshot_dir.path(), &delta_dirs, out_dir.path(), 2).is_err());
+    }
+
+    #[test]
+    fn test_delta_after_rewind() {
+        using_test_db(|conn| {
+            let dirs: Vec<_> = (0..5).map(|_| tempfile::tempdir().unwrap()).collect();
+            internal_state::set_relational_processed_seq(20, conn);
+            let full = export_dataset(conn, dirs[0].path(), 2);
+
+            internal_state::set_relational_processed_seq(30, conn);
+            let delta = export_delta(conn, &full, dirs[1].path(), 2);
+            assert_eq!(delta.relational_processed_seq, Some(30));
+
+            // Rewound to before the delta, and processed past it again
+            rewind_relational_tables(conn, 25);
+            internal_state::set_relational_processed_seq(40, conn);
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                export_delta(conn, &delta, dirs[2].path(), 2)
+            }));
+            assert!(res.is_err());
+
+            // The rewind was after the full export's seq, so deltas of it are fine
+            let delta = export_delta(conn, &full, dirs[3].path(), 2);
+            assert_eq!(delta.num_relational_rewinds, 1);
+            assert_eq!(delta.relational_processed_seq, Some(40));
+            let delta = export_delta(conn, &delta, dirs[4].path(), 2);
+            assert_eq!(delta.base.unwrap().relational_processed_seq, 40);
+        });
+    }
+}
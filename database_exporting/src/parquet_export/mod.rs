@@ -0,0 +1,488 @@
+//! Exports the relational tables to Parquet, for loading without Postgres, e.g. in DuckDB or
+//! Polars. Each table is written to a directory of numbered files, with a `manifest.json` listing
+//! the files, row counts and schemas.
+
+pub mod delta;
+mod rows;
+mod schema;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use arrow::datatypes::SchemaRef;
+use arrow::json::ReaderBuilder;
+use arrow::record_batch::RecordBatch;
+use chrono::{Datelike, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use postgres_db::connection::{DbConnection, QueryRunner};
+use postgres_db::{dependencies, download_metrics, ghsa, internal_state, packages, versions};
+use serde::Serialize;
+
+use crate::manifest::{schema_manifest, FileManifest, Manifest, TableManifest, FORMAT_VERSION};
+use rows::*;
+
+pub const DEFAULT_ROWS_PER_FILE: usize = 1_000_000;
+
+const PAGE_SIZE: i64 = 10_000;
+
+/// Download metrics are paged by fewer packages, since each has up to thousands of daily counts.
+const METRICS_PAGE_SIZE: i64 = 1_000;
+
+/// Exports the tables to `export_dir` and writes the manifest. All tables are read in one
+/// repeatable read transaction, so they are consistent with each other and with
+/// `relational_processed_seq`.
+pub fn export_dataset(
+    conn: &mut DbConnection,
+    export_dir: &Path,
+    rows_per_file: usize,
+) -> Manifest {
+    fs::create_dir_all(export_dir)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {}", export_dir.display(), e));
+    let exported_at = Utc::now();
+
+    let manifest = conn
+        .run_psql_transaction(|mut conn| {
+            conn.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")?;
+            let relational_processed_seq =
+                internal_state::query_relational_processed_seq(&mut conn);
+            let num_relational_rewinds = internal_state::query_relational_rewinds(&mut conn).len();
+
+            let mut tables = vec![
+                export_by_id::<_, _, PackageRow>(
+                    &mut conn,
+                    export_dir,
+                    rows_per_file,
+                    "packages",
+                    schema::packages(),
+                    packages::query_packages_after_id,
+                    |p| p.id,
+                ),
+                export_by_id::<_, _, VersionRow>(
+                    &mut conn,
+                    export_dir,
+                    rows_per_file,
+                    "versions",
+                    schema::versions(),
+                    versions::query_versions_after_id,
+                    |v| v.id,
+                ),
+                export_by_id::<_, _, DependencyRow>(
+                    &mut conn,
+                    export_dir,
+                    rows_per_file,
+                    "dependencies",
+                    schema::dependencies(),
+                    dependencies::query_dependencies_after_id,
+                    |d| d.id,
+                ),
+                export_ghsa(&mut conn, export_dir, rows_per_file),
+                export_by_id::<_, _, VulnerabilityRow>(
+                    &mut conn,
+                    export_dir,
+                    rows_per_file,
+                    "vulnerabilities",
+                    schema::vulnerabilities(),
+                    ghsa::query_vulnerabilities_after_id,
+                    |(id, _)| *id,
+                ),
+            ];
+            tables.extend(export_download_metrics(
+                &mut conn,
+                export_dir,
+                rows_per_file,
+            ));
+
+            let manifest = Manifest {
+                format_version: FORMAT_VERSION,
+                exported_at,
+                relational_processed_seq,
+                num_relational_rewinds,
+                base: None,
+                tables,
+            };
+            // read only, so there's nothing to commit
+            Ok((manifest, false))
+        })
+        .expect("Failed to export the tables");
+
+    manifest.write(export_dir);
+    manifest
+}
+
+/// Exports a table with an integer id, paging through it with `query_after_id`.
+fn export_by_id<R, T, Row>(
+    conn: &mut R,
+    export_dir: &Path,
+    rows_per_file: usize,
+    name: &str,
+    schema: SchemaRef,
+    query_after_id: fn(&mut R, i64, i64) -> Vec<T>,
+    id: fn(&T) -> i64,
+) -> TableManifest
+where
+    R: QueryRunner,
+    T: Into<Row>,
+    Row: Serialize,
+{
+    let mut writer = PartWriter::new(export_dir, name.to_string(), schema.clone(), rows_per_file);
+    let mut after_id = 0;
+    loop {
+        let page = query_after_id(conn, after_id, PAGE_SIZE);
+        let Some(last) = page.last() else {
+            break;
+        };
+        after_id = id(last);
+        let rows: Vec<Row> = page.into_iter().map(Into::into).collect();
+        writer.write(&rows);
+    }
+    table_manifest(name, &schema, None, writer.finish())
+}
+
+/// Exports `ghsa`, which is keyed by the advisory's id.
+fn export_ghsa<R: QueryRunner>(
+    conn: &mut R,
+    export_dir: &Path,
+    rows_per_file: usize,
+) -> TableManifest {
+    let schema = schema::ghsa();
+    let mut writer = PartWriter::new(
+        export_dir,
+        "ghsa".to_string(),
+        schema.clone(),
+        rows_per_file,
+    );
+    let mut after_id = String::new();
+    loop {
+        let page = ghsa::query_ghsa_after_id(conn, &after_id, PAGE_SIZE);
+        let Some(last) = page.last() else {
+            break;
+        };
+        after_id = last.id.clone();
+        let rows: Vec<GhsaRow> = page.into_iter().map(Into::into).collect();
+        writer.write(&rows);
+    }
+    table_manifest("ghsa", &schema, None, writer.finish())
+}
+
+/// Exports `download_metrics`, and the daily download counts and gaps of their packages. The
+/// daily counts are partitioned by year, like in Postgres.
+fn export_download_metrics<R: QueryRunner>(
+    conn: &mut R,
+    export_dir: &Path,
+    rows_per_file: usize,
+) -> Vec<TableManifest> {
+    let (metrics_schema, daily_schema, gaps_schema) = (
+        schema::download_metrics(),
+        schema::download_counts_daily(),
+        schema::download_count_gaps(),
+    );
+    let mut metrics_writer = PartWriter::new(
+        export_dir,
+        "download_metrics".to_string(),
+        metrics_schema.clone(),
+        rows_per_file,
+    );
+    let mut gaps_writer = PartWriter::new(
+        export_dir,
+        "download_count_gaps".to_string(),
+        gaps_schema.clone(),
+        rows_per_file,
+    );
+    let mut daily_writers: BTreeMap<i32, PartWriter> = BTreeMap::new();
+
+    let mut after_id = 0;
+    loop {
+        let page =
+            download_metrics::query_download_metrics_after_id(conn, after_id, METRICS_PAGE_SIZE);
+        let Some(last) = page.last() else {
+            break;
+        };
+        after_id = last.id;
+        let package_ids: Vec<i64> = page.iter().map(|m| m.package_id).collect();
+
+        let mut daily_by_year: BTreeMap<i32, Vec<DailyDownloadCountRow>> = BTreeMap::new();
+        for count in
+            download_metrics::query_daily_download_counts_by_package_ids(conn, &package_ids)
+        {
+            daily_by_year
+                .entry(count.day.year())
+                .or_default()
+                .push(count.into());
+        }
+        for (year, rows) in daily_by_year {
+            daily_writers
+                .entry(year)
+                .or_insert_with(|| {
+                    PartWriter::new(
+                        export_dir,
+                        format!("download_counts_daily/year={}", year),
+                        daily_schema.clone(),
+                        rows_per_file,
+                    )
+                })
+                .write(&rows);
+        }
+
+        let gaps: Vec<DownloadCountGapRow> =
+            download_metrics::query_download_count_gaps_by_package_ids(conn, &package_ids)
+                .into_iter()
+                .map(Into::into)
+                .collect();
+        gaps_writer.write(&gaps);
+
+        let metrics: Vec<DownloadMetricRow> = page.into_iter().map(Into::into).collect();
+        metrics_writer.write(&metrics);
+    }
+
+    let daily_files = daily_writers
+        .into_values()
+        .flat_map(|w| w.finish())
+        .collect();
+    vec![
+        table_manifest(
+            "download_metrics",
+            &metrics_schema,
+            None,
+            metrics_writer.finish(),
+        ),
+        table_manifest(
+            "download_counts_daily",
+            &daily_schema,
+            Some("year"),
+            daily_files,
+        ),
+        table_manifest(
+            "download_count_gaps",
+            &gaps_schema,
+            None,
+            gaps_writer.finish(),
+        ),
+    ]
+}
+
+fn table_manifest(
+    name: &str,
+    schema: &SchemaRef,
+    partitioned_by: Option<&str>,
+    files: Vec<FileManifest>,
+) -> TableManifest {
+    let num_rows = files.iter().map(|f| f.num_rows).sum();
+    tracing::info!(
+        table = name,
+        num_rows,
+        num_files = files.len(),
+        "Exported table"
+    );
+    TableManifest {
+        name: name.to_string(),
+        num_rows,
+        partitioned_by: partitioned_by.map(|p| p.to_string()),
+        upsert_key: None,
+        files,
+        schema: schema_manifest(schema),
+    }
+}
+
+/// Writes rows to numbered Parquet files in a directory, starting a new file every
+/// `rows_per_file` rows.
+pub(crate) struct PartWriter {
+    export_dir: PathBuf,
+    /// Relative to `export_dir`.
+    dir: String,
+    schema: SchemaRef,
+    rows_per_file: usize,
+    current: Option<(ArrowWriter<File>, FileManifest)>,
+    files: Vec<FileManifest>,
+}
+
+impl PartWriter {
+    pub(crate) fn new(
+        export_dir: &Path,
+        dir: String,
+        schema: SchemaRef,
+        rows_per_file: usize,
+    ) -> PartWriter {
+        let path = export_dir.join(&dir);
+        fs::create_dir_all(&path)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", path.display(), e));
+        PartWriter {
+            export_dir: export_dir.to_path_buf(),
+            dir,
+            schema,
+            rows_per_file,
+            current: None,
+            files: vec![],
+        }
+    }
+
+    pub(crate) fn write<S: Serialize>(&mut self, rows: &[S]) {
+        if !rows.is_empty() {
+            self.write_batch(&record_batch(&self.schema, rows));
+        }
+    }
+
+    pub(crate) fn write_batch(&mut self, batch: &RecordBatch) {
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            if self.current.is_none() {
+                self.open_next_file();
+            }
+            let (writer, file) = self.current.as_mut().unwrap();
+            let n = (batch.num_rows() - offset).min(self.rows_per_file - file.num_rows as usize);
+            writer
+                .write(&batch.slice(offset, n))
+                .unwrap_or_else(|e| panic!("Failed to write {}: {}", file.path, e));
+            file.num_rows += n as u64;
+            offset += n;
+
+            if file.num_rows as usize >= self.rows_per_file {
+                self.close_current_file();
+            }
+        }
+    }
+
+    /// Closes the last file and returns all the files written. Writes an empty file if there
+    /// were no rows, so that the schema is still there.
+    pub(crate) fn finish(mut self) -> Vec<FileManifest> {
+        if self.current.is_none() && self.files.is_empty() {
+            self.open_next_file();
+        }
+        self.close_current_file();
+        self.files
+    }
+
+    fn open_next_file(&mut self) {
+        let path = format!("{}/part-{:05}.parquet", self.dir, self.files.len());
+        let file = File::create(self.export_dir.join(&path))
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", path, e));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(file, self.schema.clone(), Some(props))
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", path, e));
+        self.current = Some((writer, FileManifest { path, num_rows: 0 }));
+    }
+
+    fn close_current_file(&mut self) {
+        if let Some((writer, file)) = self.current.take() {
+            writer
+                .close()
+                .unwrap_or_else(|e| panic!("Failed to write {}: {}", file.path, e));
+            self.files.push(file);
+        }
+    }
+}
+
+/// Converts rows to a record batch, by serializing them like JSON objects.
+fn record_batch<S: Serialize>(schema: &SchemaRef, rows: &[S]) -> RecordBatch {
+    let mut decoder = ReaderBuilder::new(schema.clone())
+        .with_batch_size(rows.len().max(1))
+        .build_decoder()
+        .expect("Invalid schema");
+    decoder
+        .serialize(rows)
+        .expect("Rows don't match the schema");
+    decoder
+        .flush()
+        .expect("Rows don't match the schema")
+        .unwrap_or_else(|| RecordBatch::new_empty(schema.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use arrow::array::{Array, Date32Array, Int64Array};
+    use chrono::NaiveDate;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use postgres_db::download_metrics::DailyDownloadCount;
+
+    use super::rows::DailyDownloadCountRow;
+    use super::{schema, PartWriter, DEFAULT_ROWS_PER_FILE};
+    use crate::manifest::FileManifest;
+
+    #[test]
+    fn test_part_writer_rolls_over_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = PartWriter::new(
+            dir.path(),
+            "download_counts_daily/year=2022".to_string(),
+            schema::download_counts_daily(),
+            2,
+        );
+        let rows: Vec<DailyDownloadCountRow> = (1..=5)
+            .map(|d| {
+                DailyDownloadCount {
+                    package_id: 1,
+                    day: NaiveDate::from_ymd_opt(2022, 1, d).unwrap(),
+                    count: d as i64 * 10,
+                }
+                .into()
+            })
+            .collect();
+        writer.write(&rows[..3]);
+        writer.write(&rows[3..]);
+
+        let files = writer.finish();
+        assert_eq!(
+            files,
+            vec![
+                FileManifest {
+                    path: "download_counts_daily/year=2022/part-00000.parquet".to_string(),
+                    num_rows: 2
+                },
+                FileManifest {
+                    path: "download_counts_daily/year=2022/part-00001.parquet".to_string(),
+                    num_rows: 2
+                },
+                FileManifest {
+                    path: "download_counts_daily/year=2022/part-00002.parquet".to_string(),
+                    num_rows: 1
+                },
+            ]
+        );
+
+        let file = File::open(dir.path().join(&files[2].path)).unwrap();
+        let batches = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let days = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<Date32Array>()
+            .unwrap();
+        assert_eq!(days.value_as_date(0), NaiveDate::from_ymd_opt(2022, 1, 5));
+        let counts = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(counts.value(0), 50);
+        assert_eq!(counts.len(), 1);
+    }
+
+    #[test]
+    fn test_part_writer_writes_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = PartWriter::new(
+            dir.path(),
+            "ghsa".to_string(),
+            schema::ghsa(),
+            DEFAULT_ROWS_PER_FILE,
+        );
+        let files = writer.finish();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].num_rows, 0);
+
+        let file = File::open(dir.path().join(&files[0].path)).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(builder.schema().fields().len(), 11);
+    }
+}
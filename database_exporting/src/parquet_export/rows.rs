@@ -0,0 +1,504 @@
+//! Rows of the exported tables, shaped like their Arrow schemas in `schema.rs`. Timestamps are
+//! microseconds since the epoch and dates are days since the epoch.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use postgres_db::custom_types::{
+    AliasSubspec, DownloadCount, PackageStateTimePoint, PackageStateType, ParsedSpec,
+    PrereleaseTag, RepoHostInfo, RepoInfo, Semver, Vcs, VersionComparator, VersionConstraint,
+    VersionStateTimePoint, VersionStateType,
+};
+use postgres_db::dependencies::Dependency;
+use postgres_db::download_metrics::{DailyDownloadCount, DownloadCountGap, FullDownloadMetric};
+use postgres_db::ghsa::{Ghsa, GhsaVulnerability};
+use postgres_db::packages::Package;
+use postgres_db::versions::Version;
+use serde::Serialize;
+use serde_json::Value;
+
+fn micros(t: DateTime<Utc>) -> i64 {
+    t.timestamp_micros()
+}
+
+fn days(d: NaiveDate) -> i32 {
+    (d - NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()).num_days() as i32
+}
+
+fn json(v: Value) -> String {
+    v.to_string()
+}
+
+#[derive(Serialize)]
+pub struct PrereleaseTagRow {
+    tag_type: &'static str,
+    string_case: Option<String>,
+    int_case: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SemverRow {
+    major: i64,
+    minor: i64,
+    bug: i64,
+    prerelease: Vec<PrereleaseTagRow>,
+    build: Vec<String>,
+}
+
+impl From<Semver> for SemverRow {
+    fn from(v: Semver) -> Self {
+        SemverRow {
+            major: v.major,
+            minor: v.minor,
+            bug: v.bug,
+            prerelease: v
+                .prerelease
+                .into_iter()
+                .map(|tag| match tag {
+                    PrereleaseTag::String(s) => PrereleaseTagRow {
+                        tag_type: "string",
+                        string_case: Some(s),
+                        int_case: None,
+                    },
+                    PrereleaseTag::Int(i) => PrereleaseTagRow {
+                        tag_type: "int",
+                        string_case: None,
+                        int_case: Some(i),
+                    },
+                })
+                .collect(),
+            build: v.build,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VersionComparatorRow {
+    operator: &'static str,
+    semver: Option<SemverRow>,
+}
+
+impl From<VersionComparator> for VersionComparatorRow {
+    fn from(c: VersionComparator) -> Self {
+        let (operator, v) = match c {
+            VersionComparator::Any => ("*", None),
+            VersionComparator::Eq(v) => ("=", Some(v)),
+            VersionComparator::Gt(v) => (">", Some(v)),
+            VersionComparator::Gte(v) => (">=", Some(v)),
+            VersionComparator::Lt(v) => ("<", Some(v)),
+            VersionComparator::Lte(v) => ("<=", Some(v)),
+        };
+        VersionComparatorRow {
+            operator,
+            semver: v.map(SemverRow::from),
+        }
+    }
+}
+
+fn disjuncts(c: VersionConstraint) -> Vec<Vec<VersionComparatorRow>> {
+    c.0.into_iter()
+        .map(|conjuncts| conjuncts.into_iter().map(Into::into).collect())
+        .collect()
+}
+
+#[derive(Serialize, Default)]
+pub struct ParsedSpecRow {
+    dep_type: &'static str,
+    range_disjuncts: Option<Vec<Vec<VersionComparatorRow>>>,
+    tag_name: Option<String>,
+    git_spec: Option<String>,
+    remote_url: Option<String>,
+    alias_package_name: Option<String>,
+    alias_package_id_if_exists: Option<i64>,
+    alias_subdep_type: Option<&'static str>,
+    alias_subdep_range_disjuncts: Option<Vec<Vec<VersionComparatorRow>>>,
+    alias_subdep_tag_name: Option<String>,
+    file_path: Option<String>,
+    directory_path: Option<String>,
+    invalid_message: Option<String>,
+}
+
+impl From<ParsedSpec> for ParsedSpecRow {
+    fn from(spec: ParsedSpec) -> Self {
+        match spec {
+            ParsedSpec::Range(c) => ParsedSpecRow {
+                dep_type: "range",
+                range_disjuncts: Some(disjuncts(c)),
+                ..Default::default()
+            },
+            ParsedSpec::Tag(tag) => ParsedSpecRow {
+                dep_type: "tag",
+                tag_name: Some(tag),
+                ..Default::default()
+            },
+            ParsedSpec::Git(git) => ParsedSpecRow {
+                dep_type: "git",
+                git_spec: Some(git),
+                ..Default::default()
+            },
+            ParsedSpec::Remote(url) => ParsedSpecRow {
+                dep_type: "remote",
+                remote_url: Some(url),
+                ..Default::default()
+            },
+            ParsedSpec::Alias(name, id, AliasSubspec::Range(c)) => ParsedSpecRow {
+                dep_type: "alias",
+                alias_package_name: Some(name),
+                alias_package_id_if_exists: id,
+                alias_subdep_type: Some("range"),
+                alias_subdep_range_disjuncts: Some(disjuncts(c)),
+                ..Default::default()
+            },
+            ParsedSpec::Alias(name, id, AliasSubspec::Tag(tag)) => ParsedSpecRow {
+                dep_type: "alias",
+                alias_package_name: Some(name),
+                alias_package_id_if_exists: id,
+                alias_subdep_type: Some("tag"),
+                alias_subdep_tag_name: Some(tag),
+                ..Default::default()
+            },
+            ParsedSpec::File(path) => ParsedSpecRow {
+                dep_type: "file",
+                file_path: Some(path),
+                ..Default::default()
+            },
+            ParsedSpec::Directory(path) => ParsedSpecRow {
+                dep_type: "directory",
+                directory_path: Some(path),
+                ..Default::default()
+            },
+            ParsedSpec::Invalid(message) => ParsedSpecRow {
+                dep_type: "invalid",
+                invalid_message: Some(message),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RepoInfoRow {
+    cloneable_repo_url: String,
+    cloneable_repo_dir: String,
+    vcs: &'static str,
+    host: &'static str,
+    github_bitbucket_gitlab_user: Option<String>,
+    github_bitbucket_gitlab_repo: Option<String>,
+    gist_id: Option<String>,
+}
+
+impl From<RepoInfo> for RepoInfoRow {
+    fn from(info: RepoInfo) -> Self {
+        let (host, user, repo, gist_id) = match info.host_info {
+            RepoHostInfo::Github { user, repo } => ("github", Some(user), Some(repo), None),
+            RepoHostInfo::Bitbucket { user, repo } => ("bitbucket", Some(user), Some(repo), None),
+            RepoHostInfo::Gitlab { user, repo } => ("gitlab", Some(user), Some(repo), None),
+            RepoHostInfo::Gist { id } => ("gist", None, None, Some(id)),
+            RepoHostInfo::Thirdparty => ("3rdparty", None, None, None),
+        };
+        RepoInfoRow {
+            cloneable_repo_url: info.cloneable_repo_url,
+            cloneable_repo_dir: info.cloneable_repo_dir,
+            vcs: match info.vcs {
+                Vcs::Git => "git",
+            },
+            host,
+            github_bitbucket_gitlab_user: user,
+            github_bitbucket_gitlab_repo: repo,
+            gist_id,
+        }
+    }
+}
+
+fn package_state_label(state: &PackageStateType) -> &'static str {
+    match state {
+        PackageStateType::Normal => "normal",
+        PackageStateType::Unpublished => "unpublished",
+        PackageStateType::Deleted => "deleted",
+    }
+}
+
+fn version_state_label(state: &VersionStateType) -> &'static str {
+    match state {
+        VersionStateType::Normal => "normal",
+        VersionStateType::Unpublished => "unpublished",
+        VersionStateType::Deleted => "deleted",
+    }
+}
+
+#[derive(Serialize)]
+pub struct PackageStateRow {
+    package_state_type: &'static str,
+    seq: i64,
+    diff_entry_id: i64,
+    estimated_time: Option<i64>,
+}
+
+impl From<PackageStateTimePoint> for PackageStateRow {
+    fn from(p: PackageStateTimePoint) -> Self {
+        PackageStateRow {
+            package_state_type: package_state_label(&p.state),
+            seq: p.seq,
+            diff_entry_id: p.diff_entry_id,
+            estimated_time: p.estimated_time.map(micros),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VersionStateRow {
+    version_state_type: &'static str,
+    seq: i64,
+    diff_entry_id: i64,
+    estimated_time: Option<i64>,
+}
+
+impl From<VersionStateTimePoint> for VersionStateRow {
+    fn from(p: VersionStateTimePoint) -> Self {
+        VersionStateRow {
+            version_state_type: version_state_label(&p.state),
+            seq: p.seq,
+            diff_entry_id: p.diff_entry_id,
+            estimated_time: p.estimated_time.map(micros),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PackageRow {
+    id: i64,
+    name: String,
+    current_package_state_type: &'static str,
+    package_state_history: Vec<PackageStateRow>,
+    dist_tag_latest_version: Option<i64>,
+    created: Option<i64>,
+    modified: Option<i64>,
+    other_dist_tags: Option<String>,
+    other_time_data: Option<String>,
+    unpublished_data: Option<String>,
+}
+
+impl From<Package> for PackageRow {
+    fn from(p: Package) -> Self {
+        PackageRow {
+            id: p.id,
+            name: p.name,
+            current_package_state_type: package_state_label(&p.current_package_state_type),
+            package_state_history: p
+                .package_state_history
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            dist_tag_latest_version: p.dist_tag_latest_version,
+            created: p.created.map(micros),
+            modified: p.modified.map(micros),
+            other_dist_tags: p.other_dist_tags.map(json),
+            other_time_data: p.other_time_data.map(json),
+            unpublished_data: p.unpublished_data.map(json),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VersionRow {
+    id: i64,
+    package_id: i64,
+    semver: SemverRow,
+    current_version_state_type: &'static str,
+    version_state_history: Vec<VersionStateRow>,
+    tarball_url: String,
+    repository_raw: Option<String>,
+    repository_parsed: Option<RepoInfoRow>,
+    created: i64,
+    extra_metadata: String,
+    prod_dependencies: Vec<i64>,
+    dev_dependencies: Vec<i64>,
+    peer_dependencies: Vec<i64>,
+    optional_dependencies: Vec<i64>,
+}
+
+impl From<Version> for VersionRow {
+    fn from(v: Version) -> Self {
+        VersionRow {
+            id: v.id,
+            package_id: v.package_id,
+            semver: v.semver.into(),
+            current_version_state_type: version_state_label(&v.current_version_state_type),
+            version_state_history: v
+                .version_state_history
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            tarball_url: v.tarball_url,
+            repository_raw: v.repository_raw.map(json),
+            repository_parsed: v.repository_parsed.map(Into::into),
+            created: micros(v.created),
+            extra_metadata: json(v.extra_metadata),
+            prod_dependencies: v.prod_dependencies,
+            dev_dependencies: v.dev_dependencies,
+            peer_dependencies: v.peer_dependencies,
+            optional_dependencies: v.optional_dependencies,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DependencyRow {
+    id: i64,
+    dst_package_name: String,
+    dst_package_id_if_exists: Option<i64>,
+    raw_spec: String,
+    spec: ParsedSpecRow,
+    prod_freq_count: i64,
+    dev_freq_count: i64,
+    peer_freq_count: i64,
+    optional_freq_count: i64,
+    md5digest: String,
+    md5digest_with_version: String,
+}
+
+impl From<Dependency> for DependencyRow {
+    fn from(d: Dependency) -> Self {
+        DependencyRow {
+            id: d.id,
+            dst_package_name: d.dst_package_name,
+            dst_package_id_if_exists: d.dst_package_id_if_exists,
+            raw_spec: json(d.raw_spec),
+            spec: d.spec.into(),
+            prod_freq_count: d.prod_freq_count,
+            dev_freq_count: d.dev_freq_count,
+            peer_freq_count: d.peer_freq_count,
+            optional_freq_count: d.optional_freq_count,
+            md5digest: d.md5digest,
+            md5digest_with_version: d.md5digest_with_version,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GhsaRow {
+    id: String,
+    severity: String,
+    description: String,
+    summary: String,
+    withdrawn_at: Option<i64>,
+    published_at: i64,
+    updated_at: i64,
+    refs: Vec<String>,
+    cvss_score: Option<f32>,
+    cvss_vector: Option<String>,
+    source: String,
+}
+
+impl From<Ghsa> for GhsaRow {
+    fn from(g: Ghsa) -> Self {
+        GhsaRow {
+            id: g.id,
+            severity: g.severity,
+            description: g.description,
+            summary: g.summary,
+            withdrawn_at: g.withdrawn_at.map(micros),
+            published_at: micros(g.published_at),
+            updated_at: micros(g.updated_at),
+            refs: g.refs,
+            cvss_score: g.cvss_score,
+            cvss_vector: g.cvss_vector,
+            source: g.source,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VulnerabilityRow {
+    id: i64,
+    ghsa_id: String,
+    package_name: String,
+    vulnerable_version_lower_bound: Option<SemverRow>,
+    vulnerable_version_lower_bound_inclusive: bool,
+    vulnerable_version_upper_bound: Option<SemverRow>,
+    vulnerable_version_upper_bound_inclusive: bool,
+    first_patched_version: Option<SemverRow>,
+    vulnerable_version_constraint: Vec<Vec<VersionComparatorRow>>,
+}
+
+impl From<(i64, GhsaVulnerability)> for VulnerabilityRow {
+    fn from((id, v): (i64, GhsaVulnerability)) -> Self {
+        VulnerabilityRow {
+            id,
+            ghsa_id: v.ghsa_id,
+            package_name: v.package_name,
+            vulnerable_version_lower_bound: v.vulnerable_version_lower_bound.map(Into::into),
+            vulnerable_version_lower_bound_inclusive: v.vulnerable_version_lower_bound_inclusive,
+            vulnerable_version_upper_bound: v.vulnerable_version_upper_bound.map(Into::into),
+            vulnerable_version_upper_bound_inclusive: v.vulnerable_version_upper_bound_inclusive,
+            first_patched_version: v.first_patched_version.map(Into::into),
+            vulnerable_version_constraint: disjuncts(v.vulnerable_version_constraint),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DownloadCountRow {
+    date: i32,
+    count: Option<i64>,
+}
+
+impl From<DownloadCount> for DownloadCountRow {
+    fn from(c: DownloadCount) -> Self {
+        DownloadCountRow {
+            date: days(c.date),
+            count: c.count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DownloadMetricRow {
+    id: i64,
+    package_id: i64,
+    download_counts: Vec<DownloadCountRow>,
+    latest_date: i32,
+}
+
+impl From<FullDownloadMetric> for DownloadMetricRow {
+    fn from(m: FullDownloadMetric) -> Self {
+        DownloadMetricRow {
+            id: m.id,
+            package_id: m.package_id,
+            download_counts: m.download_counts.into_iter().map(Into::into).collect(),
+            latest_date: days(m.latest_date),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DailyDownloadCountRow {
+    package_id: i64,
+    day: i32,
+    count: i64,
+}
+
+impl From<DailyDownloadCount> for DailyDownloadCountRow {
+    fn from(c: DailyDownloadCount) -> Self {
+        DailyDownloadCountRow {
+            package_id: c.package_id,
+            day: days(c.day),
+            count: c.count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DownloadCountGapRow {
+    package_id: i64,
+    start_date: i32,
+    end_date: i32,
+}
+
+impl From<DownloadCountGap> for DownloadCountGapRow {
+    fn from(g: DownloadCountGap) -> Self {
+        DownloadCountGapRow {
+            package_id: g.package_id,
+            start_date: days(g.start_date),
+            end_date: days(g.end_date),
+        }
+    }
+}
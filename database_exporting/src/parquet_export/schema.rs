@@ -0,0 +1,231 @@
+//! The Arrow schemas of the exported tables. Composite Postgres types become structs with the
+//! same fields, arrays become lists, enums become their Postgres labels, and `jsonb` becomes
+//! JSON text.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+
+fn list(item: DataType, nullable: bool) -> DataType {
+    DataType::List(Arc::new(Field::new("item", item, nullable)))
+}
+
+fn timestamp() -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+}
+
+fn json(name: &str, nullable: bool) -> Field {
+    Field::new(name, DataType::Utf8, nullable).with_metadata(HashMap::from([(
+        "encoding".to_string(),
+        "json".to_string(),
+    )]))
+}
+
+/// `prerelease_tag_struct`
+fn prerelease_tag() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("tag_type", DataType::Utf8, false),
+        Field::new("string_case", DataType::Utf8, true),
+        Field::new("int_case", DataType::Int64, true),
+    ]))
+}
+
+/// `semver_struct`
+fn semver() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("major", DataType::Int64, false),
+        Field::new("minor", DataType::Int64, false),
+        Field::new("bug", DataType::Int64, false),
+        Field::new("prerelease", list(prerelease_tag(), false), false),
+        Field::new("build", list(DataType::Utf8, false), false),
+    ]))
+}
+
+/// `version_comparator_struct`
+fn version_comparator() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("operator", DataType::Utf8, false),
+        Field::new("semver", semver(), true),
+    ]))
+}
+
+/// `constraint_disjuncts`: a list of disjuncts, each a list of conjunct comparators.
+fn constraint_disjuncts() -> DataType {
+    list(list(version_comparator(), false), false)
+}
+
+/// `parsed_spec_struct`
+fn parsed_spec() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("dep_type", DataType::Utf8, false),
+        Field::new("range_disjuncts", constraint_disjuncts(), true),
+        Field::new("tag_name", DataType::Utf8, true),
+        Field::new("git_spec", DataType::Utf8, true),
+        Field::new("remote_url", DataType::Utf8, true),
+        Field::new("alias_package_name", DataType::Utf8, true),
+        Field::new("alias_package_id_if_exists", DataType::Int64, true),
+        Field::new("alias_subdep_type", DataType::Utf8, true),
+        Field::new("alias_subdep_range_disjuncts", constraint_disjuncts(), true),
+        Field::new("alias_subdep_tag_name", DataType::Utf8, true),
+        Field::new("file_path", DataType::Utf8, true),
+        Field::new("directory_path", DataType::Utf8, true),
+        Field::new("invalid_message", DataType::Utf8, true),
+    ]))
+}
+
+/// `repo_info_struct`
+fn repo_info() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("cloneable_repo_url", DataType::Utf8, false),
+        Field::new("cloneable_repo_dir", DataType::Utf8, false),
+        Field::new("vcs", DataType::Utf8, false),
+        Field::new("host", DataType::Utf8, false),
+        Field::new("github_bitbucket_gitlab_user", DataType::Utf8, true),
+        Field::new("github_bitbucket_gitlab_repo", DataType::Utf8, true),
+        Field::new("gist_id", DataType::Utf8, true),
+    ]))
+}
+
+/// `package_state_struct` or `version_state_struct`
+fn state(state_type_field: &str) -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new(state_type_field, DataType::Utf8, false),
+        Field::new("seq", DataType::Int64, false),
+        Field::new("diff_entry_id", DataType::Int64, false),
+        Field::new("estimated_time", timestamp(), true),
+    ]))
+}
+
+/// `download_count_struct`
+fn download_count() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("date", DataType::Date32, false),
+        Field::new("count", DataType::Int64, true),
+    ]))
+}
+
+pub fn packages() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("current_package_state_type", DataType::Utf8, false),
+        Field::new(
+            "package_state_history",
+            list(state("package_state_type"), false),
+            false,
+        ),
+        Field::new("dist_tag_latest_version", DataType::Int64, true),
+        Field::new("created", timestamp(), true),
+        Field::new("modified", timestamp(), true),
+        json("other_dist_tags", true),
+        json("other_time_data", true),
+        json("unpublished_data", true),
+    ]))
+}
+
+pub fn versions() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("package_id", DataType::Int64, false),
+        Field::new("semver", semver(), false),
+        Field::new("current_version_state_type", DataType::Utf8, false),
+        Field::new(
+            "version_state_history",
+            list(state("version_state_type"), false),
+            false,
+        ),
+        Field::new("tarball_url", DataType::Utf8, false),
+        json("repository_raw", true),
+        Field::new("repository_parsed", repo_info(), true),
+        Field::new("created", timestamp(), false),
+        json("extra_metadata", false),
+        Field::new("prod_dependencies", list(DataType::Int64, false), false),
+        Field::new("dev_dependencies", list(DataType::Int64, false), false),
+        Field::new("peer_dependencies", list(DataType::Int64, false), false),
+        Field::new("optional_dependencies", list(DataType::Int64, false), false),
+    ]))
+}
+
+pub fn dependencies() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("dst_package_name", DataType::Utf8, false),
+        Field::new("dst_package_id_if_exists", DataType::Int64, true),
+        json("raw_spec", false),
+        Field::new("spec", parsed_spec(), false),
+        Field::new("prod_freq_count", DataType::Int64, false),
+        Field::new("dev_freq_count", DataType::Int64, false),
+        Field::new("peer_freq_count", DataType::Int64, false),
+        Field::new("optional_freq_count", DataType::Int64, false),
+        Field::new("md5digest", DataType::Utf8, false),
+        Field::new("md5digest_with_version", DataType::Utf8, false),
+    ]))
+}
+
+pub fn ghsa() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("severity", DataType::Utf8, false),
+        Field::new("description", DataType::Utf8, false),
+        Field::new("summary", DataType::Utf8, false),
+        Field::new("withdrawn_at", timestamp(), true),
+        Field::new("published_at", timestamp(), false),
+        Field::new("updated_at", timestamp(), false),
+        Field::new("refs", list(DataType::Utf8, false), false),
+        Field::new("cvss_score", DataType::Float32, true),
+        Field::new("cvss_vector", DataType::Utf8, true),
+        Field::new("source", DataType::Utf8, false),
+    ]))
+}
+
+pub fn vulnerabilities() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("ghsa_id", DataType::Utf8, false),
+        Field::new("package_name", DataType::Utf8, false),
+        Field::new("vulnerable_version_lower_bound", semver(), true),
+        Field::new(
+            "vulnerable_version_lower_bound_inclusive",
+            DataType::Boolean,
+            false,
+        ),
+        Field::new("vulnerable_version_upper_bound", semver(), true),
+        Field::new(
+            "vulnerable_version_upper_bound_inclusive",
+            DataType::Boolean,
+            false,
+        ),
+        Field::new("first_patched_version", semver(), true),
+        Field::new(
+            "vulnerable_version_constraint",
+            constraint_disjuncts(),
+            false,
+        ),
+    ]))
+}
+
+pub fn download_metrics() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("package_id", DataType::Int64, false),
+        Field::new("download_counts", list(download_count(), false), false),
+        Field::new("latest_date", DataType::Date32, false),
+    ]))
+}
+
+pub fn download_counts_daily() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("package_id", DataType::Int64, false),
+        Field::new("day", DataType::Date32, false),
+        Field::new("count", DataType::Int64, false),
+    ]))
+}
+
+pub fn download_count_gaps() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("package_id", DataType::Int64, false),
+        Field::new("start_date", DataType::Date32, false),
+        Field::new("end_date", DataType::Date32, false),
+    ]))
+}
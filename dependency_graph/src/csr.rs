@@ -0,0 +1,49 @@
+use crate::edges::{Edge, RawEdge};
+
+/// Edges in compressed sparse row form: the edges of node `n` are
+/// `edges[offsets[n]..offsets[n + 1]]`.
+pub(crate) struct Csr {
+    pub(crate) offsets: Vec<u64>,
+    pub(crate) edges: Vec<Edge>,
+}
+
+impl Csr {
+    /// Builds from edges sorted by dependency.
+    pub(crate) fn from_sorted(edges: &[RawEdge]) -> Csr {
+        let num_nodes = edges.last().map_or(0, |e| e.dependency as usize + 1);
+        let mut offsets = Vec::with_capacity(num_nodes + 1);
+        offsets.push(0);
+        for (i, e) in edges.iter().enumerate() {
+            assert!(
+                e.dependency as usize + 1 >= offsets.len(),
+                "Edges must be sorted by dependency"
+            );
+            while offsets.len() <= e.dependency as usize {
+                offsets.push(i as u64);
+            }
+        }
+        while offsets.len() <= num_nodes {
+            offsets.push(edges.len() as u64);
+        }
+        Csr {
+            offsets,
+            edges: edges.iter().map(|e| e.edge).collect(),
+        }
+    }
+
+    pub(crate) fn num_nodes(&self) -> u32 {
+        (self.offsets.len() - 1) as u32
+    }
+
+    pub(crate) fn num_edges(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub(crate) fn edges(&self, node: u32) -> &[Edge] {
+        let node = node as usize;
+        if node + 1 >= self.offsets.len() {
+            return &[];
+        }
+        &self.edges[self.offsets[node] as usize..self.offsets[node + 1] as usize]
+    }
+}
@@ -0,0 +1,143 @@
+//! Building the graph from the relational tables and keeping it up to date.
+
+use std::collections::{HashMap, HashSet};
+
+use postgres_db::connection::QueryRunner;
+use postgres_db::dependencies::{
+    get_dependency_dsts_by_ids, get_dependency_ids_by_dst_name, query_dependency_dsts_after_id,
+};
+use postgres_db::diff_log::DiffLogInstruction;
+use postgres_db::packages::maybe_get_package_id_by_name;
+use postgres_db::versions::{
+    get_package_ids_with_any_dependency, get_version_dependencies_by_package_ids,
+    query_version_dependencies_after_id, VersionDependencies,
+};
+
+use crate::csr::Csr;
+use crate::edges::{merge_edges, version_edges, RawEdge};
+use crate::DependencyGraph;
+
+const PAGE_SIZE: i64 = 10_000;
+
+/// How many packages to load at once when updating.
+const UPDATE_CHUNK_SIZE: usize = 1024;
+
+impl DependencyGraph {
+    /// Builds the graph from all versions in the database.
+    pub fn build<R: QueryRunner>(conn: &mut R) -> DependencyGraph {
+        let mut dst_of = HashMap::new();
+        let mut last_id = 0;
+        loop {
+            let page = query_dependency_dsts_after_id(conn, last_id, PAGE_SIZE);
+            match page.last() {
+                Some((id, _)) => last_id = *id,
+                None => break,
+            }
+            dst_of.extend(page);
+        }
+
+        let mut edges: Vec<RawEdge> = vec![];
+        let mut last_id = 0;
+        loop {
+            let page = query_version_dependencies_after_id(conn, last_id, PAGE_SIZE);
+            match page.last() {
+                Some(v) => last_id = v.id,
+                None => break,
+            }
+            // merging each page keeps memory down, since most packages have many versions with
+            // the same dependencies
+            edges.extend(merge_edges(
+                page.iter()
+                    .flat_map(|v| version_edges(v, &dst_of))
+                    .collect(),
+            ));
+        }
+
+        DependencyGraph::from_csr(Csr::from_sorted(&merge_edges(edges)))
+    }
+
+    /// Reloads the edges from the versions of the given packages.
+    pub fn refresh_packages<R: QueryRunner>(&mut self, conn: &mut R, package_ids: &[i64]) {
+        for chunk in package_ids.chunks(UPDATE_CHUNK_SIZE) {
+            let versions = get_version_dependencies_by_package_ids(conn, chunk);
+
+            let dep_ids: Vec<i64> = versions
+                .iter()
+                .flat_map(|v| {
+                    v.prod_dependencies
+                        .iter()
+                        .chain(&v.dev_dependencies)
+                        .chain(&v.peer_dependencies)
+                        .chain(&v.optional_dependencies)
+                })
+                .copied()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            let dst_of: HashMap<i64, i64> = get_dependency_dsts_by_ids(conn, &dep_ids)
+                .into_iter()
+                .filter_map(|(id, dst)| Some((id, dst?)))
+                .collect();
+
+            let mut by_package: HashMap<i64, Vec<VersionDependencies>> =
+                chunk.iter().map(|p| (*p, vec![])).collect();
+            for v in versions {
+                by_package.get_mut(&v.package_id).unwrap().push(v);
+            }
+            for (package_id, versions) in by_package {
+                self.update_package(package_id, &versions, &dst_of);
+            }
+        }
+    }
+}
+
+/// The packages touched by a batch of diff log entries, which need their edges reloaded once
+/// the batch has been written to the relational tables.
+#[derive(Debug, Default)]
+pub struct GraphUpdate {
+    /// Packages whose versions changed.
+    changed: HashSet<String>,
+    /// Packages that may have just been created, so dependencies on them now point somewhere.
+    created: HashSet<String>,
+}
+
+impl GraphUpdate {
+    pub fn note_entry(&mut self, package: &str, instr: &DiffLogInstruction) {
+        match instr {
+            DiffLogInstruction::CreateVersion(..)
+            | DiffLogInstruction::UpdateVersion(..)
+            | DiffLogInstruction::DeleteVersion(..) => {
+                self.changed.insert(package.to_owned());
+            }
+            DiffLogInstruction::PatchPackageReferences => {
+                self.created.insert(package.to_owned());
+            }
+            DiffLogInstruction::CreatePackage(_) | DiffLogInstruction::UpdatePackage(_) => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.created.is_empty()
+    }
+
+    /// Reloads the edges of all touched packages. This must see the relational tables after
+    /// the entries were processed.
+    pub fn apply<R: QueryRunner>(self, conn: &mut R, graph: &mut DependencyGraph) {
+        let mut package_ids: HashSet<i64> = self
+            .changed
+            .iter()
+            .filter_map(|name| maybe_get_package_id_by_name(conn, name))
+            .collect();
+
+        for name in &self.created {
+            let dep_ids = get_dependency_ids_by_dst_name(conn, name);
+            if !dep_ids.is_empty() {
+                package_ids.extend(get_package_ids_with_any_dependency(conn, &dep_ids));
+            }
+        }
+
+        let mut package_ids: Vec<i64> = package_ids.into_iter().collect();
+        package_ids.sort_unstable();
+        graph.refresh_packages(conn, &package_ids);
+    }
+}
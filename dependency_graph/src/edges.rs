@@ -0,0 +1,148 @@
+//! Turning version rows into the graph's edges.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use postgres_db::custom_types::{VersionStateTimePoint, VersionStateType};
+use postgres_db::versions::VersionDependencies;
+
+use crate::DepKinds;
+
+/// An edge from a package to a package that depends on it, stored in the adjacency list of the
+/// dependency. The edge exists from `since` until (not including) `until`, in unix seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Edge {
+    pub(crate) dependent: u32,
+    pub(crate) kinds: DepKinds,
+    pub(crate) since: i64,
+    pub(crate) until: i64,
+}
+
+impl Edge {
+    pub(crate) fn exists_at(&self, t: i64) -> bool {
+        self.since <= t && t < self.until
+    }
+}
+
+/// An edge along with the dependency whose adjacency list it goes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawEdge {
+    pub(crate) dependency: u32,
+    pub(crate) edge: Edge,
+}
+
+pub(crate) fn node_id(package_id: i64) -> u32 {
+    u32::try_from(package_id)
+        .unwrap_or_else(|_| panic!("Package id {} is too big for the graph", package_id))
+}
+
+/// The times during which a version existed, in unix seconds, with `i64::MAX` as the end of
+/// versions that still exist. State changes without a known time are ignored, so a version
+/// deleted at an unknown time counts as existing from then on.
+pub fn live_intervals(
+    created: DateTime<Utc>,
+    history: &[VersionStateTimePoint],
+) -> Vec<(i64, i64)> {
+    let mut intervals = vec![];
+    let mut since = Some(created.timestamp());
+    for point in history {
+        let t = match point.estimated_time {
+            Some(t) => t.timestamp(),
+            None => continue,
+        };
+        match (&point.state, since) {
+            (VersionStateType::Normal, None) => since = Some(t),
+            (VersionStateType::Deleted | VersionStateType::Unpublished, Some(s)) => {
+                if s < t {
+                    intervals.push((s, t));
+                }
+                since = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = since {
+        intervals.push((s, i64::MAX));
+    }
+    intervals
+}
+
+/// The edges contributed by one version. `dst_of` maps dependency row ids to the ids of the
+/// packages they point to, and is missing dependencies on packages that don't exist.
+pub(crate) fn version_edges(v: &VersionDependencies, dst_of: &HashMap<i64, i64>) -> Vec<RawEdge> {
+    let intervals = live_intervals(v.created, &v.version_state_history);
+    let dependent = node_id(v.package_id);
+
+    let by_kind = [
+        (DepKinds::PROD, &v.prod_dependencies),
+        (DepKinds::DEV, &v.dev_dependencies),
+        (DepKinds::PEER, &v.peer_dependencies),
+        (DepKinds::OPTIONAL, &v.optional_dependencies),
+    ];
+
+    let mut edges = vec![];
+    for (kinds, dep_ids) in by_kind {
+        for dep_id in dep_ids {
+            let dependency = match dst_of.get(dep_id) {
+                Some(dst) => node_id(*dst),
+                None => continue,
+            };
+            for (since, until) in &intervals {
+                edges.push(RawEdge {
+                    dependency,
+                    edge: Edge {
+                        dependent,
+                        kinds,
+                        since: *since,
+                        until: *until,
+                    },
+                });
+            }
+        }
+    }
+    edges
+}
+
+/// Merges edges between the same packages: first overlapping intervals of the same kind, and
+/// then kinds with the same interval. The result is sorted by dependency and then dependent.
+pub(crate) fn merge_edges(mut edges: Vec<RawEdge>) -> Vec<RawEdge> {
+    edges.sort_unstable_by_key(|e| {
+        (
+            e.dependency,
+            e.edge.dependent,
+            e.edge.kinds.bits(),
+            e.edge.since,
+        )
+    });
+    let mut by_kind: Vec<RawEdge> = Vec::with_capacity(edges.len());
+    for e in edges {
+        match by_kind.last_mut() {
+            Some(last)
+                if last.dependency == e.dependency
+                    && last.edge.dependent == e.edge.dependent
+                    && last.edge.kinds == e.edge.kinds
+                    && e.edge.since <= last.edge.until =>
+            {
+                last.edge.until = last.edge.until.max(e.edge.until);
+            }
+            _ => by_kind.push(e),
+        }
+    }
+
+    by_kind.sort_unstable_by_key(|e| (e.dependency, e.edge.dependent, e.edge.since, e.edge.until));
+    let mut merged: Vec<RawEdge> = Vec::with_capacity(by_kind.len());
+    for e in by_kind {
+        match merged.last_mut() {
+            Some(last)
+                if last.dependency == e.dependency
+                    && last.edge.dependent == e.edge.dependent
+                    && last.edge.since == e.edge.since
+                    && last.edge.until == e.edge.until =>
+            {
+                last.edge.kinds = last.edge.kinds | e.edge.kinds;
+            }
+            _ => merged.push(e),
+        }
+    }
+    merged
+}
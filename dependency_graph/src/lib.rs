@@ -0,0 +1,308 @@
+//! A reverse dependency graph of the relational tables: for each package, the packages with a
+//! version that depends on it. It answers "what is downstream of this package" without going
+//! back to the database, optionally as the graph was at some point in time.
+//!
+//! Each edge remembers the kinds of dependency it came from and the time range during which some
+//! version of the dependent had it, so one graph can answer queries about any point in time.
+//! Versions and dependencies are only known at the package level: an edge says that some version
+//! of the dependent depends on some version of the dependency, regardless of the spec.
+//!
+//! The graph is built with [`DependencyGraph::build`], kept up to date with [`GraphUpdate`]
+//! while the relational tables are, and can be saved to disk between runs.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::BitOr;
+
+use chrono::{DateTime, Utc};
+use postgres_db::versions::VersionDependencies;
+
+mod csr;
+mod db;
+mod edges;
+mod persist;
+
+pub use db::GraphUpdate;
+pub use edges::live_intervals;
+
+use csr::Csr;
+use edges::{merge_edges, node_id, version_edges, Edge, RawEdge};
+
+/// A set of dependency kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DepKinds(u8);
+
+impl DepKinds {
+    pub const NONE: DepKinds = DepKinds(0);
+    pub const PROD: DepKinds = DepKinds(1);
+    pub const DEV: DepKinds = DepKinds(2);
+    pub const PEER: DepKinds = DepKinds(4);
+    pub const OPTIONAL: DepKinds = DepKinds(8);
+    /// The kinds that get installed along with the dependent.
+    pub const RUNTIME: DepKinds = DepKinds(1 | 4 | 8);
+    pub const ALL: DepKinds = DepKinds(1 | 2 | 4 | 8);
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u8) -> Option<DepKinds> {
+        if bits & !DepKinds::ALL.0 == 0 {
+            Some(DepKinds(bits))
+        } else {
+            None
+        }
+    }
+
+    pub fn intersects(self, other: DepKinds) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn contains(self, other: DepKinds) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for DepKinds {
+    type Output = DepKinds;
+
+    fn bitor(self, rhs: DepKinds) -> DepKinds {
+        DepKinds(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClosureQuery {
+    /// Only follow dependencies that existed at this time. If `None`, only follow dependencies
+    /// that still exist.
+    pub at: Option<DateTime<Utc>>,
+    /// Only return packages at most this many dependencies away.
+    pub max_depth: Option<u32>,
+    /// Only follow dependencies of these kinds.
+    pub kinds: DepKinds,
+}
+
+impl Default for ClosureQuery {
+    fn default() -> Self {
+        ClosureQuery {
+            at: None,
+            max_depth: None,
+            kinds: DepKinds::RUNTIME,
+        }
+    }
+}
+
+/// A package downstream of the queried one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Downstream {
+    pub package_id: i64,
+    /// The length of the shortest dependency chain from this package to the queried one.
+    pub depth: u32,
+}
+
+/// Edges are stored in a [`Csr`] which is rebuilt now and then. In between, updated packages
+/// have their old edges hidden, and their new edges kept on the side.
+pub struct DependencyGraph {
+    base: Csr,
+    /// Dependents whose edges in `base` are outdated.
+    stale: HashSet<u32>,
+    /// New edges, by dependency.
+    added: HashMap<u32, Vec<Edge>>,
+    /// The keys of `added` that each dependent has edges under.
+    added_by_dependent: HashMap<u32, Vec<u32>>,
+    num_added: usize,
+}
+
+/// The smallest number of added edges that makes us rebuild the base graph. We also wait for a
+/// tenth of the size of the base graph, so rebuilding takes amortized constant time per edge.
+const MIN_COMPACT_EDGES: usize = 1 << 16;
+
+impl DependencyGraph {
+    /// Builds a graph from versions and the packages their dependencies point to. `dst_of` maps
+    /// dependency ids to `dst_package_id_if_exists`, leaving out dependencies without one.
+    pub fn from_versions<'a>(
+        versions: impl IntoIterator<Item = &'a VersionDependencies>,
+        dst_of: &HashMap<i64, i64>,
+    ) -> DependencyGraph {
+        let edges = versions
+            .into_iter()
+            .flat_map(|v| version_edges(v, dst_of))
+            .collect();
+        DependencyGraph::from_csr(Csr::from_sorted(&merge_edges(edges)))
+    }
+
+    fn from_csr(base: Csr) -> DependencyGraph {
+        DependencyGraph {
+            base,
+            stale: HashSet::new(),
+            added: HashMap::new(),
+            added_by_dependent: HashMap::new(),
+            num_added: 0,
+        }
+    }
+
+    pub fn num_edges(&self) -> usize {
+        let num_base = if self.stale.is_empty() {
+            self.base.num_edges()
+        } else {
+            self.base
+                .edges
+                .iter()
+                .filter(|e| !self.stale.contains(&e.dependent))
+                .count()
+        };
+        num_base + self.num_added
+    }
+
+    /// Replaces the edges from the versions of `package_id` with edges from `versions`, which
+    /// should be all of its versions.
+    pub fn update_package<'a>(
+        &mut self,
+        package_id: i64,
+        versions: impl IntoIterator<Item = &'a VersionDependencies>,
+        dst_of: &HashMap<i64, i64>,
+    ) {
+        let dependent = node_id(package_id);
+        let edges: Vec<RawEdge> = versions
+            .into_iter()
+            .inspect(|v| assert_eq!(v.package_id, package_id))
+            .flat_map(|v| version_edges(v, dst_of))
+            .collect();
+        self.replace_dependent(dependent, merge_edges(edges));
+    }
+
+    fn replace_dependent(&mut self, dependent: u32, edges: Vec<RawEdge>) {
+        for dependency in self
+            .added_by_dependent
+            .remove(&dependent)
+            .unwrap_or_default()
+        {
+            let list = self.added.get_mut(&dependency).unwrap();
+            let before = list.len();
+            list.retain(|e| e.dependent != dependent);
+            self.num_added -= before - list.len();
+            if list.is_empty() {
+                self.added.remove(&dependency);
+            }
+        }
+        self.stale.insert(dependent);
+
+        let mut dependencies = vec![];
+        for e in edges {
+            if dependencies.last() != Some(&e.dependency) {
+                dependencies.push(e.dependency);
+            }
+            self.added.entry(e.dependency).or_default().push(e.edge);
+            self.num_added += 1;
+        }
+        if !dependencies.is_empty() {
+            self.added_by_dependent.insert(dependent, dependencies);
+        }
+
+        if self.num_added >= MIN_COMPACT_EDGES.max(self.base.num_edges() / 10) {
+            self.compact();
+        }
+    }
+
+    /// Rebuilds the base graph to include all updates.
+    pub fn compact(&mut self) {
+        if self.stale.is_empty() && self.added.is_empty() {
+            return;
+        }
+        let num_nodes = self
+            .added
+            .keys()
+            .map(|n| n + 1)
+            .fold(self.base.num_nodes(), u32::max);
+        let edges: Vec<RawEdge> = (0..num_nodes)
+            .flat_map(|dependency| {
+                self.edges_of(dependency)
+                    .map(move |edge| RawEdge { dependency, edge })
+            })
+            .collect();
+        *self = DependencyGraph::from_csr(Csr::from_sorted(&merge_edges(edges)));
+    }
+
+    fn edges_of(&self, dependency: u32) -> impl Iterator<Item = Edge> + '_ {
+        let base = self
+            .base
+            .edges(dependency)
+            .iter()
+            .filter(|e| !self.stale.contains(&e.dependent));
+        let added = self.added.get(&dependency).into_iter().flatten();
+        base.chain(added).copied()
+    }
+
+    fn dependents_at(
+        &self,
+        dependency: u32,
+        t: i64,
+        kinds: DepKinds,
+    ) -> impl Iterator<Item = u32> + '_ {
+        self.edges_of(dependency)
+            .filter(move |e| e.kinds.intersects(kinds) && e.exists_at(t))
+            .map(|e| e.dependent)
+    }
+
+    /// The packages that directly depend on `package_id`, sorted by id.
+    pub fn direct_dependents(
+        &self,
+        package_id: i64,
+        at: Option<DateTime<Utc>>,
+        kinds: DepKinds,
+    ) -> Vec<i64> {
+        let mut dependents: Vec<i64> = self
+            .dependents_at(node_id(package_id), timestamp(at), kinds)
+            .map(i64::from)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        dependents.sort_unstable();
+        dependents
+    }
+
+    /// The packages that directly depend on `package_id` at any point in time, sorted by id.
+    pub fn dependents_ever(&self, package_id: i64, kinds: DepKinds) -> Vec<i64> {
+        let mut dependents: Vec<i64> = self
+            .edges_of(node_id(package_id))
+            .filter(|e| e.kinds.intersects(kinds))
+            .map(|e| i64::from(e.dependent))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        dependents.sort_unstable();
+        dependents
+    }
+
+    /// The packages that transitively depend on `package_id`, not including itself, sorted by
+    /// depth and then id.
+    pub fn transitive_dependents(&self, package_id: i64, query: &ClosureQuery) -> Vec<Downstream> {
+        let t = timestamp(query.at);
+        let start = node_id(package_id);
+
+        let mut seen = HashSet::from([start]);
+        let mut queue = VecDeque::from([(start, 0)]);
+        let mut downstream = vec![];
+        while let Some((node, depth)) = queue.pop_front() {
+            if query.max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            for dependent in self.dependents_at(node, t, query.kinds) {
+                if seen.insert(dependent) {
+                    downstream.push(Downstream {
+                        package_id: i64::from(dependent),
+                        depth: depth + 1,
+                    });
+                    queue.push_back((dependent, depth + 1));
+                }
+            }
+        }
+        downstream.sort_unstable_by_key(|d| (d.depth, d.package_id));
+        downstream
+    }
+}
+
+/// The time to check edges at. Edges that still exist end at `i64::MAX`, so they are the only
+/// ones that exist just before it.
+fn timestamp(at: Option<DateTime<Utc>>) -> i64 {
+    at.map_or(i64::MAX - 1, |t| t.timestamp())
+}
@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use dependency_graph::{ClosureQuery, DepKinds, DependencyGraph};
+use postgres_db::connection::DbConnection;
+use postgres_db::packages::{get_package, maybe_get_package_id_by_name};
+
+const USAGE: &str =
+    "<package name> [--at <RFC 3339 time>] [--max-depth <n>] [--include-dev] [--direct]";
+
+fn main() {
+    let _tracing = utils::init_tracing_stderr("dependency_graph");
+    let args = std::env::args().collect::<Vec<_>>();
+    let program = &args[0];
+
+    let mut name = None;
+    let mut query = ClosureQuery::default();
+    let mut direct = false;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--at" => {
+                let at = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                let at =
+                    DateTime::parse_from_rfc3339(at).unwrap_or_else(|_| exit_with_usage(program));
+                query.at = Some(at.with_timezone(&Utc));
+            }
+            "--max-depth" => {
+                let depth = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                query.max_depth = Some(depth.parse().unwrap_or_else(|_| exit_with_usage(program)));
+            }
+            "--include-dev" => query.kinds = DepKinds::ALL,
+            "--direct" => direct = true,
+            _ if name.is_none() && !arg.starts_with("--") => name = Some(arg.as_str()),
+            _ => exit_with_usage(program),
+        }
+    }
+    let name = name.unwrap_or_else(|| exit_with_usage(program));
+
+    let mut conn = DbConnection::connect();
+    let package_id = maybe_get_package_id_by_name(&mut conn, name).unwrap_or_else(|| {
+        tracing::error!(package = name, "Package not found");
+        std::process::exit(1);
+    });
+
+    // a saved graph may be behind the relational tables, but is much faster than a rebuild
+    let graph = match std::env::var("DEPENDENCY_GRAPH_PATH") {
+        Ok(path) => {
+            let (graph, through_seq) =
+                DependencyGraph::load(&path).expect("Failed to load the dependency graph");
+            tracing::info!(through_seq, "Loaded dependency graph");
+            graph
+        }
+        Err(_) => {
+            tracing::info!("Building dependency graph");
+            DependencyGraph::build(&mut conn)
+        }
+    };
+
+    if direct {
+        for id in graph.direct_dependents(package_id, query.at, query.kinds) {
+            println!("{}", get_package(&mut conn, id).name);
+        }
+    } else {
+        for d in graph.transitive_dependents(package_id, &query) {
+            println!("{}\t{}", d.depth, get_package(&mut conn, d.package_id).name);
+        }
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
@@ -0,0 +1,106 @@
+//! Saving the graph to disk, so it doesn't have to be rebuilt from the database on every run.
+//!
+//! The file is little endian: the magic bytes, the last diff log seq included in the graph, the
+//! number of nodes and edges, the node offsets, and then the edges.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::csr::Csr;
+use crate::edges::Edge;
+use crate::{DepKinds, DependencyGraph};
+
+const MAGIC: &[u8; 8] = b"NPMDEPG1";
+
+impl DependencyGraph {
+    /// Saves the graph, recording that it includes the diff log up to `through_seq`.
+    pub fn save(&mut self, path: impl AsRef<Path>, through_seq: i64) -> io::Result<()> {
+        self.compact();
+
+        // write to a temporary file first, so we never leave a partial graph behind
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&through_seq.to_le_bytes())?;
+        w.write_all(&u64::from(self.base.num_nodes()).to_le_bytes())?;
+        w.write_all(&(self.base.num_edges() as u64).to_le_bytes())?;
+        for offset in &self.base.offsets {
+            w.write_all(&offset.to_le_bytes())?;
+        }
+        for e in &self.base.edges {
+            w.write_all(&e.dependent.to_le_bytes())?;
+            w.write_all(&[e.kinds.bits()])?;
+            w.write_all(&e.since.to_le_bytes())?;
+            w.write_all(&e.until.to_le_bytes())?;
+        }
+        w.into_inner()?.sync_all()?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    /// Loads a saved graph, along with the last diff log seq it includes.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<(DependencyGraph, i64)> {
+        let mut r = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a dependency graph file"));
+        }
+        let through_seq = read_i64(&mut r)?;
+        let num_nodes = read_u64(&mut r)? as usize;
+        let num_edges = read_u64(&mut r)? as usize;
+
+        let offsets = (0..=num_nodes)
+            .map(|_| read_u64(&mut r))
+            .collect::<io::Result<Vec<u64>>>()?;
+        if offsets.first() != Some(&0)
+            || offsets.last() != Some(&(num_edges as u64))
+            || offsets.windows(2).any(|w| w[0] > w[1])
+        {
+            return Err(invalid_data("corrupt offsets"));
+        }
+
+        let edges = (0..num_edges)
+            .map(|_| {
+                let mut dependent = [0; 4];
+                r.read_exact(&mut dependent)?;
+                let mut kinds = [0; 1];
+                r.read_exact(&mut kinds)?;
+                Ok(Edge {
+                    dependent: u32::from_le_bytes(dependent),
+                    kinds: DepKinds::from_bits(kinds[0])
+                        .ok_or_else(|| invalid_data("corrupt dependency kinds"))?,
+                    since: read_i64(&mut r)?,
+                    until: read_i64(&mut r)?,
+                })
+            })
+            .collect::<io::Result<Vec<Edge>>>()?;
+
+        if r.read(&mut [0])? != 0 {
+            return Err(invalid_data("trailing bytes"));
+        }
+
+        Ok((
+            DependencyGraph::from_csr(Csr { offsets, edges }),
+            through_seq,
+        ))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_i64(r: &mut impl Read) -> io::Result<i64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeZone, Utc};
+use dependency_graph::{ClosureQuery, DepKinds, DependencyGraph, Downstream};
+use postgres_db::custom_types::{VersionStateTimePoint, VersionStateType};
+use postgres_db::versions::VersionDependencies;
+
+fn day(d: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2022, 1, d, 0, 0, 0).unwrap()
+}
+
+/// Dependency ids are `1000 * dst + kind`, so every dependency points to the package `id / 1000`.
+fn dst_of(versions: &[VersionDependencies]) -> HashMap<i64, i64> {
+    versions
+        .iter()
+        .flat_map(|v| {
+            v.prod_dependencies
+                .iter()
+                .chain(&v.dev_dependencies)
+                .chain(&v.peer_dependencies)
+                .chain(&v.optional_dependencies)
+        })
+        .map(|id| (*id, id / 1000))
+        .collect()
+}
+
+fn dep(dst: i64) -> i64 {
+    dst * 1000
+}
+
+struct V {
+    package_id: i64,
+    created: DateTime<Utc>,
+    history: Vec<VersionStateTimePoint>,
+    prod: Vec<i64>,
+    dev: Vec<i64>,
+    peer: Vec<i64>,
+}
+
+impl V {
+    fn new(package_id: i64, created: u32, prod: &[i64]) -> V {
+        V {
+            package_id,
+            created: day(created),
+            history: vec![],
+            prod: prod.iter().map(|p| dep(*p)).collect(),
+            dev: vec![],
+            peer: vec![],
+        }
+    }
+
+    fn dev(mut self, dev: &[i64]) -> V {
+        self.dev = dev.iter().map(|p| dep(*p) + 1).collect();
+        self
+    }
+
+    fn peer(mut self, peer: &[i64]) -> V {
+        self.peer = peer.iter().map(|p| dep(*p) + 2).collect();
+        self
+    }
+
+    fn state(mut self, state: VersionStateType, time: Option<u32>) -> V {
+        self.history.push(VersionStateTimePoint {
+            state,
+            seq: 0,
+            diff_entry_id: 0,
+            estimated_time: time.map(day),
+        });
+        self
+    }
+}
+
+fn rows(versions: Vec<V>) -> Vec<VersionDependencies> {
+    versions
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| VersionDependencies {
+            id: i as i64 + 1,
+            package_id: v.package_id,
+            created: v.created,
+            version_state_history: v.history,
+            prod_dependencies: v.prod,
+            dev_dependencies: v.dev,
+            peer_dependencies: v.peer,
+            optional_dependencies: vec![],
+        })
+        .collect()
+}
+
+fn graph(versions: Vec<V>) -> DependencyGraph {
+    let rows = rows(versions);
+    DependencyGraph::from_versions(&rows, &dst_of(&rows))
+}
+
+fn closure(g: &DependencyGraph, package_id: i64, query: ClosureQuery) -> Vec<(i64, u32)> {
+    g.transitive_dependents(package_id, &query)
+        .into_iter()
+        .map(|Downstream { package_id, depth }| (package_id, depth))
+        .collect()
+}
+
+#[test]
+fn test_transitive_dependents() {
+    // 1 <- 2 <- 3 <- 4, and 1 <- 4 directly
+    let g = graph(vec![
+        V::new(2, 1, &[1]),
+        V::new(3, 1, &[2]),
+        V::new(4, 1, &[3, 1]),
+        V::new(5, 1, &[]),
+    ]);
+    assert_eq!(
+        closure(&g, 1, ClosureQuery::default()),
+        vec![(2, 1), (4, 1), (3, 2)]
+    );
+    assert_eq!(g.direct_dependents(1, None, DepKinds::ALL), vec![2, 4]);
+    assert_eq!(closure(&g, 4, ClosureQuery::default()), vec![]);
+    assert_eq!(closure(&g, 5, ClosureQuery::default()), vec![]);
+    assert_eq!(closure(&g, 100, ClosureQuery::default()), vec![]);
+}
+
+#[test]
+fn test_cycles() {
+    let g = graph(vec![V::new(1, 1, &[2]), V::new(2, 1, &[1])]);
+    assert_eq!(closure(&g, 1, ClosureQuery::default()), vec![(2, 1)]);
+}
+
+#[test]
+fn test_max_depth() {
+    let g = graph(vec![
+        V::new(2, 1, &[1]),
+        V::new(3, 1, &[2]),
+        V::new(4, 1, &[3]),
+    ]);
+    let query = |max_depth| ClosureQuery {
+        max_depth: Some(max_depth),
+        ..Default::default()
+    };
+    assert_eq!(closure(&g, 1, query(0)), vec![]);
+    assert_eq!(closure(&g, 1, query(2)), vec![(2, 1), (3, 2)]);
+}
+
+#[test]
+fn test_kinds() {
+    let g = graph(vec![
+        V::new(2, 1, &[]).dev(&[1]),
+        V::new(3, 1, &[]).peer(&[1]),
+        V::new(4, 1, &[2]),
+    ]);
+    assert_eq!(closure(&g, 1, ClosureQuery::default()), vec![(3, 1)]);
+    assert_eq!(
+        closure(
+            &g,
+            1,
+            ClosureQuery {
+                kinds: DepKinds::ALL,
+                ..Default::default()
+            }
+        ),
+        vec![(2, 1), (3, 1), (4, 2)]
+    );
+    assert_eq!(g.direct_dependents(1, None, DepKinds::DEV), vec![2]);
+    assert_eq!(g.direct_dependents(1, None, DepKinds::PROD), vec![]);
+}
+
+#[test]
+fn test_time_bounds() {
+    let g = graph(vec![
+        // 2 depends on 1 from day 3
+        V::new(2, 3, &[1]),
+        // 3 depends on 1 from day 2 until its version is deleted on day 5, and again from a
+        // version published on day 7
+        V::new(3, 2, &[1]).state(VersionStateType::Deleted, Some(5)),
+        V::new(3, 7, &[1]),
+        // 4 depends on 2 from day 1, until it is unpublished on day 4 and restored on day 6
+        V::new(4, 1, &[2])
+            .state(VersionStateType::Unpublished, Some(4))
+            .state(VersionStateType::Normal, Some(6)),
+        // deletions at unknown times are ignored
+        V::new(5, 1, &[1]).state(VersionStateType::Deleted, None),
+    ]);
+    let at = |d| ClosureQuery {
+        at: Some(day(d)),
+        ..Default::default()
+    };
+    assert_eq!(closure(&g, 1, at(1)), vec![(5, 1)]);
+    assert_eq!(closure(&g, 1, at(2)), vec![(3, 1), (5, 1)]);
+    assert_eq!(closure(&g, 1, at(3)), vec![(2, 1), (3, 1), (5, 1), (4, 2)]);
+    assert_eq!(closure(&g, 1, at(4)), vec![(2, 1), (3, 1), (5, 1)]);
+    assert_eq!(closure(&g, 1, at(5)), vec![(2, 1), (5, 1)]);
+    assert_eq!(closure(&g, 1, at(6)), vec![(2, 1), (5, 1), (4, 2)]);
+    assert_eq!(closure(&g, 1, at(8)), vec![(2, 1), (3, 1), (5, 1), (4, 2)]);
+    assert_eq!(
+        closure(&g, 1, ClosureQuery::default()),
+        vec![(2, 1), (3, 1), (5, 1), (4, 2)]
+    );
+
+    // only the versions that still exist count for the current graph
+    let g = graph(vec![
+        V::new(2, 1, &[1]).state(VersionStateType::Deleted, Some(2)),
+        V::new(3, 1, &[1]),
+    ]);
+    assert_eq!(g.direct_dependents(1, None, DepKinds::ALL), vec![3]);
+    assert_eq!(
+        g.direct_dependents(1, Some(day(1)), DepKinds::ALL),
+        vec![2, 3]
+    );
+    assert_eq!(g.dependents_ever(1, DepKinds::ALL), vec![2, 3]);
+    assert_eq!(g.dependents_ever(1, DepKinds::DEV), vec![]);
+}
+
+#[test]
+fn test_versions_are_merged() {
+    let g = graph(vec![
+        V::new(2, 1, &[1]),
+        V::new(2, 2, &[1]),
+        V::new(2, 3, &[1]).dev(&[1]),
+    ]);
+    assert_eq!(g.num_edges(), 2);
+}
+
+#[test]
+fn test_missing_dependencies_are_skipped() {
+    let rows = rows(vec![V::new(2, 1, &[1, 3])]);
+    let mut dst_of = dst_of(&rows);
+    dst_of.remove(&dep(3));
+    let g = DependencyGraph::from_versions(&rows, &dst_of);
+    assert_eq!(g.num_edges(), 1);
+    assert_eq!(g.direct_dependents(3, None, DepKinds::ALL), vec![]);
+}
+
+#[test]
+fn test_update_package() {
+    let mut g = graph(vec![
+        V::new(2, 1, &[1]),
+        V::new(3, 1, &[2]),
+        V::new(4, 1, &[1]),
+    ]);
+
+    // 3 switches from depending on 2 to depending on 1
+    let updated = rows(vec![
+        V::new(3, 1, &[2]).state(VersionStateType::Deleted, Some(2)),
+        V::new(3, 2, &[1]),
+    ]);
+    g.update_package(3, &updated, &dst_of(&updated));
+    assert_eq!(
+        closure(&g, 1, ClosureQuery::default()),
+        vec![(2, 1), (3, 1), (4, 1)]
+    );
+    assert_eq!(g.direct_dependents(2, None, DepKinds::ALL), vec![]);
+    assert_eq!(g.direct_dependents(2, Some(day(1)), DepKinds::ALL), vec![3]);
+
+    // updating again replaces the first update, and removing all versions removes the edges
+    let updated = rows(vec![V::new(3, 1, &[4])]);
+    g.update_package(3, &updated, &dst_of(&updated));
+    g.update_package(4, &[], &HashMap::new());
+    assert_eq!(closure(&g, 1, ClosureQuery::default()), vec![(2, 1)]);
+    assert_eq!(closure(&g, 4, ClosureQuery::default()), vec![(3, 1)]);
+    assert_eq!(g.num_edges(), 2);
+
+    // new packages can show up
+    let new = rows(vec![V::new(50, 1, &[3])]);
+    g.update_package(50, &new, &dst_of(&new));
+    assert_eq!(
+        closure(&g, 4, ClosureQuery::default()),
+        vec![(3, 1), (50, 2)]
+    );
+
+    let before = closure(&g, 4, ClosureQuery::default());
+    g.compact();
+    assert_eq!(closure(&g, 4, ClosureQuery::default()), before);
+    assert_eq!(g.num_edges(), 3);
+}
+
+#[test]
+fn test_save_and_load() {
+    let mut g = graph(vec![
+        V::new(2, 1, &[1]).state(VersionStateType::Deleted, Some(3)),
+        V::new(3, 1, &[2]).peer(&[1]),
+        V::new(4, 2, &[]).dev(&[3]),
+    ]);
+    let new = rows(vec![V::new(5, 2, &[4])]);
+    g.update_package(5, &new, &dst_of(&new));
+
+    let dir = std::env::temp_dir().join(format!("dependency_graph_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("graph.bin");
+    g.save(&path, 1234).unwrap();
+    let (loaded, through_seq) = DependencyGraph::load(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(through_seq, 1234);
+    assert_eq!(loaded.num_edges(), g.num_edges());
+    for d in 1..=3 {
+        let query = ClosureQuery {
+            at: Some(day(d)),
+            kinds: DepKinds::ALL,
+            max_depth: None,
+        };
+        assert_eq!(closure(&loaded, 1, query.clone()), closure(&g, 1, query));
+    }
+    assert_eq!(
+        closure(
+            &loaded,
+            1,
+            ClosureQuery {
+                kinds: DepKinds::ALL,
+                ..Default::default()
+            }
+        ),
+        vec![(3, 1), (4, 2), (5, 3)]
+    );
+}
+
+#[test]
+fn test_load_rejects_other_files() {
+    let path =
+        std::env::temp_dir().join(format!("dependency_graph_bad_{}.bin", std::process::id()));
+    std::fs::write(&path, b"not a graph").unwrap();
+    let err = DependencyGraph::load(&path).err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -11,12 +11,13 @@ use postgres_db::{
 
 use utils::{FilterJsonCases, RemoveInto};
 
+use crate::json_path;
+
 fn deserialize_spec(c: Value) -> Spec {
     match c {
-        // the spec must parse ok. This should hold, since invalid specs are parsed successfully as invalid, not errors.
-        // error should only occur due to I/O problems.
+        // invalid specs are parsed successfully as invalid, not errors.
         Value::String(spec_str) => {
-            let parsed = semver_spec_serialization::parse_spec_via_node_cached(&spec_str).unwrap();
+            let parsed = semver_spec_serialization::parse_spec_cached(&spec_str);
             Spec {
                 raw: spec_str.into(),
                 parsed,
@@ -36,6 +37,7 @@ fn deserialize_dependencies(
     version_blob: &mut Map<String, Value>,
     key: &'static str,
 ) -> Vec<(String, Spec)> {
+    let _path = json_path::enter(key);
     let dependencies_maybe_val = version_blob
         .remove(key)
         .and_then(|x| x.null_to_none())
@@ -79,6 +81,7 @@ fn deserialize_version_blob(
     let peer_dependencies = deserialize_dependencies(&mut version_blob, "peerDependencies");
     let optional_dependencies = deserialize_dependencies(&mut version_blob, "optionalDependencies");
 
+    let dist_path = json_path::enter("dist");
     // We should always have a "dist" field, and it should always be an object
     let mut dist = version_blob
         .remove_key_unwrap_type::<Map<String, Value>>("dist")
@@ -114,6 +117,7 @@ fn deserialize_version_blob(
         signature0_keyid: sig0_keyid,
         npm_signature: dist.remove_key_unwrap_type::<String>("npm-signature"), // if npm-signature exists must be a string
     };
+    drop(dist_path);
 
     let repository_blob = version_blob
         .remove("repository")
@@ -135,9 +139,9 @@ fn deserialize_version_blob(
         if num_versions == 1 {
             &modified_time
         } else {
-            eprintln!(
-                "WARNING: version {} does not have a time. Imputing from modified time.",
-                version
+            tracing::warn!(
+                version = %version,
+                "Version does not have a time. Imputing from modified time."
             );
             &modified_time
         }
@@ -162,6 +166,7 @@ struct ParsedTimesData {
 }
 
 fn deserialize_times_normal(j: &mut Map<String, Value>) -> ParsedTimesData {
+    let _path = json_path::enter("time");
     // If time exists (checked in deserialize_times), then time must be a dictionary
     let time_raw = j
         .remove_key_unwrap_type::<Map<String, Value>>("time")
@@ -210,7 +215,9 @@ fn deserialize_times_ctime(j: &mut Map<String, Value>) -> ParsedTimesData {
 
     // There must be a versions field that is a dictionary.
     let versions_map = j.get_mut("versions").unwrap().as_object_mut().unwrap();
+    let _versions_path = json_path::enter("versions");
     for (v_key, v_blob) in versions_map.iter_mut() {
+        let _v_path = json_path::enter(v_key.as_str());
         // Each version must be a dictionary
         let v_obj = v_blob.as_object_mut().unwrap();
 
@@ -312,11 +319,13 @@ fn deserialize_latest_tag(dist_tags: &mut Map<String, Value>) -> Option<Semver>
 pub fn deserialize_packument_blob_normal(
     mut j: Map<String, Value>,
 ) -> (PackageOnlyPackument, AllVersionPackuments) {
+    let dist_tags_path = json_path::enter("dist-tags");
     // We have to have dist-tags, and it must be a dictionary
     let mut dist_tags = j
         .remove_key_unwrap_type::<Map<String, Value>>("dist-tags")
         .unwrap();
     let latest_semver = deserialize_latest_tag(&mut dist_tags);
+    drop(dist_tags_path);
 
     let ParsedTimesData {
         created,
@@ -333,6 +342,8 @@ pub fn deserialize_packument_blob_normal(
     let version_packuments: BTreeMap<Semver, VersionOnlyPackument> = version_packuments_map
         .into_iter()
         .map(|(v_str, blob)| {
+            let _versions_path = json_path::enter("versions");
+            let _v_path = json_path::enter(v_str.as_str());
             // each version string must parse ok
             let version = semver_spec_serialization::parse_semver(&v_str).unwrap();
             // and each version data must be a dictionary
@@ -392,6 +403,7 @@ pub fn deserialize_packument_blob_unpublished(
 
     // For an unpublished package, we must have a time dictionary, and it must have an unpublished key.
     // Note that we have to remove the unpublished key from the times data, otherwise deserialize_times would fail to parse it.
+    let unpublished_path = json_path::enter("time");
     let unpublished_blob = j
         .get_mut("time")
         .unwrap()
@@ -399,6 +411,7 @@ pub fn deserialize_packument_blob_unpublished(
         .unwrap()
         .remove_key_unwrap_type::<Value>("unpublished")
         .unwrap();
+    drop(unpublished_path);
     let ParsedTimesData {
         created,
         modified,
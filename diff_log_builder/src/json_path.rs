@@ -0,0 +1,85 @@
+//! Best-effort tracking of where in a packument the deserializer currently is,
+//! so that a panic can be attributed to a JSON path when the change is quarantined.
+//!
+//! Components are pushed with `enter`, and popped when the returned guard is dropped.
+//! While unwinding from a panic the guards do *not* pop, which leaves the path at
+//! the point of the panic for `take_current_path` to read.
+
+use std::cell::RefCell;
+use std::thread;
+
+thread_local! {
+    static CURRENT_PATH: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+pub(crate) struct JsonPathGuard {
+    _private: (),
+}
+
+pub(crate) fn enter<S: Into<String>>(component: S) -> JsonPathGuard {
+    CURRENT_PATH.with(|p| p.borrow_mut().push(component.into()));
+    JsonPathGuard { _private: () }
+}
+
+impl Drop for JsonPathGuard {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            CURRENT_PATH.with(|p| p.borrow_mut().pop());
+        }
+    }
+}
+
+pub(crate) fn reset() {
+    CURRENT_PATH.with(|p| p.borrow_mut().clear());
+}
+
+/// Returns the current path as a JSON pointer (e.g. `/versions/1.0.0/dist`), and clears it.
+pub(crate) fn take_current_path() -> Option<String> {
+    let components = CURRENT_PATH.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    if components.is_empty() {
+        None
+    } else {
+        Some(
+            components
+                .iter()
+                .map(|c| format!("/{}", c.replace('~', "~0").replace('/', "~1")))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_kept_on_panic() {
+        reset();
+        let result = std::panic::catch_unwind(|| {
+            let _versions = enter("versions");
+            {
+                let _ok = enter("0.1.0");
+            }
+            let _v = enter("1.0.0");
+            let _dist = enter("dist");
+            panic!("bad dist");
+        });
+        assert!(result.is_err());
+        assert_eq!(
+            take_current_path(),
+            Some("/versions/1.0.0/dist".to_string())
+        );
+        assert_eq!(take_current_path(), None);
+    }
+
+    #[test]
+    fn test_path_escaping() {
+        reset();
+        let _deps = enter("dependencies");
+        let _name = enter("@types/node");
+        assert_eq!(
+            take_current_path(),
+            Some("/dependencies/@types~1node".to_string())
+        );
+    }
+}
@@ -1,66 +1,287 @@
 pub mod deserialize;
+mod json_path;
 
+use postgres_db::change_log;
 use postgres_db::change_log::Change;
 use postgres_db::connection::DbConnectionInTransaction;
 use postgres_db::custom_types::Semver;
 use postgres_db::diff_log;
 use postgres_db::diff_log::internal_diff_log_state::manager::DiffStateManager;
+use postgres_db::diff_log::quarantine;
+use postgres_db::diff_log::quarantine::NewQuarantinedChange;
+use postgres_db::diff_log::quarantine::QuarantinedChange;
 use postgres_db::diff_log::DiffLogInstruction;
 use postgres_db::diff_log::NewDiffLogEntry;
 use postgres_db::diff_log::NewDiffLogEntryWithHash;
+use postgres_db::internal_state;
 use postgres_db::packument::AllVersionPackuments;
 use postgres_db::packument::PackageOnlyPackument;
 use postgres_db::packument::VersionOnlyPackument;
+use postgres_db::rewind::rewind_relational_tables;
 use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::iter;
 use std::panic;
 use std::panic::AssertUnwindSafe;
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
 use serde_json::{Map, Value};
 
+use chrono::Utc;
+use utils::panic_as_string;
 use utils::RemoveInto;
 
 pub struct ProcessChangeSuccessMetrics {
     pub read_bytes: usize,
     pub write_bytes: usize,
     pub write_duration: Duration,
+    /// Changes which panicked, and were moved to the quarantine instead of being processed.
+    pub quarantined: Vec<ProcessChangeError>,
 }
 
 #[derive(Debug)]
 pub struct ProcessChangeError {
     pub seq: i64,
+    pub package_name: Option<String>,
     pub message: String,
+    pub json_path: Option<String>,
     pub err: Box<dyn Any + Send>,
 }
 
 pub fn process_changes(
     conn: &mut DbConnectionInTransaction,
     changes: Vec<Change>,
-) -> Result<ProcessChangeSuccessMetrics, ProcessChangeError> {
-    let mut state_manager = DiffStateManager::new();
+) -> ProcessChangeSuccessMetrics {
+    process_changes_partitioned(conn, changes, 1)
+}
+
+/// Processes a batch of changes by sharding them across `num_workers` threads by
+/// the hash of their package name. Changes to the same package always land in the same
+/// shard (in seq order), so each worker can own its own `DiffStateManager`. The resulting
+/// diff entries are merged back together in seq order before being written.
+///
+/// Changes which panic are skipped and recorded in the `diff_log_quarantine` table,
+/// so that they can be replayed later with `retry_quarantined_changes`.
+pub fn process_changes_partitioned(
+    conn: &mut DbConnectionInTransaction,
+    changes: Vec<Change>,
+    num_workers: usize,
+) -> ProcessChangeSuccessMetrics {
+    assert!(num_workers > 0, "num_workers must be positive");
+
+    let mut shards: Vec<Vec<Change>> = (0..num_workers).map(|_| Vec::new()).collect();
+    for c in changes {
+        let shard = change_package_name(&c)
+            .map(|name| shard_for_package(name, num_workers))
+            .unwrap_or(0);
+        shards[shard].push(c);
+    }
+
+    // Reading the internal state must happen on this thread, since the
+    // connection can not be shared with the workers.
+    let mut state_managers: Vec<DiffStateManager> = shards
+        .iter()
+        .map(|shard| {
+            let mut manager = DiffStateManager::new();
+            manager.prefetch_packages(
+                shard
+                    .iter()
+                    .filter_map(|c| change_package_name(c).map(|n| n.to_owned()))
+                    .collect(),
+                conn,
+            );
+            manager
+        })
+        .collect();
+
+    let shard_results: Vec<ShardResult> = if num_workers == 1 {
+        vec![process_shard(
+            shards.pop().unwrap(),
+            state_managers.pop().unwrap(),
+        )]
+    } else {
+        thread::scope(|s| {
+            let handles: Vec<_> = shards
+                .into_iter()
+                .zip(state_managers.into_iter())
+                .map(|(shard, manager)| s.spawn(move || process_shard(shard, manager)))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("diff log worker thread panicked"))
+                .collect()
+        })
+    };
+
+    let mut read_bytes = 0;
+    let mut write_bytes = 0;
+    let mut finished_managers = Vec::with_capacity(num_workers);
     let mut new_diff_entries: Vec<NewDiffLogEntryWithHash> = Vec::new();
+    let mut quarantined: Vec<ProcessChangeError> = Vec::new();
+
+    for shard_result in shard_results {
+        read_bytes += shard_result.read_bytes;
+        write_bytes += shard_result.write_bytes;
+        finished_managers.push(shard_result.state_manager);
+        new_diff_entries.extend(shard_result.new_diff_entries);
+        quarantined.extend(shard_result.quarantined);
+    }
+
+    quarantined.sort_by_key(|q| q.seq);
+
+    // All entries for a single seq come from a single shard, so a stable
+    // sort keeps the entries of each change in their original order.
+    new_diff_entries.sort_by_key(|e| e.entry.seq);
+
+    let write_start = Instant::now();
+
+    for manager in finished_managers {
+        manager.flush_to_db(conn);
+    }
+    diff_log::insert_diff_log_entries(
+        new_diff_entries.into_iter().map(|x| x.entry).collect(),
+        conn,
+    );
+    let quarantined_time = Utc::now();
+    quarantine::quarantine_changes(
+        quarantined
+            .iter()
+            .map(|q| NewQuarantinedChange {
+                seq: q.seq,
+                package_name: q.package_name.clone(),
+                panic_message: q.message.clone(),
+                json_path: q.json_path.clone(),
+                quarantined_time,
+            })
+            .collect(),
+        conn,
+    );
+
+    let write_duration = write_start.elapsed();
+
+    ProcessChangeSuccessMetrics {
+        read_bytes,
+        write_bytes,
+        write_duration,
+        quarantined,
+    }
+}
+
+/// Replays previously quarantined changes. Changes which now succeed are removed from
+/// the quarantine, while changes which fail again have their quarantine entry updated.
+///
+/// A quarantined change is only replayed if no later change to the same package has been
+/// processed since. Otherwise it has been superseded by the later (full) packument and is
+/// simply removed from the quarantine.
+///
+/// The diff entries of replayed changes keep their original seq, so if the relational tables are
+/// already past the first of them, they are rewound to right before it, and relational_db_builder
+/// processes the diff entries from there again.
+pub fn retry_quarantined_changes(
+    conn: &mut DbConnectionInTransaction,
+    quarantined_changes: Vec<QuarantinedChange>,
+) -> RetryQuarantineMetrics {
+    let (superseded, to_retry): (Vec<_>, Vec<_>) =
+        quarantined_changes
+            .into_iter()
+            .partition(|q| match &q.package_name {
+                Some(name) => diff_log::query_package_has_diff_entries_after_seq(name, q.seq, conn),
+                None => false,
+            });
+
+    let superseded_seqs: Vec<i64> = superseded.iter().map(|q| q.seq).collect();
+    quarantine::remove_quarantined_changes(&superseded_seqs, conn);
+
+    let retry_seqs: Vec<i64> = to_retry.iter().map(|q| q.seq).collect();
+    let changes = change_log::query_changes_by_seqs(&retry_seqs, conn);
+
+    let metrics = process_changes(conn, changes);
+
+    let still_failing: HashSet<i64> = metrics.quarantined.iter().map(|q| q.seq).collect();
+    let fixed_seqs: Vec<i64> = retry_seqs
+        .into_iter()
+        .filter(|s| !still_failing.contains(s))
+        .collect();
+    quarantine::remove_quarantined_changes(&fixed_seqs, conn);
+
+    let relational_seq = internal_state::query_relational_processed_seq(conn).unwrap_or(0);
+    let relational_rewound_to = fixed_seqs
+        .iter()
+        .min()
+        .map(|first_fixed| first_fixed - 1)
+        .filter(|to_seq| *to_seq < relational_seq);
+    if let Some(to_seq) = relational_rewound_to {
+        rewind_relational_tables(conn, to_seq);
+    }
+
+    RetryQuarantineMetrics {
+        fixed_seqs,
+        superseded_seqs,
+        still_failing: metrics.quarantined,
+        relational_rewound_to,
+    }
+}
+
+pub struct RetryQuarantineMetrics {
+    pub fixed_seqs: Vec<i64>,
+    pub superseded_seqs: Vec<i64>,
+    pub still_failing: Vec<ProcessChangeError>,
+    /// The seq the relational tables were rewound to, if they were already past a fixed change.
+    pub relational_rewound_to: Option<i64>,
+}
+
+struct ShardResult {
+    read_bytes: usize,
+    write_bytes: usize,
+    state_manager: DiffStateManager,
+    new_diff_entries: Vec<NewDiffLogEntryWithHash>,
+    quarantined: Vec<ProcessChangeError>,
+}
+
+fn process_shard(changes: Vec<Change>, mut state_manager: DiffStateManager) -> ShardResult {
+    let mut new_diff_entries: Vec<NewDiffLogEntryWithHash> = Vec::new();
+    let mut quarantined = Vec::new();
 
     let mut read_bytes = 0;
     let mut write_bytes = 0;
 
     for c in changes {
         let seq = c.seq;
+        let package_name = change_package_name(&c).map(|n| n.to_owned());
+
+        // If the change panics part way through updating the state, the package's state must
+        // be put back, since later changes to it in this batch (and the flush) depend on it.
+        let saved_state = package_name
+            .as_deref()
+            .and_then(|name| state_manager.save_package(name));
 
+        json_path::reset();
         let result = panic::catch_unwind(AssertUnwindSafe(|| {
-            process_change(conn, c, &mut state_manager, &mut new_diff_entries)
+            process_change(c, &mut state_manager, &mut new_diff_entries)
         }));
 
         let (rb, wb) = match result {
             Err(err) => {
-                let err_message = format!("Failed on seq: {}.\n:{:?}", seq, err);
-                return Result::Err(ProcessChangeError {
+                if let (Some(name), Some(saved)) = (&package_name, saved_state) {
+                    state_manager.restore_package(name.clone(), saved);
+                }
+                let json_path = json_path::take_current_path();
+                let err_message = format!("Failed on seq: {}.\n{}", seq, panic_as_string(&err));
+                quarantined.push(ProcessChangeError {
                     seq,
+                    package_name,
                     message: err_message,
+                    json_path,
                     err,
                 });
+                continue;
             }
             Ok(r) => r,
         };
@@ -69,30 +290,33 @@ pub fn process_changes(
         write_bytes += wb;
     }
 
-    let write_start = Instant::now();
-
-    state_manager.flush_to_db(conn);
-    diff_log::insert_diff_log_entries(
-        new_diff_entries.into_iter().map(|x| x.entry).collect(),
-        conn,
-    );
-
-    let write_duration = write_start.elapsed();
-
-    Ok(ProcessChangeSuccessMetrics {
+    ShardResult {
         read_bytes,
         write_bytes,
-        write_duration,
-    })
+        state_manager,
+        new_diff_entries,
+        quarantined,
+    }
+}
+
+fn change_package_name(c: &Change) -> Option<&str> {
+    c.raw_json.get("id").and_then(|id| id.as_str())
+}
+
+fn shard_for_package(package_name: &str, num_workers: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    package_name.hash(&mut hasher);
+    (hasher.finish() % num_workers as u64) as usize
 }
 
 fn process_change(
-    conn: &mut DbConnectionInTransaction,
     c: Change,
     state_manager: &mut DiffStateManager,
     new_diff_entries: &mut Vec<NewDiffLogEntryWithHash>,
 ) -> (usize, usize) {
     let seq = c.seq;
+    let span = tracing::info_span!("change", seq, package = tracing::field::Empty);
+    let _span = span.enter();
     // TODO[perf]: DELETE THIS LATER!
     let change_bytes = serde_json::to_vec(&c.raw_json).unwrap().len();
 
@@ -102,16 +326,15 @@ fn process_change(
         None => return (change_bytes, 0),
     };
 
-    if package_name == "zidane" || package_name == "apizee-api" {
-        println!("{} in seq: {}", package_name, seq);
-    }
+    span.record("package", package_name.as_str());
+    tracing::debug!("Diffing change");
 
     let (_, package_data_hash, package_data_num_bytes) = package_data.serialize_and_hash();
 
     // 1. Lookup current state
     // 2. Decide what type of change it is (look at hashes, etc.) and compute diffs
     let diff_instrs: Vec<(NewDiffLogEntryWithHash, usize)> =
-        match state_manager.lookup_package(package_name.clone(), conn) {
+        match state_manager.lookup_prefetched_package(&package_name) {
             Some(hash_state) => {
                 // The package already exists in the DB.
                 // First, we must update all the versions
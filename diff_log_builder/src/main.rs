@@ -1,116 +1,243 @@
-use std::time::Instant;
-
 use chrono::Utc;
-use diff_log_builder::process_changes;
+use diff_log_builder::process_changes_partitioned;
+use diff_log_builder::retry_quarantined_changes;
+use diff_log_builder::ProcessChangeError;
+use diff_log_builder::ProcessChangeSuccessMetrics;
 use metrics_logging::{
     DiffLogBatchCompleteMetrics, DiffLogEndSessionMetrics, DiffLogPanicMetrics,
-    DiffLogStartSessionMetrics, MetricsLoggerTrait,
+    DiffLogStartSessionMetrics, MetricsLogger, MetricsLoggerTrait,
+};
+use pipeline_stage::{
+    PageSize, PageStats, PipelineStage, Runner, SessionStart, SessionSummary, StageError,
+    DAEMON_POLL_INTERVAL,
 };
 use postgres_db::change_log;
+use postgres_db::change_log::Change;
 
-use postgres_db::connection::DbConnection;
+use postgres_db::connection::{DbConnection, DbConnectionInTransaction};
+use postgres_db::diff_log::quarantine;
 use postgres_db::internal_state;
+use postgres_db::notifications::{Listener, CHANGE_LOG_CHANNEL, DIFF_LOG_CHANNEL};
 
 use utils::check_no_concurrent_processes;
 
 const PAGE_SIZE: i64 = 1024;
 
 fn main() {
+    let _tracing = utils::init_tracing("diff_log_builder");
     check_no_concurrent_processes("diff_log_builder");
 
+    let mut args: Vec<String> = std::env::args().collect();
+    let dry_run = pipeline_stage::take_flag(&mut args, "--dry-run");
+    let daemon = pipeline_stage::take_flag(&mut args, "--daemon");
+
     let mut conn = DbConnection::connect();
-    let mut metrics_logger = metrics_logging::new_metrics_logger(false);
+    let mut metrics_logger = MetricsLogger::from_env_or_exit();
+
+    let num_workers: usize = match args.len() {
+        1 => 1,
+        2 if args[1] == "retry-quarantine" => {
+            // Retrying may rewind the relational tables.
+            check_no_concurrent_processes("relational_db_builder");
+            retry_quarantine(&mut conn, &mut metrics_logger);
+            return;
+        }
+        2 => args[1]
+            .parse()
+            .ok()
+            .filter(|n| *n > 0)
+            .unwrap_or_else(|| panic!("Invalid number of workers: {}", args[1])),
+        _ => {
+            eprintln!(
+                "Usage: {} [--dry-run | --daemon] [num_workers | retry-quarantine]",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let mut stage = DiffLogStage {
+        num_workers,
+        num_changes_total: 0,
+    };
+    let mut runner = Runner::new(PageSize::Fixed(PAGE_SIZE), metrics_logger)
+        .dry_run(dry_run)
+        .stop_on_signals();
+    if daemon {
+        let mut listener = Listener::connect(&[CHANGE_LOG_CHANNEL]);
+        runner.run_daemon(&mut stage, &mut conn, &mut listener, DAEMON_POLL_INTERVAL);
+    } else {
+        runner.run(&mut stage, &mut conn);
+    }
+}
 
-    let mut processed_up_to = internal_state::query_diff_log_processed_seq(&mut conn).unwrap_or(0);
+struct DiffLogStage {
+    num_workers: usize,
+    num_changes_total: i64,
+}
 
-    let num_changes_total = change_log::query_num_changes_after_seq(processed_up_to, &mut conn);
-    let mut num_changes_so_far = 0;
+impl PipelineStage for DiffLogStage {
+    type Item = Change;
+    type Cursor = i64;
+    type Output = ProcessChangeSuccessMetrics;
 
-    let session_start_time = Utc::now();
-    let session_start_seq_exclusive = processed_up_to;
+    fn name(&self) -> &'static str {
+        "diff_log_builder"
+    }
 
-    metrics_logger.log_diff_log_builder_start_session(DiffLogStartSessionMetrics {
-        session_start_time,
-        session_start_seq_exclusive,
-        session_num_seqs: num_changes_total,
-    });
+    fn load_cursor(&mut self, conn: &mut DbConnection) -> i64 {
+        internal_state::query_diff_log_processed_seq(conn).unwrap_or(0)
+    }
 
-    // TODO: Extract this into function (duplicated in download_queuer/src/main.rs)
-    loop {
-        let batch_start = Instant::now();
-        let batch_start_time = Utc::now();
+    fn save_cursor(&mut self, conn: &mut DbConnectionInTransaction, cursor: &i64) {
+        internal_state::set_diff_log_processed_seq(*cursor, conn);
+    }
+
+    fn query_page(&mut self, conn: &mut DbConnection, cursor: &i64, page_size: i64) -> Vec<Change> {
+        change_log::query_changes_after_seq(*cursor, page_size, conn)
+    }
 
-        let changes = change_log::query_changes_after_seq(processed_up_to, PAGE_SIZE, &mut conn);
+    fn advance(&self, _cursor: &i64, page: &[Change]) -> i64 {
+        page.last().unwrap().seq
+    }
 
-        let read_duration = batch_start.elapsed();
+    fn notify_channel(&self) -> Option<&'static str> {
+        Some(DIFF_LOG_CHANNEL)
+    }
 
-        let num_changes = changes.len() as i64;
-        num_changes_so_far += num_changes;
-        if num_changes == 0 {
+    fn process_page(
+        &mut self,
+        conn: &mut DbConnectionInTransaction,
+        page: Vec<Change>,
+    ) -> Result<ProcessChangeSuccessMetrics, StageError> {
+        // changes that panic are quarantined, so only the rest of the page can fail it
+        Ok(process_changes_partitioned(conn, page, self.num_workers))
+    }
+
+    fn start_session(
+        &mut self,
+        conn: &mut DbConnection,
+        logger: &mut MetricsLogger,
+        session: &SessionStart<i64>,
+    ) {
+        self.num_changes_total = change_log::query_num_changes_after_seq(session.cursor, conn);
+
+        logger.log_diff_log_builder_start_session(DiffLogStartSessionMetrics {
+            session_start_time: session.start_time,
+            session_start_seq_exclusive: session.cursor,
+            session_num_seqs: self.num_changes_total,
+        });
+    }
+
+    fn page_completed(
+        &mut self,
+        logger: &mut MetricsLogger,
+        page: &PageStats<i64>,
+        output: &ProcessChangeSuccessMetrics,
+    ) {
+        log_quarantined(logger, &output.quarantined);
+
+        logger.log_diff_log_builder_batch_complete_metrics(DiffLogBatchCompleteMetrics {
+            batch_start_time: page.start_time,
+            batch_start_seq_inclusive: page.first,
+            batch_end_seq_inclusive: page.last,
+            batch_num_processed_seqs: page.num_items,
+            batch_bytes_read: output.read_bytes as i64,
+            batch_bytes_written: output.write_bytes as i64,
+            batch_reading_duration: chrono::Duration::from_std(page.read_duration).unwrap(),
+            batch_writing_duration: chrono::Duration::from_std(output.write_duration).unwrap(),
+            batch_total_duration: chrono::Duration::from_std(page.total_duration).unwrap(),
+            session_num_seqs: self.num_changes_total,
+            session_num_seqs_processed_so_far: page.session_items_so_far,
+            session_start_time: page.session_start_time,
+        });
+    }
+
+    fn page_failed(&mut self, logger: &mut MetricsLogger, first: &i64, err: &StageError) {
+        logger.log_diff_log_builder_panic(DiffLogPanicMetrics {
+            panic_time: Utc::now(),
+            panic_on_seq_id: err.seq.unwrap_or(*first),
+            panic_message: err.message.clone(),
+        });
+    }
+
+    fn end_session(
+        &mut self,
+        _conn: &mut DbConnection,
+        logger: &mut MetricsLogger,
+        session: &SessionSummary<i64>,
+    ) {
+        logger.log_diff_log_builder_end_session(DiffLogEndSessionMetrics {
+            session_start_time: session.start_time,
+            session_end_time: session.end_time,
+            session_start_seq_exclusive: session.start,
+            session_end_seq_inclusive: session.end,
+            session_num_seqs: session.num_items,
+            session_total_duration: session.end_time - session.start_time,
+        })
+    }
+}
+
+const RETRY_QUARANTINE_PAGE_SIZE: i64 = 128;
+
+fn retry_quarantine(conn: &mut DbConnection, metrics_logger: &mut MetricsLogger) {
+    let num_quarantined = quarantine::query_num_quarantined_changes(conn);
+    tracing::info!(num_quarantined, "Retrying quarantined changes");
+
+    let mut retried_up_to = 0;
+    let (mut num_fixed, mut num_superseded, mut num_still_failing) = (0, 0, 0);
+
+    loop {
+        let quarantined = quarantine::query_quarantined_changes_after_seq(
+            retried_up_to,
+            RETRY_QUARANTINE_PAGE_SIZE,
+            conn,
+        );
+        if quarantined.is_empty() {
             break;
         }
+        retried_up_to = quarantined.last().unwrap().seq;
 
-        let first_seq_in_page = changes.first().unwrap().seq;
-        let last_seq_in_page = changes.last().unwrap().seq;
-
-        let process_changes_metrics = conn
+        let retry_metrics = conn
             .run_psql_transaction(|mut trans_conn| {
-                match process_changes(&mut trans_conn, changes) {
-                    Ok(res) => {
-                        internal_state::set_diff_log_processed_seq(
-                            last_seq_in_page,
-                            &mut trans_conn,
-                        );
-                        Ok((res, true))
-                    }
-                    Err(err) => {
-                        metrics_logger.log_diff_log_builder_panic(DiffLogPanicMetrics {
-                            panic_time: Utc::now(),
-                            panic_on_seq_id: err.seq,
-                            panic_message: err.message,
-                        });
-                        std::panic::resume_unwind(err.err);
-                    }
-                }
+                Ok((
+                    retry_quarantined_changes(&mut trans_conn, quarantined),
+                    true,
+                ))
             })
             .unwrap();
 
-        processed_up_to = last_seq_in_page;
-
-        let batch_total_duration = batch_start.elapsed();
-
-        metrics_logger.log_diff_log_builder_batch_complete_metrics(DiffLogBatchCompleteMetrics {
-            batch_start_time,
-            batch_start_seq_inclusive: first_seq_in_page,
-            batch_end_seq_inclusive: last_seq_in_page,
-            batch_num_processed_seqs: num_changes,
-            batch_bytes_read: process_changes_metrics.read_bytes as i64,
-            batch_bytes_written: process_changes_metrics.write_bytes as i64,
-            batch_reading_duration: chrono::Duration::from_std(read_duration).unwrap(),
-            batch_writing_duration: chrono::Duration::from_std(
-                process_changes_metrics.write_duration,
-            )
-            .unwrap(),
-            batch_total_duration: chrono::Duration::from_std(batch_total_duration).unwrap(),
-            session_num_seqs: num_changes_total,
-            session_num_seqs_processed_so_far: num_changes_so_far,
-            session_start_time,
-        });
-
-        if num_changes < PAGE_SIZE {
-            break;
+        log_quarantined(metrics_logger, &retry_metrics.still_failing);
+        if let Some(to_seq) = retry_metrics.relational_rewound_to {
+            tracing::info!(to_seq, "Rewound the relational tables");
         }
+
+        num_fixed += retry_metrics.fixed_seqs.len();
+        num_superseded += retry_metrics.superseded_seqs.len();
+        num_still_failing += retry_metrics.still_failing.len();
     }
 
-    let session_end_time = Utc::now();
-    let session_total_duration = session_end_time - session_start_time;
-
-    metrics_logger.log_diff_log_builder_end_session(DiffLogEndSessionMetrics {
-        session_start_time,
-        session_end_time,
-        session_start_seq_exclusive,
-        session_end_seq_inclusive: processed_up_to,
-        session_num_seqs: num_changes_so_far,
-        session_total_duration,
-    })
+    tracing::info!(
+        num_fixed,
+        num_superseded,
+        num_still_failing,
+        "Done retrying quarantine"
+    );
+}
+
+fn log_quarantined(metrics_logger: &mut MetricsLogger, quarantined: &[ProcessChangeError]) {
+    for q in quarantined {
+        tracing::error!(
+            seq = q.seq,
+            package = q.package_name.as_deref(),
+            path = q.json_path.as_deref(),
+            message = %q.message,
+            "Quarantined change"
+        );
+        metrics_logger.log_diff_log_builder_panic(DiffLogPanicMetrics {
+            panic_time: Utc::now(),
+            panic_on_seq_id: q.seq,
+            panic_message: q.message.clone(),
+        });
+    }
 }
@@ -1,6 +1,6 @@
 use super::*;
 use chrono::Utc;
-use diff_log_builder::process_changes;
+use diff_log_builder::process_changes_partitioned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -15,6 +15,13 @@ pub struct DiffTestState {
 }
 
 pub fn run_change_batches(raw_batches: Vec<Vec<Value>>) -> DiffTestState {
+    run_change_batches_partitioned(raw_batches, 1)
+}
+
+pub fn run_change_batches_partitioned(
+    raw_batches: Vec<Vec<Value>>,
+    num_workers: usize,
+) -> DiffTestState {
     let change_recv_time = Utc::now();
 
     postgres_db::connection::testing::using_test_db(|conn| {
@@ -45,7 +52,8 @@ pub fn run_change_batches(raw_batches: Vec<Vec<Value>>) -> DiffTestState {
 
         for batch in change_batches {
             conn.run_psql_transaction(|mut trans_conn| {
-                process_changes(&mut trans_conn, batch).unwrap();
+                let metrics = process_changes_partitioned(&mut trans_conn, batch, num_workers);
+                assert!(metrics.quarantined.is_empty());
                 Ok(((), true))
             })
             .unwrap();
@@ -1,21 +1,30 @@
 use chrono::NaiveDate;
 use postgres_db::{download_metrics::DownloadMetric, packages::Package};
 use serde::Deserialize;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::{
     sync::{Mutex, Semaphore},
     task::JoinHandle,
 };
+use tracing::Instrument;
 
+use crate::client::{DownloadsClient, HttpClient, NPM_API_URL};
 use crate::make_download_metric;
 
+/// How long to pause all queries for when we get rate-limited, unless configured otherwise.
+pub const DEFAULT_RATE_LIMIT_PAUSE: Duration = Duration::from_secs(1200);
+
+/// How many times a rate-limited window of a query is retried after the pause.
+const RATE_LIMIT_RETRIES: usize = 3;
+
 /// API wrapper that handles the rate limiting
 #[derive(Debug, Clone)]
 pub struct API {
     pub pool: Arc<Semaphore>,
     pub pool_size: u32,
     pub rl_lock: Arc<Mutex<()>>,
-    pub client: reqwest::Client,
+    pub client: Arc<dyn DownloadsClient>,
+    pub rate_limit_pause: Duration,
 }
 
 pub type QueryTaskHandle = JoinHandle<(Result<DownloadMetric, ApiError>, Package)>;
@@ -23,20 +32,36 @@ pub type BulkQueryTaskHandle = JoinHandle<(Result<Vec<DownloadMetric>, ApiError>
 
 impl API {
     pub fn new(pool_size: u32) -> API {
+        API::with_client(pool_size, Arc::new(HttpClient::new(NPM_API_URL)))
+    }
+
+    pub fn with_client(pool_size: u32, client: Arc<dyn DownloadsClient>) -> API {
         API {
             pool: Arc::new(Semaphore::new(pool_size as usize)),
             pool_size,
             rl_lock: Arc::new(Mutex::new(())),
-            client: reqwest::Client::new(),
+            client,
+            rate_limit_pause: DEFAULT_RATE_LIMIT_PAUSE,
         }
     }
 
+    pub fn with_rate_limit_pause(mut self, pause: Duration) -> API {
+        self.rate_limit_pause = pause;
+        self
+    }
+
+    /// Waits until the pause after getting rate-limited, if any, is over.
+    pub async fn wait_for_rate_limit(&self) {
+        drop(self.rl_lock.lock().await);
+    }
+
     pub fn spawn_bulk_query_task(
         self,
         pkgs: Vec<Package>,
         lbound: chrono::NaiveDate,
         rbound: chrono::NaiveDate,
     ) -> BulkQueryTaskHandle {
+        let span = tracing::info_span!("bulk_query", num_packages = pkgs.len());
         tokio::spawn(async move {
             let thunk = async {
                 let api_result = self.bulkquery_npm_metrics(&pkgs, &lbound, &rbound).await?;
@@ -44,12 +69,12 @@ impl API {
                 for (pkg_name, result) in api_result {
                     if let Some(result) = result {
                         let pkg = pkgs.iter().find(|p| p.name == pkg_name).unwrap(); // yeah, this is bad
-                        metrics.push(make_download_metric(pkg, &result).await?);
+                        metrics.push(make_download_metric(pkg, &result, lbound, rbound));
                     }
                 }
                 Ok(metrics)
             };
-            (thunk.await, pkgs)
+            (thunk.instrument(span).await, pkgs)
         })
     }
 
@@ -59,35 +84,34 @@ impl API {
         lbound: chrono::NaiveDate,
         rbound: chrono::NaiveDate,
     ) -> QueryTaskHandle {
+        let span = tracing::info_span!("query", package = %pkg.name);
         tokio::spawn(async move {
             let thunk = async {
                 let api_result = self.query_npm_metrics(&pkg, &lbound, &rbound).await?;
-                make_download_metric(&pkg, &api_result).await
+                Ok(make_download_metric(&pkg, &api_result, lbound, rbound))
             };
-            (thunk.await, pkg)
+            (thunk.instrument(span).await, pkg)
         })
     }
 
-    /// Sends a query with reqwest, taking account of the rate limit pool.
+    /// Sends a query with the client, taking account of the rate limit pool.
     async fn send_query(&self, query: &str) -> Result<(Option<String>, bool), ApiError> {
-        {
-            self.rl_lock.lock().await;
-        }
+        self.wait_for_rate_limit().await;
         let permit = self.pool.acquire().await.unwrap();
 
         let max_retries = 10;
         for retry_count in 0..max_retries {
-            let resp_result = self.client.get(query).send().await;
+            let resp_result = self.client.get(query).await;
             let resp = match resp_result {
-                Err(err) => {
+                Err(ApiError::Reqwest(err)) => {
                     let err_str = format!("{}", err);
                     if err_str.contains("connection closed before message completed")
                         && retry_count < max_retries - 1
                     {
                         // sleep for 10 seconds
-                        println!(
-                            "received bad response: {:?}, sleeping for 10 seconds before retry",
-                            Err::<(), _>(err)
+                        tracing::warn!(
+                            error = %err,
+                            "Received a bad response, sleeping for 10 seconds before retrying"
                         );
                         tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                         continue;
@@ -95,14 +119,15 @@ impl API {
                         return Err(ApiError::Reqwest(err));
                     }
                 }
+                Err(e) => return Err(e),
                 Ok(resp) => resp,
             };
 
-            if resp.status() == 429 {
+            if resp.status == 429 {
                 drop(permit);
                 return Ok((None, true));
             } else {
-                let text = resp.text().await?;
+                let text = resp.body;
                 let trimmed = text.trim();
                 if trimmed.is_empty()
                     || trimmed == "Internal Server Error"
@@ -110,9 +135,9 @@ impl API {
                     || trimmed.contains("502 Bad Gateway")
                 {
                     // sleep for 10 seconds
-                    println!(
-                        "received bad response: {}, sleeping for 10 seconds before retry",
-                        text
+                    tracing::warn!(
+                        response = %text,
+                        "Received a bad response, sleeping for 10 seconds before retrying"
                     );
                     tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                 } else {
@@ -123,26 +148,34 @@ impl API {
         }
 
         drop(permit);
-        Ok((None, true))
+        Err(ApiError::Other(format!(
+            "no good response after {} retries",
+            max_retries
+        )))
     }
 
     /// When we are getting rate limited, this function is called, and it handles the sleep.
-    /// The idea is to get only one thread to sleep and all the others to return.
+    /// The idea is to get only one thread to sleep and all the others to return. Queries wait
+    /// for the sleep to be over before being sent.
     async fn handle_rate_limit(&self) {
         let lock = match self.rl_lock.try_lock() {
             Ok(l) => l,
             Err(_) => return,
         };
-        let time = std::time::Duration::from_secs(1200);
-        println!(
-            "Rate-limit hit, sleeping for {} minutes",
-            (time.as_secs() as f64) / 60.0
+        let time = self.rate_limit_pause;
+        tracing::warn!(
+            pause_minutes = (time.as_secs() as f64) / 60.0,
+            "Rate-limit hit, pausing all queries"
         );
         tokio::time::sleep(time).await;
         drop(lock);
     }
 
     /// Abstraction to remove duplicate code between the bulk and single query functions.
+    /// Windows that fail for reasons other than rate-limiting or the package not existing are
+    /// left out of the result, so they become gaps in the counts. A rate-limited window is
+    /// retried after the pause, and if it's still rate-limited, the windows we already have are
+    /// returned and the rest become gaps. Only if we have none is it a `RateLimit` error.
     async fn query_abstraction<T, R: for<'a> Deserialize<'a>, M>(
         &self,
         thing_to_query: &T,
@@ -172,26 +205,73 @@ impl API {
 
             let query_thing = formatter(thing_to_query);
 
-            // println!(
-            //     "Querying {} from {} to {}",
-            //     query_thing, rel_lbound, rel_rbound
-            // );
-
             let query = format!(
-                "https://api.npmjs.org/downloads/range/{}:{}/{}",
+                "downloads/range/{}:{}/{}",
                 rel_lbound, rel_rbound, query_thing
             );
 
-            println!("Querying {}", query);
-            let (resp_text, is_rate_limited) = self.send_query(&query).await?;
+            let window = tracing::info_span!("window", lbound = %rel_lbound, rbound = %rel_rbound);
+            tracing::debug!(parent: &window, %query, "Querying");
+            let next_lbound = rel_rbound + chronoutil::RelativeDuration::days(1);
+
+            let mut retries = 0;
+            let sent = loop {
+                let sent = self.send_query(&query).instrument(window.clone()).await;
+                if matches!(sent, Ok((_, true))) && retries < RATE_LIMIT_RETRIES {
+                    retries += 1;
+                    tracing::warn!(
+                        parent: &window,
+                        retries,
+                        "Rate-limited, retrying after the pause"
+                    );
+                    self.handle_rate_limit().await;
+                    continue;
+                }
+                break sent;
+            };
+
+            let (resp_text, is_rate_limited) = match sent {
+                Ok(resp) => resp,
+                Err(e @ (ApiError::Reqwest(_) | ApiError::Other(_))) => {
+                    tracing::warn!(
+                        parent: &window,
+                        error = %e,
+                        "Skipping the window, it becomes a gap"
+                    );
+                    rel_lbound = next_lbound;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
             if is_rate_limited {
                 self.handle_rate_limit().await;
-                return Err(ApiError::RateLimit);
+                return match api_result {
+                    Some(partial) => {
+                        tracing::warn!(
+                            parent: &window,
+                            "Still rate-limited, the rest of the windows become gaps"
+                        );
+                        Ok(partial)
+                    }
+                    None => Err(ApiError::RateLimit),
+                };
             }
             let text = resp_text.unwrap();
 
-            let result: R = parse_resp(text)?;
+            let result: R = match parse_resp(text) {
+                Ok(result) => result,
+                Err(ApiError::Other(e)) => {
+                    tracing::warn!(
+                        parent: &window,
+                        error = %e,
+                        "Skipping the window, it becomes a gap"
+                    );
+                    rel_lbound = next_lbound;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
             if api_result.is_none() {
                 api_result = Some(result);
@@ -201,9 +281,9 @@ impl API {
                 api_result = Some(new_api_result);
             }
 
-            rel_lbound = rel_rbound + chronoutil::RelativeDuration::days(1);
+            rel_lbound = next_lbound;
         }
-        api_result.ok_or_else(|| panic!("api_result is None"))
+        api_result.ok_or_else(|| ApiError::Other("every window failed".to_string()))
     }
 
     /// Performs a regular query in npm download metrics api for each package given
@@ -359,7 +439,7 @@ impl API {
                 *new_pkg_res_opt = Some(new_pkg_res);
             }
             if !not_founds.is_empty() {
-                println!("Not found: {}", not_founds);
+                tracing::info!(packages = %not_founds, "Not found");
             }
         };
         self.query_abstraction(pkgs, formatter, merger, lbound, rbound)
@@ -374,12 +454,12 @@ impl Default for API {
 }
 
 fn serde_panic_fn<T>(text: &str, e: serde_json::Error) -> ! {
-    println!(
-        "failed to deserialize into type: {}",
-        std::any::type_name::<T>()
+    tracing::error!(
+        into = std::any::type_name::<T>(),
+        text,
+        error = ?e,
+        "Failed to deserialize a response"
     );
-    println!("failed to deserialize: {}", text);
-    println!("error: {:?}", e);
     std::process::exit(1);
     // panic!("error deserializing");
 }
@@ -430,6 +510,8 @@ pub enum ApiError {
     DoesNotExist,
     Other(String), // where String is the error message
     RateLimit,
+    /// A fixture is missing or can't be read.
+    Fixture(String),
 }
 
 impl From<reqwest::Error> for ApiError {
@@ -452,6 +534,7 @@ impl std::fmt::Display for ApiError {
             ApiError::RateLimit => write!(f, "rate-limited"),
             ApiError::DoesNotExist => write!(f, "package does not exist"),
             ApiError::Other(msg) => write!(f, "other error: {}", msg),
+            ApiError::Fixture(msg) => write!(f, "fixture error: {}", msg),
         }
     }
 }
@@ -0,0 +1,169 @@
+//! Where the api's responses come from: api.npmjs.org, a stand-in for it at another url (see
+//! [`crate::standin`]), or fixtures recorded from either.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api::ApiError;
+
+pub const NPM_API_URL: &str = "https://api.npmjs.org";
+
+#[async_trait]
+pub trait DownloadsClient: Debug + Send + Sync {
+    /// GETs a path like `downloads/range/2022-01-01:2022-12-31/react`.
+    async fn get(&self, path: &str) -> Result<ApiResponse, ApiError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Picks the client from the environment: `DOWNLOAD_METRICS_REPLAY=<dir>` replays fixtures,
+/// otherwise we talk to `NPM_DOWNLOADS_API_URL` (api.npmjs.org by default), recording fixtures
+/// if `DOWNLOAD_METRICS_RECORD=<dir>` is set.
+pub fn client_from_env() -> Arc<dyn DownloadsClient> {
+    if let Ok(dir) = std::env::var("DOWNLOAD_METRICS_REPLAY") {
+        tracing::info!(%dir, "Replaying fixtures");
+        return Arc::new(ReplayClient::new(dir));
+    }
+
+    let url = std::env::var("NPM_DOWNLOADS_API_URL").unwrap_or_else(|_| NPM_API_URL.to_string());
+    let http = HttpClient::new(url);
+    match std::env::var("DOWNLOAD_METRICS_RECORD") {
+        Ok(dir) => {
+            tracing::info!(%dir, "Recording fixtures");
+            Arc::new(RecordingClient::new(http, dir))
+        }
+        Err(_) => Arc::new(http),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl HttpClient {
+    pub fn new(base_url: impl Into<String>) -> HttpClient {
+        HttpClient {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl DownloadsClient for HttpClient {
+    async fn get(&self, path: &str) -> Result<ApiResponse, ApiError> {
+        let resp = self
+            .client
+            .get(format!("{}/{}", self.base_url, path))
+            .send()
+            .await?;
+        let status = resp.status().as_u16();
+        let body = resp.text().await?;
+        Ok(ApiResponse { status, body })
+    }
+}
+
+/// The responses to one path, in the order they were received.
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    path: String,
+    responses: Vec<ApiResponse>,
+}
+
+/// Fixtures are named by a hash of their path, since bulk paths are too long for file names.
+fn fixture_file(dir: &Path, path: &str) -> PathBuf {
+    let hash = Sha256::digest(path.as_bytes());
+    let name: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    dir.join(format!("{}.json", name))
+}
+
+fn read_fixture(file: &Path) -> Result<Option<Fixture>, ApiError> {
+    match std::fs::read_to_string(file) {
+        Ok(s) => serde_json::from_str(&s)
+            .map(Some)
+            .map_err(|e| ApiError::Fixture(format!("{}: {}", file.display(), e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Passes requests through to another client, appending every response to a fixture.
+#[derive(Debug)]
+pub struct RecordingClient<C> {
+    inner: C,
+    dir: PathBuf,
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl<C> RecordingClient<C> {
+    pub fn new(inner: C, dir: impl Into<PathBuf>) -> RecordingClient<C> {
+        RecordingClient {
+            inner,
+            dir: dir.into(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<C: DownloadsClient> DownloadsClient for RecordingClient<C> {
+    async fn get(&self, path: &str) -> Result<ApiResponse, ApiError> {
+        let resp = self.inner.get(path).await?;
+
+        let _lock = self.lock.lock().await;
+        std::fs::create_dir_all(&self.dir)?;
+        let file = fixture_file(&self.dir, path);
+        let mut fixture = read_fixture(&file)?.unwrap_or_else(|| Fixture {
+            path: path.to_string(),
+            responses: vec![],
+        });
+        fixture.responses.push(resp.clone());
+        std::fs::write(&file, serde_json::to_string_pretty(&fixture).unwrap())?;
+
+        Ok(resp)
+    }
+}
+
+/// Serves recorded fixtures without touching the network. The responses to a path are served in
+/// the order they were recorded, and the last one is repeated after that.
+#[derive(Debug)]
+pub struct ReplayClient {
+    dir: PathBuf,
+    served: std::sync::Mutex<HashMap<String, usize>>,
+}
+
+impl ReplayClient {
+    pub fn new(dir: impl Into<PathBuf>) -> ReplayClient {
+        ReplayClient {
+            dir: dir.into(),
+            served: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl DownloadsClient for ReplayClient {
+    async fn get(&self, path: &str) -> Result<ApiResponse, ApiError> {
+        let fixture = read_fixture(&fixture_file(&self.dir, path))?
+            .filter(|f| !f.responses.is_empty())
+            .ok_or_else(|| ApiError::Fixture(format!("no fixture for {}", path)))?;
+
+        let mut served = self.served.lock().unwrap();
+        let count = served.entry(path.to_string()).or_insert(0);
+        let i = (*count).min(fixture.responses.len() - 1);
+        *count += 1;
+        Ok(fixture.responses[i].clone())
+    }
+}
@@ -0,0 +1,388 @@
+//! The fetching runs of the `download_metrics` binary. They work with any [`API`] client, and
+//! when rate-limited they save their progress, wait out the pause, and resume, instead of exiting.
+
+use std::time::Instant;
+
+use chrono::NaiveDate;
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
+use postgres_db::connection::DbConnection;
+use postgres_db::custom_types::PackageStateType;
+use postgres_db::download_metrics::DownloadMetric;
+use postgres_db::download_metrics::QueriedDownloadMetric;
+use postgres_db::packages::Package;
+
+use crate::api::ApiError;
+use crate::api::QueryTaskHandle;
+use crate::api::API;
+use crate::make_gap_metric;
+use crate::Bounds;
+
+const COMPONENT: &str = "download_metrics";
+
+/// Inserts new download metric rows by using the `packages` table and querying npm.
+/// The id of the next package to query is saved after every chunk. If packages get rate-limited,
+/// it's the id of the first of them, and we resume from it once the pause is over. Packages that
+/// fail for other reasons get a metric whose whole range is a gap.
+pub async fn insert_from_packages(
+    conn: &mut DbConnection,
+    logger: &mut MetricsLogger,
+    api: &API,
+    bounds: Bounds,
+) {
+    let mut pkg_id = postgres_db::internal_state::query_download_metrics_pkg_seq(conn).unwrap_or(1);
+
+    tracing::info!(pkg_id, "Inserting metrics");
+
+    // NOTE: Bulk queries are limited to at most 128 packages at a time and at most 365 days of data.
+    //       however, we can't bulk query scoped packages.
+
+    // therefore we run in chunks of 128 packages (+ scoped packages, max 128 too for consistency)
+
+    let mut finished = false; // we break the loop if we have no more packages to query
+    while !finished {
+        let chunk_start = Instant::now();
+        let mut num_failed = 0;
+        let mut chunk_pkg_id = pkg_id;
+        let mut normal_packages = Vec::new();
+        let mut scoped_packages = Vec::new();
+
+        while normal_packages.len() < 128 && scoped_packages.len() < 128 {
+            let pkg = postgres_db::packages::maybe_get_package(conn, chunk_pkg_id);
+            match pkg {
+                None => {
+                    // could be that ids are not contiguous, so we need to get the next id
+                    let next_pkg_id = postgres_db::packages::query_next_pkg_id(conn, chunk_pkg_id);
+                    match next_pkg_id {
+                        None => {
+                            // no more packages to query
+                            finished = true;
+                            break;
+                        }
+                        Some(next_pkg_id) => {
+                            tracing::debug!(
+                                pkg_id = chunk_pkg_id,
+                                next_pkg_id,
+                                "No package with the id, skipping to the next id"
+                            );
+                            chunk_pkg_id = next_pkg_id;
+                        }
+                    }
+                }
+                Some(pkg) => {
+                    let has_metrics =
+                        postgres_db::download_metrics::has_metrics_for_package_id(conn, pkg.id);
+                    if has_normal_metadata(&pkg) && !has_metrics {
+                        if pkg.name.starts_with('@') {
+                            scoped_packages.push(pkg);
+                        } else {
+                            normal_packages.push(pkg);
+                        }
+                    } else if has_metrics {
+                        tracing::trace!(package = %pkg.name, "Already has metrics, skipping");
+                    }
+                    chunk_pkg_id += 1;
+                }
+            }
+        }
+
+        let mut download_metrics: Vec<DownloadMetric> = Vec::new();
+        let mut rate_limited: Vec<Package> = Vec::new();
+        let mut scoped_handles: Vec<QueryTaskHandle> = Vec::new();
+
+        // normal packages can be queried in bulk
+        let maybe_bulk_handle = {
+            if !normal_packages.is_empty() {
+                let api = api.clone();
+                Some(api.spawn_bulk_query_task(normal_packages, bounds.lower, bounds.upper))
+            } else {
+                None
+            }
+        };
+
+        // scoped packages need to be handled separately one-by-one
+        for pkg in scoped_packages {
+            let api = api.clone();
+            scoped_handles.push(api.spawn_query_task(pkg, bounds.lower, bounds.upper));
+        }
+
+        for handle in scoped_handles {
+            match handle.await.unwrap() {
+                (Ok(metric), _) => {
+                    pretty_print_metric(&metric);
+                    download_metrics.push(metric);
+                }
+                (Err(ApiError::RateLimit), pkg) => {
+                    tracing::warn!(package = %pkg.name, "Rate-limited");
+                    rate_limited.push(pkg);
+                }
+                (Err(ApiError::DoesNotExist), pkg) => {
+                    tracing::warn!(package = %pkg.name, "Package does not exist");
+                    num_failed += 1;
+                }
+                (Err(e), pkg) => {
+                    tracing::error!(
+                        package = %pkg.name,
+                        error = %e,
+                        "Query failed, recording a gap"
+                    );
+                    num_failed += 1;
+                    download_metrics.push(make_gap_metric(&pkg, bounds.lower, bounds.upper));
+                }
+            };
+        }
+
+        if let Some(bulk_handle) = maybe_bulk_handle {
+            match bulk_handle.await.unwrap() {
+                (Ok(metrics), _) => {
+                    // NOTE: the result may not have the same packages as the query (some
+                    //       packages don't exist)
+                    for metric in metrics {
+                        pretty_print_metric(&metric);
+                        download_metrics.push(metric);
+                    }
+                }
+                (Err(ApiError::RateLimit), pkgs) => {
+                    tracing::warn!(num_packages = pkgs.len(), "Rate-limited");
+                    rate_limited.extend(pkgs);
+                }
+                (Err(ApiError::DoesNotExist), pkgs) => {
+                    let names: Vec<&str> = pkgs.iter().map(|p| p.name.as_str()).collect();
+                    tracing::warn!(packages = ?names, "Packages do not exist");
+                    num_failed += pkgs.len();
+                }
+                (Err(e), pkgs) => {
+                    tracing::error!(
+                        num_packages = pkgs.len(),
+                        error = %e,
+                        "Bulk query failed, recording gaps"
+                    );
+                    num_failed += pkgs.len();
+                    for pkg in pkgs {
+                        download_metrics.push(make_gap_metric(&pkg, bounds.lower, bounds.upper));
+                    }
+                }
+            };
+        }
+
+        // the packages after the first rate-limited one that we did get are skipped when we
+        // resume, since they have metrics by then
+        let next_pkg_id = rate_limited
+            .iter()
+            .map(|p| p.id)
+            .min()
+            .unwrap_or(chunk_pkg_id);
+
+        logger.log_event(
+            MetricsEvent::new(COMPONENT, EventKind::Progress)
+                .count("metrics", download_metrics.len() as u64)
+                .count("failed", num_failed as u64)
+                .count("rate_limited", rate_limited.len() as u64)
+                .gauge("pkg_id", next_pkg_id)
+                .duration("duration", chunk_start.elapsed()),
+        );
+        conn.run_psql_transaction(|mut conn| {
+            for metric in download_metrics {
+                postgres_db::download_metrics::insert_download_metric(&mut conn, metric);
+            }
+            postgres_db::internal_state::set_download_metrics_pkg_seq(next_pkg_id, &mut conn);
+            Ok(((), true))
+        })
+        .expect("failed to insert download metrics");
+
+        pkg_id = next_pkg_id;
+        if !rate_limited.is_empty() {
+            tracing::info!(pkg_id, "Pausing, then resuming from the pkg_id");
+            api.wait_for_rate_limit().await;
+            finished = false;
+        }
+    }
+
+    tracing::info!(pkg_id, "Done inserting metrics");
+}
+
+/// Fetches the days since the latest one of metrics that are more than a week old.
+pub async fn update_from_packages(
+    conn: &mut DbConnection,
+    logger: &mut MetricsLogger,
+    api: &API,
+    bounds: Bounds,
+) {
+    let mut metrics = query_metrics_older_than_a_week(conn, bounds);
+    while !metrics.is_empty() {
+        refetch_metrics(
+            conn,
+            logger,
+            api,
+            bounds,
+            metrics,
+            |metric| metric.latest_date,
+            false,
+        )
+        .await;
+        // rate-limited metrics are still old, so they're queried again
+        metrics = query_metrics_older_than_a_week(conn, bounds);
+    }
+
+    tracing::info!("Done updating metrics");
+}
+
+/// Fetches the whole history of packages that still have legacy weekly counts, replacing them
+/// with daily counts.
+pub async fn backfill_daily_counts(
+    conn: &mut DbConnection,
+    logger: &mut MetricsLogger,
+    api: &API,
+    bounds: Bounds,
+) {
+    // packages that fail keep their weekly counts, so we page by id to not retry them forever
+    let mut after_id = 0;
+    loop {
+        let metrics =
+            postgres_db::download_metrics::query_metrics_with_weekly_counts(conn, after_id, 128);
+        match metrics.last() {
+            Some(last) => after_id = last.id,
+            None => break,
+        }
+        let rate_limited =
+            refetch_metrics(conn, logger, api, bounds, metrics, |_| bounds.lower, true).await;
+        if let Some(first) = rate_limited.iter().min() {
+            after_id = first - 1;
+        }
+    }
+
+    tracing::info!("Done backfilling daily counts");
+}
+
+/// Fetches the counts of `metrics` from the day `lower_bound` returns for each through
+/// `bounds.upper`. The fetched days replace what we had for them, and if `clear_weekly` is set, so
+/// do the legacy weekly counts. If any get rate-limited, returns their ids once the pause is over.
+async fn refetch_metrics<L>(
+    conn: &mut DbConnection,
+    logger: &mut MetricsLogger,
+    api: &API,
+    bounds: Bounds,
+    metrics: Vec<QueriedDownloadMetric>,
+    lower_bound: L,
+    clear_weekly: bool,
+) -> Vec<i64>
+where
+    L: Fn(&QueriedDownloadMetric) -> NaiveDate,
+{
+    let start = Instant::now();
+    let mut handles: Vec<(i64, QueryTaskHandle)> = Vec::new();
+
+    for metric in metrics {
+        let pkg = postgres_db::packages::get_package(conn, metric.package_id);
+        let lower_bound_date = lower_bound(&metric);
+
+        let api = api.clone();
+        handles.push((
+            metric.id,
+            api.spawn_query_task(pkg, lower_bound_date, bounds.upper),
+        ));
+    }
+
+    // where i64 is the id of the metric
+    let mut metrics_to_upd: Vec<(i64, DownloadMetric)> = Vec::new();
+    let mut rate_limited: Vec<i64> = Vec::new();
+    let mut num_failed = 0;
+
+    for (id, handle) in handles {
+        let metric = match handle.await.unwrap() {
+            (Ok(metric), _) => metric,
+            (Err(ApiError::RateLimit), _) => {
+                rate_limited.push(id);
+                continue;
+            }
+            (Err(e), pkg) => {
+                tracing::error!(package = %pkg.name, error = %e, "Query failed");
+                num_failed += 1;
+                continue;
+            }
+        };
+
+        pretty_print_metric(&metric);
+        metrics_to_upd.push((id, metric));
+    }
+
+    logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::Progress)
+            .count("metrics", metrics_to_upd.len() as u64)
+            .count("failed", num_failed)
+            .count("rate_limited", rate_limited.len() as u64)
+            .duration("duration", start.elapsed()),
+    );
+    conn.run_psql_transaction(|mut conn| {
+        for (id, metric) in metrics_to_upd {
+            postgres_db::download_metrics::update_metric_by_id(&mut conn, id, metric);
+            if clear_weekly {
+                postgres_db::download_metrics::clear_weekly_download_counts(&mut conn, id);
+            }
+        }
+        Ok(((), true))
+    })
+    .expect("couldn't run transaction");
+
+    if !rate_limited.is_empty() {
+        tracing::warn!(num_packages = rate_limited.len(), "Rate-limited, pausing");
+        api.wait_for_rate_limit().await;
+    }
+    rate_limited
+}
+
+/// Queries all metrics older than a week. The query is limited to 128 results.
+fn query_metrics_older_than_a_week(
+    conn: &mut DbConnection,
+    bounds: Bounds,
+) -> Vec<QueriedDownloadMetric> {
+    let week_ago = get_a_week_ago(&bounds.lower, &bounds.upper) - chrono::Duration::days(7);
+    tracing::debug!(before = %week_ago, "Querying metrics older than a week");
+    postgres_db::download_metrics::query_metric_latest_less_than(conn, week_ago, 128)
+}
+
+/// Helper to check if a package has normal metadata
+fn has_normal_metadata(pkg: &Package) -> bool {
+    pkg.current_package_state_type == PackageStateType::Normal
+}
+
+/// Returns the earliest date that matches a week given the epoch, using the same logic as npm
+/// queries.
+fn get_a_week_ago(lbound: &chrono::NaiveDate, rbound: &chrono::NaiveDate) -> NaiveDate {
+    let delta = chronoutil::RelativeDuration::years(1);
+    let mut rel_lbound = *lbound;
+    let mut res = *lbound;
+    let rule = chronoutil::DateRule::new(rel_lbound + delta, delta);
+    for mut rel_rbound in rule {
+        if rel_lbound > *rbound {
+            break;
+        }
+
+        if rel_rbound > *rbound {
+            rel_rbound = *rbound;
+        }
+
+        res = rel_rbound;
+        rel_lbound = rel_rbound + chronoutil::RelativeDuration::days(1);
+    }
+
+    // now traverse weeks, until we get a week less than `res`
+    let delta = chrono::Duration::weeks(1);
+    let mut rel_lbound = *lbound;
+    let rbound = res;
+
+    while rel_lbound < rbound {
+        rel_lbound += delta;
+    }
+
+    rel_lbound - delta
+}
+
+fn pretty_print_metric(metric: &DownloadMetric) {
+    tracing::trace!(
+        package_id = metric.package_id,
+        latest = %metric.latest_date,
+        num_counts = metric.counts.len(),
+        num_gaps = metric.gaps.len(),
+        "Fetched metric"
+    );
+}
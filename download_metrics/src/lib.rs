@@ -1,11 +1,17 @@
-use api::{ApiError, ApiResult};
+use std::collections::HashSet;
+
+use api::ApiResult;
 use chrono::NaiveDate;
 use lazy_static::lazy_static;
 use postgres_db::{
-    custom_types::DownloadCount, download_metrics::DownloadMetric, packages::Package,
+    download_metrics::{DailyDownloadCount, DownloadCountGap, DownloadMetric},
+    packages::Package,
 };
 
 pub mod api;
+pub mod client;
+pub mod fetch;
+pub mod standin;
 
 lazy_static!(
     pub static ref LOWER_BOUND_DATE: NaiveDate = chrono::NaiveDate::from_ymd_opt(2015, 1, 10).unwrap();
@@ -17,45 +23,92 @@ lazy_static!(
                                                  - chrono::Duration::days(3);
 );
 
-pub async fn make_download_metric(
+/// The days to fetch counts for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub lower: NaiveDate,
+    pub upper: NaiveDate,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds {
+            lower: *LOWER_BOUND_DATE,
+            upper: *UPPER_BOUND_DATE,
+        }
+    }
+}
+
+/// Converts the daily counts the api returned for the days from `lbound` through `rbound` into a
+/// `DownloadMetric`. Zero counts are left out, and the days that the api didn't return, or
+/// returned null counts for, become gaps.
+pub fn make_download_metric(
     pkg: &Package,
     api_result: &ApiResult,
-) -> Result<DownloadMetric, ApiError> {
-    // we need to convert the results into DownloadMetric, merging daily results
-    // into weekly results
-    let mut weekly_results: Vec<DownloadCount> = Vec::new();
-    let mut i = 0;
-
-    loop {
-        let mut weekly_count: Option<i64> = api_result.downloads[i].downloads;
-        let mut j = i + 1;
-        while j < api_result.downloads.len() && j < i + 7 {
-            weekly_count =
-                weekly_count.and_then(|wc| api_result.downloads[j].downloads.map(|cc| wc + cc));
-            j += 1;
-        }
+    lbound: NaiveDate,
+    rbound: NaiveDate,
+) -> DownloadMetric {
+    let mut counts = Vec::new();
+    let mut gaps: Vec<DownloadCountGap> = Vec::new();
+    let mut known = HashSet::new();
 
-        let date =
-            chrono::NaiveDate::parse_from_str(&api_result.downloads[i].day, "%Y-%m-%d").unwrap();
+    for dl in &api_result.downloads {
+        let day = chrono::NaiveDate::parse_from_str(&dl.day, "%Y-%m-%d").unwrap();
+        if day < lbound || day > rbound {
+            continue;
+        }
+        if let Some(count) = dl.downloads {
+            known.insert(day);
+            // we don't insert zero counts, they're implied by the lack of a row
+            if count > 0 {
+                counts.push(DailyDownloadCount {
+                    package_id: pkg.id,
+                    day,
+                    count,
+                });
+            }
+        }
+    }
 
-        // we set i to j so that we skip the days we already counted
-        i = j;
+    let mut day = lbound;
+    while day <= rbound {
+        if !known.contains(&day) {
+            match gaps.last_mut() {
+                Some(gap) if gap.end_date.succ_opt() == Some(day) => gap.end_date = day,
+                _ => gaps.push(DownloadCountGap {
+                    package_id: pkg.id,
+                    start_date: day,
+                    end_date: day,
+                }),
+            }
+        }
+        day = day.succ_opt().unwrap();
+    }
 
-        let count = DownloadCount {
-            date,
-            count: weekly_count,
-        };
+    counts.sort_unstable_by_key(|c| c.day);
+    counts.dedup_by_key(|c| c.day);
 
-        // we don't insert zero counts or null counts
-        if matches!(weekly_count, Some(x) if x > 0) {
-            weekly_results.push(count);
-        }
+    DownloadMetric {
+        package_id: pkg.id,
+        start_date: lbound,
+        latest_date: rbound,
+        counts,
+        gaps,
+    }
+}
 
-        if i >= api_result.downloads.len() {
-            // we still want to know the latest, even if it's zero and we didn't insert it
-            let latest = date;
-            // println!("did package {}", pkg.name);
-            return Ok(DownloadMetric::new(pkg.id, weekly_results, latest));
-        }
+/// The `DownloadMetric` of a package we couldn't fetch any counts of, so all the days from
+/// `lbound` through `rbound` are a gap.
+pub fn make_gap_metric(pkg: &Package, lbound: NaiveDate, rbound: NaiveDate) -> DownloadMetric {
+    DownloadMetric {
+        package_id: pkg.id,
+        start_date: lbound,
+        latest_date: rbound,
+        counts: vec![],
+        gaps: vec![DownloadCountGap {
+            package_id: pkg.id,
+            start_date: lbound,
+            end_date: rbound,
+        }],
     }
 }
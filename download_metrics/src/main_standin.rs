@@ -0,0 +1,44 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use download_metrics::standin::{serve, StandIn};
+
+const USAGE: &str = "[--port <port>] [--rate-limit-every <n>]";
+
+/// Serves made-up download counts on localhost, for running `download_metrics` with
+/// `NPM_DOWNLOADS_API_URL=http://127.0.0.1:<port>`.
+#[tokio::main]
+async fn main() {
+    let _tracing = utils::init_tracing("downloads_api_standin");
+    let args = std::env::args().collect::<Vec<_>>();
+    let program = &args[0];
+
+    let mut port: u16 = 8086;
+    let mut rate_limit_every = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--port" => {
+                let p = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                port = p.parse().unwrap_or_else(|_| exit_with_usage(program));
+            }
+            "--rate-limit-every" => {
+                let n = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                rate_limit_every = Some(n.parse().unwrap_or_else(|_| exit_with_usage(program)));
+            }
+            _ => exit_with_usage(program),
+        }
+    }
+
+    let standin = Arc::new(StandIn::new(rate_limit_every));
+    let (addr, server) = serve(standin, &SocketAddr::from(([127, 0, 0, 1], port)), async {
+        tokio::signal::ctrl_c().await.ok();
+    });
+    tracing::info!(%addr, "Listening");
+    server.await.expect("stand-in server failed");
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
@@ -0,0 +1,169 @@
+//! A local stand-in for the download counts api of api.npmjs.org, so the fetching can be run and
+//! tested offline. Counts are made up, but always the same for a package and day, and the
+//! stand-in can be told to rate-limit some of the requests.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use chrono::{Datelike, NaiveDate};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::{json, Map, Value};
+
+use crate::client::ApiResponse;
+
+/// Packages with names starting with this don't exist.
+pub const MISSING_PREFIX: &str = "missing-";
+/// Requests for packages with names (without the scope) starting with this fail with a server
+/// error.
+pub const BROKEN_PREFIX: &str = "broken-";
+
+#[derive(Debug, Default)]
+pub struct StandIn {
+    /// Rate-limit every nth request, if set.
+    rate_limit_every: Option<usize>,
+    requests: AtomicUsize,
+}
+
+impl StandIn {
+    pub fn new(rate_limit_every: Option<usize>) -> StandIn {
+        StandIn {
+            rate_limit_every,
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Responds to a path like `downloads/range/2022-01-01:2022-12-31/react,lodash`.
+    pub fn respond(&self, path: &str) -> ApiResponse {
+        let n = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        if matches!(self.rate_limit_every, Some(every) if n % every == 0) {
+            return ApiResponse {
+                status: 429,
+                body: "Too Many Requests".to_string(),
+            };
+        }
+
+        let (start, end, names) = match parse_range_path(path) {
+            Some(parsed) => parsed,
+            None => return error(400, format!("invalid path: {}", path)),
+        };
+
+        if names.iter().any(|name| is_broken(name)) {
+            return error(500, "broken package".to_string());
+        }
+
+        if let [name] = names.as_slice() {
+            if name.starts_with(MISSING_PREFIX) {
+                return error(404, format!("package {} not found", name));
+            }
+            return ok(range_json(name, start, end));
+        }
+
+        let results: Map<String, Value> = names
+            .iter()
+            .map(|name| {
+                let result = if name.starts_with(MISSING_PREFIX) {
+                    Value::Null
+                } else {
+                    range_json(name, start, end)
+                };
+                (name.to_string(), result)
+            })
+            .collect();
+        ok(Value::Object(results))
+    }
+}
+
+/// The made-up count of a package on a day, or `None` if the api returns a null count for it.
+pub fn made_up_count(package: &str, day: NaiveDate) -> Option<i64> {
+    let seed: u32 = package.bytes().map(u32::from).sum::<u32>() + day.ordinal();
+    if seed % 29 == 0 {
+        None
+    } else {
+        Some((seed % 5) as i64)
+    }
+}
+
+fn is_broken(name: &str) -> bool {
+    let unscoped = name.rsplit('/').next().unwrap_or(name);
+    unscoped.starts_with(BROKEN_PREFIX)
+}
+
+fn parse_range_path(path: &str) -> Option<(NaiveDate, NaiveDate, Vec<&str>)> {
+    let rest = path
+        .trim_start_matches('/')
+        .strip_prefix("downloads/range/")?;
+    let (range, names) = rest.split_once('/')?;
+    let (start, end) = range.split_once(':')?;
+    let start = NaiveDate::parse_from_str(start, "%Y-%m-%d").ok()?;
+    let end = NaiveDate::parse_from_str(end, "%Y-%m-%d").ok()?;
+    let names: Vec<&str> = names.split(',').filter(|n| !n.is_empty()).collect();
+    if start > end || names.is_empty() {
+        return None;
+    }
+    Some((start, end, names))
+}
+
+fn range_json(name: &str, start: NaiveDate, end: NaiveDate) -> Value {
+    let mut downloads = vec![];
+    let mut day = start;
+    while day <= end {
+        downloads.push(json!({
+            "day": day.format("%Y-%m-%d").to_string(),
+            "downloads": made_up_count(name, day),
+        }));
+        day = day.succ_opt().unwrap();
+    }
+    json!({
+        "start": start.format("%Y-%m-%d").to_string(),
+        "end": end.format("%Y-%m-%d").to_string(),
+        "package": name,
+        "downloads": downloads,
+    })
+}
+
+fn ok(body: Value) -> ApiResponse {
+    ApiResponse {
+        status: 200,
+        body: body.to_string(),
+    }
+}
+
+fn error(status: u16, message: String) -> ApiResponse {
+    ApiResponse {
+        status,
+        body: json!({ "error": message }).to_string(),
+    }
+}
+
+/// Binds an http server for the stand-in to `addr`, returning the address it's listening on and
+/// the future that runs it until `shutdown` completes.
+pub fn serve(
+    standin: Arc<StandIn>,
+    addr: &SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> (SocketAddr, impl Future<Output = Result<(), hyper::Error>>) {
+    let make_svc = make_service_fn(move |_| {
+        let standin = standin.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let resp = standin.respond(req.uri().path());
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .status(resp.status)
+                            .body(Body::from(resp.body))
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(addr).serve(make_svc);
+    let local_addr = server.local_addr();
+    (local_addr, server.with_graceful_shutdown(shutdown))
+}
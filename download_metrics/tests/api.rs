@@ -0,0 +1,225 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+use download_metrics::api::{ApiError, API};
+use download_metrics::client::{DownloadsClient, HttpClient, RecordingClient, ReplayClient};
+use download_metrics::standin::{made_up_count, serve, StandIn};
+use postgres_db::custom_types::PackageStateType;
+use postgres_db::download_metrics::DownloadMetric;
+use postgres_db::packages::Package;
+use tokio::sync::oneshot;
+
+fn day(month: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2022, month, d).unwrap()
+}
+
+fn package(id: i64, name: &str) -> Package {
+    Package {
+        id,
+        name: name.to_string(),
+        current_package_state_type: PackageStateType::Normal,
+        package_state_history: vec![],
+        dist_tag_latest_version: None,
+        created: None,
+        modified: None,
+        other_dist_tags: None,
+        other_time_data: None,
+        unpublished_data: None,
+    }
+}
+
+/// Starts a stand-in, which stops when the returned sender is dropped.
+fn start_standin(rate_limit_every: Option<usize>) -> (SocketAddr, oneshot::Sender<()>) {
+    let (tx, rx) = oneshot::channel::<()>();
+    let (addr, server) = serve(
+        Arc::new(StandIn::new(rate_limit_every)),
+        &SocketAddr::from(([127, 0, 0, 1], 0)),
+        async {
+            rx.await.ok();
+        },
+    );
+    tokio::spawn(server);
+    (addr, tx)
+}
+
+fn http_client(addr: SocketAddr) -> HttpClient {
+    HttpClient::new(format!("http://{}", addr))
+}
+
+/// The nonzero counts and the gaps of a metric.
+fn summarize(metric: &DownloadMetric) -> (Vec<(NaiveDate, i64)>, Vec<(NaiveDate, NaiveDate)>) {
+    (
+        metric.counts.iter().map(|c| (c.day, c.count)).collect(),
+        metric
+            .gaps
+            .iter()
+            .map(|g| (g.start_date, g.end_date))
+            .collect(),
+    )
+}
+
+#[tokio::test]
+async fn test_counts_and_gaps() {
+    let (addr, _stop) = start_standin(None);
+    let api = API::with_client(2, Arc::new(http_client(addr)));
+
+    let (metric, _) = api
+        .spawn_query_task(package(1, "react"), day(1, 1), day(3, 1))
+        .await
+        .unwrap();
+    let metric = metric.unwrap();
+    let (counts, gaps) = summarize(&metric);
+
+    assert_eq!(
+        (metric.start_date, metric.latest_date),
+        (day(1, 1), day(3, 1))
+    );
+    // the stand-in returns null counts for react on these days
+    assert_eq!(
+        gaps,
+        vec![(day(1, 24), day(1, 24)), (day(2, 22), day(2, 22))]
+    );
+    let mut d = day(1, 1);
+    let mut expected = vec![];
+    while d <= day(3, 1) {
+        if let Some(count) = made_up_count("react", d).filter(|c| *c > 0) {
+            expected.push((d, count));
+        }
+        d = d.succ_opt().unwrap();
+    }
+    assert_eq!(counts, expected);
+}
+
+#[tokio::test]
+async fn test_missing_packages() {
+    let (addr, _stop) = start_standin(None);
+    let api = API::with_client(2, Arc::new(http_client(addr)));
+
+    let (metric, _) = api
+        .clone()
+        .spawn_query_task(package(1, "missing-pkg"), day(1, 1), day(1, 7))
+        .await
+        .unwrap();
+    assert!(matches!(metric, Err(ApiError::DoesNotExist)));
+
+    let (metrics, _) = api
+        .spawn_bulk_query_task(
+            vec![package(1, "missing-pkg"), package(2, "lodash")],
+            day(1, 1),
+            day(1, 7),
+        )
+        .await
+        .unwrap();
+    let ids: Vec<i64> = metrics.unwrap().iter().map(|m| m.package_id).collect();
+    assert_eq!(ids, vec![2]);
+}
+
+#[tokio::test]
+async fn test_rate_limit_pauses() {
+    let (addr, _stop) = start_standin(Some(2));
+    let api = API::with_client(1, Arc::new(http_client(addr)))
+        .with_rate_limit_pause(Duration::from_millis(50));
+
+    let query = |name: &str| {
+        api.clone()
+            .spawn_query_task(package(1, name), day(1, 1), day(1, 7))
+    };
+    assert!(query("react").await.unwrap().0.is_ok());
+    // the second request is rate-limited and retried after the pause, and the query sent during
+    // the pause waits it out
+    let start = Instant::now();
+    let limited = query("react");
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let after = query("lodash");
+    assert!(limited.await.unwrap().0.is_ok());
+    assert!(after.await.unwrap().0.is_ok());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_rate_limited_windows_are_retried() {
+    let (addr, _stop) = start_standin(None);
+    let api = API::with_client(1, Arc::new(http_client(addr)));
+    let (limited_addr, _stop_limited) = start_standin(Some(2));
+    let limited_api = API::with_client(1, Arc::new(http_client(limited_addr)))
+        .with_rate_limit_pause(Duration::from_millis(10));
+
+    // three windows of a year, every other request rate-limited
+    let lbound = NaiveDate::from_ymd_opt(2019, 6, 1).unwrap();
+    let (metric, _) = api
+        .spawn_query_task(package(1, "react"), lbound, day(3, 1))
+        .await
+        .unwrap();
+    let (limited_metric, _) = limited_api
+        .spawn_query_task(package(1, "react"), lbound, day(3, 1))
+        .await
+        .unwrap();
+    assert_eq!(
+        summarize(&limited_metric.unwrap()),
+        summarize(&metric.unwrap())
+    );
+}
+
+#[tokio::test]
+async fn test_rate_limit_after_retries() {
+    // every request is rate-limited
+    let (addr, _stop) = start_standin(Some(1));
+    let api = API::with_client(1, Arc::new(http_client(addr)))
+        .with_rate_limit_pause(Duration::from_millis(10));
+
+    let (metric, _) = api
+        .spawn_query_task(package(1, "react"), day(1, 1), day(1, 7))
+        .await
+        .unwrap();
+    assert!(matches!(metric, Err(ApiError::RateLimit)));
+}
+
+#[tokio::test]
+async fn test_record_and_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let (addr, stop) = start_standin(None);
+
+    let recording = API::with_client(
+        2,
+        Arc::new(RecordingClient::new(http_client(addr), dir.path())),
+    );
+    let (recorded, _) = recording
+        .spawn_query_task(package(1, "react"), day(1, 1), day(3, 1))
+        .await
+        .unwrap();
+    drop(stop);
+
+    let replay = API::with_client(2, Arc::new(ReplayClient::new(dir.path())));
+    let (replayed, _) = replay
+        .spawn_query_task(package(1, "react"), day(1, 1), day(3, 1))
+        .await
+        .unwrap();
+    assert_eq!(summarize(&recorded.unwrap()), summarize(&replayed.unwrap()));
+
+    // nothing was recorded for this path
+    let client = ReplayClient::new(dir.path());
+    assert!(matches!(
+        client
+            .get("downloads/range/2022-01-01:2022-01-07/lodash")
+            .await,
+        Err(ApiError::Fixture(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_replay_repeats_responses_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let (addr, _stop) = start_standin(Some(2));
+    let recording = RecordingClient::new(http_client(addr), dir.path());
+    let path = "downloads/range/2022-01-01:2022-01-07/react";
+    let first = recording.get(path).await.unwrap();
+    let second = recording.get(path).await.unwrap();
+    assert_eq!((first.status, second.status), (200, 429));
+
+    let replay = ReplayClient::new(dir.path());
+    assert_eq!(replay.get(path).await.unwrap(), first);
+    assert_eq!(replay.get(path).await.unwrap(), second);
+    assert_eq!(replay.get(path).await.unwrap(), second);
+}
@@ -0,0 +1,170 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use download_metrics::api::API;
+use download_metrics::client::HttpClient;
+use download_metrics::fetch::{insert_from_packages, update_from_packages};
+use download_metrics::standin::{made_up_count, serve, StandIn};
+use download_metrics::Bounds;
+use metrics_logging::MetricsLogger;
+use postgres_db::connection::testing::using_test_db;
+use postgres_db::connection::DbConnection;
+use postgres_db::custom_types::PackageStateType;
+use postgres_db::download_metrics::{
+    has_metrics_for_package_id, query_daily_download_counts, query_download_count_gaps,
+};
+use postgres_db::internal_state::query_download_metrics_pkg_seq;
+use postgres_db::packages::{insert_new_package, NewPackage, Package};
+
+fn day(month: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2022, month, d).unwrap()
+}
+
+fn new_package(conn: &mut DbConnection, name: &str) -> Package {
+    insert_new_package(
+        conn,
+        NewPackage {
+            name: name.to_string(),
+            current_package_state_type: PackageStateType::Normal,
+            package_state_history: vec![],
+            dist_tag_latest_version: None,
+            created: None,
+            modified: None,
+            other_dist_tags: None,
+            other_time_data: None,
+            unpublished_data: None,
+        },
+    )
+}
+
+/// What the stand-in returns for a package between two days, as nonzero counts and gaps.
+fn expected(name: &str, from: NaiveDate, to: NaiveDate) -> (Vec<(NaiveDate, i64)>, Vec<NaiveDate>) {
+    let (mut counts, mut gaps) = (vec![], vec![]);
+    let mut d = from;
+    while d <= to {
+        match made_up_count(name, d) {
+            Some(0) => {}
+            Some(count) => counts.push((d, count)),
+            None => gaps.push(d),
+        }
+        d = d.succ_opt().unwrap();
+    }
+    (counts, gaps)
+}
+
+fn days_between(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    from.iter_days().take_while(|d| *d <= to).collect()
+}
+
+fn stored(conn: &mut DbConnection, package_id: i64) -> (Vec<(NaiveDate, i64)>, Vec<NaiveDate>) {
+    let counts = query_daily_download_counts(conn, package_id)
+        .into_iter()
+        .map(|c| (c.day, c.count))
+        .collect();
+    let mut gaps = vec![];
+    for gap in query_download_count_gaps(conn, package_id) {
+        let mut d = gap.start_date;
+        while d <= gap.end_date {
+            gaps.push(d);
+            d = d.succ_opt().unwrap();
+        }
+    }
+    (counts, gaps)
+}
+
+#[test]
+fn test_insert_and_update_survive_rate_limits() {
+    using_test_db(|conn| {
+        let packages: Vec<Package> = ["react", "@scope/pkg", "missing-pkg", "lodash"]
+            .into_iter()
+            .map(|name| new_package(conn, name))
+            .collect();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            // every third request is rate-limited
+            let (addr, server) = serve(
+                Arc::new(StandIn::new(Some(3))),
+                &SocketAddr::from(([127, 0, 0, 1], 0)),
+                futures::future::pending(),
+            );
+            tokio::spawn(server);
+            let api = API::with_client(2, Arc::new(HttpClient::new(format!("http://{}", addr))))
+                .with_rate_limit_pause(Duration::from_millis(10));
+            let mut logger = MetricsLogger::null();
+
+            let bounds = Bounds {
+                lower: day(1, 1),
+                upper: day(3, 1),
+            };
+            insert_from_packages(conn, &mut logger, &api, bounds).await;
+
+            for pkg in &packages {
+                let exists = !pkg.name.starts_with("missing-");
+                assert_eq!(has_metrics_for_package_id(conn, pkg.id), exists);
+                if exists {
+                    assert_eq!(
+                        stored(conn, pkg.id),
+                        expected(&pkg.name, bounds.lower, bounds.upper)
+                    );
+                }
+            }
+            let last_id = packages.iter().map(|p| p.id).max().unwrap();
+            assert_eq!(query_download_metrics_pkg_seq(conn), Some(last_id + 1));
+
+            let bounds = Bounds {
+                lower: day(1, 1),
+                upper: day(4, 1),
+            };
+            update_from_packages(conn, &mut logger, &api, bounds).await;
+            assert_eq!(
+                stored(conn, packages[0].id),
+                expected("react", bounds.lower, bounds.upper)
+            );
+        });
+    });
+}
+
+#[test]
+fn test_insert_records_failed_packages_as_gaps() {
+    using_test_db(|conn| {
+        let packages: Vec<Package> = ["react", "broken-pkg", "@scope/broken-pkg", "@scope/pkg"]
+            .into_iter()
+            .map(|name| new_package(conn, name))
+            .collect();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let (addr, server) = serve(
+                Arc::new(StandIn::new(None)),
+                &SocketAddr::from(([127, 0, 0, 1], 0)),
+                futures::future::pending(),
+            );
+            tokio::spawn(server);
+            let api = API::with_client(2, Arc::new(HttpClient::new(format!("http://{}", addr))));
+            let mut logger = MetricsLogger::null();
+
+            let bounds = Bounds {
+                lower: day(1, 1),
+                upper: day(3, 1),
+            };
+            insert_from_packages(conn, &mut logger, &api, bounds).await;
+
+            // the bulk query of react and broken-pkg fails as a whole
+            for pkg in &packages[..3] {
+                assert_eq!(
+                    stored(conn, pkg.id),
+                    (vec![], days_between(bounds.lower, bounds.upper))
+                );
+            }
+            assert_eq!(
+                stored(conn, packages[3].id),
+                expected("@scope/pkg", bounds.lower, bounds.upper)
+            );
+            let last_id = packages.iter().map(|p| p.id).max().unwrap();
+            assert_eq!(query_download_metrics_pkg_seq(conn), Some(last_id + 1));
+        });
+    });
+}
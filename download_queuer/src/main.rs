@@ -1,52 +1,133 @@
+use metrics_logging::{MetricsEvent, MetricsLogger};
+use pipeline_stage::{
+    PageSize, PageStats, PipelineStage, Runner, SessionStart, StageError, DAEMON_POLL_INTERVAL,
+};
 use postgres_db::change_log;
 use postgres_db::change_log::Change;
-use postgres_db::connection::DbConnection;
+use postgres_db::connection::{DbConnection, DbConnectionInTransaction};
 use postgres_db::download_queue;
 use postgres_db::internal_state;
+use postgres_db::notifications::{Listener, CHANGE_LOG_CHANNEL, QUEUED_DOWNLOADS_CHANNEL};
 use utils::check_no_concurrent_processes;
 
 const PAGE_SIZE: i64 = 1024;
 
 fn main() {
+    let _tracing = utils::init_tracing("download_queuer");
     check_no_concurrent_processes("download_queuer");
 
+    let mut args: Vec<String> = std::env::args().collect();
+    let dry_run = pipeline_stage::take_flag(&mut args, "--dry-run");
+    let daemon = pipeline_stage::take_flag(&mut args, "--daemon");
+    if args.len() != 1 {
+        eprintln!("Usage: {} [--dry-run | --daemon]", args[0]);
+        std::process::exit(1);
+    }
+
     let mut conn = DbConnection::connect();
 
-    let mut queued_up_to = internal_state::query_queued_downloads_seq(&mut conn).unwrap_or(0);
+    let mut stage = DownloadQueuerStage {
+        num_changes_total: 0,
+    };
+    let mut runner = Runner::new(
+        PageSize::Fixed(PAGE_SIZE),
+        MetricsLogger::from_env_or_exit(),
+    )
+    .dry_run(dry_run)
+    .stop_on_signals();
+    if daemon {
+        let mut listener = Listener::connect(&[CHANGE_LOG_CHANNEL]);
+        runner.run_daemon(&mut stage, &mut conn, &mut listener, DAEMON_POLL_INTERVAL);
+    } else {
+        runner.run(&mut stage, &mut conn);
+    }
+}
 
-    let num_changes_total = change_log::query_num_changes_after_seq(queued_up_to, &mut conn);
-    let mut num_changes_so_far = 0;
-    let mut first_time = true;
+struct DownloadQueuerStage {
+    num_changes_total: i64,
+}
 
-    loop {
-        println!(
-            "Fetching seq > {}, page size = {} ({:.1}%)",
-            queued_up_to,
-            PAGE_SIZE,
-            100.0 * (num_changes_so_far as f64) / (num_changes_total as f64)
-        );
-        let changes = change_log::query_changes_after_seq(queued_up_to, PAGE_SIZE, &mut conn);
-        let num_changes = changes.len() as i64;
-        num_changes_so_far += num_changes;
-        if num_changes == 0 {
-            break;
-        }
+impl PipelineStage for DownloadQueuerStage {
+    type Item = Change;
+    type Cursor = i64;
+    type Output = usize;
+
+    fn name(&self) -> &'static str {
+        "download_queuer"
+    }
 
-        let last_seq_in_page = changes.last().unwrap().seq;
+    fn load_cursor(&mut self, conn: &mut DbConnection) -> i64 {
+        internal_state::query_queued_downloads_seq(conn).unwrap_or(0)
+    }
 
-        let downloads_to_enqueue: Vec<_> = changes
+    fn save_cursor(&mut self, conn: &mut DbConnectionInTransaction, cursor: &i64) {
+        internal_state::set_queued_downloads_seq(*cursor, conn);
+    }
+
+    fn query_page(&mut self, conn: &mut DbConnection, cursor: &i64, page_size: i64) -> Vec<Change> {
+        change_log::query_changes_after_seq(*cursor, page_size, conn)
+    }
+
+    fn advance(&self, _cursor: &i64, page: &[Change]) -> i64 {
+        page.last().unwrap().seq
+    }
+
+    fn notify_channel(&self) -> Option<&'static str> {
+        Some(QUEUED_DOWNLOADS_CHANNEL)
+    }
+
+    fn process_page(
+        &mut self,
+        conn: &mut DbConnectionInTransaction,
+        page: Vec<Change>,
+    ) -> Result<usize, StageError> {
+        let downloads_to_enqueue: Vec<_> = page
             .into_iter()
             .flat_map(download_tasks_for_change)
             .collect();
 
-        download_queue::enqueue_downloads(downloads_to_enqueue, &mut conn);
-        internal_state::set_queued_downloads_seq(last_seq_in_page, &mut conn);
-        queued_up_to = last_seq_in_page;
+        Ok(download_queue::enqueue_downloads(
+            downloads_to_enqueue,
+            conn,
+        ))
+    }
 
-        if num_changes < PAGE_SIZE && !first_time {
-            break;
-        }
-        first_time = false;
+    fn start_session(
+        &mut self,
+        conn: &mut DbConnection,
+        _logger: &mut MetricsLogger,
+        session: &SessionStart<i64>,
+    ) {
+        self.num_changes_total = change_log::query_num_changes_after_seq(session.cursor, conn);
+    }
+
+    fn progress_event(
+        &self,
+        event: MetricsEvent,
+        page: &PageStats<i64>,
+        num_enqueued: &usize,
+    ) -> MetricsEvent {
+        event
+            .count("downloads_enqueued", *num_enqueued as u64)
+            .gauge("seq", page.last)
+            .gauge(
+                "seqs_left",
+                self.num_changes_total - page.session_items_so_far,
+            )
+    }
+
+    fn page_completed(
+        &mut self,
+        _logger: &mut MetricsLogger,
+        page: &PageStats<i64>,
+        num_enqueued: &usize,
+    ) {
+        tracing::info!(
+            num_enqueued,
+            seq = page.last,
+            "Queued downloads ({:.1}%)",
+            100.0 * (page.session_items_so_far as f64) / (self.num_changes_total as f64)
+        );
     }
 }
 
@@ -1,5 +1,6 @@
 use blob_idx_server::errors::{BlobError, ClientError};
 use blob_idx_server::http::{JobType, SubmitJobRequest};
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
 use postgres_db::connection::DbConnection;
 use postgres_db::custom_types::DownloadFailed;
 use postgres_db::download_queue::{
@@ -8,14 +9,20 @@ use postgres_db::download_queue::{
 };
 use postgres_db::download_tarball::DownloadedTarball;
 use std::collections::HashMap;
+use std::time::Instant;
 use std::{os::unix::prelude::PermissionsExt, sync::mpsc::channel};
 use tokio::task::JoinHandle;
+use tracing::Instrument;
 
 use crate::{
     download_error::DownloadError,
     download_threadpool::{DbMessage, DownloadThreadPool},
 };
 
+/// The blob storage jobs don't report the size of the tarballs they download, so `num_bytes` of
+/// those is 0. todo: get size
+const UNKNOWN_BLOB_NUM_BYTES: i64 = 0;
+
 /// Downloads the given task to the given directory. This function cannot panic.
 pub async fn download_task(
     task: &DownloadTask,
@@ -53,6 +60,49 @@ pub async fn download_task(
     Ok(downloaded_tarball)
 }
 
+/// Counts what happened to the tasks of a chunk, for its progress event.
+struct ChunkMetrics {
+    component: &'static str,
+    start: Instant,
+    downloaded: u64,
+    failed: u64,
+    bytes: u64,
+}
+
+impl ChunkMetrics {
+    fn new(component: &'static str) -> ChunkMetrics {
+        ChunkMetrics {
+            component,
+            start: Instant::now(),
+            downloaded: 0,
+            failed: 0,
+            bytes: 0,
+        }
+    }
+
+    fn downloaded(&mut self, tarball: &DownloadedTarball) {
+        self.downloaded += 1;
+        self.bytes += tarball.num_bytes as u64;
+    }
+
+    fn failed(&mut self, num_tasks: usize) {
+        self.failed += num_tasks as u64;
+    }
+
+    /// Logs the chunk's progress and starts counting the next chunk.
+    fn log(&mut self, logger: &mut MetricsLogger, tasks_left: i64) {
+        logger.log_event(
+            MetricsEvent::new(self.component, EventKind::Progress)
+                .count("downloaded", self.downloaded)
+                .count("failed", self.failed)
+                .count("bytes", self.bytes)
+                .gauge("tasks_left", tasks_left)
+                .duration("duration", self.start.elapsed()),
+        );
+        *self = ChunkMetrics::new(self.component);
+    }
+}
+
 /// Updates the database with the given tarballs and then clears the queue.
 pub fn update_from_tarball_queue(conn: &mut DbConnection, tarballs: &mut Vec<DownloadedTarball>) {
     if tarballs.is_empty() {
@@ -73,6 +123,7 @@ pub fn update_from_tarball_queue(conn: &mut DbConnection, tarballs: &mut Vec<Dow
 /// If the number of workers is 0 or greater than TASKS_CHUNK_SIZE (unreasonable amount).
 pub fn download_to_dest(
     conn: &mut DbConnection,
+    logger: &mut MetricsLogger,
     dest: &str,
     num_workers: usize,
     retry_failed: bool,
@@ -81,14 +132,18 @@ pub fn download_to_dest(
 
     // get all tasks with no failed downloads
     let tasks_len = get_total_tasks_num(conn, retry_failed);
-    println!("{} tasks to download", tasks_len);
+    tracing::info!(num_tasks = tasks_len, "Tasks to download");
+    logger.log_event(
+        MetricsEvent::new("downloader", EventKind::SessionStart).gauge("tasks", tasks_len),
+    );
+    let mut chunk_metrics = ChunkMetrics::new("downloader");
 
     let (db_sender, db_receiver) = channel();
     let pool = DownloadThreadPool::new(num_workers, dest, db_sender);
 
     // get first round of tasks, with no failed downloads
     let tasks: Vec<DownloadTask> = load_chunk_init(conn, retry_failed);
-    println!("Got {} tasks", tasks.len());
+    tracing::info!(num_tasks = tasks.len(), "Got tasks");
 
     if tasks.is_empty() {
         return Ok(());
@@ -109,18 +164,22 @@ pub fn download_to_dest(
     pool.download_chunk(tasks);
 
     for i in 0..tasks_len {
-        println!(
+        tracing::debug!(
             "Status: {}/{} - Chunkwise: {}/{}",
-            i, tasks_len, download_counter, last_chunk_size
+            i,
+            tasks_len,
+            download_counter,
+            last_chunk_size
         );
         if (download_counter + 1) == last_chunk_size {
             update_from_tarball_queue(conn, &mut tarballs_queue);
+            chunk_metrics.log(logger, tasks_len - i);
 
-            println!("Sending new chunk of tasks to pool");
+            tracing::info!("Sending new chunk of tasks to pool");
             // get next round of tasks, with no failed downloads and with tasks that have greater
             // url sort-position than the last chunk
             let tasks: Vec<DownloadTask> = load_chunk_next(conn, &last_url, retry_failed);
-            println!("Got {} tasks", tasks.len());
+            tracing::info!(num_tasks = tasks.len(), "Got tasks");
 
             // reassign last_url and last_chunk_size to the new chunk of tasks
             if !tasks.is_empty() {
@@ -138,11 +197,13 @@ pub fn download_to_dest(
             // doesn't make sense to pool errors as we have to update each row in the tasks in a
             // loop.
             DbMessage::Tarball(tarball) => {
-                println!("Done downloading task {}", tarball.tarball_url);
+                tracing::info!(url = %tarball.tarball_url, "Done downloading task");
+                chunk_metrics.downloaded(&tarball);
                 tarballs_queue.push(*tarball);
             }
             DbMessage::Error(e, task) => {
-                println!("Error downloading task {} -> {}", task.url, e);
+                tracing::error!(url = %task.url, error = %e, "Error downloading task");
+                chunk_metrics.failed(1);
                 update_from_error(conn, &task, e.into());
             }
         }
@@ -150,8 +211,10 @@ pub fn download_to_dest(
     }
 
     update_from_tarball_queue(conn, &mut tarballs_queue);
+    chunk_metrics.log(logger, 0);
+    logger.log_event(MetricsEvent::new("downloader", EventKind::SessionEnd));
 
-    println!("Done downloading tasks");
+    tracing::info!("Done downloading tasks");
 
     Ok(())
 }
@@ -162,6 +225,7 @@ pub fn download_to_dest(
 /// downloaded in parallel. The retry_failed flag indicates whether to retry failed downloads.
 pub async fn download_to_cluster(
     conn: &mut DbConnection,
+    logger: &mut MetricsLogger,
     num_parallel_dl: usize,
     retry_failed: bool,
 ) -> std::io::Result<()> {
@@ -174,13 +238,19 @@ pub async fn download_to_cluster(
     // get all tasks with no failed downloads if retry_failed is false
     let tasks_len = get_total_tasks_num(conn, retry_failed);
     let mut current_count = 0;
-    println!("[MAIN] {} tasks to download", tasks_len);
+    let mut tasks_left = tasks_len;
+    tracing::info!(num_tasks = tasks_len, "Tasks to download");
+    logger.log_event(
+        MetricsEvent::new("cluster_downloader", EventKind::SessionStart).gauge("tasks", tasks_len),
+    );
 
     let mut tasks: Vec<DownloadTask> = load_chunk_init(conn, retry_failed);
     let mut print_progress = |len| {
-        println!(
-            "[MAIN] Got {} tasks. Progress: {}/{}",
-            len, current_count, tasks_len
+        tracing::info!(
+            num_tasks = len,
+            "Got tasks. Progress: {}/{}",
+            current_count,
+            tasks_len
         );
         current_count += len;
     };
@@ -188,6 +258,7 @@ pub async fn download_to_cluster(
 
     while !tasks.is_empty() {
         let mut handles = vec![];
+        let mut chunk_metrics = ChunkMetrics::new("cluster_downloader");
 
         for (worker_id, chunk) in tasks.chunks(req_chunk_size).enumerate() {
             let blob_api_url = blob_api_url.clone();
@@ -209,96 +280,102 @@ pub async fn download_to_cluster(
                 (DownloadError, Vec<DownloadTask>),
             >;
 
-            let handle: JoinHandle<ClusterResult> = tokio::spawn(async move {
-                let data = SubmitJobRequest {
-                    job_type: JobType::DownloadURLs { urls: urls.clone() },
-                };
-                let mut tbs = vec![];
-                let thunk = async {
-                    println!("[{}] Sending job to cluster", worker_id);
-                    let res = client
-                        .post(&format!("{}/job/submit", blob_api_url))
-                        .header("Authorization", blob_api_key.clone())
-                        .json(&data)
-                        .send()
-                        .await?;
-                    let txt = res.text().await?;
-                    if txt.is_empty() {
-                        // success
-                        println!("[{}] Downloaded {} tarballs", worker_id, urls.len());
-
-                        for url in urls.iter() {
-                            let task = url_to_task.get(url.as_str()).unwrap();
-                            let downloaded =
-                                DownloadedTarball::from_task_blob(task, url.to_string(), None); // todo: get size
-                            tbs.push(Ok(downloaded));
-                        }
-                        Ok(tbs)
-                    } else {
-                        // unravel the error (yes, it's a bad API)
-                        println!("[{}] Error downloading tarballs: {}", worker_id, txt);
-                        let obj: serde_json::Value =
-                            serde_json::from_str(&txt).map_err(|_| DownloadError::ClusterError)?;
-                        let err: ClientError = serde_json::from_value(obj["error"].clone())
-                            .map_err(|_| DownloadError::ClusterError)?;
-
-                        match err {
-                            // this kind of error is benign, we need to make the assumption
-                            // that the tarballs were downloaded correctly here. yet,
-                            // we need to resubmit the job with the missing tarballs.
-                            ClientError::BlobError(BlobError::AlreadyExists(url)) => {
-                                println!("[{}] Already downloaded {}", worker_id, url);
-                                tbs.push(Ok(DownloadedTarball::from_task_blob(
-                                    // todo: get size
-                                    url_to_task.get(&url).unwrap(),
+            let span =
+                tracing::info_span!("cluster_job", worker = worker_id, num_urls = urls.len());
+            let handle: JoinHandle<ClusterResult> = tokio::spawn(
+                async move {
+                    let data = SubmitJobRequest {
+                        job_type: JobType::DownloadURLs { urls: urls.clone() },
+                    };
+                    let mut tbs = vec![];
+                    let thunk = async {
+                        tracing::info!("Sending job to cluster");
+                        let res = client
+                            .post(&format!("{}/job/submit", blob_api_url))
+                            .header("Authorization", blob_api_key.clone())
+                            .json(&data)
+                            .send()
+                            .await?;
+                        let txt = res.text().await?;
+                        if txt.is_empty() {
+                            // success
+                            tracing::info!("Downloaded tarballs");
+
+                            for url in urls.iter() {
+                                let task = url_to_task.get(url.as_str()).unwrap();
+                                let downloaded = DownloadedTarball::from_task_blob(
+                                    task,
                                     url.to_string(),
-                                    None,
-                                )));
-
-                                Ok(tbs)
-                            }
-                            ClientError::DownloadFailed { urls: failed_urls } => {
-                                println!(
-                                    "[{}] {}/{} tarball downloads failed",
-                                    worker_id,
-                                    failed_urls.len(),
-                                    urls.len()
+                                    UNKNOWN_BLOB_NUM_BYTES,
                                 );
-                                for (url, status) in failed_urls.iter() {
-                                    let task = url_to_task.get(url.as_str()).unwrap();
-                                    tbs.push(Err((
-                                        task.clone(),
-                                        DownloadError::StatusNotOk((*status).into()),
+                                tbs.push(Ok(downloaded));
+                            }
+                            Ok(tbs)
+                        } else {
+                            // unravel the error (yes, it's a bad API)
+                            tracing::error!(response = %txt, "Error downloading tarballs");
+                            let obj: serde_json::Value = serde_json::from_str(&txt)
+                                .map_err(|_| DownloadError::ClusterError)?;
+                            let err: ClientError = serde_json::from_value(obj["error"].clone())
+                                .map_err(|_| DownloadError::ClusterError)?;
+
+                            match err {
+                                // this kind of error is benign, we need to make the assumption
+                                // that the tarballs were downloaded correctly here. yet,
+                                // we need to resubmit the job with the missing tarballs.
+                                ClientError::BlobError(BlobError::AlreadyExists(url)) => {
+                                    tracing::info!(blob_key = %url, "Already downloaded");
+                                    tbs.push(Ok(DownloadedTarball::from_task_blob(
+                                        url_to_task.get(&url).unwrap(),
+                                        url.to_string(),
+                                        UNKNOWN_BLOB_NUM_BYTES,
                                     )));
-                                    urls.retain(|u| u != url);
-                                }
 
-                                for url in urls.iter() {
-                                    let task = url_to_task.get(url.as_str()).unwrap();
-                                    let downloaded = DownloadedTarball::from_task_blob(
-                                        task,
-                                        url.to_string(),
-                                        None,
-                                    ); // todo: get size
-                                    tbs.push(Ok(downloaded));
+                                    Ok(tbs)
                                 }
-                                Ok(tbs)
+                                ClientError::DownloadFailed { urls: failed_urls } => {
+                                    tracing::warn!(
+                                        "{}/{} tarball downloads failed",
+                                        failed_urls.len(),
+                                        urls.len()
+                                    );
+                                    for (url, status) in failed_urls.iter() {
+                                        let task = url_to_task.get(url.as_str()).unwrap();
+                                        tbs.push(Err((
+                                            task.clone(),
+                                            DownloadError::StatusNotOk((*status).into()),
+                                        )));
+                                        urls.retain(|u| u != url);
+                                    }
+
+                                    for url in urls.iter() {
+                                        let task = url_to_task.get(url.as_str()).unwrap();
+                                        let downloaded = DownloadedTarball::from_task_blob(
+                                            task,
+                                            url.to_string(),
+                                            UNKNOWN_BLOB_NUM_BYTES,
+                                        );
+                                        tbs.push(Ok(downloaded));
+                                    }
+                                    Ok(tbs)
+                                }
+                                _ => Err(DownloadError::ClusterError),
                             }
-                            _ => Err(DownloadError::ClusterError),
                         }
-                    }
-                };
-                let res = thunk.await;
-                res.map_err(|e| {
-                    (
-                        e,
-                        urls.iter()
-                            .flat_map(|u| url_to_task.get(u))
-                            .cloned()
-                            .collect(),
-                    )
-                })
-            });
+                    };
+                    let res = thunk.await;
+                    res.map_err(|e| {
+                        (
+                            e,
+                            urls.iter()
+                                .flat_map(|u| url_to_task.get(u))
+                                .cloned()
+                                .collect(),
+                        )
+                    })
+                }
+                .instrument(span),
+            );
             handles.push(handle);
         }
 
@@ -312,6 +389,7 @@ pub async fn download_to_cluster(
                         match tb {
                             Ok(tb) => good_tbs.push(tb),
                             Err((task, err)) => {
+                                chunk_metrics.failed(1);
                                 update_from_error(conn, &task, err.into());
                             }
                         }
@@ -319,26 +397,42 @@ pub async fn download_to_cluster(
                 }
                 // means cluster error, not per-tarball error
                 Err((e, tasks)) => {
+                    logger.log_event(
+                        MetricsEvent::new("cluster_downloader", EventKind::Error)
+                            .count("tasks", tasks.len() as u64)
+                            .text("message", e.to_string()),
+                    );
+                    chunk_metrics.failed(tasks.len());
                     let sql_err: DownloadFailed = e.into();
-                    println!("[MAIN] Updating {} failed tasks", tasks.len());
+                    tracing::error!(num_tasks = tasks.len(), error = %e, "Cluster job failed");
                     for task in tasks {
                         update_from_error(conn, &task, sql_err.clone());
                     }
                 }
             }
 
+            for tb in &good_tbs {
+                chunk_metrics.downloaded(tb);
+            }
             if !good_tbs.is_empty() {
                 // NOTE: there is a print statement in update_from_tarballs
                 update_from_tarballs(conn, &good_tbs);
             }
         }
 
+        tasks_left -= tasks.len() as i64;
+        chunk_metrics.log(logger, tasks_left);
+
         // refill tasks
         tasks = load_chunk_next(conn, &tasks.last().unwrap().url, retry_failed);
         print_progress(tasks.len());
     }
+    logger.log_event(MetricsEvent::new(
+        "cluster_downloader",
+        EventKind::SessionEnd,
+    ));
 
-    println!("Done downloading tasks");
+    tracing::info!("Done downloading tasks");
 
     Ok(())
 }
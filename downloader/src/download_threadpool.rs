@@ -4,6 +4,7 @@ use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::sync::Mutex;
+use tracing::Instrument;
 
 use crate::download_db::download_task;
 use crate::download_error::DownloadError;
@@ -36,13 +37,14 @@ impl Worker {
     ) -> Worker {
         let dest = destination.to_string();
         let task = handle.spawn(async move {
-            println!("Worker {} started", id);
+            tracing::info!(worker = id, "Worker started");
             loop {
                 let msg = task_receiver.lock().unwrap().recv().unwrap();
                 match msg {
                     TaskMessage::Task(dl) => {
-                        println!("Worker {} downloading {}", id, dl.url);
-                        let tarball = download_task(&dl, &dest).await;
+                        let span = tracing::info_span!("download", worker = id, url = %dl.url);
+                        tracing::info!(parent: &span, "Downloading");
+                        let tarball = download_task(&dl, &dest).instrument(span).await;
                         match tarball {
                             Ok(tar) => db_sender.send(DbMessage::Tarball(Box::new(tar))).unwrap(),
                             Err(e) => db_sender.send(DbMessage::Error(e, dl)).unwrap(),
@@ -114,14 +116,14 @@ impl DownloadThreadPool {
 
 impl Drop for DownloadThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
+        tracing::info!("Sending terminate message to all workers.");
 
         for _ in &self.workers {
             self.task_sender.send(TaskMessage::Exit).unwrap();
         }
 
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            tracing::info!(worker = worker.id, "Shutting down worker");
 
             let thread = worker.thread.take().unwrap();
             self.tokio_runtime.block_on(async {
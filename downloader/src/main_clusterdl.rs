@@ -1,9 +1,11 @@
 use downloader::download_db::download_to_cluster;
+use metrics_logging::MetricsLogger;
 use postgres_db::connection::DbConnection;
 use utils::check_no_concurrent_processes;
 
 #[tokio::main]
 pub async fn main() {
+    let _tracing = utils::init_tracing("cluster_downloader");
     check_no_concurrent_processes("cluster_downloader");
 
     let args = std::env::args().collect::<Vec<_>>();
@@ -16,6 +18,7 @@ pub async fn main() {
     }
 
     let mut conn = DbConnection::connect();
+    let mut metrics_logger = MetricsLogger::from_env_or_exit();
     let num_parallel_dl = args[1].parse::<usize>().unwrap();
     let retry = if args.len() > 2 {
         args[2] == "true"
@@ -23,7 +26,7 @@ pub async fn main() {
         false
     };
 
-    download_to_cluster(&mut conn, num_parallel_dl, retry)
+    download_to_cluster(&mut conn, &mut metrics_logger, num_parallel_dl, retry)
         .await
         .expect("Failed to download");
 }
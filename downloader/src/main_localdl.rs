@@ -1,8 +1,10 @@
 use downloader::download_db::download_to_dest;
+use metrics_logging::MetricsLogger;
 use postgres_db::connection::DbConnection;
 use utils::check_no_concurrent_processes;
 
 pub fn main() {
+    let _tracing = utils::init_tracing("downloader");
     check_no_concurrent_processes("downloader");
 
     let args = std::env::args().collect::<Vec<_>>();
@@ -15,6 +17,7 @@ pub fn main() {
     }
 
     let mut conn = DbConnection::connect();
+    let mut metrics_logger = MetricsLogger::from_env_or_exit();
     let dest = &args[1];
     let num_workers = args[2].parse::<usize>().unwrap();
     let retry = if args.len() > 3 {
@@ -29,5 +32,6 @@ pub fn main() {
         std::process::exit(1);
     }
 
-    download_to_dest(&mut conn, dest, num_workers, retry).expect("Failed to download");
+    download_to_dest(&mut conn, &mut metrics_logger, dest, num_workers, retry)
+        .expect("Failed to download");
 }
@@ -0,0 +1,154 @@
+//! Computing exposure from the relational tables.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use chrono::{TimeZone, Utc};
+use dependency_graph::{live_intervals, DepKinds, DependencyGraph};
+use postgres_db::connection::{DbConnection, QueryRunner};
+use postgres_db::dependencies::get_dependencies_by_ids;
+use postgres_db::exposures::{VersionExposure, VersionVulnerability};
+use postgres_db::ghsa::query_ghsa_by_id;
+use postgres_db::packages::maybe_get_package_id_by_name;
+use postgres_db::versions::{
+    get_version_dependencies_by_package_ids, get_version_releases_by_package_ids,
+};
+
+use crate::{is_affected, propagate, DepSpec, Dependent, Release, VersionSource};
+
+/// How many dependent packages to load at once.
+const CHUNK_SIZE: usize = 1024;
+
+/// Versions and dependencies from the relational tables, finding dependents with a
+/// [`DependencyGraph`] that must be up to date with them.
+pub struct DbSource<'a, R> {
+    conn: &'a mut R,
+    graph: &'a DependencyGraph,
+    releases: HashMap<i64, Rc<Vec<Release>>>,
+}
+
+impl<'a, R: QueryRunner> DbSource<'a, R> {
+    pub fn new(conn: &'a mut R, graph: &'a DependencyGraph) -> DbSource<'a, R> {
+        DbSource {
+            conn,
+            graph,
+            releases: HashMap::new(),
+        }
+    }
+}
+
+impl<R: QueryRunner> VersionSource for DbSource<'_, R> {
+    fn releases(&mut self, package_id: i64) -> Rc<Vec<Release>> {
+        if let Some(releases) = self.releases.get(&package_id) {
+            return releases.clone();
+        }
+        let releases: Rc<Vec<Release>> = Rc::new(
+            get_version_releases_by_package_ids(self.conn, &[package_id])
+                .into_iter()
+                .map(|v| Release {
+                    version_id: v.id,
+                    live: live_intervals(v.created, &v.version_state_history),
+                    semver: v.semver,
+                })
+                .collect(),
+        );
+        self.releases.insert(package_id, releases.clone());
+        releases
+    }
+
+    fn dependents(&mut self, package_id: i64) -> Vec<Dependent> {
+        let mut dependents = vec![];
+        let package_ids = self.graph.dependents_ever(package_id, DepKinds::RUNTIME);
+        for chunk in package_ids.chunks(CHUNK_SIZE) {
+            let versions = get_version_dependencies_by_package_ids(self.conn, chunk);
+
+            let dep_ids: Vec<i64> = versions
+                .iter()
+                .flat_map(|v| {
+                    v.prod_dependencies
+                        .iter()
+                        .chain(&v.peer_dependencies)
+                        .chain(&v.optional_dependencies)
+                })
+                .copied()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            let specs: HashMap<i64, DepSpec> = get_dependencies_by_ids(self.conn, &dep_ids)
+                .into_iter()
+                .filter(|d| d.dst_package_id_if_exists == Some(package_id))
+                .filter_map(|d| Some((d.id, DepSpec::from_parsed(&d.spec)?)))
+                .collect();
+
+            for v in versions {
+                let runtime_deps = v
+                    .prod_dependencies
+                    .iter()
+                    .chain(&v.peer_dependencies)
+                    .chain(&v.optional_dependencies);
+                for dep_id in runtime_deps {
+                    if let Some(spec) = specs.get(dep_id) {
+                        dependents.push(Dependent {
+                            version_id: v.id,
+                            package_id: v.package_id,
+                            live: live_intervals(v.created, &v.version_state_history),
+                            dependency_id: *dep_id,
+                            spec: spec.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        dependents
+    }
+}
+
+/// Computes the versions an advisory directly affects, and the exposure windows of versions that
+/// depend on them, at most `max_depth` dependencies deep. Withdrawn advisories affect nothing.
+pub fn compute_advisory(
+    conn: &mut DbConnection,
+    graph: &DependencyGraph,
+    ghsa_id: &str,
+    max_depth: Option<u32>,
+) -> (Vec<VersionVulnerability>, Vec<VersionExposure>) {
+    let (advisory, vulns) = query_ghsa_by_id(conn, ghsa_id);
+    if advisory.withdrawn_at.is_some() {
+        return (vec![], vec![]);
+    }
+
+    let mut source = DbSource::new(conn, graph);
+    let mut affected = vec![];
+    for vuln in &vulns {
+        let package_id = match maybe_get_package_id_by_name(source.conn, &vuln.package_name) {
+            Some(id) => id,
+            None => continue,
+        };
+        for release in source.releases(package_id).iter() {
+            if is_affected(&vuln.vulnerable_version_constraint, &release.semver) {
+                affected.push((package_id, release.version_id));
+            }
+        }
+    }
+    affected.sort_unstable();
+    affected.dedup();
+
+    let exposures = propagate(&mut source, &affected, max_depth)
+        .into_iter()
+        .map(|e| VersionExposure {
+            version_id: e.version_id,
+            ghsa_id: ghsa_id.to_owned(),
+            exposed_from: Utc.timestamp_opt(e.since, 0).unwrap(),
+            exposed_until: (e.until != i64::MAX).then(|| Utc.timestamp_opt(e.until, 0).unwrap()),
+            depth: e.path.len() as i32,
+            path: e.path,
+        })
+        .collect();
+    let affected = affected
+        .into_iter()
+        .map(|(_, version_id)| VersionVulnerability {
+            version_id,
+            ghsa_id: ghsa_id.to_owned(),
+        })
+        .collect();
+    (affected, exposures)
+}
@@ -0,0 +1,268 @@
+//! Which versions an advisory affects, directly and through dependencies, and when.
+//!
+//! A version is directly affected if it satisfies the vulnerable range of one of the advisory's
+//! vulnerabilities. A version is exposed at some time if installing it then would have installed
+//! a directly affected version, assuming each runtime dependency resolves to the greatest
+//! version satisfying its spec that existed at that time. This ignores lockfiles, dist-tags other
+//! than `latest` (which is taken to be the greatest release), aliases, and deduplication, so it
+//! is what a fresh install of the version alone would have gotten.
+//!
+//! Exposure is computed breadth-first from the affected versions over the reverse dependency
+//! graph, one depth at a time, so each moment of a version's exposure gets a shortest path.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use postgres_db::custom_types::{ParsedSpec, Semver, VersionConstraint};
+use semver_spec_serialization::{compare_versions, satisfies};
+
+mod db;
+mod windows;
+
+pub use db::{compute_advisory, DbSource};
+
+use windows::{insert, intersect, subtract, Window};
+
+/// A version of a package, and the times it existed.
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub version_id: i64,
+    pub semver: Semver,
+    /// Sorted, disjoint `[since, until)` windows in unix seconds, with `i64::MAX` for versions
+    /// that still exist.
+    pub live: Vec<(i64, i64)>,
+}
+
+/// The dependency specs we can resolve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DepSpec {
+    Range(VersionConstraint),
+    Latest,
+}
+
+impl DepSpec {
+    /// The spec to resolve for a parsed spec, or `None` if it doesn't pick a version of the
+    /// package the dependency points to.
+    pub fn from_parsed(spec: &ParsedSpec) -> Option<DepSpec> {
+        match spec {
+            ParsedSpec::Range(r) => Some(DepSpec::Range(r.clone())),
+            ParsedSpec::Tag(t) if t == "latest" => Some(DepSpec::Latest),
+            _ => None,
+        }
+    }
+
+    fn accepts(&self, v: &Semver) -> bool {
+        match self {
+            DepSpec::Range(r) => satisfies(r, v, false),
+            DepSpec::Latest => v.prerelease.is_empty(),
+        }
+    }
+}
+
+/// A version with a runtime dependency on some package.
+#[derive(Debug, Clone)]
+pub struct Dependent {
+    pub version_id: i64,
+    pub package_id: i64,
+    pub live: Vec<(i64, i64)>,
+    /// Identifies the spec, so its resolution can be reused by other dependents.
+    pub dependency_id: i64,
+    pub spec: DepSpec,
+}
+
+/// Where versions and dependencies come from.
+pub trait VersionSource {
+    /// All versions of a package.
+    fn releases(&mut self, package_id: i64) -> Rc<Vec<Release>>;
+
+    /// The versions with a runtime dependency on a package, in any order.
+    fn dependents(&mut self, package_id: i64) -> Vec<Dependent>;
+}
+
+/// A window during which installing a version would install an affected version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exposure {
+    pub version_id: i64,
+    pub since: i64,
+    /// `i64::MAX` if still exposed.
+    pub until: i64,
+    /// The versions installed along the way, ending with the affected one.
+    pub path: Vec<i64>,
+}
+
+/// Whether a version is affected by a vulnerable range. Unlike in npm ranges, prereleases of
+/// versions in the range are in it, e.g. `1.2.0-beta` is in `< 1.2.1`.
+pub fn is_affected(constraint: &VersionConstraint, v: &Semver) -> bool {
+    satisfies(constraint, v, true)
+}
+
+/// The version that `spec` resolves to over time, as sorted, disjoint
+/// `(since, until, version_id)` windows. There are gaps when no version satisfies it.
+pub fn resolution_windows(spec: &DepSpec, releases: &[Release]) -> Vec<(i64, i64, i64)> {
+    let mut candidates: Vec<&Release> = releases
+        .iter()
+        .filter(|r| spec.accepts(&r.semver))
+        .collect();
+    candidates.sort_by(|a, b| compare_versions(&a.semver, &b.semver));
+
+    // (time, rank, +1 or -1)
+    let mut events: Vec<(i64, usize, i32)> = vec![];
+    for (rank, r) in candidates.iter().enumerate() {
+        for (since, until) in &r.live {
+            events.push((*since, rank, 1));
+            if *until != i64::MAX {
+                events.push((*until, rank, -1));
+            }
+        }
+    }
+    events.sort_unstable();
+
+    let mut live: BTreeMap<usize, i32> = BTreeMap::new();
+    let mut windows = vec![];
+    let mut current: Option<(i64, usize)> = None;
+    let mut i = 0;
+    while i < events.len() {
+        let t = events[i].0;
+        while i < events.len() && events[i].0 == t {
+            let (_, rank, delta) = events[i];
+            let count = live.entry(rank).or_insert(0);
+            *count += delta;
+            if *count == 0 {
+                live.remove(&rank);
+            }
+            i += 1;
+        }
+        let best = live.keys().next_back().copied();
+        if current.map(|(_, rank)| rank) != best {
+            if let Some((since, rank)) = current {
+                windows.push((since, t, candidates[rank].version_id));
+            }
+            current = best.map(|rank| (t, rank));
+        }
+    }
+    if let Some((since, rank)) = current {
+        windows.push((since, i64::MAX, candidates[rank].version_id));
+    }
+    windows
+}
+
+/// Part of the exposure of a version found at the current depth.
+struct Piece {
+    window: Window,
+    path: Vec<i64>,
+}
+
+/// Computes the exposure windows of all versions that depend on the `affected` versions, given
+/// as `(package_id, version_id)`, going at most `max_depth` dependencies deep. The result is
+/// sorted by version and time, and doesn't include the affected versions themselves.
+pub fn propagate<S: VersionSource>(
+    source: &mut S,
+    affected: &[(i64, i64)],
+    max_depth: Option<u32>,
+) -> Vec<Exposure> {
+    // for each version, the times it's known to be exposed, at any depth so far
+    let mut covered: HashMap<i64, Vec<Window>> = HashMap::new();
+    // the pieces found at the last depth, by package and version
+    let mut frontier: BTreeMap<i64, BTreeMap<i64, Vec<Piece>>> = BTreeMap::new();
+
+    for (package_id, version_id) in affected {
+        let releases = source.releases(*package_id);
+        let release = match releases.iter().find(|r| r.version_id == *version_id) {
+            Some(r) => r,
+            None => continue,
+        };
+        covered.insert(*version_id, release.live.clone());
+        let pieces = release
+            .live
+            .iter()
+            .map(|w| Piece {
+                window: *w,
+                path: vec![],
+            })
+            .collect();
+        frontier
+            .entry(*package_id)
+            .or_default()
+            .insert(*version_id, pieces);
+    }
+
+    let mut exposures = vec![];
+    let mut depth = 0;
+    while !frontier.is_empty() && max_depth != Some(depth) {
+        depth += 1;
+        let mut next: BTreeMap<i64, BTreeMap<i64, Vec<Piece>>> = BTreeMap::new();
+
+        for (package_id, exposed) in &frontier {
+            let releases = source.releases(*package_id);
+            let mut resolutions: HashMap<i64, Vec<(i64, i64, i64)>> = HashMap::new();
+            let mut dependents = source.dependents(*package_id);
+            // so ties between paths of the same length are broken the same way every time
+            dependents.sort_by_key(|d| (d.version_id, d.dependency_id));
+
+            for d in dependents {
+                let resolution = resolutions
+                    .entry(d.dependency_id)
+                    .or_insert_with(|| resolution_windows(&d.spec, &releases));
+                for (since, until, resolved) in resolution.iter() {
+                    let pieces = match exposed.get(resolved) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    for piece in pieces {
+                        let window = (piece.window.0.max(*since), piece.window.1.min(*until));
+                        if window.0 >= window.1 {
+                            continue;
+                        }
+                        let version_covered = covered.entry(d.version_id).or_default();
+                        for part in intersect(&[window], &d.live) {
+                            for new in subtract(part, version_covered) {
+                                insert(version_covered, new);
+                                let mut path = Vec::with_capacity(piece.path.len() + 1);
+                                path.push(*resolved);
+                                path.extend(piece.path.iter());
+                                next.entry(d.package_id)
+                                    .or_default()
+                                    .entry(d.version_id)
+                                    .or_default()
+                                    .push(Piece { window: new, path });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for versions in next.values() {
+            for (version_id, pieces) in versions {
+                exposures.extend(pieces.iter().map(|p| Exposure {
+                    version_id: *version_id,
+                    since: p.window.0,
+                    until: p.window.1,
+                    path: p.path.clone(),
+                }));
+            }
+        }
+        frontier = next;
+    }
+
+    merge_exposures(exposures)
+}
+
+/// Sorts exposures and merges adjacent ones with the same path.
+fn merge_exposures(mut exposures: Vec<Exposure>) -> Vec<Exposure> {
+    exposures.sort_unstable_by_key(|e| (e.version_id, e.since));
+    let mut merged: Vec<Exposure> = Vec::with_capacity(exposures.len());
+    for e in exposures {
+        match merged.last_mut() {
+            Some(last)
+                if last.version_id == e.version_id
+                    && last.until == e.since
+                    && last.path == e.path =>
+            {
+                last.until = e.until;
+            }
+            _ => merged.push(e),
+        }
+    }
+    merged
+}
@@ -0,0 +1,73 @@
+use dependency_graph::DependencyGraph;
+use exposure_builder::compute_advisory;
+use postgres_db::connection::DbConnection;
+use postgres_db::exposures::replace_ghsa_exposures;
+use postgres_db::ghsa::query_ghsa_ids;
+
+const USAGE: &str = "[--ghsa <id>]... [--max-depth <n>]";
+
+fn main() {
+    let _tracing = utils::init_tracing("exposure_builder");
+    utils::check_no_concurrent_processes("exposure_builder");
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let program = &args[0];
+
+    let mut ghsa_ids = vec![];
+    let mut max_depth = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--ghsa" => {
+                let id = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                ghsa_ids.push(id.clone());
+            }
+            "--max-depth" => {
+                let depth = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                max_depth = Some(depth.parse().unwrap_or_else(|_| exit_with_usage(program)));
+            }
+            _ => exit_with_usage(program),
+        }
+    }
+
+    let mut conn = DbConnection::connect();
+    if ghsa_ids.is_empty() {
+        ghsa_ids = query_ghsa_ids(&mut conn);
+        ghsa_ids.sort();
+    }
+
+    // a saved graph may be behind the relational tables, but is much faster than a rebuild
+    let graph = match std::env::var("DEPENDENCY_GRAPH_PATH") {
+        Ok(path) => {
+            let (graph, through_seq) =
+                DependencyGraph::load(&path).expect("Failed to load the dependency graph");
+            tracing::info!(through_seq, "Loaded dependency graph");
+            graph
+        }
+        Err(_) => {
+            tracing::info!("Building dependency graph");
+            DependencyGraph::build(&mut conn)
+        }
+    };
+
+    for (i, ghsa_id) in ghsa_ids.iter().enumerate() {
+        let _span = tracing::info_span!("advisory", ghsa_id = %ghsa_id).entered();
+        let (affected, exposures) = compute_advisory(&mut conn, &graph, ghsa_id, max_depth);
+        tracing::info!(
+            progress = %format_args!("{}/{}", i + 1, ghsa_ids.len()),
+            num_affected = affected.len(),
+            num_exposures = exposures.len(),
+            "Computed exposures"
+        );
+        conn.run_psql_transaction(|mut conn| {
+            replace_ghsa_exposures(&mut conn, ghsa_id, affected, exposures);
+            Ok(((), true))
+        })
+        .unwrap();
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
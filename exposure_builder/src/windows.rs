@@ -0,0 +1,59 @@
+//! Sorted lists of disjoint time windows `[since, until)`, in unix seconds.
+
+pub(crate) type Window = (i64, i64);
+
+/// The intersection of two sorted lists of disjoint windows.
+pub(crate) fn intersect(a: &[Window], b: &[Window]) -> Vec<Window> {
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let since = a[i].0.max(b[j].0);
+        let until = a[i].1.min(b[j].1);
+        if since < until {
+            out.push((since, until));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    out
+}
+
+/// The parts of `w` that aren't in `covered`.
+pub(crate) fn subtract(w: Window, covered: &[Window]) -> Vec<Window> {
+    let mut out = vec![];
+    let mut since = w.0;
+    for c in covered {
+        if c.1 <= since {
+            continue;
+        }
+        if c.0 >= w.1 {
+            break;
+        }
+        if since < c.0 {
+            out.push((since, c.0));
+        }
+        since = since.max(c.1);
+        if since >= w.1 {
+            return out;
+        }
+    }
+    if since < w.1 {
+        out.push((since, w.1));
+    }
+    out
+}
+
+/// Adds `w` to `covered`, merging it with the windows it overlaps or touches.
+pub(crate) fn insert(covered: &mut Vec<Window>, w: Window) {
+    let start = covered.partition_point(|c| c.1 < w.0);
+    let end = covered.partition_point(|c| c.0 <= w.1);
+    if start == end {
+        covered.insert(start, w);
+    } else {
+        let merged = (w.0.min(covered[start].0), w.1.max(covered[end - 1].1));
+        covered.splice(start..end, [merged]);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use exposure_builder::{
+    is_affected, propagate, resolution_windows, DepSpec, Dependent, Exposure, Release,
+    VersionSource,
+};
+use semver_spec_serialization::{parse_range, parse_semver};
+
+const MAX: i64 = i64::MAX;
+
+fn range(s: &str) -> DepSpec {
+    DepSpec::Range(parse_range(s).unwrap())
+}
+
+/// Versions are `(version_id, semver, created, deleted)`, and package ids are `version_id / 10`.
+#[derive(Default)]
+struct Registry {
+    releases: HashMap<i64, Vec<Release>>,
+    dependents: HashMap<i64, Vec<Dependent>>,
+    next_dependency_id: i64,
+}
+
+impl Registry {
+    fn version(&mut self, version_id: i64, semver: &str, created: i64, deleted: Option<i64>) {
+        self.releases
+            .entry(version_id / 10)
+            .or_default()
+            .push(Release {
+                version_id,
+                semver: parse_semver(semver).unwrap(),
+                live: vec![(created, deleted.unwrap_or(MAX))],
+            });
+    }
+
+    fn depends(&mut self, version_id: i64, package_id: i64, spec: DepSpec) {
+        let live = self.releases[&(version_id / 10)]
+            .iter()
+            .find(|r| r.version_id == version_id)
+            .unwrap()
+            .live
+            .clone();
+        self.next_dependency_id += 1;
+        self.dependents
+            .entry(package_id)
+            .or_default()
+            .push(Dependent {
+                version_id,
+                package_id: version_id / 10,
+                live,
+                dependency_id: self.next_dependency_id,
+                spec,
+            });
+    }
+}
+
+impl VersionSource for Registry {
+    fn releases(&mut self, package_id: i64) -> Rc<Vec<Release>> {
+        Rc::new(self.releases.get(&package_id).cloned().unwrap_or_default())
+    }
+
+    fn dependents(&mut self, package_id: i64) -> Vec<Dependent> {
+        self.dependents
+            .get(&package_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn exposure(version_id: i64, since: i64, until: i64, path: &[i64]) -> Exposure {
+    Exposure {
+        version_id,
+        since,
+        until,
+        path: path.to_vec(),
+    }
+}
+
+#[test]
+fn test_resolution_windows() {
+    let mut r = Registry::default();
+    r.version(10, "1.0.0", 1, None);
+    r.version(11, "1.2.0-beta.1", 2, None);
+    r.version(12, "1.1.0", 3, Some(5));
+    r.version(13, "2.0.0", 4, None);
+    let releases = &r.releases[&1];
+
+    assert_eq!(
+        resolution_windows(&range("^1.0.0"), releases),
+        vec![(1, 3, 10), (3, 5, 12), (5, MAX, 10)]
+    );
+    assert_eq!(
+        resolution_windows(&DepSpec::Latest, releases),
+        vec![(1, 3, 10), (3, 4, 12), (4, MAX, 13)]
+    );
+    assert_eq!(
+        resolution_windows(&range(">=1.2.0-beta.0 <1.2.0"), releases),
+        vec![(2, MAX, 11)]
+    );
+    assert_eq!(resolution_windows(&range("^3.0.0"), releases), vec![]);
+}
+
+#[test]
+fn test_prereleases_are_affected() {
+    let vulnerable = parse_range("<1.2.1").unwrap();
+    assert!(is_affected(&vulnerable, &parse_semver("1.2.0").unwrap()));
+    assert!(is_affected(
+        &vulnerable,
+        &parse_semver("1.2.1-beta").unwrap()
+    ));
+    assert!(!is_affected(&vulnerable, &parse_semver("1.2.1").unwrap()));
+}
+
+/// 1@1.0.0 is affected and fixed in 1.1.0 at time 5. Package 2 depends on 1 and package 3 on 2.
+fn chain() -> Registry {
+    let mut r = Registry::default();
+    r.version(10, "1.0.0", 1, None);
+    r.version(11, "1.1.0", 5, None);
+    r.version(20, "1.0.0", 2, None);
+    r.depends(20, 1, range("^1.0.0"));
+    r.version(30, "1.0.0", 0, None);
+    r.depends(30, 2, range("*"));
+    r
+}
+
+#[test]
+fn test_transitive_exposure() {
+    let mut r = chain();
+    assert_eq!(
+        propagate(&mut r, &[(1, 10)], None),
+        vec![
+            exposure(20, 2, 5, &[10]),
+            // 3 can't be installed before 2 is published
+            exposure(30, 2, 5, &[20, 10]),
+        ]
+    );
+    assert_eq!(
+        propagate(&mut r, &[(1, 10)], Some(1)),
+        vec![exposure(20, 2, 5, &[10])]
+    );
+}
+
+#[test]
+fn test_pinned_dependencies_stay_exposed() {
+    let mut r = chain();
+    r.version(40, "1.0.0", 3, Some(8));
+    r.depends(40, 1, range("1.0.0"));
+    assert_eq!(
+        propagate(&mut r, &[(1, 10)], None),
+        vec![
+            exposure(20, 2, 5, &[10]),
+            exposure(30, 2, 5, &[20, 10]),
+            // only while it exists
+            exposure(40, 3, 8, &[10]),
+        ]
+    );
+}
+
+#[test]
+fn test_shortest_path_wins() {
+    let mut r = chain();
+    // 3@2.0.0 depends on both 1 and 2
+    r.version(31, "2.0.0", 3, None);
+    r.depends(31, 2, range("^1.0.0"));
+    r.depends(31, 1, range("~1.0.0"));
+    // 1.0.x is never fixed, so it's exposed through 1 from the start, and through 2 until 5
+    assert_eq!(
+        propagate(&mut r, &[(1, 10)], None),
+        vec![
+            exposure(20, 2, 5, &[10]),
+            exposure(30, 2, 5, &[20, 10]),
+            exposure(31, 3, MAX, &[10]),
+        ]
+    );
+}
+
+#[test]
+fn test_windows_are_split_by_path() {
+    let mut r = Registry::default();
+    r.version(10, "1.0.0", 1, None);
+    r.version(11, "1.0.1", 4, None);
+    r.version(20, "1.0.0", 1, None);
+    r.depends(20, 1, range("^1.0.0"));
+    // both 1.0.0 and 1.0.1 are affected, so 2 is always exposed, but through different versions
+    assert_eq!(
+        propagate(&mut r, &[(1, 10), (1, 11)], None),
+        vec![exposure(20, 1, 4, &[10]), exposure(20, 4, MAX, &[11])]
+    );
+}
+
+#[test]
+fn test_cycles() {
+    let mut r = chain();
+    // 2 and 3 depend on each other, which doesn't change anything
+    r.depends(20, 3, range("*"));
+    assert_eq!(
+        propagate(&mut r, &[(1, 10)], None),
+        vec![exposure(20, 2, 5, &[10]), exposure(30, 2, 5, &[20, 10])]
+    );
+}
@@ -1,16 +1,23 @@
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
 use postgres_db::{
     connection::{DbConnection, QueryRunner},
-    custom_types::Semver,
-    ghsa::GhsaVulnerability,
+    custom_types::{Semver, VersionConstraint},
+    ghsa::{GhsaHistoryVulnerability, GhsaRevision, GhsaVulnerability},
 };
 use semver_spec_serialization::ParseSemverError;
 
 use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 use graphql_client::{GraphQLQuery, Response};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+/// The `source` of the advisories we scrape.
+const GHSA_SOURCE: &str = "github";
+
+const COMPONENT: &str = "ghsa_scraper";
+
 pub type URI = String;
 pub type DateTime = String; // TODO: change this to chrono time
 
@@ -22,6 +29,14 @@ pub type DateTime = String; // TODO: change this to chrono time
 )]
 struct QueryAllGHSA;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github.graphql",
+    query_path = "src/query.graphql",
+    response_derives = "Debug, Deserialize, Serialize, Clone"
+)]
+struct QueryUpdatedGHSA;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SecurityVulnerability {
@@ -33,6 +48,7 @@ pub struct SecurityVulnerability {
 #[serde(rename_all = "camelCase")]
 pub struct Advisory {
     pub ghsa_id: String,
+    pub identifiers: Vec<Identifier>,
     pub summary: String,
     pub description: String,
     pub references: Vec<Reference>,
@@ -44,6 +60,14 @@ pub struct Advisory {
     pub vulnerabilities: VulnNodes,
 }
 
+/// What `QueryUpdatedGHSA` returns, where the severity is on the advisory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdvisoryWithSeverity {
+    #[serde(flatten)]
+    pub advisory: Advisory,
+    pub severity: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Cvss {
@@ -51,6 +75,15 @@ pub struct Cvss {
     pub vector_string: Option<String>,
 }
 
+/// Another id of an advisory, e.g. its CVE.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identifier {
+    #[serde(rename = "type")]
+    pub id_type: String,
+    pub value: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Reference {
@@ -107,43 +140,64 @@ pub enum GQLError {
     Serde(#[from] serde_json::Error),
 }
 
-pub async fn scrape_ghsa(token: &str) -> Result<Vec<SecurityVulnerability>, GQLError> {
+async fn run_query<Q: GraphQLQuery>(
+    token: &str,
+    variables: Q::Variables,
+) -> Result<Q::ResponseData, GQLError> {
+    let query = Q::build_query(variables);
+    let res = reqwest::Client::new()
+        .post("https://api.github.com/graphql")
+        .bearer_auth(token.to_string())
+        // github's graphql api requires user-agent header
+        .header("User-Agent", "much scraper, much win")
+        .json(&query)
+        .send()
+        .await?;
+    let res: Response<Q::ResponseData> = res.json().await?;
+    res.data.ok_or_else(|| {
+        let errs = res
+            .errors
+            .unwrap_or_default()
+            .iter()
+            .map(|e| e.message.clone())
+            .collect::<Vec<_>>()
+            .join(", ");
+        GQLError::MalformedResponse(errs)
+    })
+}
+
+pub async fn scrape_ghsa(
+    token: &str,
+    logger: &mut MetricsLogger,
+) -> Result<Vec<SecurityVulnerability>, GQLError> {
     let mut scraped_vulns: Vec<SecurityVulnerability> = vec![];
     let mut ghsa_ids = HashSet::new(); // to avoid dups, graphql is flaky
     let mut cursor: Option<String> = Option::None;
     loop {
-        let query = QueryAllGHSA::build_query(query_all_ghsa::Variables {
-            cursor: cursor.clone(),
-        });
-        let res = reqwest::Client::new()
-            .post("https://api.github.com/graphql")
-            .bearer_auth(token.to_string())
-            // github's graphql api requires user-agent header
-            .header("User-Agent", "much scraper, much win")
-            .json(&query)
-            .send()
-            .await?;
-        let res: Response<query_all_ghsa::ResponseData> = res.json().await?;
-        let data: query_all_ghsa::ResponseData = res.data.ok_or_else(|| {
-            let errs = res
-                .errors
-                .unwrap_or_default()
-                .iter()
-                .map(|e| e.message.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
-            GQLError::MalformedResponse(errs)
-        })?;
+        let page_start = Instant::now();
+        let data = run_query::<QueryAllGHSA>(
+            token,
+            query_all_ghsa::Variables {
+                cursor: cursor.clone(),
+            },
+        )
+        .await?;
         let vulns =
             serde_json::to_value(data.security_vulnerabilities.nodes.ok_or_else(|| {
                 GQLError::MalformedResponse("SecurityVulnerabilities.nodes was null".to_string())
             })?)?;
         let vulns: Vec<SecurityVulnerability> = serde_json::from_value(vulns)?;
         let num_vulns = vulns.len();
-        println!("Scraped {} vulns", num_vulns);
-        println!(
-            "Cursor: {:?}\nNew Cursor: {:?}",
-            cursor, data.security_vulnerabilities.page_info.end_cursor
+        tracing::info!(num_vulns, "Scraped a page of vulnerabilities");
+        logger.log_event(
+            MetricsEvent::new(COMPONENT, EventKind::Progress)
+                .count("advisories", num_vulns as u64)
+                .duration("duration", page_start.elapsed()),
+        );
+        tracing::debug!(
+            cursor = ?cursor,
+            new_cursor = ?data.security_vulnerabilities.page_info.end_cursor,
+            "Next page"
         );
         cursor = data.security_vulnerabilities.page_info.end_cursor;
         for vuln in vulns {
@@ -156,18 +210,111 @@ pub async fn scrape_ghsa(token: &str) -> Result<Vec<SecurityVulnerability>, GQLE
             break;
         }
     }
-    println!("In total, found {} vulns", scraped_vulns.len());
+    tracing::info!(num_vulns = scraped_vulns.len(), "Done scraping");
+    Ok(scraped_vulns)
+}
+
+/// Scrapes the advisories updated since `since`, keeping those with npm vulnerabilities or that
+/// are in `known_ids`, since their npm vulnerabilities may have been removed.
+pub async fn scrape_updated_ghsa(
+    token: &str,
+    since: chrono::DateTime<chrono::Utc>,
+    known_ids: &HashSet<String>,
+    logger: &mut MetricsLogger,
+) -> Result<Vec<SecurityVulnerability>, GQLError> {
+    let mut scraped_vulns: Vec<SecurityVulnerability> = vec![];
+    let mut ghsa_ids = HashSet::new(); // to avoid dups, graphql is flaky
+    let mut cursor: Option<String> = Option::None;
+    loop {
+        let page_start = Instant::now();
+        let data = run_query::<QueryUpdatedGHSA>(
+            token,
+            query_updated_ghsa::Variables {
+                cursor: cursor.clone(),
+                updated_since: Some(since.to_rfc3339()),
+            },
+        )
+        .await?;
+        let advisories =
+            serde_json::to_value(data.security_advisories.nodes.ok_or_else(|| {
+                GQLError::MalformedResponse("SecurityAdvisories.nodes was null".to_string())
+            })?)?;
+        let advisories: Vec<AdvisoryWithSeverity> = serde_json::from_value(advisories)?;
+        tracing::info!(
+            num_advisories = advisories.len(),
+            "Scraped a page of updated advisories"
+        );
+        logger.log_event(
+            MetricsEvent::new(COMPONENT, EventKind::Progress)
+                .count("advisories", advisories.len() as u64)
+                .duration("duration", page_start.elapsed()),
+        );
+        cursor = data.security_advisories.page_info.end_cursor;
+        for a in advisories {
+            let relevant = !a.advisory.vulnerabilities.nodes.is_empty()
+                || known_ids.contains(&a.advisory.ghsa_id);
+            if relevant && ghsa_ids.insert(a.advisory.ghsa_id.clone()) {
+                scraped_vulns.push(SecurityVulnerability {
+                    advisory: a.advisory,
+                    severity: a.severity,
+                });
+            }
+        }
+        if !data.security_advisories.page_info.has_next_page {
+            break;
+        }
+    }
+    tracing::info!(
+        num_advisories = scraped_vulns.len(),
+        %since,
+        "Done scraping updated npm advisories"
+    );
     Ok(scraped_vulns)
 }
 
-fn insert_ghsa<R>(conn: &mut R, vulns: Vec<SecurityVulnerability>)
+/// Inserts the advisories, returning the number of new revisions.
+fn insert_ghsa<R>(
+    conn: &mut R,
+    vulns: Vec<SecurityVulnerability>,
+    observed_at: chrono::DateTime<chrono::Utc>,
+) -> usize
 where
     R: QueryRunner,
 {
     let mut ghsa_cwe_pairs: HashSet<(String, String)> = HashSet::new();
     let mut cwe_info: HashMap<String, (String, String)> = HashMap::new();
+    let mut num_revisions = 0;
 
     for vuln in vulns {
+        let _span = tracing::info_span!("advisory", ghsa_id = %vuln.advisory.ghsa_id).entered();
+        let history_vulns = vuln
+            .advisory
+            .vulnerabilities
+            .nodes
+            .iter()
+            .map(|v| GhsaHistoryVulnerability {
+                package_name: v.package.name.clone(),
+                vulnerable_version_range: v.vulnerable_version_range.clone(),
+                first_patched_version: v
+                    .first_patched_version
+                    .as_ref()
+                    .map(|p| p.identifier.clone()),
+            })
+            .collect();
+        let cwe_ids = vuln
+            .advisory
+            .cwes
+            .nodes
+            .iter()
+            .map(|c| c.cwe_id.clone())
+            .collect();
+        let aliases: Vec<String> = vuln
+            .advisory
+            .identifiers
+            .iter()
+            .map(|i| i.value.clone())
+            .collect();
+
         let vulnerabilities: Vec<GhsaVulnerability> = vuln
             .advisory
             .vulnerabilities
@@ -177,14 +324,19 @@ where
                 let patch = vuln_node.first_patched_version.and_then(|v| {
                     let parse_result = parse_version_best_effort(&v.identifier, false);
                     if parse_result.is_err() {
-                        println!(
-                            "Warning: failed to parse patch semver {}, id = {}",
-                            v.identifier, vuln.advisory.ghsa_id
+                        tracing::warn!(
+                            package = %vuln_node.package.name,
+                            patch = %v.identifier,
+                            "Failed to parse the first patched version"
                         );
                     }
                     parse_result.ok().map(|(v, _is_wildcard)| v)
                 });
 
+                let constraint = parse_range_constraint(
+                    &vuln_node.vulnerable_version_range,
+                    &vuln.advisory.ghsa_id,
+                )?;
                 let (lower_v, lower_inc, upper_v, upper_inc) =
                     parse_range(vuln_node.vulnerable_version_range, &vuln.advisory.ghsa_id);
 
@@ -196,6 +348,7 @@ where
                     vulnerable_version_upper_bound: upper_v,
                     vulnerable_version_upper_bound_inclusive: upper_inc,
                     first_patched_version: patch,
+                    vulnerable_version_constraint: constraint,
                 })
             })
             .collect();
@@ -244,9 +397,17 @@ where
                 .cvss
                 .filter(|c| c.vector_string.is_some())
                 .map(|c| c.vector_string.unwrap()),
+            source: GHSA_SOURCE.to_owned(),
         };
+        let revision = GhsaRevision::new(&ghsa_db_struct, cwe_ids, history_vulns, observed_at);
+        if postgres_db::ghsa::insert_ghsa_revision(conn, revision) {
+            num_revisions += 1;
+        }
+        let ghsa_id = ghsa_db_struct.id.clone();
         postgres_db::ghsa::insert_ghsa(conn, ghsa_db_struct, vulnerabilities);
+        postgres_db::ghsa::set_ghsa_aliases(conn, &ghsa_id, GHSA_SOURCE, aliases);
     }
+    tracing::info!(num_revisions, "Recorded new advisory revisions");
 
     let cwes_to_insert: Vec<_> = cwe_info
         .into_iter()
@@ -264,6 +425,7 @@ where
 
     postgres_db::ghsa::insert_cwes(conn, cwes_to_insert);
     postgres_db::ghsa::associate_ghsa_to_cwe(conn, ghsa_cwe_relations_to_insert);
+    num_revisions
 }
 
 #[tokio::main]
@@ -271,21 +433,84 @@ async fn main() {
     utils::check_no_concurrent_processes("ghsa_scraper");
     dotenvy::from_filename(".secret.env").expect("failed to load .secret.env. To setup GHSA scraping, run:\necho \"export GITHUB_TOKEN=<TYPE API TOKEN HERE>\" >> .secret.env\n\nThe token must be a Github PAT with read:packages permission.\n\n");
 
+    // after .secret.env, which has priority over the .env this loads
+    let _tracing = utils::init_tracing("ghsa_scraper");
+
     let github_token = std::env::var("GITHUB_TOKEN").expect("GITHUB_TOKEN env var not set");
 
-    let vulns = scrape_ghsa(&github_token).await.unwrap_or_else(|e| {
-        println!("Error: {}", e);
+    let args = std::env::args().collect::<Vec<_>>();
+    let full = match args.get(1).map(|a| a.as_str()) {
+        None | Some("incremental") => false,
+        Some("full") => true,
+        _ => {
+            eprintln!("Usage: {} [incremental|full]", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let mut conn = DbConnection::connect();
+    let mut metrics_logger = MetricsLogger::from_env_or_exit();
+    let observed_at = chrono::Utc::now();
+    let start = Instant::now();
+    metrics_logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::SessionStart)
+            .text("mode", if full { "full" } else { "incremental" }),
+    );
+
+    // incremental scraping starts from the latest update we have, which is seen again, but
+    // unchanged advisories don't add to the history
+    let since = if full {
+        None
+    } else {
+        postgres_db::ghsa::query_latest_ghsa_updated_at(&mut conn)
+    };
+    let vulns = match since {
+        Some(since) => {
+            let known_ids: HashSet<String> = postgres_db::ghsa::query_ghsa_ids(&mut conn)
+                .into_iter()
+                .collect();
+            scrape_updated_ghsa(&github_token, since, &known_ids, &mut metrics_logger).await
+        }
+        None => scrape_ghsa(&github_token, &mut metrics_logger).await,
+    }
+    .unwrap_or_else(|e| {
+        tracing::error!(error = %e, "Scraping failed");
+        metrics_logger.log_event(
+            MetricsEvent::new(COMPONENT, EventKind::Error).text("message", e.to_string()),
+        );
         std::process::exit(1);
     });
+    let num_advisories = vulns.len();
 
-    let mut conn = DbConnection::connect();
-    conn.run_psql_transaction(|mut conn| {
-        insert_ghsa(&mut conn, vulns);
-        Ok(((), true))
-    })
-    .unwrap();
+    let num_revisions = conn
+        .run_psql_transaction(|mut conn| {
+            let num_revisions = insert_ghsa(&mut conn, vulns, observed_at);
+            Ok((num_revisions, true))
+        })
+        .unwrap();
+    metrics_logger.log_event(
+        MetricsEvent::new(COMPONENT, EventKind::SessionEnd)
+            .count("advisories", num_advisories as u64)
+            .count("revisions", num_revisions as u64)
+            .duration("duration", start.elapsed()),
+    );
+}
+
+/// The exact constraint of a GHSA range, or `None` (with a warning) if it can't be parsed.
+fn parse_range_constraint(range_str: &str, ghsa_id: &str) -> Option<VersionConstraint> {
+    semver_spec_serialization::parse_advisory_range(range_str)
+        .map_err(|err| {
+            tracing::warn!(
+                ghsa_id,
+                range = range_str,
+                error = %err,
+                "Skipping a vulnerable range that can't be parsed"
+            )
+        })
+        .ok()
 }
 
+/// The lower and upper bound of a range, for the bound columns of `vulnerabilities`.
 fn parse_range(range_str: String, ghsa_id: &str) -> (Option<Semver>, bool, Option<Semver>, bool) {
     let range_components: Vec<_> = range_str.split(", ").collect();
 
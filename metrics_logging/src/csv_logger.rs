@@ -10,17 +10,20 @@ use chrono::{DateTime, Utc};
 
 use crate::{
     DiffLogBatchCompleteMetrics, DiffLogEndSessionMetrics, DiffLogPanicMetrics,
-    DiffLogStartSessionMetrics, MetricsLoggerTrait, RelationalDbBatchCompleteMetrics,
-    RelationalDbEndSessionMetrics, RelationalDbPanicMetrics, RelationalDbStartSessionMetrics,
+    DiffLogStartSessionMetrics, FieldValue, MetricsEvent, MetricsLoggerTrait,
+    RelationalDbBatchCompleteMetrics, RelationalDbEndSessionMetrics, RelationalDbPanicMetrics,
+    RelationalDbStartSessionMetrics,
 };
 
 pub struct CsvLogger {
+    dir: PathBuf,
     writers: HashMap<String, csv::Writer<File>>,
 }
 
 impl CsvLogger {
-    pub(crate) fn new() -> CsvLogger {
+    pub(crate) fn new(dir: PathBuf) -> CsvLogger {
         CsvLogger {
+            dir,
             writers: HashMap::new(),
         }
     }
@@ -50,9 +53,10 @@ fn open_csv_file<P: AsRef<Path>>(path: P) -> csv::Writer<File> {
 }
 
 impl CsvLogger {
-    fn get_csv_file(&mut self, name: &'static str) -> &mut csv::Writer<File> {
+    fn get_csv_file(&mut self, name: &str) -> &mut csv::Writer<File> {
+        let dir = &self.dir;
         self.writers.entry(name.to_string()).or_insert_with(|| {
-            let mut path: PathBuf = ["logs", name].iter().collect();
+            let mut path = dir.join(name);
             path.set_extension("csv");
             open_csv_file(path)
         })
@@ -87,6 +91,46 @@ struct DiffLogSessionMetricsCsv {
     session_total_duration_secs: Option<f64>,
 }
 
+#[derive(Serialize)]
+struct RelationalDbBatchCompleteMetricsCsv {
+    batch_start_time: DateTime<Utc>,
+    batch_end_time: DateTime<Utc>,
+    batch_start_seq_inclusive: i64,
+    batch_end_seq_inclusive: i64,
+    batch_num_processed_seqs: i64,
+    batch_num_processed_diff_entries: i64,
+    batch_bytes_read: i64,
+    batch_bytes_written: i64,
+    batch_reading_duration_secs: f64,
+    batch_writing_duration_secs: f64,
+    batch_total_duration_secs: f64,
+    session_num_seqs: i64,
+    session_num_diff_entries: i64,
+    session_num_seqs_processed_so_far: i64,
+    session_num_diff_entries_processed_so_far: i64,
+    session_start_time: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct RelationalDbSessionMetricsCsv {
+    event_type: String,
+    session_start_time: DateTime<Utc>,
+    session_start_seq_exclusive: i64,
+    session_num_seqs: i64,
+    session_num_diff_entries: i64,
+    session_end_time: Option<DateTime<Utc>>,
+    session_end_seq_inclusive: Option<i64>,
+    session_total_duration_secs: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct EventCsv {
+    time: DateTime<Utc>,
+    kind: &'static str,
+    /// A JSON object, since the fields differ between events.
+    fields: String,
+}
+
 impl MetricsLoggerTrait for CsvLogger {
     fn log_diff_log_builder_batch_complete_metrics(
         &mut self,
@@ -172,23 +216,120 @@ impl MetricsLoggerTrait for CsvLogger {
 
     fn log_relational_db_builder_batch_complete_metrics(
         &mut self,
-        _metrics: RelationalDbBatchCompleteMetrics,
+        metrics_tmp: RelationalDbBatchCompleteMetrics,
     ) {
-        todo!()
+        let time_now = Utc::now();
+        let metrics = RelationalDbBatchCompleteMetricsCsv {
+            batch_start_time: metrics_tmp.batch_start_time,
+            batch_end_time: time_now,
+            batch_start_seq_inclusive: metrics_tmp.batch_start_seq_inclusive,
+            batch_end_seq_inclusive: metrics_tmp.batch_end_seq_inclusive,
+            batch_num_processed_seqs: metrics_tmp.batch_num_processed_seqs,
+            batch_num_processed_diff_entries: metrics_tmp.batch_num_processed_diff_entries,
+            batch_bytes_read: metrics_tmp.batch_bytes_read,
+            batch_bytes_written: metrics_tmp.batch_bytes_written,
+            batch_reading_duration_secs: metrics_tmp
+                .batch_reading_duration
+                .to_std()
+                .unwrap()
+                .as_secs_f64(),
+            batch_writing_duration_secs: metrics_tmp
+                .batch_writing_duration
+                .to_std()
+                .unwrap()
+                .as_secs_f64(),
+            batch_total_duration_secs: metrics_tmp
+                .batch_total_duration
+                .to_std()
+                .unwrap()
+                .as_secs_f64(),
+            session_num_seqs: metrics_tmp.session_num_seqs,
+            session_num_diff_entries: metrics_tmp.session_num_diff_entries,
+            session_num_seqs_processed_so_far: metrics_tmp.session_num_seqs_processed_so_far,
+            session_num_diff_entries_processed_so_far: metrics_tmp
+                .session_num_diff_entries_processed_so_far,
+            session_start_time: metrics_tmp.session_start_time,
+        };
+
+        let csv = self.get_csv_file("relational_db_builder_batch_metrics");
+        csv.serialize(metrics).unwrap();
+        csv.flush().unwrap();
     }
 
     fn log_relational_db_builder_start_session(
         &mut self,
-        _metrics: RelationalDbStartSessionMetrics,
+        start_metrics: RelationalDbStartSessionMetrics,
+    ) {
+        let metrics = RelationalDbSessionMetricsCsv {
+            event_type: "start_session".to_owned(),
+            session_start_time: start_metrics.session_start_time,
+            session_start_seq_exclusive: start_metrics.session_start_seq_exclusive,
+            session_num_seqs: start_metrics.session_num_seqs,
+            session_num_diff_entries: start_metrics.session_num_diff_entries,
+            session_end_time: None,
+            session_end_seq_inclusive: None,
+            session_total_duration_secs: None,
+        };
+
+        let csv = self.get_csv_file("relational_db_builder_session_metrics");
+        csv.serialize(metrics).unwrap();
+        csv.flush().unwrap();
+    }
+
+    fn log_relational_db_builder_end_session(
+        &mut self,
+        end_metrics: RelationalDbEndSessionMetrics,
     ) {
-        todo!()
+        let metrics = RelationalDbSessionMetricsCsv {
+            event_type: "end_session".to_owned(),
+            session_start_time: end_metrics.session_start_time,
+            session_start_seq_exclusive: end_metrics.session_start_seq_exclusive,
+            session_num_seqs: end_metrics.session_num_seqs,
+            session_num_diff_entries: end_metrics.session_num_diff_entries,
+            session_end_time: Some(end_metrics.session_end_time),
+            session_end_seq_inclusive: Some(end_metrics.session_end_seq_inclusive),
+            session_total_duration_secs: Some(
+                end_metrics
+                    .session_total_duration
+                    .to_std()
+                    .unwrap()
+                    .as_secs_f64(),
+            ),
+        };
+
+        let csv = self.get_csv_file("relational_db_builder_session_metrics");
+        csv.serialize(metrics).unwrap();
+        csv.flush().unwrap();
     }
 
-    fn log_relational_db_builder_end_session(&mut self, _metrics: RelationalDbEndSessionMetrics) {
-        todo!()
+    fn log_relational_db_builder_panic(&mut self, metrics: RelationalDbPanicMetrics) {
+        let csv = &mut self.get_csv_file("relational_db_builder_panic_metrics");
+        csv.serialize(metrics).unwrap();
+        csv.flush().unwrap();
     }
 
-    fn log_relational_db_builder_panic(&mut self, _metrics: RelationalDbPanicMetrics) {
-        todo!()
+    fn log_event(&mut self, event: MetricsEvent) {
+        let fields: serde_json::Map<String, serde_json::Value> = event
+            .fields
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    FieldValue::Count(n) => n.into(),
+                    FieldValue::Gauge(n) => n.into(),
+                    FieldValue::Seconds(secs) => secs.into(),
+                    FieldValue::Text(text) => text.into(),
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        let row = EventCsv {
+            time: event.time,
+            kind: event.kind.as_str(),
+            fields: serde_json::Value::Object(fields).to_string(),
+        };
+
+        let csv = self.get_csv_file(&format!("{}_events", event.component));
+        csv.serialize(row).unwrap();
+        csv.flush().unwrap();
     }
 }
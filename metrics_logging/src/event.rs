@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// What kind of thing a [`MetricsEvent`] reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    SessionStart,
+    /// Some work got done, e.g. a page of changes was fetched or a tarball was uploaded.
+    Progress,
+    /// Something failed. Error rates are errors over progress events.
+    Error,
+    SessionEnd,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::SessionStart => "session_start",
+            EventKind::Progress => "progress",
+            EventKind::Error => "error",
+            EventKind::SessionEnd => "session_end",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    /// Added up across events, e.g. the number of changes fetched or bytes uploaded.
+    Count(u64),
+    /// A level at the time of the event, e.g. the latest seq, or how many seqs are left.
+    Gauge(i64),
+    /// A duration, e.g. how long a page took.
+    Seconds(f64),
+    /// Free text, e.g. an error message. Prometheus leaves these out.
+    Text(String),
+}
+
+/// A metrics event from any component of the follower, for the components that don't have
+/// their own metrics structs.
+#[derive(Debug, Clone)]
+pub struct MetricsEvent {
+    /// The binary or pipeline stage, e.g. `changes_fetcher`.
+    pub component: &'static str,
+    pub kind: EventKind,
+    pub fields: Vec<(&'static str, FieldValue)>,
+    pub time: DateTime<Utc>,
+}
+
+impl MetricsEvent {
+    pub fn new(component: &'static str, kind: EventKind) -> MetricsEvent {
+        MetricsEvent {
+            component,
+            kind,
+            fields: vec![],
+            time: Utc::now(),
+        }
+    }
+
+    pub fn count(mut self, name: &'static str, value: u64) -> MetricsEvent {
+        self.fields.push((name, FieldValue::Count(value)));
+        self
+    }
+
+    pub fn gauge(mut self, name: &'static str, value: i64) -> MetricsEvent {
+        self.fields.push((name, FieldValue::Gauge(value)));
+        self
+    }
+
+    pub fn duration(mut self, name: &'static str, value: Duration) -> MetricsEvent {
+        self.fields
+            .push((name, FieldValue::Seconds(value.as_secs_f64())));
+        self
+    }
+
+    pub fn text(mut self, name: &'static str, value: impl Into<String>) -> MetricsEvent {
+        self.fields.push((name, FieldValue::Text(value.into())));
+        self
+    }
+}
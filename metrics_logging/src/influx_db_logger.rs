@@ -9,8 +9,9 @@ use tokio::runtime::Runtime;
 
 use crate::{
     DiffLogBatchCompleteMetrics, DiffLogEndSessionMetrics, DiffLogPanicMetrics,
-    DiffLogStartSessionMetrics, MetricsLoggerTrait, RelationalDbBatchCompleteMetrics,
-    RelationalDbEndSessionMetrics, RelationalDbPanicMetrics, RelationalDbStartSessionMetrics,
+    DiffLogStartSessionMetrics, FieldValue, MetricsEvent, MetricsLoggerTrait,
+    RelationalDbBatchCompleteMetrics, RelationalDbEndSessionMetrics, RelationalDbPanicMetrics,
+    RelationalDbStartSessionMetrics,
 };
 
 pub(crate) struct InfluxDbLogger {
@@ -413,4 +414,24 @@ impl MetricsLoggerTrait for InfluxDbLogger {
 
         self.write_data_point(p);
     }
+
+    fn log_event(&mut self, event: MetricsEvent) {
+        let mut builder = DataPoint::builder(format!("{}_metrics", event.component))
+            .tag("event_type", event.kind.as_str())
+            .field("event_time", event.time.to_string());
+        for (name, value) in event.fields {
+            builder = match value {
+                FieldValue::Count(n) => builder.field(name, n as i64),
+                FieldValue::Gauge(n) => builder.field(name, n),
+                FieldValue::Seconds(secs) => builder.field(name, secs),
+                FieldValue::Text(text) => builder.field(name, text),
+            };
+        }
+        let p = builder
+            .timestamp(event.time.timestamp_nanos())
+            .build()
+            .unwrap();
+
+        self.write_data_point(p);
+    }
 }
@@ -1,12 +1,21 @@
 mod csv_logger;
+mod event;
 mod influx_db_logger;
-mod null_logger;
+mod prometheus_logger;
+
+pub use event::{EventKind, FieldValue, MetricsEvent};
 
 use chrono::{DateTime, Duration, Utc};
 use dotenv::dotenv;
 use serde::Serialize;
 use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
+#[derive(Clone)]
 pub struct DiffLogBatchCompleteMetrics {
     pub batch_start_time: DateTime<Utc>,
     pub batch_start_seq_inclusive: i64,
@@ -22,12 +31,14 @@ pub struct DiffLogBatchCompleteMetrics {
     pub session_start_time: DateTime<Utc>,
 }
 
+#[derive(Clone)]
 pub struct DiffLogStartSessionMetrics {
     pub session_start_time: DateTime<Utc>,
     pub session_start_seq_exclusive: i64,
     pub session_num_seqs: i64,
 }
 
+#[derive(Clone)]
 pub struct DiffLogEndSessionMetrics {
     pub session_start_time: DateTime<Utc>,
     pub session_start_seq_exclusive: i64,
@@ -37,13 +48,14 @@ pub struct DiffLogEndSessionMetrics {
     pub session_total_duration: Duration,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct DiffLogPanicMetrics {
     pub panic_time: DateTime<Utc>,
     pub panic_on_seq_id: i64,
     pub panic_message: String,
 }
 
+#[derive(Clone)]
 pub struct RelationalDbBatchCompleteMetrics {
     pub batch_start_time: DateTime<Utc>,
     pub batch_start_seq_inclusive: i64,
@@ -62,6 +74,7 @@ pub struct RelationalDbBatchCompleteMetrics {
     pub session_start_time: DateTime<Utc>,
 }
 
+#[derive(Clone)]
 pub struct RelationalDbStartSessionMetrics {
     pub session_start_time: DateTime<Utc>,
     pub session_start_seq_exclusive: i64,
@@ -69,6 +82,7 @@ pub struct RelationalDbStartSessionMetrics {
     pub session_num_diff_entries: i64,
 }
 
+#[derive(Clone)]
 pub struct RelationalDbEndSessionMetrics {
     pub session_start_time: DateTime<Utc>,
     pub session_start_seq_exclusive: i64,
@@ -79,7 +93,7 @@ pub struct RelationalDbEndSessionMetrics {
     pub session_total_duration: Duration,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct RelationalDbPanicMetrics {
     pub panic_time: DateTime<Utc>,
     pub panic_on_seq_id: i64,
@@ -100,78 +114,212 @@ pub trait MetricsLoggerTrait {
     fn log_relational_db_builder_start_session(&mut self, metrics: RelationalDbStartSessionMetrics);
     fn log_relational_db_builder_end_session(&mut self, metrics: RelationalDbEndSessionMetrics);
     fn log_relational_db_builder_panic(&mut self, metrics: RelationalDbPanicMetrics);
+
+    fn log_event(&mut self, event: MetricsEvent);
 }
 
-pub struct MetricsLogger(Box<dyn MetricsLoggerTrait + Send>);
+type Backends = Vec<Box<dyn MetricsLoggerTrait + Send>>;
+
+/// Sends metrics to every configured backend, e.g. both CSV files and Prometheus. Clones share
+/// the backends, so worker threads and tasks can log through a clone.
+#[derive(Clone)]
+pub struct MetricsLogger(Arc<Mutex<Backends>>);
+
+impl MetricsLogger {
+    /// Starts the backends enabled in `config`. With no backends enabled, metrics are dropped.
+    pub fn new(config: &MetricsConfig) -> Result<MetricsLogger, MetricsError> {
+        let mut backends: Backends = vec![];
+        if let Some(dir) = &config.csv_dir {
+            backends.push(Box::new(csv_logger::CsvLogger::new(dir.clone())));
+        }
+        if let Some(influx) = &config.influx_db {
+            backends.push(Box::new(influx_db_logger::InfluxDbLogger::new(
+                influx.host.clone(),
+                influx.org.clone(),
+                influx.token.clone(),
+                influx.bucket.clone(),
+            )));
+        }
+        if let Some(addr) = config.prometheus_addr {
+            backends.push(Box::new(prometheus_logger::PrometheusLogger::new(addr)?));
+        }
+        Ok(MetricsLogger(Arc::new(Mutex::new(backends))))
+    }
+
+    /// Starts the backends configured in the environment (see [`MetricsConfig::from_env`]), or
+    /// exits if they are misconfigured. For binaries, before they start working.
+    pub fn from_env_or_exit() -> MetricsLogger {
+        MetricsConfig::from_env()
+            .and_then(|config| MetricsLogger::new(&config))
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to set up metrics: {}", e);
+                std::process::exit(1);
+            })
+    }
+
+    /// A logger which drops all metrics, for tests and for binaries without metrics.
+    pub fn null() -> MetricsLogger {
+        MetricsLogger(Arc::new(Mutex::new(vec![])))
+    }
+
+    fn log_all<M: Clone>(&mut self, metrics: M, log: fn(&mut dyn MetricsLoggerTrait, M)) {
+        // a backend that panicked while logging shouldn't stop the others
+        let mut backends = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for backend in backends.iter_mut() {
+            log(backend.as_mut(), metrics.clone());
+        }
+    }
+}
 
 impl MetricsLoggerTrait for MetricsLogger {
     fn log_diff_log_builder_batch_complete_metrics(
         &mut self,
         metrics: DiffLogBatchCompleteMetrics,
     ) {
-        self.0.log_diff_log_builder_batch_complete_metrics(metrics)
+        self.log_all(metrics, |b, m| {
+            b.log_diff_log_builder_batch_complete_metrics(m)
+        })
     }
 
     fn log_diff_log_builder_start_session(&mut self, metrics: DiffLogStartSessionMetrics) {
-        self.0.log_diff_log_builder_start_session(metrics)
+        self.log_all(metrics, |b, m| b.log_diff_log_builder_start_session(m))
     }
 
     fn log_diff_log_builder_end_session(&mut self, metrics: DiffLogEndSessionMetrics) {
-        self.0.log_diff_log_builder_end_session(metrics)
+        self.log_all(metrics, |b, m| b.log_diff_log_builder_end_session(m))
     }
 
     fn log_diff_log_builder_panic(&mut self, metrics: DiffLogPanicMetrics) {
-        self.0.log_diff_log_builder_panic(metrics)
+        self.log_all(metrics, |b, m| b.log_diff_log_builder_panic(m))
     }
 
     fn log_relational_db_builder_batch_complete_metrics(
         &mut self,
         metrics: RelationalDbBatchCompleteMetrics,
     ) {
-        self.0
-            .log_relational_db_builder_batch_complete_metrics(metrics)
+        self.log_all(metrics, |b, m| {
+            b.log_relational_db_builder_batch_complete_metrics(m)
+        })
     }
 
     fn log_relational_db_builder_start_session(
         &mut self,
         metrics: RelationalDbStartSessionMetrics,
     ) {
-        self.0.log_relational_db_builder_start_session(metrics)
+        self.log_all(metrics, |b, m| b.log_relational_db_builder_start_session(m))
     }
 
     fn log_relational_db_builder_end_session(&mut self, metrics: RelationalDbEndSessionMetrics) {
-        self.0.log_relational_db_builder_end_session(metrics)
+        self.log_all(metrics, |b, m| b.log_relational_db_builder_end_session(m))
     }
 
     fn log_relational_db_builder_panic(&mut self, metrics: RelationalDbPanicMetrics) {
-        self.0.log_relational_db_builder_panic(metrics)
+        self.log_all(metrics, |b, m| b.log_relational_db_builder_panic(m))
+    }
+
+    fn log_event(&mut self, event: MetricsEvent) {
+        self.log_all(event, |b, e| b.log_event(e))
     }
 }
 
-pub fn new_metrics_logger(testing_mode: bool) -> MetricsLogger {
-    if testing_mode {
-        MetricsLogger(Box::new(null_logger::NullLogger::new()))
-    } else {
-        dotenv().expect("failed to load .env");
+#[derive(Debug, Clone)]
+pub struct InfluxDbConfig {
+    pub host: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: String,
+}
 
-        let enable_influx: bool = env::var("ENABLE_INFLUX_DB_LOGGING")
-            .unwrap()
-            .parse()
-            .unwrap();
+/// Which metrics backends to use. Any number of them can be enabled at once.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsConfig {
+    /// Directory to append CSV files of metrics to.
+    pub csv_dir: Option<PathBuf>,
+    pub influx_db: Option<InfluxDbConfig>,
+    /// Address to serve OpenMetrics on, at `/metrics`, for Prometheus to scrape.
+    pub prometheus_addr: Option<SocketAddr>,
+}
 
-        if enable_influx {
-            let host = env::var("INFLUX_DB_HOST").unwrap();
-            let org = env::var("INFLUX_DB_ORG").unwrap();
-            let bucket = env::var("INFLUX_DB_BUCKET").unwrap();
+impl MetricsConfig {
+    /// Reads the config from the environment, including `.env` and `.secret.env` if they exist:
+    ///
+    /// - `ENABLE_INFLUX_DB_LOGGING` (default `false`), with `INFLUX_DB_HOST`, `INFLUX_DB_ORG`,
+    ///   `INFLUX_DB_BUCKET` and `INFLUX_DB_TOKEN`
+    /// - `ENABLE_CSV_LOGGING` (default `true` unless InfluxDB is enabled), with `CSV_LOGGING_DIR`
+    ///   (default `logs`)
+    /// - `PROMETHEUS_LISTEN_ADDR`, e.g. `0.0.0.0:9187`, if set
+    pub fn from_env() -> Result<MetricsConfig, MetricsError> {
+        dotenv().ok();
+        dotenv::from_filename(".secret.env").ok();
 
-            dotenv::from_filename(".secret.env").expect("failed to load .secret.env. To setup InfluxDB logging, run:\necho \"export INFLUX_DB_TOKEN=<TYPE API TOKEN HERE>\" >> .secret.env\n\nOr to disable InfluxDB, set ENABLE_INFLUX_DB_LOGGING=false in .env");
+        let enable_influx = parse_env_var("ENABLE_INFLUX_DB_LOGGING")?.unwrap_or(false);
+        let influx_db = if enable_influx {
+            Some(InfluxDbConfig {
+                host: require_env_var("INFLUX_DB_HOST")?,
+                org: require_env_var("INFLUX_DB_ORG")?,
+                bucket: require_env_var("INFLUX_DB_BUCKET")?,
+                token: require_env_var("INFLUX_DB_TOKEN")?,
+            })
+        } else {
+            None
+        };
 
-            let token = env::var("INFLUX_DB_TOKEN").unwrap();
-            MetricsLogger(Box::new(influx_db_logger::InfluxDbLogger::new(
-                host, org, token, bucket,
-            )))
+        let enable_csv = parse_env_var("ENABLE_CSV_LOGGING")?.unwrap_or(!enable_influx);
+        let csv_dir = if enable_csv {
+            Some(
+                env::var("CSV_LOGGING_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| PathBuf::from("logs")),
+            )
         } else {
-            MetricsLogger(Box::new(csv_logger::CsvLogger::new()))
+            None
+        };
+
+        Ok(MetricsConfig {
+            csv_dir,
+            influx_db,
+            prometheus_addr: parse_env_var("PROMETHEUS_LISTEN_ADDR")?,
+        })
+    }
+}
+
+fn require_env_var(name: &'static str) -> Result<String, MetricsError> {
+    env::var(name).map_err(|_| MetricsError::MissingEnvVar(name))
+}
+
+fn parse_env_var<T: FromStr>(name: &'static str) -> Result<Option<T>, MetricsError> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| MetricsError::InvalidEnvVar(name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[derive(Debug)]
+pub enum MetricsError {
+    MissingEnvVar(&'static str),
+    InvalidEnvVar(&'static str, String),
+    Bind(SocketAddr, std::io::Error),
+}
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsError::MissingEnvVar(name) => write!(
+                f,
+                "{} must be set in .env (or .secret.env for tokens) for the enabled metrics backends",
+                name
+            ),
+            MetricsError::InvalidEnvVar(name, value) => {
+                write!(f, "invalid value for {}: {:?}", name, value)
+            }
+            MetricsError::Bind(addr, e) => {
+                write!(f, "failed to serve metrics on {}: {}", addr, e)
+            }
         }
     }
 }
+
+impl std::error::Error for MetricsError {}
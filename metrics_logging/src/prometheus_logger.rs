@@ -0,0 +1,508 @@
+use std::{
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread::JoinHandle,
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+
+use crate::{
+    DiffLogBatchCompleteMetrics, DiffLogEndSessionMetrics, DiffLogPanicMetrics,
+    DiffLogStartSessionMetrics, FieldValue, MetricsError, MetricsEvent, MetricsLoggerTrait,
+    RelationalDbBatchCompleteMetrics, RelationalDbEndSessionMetrics, RelationalDbPanicMetrics,
+    RelationalDbStartSessionMetrics,
+};
+
+const CONTENT_TYPE_OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StageLabels {
+    stage: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct PhaseLabels {
+    stage: String,
+    /// `read`, `write` or `total`.
+    phase: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DirectionLabels {
+    stage: String,
+    /// `read` or `written`.
+    direction: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EventLabels {
+    component: String,
+    kind: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EventFieldLabels {
+    component: String,
+    kind: String,
+    field: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct GaugeFieldLabels {
+    component: String,
+    field: String,
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+fn duration_histogram() -> Histogram {
+    // 10ms to about 11 minutes
+    Histogram::new(exponential_buckets(0.01, 2.0, 17))
+}
+
+fn bytes_histogram() -> Histogram {
+    // 1KiB to 16GiB
+    Histogram::new(exponential_buckets(1024.0, 4.0, 13))
+}
+
+/// Keeps metrics in memory, and serves them in the OpenMetrics text format on
+/// `http://<addr>/metrics` for Prometheus to scrape.
+pub(crate) struct PrometheusLogger {
+    batch_duration_seconds: HistogramFamily<PhaseLabels>,
+    batch_bytes: HistogramFamily<DirectionLabels>,
+    processed_seq: Family<StageLabels, Gauge>,
+    seq_lag: Family<StageLabels, Gauge>,
+    seqs_processed: Family<StageLabels, Counter>,
+    diff_entries_processed: Family<StageLabels, Counter>,
+    panics: Family<StageLabels, Counter>,
+    events: Family<EventLabels, Counter>,
+    event_counts: Family<EventFieldLabels, Counter>,
+    event_gauges: Family<GaugeFieldLabels, Gauge>,
+    event_durations: HistogramFamily<EventFieldLabels>,
+    local_addr: SocketAddr,
+    _server_thread: JoinHandle<()>,
+}
+
+impl PrometheusLogger {
+    /// Binds the metrics endpoint to `addr`, and serves it from a background thread.
+    pub(crate) fn new(addr: SocketAddr) -> Result<PrometheusLogger, MetricsError> {
+        let mut registry = Registry::with_prefix("npm_follower");
+
+        let batch_duration_seconds: HistogramFamily<PhaseLabels> =
+            Family::new_with_constructor(duration_histogram);
+        registry.register(
+            "batch_duration_seconds",
+            "How long reading, writing and all of a batch took",
+            batch_duration_seconds.clone(),
+        );
+        let batch_bytes: HistogramFamily<DirectionLabels> =
+            Family::new_with_constructor(bytes_histogram);
+        registry.register(
+            "batch_bytes",
+            "How many bytes a batch read and wrote",
+            batch_bytes.clone(),
+        );
+        let processed_seq = Family::<StageLabels, Gauge>::default();
+        registry.register(
+            "processed_seq",
+            "The last seq processed",
+            processed_seq.clone(),
+        );
+        let seq_lag = Family::<StageLabels, Gauge>::default();
+        registry.register(
+            "seq_lag",
+            "How many seqs the current session has left to process",
+            seq_lag.clone(),
+        );
+        let seqs_processed = Family::<StageLabels, Counter>::default();
+        registry.register(
+            "seqs_processed",
+            "Seqs processed since starting",
+            seqs_processed.clone(),
+        );
+        let diff_entries_processed = Family::<StageLabels, Counter>::default();
+        registry.register(
+            "diff_entries_processed",
+            "Diff entries processed since starting",
+            diff_entries_processed.clone(),
+        );
+        let panics = Family::<StageLabels, Counter>::default();
+        registry.register(
+            "panics",
+            "Panics while processing, including quarantined changes",
+            panics.clone(),
+        );
+        let events = Family::<EventLabels, Counter>::default();
+        registry.register(
+            "events",
+            "Events logged by each component, by kind",
+            events.clone(),
+        );
+        let event_counts = Family::<EventFieldLabels, Counter>::default();
+        registry.register(
+            "event_counts",
+            "The sum of each count field of the events of each component",
+            event_counts.clone(),
+        );
+        let event_gauges = Family::<GaugeFieldLabels, Gauge>::default();
+        registry.register(
+            "event_gauge",
+            "The latest value of each gauge field of the events of each component",
+            event_gauges.clone(),
+        );
+        let event_durations: HistogramFamily<EventFieldLabels> =
+            Family::new_with_constructor(duration_histogram);
+        registry.register(
+            "event_duration_seconds",
+            "The duration fields of the events of each component",
+            event_durations.clone(),
+        );
+
+        let (local_addr, server_thread) = serve(Arc::new(registry), addr)?;
+
+        let logger = PrometheusLogger {
+            batch_duration_seconds,
+            batch_bytes,
+            processed_seq,
+            seq_lag,
+            seqs_processed,
+            diff_entries_processed,
+            panics,
+            events,
+            event_counts,
+            event_gauges,
+            event_durations,
+            local_addr,
+            _server_thread: server_thread,
+        };
+        println!("Serving metrics on http://{}/metrics", logger.local_addr());
+        Ok(logger)
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn log_batch(
+        &mut self,
+        stage: &str,
+        end_seq: i64,
+        num_seqs: i64,
+        bytes_read: i64,
+        bytes_written: i64,
+        durations: [(&str, chrono::Duration); 3],
+        seq_lag: i64,
+    ) {
+        let labels = stage_labels(stage);
+        for (phase, duration) in durations {
+            self.batch_duration_seconds
+                .get_or_create(&PhaseLabels {
+                    stage: stage.to_string(),
+                    phase: phase.to_string(),
+                })
+                .observe(duration.to_std().unwrap_or_default().as_secs_f64());
+        }
+        for (direction, bytes) in [("read", bytes_read), ("written", bytes_written)] {
+            self.batch_bytes
+                .get_or_create(&DirectionLabels {
+                    stage: stage.to_string(),
+                    direction: direction.to_string(),
+                })
+                .observe(bytes as f64);
+        }
+        self.processed_seq.get_or_create(&labels).set(end_seq);
+        self.seq_lag.get_or_create(&labels).set(seq_lag);
+        self.seqs_processed
+            .get_or_create(&labels)
+            .inc_by(num_seqs.max(0) as u64);
+    }
+}
+
+fn stage_labels(stage: &str) -> StageLabels {
+    StageLabels {
+        stage: stage.to_string(),
+    }
+}
+
+fn serve(
+    registry: Arc<Registry>,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, JoinHandle<()>), MetricsError> {
+    // bound here, so that errors are returned. The runtime is only made on the server thread,
+    // since the caller may be async itself
+    let listener = TcpListener::bind(addr).map_err(|e| MetricsError::Bind(addr, e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| MetricsError::Bind(addr, e))?;
+
+    let make_svc = make_service_fn(move |_| {
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let resp = respond(&registry, req.uri().path());
+                async move { Ok::<_, Infallible>(resp) }
+            }))
+        }
+    });
+
+    let server_thread = std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let result = rt.block_on(async move { Server::from_tcp(listener)?.serve(make_svc).await });
+        if let Err(e) = result {
+            eprintln!("Metrics endpoint on {} failed: {}", local_addr, e);
+        }
+    });
+    Ok((local_addr, server_thread))
+}
+
+fn respond(registry: &Registry, path: &str) -> Response<Body> {
+    if path != "/metrics" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let mut body = String::new();
+    encode(&mut body, registry).expect("encoding metrics to a string can't fail");
+    Response::builder()
+        .header(CONTENT_TYPE, CONTENT_TYPE_OPENMETRICS)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+impl MetricsLoggerTrait for PrometheusLogger {
+    fn log_diff_log_builder_batch_complete_metrics(
+        &mut self,
+        metrics: DiffLogBatchCompleteMetrics,
+    ) {
+        self.log_batch(
+            "diff_log_builder",
+            metrics.batch_end_seq_inclusive,
+            metrics.batch_num_processed_seqs,
+            metrics.batch_bytes_read,
+            metrics.batch_bytes_written,
+            [
+                ("read", metrics.batch_reading_duration),
+                ("write", metrics.batch_writing_duration),
+                ("total", metrics.batch_total_duration),
+            ],
+            metrics.session_num_seqs - metrics.session_num_seqs_processed_so_far,
+        );
+    }
+
+    fn log_diff_log_builder_start_session(&mut self, metrics: DiffLogStartSessionMetrics) {
+        let labels = stage_labels("diff_log_builder");
+        self.processed_seq
+            .get_or_create(&labels)
+            .set(metrics.session_start_seq_exclusive);
+        self.seq_lag
+            .get_or_create(&labels)
+            .set(metrics.session_num_seqs);
+    }
+
+    fn log_diff_log_builder_end_session(&mut self, metrics: DiffLogEndSessionMetrics) {
+        let labels = stage_labels("diff_log_builder");
+        self.processed_seq
+            .get_or_create(&labels)
+            .set(metrics.session_end_seq_inclusive);
+        self.seq_lag.get_or_create(&labels).set(0);
+    }
+
+    fn log_diff_log_builder_panic(&mut self, _metrics: DiffLogPanicMetrics) {
+        self.panics
+            .get_or_create(&stage_labels("diff_log_builder"))
+            .inc();
+    }
+
+    fn log_relational_db_builder_batch_complete_metrics(
+        &mut self,
+        metrics: RelationalDbBatchCompleteMetrics,
+    ) {
+        self.log_batch(
+            "relational_db_builder",
+            metrics.batch_end_seq_inclusive,
+            metrics.batch_num_processed_seqs,
+            metrics.batch_bytes_read,
+            metrics.batch_bytes_written,
+            [
+                ("read", metrics.batch_reading_duration),
+                ("write", metrics.batch_writing_duration),
+                ("total", metrics.batch_total_duration),
+            ],
+            metrics.session_num_seqs - metrics.session_num_seqs_processed_so_far,
+        );
+        self.diff_entries_processed
+            .get_or_create(&stage_labels("relational_db_builder"))
+            .inc_by(metrics.batch_num_processed_diff_entries.max(0) as u64);
+    }
+
+    fn log_relational_db_builder_start_session(
+        &mut self,
+        metrics: RelationalDbStartSessionMetrics,
+    ) {
+        let labels = stage_labels("relational_db_builder");
+        self.processed_seq
+            .get_or_create(&labels)
+            .set(metrics.session_start_seq_exclusive);
+        self.seq_lag
+            .get_or_create(&labels)
+            .set(metrics.session_num_seqs);
+    }
+
+    fn log_relational_db_builder_end_session(&mut self, metrics: RelationalDbEndSessionMetrics) {
+        let labels = stage_labels("relational_db_builder");
+        self.processed_seq
+            .get_or_create(&labels)
+            .set(metrics.session_end_seq_inclusive);
+        self.seq_lag.get_or_create(&labels).set(0);
+    }
+
+    fn log_relational_db_builder_panic(&mut self, _metrics: RelationalDbPanicMetrics) {
+        self.panics
+            .get_or_create(&stage_labels("relational_db_builder"))
+            .inc();
+    }
+
+    fn log_event(&mut self, event: MetricsEvent) {
+        let component = event.component.to_string();
+        let kind = event.kind.as_str().to_string();
+        self.events
+            .get_or_create(&EventLabels {
+                component: component.clone(),
+                kind: kind.clone(),
+            })
+            .inc();
+        for (name, value) in event.fields {
+            let labels = EventFieldLabels {
+                component: component.clone(),
+                kind: kind.clone(),
+                field: name.to_string(),
+            };
+            match value {
+                FieldValue::Count(n) => {
+                    self.event_counts.get_or_create(&labels).inc_by(n);
+                }
+                FieldValue::Gauge(n) => {
+                    self.event_gauges
+                        .get_or_create(&GaugeFieldLabels {
+                            component: component.clone(),
+                            field: name.to_string(),
+                        })
+                        .set(n);
+                }
+                FieldValue::Seconds(secs) => {
+                    self.event_durations.get_or_create(&labels).observe(secs)
+                }
+                FieldValue::Text(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::EventKind;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serves_metrics() {
+        let mut logger = PrometheusLogger::new(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        logger.log_diff_log_builder_batch_complete_metrics(DiffLogBatchCompleteMetrics {
+            batch_start_time: Utc::now(),
+            batch_start_seq_inclusive: 11,
+            batch_end_seq_inclusive: 20,
+            batch_num_processed_seqs: 10,
+            batch_bytes_read: 2048,
+            batch_bytes_written: 1024,
+            batch_reading_duration: Duration::milliseconds(500),
+            batch_writing_duration: Duration::milliseconds(250),
+            batch_total_duration: Duration::seconds(1),
+            session_num_seqs: 100,
+            session_num_seqs_processed_so_far: 10,
+            session_start_time: Utc::now(),
+        });
+        logger.log_diff_log_builder_panic(DiffLogPanicMetrics {
+            panic_time: Utc::now(),
+            panic_on_seq_id: 21,
+            panic_message: "oops".into(),
+        });
+
+        let response = get(logger.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(CONTENT_TYPE_OPENMETRICS));
+        for line in [
+            r#"npm_follower_processed_seq{stage="diff_log_builder"} 20"#,
+            r#"npm_follower_seq_lag{stage="diff_log_builder"} 90"#,
+            r#"npm_follower_seqs_processed_total{stage="diff_log_builder"} 10"#,
+            r#"npm_follower_panics_total{stage="diff_log_builder"} 1"#,
+            r#"npm_follower_batch_duration_seconds_count{stage="diff_log_builder",phase="total"} 1"#,
+            r#"npm_follower_batch_bytes_sum{stage="diff_log_builder",direction="read"} 2048.0"#,
+        ] {
+            assert!(response.contains(line), "{} not in {}", line, response);
+        }
+        assert!(response.trim_end().ends_with("# EOF"));
+
+        logger.log_event(
+            MetricsEvent::new("changes_fetcher", EventKind::Progress)
+                .count("changes", 3)
+                .gauge("seq_lag", 7)
+                .duration("duration", std::time::Duration::from_millis(20))
+                .text("note", "not exported"),
+        );
+        logger.log_event(
+            MetricsEvent::new("changes_fetcher", EventKind::Progress)
+                .count("changes", 2)
+                .gauge("seq_lag", 4),
+        );
+        let response = get(logger.local_addr(), "/metrics");
+        for line in [
+            r#"npm_follower_events_total{component="changes_fetcher",kind="progress"} 2"#,
+            r#"npm_follower_event_counts_total{component="changes_fetcher",kind="progress",field="changes"} 5"#,
+            r#"npm_follower_event_gauge{component="changes_fetcher",field="seq_lag"} 4"#,
+            r#"npm_follower_event_duration_seconds_count{component="changes_fetcher",kind="progress",field="duration"} 1"#,
+        ] {
+            assert!(response.contains(line), "{} not in {}", line, response);
+        }
+        assert!(!response.contains("note"));
+
+        assert!(get(logger.local_addr(), "/").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,463 @@
+//! Building the ideal tree, following arborist's `buildIdealTree`: take the shallowest node with
+//! missing or invalid dependencies, pick a version for each of them, and place it as high up in
+//! the tree as it can go without breaking anything.
+
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use semver_spec_serialization::compare_versions;
+
+use crate::manifest::{Edge, EdgeType, VersionManifest};
+use crate::pick::{pick_manifest, registry_target};
+use crate::registry::Registry;
+use crate::tree::{manifest_satisfies, NodeId, Tree, ROOT};
+use crate::{ResolveError, ResolveOptions};
+
+/// We give up on trees with more placements than this, in case something keeps replacing itself.
+const MAX_PLACEMENTS: usize = 100_000;
+
+/// How many times we go back over the finished tree to fix edges that later placements broke.
+const MAX_PASSES: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+enum CanPlace {
+    /// Nothing with that name is at the target yet, so the dep can go there.
+    Ok,
+    /// There's already a node at the target that works.
+    Keep(NodeId),
+    /// There's a node at the target that can be swapped for the dep.
+    Replace(NodeId),
+    Conflict,
+}
+
+pub(crate) struct Builder<'a, R: Registry> {
+    registry: &'a mut R,
+    options: &'a ResolveOptions,
+    pub(crate) tree: Tree,
+    /// Nodes whose edges need checking, ordered by depth and then path like arborist's queue.
+    queue: BTreeSet<(usize, String, NodeId)>,
+    /// Edges we already failed to find a version for, so that we don't keep trying.
+    given_up: HashSet<(NodeId, String)>,
+    /// Required edges we failed to find a version for. These only fail the whole resolve if they
+    /// aren't under an optional dependency that can be left out instead.
+    failures: Vec<(NodeId, ResolveError)>,
+    pub(crate) warnings: Vec<String>,
+    placements: usize,
+}
+
+impl<'a, R: Registry> Builder<'a, R> {
+    pub(crate) fn new(tree: Tree, registry: &'a mut R, options: &'a ResolveOptions) -> Self {
+        Builder {
+            registry,
+            options,
+            tree,
+            queue: BTreeSet::new(),
+            given_up: HashSet::new(),
+            failures: vec![],
+            warnings: vec![],
+            placements: 0,
+        }
+    }
+
+    pub(crate) fn build(&mut self) -> Result<(), ResolveError> {
+        self.enqueue(ROOT);
+        for _ in 0..MAX_PASSES {
+            while let Some((_, _, id)) = self.queue.pop_first() {
+                if !self.tree.node(id).removed {
+                    self.build_deps(id)?;
+                }
+            }
+
+            // replacing a node can break edges of nodes we were already done with
+            let broken: Vec<NodeId> = self
+                .tree
+                .live_nodes()
+                .filter(|id| {
+                    let node = self.tree.node(*id);
+                    node.edges.iter().any(|e| {
+                        !self.given_up.contains(&(*id, e.name.clone())) && self.is_problem(*id, e)
+                    })
+                })
+                .collect();
+            if broken.is_empty() {
+                break;
+            }
+            for id in broken {
+                self.enqueue(id);
+            }
+        }
+
+        self.remove_failed_optionals()?;
+        self.tree.prune_unreachable();
+        self.tree.compute_flags();
+        Ok(())
+    }
+
+    fn enqueue(&mut self, id: NodeId) {
+        let node = self.tree.node(id);
+        self.queue.insert((node.depth, self.tree.path(id), id));
+    }
+
+    /// An edge needs work if it resolves to nothing or to something that doesn't satisfy it.
+    /// Optional peer dependencies are fine to leave out.
+    fn is_problem(&self, from: NodeId, edge: &Edge) -> bool {
+        match self.tree.resolve(from, edge) {
+            Some(to) => !self.tree.satisfies_edge(to, edge),
+            None => edge.edge_type != EdgeType::PeerOptional,
+        }
+    }
+
+    fn build_deps(&mut self, id: NodeId) -> Result<(), ResolveError> {
+        let edges = self.tree.node(id).edges.clone();
+        for edge in edges {
+            // placing an earlier dep can replace the node we're working on
+            if self.tree.node(id).removed {
+                return Ok(());
+            }
+            if self.given_up.contains(&(id, edge.name.clone())) || !self.is_problem(id, &edge) {
+                continue;
+            }
+            match self.fetch(id, &edge) {
+                Ok(manifest) => {
+                    self.place_dep(id, &edge, manifest)?;
+                }
+                Err(e) => self.give_up(id, &edge, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn give_up(&mut self, from: NodeId, edge: &Edge, err: ResolveError) {
+        self.given_up.insert((from, edge.name.clone()));
+        if edge.edge_type.is_optional() {
+            self.warnings
+                .push(format!("skipping optional dependency: {}", err));
+        } else {
+            self.failures.push((from, err));
+        }
+    }
+
+    fn fetch(&mut self, from: NodeId, edge: &Edge) -> Result<Rc<VersionManifest>, ResolveError> {
+        let required_by = self.tree.describe(from);
+        let (package_name, spec) =
+            registry_target(edge).ok_or_else(|| ResolveError::UnsupportedSpec {
+                name: edge.name.clone(),
+                spec: edge.raw_spec(),
+                required_by: required_by.clone(),
+            })?;
+        let packument =
+            self.registry
+                .packument(package_name)
+                .ok_or_else(|| ResolveError::PackageNotFound {
+                    name: package_name.to_owned(),
+                    required_by: required_by.clone(),
+                })?;
+        pick_manifest(&packument, &spec).ok_or_else(|| ResolveError::NoMatchingVersion {
+            name: edge.name.clone(),
+            spec: edge.raw_spec(),
+            required_by,
+        })
+    }
+
+    /// Places a dep for the edge, returning the node that now satisfies it, if any.
+    fn place_dep(
+        &mut self,
+        from: NodeId,
+        edge: &Edge,
+        manifest: Rc<VersionManifest>,
+    ) -> Result<Option<NodeId>, ResolveError> {
+        self.placements += 1;
+        if self.placements > MAX_PLACEMENTS {
+            return Err(ResolveError::TooManyNodes);
+        }
+
+        let start = self.tree.start_node(from, edge);
+        // the highest target that works, and the highest that works if we ignore the dep's peers
+        let mut chosen: Option<(NodeId, CanPlace)> = None;
+        let mut chosen_ignoring_peers: Option<(NodeId, CanPlace)> = None;
+        for target in self.tree.ancestry(start) {
+            // a package can't provide a dep for its children that it also needs from its parent
+            let target_has_peer_edge = self
+                .tree
+                .node(target)
+                .edge(&edge.name)
+                .is_some_and(|e| e.edge_type.is_peer());
+            if target != ROOT && target_has_peer_edge {
+                continue;
+            }
+
+            let (with_peers, ignoring_peers) = self.can_place(target, from, edge, &manifest, start);
+            if matches!(ignoring_peers, CanPlace::Conflict) {
+                break;
+            }
+            chosen_ignoring_peers = Some((target, ignoring_peers));
+            if !matches!(with_peers, CanPlace::Conflict) {
+                chosen = Some((target, with_peers));
+            }
+            if matches!(ignoring_peers, CanPlace::Keep(_) | CanPlace::Replace(_)) {
+                break;
+            }
+        }
+
+        let (target, how) = match (chosen, chosen_ignoring_peers) {
+            (Some(c), _) => c,
+            (None, fallback) => {
+                let err = ResolveError::PeerConflict {
+                    name: edge.name.clone(),
+                    version: manifest.version.to_string(),
+                    required_by: self.tree.describe(from),
+                };
+                if self.options.strict_peer_deps || from == ROOT {
+                    return Err(err);
+                }
+                self.warnings.push(format!("{}, installing anyway", err));
+                match fallback {
+                    Some(f) => f,
+                    None => {
+                        self.given_up.insert((from, edge.name.clone()));
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+
+        match how {
+            CanPlace::Keep(existing) => Ok(Some(existing)),
+            CanPlace::Ok => self.add(target, edge, manifest).map(Some),
+            CanPlace::Replace(old) => {
+                self.tree.remove_subtree(old);
+                self.add(target, edge, manifest).map(Some)
+            }
+            CanPlace::Conflict => unreachable!("chose a conflicting target"),
+        }
+    }
+
+    fn add(
+        &mut self,
+        target: NodeId,
+        edge: &Edge,
+        manifest: Rc<VersionManifest>,
+    ) -> Result<NodeId, ResolveError> {
+        let id = self.tree.add_child(target, edge.name.clone(), manifest);
+        self.enqueue(id);
+
+        // the new node may shadow, or replace, what other nodes were using
+        let broken: Vec<NodeId> = self
+            .tree
+            .edges_in(id)
+            .into_iter()
+            .filter(|(_, e)| !self.tree.satisfies_edge(id, e))
+            .map(|(dependent, _)| dependent)
+            .collect();
+        for dependent in broken {
+            self.enqueue(dependent);
+        }
+
+        // peers have to go next to the node, so place them before anything else takes the spot
+        let peer_edges: Vec<Edge> = self
+            .tree
+            .node(id)
+            .edges
+            .iter()
+            .filter(|e| e.edge_type.is_peer())
+            .cloned()
+            .collect();
+        for peer_edge in peer_edges {
+            if self.tree.node(id).removed {
+                break;
+            }
+            if !self.is_problem(id, &peer_edge) {
+                continue;
+            }
+            match self.fetch(id, &peer_edge) {
+                Ok(m) => {
+                    self.place_dep(id, &peer_edge, m)?;
+                }
+                Err(e) => self.give_up(id, &peer_edge, e),
+            }
+        }
+        Ok(id)
+    }
+
+    /// Checks whether the dep can go at `target`, both with and without checking that its peer
+    /// dependencies can go there too.
+    fn can_place(
+        &mut self,
+        target: NodeId,
+        from: NodeId,
+        edge: &Edge,
+        manifest: &VersionManifest,
+        start: NodeId,
+    ) -> (CanPlace, CanPlace) {
+        // if the target has its own dependency on the name, the dep has to work for it too
+        if target != from {
+            if let Some(target_edge) = self.tree.node(target).edge(&edge.name) {
+                if !manifest_satisfies(manifest, target_edge) {
+                    return (CanPlace::Conflict, CanPlace::Conflict);
+                }
+            }
+        }
+
+        let ignoring_peers = match self.tree.node(target).children.get(&edge.name) {
+            Some(&current) => self.can_place_current(target, from, edge, manifest, start, current),
+            None => self.can_place_no_current(target, target != from, &edge.name, manifest),
+        };
+        match ignoring_peers {
+            CanPlace::Ok | CanPlace::Replace(_) if !self.peers_ok(target, manifest) => {
+                (CanPlace::Conflict, ignoring_peers)
+            }
+            _ => (ignoring_peers, ignoring_peers),
+        }
+    }
+
+    fn can_place_current(
+        &self,
+        target: NodeId,
+        from: NodeId,
+        edge: &Edge,
+        manifest: &VersionManifest,
+        start: NodeId,
+        current: NodeId,
+    ) -> CanPlace {
+        let current_manifest = self
+            .tree
+            .node(current)
+            .manifest()
+            .expect("the root isn't anyone's child");
+        let current_ok = self.tree.satisfies_edge(current, edge);
+        if current_manifest.name == manifest.name
+            && current_manifest.version == manifest.version
+            && current_ok
+        {
+            return CanPlace::Keep(current);
+        }
+
+        let is_newer = compare_versions(&manifest.version, &current_manifest.version).is_ge();
+        if is_newer && self.can_replace(current, manifest) {
+            return CanPlace::Replace(current);
+        }
+        if current_ok {
+            return CanPlace::Keep(current);
+        }
+
+        // we could still nest it deeper, so only force a replacement as a last resort
+        if target != start {
+            return CanPlace::Conflict;
+        }
+        if !edge.edge_type.is_peer() && target == from {
+            return CanPlace::Replace(current);
+        }
+        CanPlace::Conflict
+    }
+
+    /// Returns true if everything that uses `current` would be fine with `manifest` instead.
+    fn can_replace(&self, current: NodeId, manifest: &VersionManifest) -> bool {
+        self.tree
+            .edges_in(current)
+            .into_iter()
+            .all(|(_, e)| manifest_satisfies(manifest, e))
+    }
+
+    /// Checks that putting the dep at `target` doesn't shadow a version that the target's other
+    /// descendants are happily using.
+    fn can_place_no_current(
+        &self,
+        target: NodeId,
+        check_shadowing: bool,
+        name: &str,
+        manifest: &VersionManifest,
+    ) -> CanPlace {
+        if !check_shadowing {
+            return CanPlace::Ok;
+        }
+        let shadowed = match self.tree.resolve_from(target, name) {
+            Some(n) => n,
+            None => return CanPlace::Ok,
+        };
+        let breaks_a_descendant = self
+            .tree
+            .edges_in(shadowed)
+            .into_iter()
+            .any(|(dependent, e)| {
+                self.tree.is_descendant_of(dependent, target)
+                    && self.tree.satisfies_edge(shadowed, e)
+                    && !manifest_satisfies(manifest, e)
+            });
+        if breaks_a_descendant {
+            CanPlace::Conflict
+        } else {
+            CanPlace::Ok
+        }
+    }
+
+    /// Checks that the dep's peer dependencies are either already satisfied from `target`, or
+    /// can be placed there alongside it.
+    fn peers_ok(&mut self, target: NodeId, manifest: &VersionManifest) -> bool {
+        let peer_edges: Vec<Edge> = manifest
+            .dependencies
+            .edges(false)
+            .into_iter()
+            .filter(|e| e.edge_type.is_peer() && e.name != manifest.name)
+            .collect();
+
+        for pe in peer_edges {
+            let existing = self.tree.resolve_from(target, &pe.name);
+            match existing {
+                Some(n) if self.tree.satisfies_edge(n, &pe) => continue,
+                None if pe.edge_type == EdgeType::PeerOptional => continue,
+                Some(n) if self.tree.node(target).children.get(&pe.name) == Some(&n) => {
+                    return false;
+                }
+                _ => {}
+            }
+
+            // a new copy of the peer would have to go at the target. If we can't find a version
+            // for it, placing it will report that, so don't hold up the dep because of it
+            let peer_manifest = match self.fetch(target, &pe) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            if let Some(target_edge) = self.tree.node(target).edge(&pe.name) {
+                if !manifest_satisfies(&peer_manifest, target_edge) {
+                    return false;
+                }
+            }
+            if matches!(
+                self.can_place_no_current(target, true, &pe.name, &peer_manifest),
+                CanPlace::Conflict
+            ) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Leaves out the optional dependencies that have a required dependency we couldn't find,
+    /// along with everything that needs them. Fails if a failure isn't under an optional dependency.
+    fn remove_failed_optionals(&mut self) -> Result<(), ResolveError> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        self.tree.compute_flags();
+
+        for (from, err) in std::mem::take(&mut self.failures) {
+            let node = self.tree.node(from);
+            if node.removed {
+                continue;
+            }
+            if from == ROOT || !node.flags.optional {
+                return Err(err);
+            }
+
+            self.warnings
+                .push(format!("skipping optional dependency: {}", err));
+            for n in self.tree.optional_set(from) {
+                if !self.tree.node(n).removed {
+                    self.tree.remove_subtree(n);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+//! A registry backed by the relational tables, showing the registry as it was at a point in time.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use postgres_db::connection::QueryRunner;
+use postgres_db::custom_types::{
+    PackageStateTimePoint, PackageStateType, Semver, VersionStateType,
+};
+use postgres_db::dependencies::{get_dependencies_by_ids, Dependency};
+use postgres_db::diff_log::{query_diff_entry_by_id, DiffLogInstruction};
+use postgres_db::packages::{get_package, maybe_get_package_id_by_name};
+use postgres_db::packument::{PackageOnlyPackument, Spec};
+use postgres_db::versions::{get_versions_by_package_id, Version};
+use semver_spec_serialization::parse_semver;
+use serde_json::Map;
+
+use crate::manifest::{deprecation_message, lock_metadata, Dependencies, VersionManifest};
+use crate::registry::{Packument, Registry};
+
+pub struct DbRegistry<'c, R: QueryRunner> {
+    conn: &'c mut R,
+    at: DateTime<Utc>,
+    cache: HashMap<String, Option<Rc<Packument>>>,
+}
+
+impl<'c, R: QueryRunner> DbRegistry<'c, R> {
+    pub fn new(conn: &'c mut R, at: DateTime<Utc>) -> DbRegistry<'c, R> {
+        DbRegistry {
+            conn,
+            at,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn load_packument(&mut self, name: &str) -> Option<Packument> {
+        let package_id = maybe_get_package_id_by_name(self.conn, name)?;
+        let package = get_package(self.conn, package_id);
+
+        let change_times = change_times(&package.package_state_history);
+        let package_state = state_at(
+            package
+                .package_state_history
+                .iter()
+                .map(|p| (p, p.seq, p.estimated_time)),
+            &change_times,
+            self.at,
+        )?;
+        if package_state.state != PackageStateType::Normal {
+            return None;
+        }
+
+        let mut versions: Vec<Version> = get_versions_by_package_id(self.conn, package_id)
+            .into_iter()
+            .filter(|v| v.created <= self.at)
+            .filter(|v| {
+                let state = state_at(
+                    v.version_state_history
+                        .iter()
+                        .map(|p| (&p.state, p.seq, p.estimated_time)),
+                    &change_times,
+                    self.at,
+                );
+                state.is_none_or(|s| *s == VersionStateType::Normal)
+            })
+            .collect();
+        versions.sort_by_key(|v| v.created);
+
+        // dev dependencies of other packages never get installed, so we don't need them
+        let dep_ids: Vec<i64> = versions
+            .iter()
+            .flat_map(|v| {
+                v.prod_dependencies
+                    .iter()
+                    .chain(&v.optional_dependencies)
+                    .chain(&v.peer_dependencies)
+            })
+            .copied()
+            .collect();
+        let deps: HashMap<i64, Dependency> = get_dependencies_by_ids(self.conn, &dep_ids)
+            .into_iter()
+            .map(|d| (d.id, d))
+            .collect();
+
+        let dist_tags = self.dist_tags_of(package_state);
+        let manifests = versions
+            .iter()
+            .map(|v| Rc::new(version_manifest(name, v, &deps)))
+            .collect();
+        Some(Packument {
+            name: name.to_owned(),
+            dist_tags,
+            versions: manifests,
+        })
+    }
+
+    /// The dist-tags of the package as of the diff entry of its state point.
+    fn dist_tags_of(&mut self, state: &PackageStateTimePoint) -> BTreeMap<String, Semver> {
+        let entry = query_diff_entry_by_id(state.diff_entry_id, self.conn);
+        let packument = match entry.map(|e| e.instr) {
+            Some(DiffLogInstruction::CreatePackage(p) | DiffLogInstruction::UpdatePackage(p)) => p,
+            _ => return BTreeMap::new(),
+        };
+        let PackageOnlyPackument::Normal {
+            latest,
+            other_dist_tags,
+            ..
+        } = packument
+        else {
+            return BTreeMap::new();
+        };
+
+        other_dist_tags
+            .into_iter()
+            .filter_map(|(tag, v)| Some((tag, parse_semver(v.as_str()?).ok()?)))
+            .chain(latest.map(|v| ("latest".to_owned(), v)))
+            .collect()
+    }
+}
+
+impl<'c, R: QueryRunner> Registry for DbRegistry<'c, R> {
+    fn packument(&mut self, name: &str) -> Option<Rc<Packument>> {
+        if let Some(cached) = self.cache.get(name) {
+            return cached.clone();
+        }
+        let packument = self.load_packument(name).map(Rc::new);
+        self.cache.insert(name.to_owned(), packument.clone());
+        packument
+    }
+}
+
+/// The estimated times of the package's changes, by seq.
+fn change_times(history: &[PackageStateTimePoint]) -> BTreeMap<i64, DateTime<Utc>> {
+    history
+        .iter()
+        .filter_map(|p| Some((p.seq, p.estimated_time?)))
+        .collect()
+}
+
+/// The state as of `at` is the last one in seq order which is no later than `at`. States without
+/// an estimated time (e.g. deletions) are placed at the time of the latest change to the package
+/// at or before their seq, or before everything if there is none.
+fn state_at<S>(
+    history: impl Iterator<Item = (S, i64, Option<DateTime<Utc>>)>,
+    change_times: &BTreeMap<i64, DateTime<Utc>>,
+    at: DateTime<Utc>,
+) -> Option<S> {
+    history
+        .filter(|(_, seq, time)| {
+            let time = time.or_else(|| change_times.range(..=*seq).next_back().map(|(_, t)| *t));
+            time.is_none_or(|t| t <= at)
+        })
+        .last()
+        .map(|(state, _, _)| state)
+}
+
+fn version_manifest(name: &str, v: &Version, deps: &HashMap<i64, Dependency>) -> VersionManifest {
+    let specs = |ids: &[i64]| -> Vec<(String, Spec)> {
+        ids.iter()
+            .map(|id| {
+                let d = deps
+                    .get(id)
+                    .unwrap_or_else(|| panic!("Missing dependency row {}", id));
+                (
+                    d.dst_package_name.clone(),
+                    Spec {
+                        raw: d.raw_spec.clone(),
+                        parsed: d.spec.clone(),
+                    },
+                )
+            })
+            .collect()
+    };
+
+    let empty = Map::new();
+    let extra_metadata = v.extra_metadata.as_object().unwrap_or(&empty);
+
+    let mut dependencies = Dependencies {
+        dependencies: specs(&v.prod_dependencies),
+        optional_dependencies: specs(&v.optional_dependencies),
+        peer_dependencies: specs(&v.peer_dependencies),
+        ..Default::default()
+    };
+    dependencies.read_metadata(extra_metadata);
+
+    VersionManifest {
+        name: name.to_owned(),
+        version: v.semver.clone(),
+        tarball_url: v.tarball_url.clone(),
+        deprecated: deprecation_message(extra_metadata),
+        dependencies,
+        lock_metadata: lock_metadata(extra_metadata),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn t(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 1, day, 0, 0, 0).unwrap()
+    }
+
+    fn at<S: Copy>(
+        history: &[(S, i64, Option<DateTime<Utc>>)],
+        change_times: &BTreeMap<i64, DateTime<Utc>>,
+        day: u32,
+    ) -> Option<S> {
+        state_at(history.iter().copied(), change_times, t(day))
+    }
+
+    #[test]
+    fn test_state_at_timed_states() {
+        let history = [("a", 1, Some(t(2))), ("b", 2, Some(t(4)))];
+        let change_times = BTreeMap::from([(1, t(2)), (2, t(4))]);
+
+        assert_eq!(at(&history, &change_times, 1), None);
+        assert_eq!(at(&history, &change_times, 2), Some("a"));
+        assert_eq!(at(&history, &change_times, 3), Some("a"));
+        assert_eq!(at(&history, &change_times, 5), Some("b"));
+    }
+
+    #[test]
+    fn test_state_at_untimed_state_follows_previous_change() {
+        let history = [("normal", 1, Some(t(2))), ("deleted", 2, None)];
+        let change_times = BTreeMap::from([(1, t(2))]);
+
+        assert_eq!(at(&history, &change_times, 1), None);
+        assert_eq!(at(&history, &change_times, 2), Some("deleted"));
+        assert_eq!(at(&history, &change_times, 5), Some("deleted"));
+    }
+
+    #[test]
+    fn test_state_at_untimed_state_uses_change_at_same_seq() {
+        let history = [("normal", 1, Some(t(2))), ("deleted", 5, None)];
+        let change_times = BTreeMap::from([(1, t(2)), (5, t(4))]);
+
+        assert_eq!(at(&history, &change_times, 3), Some("normal"));
+        assert_eq!(at(&history, &change_times, 4), Some("deleted"));
+    }
+
+    #[test]
+    fn test_state_at_only_untimed_state() {
+        let history = [("missing", 1, None)];
+        let change_times = BTreeMap::new();
+
+        assert_eq!(at(&history, &change_times, 1), Some("missing"));
+    }
+}
@@ -0,0 +1,167 @@
+//! A dependency resolver that reproduces what `npm install` (i.e. arborist) would install,
+//! without running npm. Packages come from a [`Registry`], which is usually a [`DbRegistry`]
+//! showing the relational tables as they were at some point in time.
+//!
+//! It follows arborist's hoisting, peer dependency, optional dependency and dist-tag rules,
+//! and produces a `package-lock.json`. Known differences from npm:
+//! - bundled dependencies are left out, since they come from inside tarballs,
+//! - lockfile entries have no `integrity`, since we don't store it,
+//! - `engines`, `os` and `cpu` are never checked, so optional dependencies are only skipped
+//!   if they can't be resolved,
+//! - git, tarball, file and directory specs fail with [`ResolveError::UnsupportedSpec`].
+
+use std::fmt;
+use std::rc::Rc;
+
+use postgres_db::custom_types::Semver;
+use serde_json::Value;
+
+mod build;
+pub mod db_registry;
+mod lockfile;
+pub mod manifest;
+mod pick;
+pub mod registry;
+mod tree;
+
+pub use db_registry::DbRegistry;
+pub use manifest::{RootManifest, VersionManifest};
+pub use registry::{Packument, Registry};
+pub use tree::DepFlags;
+
+use build::Builder;
+use tree::{Tree, ROOT};
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// Fail on peer dependency conflicts instead of installing anyway, like `--strict-peer-deps`.
+    pub strict_peer_deps: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    PackageNotFound {
+        name: String,
+        required_by: String,
+    },
+    NoMatchingVersion {
+        name: String,
+        spec: String,
+        required_by: String,
+    },
+    UnsupportedSpec {
+        name: String,
+        spec: String,
+        required_by: String,
+    },
+    PeerConflict {
+        name: String,
+        version: String,
+        required_by: String,
+    },
+    TooManyNodes,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::PackageNotFound { name, required_by } => {
+                write!(
+                    f,
+                    "package {} (required by {}) not found",
+                    name, required_by
+                )
+            }
+            ResolveError::NoMatchingVersion {
+                name,
+                spec,
+                required_by,
+            } => write!(
+                f,
+                "no version of {} matches {} (required by {})",
+                name, spec, required_by
+            ),
+            ResolveError::UnsupportedSpec {
+                name,
+                spec,
+                required_by,
+            } => write!(
+                f,
+                "unsupported spec {} for {} (required by {})",
+                spec, name, required_by
+            ),
+            ResolveError::PeerConflict {
+                name,
+                version,
+                required_by,
+            } => write!(
+                f,
+                "could not place {}@{} (required by {}) without conflicting peer dependencies",
+                name, version, required_by
+            ),
+            ResolveError::TooManyNodes => write!(f, "gave up on a tree that kept growing"),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// A package in the resolved tree.
+#[derive(Debug, Clone)]
+pub struct ResolvedPackage {
+    /// Where it is installed, e.g. `node_modules/a/node_modules/b`.
+    pub path: String,
+    /// The name of the package in the registry, which differs from the last part of the path
+    /// for aliases.
+    pub name: String,
+    pub version: Semver,
+    pub flags: DepFlags,
+}
+
+pub struct Resolution {
+    tree: Tree,
+    /// Problems that npm would also only warn about, e.g. skipped optional dependencies.
+    pub warnings: Vec<String>,
+}
+
+impl Resolution {
+    /// The installed packages, sorted by path.
+    pub fn packages(&self) -> Vec<ResolvedPackage> {
+        let mut packages: Vec<ResolvedPackage> = self
+            .tree
+            .live_nodes()
+            .filter(|id| *id != ROOT)
+            .map(|id| {
+                let node = self.tree.node(id);
+                let m = node.manifest().expect("only the root has no manifest");
+                ResolvedPackage {
+                    path: self.tree.path(id),
+                    name: m.name.clone(),
+                    version: m.version.clone(),
+                    flags: node.flags,
+                }
+            })
+            .collect();
+        packages.sort_by(|a, b| a.path.cmp(&b.path));
+        packages
+    }
+
+    /// The tree as a version 3 `package-lock.json`.
+    pub fn to_package_lock(&self) -> Value {
+        lockfile::package_lock(&self.tree)
+    }
+}
+
+/// Resolves the dependencies of `root`, including its dev dependencies, like `npm install` would.
+pub fn resolve<R: Registry>(
+    root: RootManifest,
+    registry: &mut R,
+    options: &ResolveOptions,
+) -> Result<Resolution, ResolveError> {
+    let mut builder = Builder::new(Tree::new(Rc::new(root)), registry, options);
+    builder.build()?;
+    Ok(Resolution {
+        tree: builder.tree,
+        warnings: builder.warnings,
+    })
+}
@@ -0,0 +1,108 @@
+//! Writing a resolved tree as a `package-lock.json` (lockfile version 3).
+
+use serde_json::{json, Map, Value};
+
+use crate::manifest::{raw_spec_string, Dependencies};
+use crate::tree::{Package, Tree, ROOT};
+
+pub(crate) fn package_lock(tree: &Tree) -> Value {
+    let root = match &tree.node(ROOT).package {
+        Package::Root(root) => root,
+        Package::Version(_) => unreachable!("the root node is always the root package"),
+    };
+
+    let mut lock = Map::new();
+    if let Some(name) = &root.name {
+        lock.insert("name".to_owned(), json!(name));
+    }
+    if let Some(version) = &root.version {
+        lock.insert("version".to_owned(), json!(version));
+    }
+    lock.insert("lockfileVersion".to_owned(), json!(3));
+    lock.insert("requires".to_owned(), json!(true));
+
+    let mut root_entry = Map::new();
+    if let Some(name) = &root.name {
+        root_entry.insert("name".to_owned(), json!(name));
+    }
+    if let Some(version) = &root.version {
+        root_entry.insert("version".to_owned(), json!(version));
+    }
+    insert_dependencies(&mut root_entry, &root.dependencies, true);
+
+    let mut packages = Map::new();
+    packages.insert("".to_owned(), Value::Object(root_entry));
+
+    let mut nodes: Vec<(String, usize)> = tree
+        .live_nodes()
+        .filter(|id| *id != ROOT)
+        .map(|id| (tree.path(id), id))
+        .collect();
+    nodes.sort();
+
+    for (path, id) in nodes {
+        let node = tree.node(id);
+        let m = node.manifest().expect("only the root has no manifest");
+
+        let mut entry = Map::new();
+        if node.name != m.name {
+            entry.insert("name".to_owned(), json!(m.name));
+        }
+        entry.insert("version".to_owned(), json!(m.version.to_string()));
+        entry.insert("resolved".to_owned(), json!(m.tarball_url));
+
+        let flags = node.flags;
+        if flags.dev {
+            entry.insert("dev".to_owned(), json!(true));
+        }
+        if flags.optional {
+            entry.insert("optional".to_owned(), json!(true));
+        }
+        if flags.dev_optional && !flags.dev && !flags.optional {
+            entry.insert("devOptional".to_owned(), json!(true));
+        }
+        if flags.peer {
+            entry.insert("peer".to_owned(), json!(true));
+        }
+        if let Some(deprecated) = &m.deprecated {
+            entry.insert("deprecated".to_owned(), json!(deprecated));
+        }
+
+        insert_dependencies(&mut entry, &m.dependencies, false);
+        for (k, v) in &m.lock_metadata {
+            entry.insert(k.clone(), v.clone());
+        }
+        packages.insert(path, Value::Object(entry));
+    }
+
+    lock.insert("packages".to_owned(), Value::Object(packages));
+    Value::Object(lock)
+}
+
+fn insert_dependencies(entry: &mut Map<String, Value>, deps: &Dependencies, include_dev: bool) {
+    let mut insert = |key: &str, specs: &[(String, postgres_db::packument::Spec)]| {
+        if !specs.is_empty() {
+            let specs = specs
+                .iter()
+                .map(|(name, spec)| (name.clone(), json!(raw_spec_string(&spec.raw))))
+                .collect();
+            entry.insert(key.to_owned(), Value::Object(specs));
+        }
+    };
+
+    insert("dependencies", &deps.dependencies);
+    if include_dev {
+        insert("devDependencies", &deps.dev_dependencies);
+    }
+    insert("optionalDependencies", &deps.optional_dependencies);
+    insert("peerDependencies", &deps.peer_dependencies);
+
+    if !deps.optional_peers.is_empty() {
+        let meta = deps
+            .optional_peers
+            .iter()
+            .map(|name| (name.clone(), json!({ "optional": true })))
+            .collect();
+        entry.insert("peerDependenciesMeta".to_owned(), Value::Object(meta));
+    }
+}
@@ -0,0 +1,251 @@
+//! The parts of `package.json` files that matter for resolving dependencies.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use postgres_db::custom_types::{ParsedSpec, Semver};
+use postgres_db::packument::Spec;
+use serde_json::{Map, Value};
+
+/// Fields that npm copies from a package's `package.json` into its lockfile entry.
+const LOCK_METADATA_KEYS: [&str; 6] = ["license", "engines", "os", "cpu", "bin", "funding"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeType {
+    Prod,
+    Dev,
+    Optional,
+    Peer,
+    PeerOptional,
+}
+
+impl EdgeType {
+    pub fn is_peer(self) -> bool {
+        matches!(self, EdgeType::Peer | EdgeType::PeerOptional)
+    }
+
+    pub fn is_optional(self) -> bool {
+        matches!(self, EdgeType::Optional | EdgeType::PeerOptional)
+    }
+}
+
+/// A dependency of one package on another, like arborist's `Edge`.
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub name: String,
+    pub spec: Spec,
+    pub edge_type: EdgeType,
+}
+
+impl Edge {
+    /// The spec as written in the `package.json`, for error messages and lockfiles.
+    pub fn raw_spec(&self) -> String {
+        raw_spec_string(&self.spec.raw)
+    }
+}
+
+pub(crate) fn raw_spec_string(raw: &Value) -> String {
+    match raw {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Dependencies {
+    pub dependencies: Vec<(String, Spec)>,
+    pub dev_dependencies: Vec<(String, Spec)>,
+    pub optional_dependencies: Vec<(String, Spec)>,
+    pub peer_dependencies: Vec<(String, Spec)>,
+    /// The peer dependencies marked as optional in `peerDependenciesMeta`.
+    pub optional_peers: BTreeSet<String>,
+    /// The `bundleDependencies`, which ship inside the tarball instead of coming from the registry.
+    pub bundled: BTreeSet<String>,
+}
+
+impl Dependencies {
+    /// Reads the dependency fields of a `package.json`. Specs that aren't strings are `Invalid`.
+    pub fn from_package_json(package_json: &Map<String, Value>) -> Dependencies {
+        let read_deps = |key: &str| -> Vec<(String, Spec)> {
+            match package_json.get(key) {
+                Some(Value::Object(deps)) => deps
+                    .iter()
+                    .map(|(name, raw)| {
+                        let parsed = match raw {
+                            Value::String(s) => semver_spec_serialization::parse_spec_cached(s),
+                            other => ParsedSpec::Invalid(format!("non-string spec: {}", other)),
+                        };
+                        (
+                            name.clone(),
+                            Spec {
+                                raw: raw.clone(),
+                                parsed,
+                            },
+                        )
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        };
+
+        let mut deps = Dependencies {
+            dependencies: read_deps("dependencies"),
+            dev_dependencies: read_deps("devDependencies"),
+            optional_dependencies: read_deps("optionalDependencies"),
+            peer_dependencies: read_deps("peerDependencies"),
+            ..Default::default()
+        };
+        deps.read_metadata(package_json);
+        deps
+    }
+
+    /// Reads `peerDependenciesMeta` and `bundleDependencies`.
+    pub fn read_metadata(&mut self, package_json: &Map<String, Value>) {
+        if let Some(Value::Object(meta)) = package_json.get("peerDependenciesMeta") {
+            self.optional_peers = meta
+                .iter()
+                .filter(|(_, m)| m.get("optional") == Some(&Value::Bool(true)))
+                .map(|(name, _)| name.clone())
+                .collect();
+        }
+
+        let bundled = package_json
+            .get("bundleDependencies")
+            .or_else(|| package_json.get("bundledDependencies"));
+        self.bundled = match bundled {
+            Some(Value::Array(names)) => names
+                .iter()
+                .filter_map(|n| n.as_str().map(|n| n.to_owned()))
+                .collect(),
+            // `true` means bundling all the dependencies
+            Some(Value::Bool(true)) => self
+                .dependencies
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+    }
+
+    /// The edges out of a package, sorted by name. Like arborist, an edge overrides earlier
+    /// ones with the same name, in the order peer, prod, optional, and then dev (only for the root).
+    pub(crate) fn edges(&self, include_dev: bool) -> Vec<Edge> {
+        let mut edges: BTreeMap<String, Edge> = BTreeMap::new();
+        let mut add = |deps: &[(String, Spec)], edge_type: &dyn Fn(&str) -> EdgeType| {
+            for (name, spec) in deps {
+                edges.insert(
+                    name.clone(),
+                    Edge {
+                        name: name.clone(),
+                        spec: spec.clone(),
+                        edge_type: edge_type(name),
+                    },
+                );
+            }
+        };
+
+        add(&self.peer_dependencies, &|name| {
+            if self.optional_peers.contains(name) {
+                EdgeType::PeerOptional
+            } else {
+                EdgeType::Peer
+            }
+        });
+        add(&self.dependencies, &|_| EdgeType::Prod);
+        add(&self.optional_dependencies, &|_| EdgeType::Optional);
+        if include_dev {
+            add(&self.dev_dependencies, &|_| EdgeType::Dev);
+        }
+
+        edges.into_values().collect()
+    }
+}
+
+/// A version of a package in the registry.
+#[derive(Debug, Clone)]
+pub struct VersionManifest {
+    pub name: String,
+    pub version: Semver,
+    pub tarball_url: String,
+    pub deprecated: Option<String>,
+    pub dependencies: Dependencies,
+    /// Fields that npm copies into lockfile entries, e.g. `license`, `engines` and `bin`.
+    pub lock_metadata: Map<String, Value>,
+}
+
+impl VersionManifest {
+    /// Reads a version from its entry in a packument. Returns `None` if it doesn't have a
+    /// name, a valid version and a tarball url.
+    pub fn from_package_json(package_json: &Map<String, Value>) -> Option<VersionManifest> {
+        let name = package_json.get("name")?.as_str()?.to_owned();
+        let version = package_json.get("version")?.as_str()?;
+        let version = semver_spec_serialization::parse_semver(version).ok()?;
+        let tarball_url = package_json
+            .get("dist")?
+            .get("tarball")?
+            .as_str()?
+            .to_owned();
+
+        Some(VersionManifest {
+            name,
+            version,
+            tarball_url,
+            deprecated: deprecation_message(package_json),
+            dependencies: Dependencies::from_package_json(package_json),
+            lock_metadata: lock_metadata(package_json),
+        })
+    }
+}
+
+/// Reads the `deprecated` field. npm ignores deprecation messages that aren't non-empty strings.
+pub(crate) fn deprecation_message(package_json: &Map<String, Value>) -> Option<String> {
+    match package_json.get("deprecated") {
+        Some(Value::String(s)) if !s.is_empty() => Some(s.clone()),
+        _ => None,
+    }
+}
+
+pub(crate) fn lock_metadata(package_json: &Map<String, Value>) -> Map<String, Value> {
+    let mut meta: Map<String, Value> = LOCK_METADATA_KEYS
+        .iter()
+        .filter_map(|k| package_json.get(*k).map(|v| (k.to_string(), v.clone())))
+        .collect();
+
+    let has_install_script = package_json
+        .get("scripts")
+        .and_then(|s| s.as_object())
+        .is_some_and(|scripts| {
+            ["preinstall", "install", "postinstall"]
+                .iter()
+                .any(|k| scripts.contains_key(*k))
+        });
+    if has_install_script {
+        meta.insert("hasInstallScript".to_owned(), Value::Bool(true));
+    }
+    meta
+}
+
+/// The package being installed, i.e. the `package.json` that `npm install` is run in.
+#[derive(Debug, Clone)]
+pub struct RootManifest {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub dependencies: Dependencies,
+}
+
+impl RootManifest {
+    pub fn from_package_json(package_json: &Map<String, Value>) -> RootManifest {
+        let get_str = |k: &str| {
+            package_json
+                .get(k)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_owned())
+        };
+
+        RootManifest {
+            name: get_str("name"),
+            version: get_str("version"),
+            dependencies: Dependencies::from_package_json(package_json),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Choosing a version of a package for a spec, like npm-pick-manifest.
+
+use std::rc::Rc;
+
+use postgres_db::custom_types::{
+    AliasSubspec, ParsedSpec, Semver, VersionComparator, VersionConstraint,
+};
+use semver_spec_serialization::{compare_versions, satisfies};
+
+use crate::manifest::{Edge, VersionManifest};
+use crate::registry::Packument;
+
+/// The part of a spec that picks a version from the registry.
+pub(crate) enum RegistrySpec<'a> {
+    Range(&'a VersionConstraint),
+    Tag(&'a str),
+}
+
+/// Returns the name of the package that `edge` points to in the registry (which differs from
+/// `edge.name` for aliases) and what to pick from it, or `None` if it isn't a registry spec.
+pub(crate) fn registry_target(edge: &Edge) -> Option<(&str, RegistrySpec<'_>)> {
+    match &edge.spec.parsed {
+        ParsedSpec::Range(r) => Some((&edge.name, RegistrySpec::Range(r))),
+        ParsedSpec::Tag(t) => Some((&edge.name, RegistrySpec::Tag(t))),
+        ParsedSpec::Alias(name, _, AliasSubspec::Range(r)) => Some((name, RegistrySpec::Range(r))),
+        ParsedSpec::Alias(name, _, AliasSubspec::Tag(t)) => Some((name, RegistrySpec::Tag(t))),
+        ParsedSpec::Git(_)
+        | ParsedSpec::Remote(_)
+        | ParsedSpec::File(_)
+        | ParsedSpec::Directory(_)
+        | ParsedSpec::Invalid(_) => None,
+    }
+}
+
+/// npm treats `*` specially: it matches anything, even prereleases.
+pub(crate) fn is_star(range: &VersionConstraint) -> bool {
+    range.0.len() == 1 && range.0[0].iter().all(|c| *c == VersionComparator::Any)
+}
+
+/// Returns true if version `v` of the right package is valid for `spec`, like arborist's `depValid`.
+pub(crate) fn spec_accepts(spec: &RegistrySpec, v: &Semver) -> bool {
+    match spec {
+        // any version is fine for a tag, as long as it's there
+        RegistrySpec::Tag(_) => true,
+        RegistrySpec::Range(r) => is_star(r) || satisfies(r, v, false),
+    }
+}
+
+/// Picks the version to install for `spec`. If the `latest` tag satisfies a range, it wins.
+/// Otherwise, the greatest satisfying version that isn't deprecated wins.
+pub(crate) fn pick_manifest(
+    packument: &Packument,
+    spec: &RegistrySpec,
+) -> Option<Rc<VersionManifest>> {
+    let range = match spec {
+        RegistrySpec::Tag(tag) => {
+            let v = packument.dist_tags.get(*tag)?;
+            return packument.get_version(v).cloned();
+        }
+        RegistrySpec::Range(range) => range,
+    };
+
+    let latest = packument
+        .dist_tags
+        .get("latest")
+        .and_then(|v| packument.get_version(v));
+    if let Some(latest) = latest {
+        if is_star(range) || satisfies(range, &latest.version, false) {
+            return Some(latest.clone());
+        }
+    }
+
+    let mut best: Option<&Rc<VersionManifest>> = None;
+    for m in &packument.versions {
+        if !satisfies(range, &m.version, false) {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some(b) => match (m.deprecated.is_none(), b.deprecated.is_none()) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => compare_versions(&m.version, &b.version).is_gt(),
+            },
+        };
+        if better {
+            best = Some(m);
+        }
+    }
+    best.cloned()
+}
@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use postgres_db::custom_types::Semver;
+use semver_spec_serialization::compare_versions;
+
+use crate::manifest::VersionManifest;
+
+/// All the versions of a package that are visible to a resolve.
+#[derive(Debug, Clone)]
+pub struct Packument {
+    pub name: String,
+    pub dist_tags: BTreeMap<String, Semver>,
+    pub versions: Vec<Rc<VersionManifest>>,
+}
+
+impl Packument {
+    /// Makes a packument whose `latest` tag is the greatest version without a prerelease.
+    pub fn new(name: String, versions: Vec<VersionManifest>) -> Packument {
+        let latest = versions
+            .iter()
+            .filter(|v| v.version.prerelease.is_empty())
+            .max_by(|a, b| compare_versions(&a.version, &b.version))
+            .map(|v| v.version.clone());
+
+        Packument {
+            name,
+            dist_tags: latest
+                .into_iter()
+                .map(|v| ("latest".to_owned(), v))
+                .collect(),
+            versions: versions.into_iter().map(Rc::new).collect(),
+        }
+    }
+
+    pub fn get_version(&self, v: &Semver) -> Option<&Rc<VersionManifest>> {
+        self.versions.iter().find(|m| &m.version == v)
+    }
+}
+
+/// Where the resolver gets packuments from. Implementations decide what point in time is
+/// being resolved, and should only return versions that existed then.
+pub trait Registry {
+    /// Returns `None` if the package doesn't exist.
+    fn packument(&mut self, name: &str) -> Option<Rc<Packument>>;
+}
+
+impl Registry for HashMap<String, Rc<Packument>> {
+    fn packument(&mut self, name: &str) -> Option<Rc<Packument>> {
+        self.get(name).cloned()
+    }
+}
@@ -0,0 +1,339 @@
+//! The `node_modules` tree that the resolver builds, like arborist's `Node`s and `Edge`s.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::manifest::{Edge, EdgeType, RootManifest, VersionManifest};
+use crate::pick::{registry_target, spec_accepts};
+
+pub(crate) type NodeId = usize;
+
+pub(crate) const ROOT: NodeId = 0;
+
+pub(crate) enum Package {
+    Root(Rc<RootManifest>),
+    Version(Rc<VersionManifest>),
+}
+
+/// How a package is needed, as in the `dev`, `optional`, `devOptional` and `peer` fields of
+/// lockfile entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepFlags {
+    /// Only needed by dev dependencies of the root.
+    pub dev: bool,
+    /// Only needed by optional dependencies.
+    pub optional: bool,
+    /// Only needed by dev or optional dependencies (npm only writes this if neither of the above is set).
+    pub dev_optional: bool,
+    /// Only needed by peer dependencies.
+    pub peer: bool,
+}
+
+impl DepFlags {
+    const ALL: DepFlags = DepFlags {
+        dev: true,
+        optional: true,
+        dev_optional: true,
+        peer: true,
+    };
+    const NONE: DepFlags = DepFlags {
+        dev: false,
+        optional: false,
+        dev_optional: false,
+        peer: false,
+    };
+}
+
+pub(crate) struct Node {
+    /// The name it is installed under, which differs from the package name for aliases.
+    pub(crate) name: String,
+    pub(crate) package: Package,
+    /// Sorted by name, with at most one edge per name.
+    pub(crate) edges: Vec<Edge>,
+    pub(crate) parent: Option<NodeId>,
+    pub(crate) children: BTreeMap<String, NodeId>,
+    pub(crate) depth: usize,
+    pub(crate) removed: bool,
+    pub(crate) flags: DepFlags,
+}
+
+impl Node {
+    pub(crate) fn manifest(&self) -> Option<&Rc<VersionManifest>> {
+        match &self.package {
+            Package::Root(_) => None,
+            Package::Version(m) => Some(m),
+        }
+    }
+
+    pub(crate) fn edge(&self, name: &str) -> Option<&Edge> {
+        self.edges
+            .binary_search_by(|e| e.name.as_str().cmp(name))
+            .ok()
+            .map(|i| &self.edges[i])
+    }
+}
+
+/// Returns true if `m` is a valid target for `edge`.
+pub(crate) fn manifest_satisfies(m: &VersionManifest, edge: &Edge) -> bool {
+    match registry_target(edge) {
+        Some((package_name, spec)) => m.name == package_name && spec_accepts(&spec, &m.version),
+        None => false,
+    }
+}
+
+pub(crate) struct Tree {
+    nodes: Vec<Node>,
+    /// For each name, the nodes with an edge on that name, so that we can find the edges into a node.
+    dependents: HashMap<String, Vec<NodeId>>,
+}
+
+impl Tree {
+    pub(crate) fn new(root: Rc<RootManifest>) -> Tree {
+        let edges = root.dependencies.edges(true);
+        let mut tree = Tree {
+            nodes: vec![],
+            dependents: HashMap::new(),
+        };
+        tree.push_node(Node {
+            name: root.name.clone().unwrap_or_default(),
+            package: Package::Root(root),
+            edges,
+            parent: None,
+            children: BTreeMap::new(),
+            depth: 0,
+            removed: false,
+            flags: DepFlags::NONE,
+        });
+        tree
+    }
+
+    fn push_node(&mut self, node: Node) -> NodeId {
+        let id = self.nodes.len();
+        for e in &node.edges {
+            self.dependents.entry(e.name.clone()).or_default().push(id);
+        }
+        self.nodes.push(node);
+        id
+    }
+
+    pub(crate) fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub(crate) fn live_nodes(&self) -> impl Iterator<Item = NodeId> + '_ {
+        (0..self.nodes.len()).filter(|id| !self.nodes[*id].removed)
+    }
+
+    pub(crate) fn add_child(
+        &mut self,
+        parent: NodeId,
+        name: String,
+        manifest: Rc<VersionManifest>,
+    ) -> NodeId {
+        // bundled dependencies come inside the tarball, so we don't install them
+        let edges = manifest
+            .dependencies
+            .edges(false)
+            .into_iter()
+            .filter(|e| !manifest.dependencies.bundled.contains(&e.name))
+            .collect();
+
+        let id = self.push_node(Node {
+            name: name.clone(),
+            package: Package::Version(manifest),
+            edges,
+            parent: Some(parent),
+            children: BTreeMap::new(),
+            depth: self.nodes[parent].depth + 1,
+            removed: false,
+            flags: DepFlags::ALL,
+        });
+        let old = self.nodes[parent].children.insert(name, id);
+        assert!(old.is_none(), "adding a child over an existing one");
+        id
+    }
+
+    /// Removes a node, along with everything nested inside it.
+    pub(crate) fn remove_subtree(&mut self, id: NodeId) {
+        assert_ne!(id, ROOT, "can't remove the root");
+        if let Some(parent) = self.nodes[id].parent {
+            let name = self.nodes[id].name.clone();
+            if self.nodes[parent].children.get(&name) == Some(&id) {
+                self.nodes[parent].children.remove(&name);
+            }
+        }
+
+        let mut stack = vec![id];
+        while let Some(n) = stack.pop() {
+            self.nodes[n].removed = true;
+            stack.extend(self.nodes[n].children.values());
+        }
+    }
+
+    /// The node itself, then its parent, and so on up to the root.
+    pub(crate) fn ancestry(&self, id: NodeId) -> Vec<NodeId> {
+        let mut out = vec![id];
+        let mut cur = id;
+        while let Some(p) = self.nodes[cur].parent {
+            out.push(p);
+            cur = p;
+        }
+        out
+    }
+
+    pub(crate) fn is_descendant_of(&self, id: NodeId, ancestor: NodeId) -> bool {
+        self.ancestry(id).contains(&ancestor)
+    }
+
+    /// Where resolving an edge starts: peer dependencies are resolved from the parent,
+    /// so that they are shared with the package's siblings.
+    pub(crate) fn start_node(&self, from: NodeId, edge: &Edge) -> NodeId {
+        match self.nodes[from].parent {
+            Some(p) if edge.edge_type.is_peer() => p,
+            _ => from,
+        }
+    }
+
+    /// Finds the node that `name` resolves to from `start`, like node's `require` would.
+    pub(crate) fn resolve_from(&self, start: NodeId, name: &str) -> Option<NodeId> {
+        self.ancestry(start)
+            .into_iter()
+            .find_map(|n| self.nodes[n].children.get(name).copied())
+    }
+
+    pub(crate) fn resolve(&self, from: NodeId, edge: &Edge) -> Option<NodeId> {
+        self.resolve_from(self.start_node(from, edge), &edge.name)
+    }
+
+    pub(crate) fn satisfies_edge(&self, id: NodeId, edge: &Edge) -> bool {
+        self.nodes[id]
+            .manifest()
+            .is_some_and(|m| manifest_satisfies(m, edge))
+    }
+
+    /// The live edges that resolve to `id`.
+    pub(crate) fn edges_in(&self, id: NodeId) -> Vec<(NodeId, &Edge)> {
+        let name = &self.nodes[id].name;
+        self.dependents
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|d| !self.nodes[**d].removed)
+            .filter_map(|d| {
+                let e = self.nodes[*d].edge(name)?;
+                (self.resolve(*d, e) == Some(id)).then_some((*d, e))
+            })
+            .collect()
+    }
+
+    /// The node's location, e.g. `node_modules/a/node_modules/b`. The root is `""`.
+    pub(crate) fn path(&self, id: NodeId) -> String {
+        let mut names: Vec<&str> = self
+            .ancestry(id)
+            .into_iter()
+            .filter(|n| *n != ROOT)
+            .map(|n| self.nodes[n].name.as_str())
+            .collect();
+        names.reverse();
+        names
+            .iter()
+            .map(|n| format!("node_modules/{}", n))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Describes a node for error messages.
+    pub(crate) fn describe(&self, id: NodeId) -> String {
+        match self.nodes[id].manifest() {
+            None => "the root package".to_owned(),
+            Some(m) => format!("{}@{} (at {})", m.name, m.version, self.path(id)),
+        }
+    }
+
+    /// Removes the nodes that nothing depends on, i.e. that npm would consider extraneous.
+    pub(crate) fn prune_unreachable(&mut self) {
+        let mut reached = HashSet::from([ROOT]);
+        let mut stack = vec![ROOT];
+        while let Some(n) = stack.pop() {
+            for e in &self.nodes[n].edges {
+                if let Some(to) = self.resolve(n, e) {
+                    if reached.insert(to) {
+                        stack.push(to);
+                    }
+                }
+            }
+        }
+
+        let unreached: Vec<NodeId> = self.live_nodes().filter(|n| !reached.contains(n)).collect();
+        for n in unreached {
+            if !self.nodes[n].removed {
+                self.remove_subtree(n);
+            }
+        }
+    }
+
+    /// The nodes that have to be removed along with an optional node whose install failed:
+    /// the node itself, and everything that depends on it without an optional edge.
+    pub(crate) fn optional_set(&self, id: NodeId) -> Vec<NodeId> {
+        let mut set = vec![id];
+        let mut i = 0;
+        while i < set.len() {
+            for (from, e) in self.edges_in(set[i]) {
+                if !e.edge_type.is_optional() && from != ROOT && !set.contains(&from) {
+                    set.push(from);
+                }
+            }
+            i += 1;
+        }
+        set
+    }
+
+    /// Sets the flags of every node, like arborist's `calcDepFlags`. Every flag starts out set,
+    /// and is unset as soon as we find a path from the root that doesn't need it.
+    pub(crate) fn compute_flags(&mut self) {
+        for n in self.live_nodes().collect::<Vec<_>>() {
+            self.nodes[n].flags = if n == ROOT {
+                DepFlags::NONE
+            } else {
+                DepFlags::ALL
+            };
+        }
+
+        let mut stack = vec![ROOT];
+        while let Some(n) = stack.pop() {
+            let from_flags = self.nodes[n].flags;
+            let targets: Vec<(NodeId, EdgeType)> = self.nodes[n]
+                .edges
+                .iter()
+                .filter_map(|e| self.resolve(n, e).map(|to| (to, e.edge_type)))
+                .collect();
+
+            for (to, edge_type) in targets {
+                let dev = edge_type == EdgeType::Dev;
+                let optional = edge_type.is_optional();
+                let peer = edge_type.is_peer();
+
+                let unset_dev_optional = !from_flags.dev_optional
+                    && !from_flags.dev
+                    && !from_flags.optional
+                    && !dev
+                    && !optional;
+                let unset_dev = unset_dev_optional || (!from_flags.dev && !dev);
+                let unset_optional = unset_dev_optional || (!from_flags.optional && !optional);
+                let unset_peer = !from_flags.peer && !peer;
+
+                let flags = &mut self.nodes[to].flags;
+                let before = *flags;
+                flags.dev_optional &= !unset_dev_optional;
+                flags.dev &= !unset_dev;
+                flags.optional &= !unset_optional;
+                flags.peer &= !unset_peer;
+                if *flags != before {
+                    stack.push(to);
+                }
+            }
+        }
+    }
+}
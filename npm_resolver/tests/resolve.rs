@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use npm_resolver::{
+    resolve, Packument, Resolution, ResolveError, ResolveOptions, RootManifest, VersionManifest,
+};
+use semver_spec_serialization::parse_semver;
+
+type TestRegistry = HashMap<String, Rc<Packument>>;
+
+fn version(name: &str, v: &str, fields: Value) -> VersionManifest {
+    let mut package_json = json!({
+        "name": name,
+        "version": v,
+        "dist": { "tarball": format!("https://registry.example/{0}/-/{0}-{1}.tgz", name, v) },
+    });
+    let obj = package_json.as_object_mut().unwrap();
+    for (k, v) in fields.as_object().unwrap() {
+        obj.insert(k.clone(), v.clone());
+    }
+    VersionManifest::from_package_json(obj).unwrap()
+}
+
+fn registry(versions: Vec<VersionManifest>) -> TestRegistry {
+    let mut by_name: HashMap<String, Vec<VersionManifest>> = HashMap::new();
+    for v in versions {
+        by_name.entry(v.name.clone()).or_default().push(v);
+    }
+    by_name
+        .into_iter()
+        .map(|(name, versions)| (name.clone(), Rc::new(Packument::new(name, versions))))
+        .collect()
+}
+
+fn root(package_json: Value) -> RootManifest {
+    RootManifest::from_package_json(package_json.as_object().unwrap())
+}
+
+fn run(root_json: Value, registry: &mut TestRegistry) -> Result<Resolution, ResolveError> {
+    resolve(root(root_json), registry, &ResolveOptions::default())
+}
+
+fn installed(resolution: &Resolution) -> Vec<(String, String)> {
+    resolution
+        .packages()
+        .into_iter()
+        .map(|p| (p.path, p.version.to_string()))
+        .collect()
+}
+
+fn paths(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(p, v)| (p.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_hoists_shared_dependencies() {
+    let mut reg = registry(vec![
+        version("a", "1.0.0", json!({ "dependencies": { "c": "^1.0.0" } })),
+        version("b", "1.0.0", json!({ "dependencies": { "c": "^1.0.0" } })),
+        version("c", "1.0.0", json!({})),
+        version("c", "1.2.0", json!({})),
+    ]);
+    let res = run(
+        json!({ "dependencies": { "a": "^1.0.0", "b": "^1.0.0" } }),
+        &mut reg,
+    )
+    .unwrap();
+    assert_eq!(
+        installed(&res),
+        paths(&[
+            ("node_modules/a", "1.0.0"),
+            ("node_modules/b", "1.0.0"),
+            ("node_modules/c", "1.2.0"),
+        ])
+    );
+}
+
+#[test]
+fn test_nests_conflicting_versions() {
+    let mut reg = registry(vec![
+        version("a", "1.0.0", json!({ "dependencies": { "c": "^1.0.0" } })),
+        version("c", "1.0.0", json!({})),
+        version("c", "2.0.0", json!({})),
+    ]);
+    let res = run(
+        json!({ "dependencies": { "a": "^1.0.0", "c": "^2.0.0" } }),
+        &mut reg,
+    )
+    .unwrap();
+    assert_eq!(
+        installed(&res),
+        paths(&[
+            ("node_modules/a", "1.0.0"),
+            ("node_modules/a/node_modules/c", "1.0.0"),
+            ("node_modules/c", "2.0.0"),
+        ])
+    );
+}
+
+#[test]
+fn test_prefers_latest_tag() {
+    let mut reg = registry(vec![
+        version("c", "1.0.0", json!({})),
+        version("c", "1.1.0", json!({})),
+    ]);
+    let mut c = (*reg["c"]).clone();
+    c.dist_tags
+        .insert("latest".to_owned(), parse_semver("1.0.0").unwrap());
+    c.dist_tags
+        .insert("next".to_owned(), parse_semver("1.1.0").unwrap());
+    reg.insert("c".to_owned(), Rc::new(c));
+
+    let res = run(json!({ "dependencies": { "c": "^1.0.0" } }), &mut reg).unwrap();
+    assert_eq!(installed(&res), paths(&[("node_modules/c", "1.0.0")]));
+
+    let res = run(json!({ "dependencies": { "c": "next" } }), &mut reg).unwrap();
+    assert_eq!(installed(&res), paths(&[("node_modules/c", "1.1.0")]));
+}
+
+#[test]
+fn test_skips_deprecated_versions() {
+    let mut reg = registry(vec![
+        version("c", "1.0.0", json!({})),
+        version("c", "1.1.0", json!({ "deprecated": "broken" })),
+        version("c", "2.0.0", json!({})),
+    ]);
+    let res = run(json!({ "dependencies": { "c": "^1.0.0" } }), &mut reg).unwrap();
+    assert_eq!(installed(&res), paths(&[("node_modules/c", "1.0.0")]));
+}
+
+#[test]
+fn test_nests_when_hoisted_version_does_not_satisfy() {
+    let mut reg = registry(vec![
+        version("a", "1.0.0", json!({ "dependencies": { "c": "~1.0.0" } })),
+        version("b", "1.0.0", json!({ "dependencies": { "c": "^1.1.0" } })),
+        version("c", "1.0.0", json!({})),
+        version("c", "1.1.0", json!({})),
+    ]);
+    let res = run(json!({ "dependencies": { "a": "1", "b": "1" } }), &mut reg).unwrap();
+    assert_eq!(
+        installed(&res),
+        paths(&[
+            ("node_modules/a", "1.0.0"),
+            ("node_modules/b", "1.0.0"),
+            ("node_modules/b/node_modules/c", "1.1.0"),
+            ("node_modules/c", "1.0.0"),
+        ])
+    );
+}
+
+#[test]
+fn test_replaces_hoisted_version_when_dependents_allow() {
+    let mut reg = registry(vec![
+        version("a", "1.0.0", json!({ "dependencies": { "c": "^1.0.0" } })),
+        version("b", "1.0.0", json!({ "dependencies": { "c": "^1.1.0" } })),
+        version("c", "1.0.0", json!({})),
+        version("c", "1.1.0", json!({})),
+    ]);
+    let mut c = (*reg["c"]).clone();
+    c.dist_tags
+        .insert("latest".to_owned(), parse_semver("1.0.0").unwrap());
+    reg.insert("c".to_owned(), Rc::new(c));
+
+    let res = run(json!({ "dependencies": { "a": "1", "b": "1" } }), &mut reg).unwrap();
+    assert_eq!(
+        installed(&res),
+        paths(&[
+            ("node_modules/a", "1.0.0"),
+            ("node_modules/b", "1.0.0"),
+            ("node_modules/c", "1.1.0"),
+        ])
+    );
+}
+
+#[test]
+fn test_installs_peer_dependencies_next_to_dependent() {
+    let mut reg = registry(vec![
+        version(
+            "plugin",
+            "1.0.0",
+            json!({ "peerDependencies": { "host": "^2.0.0" } }),
+        ),
+        version("host", "1.0.0", json!({})),
+        version("host", "2.0.0", json!({})),
+    ]);
+    let res = run(json!({ "dependencies": { "plugin": "^1.0.0" } }), &mut reg).unwrap();
+    assert_eq!(
+        installed(&res),
+        paths(&[
+            ("node_modules/host", "2.0.0"),
+            ("node_modules/plugin", "1.0.0")
+        ])
+    );
+
+    let host = res
+        .packages()
+        .into_iter()
+        .find(|p| p.name == "host")
+        .unwrap();
+    assert!(host.flags.peer);
+}
+
+#[test]
+fn test_nests_peer_dependencies_that_conflict_higher_up() {
+    let mut reg = registry(vec![
+        version(
+            "x",
+            "1.0.0",
+            json!({ "dependencies": { "plugin": "^1.0.0" } }),
+        ),
+        version(
+            "plugin",
+            "1.0.0",
+            json!({ "peerDependencies": { "host": "^2.0.0" } }),
+        ),
+        version("host", "1.0.0", json!({})),
+        version("host", "2.0.0", json!({})),
+    ]);
+    let res = run(
+        json!({ "dependencies": { "host": "^1.0.0", "x": "^1.0.0" } }),
+        &mut reg,
+    )
+    .unwrap();
+    assert_eq!(
+        installed(&res),
+        paths(&[
+            ("node_modules/host", "1.0.0"),
+            ("node_modules/x", "1.0.0"),
+            ("node_modules/x/node_modules/host", "2.0.0"),
+            ("node_modules/x/node_modules/plugin", "1.0.0"),
+        ])
+    );
+}
+
+#[test]
+fn test_peer_conflict_with_root_fails() {
+    let mut reg = registry(vec![
+        version(
+            "plugin",
+            "1.0.0",
+            json!({ "peerDependencies": { "host": "^2.0.0" } }),
+        ),
+        version("host", "1.0.0", json!({})),
+        version("host", "2.0.0", json!({})),
+    ]);
+    let err = run(
+        json!({ "dependencies": { "host": "^1.0.0", "plugin": "^1.0.0" } }),
+        &mut reg,
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(err, ResolveError::PeerConflict { name, .. } if name == "plugin"));
+}
+
+#[test]
+fn test_optional_peer_dependencies_are_not_installed() {
+    let mut reg = registry(vec![
+        version(
+            "plugin",
+            "1.0.0",
+            json!({
+                "peerDependencies": { "host": "^2.0.0" },
+                "peerDependenciesMeta": { "host": { "optional": true } },
+            }),
+        ),
+        version("host", "2.0.0", json!({})),
+    ]);
+    let res = run(json!({ "dependencies": { "plugin": "^1.0.0" } }), &mut reg).unwrap();
+    assert_eq!(installed(&res), paths(&[("node_modules/plugin", "1.0.0")]));
+}
+
+#[test]
+fn test_failed_optional_dependencies_are_skipped() {
+    let mut reg = registry(vec![
+        version(
+            "opt",
+            "1.0.0",
+            json!({ "dependencies": { "missing": "^1.0.0", "c": "1" } }),
+        ),
+        version("c", "1.0.0", json!({})),
+    ]);
+    let res = run(
+        json!({ "optionalDependencies": { "opt": "^1.0.0", "also-missing": "^1.0.0" } }),
+        &mut reg,
+    )
+    .unwrap();
+    assert_eq!(installed(&res), vec![]);
+    assert_eq!(res.warnings.len(), 2);
+}
+
+#[test]
+fn test_missing_required_dependency_fails() {
+    let mut reg = registry(vec![version(
+        "a",
+        "1.0.0",
+        json!({ "dependencies": { "missing": "^1.0.0" } }),
+    )]);
+    let err = run(json!({ "dependencies": { "a": "^1.0.0" } }), &mut reg)
+        .err()
+        .unwrap();
+    assert!(matches!(err, ResolveError::PackageNotFound { name, .. } if name == "missing"));
+
+    let err = run(json!({ "dependencies": { "a": "^2.0.0" } }), &mut reg)
+        .err()
+        .unwrap();
+    assert!(matches!(err, ResolveError::NoMatchingVersion { name, .. } if name == "a"));
+}
+
+#[test]
+fn test_package_lock() {
+    let mut reg = registry(vec![
+        version(
+            "a",
+            "1.0.0",
+            json!({ "dependencies": { "b": "^1.0.0" }, "license": "MIT" }),
+        ),
+        version("b", "1.0.0", json!({})),
+        version(
+            "d",
+            "1.0.0",
+            json!({ "scripts": { "postinstall": "node x.js" } }),
+        ),
+    ]);
+    let res = run(
+        json!({
+            "name": "app",
+            "version": "0.1.0",
+            "dependencies": { "my-a": "npm:a@^1.0.0" },
+            "devDependencies": { "d": "^1.0.0" },
+        }),
+        &mut reg,
+    )
+    .unwrap();
+
+    assert_eq!(
+        res.to_package_lock(),
+        json!({
+            "name": "app",
+            "version": "0.1.0",
+            "lockfileVersion": 3,
+            "requires": true,
+            "packages": {
+                "": {
+                    "name": "app",
+                    "version": "0.1.0",
+                    "dependencies": { "my-a": "npm:a@^1.0.0" },
+                    "devDependencies": { "d": "^1.0.0" },
+                },
+                "node_modules/b": {
+                    "version": "1.0.0",
+                    "resolved": "https://registry.example/b/-/b-1.0.0.tgz",
+                },
+                "node_modules/d": {
+                    "version": "1.0.0",
+                    "resolved": "https://registry.example/d/-/d-1.0.0.tgz",
+                    "dev": true,
+                    "hasInstallScript": true,
+                },
+                "node_modules/my-a": {
+                    "name": "a",
+                    "version": "1.0.0",
+                    "resolved": "https://registry.example/a/-/a-1.0.0.tgz",
+                    "dependencies": { "b": "^1.0.0" },
+                    "license": "MIT",
+                },
+            },
+        })
+    );
+}
@@ -0,0 +1,100 @@
+//! CVSS v3 base scores, which OSV records only give as vectors.
+
+/// The base score of a CVSS v3.0 or v3.1 vector like
+/// `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`, or `None` if it isn't a valid one.
+pub fn cvss3_base_score(vector: &str) -> Option<f32> {
+    let mut parts = vector.split('/');
+    match parts.next()? {
+        "CVSS:3.0" | "CVSS:3.1" => {}
+        _ => return None,
+    }
+
+    let mut metrics = [None; 8];
+    const NAMES: [&str; 8] = ["AV", "AC", "PR", "UI", "S", "C", "I", "A"];
+    for part in parts {
+        let (name, value) = part.split_once(':')?;
+        // temporal and environmental metrics don't change the base score
+        if let Some(i) = NAMES.iter().position(|n| *n == name) {
+            metrics[i] = Some(value);
+        }
+    }
+    let [av, ac, pr, ui, s, c, i, a] = metrics;
+
+    let scope_changed = match s? {
+        "U" => false,
+        "C" => true,
+        _ => return None,
+    };
+    let av = match av? {
+        "N" => 0.85,
+        "A" => 0.62,
+        "L" => 0.55,
+        "P" => 0.2,
+        _ => return None,
+    };
+    let ac = match ac? {
+        "L" => 0.77,
+        "H" => 0.44,
+        _ => return None,
+    };
+    let pr = match (pr?, scope_changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let ui = match ui? {
+        "N" => 0.85,
+        "R" => 0.62,
+        _ => return None,
+    };
+    let cia = |m: Option<&str>| match m? {
+        "H" => Some(0.56),
+        "L" => Some(0.22),
+        "N" => Some(0.0),
+        _ => None,
+    };
+    let (c, i, a): (f64, f64, f64) = (cia(c)?, cia(i)?, cia(a)?);
+
+    let iss = 1.0 - (1.0 - c) * (1.0 - i) * (1.0 - a);
+    let impact = if scope_changed {
+        7.52 * (iss - 0.029) - 3.25 * (iss - 0.02).powi(15)
+    } else {
+        6.42 * iss
+    };
+    let exploitability = 8.22 * av * ac * pr * ui;
+
+    let score = if impact <= 0.0 {
+        0.0
+    } else if scope_changed {
+        round_up((1.08 * (impact + exploitability)).min(10.0))
+    } else {
+        round_up((impact + exploitability).min(10.0))
+    };
+    Some(score as f32)
+}
+
+/// Rounds up to one decimal, as the v3.1 spec does it to avoid floating point surprises.
+fn round_up(x: f64) -> f64 {
+    let int_input = (x * 100_000.0).round() as i64;
+    if int_input % 10_000 == 0 {
+        int_input as f64 / 100_000.0
+    } else {
+        (int_input / 10_000 + 1) as f64 / 10.0
+    }
+}
+
+/// The severity GHSA would give a CVSS score.
+pub fn severity_of_score(score: f32) -> &'static str {
+    if score >= 9.0 {
+        "CRITICAL"
+    } else if score >= 7.0 {
+        "HIGH"
+    } else if score >= 4.0 {
+        "MODERATE"
+    } else {
+        "LOW"
+    }
+}
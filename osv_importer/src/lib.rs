@@ -0,0 +1,348 @@
+//! Importing vulnerabilities from [OSV](https://ossf.github.io/osv-schema/) records, as an
+//! alternative to scraping GHSA. Records are mapped into the same tables as GHSA advisories,
+//! with `osv` as their source, so the two can be compared.
+//!
+//! Only the npm packages of a record are imported. Each vulnerable interval of a range becomes
+//! one row of `vulnerabilities`, and records with versions that aren't valid semver are skipped.
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Utc};
+use postgres_db::custom_types::Semver;
+use postgres_db::ghsa::{Ghsa, GhsaHistoryVulnerability, GhsaVulnerability};
+use semver_spec_serialization::{compare_versions, parse_advisory_range, parse_semver};
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+pub mod cvss;
+mod read;
+
+pub use read::{for_each_record, ReadError};
+
+/// The `source` of the advisories we import.
+pub const OSV_SOURCE: &str = "osv";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvRecord {
+    pub id: String,
+    pub modified: DateTime<Utc>,
+    pub published: Option<DateTime<Utc>>,
+    pub withdrawn: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+    pub details: Option<String>,
+    #[serde(default)]
+    pub severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    pub affected: Vec<OsvAffected>,
+    #[serde(default)]
+    pub references: Vec<OsvReference>,
+    /// Free-form, but GHSA puts `severity` and `cwe_ids` here.
+    pub database_specific: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvSeverity {
+    #[serde(rename = "type")]
+    pub severity_type: String,
+    pub score: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvAffected {
+    pub package: Option<OsvPackage>,
+    #[serde(default)]
+    pub ranges: Vec<OsvRange>,
+    #[serde(default)]
+    pub versions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvPackage {
+    pub ecosystem: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvRange {
+    #[serde(rename = "type")]
+    pub range_type: String,
+    pub events: Vec<OsvEvent>,
+}
+
+/// One of the keys is set. `limit` only applies to git ranges, which we ignore.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OsvEvent {
+    pub introduced: Option<String>,
+    pub fixed: Option<String>,
+    pub last_affected: Option<String>,
+    pub limit: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvReference {
+    pub url: String,
+}
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("Invalid version {version} of package {package}")]
+    InvalidVersion { package: String, version: String },
+    #[error("Invalid range {range} of package {package}")]
+    InvalidRange { package: String, range: String },
+}
+
+/// The rows for one OSV record.
+#[derive(Debug, Clone)]
+pub struct ImportedAdvisory {
+    pub advisory: Ghsa,
+    pub vulnerabilities: Vec<GhsaVulnerability>,
+    /// The ranges as written in the record, for `ghsa_history`.
+    pub history_vulnerabilities: Vec<GhsaHistoryVulnerability>,
+    pub cwe_ids: Vec<String>,
+    pub aliases: Vec<String>,
+}
+
+/// A vulnerable interval of versions of a package. The strings are as the record gave them and
+/// have been checked to be valid semver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Interval<'a> {
+    /// `None` if all versions before the upper bound are vulnerable.
+    introduced: Option<&'a str>,
+    upper: Option<UpperBound<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpperBound<'a> {
+    Fixed(&'a str),
+    LastAffected(&'a str),
+}
+
+impl Interval<'_> {
+    /// The range in GHSA's format, e.g. `>= 1.0.0, < 1.2.3`.
+    fn range(&self) -> String {
+        match (self.introduced, self.upper) {
+            (Some(lo), Some(UpperBound::LastAffected(hi))) if lo == hi => format!("= {}", lo),
+            (lo, hi) => {
+                let mut parts = vec![];
+                if let Some(lo) = lo {
+                    parts.push(format!(">= {}", lo));
+                }
+                match hi {
+                    Some(UpperBound::Fixed(v)) => parts.push(format!("< {}", v)),
+                    Some(UpperBound::LastAffected(v)) => parts.push(format!("<= {}", v)),
+                    None => {}
+                }
+                if parts.is_empty() {
+                    ">= 0".to_owned()
+                } else {
+                    parts.join(", ")
+                }
+            }
+        }
+    }
+}
+
+/// Converts a record into rows, or `None` if it doesn't affect any npm package.
+pub fn convert_record(record: &OsvRecord) -> Result<Option<ImportedAdvisory>, ConvertError> {
+    let mut vulnerabilities = vec![];
+    let mut history_vulnerabilities = vec![];
+    for affected in &record.affected {
+        let package = match &affected.package {
+            Some(p) if p.ecosystem == "npm" => &p.name,
+            _ => continue,
+        };
+        for interval in affected_intervals(package, affected)? {
+            let range = interval.range();
+            let fixed = match interval.upper {
+                Some(UpperBound::Fixed(v)) => Some(v),
+                _ => None,
+            };
+            history_vulnerabilities.push(GhsaHistoryVulnerability {
+                package_name: package.clone(),
+                vulnerable_version_range: range.clone(),
+                first_patched_version: fixed.map(|v| v.to_owned()),
+            });
+
+            let parse = |v: &str| parse_version(package, v);
+            let (upper, upper_inclusive) = match interval.upper {
+                Some(UpperBound::Fixed(v)) => (Some(parse(v)?), false),
+                Some(UpperBound::LastAffected(v)) => (Some(parse(v)?), true),
+                None => (None, true),
+            };
+            let constraint =
+                parse_advisory_range(&range).map_err(|_| ConvertError::InvalidRange {
+                    package: package.clone(),
+                    range: range.clone(),
+                })?;
+            let vuln = GhsaVulnerability {
+                ghsa_id: record.id.clone(),
+                package_name: package.clone(),
+                vulnerable_version_lower_bound: interval.introduced.map(parse).transpose()?,
+                vulnerable_version_lower_bound_inclusive: true,
+                vulnerable_version_upper_bound: upper,
+                vulnerable_version_upper_bound_inclusive: upper_inclusive,
+                first_patched_version: fixed.map(parse).transpose()?,
+                vulnerable_version_constraint: constraint,
+            };
+            // records sometimes repeat a package, and the table doesn't allow duplicate ranges
+            if !vulnerabilities.iter().any(|v: &GhsaVulnerability| {
+                v.package_name == vuln.package_name
+                    && v.vulnerable_version_constraint == vuln.vulnerable_version_constraint
+            }) {
+                vulnerabilities.push(vuln);
+            }
+        }
+    }
+    if history_vulnerabilities.is_empty() {
+        return Ok(None);
+    }
+
+    let database_specific = record.database_specific.as_ref();
+    let cvss_vector = record
+        .severity
+        .iter()
+        .find(|s| s.severity_type == "CVSS_V3")
+        .or_else(|| record.severity.first())
+        .map(|s| s.score.clone());
+    let cvss_score = cvss_vector.as_deref().and_then(cvss::cvss3_base_score);
+    let severity = database_specific
+        .and_then(|d| d.get("severity"))
+        .and_then(|s| s.as_str())
+        .map(|s| s.to_uppercase())
+        .or_else(|| cvss_score.map(|s| cvss::severity_of_score(s).to_owned()))
+        .unwrap_or_else(|| "UNKNOWN".to_owned());
+    let cwe_ids = database_specific
+        .and_then(|d| d.get("cwe_ids"))
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.as_str().map(|c| c.to_owned()))
+        .collect();
+
+    let advisory = Ghsa {
+        id: record.id.clone(),
+        severity,
+        description: record.details.clone().unwrap_or_default(),
+        summary: record.summary.clone().unwrap_or_default(),
+        withdrawn_at: record.withdrawn,
+        published_at: record.published.unwrap_or(record.modified),
+        updated_at: record.modified,
+        refs: record.references.iter().map(|r| r.url.clone()).collect(),
+        cvss_score,
+        cvss_vector,
+        source: OSV_SOURCE.to_owned(),
+    };
+
+    Ok(Some(ImportedAdvisory {
+        advisory,
+        vulnerabilities,
+        history_vulnerabilities,
+        cwe_ids,
+        aliases: record.aliases.clone(),
+    }))
+}
+
+fn parse_version(package: &str, v: &str) -> Result<Semver, ConvertError> {
+    parse_semver(v).map_err(|_| ConvertError::InvalidVersion {
+        package: package.to_owned(),
+        version: v.to_owned(),
+    })
+}
+
+/// The vulnerable intervals of an affected package: those of its semver ranges, or if it has
+/// none, each of its listed versions.
+fn affected_intervals<'a>(
+    package: &str,
+    affected: &'a OsvAffected,
+) -> Result<Vec<Interval<'a>>, ConvertError> {
+    let ranges: Vec<&OsvRange> = affected
+        .ranges
+        .iter()
+        .filter(|r| r.range_type == "SEMVER" || r.range_type == "ECOSYSTEM")
+        .collect();
+
+    if ranges.is_empty() {
+        return affected
+            .versions
+            .iter()
+            .map(|v| {
+                parse_version(package, v)?;
+                Ok(Interval {
+                    introduced: Some(v),
+                    upper: Some(UpperBound::LastAffected(v)),
+                })
+            })
+            .collect();
+    }
+
+    let mut intervals = vec![];
+    for range in ranges {
+        intervals.extend(range_intervals(package, range)?);
+    }
+    Ok(intervals)
+}
+
+/// Event in a range, with `introduced: "0"` as `Introduced(None)`.
+#[derive(Debug, Clone, Copy)]
+enum Event<'a> {
+    Introduced(Option<&'a str>),
+    Upper(UpperBound<'a>),
+}
+
+/// The intervals of a range, following the OSV evaluation rules: events are sorted by version,
+/// and each `introduced` starts an interval that the next `fixed` or `last_affected` ends.
+fn range_intervals<'a>(
+    package: &str,
+    range: &'a OsvRange,
+) -> Result<Vec<Interval<'a>>, ConvertError> {
+    let mut events: Vec<(Option<Semver>, Event<'a>)> = vec![];
+    for e in &range.events {
+        let event = if let Some(v) = &e.introduced {
+            if v == "0" {
+                events.push((None, Event::Introduced(None)));
+                continue;
+            }
+            (v, Event::Introduced(Some(v)))
+        } else if let Some(v) = &e.fixed {
+            (v, Event::Upper(UpperBound::Fixed(v)))
+        } else if let Some(v) = &e.last_affected {
+            (v, Event::Upper(UpperBound::LastAffected(v)))
+        } else {
+            continue;
+        };
+        events.push((Some(parse_version(package, event.0)?), event.1));
+    }
+    // a stable sort keeps the record's order of events at the same version
+    events.sort_by(|(a, _), (b, _)| match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(a), Some(b)) => compare_versions(a, b),
+    });
+
+    let mut intervals = vec![];
+    let mut open: Option<Option<&'a str>> = None;
+    for (_, event) in events {
+        match (event, open) {
+            (Event::Introduced(v), None) => open = Some(v),
+            (Event::Upper(upper), Some(introduced)) => {
+                intervals.push(Interval {
+                    introduced,
+                    upper: Some(upper),
+                });
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(introduced) = open {
+        intervals.push(Interval {
+            introduced,
+            upper: None,
+        });
+    }
+    Ok(intervals)
+}
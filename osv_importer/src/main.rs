@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use osv_importer::{convert_record, for_each_record, ImportedAdvisory, OSV_SOURCE};
+use postgres_db::connection::{DbConnection, QueryRunner};
+use postgres_db::ghsa::{
+    associate_ghsa_to_cwe, insert_ghsa, insert_ghsa_revision, insert_missing_cwes,
+    query_ghsa_source, set_ghsa_aliases, GhsaCweRelation, GhsaRevision,
+};
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} <directory|zip|json file> [--overwrite]", program);
+    eprintln!();
+    eprintln!("Advisories that another source (i.e. ghsa_scraper) already imported are only");
+    eprintln!("recorded in the history and aliases, unless --overwrite is given.");
+    std::process::exit(1);
+}
+
+fn main() {
+    let _tracing = utils::init_tracing("osv_importer");
+    utils::check_no_concurrent_processes("osv_importer");
+
+    let args: Vec<String> = std::env::args().collect();
+    let mut path = None;
+    let mut overwrite = false;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--overwrite" => overwrite = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(PathBuf::from(arg)),
+            _ => exit_with_usage(&args[0]),
+        }
+    }
+    let path = path.unwrap_or_else(|| exit_with_usage(&args[0]));
+
+    let mut advisories = vec![];
+    let mut num_records = 0;
+    let mut num_skipped = 0;
+    for_each_record(&path, |name, record| {
+        num_records += 1;
+        let record = match record {
+            Ok(r) => r,
+            Err(err) => {
+                tracing::warn!(record = %name, error = %err, "Skipping, not an OSV record");
+                num_skipped += 1;
+                return;
+            }
+        };
+        match convert_record(&record) {
+            Ok(Some(advisory)) => advisories.push(advisory),
+            Ok(None) => {}
+            Err(err) => {
+                tracing::warn!(osv_id = %record.id, error = %err, "Skipping the record");
+                num_skipped += 1;
+            }
+        }
+    })
+    .unwrap_or_else(|err| {
+        tracing::error!(path = %path.display(), error = %err, "Failed to read the records");
+        std::process::exit(1);
+    });
+    tracing::info!(
+        num_records,
+        num_npm = advisories.len(),
+        num_skipped,
+        "Read the records"
+    );
+
+    let observed_at = Utc::now();
+    let mut conn = DbConnection::connect();
+    conn.run_psql_transaction(|mut conn| {
+        insert_advisories(&mut conn, advisories, observed_at, overwrite);
+        Ok(((), true))
+    })
+    .unwrap();
+}
+
+fn insert_advisories<R>(
+    conn: &mut R,
+    advisories: Vec<ImportedAdvisory>,
+    observed_at: DateTime<Utc>,
+    overwrite: bool,
+) where
+    R: QueryRunner,
+{
+    let mut cwe_ids = HashSet::new();
+    let mut relations = vec![];
+    let mut num_revisions = 0;
+    let mut num_written = 0;
+    let mut num_not_owned = 0;
+
+    for imported in advisories {
+        let ghsa_id = imported.advisory.id.clone();
+        let _span = tracing::info_span!("advisory", ghsa_id = %ghsa_id).entered();
+        let revision = GhsaRevision::new(
+            &imported.advisory,
+            imported.cwe_ids.clone(),
+            imported.history_vulnerabilities,
+            observed_at,
+        );
+        if insert_ghsa_revision(conn, revision) {
+            num_revisions += 1;
+        }
+
+        let owned = match query_ghsa_source(conn, &ghsa_id) {
+            None => true,
+            Some(source) => source == OSV_SOURCE || overwrite,
+        };
+        if owned {
+            for cwe_id in imported.cwe_ids {
+                cwe_ids.insert(cwe_id.clone());
+                relations.push(GhsaCweRelation {
+                    ghsa_id: ghsa_id.clone(),
+                    cwe_id,
+                });
+            }
+            insert_ghsa(conn, imported.advisory, imported.vulnerabilities);
+            num_written += 1;
+        } else {
+            tracing::debug!("Imported from another source, only recording the history");
+            num_not_owned += 1;
+        }
+        set_ghsa_aliases(conn, &ghsa_id, OSV_SOURCE, imported.aliases);
+    }
+
+    insert_missing_cwes(conn, cwe_ids.into_iter().collect());
+    associate_ghsa_to_cwe(conn, relations);
+
+    tracing::info!(
+        num_written,
+        num_not_owned,
+        num_revisions,
+        "Wrote the advisories, leaving those from other sources alone"
+    );
+}
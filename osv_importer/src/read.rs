@@ -0,0 +1,67 @@
+//! Reading OSV records from a directory, a zip dump (like the `all.zip` of an ecosystem on
+//! osv.dev) or a single JSON file.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::OsvRecord;
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+}
+
+/// Calls `f` with the name and parse result of each `.json` file at `path`, in order of name.
+/// Files that aren't valid records are passed along with their error, so one bad record doesn't
+/// stop the import.
+pub fn for_each_record<F>(path: &Path, mut f: F) -> Result<(), ReadError>
+where
+    F: FnMut(&str, Result<OsvRecord, serde_json::Error>),
+{
+    if path.is_dir() {
+        for file in json_files_in(path)? {
+            let contents = fs::read(&file)?;
+            f(&file.to_string_lossy(), serde_json::from_slice(&contents));
+        }
+    } else if path.extension().is_some_and(|e| e == "zip") {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let mut names: Vec<String> = archive
+            .file_names()
+            .filter(|n| n.ends_with(".json"))
+            .map(|n| n.to_owned())
+            .collect();
+        names.sort();
+        for name in names {
+            let mut contents = vec![];
+            archive.by_name(&name)?.read_to_end(&mut contents)?;
+            f(&name, serde_json::from_slice(&contents));
+        }
+    } else {
+        let contents = fs::read(path)?;
+        f(&path.to_string_lossy(), serde_json::from_slice(&contents));
+    }
+    Ok(())
+}
+
+fn json_files_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "json") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
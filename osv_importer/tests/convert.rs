@@ -0,0 +1,194 @@
+use osv_importer::cvss::cvss3_base_score;
+use osv_importer::{convert_record, OsvRecord, OSV_SOURCE};
+use semver_spec_serialization::{parse_semver, satisfies};
+use serde_json::json;
+
+fn record(value: serde_json::Value) -> OsvRecord {
+    serde_json::from_value(value).unwrap()
+}
+
+fn ghsa_record() -> serde_json::Value {
+    json!({
+        "schema_version": "1.4.0",
+        "id": "GHSA-35jh-r3h4-6jhm",
+        "modified": "2023-01-10T05:04:05Z",
+        "published": "2021-05-06T16:05:51Z",
+        "aliases": ["CVE-2021-23337"],
+        "summary": "Command Injection in lodash",
+        "details": "`lodash` versions prior to 4.17.21 are vulnerable to Command Injection.",
+        "severity": [
+            {"type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:H/UI:N/S:U/C:H/I:H/A:H"}
+        ],
+        "affected": [
+            {
+                "package": {"ecosystem": "npm", "name": "lodash", "purl": "pkg:npm/lodash"},
+                "ranges": [
+                    {"type": "ECOSYSTEM", "events": [{"introduced": "0"}, {"fixed": "4.17.21"}]}
+                ],
+                "database_specific": {"source": "https://github.com/github/advisory-database"}
+            },
+            {
+                "package": {"ecosystem": "PyPI", "name": "lodash"},
+                "ranges": [
+                    {"type": "ECOSYSTEM", "events": [{"introduced": "0"}, {"fixed": "1.0"}]}
+                ]
+            }
+        ],
+        "references": [
+            {"type": "ADVISORY", "url": "https://nvd.nist.gov/vuln/detail/CVE-2021-23337"},
+            {"type": "PACKAGE", "url": "https://github.com/lodash/lodash"}
+        ],
+        "database_specific": {
+            "cwe_ids": ["CWE-77", "CWE-94"],
+            "severity": "HIGH",
+            "github_reviewed": true
+        }
+    })
+}
+
+#[test]
+fn test_converts_advisory() {
+    let imported = convert_record(&record(ghsa_record())).unwrap().unwrap();
+    let adv = &imported.advisory;
+    assert_eq!(adv.id, "GHSA-35jh-r3h4-6jhm");
+    assert_eq!(adv.source, OSV_SOURCE);
+    assert_eq!(adv.severity, "HIGH");
+    assert_eq!(adv.summary, "Command Injection in lodash");
+    assert_eq!(adv.published_at.to_rfc3339(), "2021-05-06T16:05:51+00:00");
+    assert_eq!(adv.updated_at.to_rfc3339(), "2023-01-10T05:04:05+00:00");
+    assert_eq!(adv.withdrawn_at, None);
+    assert_eq!(adv.refs.len(), 2);
+    assert_eq!(adv.cvss_score, Some(7.2));
+    assert_eq!(imported.cwe_ids, vec!["CWE-77", "CWE-94"]);
+    assert_eq!(imported.aliases, vec!["CVE-2021-23337"]);
+
+    // the PyPI package isn't imported
+    assert_eq!(imported.vulnerabilities.len(), 1);
+    let vuln = &imported.vulnerabilities[0];
+    assert_eq!(vuln.package_name, "lodash");
+    assert_eq!(vuln.vulnerable_version_lower_bound, None);
+    assert_eq!(
+        vuln.vulnerable_version_upper_bound,
+        Some(parse_semver("4.17.21").unwrap())
+    );
+    assert!(!vuln.vulnerable_version_upper_bound_inclusive);
+    assert_eq!(
+        vuln.first_patched_version,
+        Some(parse_semver("4.17.21").unwrap())
+    );
+    assert_eq!(
+        imported.history_vulnerabilities[0].vulnerable_version_range,
+        "< 4.17.21"
+    );
+}
+
+#[test]
+fn test_ignores_records_without_npm_packages() {
+    let mut value = ghsa_record();
+    value["affected"] = json!([value["affected"][1].clone()]);
+    assert!(convert_record(&record(value)).unwrap().is_none());
+}
+
+fn ranges_of(affected: serde_json::Value) -> Vec<String> {
+    let mut value = ghsa_record();
+    value["affected"] = affected;
+    convert_record(&record(value))
+        .unwrap()
+        .unwrap()
+        .history_vulnerabilities
+        .into_iter()
+        .map(|v| v.vulnerable_version_range)
+        .collect()
+}
+
+#[test]
+fn test_range_events() {
+    let ranges = ranges_of(json!([{
+        "package": {"ecosystem": "npm", "name": "pkg"},
+        "ranges": [{"type": "SEMVER", "events": [
+            {"introduced": "2.0.0"},
+            {"last_affected": "2.3.1"},
+            {"fixed": "1.5.0"},
+            {"introduced": "0"},
+            {"introduced": "3.0.0-beta.1"},
+        ]}]
+    }]));
+    assert_eq!(
+        ranges,
+        vec!["< 1.5.0", ">= 2.0.0, <= 2.3.1", ">= 3.0.0-beta.1"]
+    );
+}
+
+#[test]
+fn test_versions_without_ranges() {
+    let mut value = ghsa_record();
+    value["affected"] = json!([{
+        "package": {"ecosystem": "npm", "name": "pkg"},
+        "versions": ["1.0.0", "1.0.1"]
+    }]);
+    let imported = convert_record(&record(value)).unwrap().unwrap();
+    let ranges: Vec<_> = imported
+        .history_vulnerabilities
+        .iter()
+        .map(|v| v.vulnerable_version_range.as_str())
+        .collect();
+    assert_eq!(ranges, vec!["= 1.0.0", "= 1.0.1"]);
+
+    let constraint = &imported.vulnerabilities[1].vulnerable_version_constraint;
+    assert!(satisfies(
+        constraint,
+        &parse_semver("1.0.1").unwrap(),
+        false
+    ));
+    assert!(!satisfies(
+        constraint,
+        &parse_semver("1.0.0").unwrap(),
+        false
+    ));
+}
+
+#[test]
+fn test_invalid_versions_are_errors() {
+    let mut value = ghsa_record();
+    value["affected"][0]["ranges"][0]["events"][1] = json!({"fixed": "not a version"});
+    assert!(convert_record(&record(value)).is_err());
+}
+
+#[test]
+fn test_severity_falls_back_to_cvss() {
+    let mut value = ghsa_record();
+    value["database_specific"] = json!(null);
+    value["severity"] =
+        json!([{"type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"}]);
+    let imported = convert_record(&record(value.clone())).unwrap().unwrap();
+    assert_eq!(imported.advisory.severity, "CRITICAL");
+    assert!(imported.cwe_ids.is_empty());
+
+    value["severity"] = json!([]);
+    let imported = convert_record(&record(value)).unwrap().unwrap();
+    assert_eq!(imported.advisory.severity, "UNKNOWN");
+    assert_eq!(imported.advisory.cvss_vector, None);
+}
+
+#[test]
+fn test_cvss3_base_score() {
+    let score = |v: &str| cvss3_base_score(v);
+    assert_eq!(
+        score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"),
+        Some(9.8)
+    );
+    assert_eq!(
+        score("CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N"),
+        Some(6.1)
+    );
+    assert_eq!(
+        score("CVSS:3.0/AV:L/AC:H/PR:L/UI:N/S:U/C:N/I:N/A:N"),
+        Some(0.0)
+    );
+    assert_eq!(
+        score("CVSS:3.1/AV:N/AC:H/PR:N/UI:N/S:U/C:N/I:N/A:H/E:P"),
+        Some(5.9)
+    );
+    assert_eq!(score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H"), None);
+    assert_eq!(score("AV:N/AC:L/Au:N/C:P/I:P/A:P"), None);
+}
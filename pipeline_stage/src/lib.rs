@@ -0,0 +1,200 @@
+//! The loop shared by the binaries that work through a table in order and pick up where they left
+//! off: read how far we got from `internal_state`, query the page after that, process the page
+//! and save how far we got in one transaction, log metrics, and repeat until there is nothing
+//! left. A stage implements [`PipelineStage`] with its per-page logic, and [`Runner`] does the
+//! rest, including page sizing, panics, graceful shutdown and dry runs. A stage can also run as a
+//! daemon, which sleeps until the stage upstream of it notifies that it advanced.
+
+mod runner;
+
+pub use runner::{PageSize, Runner, DAEMON_POLL_INTERVAL};
+
+use std::any::Any;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use metrics_logging::{MetricsEvent, MetricsLogger};
+use postgres_db::connection::{DbConnection, DbConnectionInTransaction};
+use utils::panic_as_string;
+
+pub trait PipelineStage {
+    /// What a page is made of, e.g. changes.
+    type Item;
+    /// How far the stage has gotten, e.g. the last processed seq.
+    type Cursor: Clone + Debug;
+    /// What processing a page reports back, for the stage's own metrics.
+    type Output;
+
+    /// The name used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Reads the saved cursor, or where to start if there is none.
+    fn load_cursor(&mut self, conn: &mut DbConnection) -> Self::Cursor;
+
+    /// Saves the cursor, in the same transaction as processing the page that got it there.
+    fn save_cursor(&mut self, conn: &mut DbConnectionInTransaction, cursor: &Self::Cursor);
+
+    /// Queries the page after `cursor`. The page size is in the units of
+    /// [`PipelineStage::page_units`]. An empty page means there is nothing left to do.
+    fn query_page(
+        &mut self,
+        conn: &mut DbConnection,
+        cursor: &Self::Cursor,
+        page_size: i64,
+    ) -> Vec<Self::Item>;
+
+    /// The cursor after processing `page`, which is never empty.
+    fn advance(&self, cursor: &Self::Cursor, page: &[Self::Item]) -> Self::Cursor;
+
+    /// Processes a page in a transaction. Panics are caught and handled like errors: the
+    /// transaction is rolled back, [`PipelineStage::page_failed`] is called, and the panic is
+    /// resumed.
+    fn process_page(
+        &mut self,
+        conn: &mut DbConnectionInTransaction,
+        page: Vec<Self::Item>,
+    ) -> Result<Self::Output, StageError>;
+
+    /// How much of the page size a page used up. By default pages are sized by their number of
+    /// items, but e.g. diff log entries are paged by seq, and a seq can have many entries.
+    fn page_units(&self, page: &[Self::Item]) -> i64 {
+        page.len() as i64
+    }
+
+    /// The channel to notify, along with saving the cursor, so that the stages downstream of this
+    /// one can run right away. See `postgres_db::notifications`.
+    fn notify_channel(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Adds the stage's own fields, e.g. how many seqs it has left, to the progress event that the
+    /// runner logs for every page, along with the page's size and durations.
+    fn progress_event(
+        &self,
+        event: MetricsEvent,
+        _page: &PageStats<Self::Cursor>,
+        _output: &Self::Output,
+    ) -> MetricsEvent {
+        event
+    }
+
+    fn start_session(
+        &mut self,
+        _conn: &mut DbConnection,
+        _logger: &mut MetricsLogger,
+        _session: &SessionStart<Self::Cursor>,
+    ) {
+    }
+
+    fn page_completed(
+        &mut self,
+        _logger: &mut MetricsLogger,
+        _page: &PageStats<Self::Cursor>,
+        _output: &Self::Output,
+    ) {
+    }
+
+    /// Called whenever the transaction of a page is rolled back, which is after every page of a
+    /// dry run, and after a failed page before [`PipelineStage::page_failed`]. A stage that keeps
+    /// state in memory, e.g. caches of the rows it wrote, has to drop or reload it here, since the
+    /// writes are gone. A dry run processes every page against the committed tables.
+    fn page_rolled_back(&mut self, _conn: &mut DbConnection) {}
+
+    /// Called once the transaction of a failed page is rolled back, before the panic is resumed.
+    /// `first` is the cursor after the first item of the page.
+    fn page_failed(
+        &mut self,
+        _logger: &mut MetricsLogger,
+        _first: &Self::Cursor,
+        _err: &StageError,
+    ) {
+    }
+
+    fn end_session(
+        &mut self,
+        _conn: &mut DbConnection,
+        _logger: &mut MetricsLogger,
+        _session: &SessionSummary<Self::Cursor>,
+    ) {
+    }
+
+    /// Called once the runner is done with the stage, after its last session. A daemon has many
+    /// sessions, so this is the place for work that only has to happen on the way out.
+    fn shut_down(
+        &mut self,
+        _conn: &mut DbConnection,
+        _last_session: &SessionSummary<Self::Cursor>,
+    ) {
+    }
+}
+
+/// Why processing a page failed.
+#[derive(Debug)]
+pub struct StageError {
+    /// The seq that was being processed, if known.
+    pub seq: Option<i64>,
+    /// The id of the item that was being processed, if it has one besides its seq.
+    pub item_id: Option<i64>,
+    pub message: String,
+    /// The panic, which is resumed once the error is handled.
+    pub payload: Box<dyn Any + Send>,
+}
+
+impl StageError {
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> StageError {
+        StageError {
+            seq: None,
+            item_id: None,
+            message: panic_as_string(&payload),
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionStart<C> {
+    pub start_time: DateTime<Utc>,
+    /// Where the session starts, exclusive.
+    pub cursor: C,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PageStats<C> {
+    pub start_time: DateTime<Utc>,
+    /// The cursor after the first item of the page.
+    pub first: C,
+    /// The cursor after the whole page.
+    pub last: C,
+    pub num_items: i64,
+    pub num_units: i64,
+    pub read_duration: Duration,
+    pub total_duration: Duration,
+    pub session_start_time: DateTime<Utc>,
+    pub session_items_so_far: i64,
+    pub session_units_so_far: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionSummary<C> {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// Where the session started, exclusive.
+    pub start: C,
+    /// Where the session got to, inclusive. The same as `start` if there was nothing to do.
+    pub end: C,
+    pub num_pages: i64,
+    pub num_items: i64,
+    pub num_units: i64,
+    /// Whether the session was stopped before it ran out of pages.
+    pub stopped: bool,
+    pub dry_run: bool,
+}
+
+/// Removes a flag such as `--dry-run` from the arguments, returning whether it was there.
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
@@ -0,0 +1,366 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
+use postgres_db::connection::DbConnection;
+use postgres_db::notifications::{self, Listener};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+
+use crate::{PageStats, PipelineStage, SessionStart, SessionSummary, StageError};
+
+/// How long a daemon sleeps at most when there are no notifications, in case it missed some.
+pub const DAEMON_POLL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How often a sleeping daemon checks whether it should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub enum PageSize {
+    Fixed(i64),
+    /// Starts at `initial`, and once there are `history` pages, estimates the page size that
+    /// gives about `target_items` items from the ratio of items to units in the last `history`
+    /// pages, staying within `min..=max`.
+    Adaptive {
+        initial: i64,
+        target_items: i64,
+        min: i64,
+        max: i64,
+        history: usize,
+    },
+}
+
+struct PageSizer {
+    page_size: PageSize,
+    current: i64,
+    /// The (items, units) of the latest pages, newest first.
+    history: VecDeque<(i64, i64)>,
+}
+
+impl PageSizer {
+    fn new(page_size: PageSize) -> PageSizer {
+        let current = match page_size {
+            PageSize::Fixed(size) => size,
+            PageSize::Adaptive { initial, .. } => initial,
+        };
+        PageSizer {
+            page_size,
+            current,
+            history: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, num_items: i64, num_units: i64) {
+        let (target_items, min, max, history_size) = match self.page_size {
+            PageSize::Fixed(_) => return,
+            PageSize::Adaptive {
+                target_items,
+                min,
+                max,
+                history,
+                ..
+            } => (target_items, min, max, history),
+        };
+
+        self.history.push_front((num_items, num_units));
+        self.history.truncate(history_size);
+        if self.history.len() < history_size {
+            return;
+        }
+
+        let items_sum: i64 = self.history.iter().map(|(items, _)| items).sum();
+        let units_sum: i64 = self.history.iter().map(|(_, units)| units).sum();
+        if units_sum == 0 {
+            return;
+        }
+        let ratio_est = (items_sum as f64) / (units_sum as f64);
+        self.current = (((target_items as f64) / ratio_est) as i64).clamp(min, max);
+    }
+}
+
+/// Runs [`PipelineStage`]s page by page until they run out of pages or are stopped.
+pub struct Runner {
+    page_size: PageSize,
+    metrics_logger: MetricsLogger,
+    dry_run: bool,
+    stop: Arc<AtomicBool>,
+}
+
+impl Runner {
+    pub fn new(page_size: PageSize, metrics_logger: MetricsLogger) -> Runner {
+        Runner {
+            page_size,
+            metrics_logger,
+            dry_run: false,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// In a dry run every page is processed in a transaction that is rolled back, so the saved
+    /// cursor stays where it was. See [`PipelineStage::page_rolled_back`].
+    pub fn dry_run(mut self, dry_run: bool) -> Runner {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Stops after the current page on SIGTERM or SIGINT. A second signal exits right away.
+    pub fn stop_on_signals(self) -> Runner {
+        for signal in [SIGTERM, SIGINT] {
+            // registered first, so that it only sees the flag set by an earlier signal
+            flag::register_conditional_shutdown(signal, 1, self.stop.clone())
+                .expect("failed to register signal handler");
+            flag::register(signal, self.stop.clone()).expect("failed to register signal handler");
+        }
+        self
+    }
+
+    /// Once set, the runner stops after the current page.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Runs one session, which goes until there are no pages left.
+    pub fn run<S: PipelineStage>(
+        &mut self,
+        stage: &mut S,
+        conn: &mut DbConnection,
+    ) -> SessionSummary<S::Cursor> {
+        let summary = self.run_session(stage, conn);
+        stage.shut_down(conn, &summary);
+        summary
+    }
+
+    /// Runs sessions until stopped: one right away, and then another whenever `listener` gets a
+    /// notification, or after `poll_interval` at the latest. The listener should be listening
+    /// before this is called, so that nothing notified during the first session is missed.
+    pub fn run_daemon<S: PipelineStage>(
+        &mut self,
+        stage: &mut S,
+        conn: &mut DbConnection,
+        listener: &mut Listener,
+        poll_interval: Duration,
+    ) -> SessionSummary<S::Cursor> {
+        assert!(
+            !self.dry_run,
+            "a dry run can't be a daemon, since every session would redo the same pages"
+        );
+        let summary = loop {
+            let summary = self.run_session(stage, conn);
+            if summary.stopped || !self.sleep_until_notified(listener, poll_interval) {
+                break summary;
+            }
+        };
+        stage.shut_down(conn, &summary);
+        summary
+    }
+
+    /// Returns false if the runner was stopped while sleeping.
+    fn sleep_until_notified(&self, listener: &mut Listener, poll_interval: Duration) -> bool {
+        let sleep_start = Instant::now();
+        loop {
+            if self.stop.load(Ordering::SeqCst) {
+                return false;
+            }
+            let slept = sleep_start.elapsed();
+            if slept >= poll_interval
+                || listener.wait((poll_interval - slept).min(STOP_CHECK_INTERVAL))
+            {
+                return true;
+            }
+        }
+    }
+
+    fn run_session<S: PipelineStage>(
+        &mut self,
+        stage: &mut S,
+        conn: &mut DbConnection,
+    ) -> SessionSummary<S::Cursor> {
+        let session_start_time = Utc::now();
+        let start = stage.load_cursor(conn);
+        let dry_run = self.dry_run;
+        let _session = tracing::info_span!("session", stage = stage.name(), dry_run).entered();
+        tracing::info!(start = ?start, "Starting session");
+
+        self.metrics_logger
+            .log_event(MetricsEvent::new(stage.name(), EventKind::SessionStart));
+        stage.start_session(
+            conn,
+            &mut self.metrics_logger,
+            &SessionStart {
+                start_time: session_start_time,
+                cursor: start.clone(),
+                dry_run,
+            },
+        );
+
+        let mut sizer = PageSizer::new(self.page_size);
+        let mut cursor = start.clone();
+        let (mut num_pages, mut num_items, mut num_units) = (0, 0, 0);
+        let mut stopped = false;
+
+        loop {
+            if self.stop.load(Ordering::SeqCst) {
+                tracing::info!(cursor = ?cursor, "Stopping");
+                stopped = true;
+                break;
+            }
+
+            let page_start = Instant::now();
+            let page_start_time = Utc::now();
+
+            let page = stage.query_page(conn, &cursor, sizer.current);
+            let read_duration = page_start.elapsed();
+            if page.is_empty() {
+                break;
+            }
+
+            let first = stage.advance(&cursor, &page[..1]);
+            let last = stage.advance(&cursor, &page);
+            let _page = tracing::info_span!("page", first = ?first, last = ?last).entered();
+            let page_items = page.len() as i64;
+            let page_units = stage.page_units(&page);
+
+            let result = conn
+                .run_psql_transaction(|mut trans_conn| {
+                    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                        stage.process_page(&mut trans_conn, page)
+                    }))
+                    .unwrap_or_else(|payload| Err(StageError::from_panic(payload)));
+                    match res {
+                        Ok(output) => {
+                            stage.save_cursor(&mut trans_conn, &last);
+                            if let Some(channel) = stage.notify_channel() {
+                                notifications::notify(
+                                    &mut trans_conn,
+                                    channel,
+                                    &format!("{:?}", last),
+                                );
+                            }
+                            Ok((Ok(output), !dry_run))
+                        }
+                        Err(err) => Ok((Err(err), false)),
+                    }
+                })
+                .expect("failed to run the transaction of a page");
+
+            if dry_run || result.is_err() {
+                stage.page_rolled_back(conn);
+            }
+
+            let output = match result {
+                Ok(output) => output,
+                Err(err) => {
+                    tracing::error!(seq = err.seq, message = %err.message, "Page failed");
+                    let mut event = MetricsEvent::new(stage.name(), EventKind::Error)
+                        .count("items", page_items as u64)
+                        .text("message", err.message.clone());
+                    if let Some(seq) = err.seq {
+                        event = event.gauge("seq", seq);
+                    }
+                    self.metrics_logger.log_event(event);
+                    stage.page_failed(&mut self.metrics_logger, &first, &err);
+                    panic::resume_unwind(err.payload);
+                }
+            };
+
+            cursor = last.clone();
+            num_pages += 1;
+            num_items += page_items;
+            num_units += page_units;
+            sizer.record(page_items, page_units);
+
+            let stats = PageStats {
+                start_time: page_start_time,
+                first,
+                last,
+                num_items: page_items,
+                num_units: page_units,
+                read_duration,
+                total_duration: page_start.elapsed(),
+                session_start_time,
+                session_items_so_far: num_items,
+                session_units_so_far: num_units,
+            };
+            let event = MetricsEvent::new(stage.name(), EventKind::Progress)
+                .count("items", page_items as u64)
+                .count("units", page_units as u64)
+                .duration("read_duration", stats.read_duration)
+                .duration("total_duration", stats.total_duration);
+            self.metrics_logger
+                .log_event(stage.progress_event(event, &stats, &output));
+            stage.page_completed(&mut self.metrics_logger, &stats, &output);
+        }
+
+        let summary = SessionSummary {
+            start_time: session_start_time,
+            end_time: Utc::now(),
+            start,
+            end: cursor,
+            num_pages,
+            num_items,
+            num_units,
+            stopped,
+            dry_run,
+        };
+        tracing::info!(
+            num_items = summary.num_items,
+            num_pages = summary.num_pages,
+            end = ?summary.end,
+            "Session done"
+        );
+        self.metrics_logger.log_event(
+            MetricsEvent::new(stage.name(), EventKind::SessionEnd)
+                .count("pages", summary.num_pages as u64)
+                .count("items", summary.num_items as u64)
+                .duration(
+                    "duration",
+                    (summary.end_time - summary.start_time)
+                        .to_std()
+                        .unwrap_or_default(),
+                ),
+        );
+        stage.end_session(conn, &mut self.metrics_logger, &summary);
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageSize, PageSizer};
+
+    #[test]
+    fn test_adaptive_page_size() {
+        let mut sizer = PageSizer::new(PageSize::Adaptive {
+            initial: 256,
+            target_items: 8000,
+            min: 16,
+            max: 16384,
+            history: 2,
+        });
+        sizer.record(1024, 256);
+        assert_eq!(sizer.current, 256);
+        // 4 items per unit
+        sizer.record(1024, 256);
+        assert_eq!(sizer.current, 2000);
+        // the older pages are forgotten, and now there is 1 item per unit
+        sizer.record(256, 256);
+        sizer.record(256, 256);
+        assert_eq!(sizer.current, 8000);
+        sizer.record(1, 256);
+        sizer.record(1, 256);
+        assert_eq!(sizer.current, 16384);
+    }
+
+    #[test]
+    fn test_fixed_page_size() {
+        let mut sizer = PageSizer::new(PageSize::Fixed(1024));
+        for _ in 0..8 {
+            sizer.record(10, 1);
+        }
+        assert_eq!(sizer.current, 1024);
+    }
+}
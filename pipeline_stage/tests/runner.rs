@@ -0,0 +1,226 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use metrics_logging::MetricsLogger;
+use pipeline_stage::{PageSize, PageStats, PipelineStage, Runner, StageError};
+use postgres_db::connection::testing::using_test_db;
+use postgres_db::connection::{DbConnection, DbConnectionInTransaction};
+use postgres_db::internal_state;
+use postgres_db::notifications::{Listener, CHANGE_LOG_CHANNEL, DIFF_LOG_CHANNEL};
+
+/// Processes the seqs 1..=`last`, saving its cursor as the diff log's processed seq.
+#[derive(Default)]
+struct CountingStage {
+    last: Arc<AtomicI64>,
+    processed: Vec<i64>,
+    panic_on: Option<i64>,
+    stop_after_page: Option<Arc<AtomicBool>>,
+    failed: Vec<(i64, String)>,
+    /// The pages processed since the last commit or rollback, like the caches of a real stage.
+    uncommitted: Vec<Vec<i64>>,
+    rolled_back: Vec<Vec<Vec<i64>>>,
+}
+
+impl PipelineStage for CountingStage {
+    type Item = i64;
+    type Cursor = i64;
+    type Output = usize;
+
+    fn name(&self) -> &'static str {
+        "counting_stage"
+    }
+
+    fn load_cursor(&mut self, conn: &mut DbConnection) -> i64 {
+        internal_state::query_diff_log_processed_seq(conn).unwrap_or(0)
+    }
+
+    fn save_cursor(&mut self, conn: &mut DbConnectionInTransaction, cursor: &i64) {
+        internal_state::set_diff_log_processed_seq(*cursor, conn);
+    }
+
+    fn query_page(&mut self, _conn: &mut DbConnection, cursor: &i64, page_size: i64) -> Vec<i64> {
+        (cursor + 1..=self.last.load(Ordering::SeqCst))
+            .take(page_size as usize)
+            .collect()
+    }
+
+    fn advance(&self, _cursor: &i64, page: &[i64]) -> i64 {
+        *page.last().unwrap()
+    }
+
+    fn notify_channel(&self) -> Option<&'static str> {
+        Some(DIFF_LOG_CHANNEL)
+    }
+
+    fn process_page(
+        &mut self,
+        _conn: &mut DbConnectionInTransaction,
+        page: Vec<i64>,
+    ) -> Result<usize, StageError> {
+        if let Some(seq) = self.panic_on.filter(|seq| page.contains(seq)) {
+            panic!("can't process seq {}", seq);
+        }
+        let len = page.len();
+        self.processed.extend(&page);
+        self.uncommitted.push(page);
+        Ok(len)
+    }
+
+    fn page_rolled_back(&mut self, _conn: &mut DbConnection) {
+        self.rolled_back.push(std::mem::take(&mut self.uncommitted));
+    }
+
+    fn page_completed(
+        &mut self,
+        _logger: &mut MetricsLogger,
+        _page: &PageStats<i64>,
+        _output: &usize,
+    ) {
+        self.uncommitted.clear();
+        if let Some(stop) = &self.stop_after_page {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn page_failed(&mut self, _logger: &mut MetricsLogger, first: &i64, err: &StageError) {
+        self.failed.push((*first, err.message.clone()));
+    }
+}
+
+fn counting_stage(last: i64) -> CountingStage {
+    CountingStage {
+        last: Arc::new(AtomicI64::new(last)),
+        ..Default::default()
+    }
+}
+
+fn runner(page_size: i64) -> Runner {
+    Runner::new(PageSize::Fixed(page_size), MetricsLogger::null())
+}
+
+#[test]
+fn test_runs_all_pages() {
+    using_test_db(|conn| {
+        let mut stage = counting_stage(10);
+        let summary = runner(3).run(&mut stage, conn);
+
+        assert_eq!(stage.processed, (1..=10).collect::<Vec<_>>());
+        assert_eq!((summary.start, summary.end), (0, 10));
+        assert_eq!((summary.num_pages, summary.num_items), (4, 10));
+        assert!(!summary.stopped);
+        assert_eq!(internal_state::query_diff_log_processed_seq(conn), Some(10));
+
+        // nothing left to do
+        let summary = runner(3).run(&mut stage, conn);
+        assert_eq!((summary.start, summary.end, summary.num_pages), (10, 10, 0));
+    });
+}
+
+#[test]
+fn test_dry_run_leaves_cursor() {
+    using_test_db(|conn| {
+        let mut stage = counting_stage(10);
+        let summary = runner(4).dry_run(true).run(&mut stage, conn);
+
+        assert_eq!(stage.processed, (1..=10).collect::<Vec<_>>());
+        assert_eq!(summary.end, 10);
+        assert_eq!(internal_state::query_diff_log_processed_seq(conn), None);
+    });
+}
+
+#[test]
+fn test_dry_run_rolls_back_every_page() {
+    using_test_db(|conn| {
+        let mut stage = counting_stage(8);
+        let summary = runner(4).dry_run(true).run(&mut stage, conn);
+
+        assert_eq!(summary.num_pages, 2);
+        // the second page doesn't see anything the first one did
+        assert_eq!(
+            stage.rolled_back,
+            vec![vec![vec![1, 2, 3, 4]], vec![vec![5, 6, 7, 8]]]
+        );
+        assert_eq!(internal_state::query_diff_log_processed_seq(conn), None);
+
+        // nothing is rolled back outside of a dry run
+        let mut stage = counting_stage(8);
+        runner(4).run(&mut stage, conn);
+        assert!(stage.rolled_back.is_empty());
+    });
+}
+
+#[test]
+fn test_stops_after_current_page_and_resumes() {
+    using_test_db(|conn| {
+        let mut stopping = runner(4);
+        let mut stage = counting_stage(10);
+        stage.stop_after_page = Some(stopping.stop_flag());
+        let summary = stopping.run(&mut stage, conn);
+
+        assert!(summary.stopped);
+        assert_eq!(stage.processed, vec![1, 2, 3, 4]);
+        assert_eq!(internal_state::query_diff_log_processed_seq(conn), Some(4));
+
+        stage.stop_after_page = None;
+        let summary = runner(4).run(&mut stage, conn);
+        assert!(!summary.stopped);
+        assert_eq!(summary.start, 4);
+        assert_eq!(stage.processed, (1..=10).collect::<Vec<_>>());
+    });
+}
+
+#[test]
+fn test_panics_roll_back_page() {
+    using_test_db(|conn| {
+        let mut stage = counting_stage(10);
+        stage.panic_on = Some(6);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| runner(4).run(&mut stage, conn)));
+
+        assert!(res.is_err());
+        assert_eq!(stage.failed, vec![(5, "can't process seq 6".to_string())]);
+        // the panic was before the failed page got to the stage's state
+        assert_eq!(stage.rolled_back, vec![Vec::<Vec<i64>>::new()]);
+        assert_eq!(stage.processed, vec![1, 2, 3, 4]);
+        assert_eq!(internal_state::query_diff_log_processed_seq(conn), Some(4));
+    });
+}
+
+#[test]
+fn test_daemon_runs_when_notified() {
+    using_test_db(|conn| {
+        let url = std::env::var("TESTING_DATABASE_URL").unwrap();
+        let mut listener = Listener::connect_to(&url, &[CHANGE_LOG_CHANNEL]);
+        let mut downstream = Listener::connect_to(&url, &[DIFF_LOG_CHANNEL]);
+
+        let mut daemon = runner(4);
+        let stop = daemon.stop_flag();
+        let mut stage = counting_stage(10);
+        let last = stage.last.clone();
+
+        // stands in for the upstream stage
+        let upstream = thread::spawn(move || {
+            let mut client = postgres::Client::connect(&url, postgres::NoTls).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            last.store(15, Ordering::SeqCst);
+            client
+                .batch_execute(&format!("NOTIFY {}", CHANGE_LOG_CHANNEL))
+                .unwrap();
+            thread::sleep(Duration::from_millis(300));
+            stop.store(true, Ordering::SeqCst);
+        });
+
+        let started = Instant::now();
+        let summary = daemon.run_daemon(&mut stage, conn, &mut listener, Duration::from_secs(60));
+        upstream.join().unwrap();
+
+        // woken up by the notification, not by polling
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert_eq!(stage.processed, (1..=15).collect::<Vec<_>>());
+        assert_eq!(summary.end, 15);
+        assert_eq!(internal_state::query_diff_log_processed_seq(conn), Some(15));
+        assert!(downstream.wait(Duration::from_millis(200)));
+    });
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use postgres_db::connection::DbConnection;
+use postgres_db::{change_log, download_metrics, download_queue, download_tarball, internal_state};
+use serde::Serialize;
+
+/// Stages without their own threshold count as stalled once their lag is longer than this.
+const DEFAULT_STALL_THRESHOLD_SECS: i64 = 60 * 60;
+
+/// The npm API only has download counts up to yesterday, and the metrics are updated weekly, so
+/// they are always some days behind.
+const DEFAULT_DOWNLOAD_METRICS_STALL_THRESHOLD_SECS: i64 = 9 * 24 * 60 * 60;
+
+pub const STAGES: [&str; 7] = [
+    "changes_fetcher",
+    "diff_log_builder",
+    "relational_db_builder",
+    "download_queuer",
+    "downloader",
+    "tarball_transfer",
+    "download_metrics",
+];
+
+/// How long each stage may lag before it counts as stalled.
+#[derive(Debug, Clone)]
+pub struct StallThresholds {
+    pub default: Duration,
+    pub per_stage: HashMap<&'static str, Duration>,
+}
+
+impl Default for StallThresholds {
+    fn default() -> Self {
+        StallThresholds {
+            default: Duration::seconds(DEFAULT_STALL_THRESHOLD_SECS),
+            per_stage: HashMap::from([(
+                "download_metrics",
+                Duration::seconds(DEFAULT_DOWNLOAD_METRICS_STALL_THRESHOLD_SECS),
+            )]),
+        }
+    }
+}
+
+impl StallThresholds {
+    /// Reads `STALL_THRESHOLD_SECS` for the default threshold, and e.g.
+    /// `STALL_THRESHOLD_SECS_DOWNLOADER` for the threshold of a single stage.
+    pub fn from_env() -> Result<StallThresholds, String> {
+        dotenv::dotenv().ok();
+
+        let mut thresholds = StallThresholds::default();
+        if let Some(secs) = secs_from_env("STALL_THRESHOLD_SECS")? {
+            thresholds.default = secs;
+        }
+        for stage in STAGES {
+            let var = format!("STALL_THRESHOLD_SECS_{}", stage.to_uppercase());
+            if let Some(secs) = secs_from_env(&var)? {
+                thresholds.per_stage.insert(stage, secs);
+            }
+        }
+        Ok(thresholds)
+    }
+
+    pub fn for_stage(&self, stage: &str) -> Duration {
+        self.per_stage.get(stage).copied().unwrap_or(self.default)
+    }
+}
+
+fn secs_from_env(var: &str) -> Result<Option<Duration>, String> {
+    match std::env::var(var) {
+        Ok(s) => s
+            .parse()
+            .map(|secs| Some(Duration::seconds(secs)))
+            .map_err(|_| format!("{} must be a number of seconds, got {:?}", var, s)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStatus {
+    pub stage: &'static str,
+    /// The last seq the stage processed, for the stages that follow the change log. For
+    /// `changes_fetcher`, the latest seq in the change log.
+    pub seq: Option<i64>,
+    /// When the change log received `seq`.
+    pub seq_received_time: Option<DateTime<Utc>>,
+    /// How much the stage has left to do, in `unit`s. For the stages that follow the change log,
+    /// this is the difference between the latest seq and `seq`, and for `changes_fetcher`, the
+    /// difference between npm's `update_seq` and the latest seq.
+    pub behind: Option<i64>,
+    pub unit: &'static str,
+    /// How long the oldest thing the stage has left to do has been waiting, e.g. since the
+    /// change log received the first change after `seq`. For `changes_fetcher`, the time since
+    /// the latest change was received. `None` if the stage is caught up, or it's unknown.
+    pub lag_secs: Option<i64>,
+    pub stall_threshold_secs: i64,
+    /// Whether the lag is longer than the stall threshold.
+    pub stalled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub time: DateTime<Utc>,
+    /// npm's latest seq, if it was queried.
+    pub npm_seq: Option<i64>,
+    pub stages: Vec<StageStatus>,
+}
+
+impl StatusReport {
+    pub fn stalled_stages(&self) -> Vec<&'static str> {
+        self.stages
+            .iter()
+            .filter(|s| s.stalled)
+            .map(|s| s.stage)
+            .collect()
+    }
+}
+
+/// Queries how far behind each stage is at time `now`. `npm_seq` is npm's latest seq, if known.
+pub fn query_status(
+    conn: &mut DbConnection,
+    npm_seq: Option<i64>,
+    thresholds: &StallThresholds,
+    now: DateTime<Utc>,
+) -> StatusReport {
+    let latest_seq = change_log::query_latest_change_seq(conn);
+    let mut report = StatusReport {
+        time: now,
+        npm_seq,
+        stages: vec![],
+    };
+
+    let mut add_stage = |stage: &'static str,
+                         seq: Option<i64>,
+                         seq_received_time: Option<DateTime<Utc>>,
+                         behind: Option<i64>,
+                         unit: &'static str,
+                         oldest_waiting: Option<DateTime<Utc>>| {
+        let threshold = thresholds.for_stage(stage);
+        let lag = oldest_waiting.map(|t| now - t);
+        report.stages.push(StageStatus {
+            stage,
+            seq,
+            seq_received_time,
+            behind,
+            unit,
+            lag_secs: lag.map(|lag| lag.num_seconds()),
+            stall_threshold_secs: threshold.num_seconds(),
+            stalled: lag.is_some_and(|lag| lag > threshold),
+        });
+    };
+
+    let latest_received_time =
+        latest_seq.and_then(|seq| change_log::query_received_time_of_seq(seq, conn));
+    add_stage(
+        "changes_fetcher",
+        latest_seq,
+        latest_received_time,
+        npm_seq.map(|npm_seq| npm_seq - latest_seq.unwrap_or(0)),
+        "seqs",
+        latest_received_time,
+    );
+
+    let seq_stages = [
+        (
+            "diff_log_builder",
+            internal_state::query_diff_log_processed_seq(conn),
+        ),
+        (
+            "relational_db_builder",
+            internal_state::query_relational_processed_seq(conn),
+        ),
+        (
+            "download_queuer",
+            internal_state::query_queued_downloads_seq(conn),
+        ),
+    ];
+    for (stage, seq) in seq_stages {
+        let after_seq = seq.unwrap_or(0);
+        add_stage(
+            stage,
+            seq,
+            seq.and_then(|seq| change_log::query_received_time_of_seq(seq, conn)),
+            latest_seq.map(|latest_seq| latest_seq - after_seq),
+            "seqs",
+            change_log::query_received_time_after_seq(after_seq, conn),
+        );
+    }
+
+    let (num_pending, oldest_queued) = download_queue::query_pending_tasks_stats(conn);
+    add_stage(
+        "downloader",
+        None,
+        None,
+        Some(num_pending),
+        "tasks",
+        oldest_queued,
+    );
+
+    let last_url = internal_state::query_tarball_transfer_last(conn).map(|(url, _)| url);
+    let (num_untransferred, oldest_downloaded) =
+        download_tarball::query_tarballs_after_url_stats(conn, last_url.as_deref());
+    add_stage(
+        "tarball_transfer",
+        None,
+        None,
+        Some(num_untransferred),
+        "tarballs",
+        oldest_downloaded,
+    );
+
+    let stale_before = (now - thresholds.for_stage("download_metrics")).date_naive();
+    let (oldest_latest_date, num_stale) =
+        download_metrics::query_latest_date_stats(conn, stale_before);
+    add_stage(
+        "download_metrics",
+        None,
+        None,
+        Some(num_stale),
+        "stale metrics",
+        oldest_latest_date.map(|d| Utc.from_utc_datetime(&d.and_time(NaiveTime::MIN))),
+    );
+
+    report
+}
+
+/// Queries npm's latest seq.
+pub async fn query_npm_update_seq() -> Result<i64, String> {
+    let resp: serde_json::Value = reqwest::get("https://replicate.npmjs.com")
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    resp.get("update_seq")
+        .and_then(|seq| seq.as_i64())
+        .ok_or_else(|| format!("no update_seq in {}", resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use postgres_db::change_log;
+    use postgres_db::connection::testing::using_test_db;
+    use postgres_db::internal_state;
+
+    use super::{query_status, StallThresholds};
+
+    #[test]
+    fn test_seq_stage_lag() {
+        using_test_db(|conn| {
+            let t0 = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap();
+            for seq in 1..=10 {
+                change_log::insert_change(
+                    conn,
+                    seq,
+                    serde_json::json!({}),
+                    t0 + Duration::minutes(seq),
+                );
+            }
+            internal_state::set_diff_log_processed_seq(10, conn);
+            internal_state::set_relational_processed_seq(4, conn);
+
+            let now = t0 + Duration::hours(2);
+            let report = query_status(conn, Some(15), &StallThresholds::default(), now);
+            let stage = |name| report.stages.iter().find(|s| s.stage == name).unwrap();
+
+            let fetcher = stage("changes_fetcher");
+            assert_eq!(fetcher.seq, Some(10));
+            assert_eq!(fetcher.behind, Some(5));
+            assert_eq!(fetcher.lag_secs, Some(110 * 60));
+            assert!(fetcher.stalled);
+
+            let diff_log = stage("diff_log_builder");
+            assert_eq!(diff_log.behind, Some(0));
+            assert_eq!(diff_log.lag_secs, None);
+            assert!(!diff_log.stalled);
+
+            let relational = stage("relational_db_builder");
+            assert_eq!(relational.behind, Some(6));
+            assert_eq!(
+                relational.seq_received_time,
+                Some(t0 + Duration::minutes(4))
+            );
+            // waiting since seq 5 was received
+            assert_eq!(relational.lag_secs, Some(115 * 60));
+            assert!(relational.stalled);
+
+            // never ran, so it's behind by everything
+            let queuer = stage("download_queuer");
+            assert_eq!(queuer.seq, None);
+            assert_eq!(queuer.behind, Some(10));
+            assert_eq!(queuer.lag_secs, Some(119 * 60));
+
+            assert_eq!(
+                report.stalled_stages(),
+                vec![
+                    "changes_fetcher",
+                    "relational_db_builder",
+                    "download_queuer"
+                ]
+            );
+        });
+    }
+}
@@ -0,0 +1,177 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use pipeline_status::{query_npm_update_seq, query_status, StallThresholds, StatusReport};
+use postgres_db::connection::DbConnection;
+
+const USAGE: &str = "[--json] [--no-npm] [--serve <addr>]";
+
+/// Reports how far behind npm each stage is. Prints a table, or JSON with `--json`, and exits
+/// with status 2 if any stage is stalled. With `--serve`, serves the JSON on `/status` instead,
+/// with status 503 if any stage is stalled.
+#[tokio::main]
+async fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let program = &args[0];
+
+    let mut json = false;
+    let mut query_npm = true;
+    let mut serve_addr = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--no-npm" => query_npm = false,
+            "--serve" => {
+                let addr = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                serve_addr = Some(
+                    addr.parse::<SocketAddr>()
+                        .unwrap_or_else(|_| exit_with_usage(program)),
+                );
+            }
+            _ => exit_with_usage(program),
+        }
+    }
+
+    let thresholds = StallThresholds::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let conn = Arc::new(Mutex::new(DbConnection::connect()));
+
+    if let Some(addr) = serve_addr {
+        serve(addr, conn, thresholds, query_npm).await;
+        return;
+    }
+
+    let report = status(conn, thresholds, query_npm).await;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print_table(&report);
+    }
+    if !report.stalled_stages().is_empty() {
+        std::process::exit(2);
+    }
+}
+
+async fn status(
+    conn: Arc<Mutex<DbConnection>>,
+    thresholds: StallThresholds,
+    query_npm: bool,
+) -> StatusReport {
+    let npm_seq = if query_npm {
+        match query_npm_update_seq().await {
+            Ok(seq) => Some(seq),
+            Err(e) => {
+                eprintln!("Failed to query npm's latest seq: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+        query_status(&mut conn, npm_seq, &thresholds, Utc::now())
+    })
+    .await
+    .expect("failed to query the status")
+}
+
+async fn serve(
+    addr: SocketAddr,
+    conn: Arc<Mutex<DbConnection>>,
+    thresholds: StallThresholds,
+    query_npm: bool,
+) {
+    let make_svc = make_service_fn(move |_| {
+        let conn = conn.clone();
+        let thresholds = thresholds.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let conn = conn.clone();
+                let thresholds = thresholds.clone();
+                async move {
+                    if req.method() != Method::GET || req.uri().path() != "/status" {
+                        let mut not_found = Response::new(Body::empty());
+                        *not_found.status_mut() = StatusCode::NOT_FOUND;
+                        return Ok::<_, Infallible>(not_found);
+                    }
+
+                    let report = status(conn, thresholds, query_npm).await;
+                    let code = if report.stalled_stages().is_empty() {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    };
+                    let body = serde_json::to_string(&report).unwrap();
+                    Ok(Response::builder()
+                        .status(code)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body))
+                        .unwrap())
+                }
+            }))
+        }
+    });
+
+    println!("Serving status on http://{}/status", addr);
+    if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+        eprintln!("Status server failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn print_table(report: &StatusReport) {
+    if let Some(npm_seq) = report.npm_seq {
+        println!("npm is at seq {}", npm_seq);
+    }
+    println!(
+        "{:<22} {:>12} {:>26} {:>12}",
+        "stage", "seq", "behind", "lag"
+    );
+    for stage in &report.stages {
+        let seq = stage.seq.map(|s| s.to_string()).unwrap_or_default();
+        let behind = stage
+            .behind
+            .map(|b| format!("{} {}", b, stage.unit))
+            .unwrap_or_else(|| "?".to_string());
+        let lag = stage.lag_secs.map(format_secs).unwrap_or_default();
+        println!(
+            "{:<22} {:>12} {:>26} {:>12}{}",
+            stage.stage,
+            seq,
+            behind,
+            lag,
+            if stage.stalled {
+                format!("  STALLED (> {})", format_secs(stage.stall_threshold_secs))
+            } else {
+                String::new()
+            }
+        );
+    }
+}
+
+fn format_secs(secs: i64) -> String {
+    let (days, hours, mins) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
@@ -0,0 +1 @@
+DROP TABLE diff_log_quarantine;
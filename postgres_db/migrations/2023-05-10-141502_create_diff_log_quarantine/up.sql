@@ -0,0 +1,11 @@
+CREATE TABLE diff_log_quarantine (
+  seq BIGINT PRIMARY KEY NOT NULL,
+  package_name TEXT,
+  panic_message TEXT NOT NULL,
+  json_path TEXT,
+  quarantined_time TIMESTAMP WITH TIME ZONE NOT NULL,
+  num_retries INTEGER NOT NULL DEFAULT 0
+
+  -- same as diff_log, we don't enforce this so the DB can be exported without change_log
+  -- FOREIGN KEY(seq) REFERENCES change_log(seq)
+);
@@ -0,0 +1 @@
+ALTER TABLE vulnerabilities DROP COLUMN vulnerable_version_constraint;
@@ -0,0 +1,39 @@
+-- The exact set of vulnerable versions. The bound columns are kept for compatibility, but can't
+-- represent every range GHSA gives us.
+ALTER TABLE vulnerabilities ADD COLUMN vulnerable_version_constraint constraint_disjuncts;
+
+-- We don't have the original range strings, so existing rows get the constraint their bounds
+-- describe. Running ghsa_scraper again replaces them with the exact ranges.
+UPDATE vulnerabilities
+SET vulnerable_version_constraint = ARRAY [
+    ROW(
+      CASE
+        WHEN vulnerable_version_lower_bound IS NULL
+        AND vulnerable_version_upper_bound IS NULL THEN ARRAY [ROW('*', NULL)::version_comparator]
+        WHEN vulnerable_version_upper_bound IS NULL THEN ARRAY [
+          ROW(
+            (CASE WHEN vulnerable_version_lower_bound_inclusive THEN '>=' ELSE '>' END)::version_operator_enum,
+            vulnerable_version_lower_bound
+          )::version_comparator
+        ]
+        WHEN vulnerable_version_lower_bound IS NULL THEN ARRAY [
+          ROW(
+            (CASE WHEN vulnerable_version_upper_bound_inclusive THEN '<=' ELSE '<' END)::version_operator_enum,
+            vulnerable_version_upper_bound
+          )::version_comparator
+        ]
+        ELSE ARRAY [
+          ROW(
+            (CASE WHEN vulnerable_version_lower_bound_inclusive THEN '>=' ELSE '>' END)::version_operator_enum,
+            vulnerable_version_lower_bound
+          )::version_comparator,
+          ROW(
+            (CASE WHEN vulnerable_version_upper_bound_inclusive THEN '<=' ELSE '<' END)::version_operator_enum,
+            vulnerable_version_upper_bound
+          )::version_comparator
+        ]
+      END::constraint_conjuncts
+    )::constraint_conjuncts_struct
+  ]::constraint_disjuncts;
+
+ALTER TABLE vulnerabilities ALTER COLUMN vulnerable_version_constraint SET NOT NULL;
@@ -0,0 +1 @@
+DROP TABLE ghsa_history;
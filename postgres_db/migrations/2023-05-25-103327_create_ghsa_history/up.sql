@@ -0,0 +1,26 @@
+-- Every revision of an advisory that ghsa_scraper has seen, including its vulnerable ranges as
+-- GHSA gave them. Rows are only ever appended, and only when an advisory differs from its
+-- previous revision.
+CREATE TABLE ghsa_history (
+  id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  ghsa_id TEXT NOT NULL,
+  -- When the scraper saw this revision
+  observed_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  severity TEXT NOT NULL,
+  description TEXT NOT NULL,
+  summary TEXT NOT NULL,
+  withdrawn_at TIMESTAMP WITH TIME ZONE,
+  published_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  refs TEXT [] NOT NULL,
+  cvss_score real,
+  cvss_vector TEXT,
+  cwe_ids TEXT [] NOT NULL,
+  -- Array of {"package_name", "vulnerable_version_range", "first_patched_version"}, where the
+  -- versions are the raw strings from GHSA
+  vulnerabilities JSONB NOT NULL,
+  -- Hash of everything above except observed_at, to tell whether anything changed
+  revision_hash TEXT NOT NULL
+);
+
+CREATE INDEX ghsa_history_ghsa_id_idx ON ghsa_history (ghsa_id, id);
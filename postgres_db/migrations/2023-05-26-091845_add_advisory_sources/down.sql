@@ -0,0 +1,7 @@
+DROP TABLE ghsa_aliases;
+
+DROP INDEX ghsa_history_ghsa_id_idx;
+CREATE INDEX ghsa_history_ghsa_id_idx ON ghsa_history (ghsa_id, id);
+
+ALTER TABLE ghsa_history DROP COLUMN source;
+ALTER TABLE ghsa DROP COLUMN source;
@@ -0,0 +1,18 @@
+-- Where each advisory (and each revision of it) came from: 'github' for ghsa_scraper, 'osv' for
+-- osv_importer. Everything before this came from ghsa_scraper.
+ALTER TABLE ghsa ADD COLUMN source TEXT NOT NULL DEFAULT 'github';
+ALTER TABLE ghsa_history ADD COLUMN source TEXT NOT NULL DEFAULT 'github';
+
+-- Each source has its own history of an advisory
+DROP INDEX ghsa_history_ghsa_id_idx;
+CREATE INDEX ghsa_history_ghsa_id_idx ON ghsa_history (ghsa_id, source, id);
+
+-- Other ids of an advisory (CVE, OSV, ...), as each source reports them
+CREATE TABLE ghsa_aliases (
+  ghsa_id TEXT NOT NULL REFERENCES ghsa (id),
+  alias TEXT NOT NULL,
+  source TEXT NOT NULL,
+  PRIMARY KEY (ghsa_id, alias, source)
+);
+
+CREATE INDEX ghsa_aliases_alias_idx ON ghsa_aliases (alias);
@@ -0,0 +1,2 @@
+DROP TABLE version_exposures;
+DROP TABLE version_vulnerabilities;
@@ -0,0 +1,29 @@
+-- Versions that an advisory directly affects, i.e. that satisfy the vulnerable_version_constraint
+-- of one of its vulnerabilities. Prereleases count, unlike in npm ranges.
+CREATE TABLE version_vulnerabilities (
+  version_id BIGINT NOT NULL,
+  ghsa_id TEXT NOT NULL,
+  PRIMARY KEY (version_id, ghsa_id)
+);
+
+CREATE INDEX version_vulnerabilities_ghsa_id_idx ON version_vulnerabilities (ghsa_id);
+
+-- When installing a version would have installed a version affected by an advisory through its
+-- dependencies, assuming each dependency resolves to the greatest satisfying version that existed
+-- at the time. This says nothing about whether the advisory was known yet, see ghsa.published_at.
+-- The windows of a version and advisory don't overlap, and each has the shortest exposing path.
+CREATE TABLE version_exposures (
+  id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  version_id BIGINT NOT NULL,
+  ghsa_id TEXT NOT NULL,
+  exposed_from TIMESTAMP WITH TIME ZONE NOT NULL,
+  -- NULL if still exposed
+  exposed_until TIMESTAMP WITH TIME ZONE,
+  -- The number of dependencies between this version and the affected one
+  depth INTEGER NOT NULL,
+  -- The versions installed along the way, ending with the affected one
+  path BIGINT [] NOT NULL
+);
+
+CREATE INDEX version_exposures_version_id_idx ON version_exposures (version_id, ghsa_id);
+CREATE INDEX version_exposures_ghsa_id_idx ON version_exposures (ghsa_id);
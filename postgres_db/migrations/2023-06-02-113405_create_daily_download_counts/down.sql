@@ -0,0 +1,5 @@
+ALTER TABLE download_metrics ALTER COLUMN download_counts DROP DEFAULT;
+DROP VIEW download_counts_monthly;
+DROP VIEW download_counts_weekly;
+DROP TABLE download_count_gaps;
+DROP TABLE download_counts_daily;
@@ -0,0 +1,64 @@
+-- Daily npm download counts. Days from the start of the data through download_metrics.latest_date
+-- that have no row were zero, unless they're in a download_count_gaps row.
+CREATE TABLE download_counts_daily (
+  package_id BIGINT NOT NULL,
+  day DATE NOT NULL,
+  count BIGINT NOT NULL CHECK (count > 0),
+  PRIMARY KEY (package_id, day)
+) PARTITION BY RANGE (day);
+
+-- One partition per year. `download_metrics` creates the partitions for later years when it starts.
+DO $$
+BEGIN
+  FOR y IN 2015..2023 LOOP
+    EXECUTE format(
+      'CREATE TABLE download_counts_daily_%s PARTITION OF download_counts_daily FOR VALUES FROM (%L) TO (%L)',
+      y, make_date(y, 1, 1), make_date(y + 1, 1, 1)
+    );
+  END LOOP;
+END
+$$;
+
+-- Inclusive ranges of days we don't know the counts of, because the API failed for them or
+-- returned null counts. Never overlaps with rows in download_counts_daily.
+CREATE TABLE download_count_gaps (
+  package_id BIGINT NOT NULL,
+  start_date DATE NOT NULL,
+  end_date DATE NOT NULL CHECK (start_date <= end_date),
+  PRIMARY KEY (package_id, start_date)
+);
+
+CREATE VIEW download_counts_weekly AS
+WITH days AS (
+  SELECT package_id, day, count FROM download_counts_daily
+  UNION ALL
+  SELECT package_id, generate_series(start_date, end_date, INTERVAL '1 day')::DATE, NULL
+  FROM download_count_gaps
+)
+SELECT
+  package_id,
+  date_trunc('week', day)::DATE AS week,
+  COALESCE(SUM(count), 0)::BIGINT AS count,
+  -- the count is a lower bound if any days are missing
+  COUNT(*) FILTER (WHERE count IS NULL)::INTEGER AS missing_days
+FROM days
+GROUP BY package_id, week;
+
+CREATE VIEW download_counts_monthly AS
+WITH days AS (
+  SELECT package_id, day, count FROM download_counts_daily
+  UNION ALL
+  SELECT package_id, generate_series(start_date, end_date, INTERVAL '1 day')::DATE, NULL
+  FROM download_count_gaps
+)
+SELECT
+  package_id,
+  date_trunc('month', day)::DATE AS month,
+  COALESCE(SUM(count), 0)::BIGINT AS count,
+  COUNT(*) FILTER (WHERE count IS NULL)::INTEGER AS missing_days
+FROM days
+GROUP BY package_id, month;
+
+-- The weekly counts are superseded by download_counts_daily, and emptied when a package's history
+-- is fetched again with `download_metrics backfill`.
+ALTER TABLE download_metrics ALTER COLUMN download_counts SET DEFAULT '{}';
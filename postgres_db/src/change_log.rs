@@ -56,6 +56,74 @@ pub fn query_changes_after_seq(
     })
 }
 
+/// Returns an upper bound (inclusive) on the seqs which had been received by time `t`,
+/// for replaying history as of `t`. Changes without a receive time predate the ones with one,
+/// so they are always included. Returns `None` if no change was received after `t`.
+pub fn query_seq_received_as_of<R: QueryRunner>(t: DateTime<Utc>, conn: &mut R) -> Option<i64> {
+    use diesel::dsl::*;
+    use schema::change_log::dsl::*;
+
+    let first_after: Option<i64> = conn
+        .first(change_log.filter(received_time.gt(t)).select(min(seq)))
+        .unwrap_or_else(|e| panic!("Error querying DB for changes received after {}: {}", t, e));
+
+    first_after.map(|s| s - 1)
+}
+
+/// The receive time of the first change after `after_seq`, i.e. of the oldest change a stage
+/// that processed up to `after_seq` still has to process. Returns `None` if there is no such
+/// change, or if it predates receive times.
+pub fn query_received_time_after_seq<R: QueryRunner>(
+    after_seq: i64,
+    conn: &mut R,
+) -> Option<DateTime<Utc>> {
+    use schema::change_log::dsl::*;
+
+    let nested: Option<Option<DateTime<Utc>>> = conn
+        .first(
+            change_log
+                .filter(seq.gt(after_seq))
+                .order(seq)
+                .select(received_time),
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            panic!(
+                "Error querying DB for the receive time after seq {}: {}",
+                after_seq, e
+            )
+        });
+
+    nested.flatten()
+}
+
+/// The receive time of the change with seq `the_seq`, if there is one and it has one.
+pub fn query_received_time_of_seq<R: QueryRunner>(
+    the_seq: i64,
+    conn: &mut R,
+) -> Option<DateTime<Utc>> {
+    use schema::change_log::dsl::*;
+
+    let nested: Option<Option<DateTime<Utc>>> = conn
+        .first(change_log.filter(seq.eq(the_seq)).select(received_time))
+        .optional()
+        .unwrap_or_else(|e| {
+            panic!(
+                "Error querying DB for the receive time of seq {}: {}",
+                the_seq, e
+            )
+        });
+
+    nested.flatten()
+}
+
+pub fn query_changes_by_seqs<R: QueryRunner>(seqs: &[i64], conn: &mut R) -> Vec<Change> {
+    use schema::change_log::dsl::*;
+
+    conn.load(change_log.filter(seq.eq_any(seqs)).order(seq))
+        .unwrap_or_else(|e| panic!("Error querying DB for changes by seq: {}", e))
+}
+
 #[derive(Insertable, Debug)]
 #[diesel(table_name = change_log)]
 struct NewChange<'a> {
@@ -30,7 +30,7 @@ impl DbConnection {
         dotenv().expect("failed to load .env");
 
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        println!("using database url = {}", database_url);
+        tracing::debug!(database_url, "Connecting to the database");
         let mgr = bb8_diesel::DieselConnectionManager::<PgConnection>::new(&database_url);
         let pool = bb8::Pool::builder()
             .build(mgr)
@@ -86,6 +86,26 @@ impl DbConnection {
     }
 }
 
+impl DbConnection {
+    /// Runs synchronous queries (e.g. the functions taking a `super::QueryRunner`) on a
+    /// connection from the pool, outside of a transaction. The closure is run with
+    /// `block_in_place`, so this requires the multi-threaded tokio runtime.
+    pub async fn run_blocking<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut DbConnectionInTransaction) -> R,
+    {
+        let mut conn = self.pool.get().await.unwrap();
+
+        tokio::task::block_in_place(|| {
+            let mut borrowed_conn = DbConnectionInTransaction {
+                conn: &mut *conn,
+                dl_redis: None,
+            };
+            f(&mut borrowed_conn)
+        })
+    }
+}
+
 #[async_trait]
 pub trait QueryRunner {
     async fn execute<Q>(&self, query: Q) -> QueryResult<usize>
@@ -24,6 +24,7 @@ impl DbConnection {
         dotenv().expect("failed to load .env");
 
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        tracing::debug!(database_url, "Connecting to the database");
         let conn = PgConnection::establish(&database_url)
             .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
 
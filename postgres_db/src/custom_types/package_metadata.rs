@@ -95,7 +95,7 @@ impl FromSql<PackageState, Pg> for PackageStateTimePoint {
 use crate::schema::sql_types::VersionState;
 use crate::schema::sql_types::VersionStateEnum;
 
-#[derive(Debug, PartialEq, Eq, FromSqlRow, AsExpression)]
+#[derive(Debug, Clone, PartialEq, Eq, FromSqlRow, AsExpression)]
 #[diesel(sql_type = VersionStateEnum)]
 pub enum VersionStateType {
     Normal,
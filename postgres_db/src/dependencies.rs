@@ -1,6 +1,7 @@
 use crate::{connection::QueryRunner, custom_types::ParsedSpec};
 
 use super::schema::dependencies;
+use diesel::sql_types::{Array, BigInt, Nullable, Text};
 use diesel::{upsert::on_constraint, Queryable};
 use serde_json::Value;
 
@@ -22,6 +23,7 @@ pub struct NewDependency {
     md5digest_with_version: String,
 }
 
+#[derive(Clone, Copy)]
 pub enum DependencyType {
     Prod,
     Dev,
@@ -205,6 +207,19 @@ pub fn update_deps_missing_pack<R: QueryRunner>(conn: &mut R, pack_name: &str, p
         .expect("Error updating dependencies");
 }
 
+/// The inverse of `update_deps_missing_pack`: makes dependencies on the package `pack_id` point
+/// to no package, so that the package can be deleted.
+pub fn clear_deps_dst_pack<R: QueryRunner>(conn: &mut R, pack_id: i64) {
+    use super::schema::dependencies::dsl::*;
+
+    let clear_query = diesel::update(dependencies)
+        .filter(dst_package_id_if_exists.eq(pack_id))
+        .set(dst_package_id_if_exists.eq(None::<i64>));
+
+    conn.execute(clear_query)
+        .expect("Error clearing dependencies");
+}
+
 // returns (id, prod_freq_count, dev_freq_count, peer_freq_count, optional_freq_count)
 pub fn insert_dependency_inc_counts<R>(
     conn: &mut R,
@@ -272,3 +287,225 @@ where
     conn.get_result(dependencies.filter(id.eq(dep_id)))
         .expect("Error getting dep by id")
 }
+
+/// Gets the dependencies with the given ids, in no particular order. Missing ids are skipped.
+pub fn get_dependencies_by_ids<R>(conn: &mut R, dep_ids: &[i64]) -> Vec<Dependency>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    conn.load(dependencies.filter(id.eq_any(dep_ids)))
+        .expect("Error getting deps by ids")
+}
+
+/// Gets the dependencies on any of the packages named `pack_names`, whether or not they exist,
+/// in no particular order.
+pub fn get_dependencies_by_dst_names<R>(conn: &mut R, pack_names: &[String]) -> Vec<Dependency>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    let name_digests: Vec<String> = pack_names
+        .iter()
+        .map(|pack_name| {
+            let mut hasher = Sha256::new();
+            hasher.update(pack_name);
+            format!("{:x}", hasher.finalize())
+        })
+        .collect();
+
+    conn.load(
+        dependencies
+            .filter(md5digest.eq_any(name_digests))
+            .filter(dst_package_name.eq_any(pack_names)),
+    )
+    .expect("Error getting dependencies by names")
+}
+
+/// Returns up to `limit` dependencies with an id greater than `after_id`, ordered by id.
+pub fn query_dependencies_after_id<R>(conn: &mut R, after_id: i64, limit: i64) -> Vec<Dependency>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    let query = dependencies.filter(id.gt(after_id)).order(id).limit(limit);
+    conn.load(query).expect("Error querying dependencies")
+}
+
+/// Returns up to `limit` `(id, dst_package_id_if_exists)` pairs of dependencies on packages that
+/// exist, with an id greater than `after_id`, ordered by id.
+pub fn query_dependency_dsts_after_id<R>(conn: &mut R, after_id: i64, limit: i64) -> Vec<(i64, i64)>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    let query = dependencies
+        .select((id, dst_package_id_if_exists.assume_not_null()))
+        .filter(id.gt(after_id))
+        .filter(dst_package_id_if_exists.is_not_null())
+        .order(id)
+        .limit(limit);
+    conn.load(query)
+        .expect("Error querying dependency destinations")
+}
+
+/// Gets `(id, dst_package_id_if_exists)` of the dependencies with the given ids, in no particular order.
+pub fn get_dependency_dsts_by_ids<R>(conn: &mut R, dep_ids: &[i64]) -> Vec<(i64, Option<i64>)>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    conn.load(
+        dependencies
+            .select((id, dst_package_id_if_exists))
+            .filter(id.eq_any(dep_ids)),
+    )
+    .expect("Error getting dependency destinations")
+}
+
+/// Gets the ids of the dependencies on the package named `pack_name`, whether or not it exists.
+pub fn get_dependency_ids_by_dst_name<R>(conn: &mut R, pack_name: &str) -> Vec<i64>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    let mut hasher = Sha256::new();
+    hasher.update(pack_name);
+    let name_digest = format!("{:x}", hasher.finalize());
+
+    conn.load(
+        dependencies
+            .select(id)
+            .filter(md5digest.eq(name_digest))
+            .filter(dst_package_name.eq(pack_name)),
+    )
+    .expect("Error getting dependencies by name")
+}
+
+/// Returns up to `limit` `(id, raw_spec)` pairs of dependencies with an id greater than `after_id`, ordered by id.
+pub fn query_raw_specs_after_id<R>(conn: &mut R, after_id: i64, limit: i64) -> Vec<(i64, Value)>
+where
+    R: QueryRunner,
+{
+    use super::schema::dependencies::dsl::*;
+
+    conn.load(
+        dependencies
+            .select((id, raw_spec))
+            .filter(id.gt(after_id))
+            .order(id)
+            .limit(limit),
+    )
+    .expect("Error querying raw specs of dependencies")
+}
+
+/// A dependency whose freq counts differ from the number of normal versions that have it.
+#[derive(QueryableByName, Debug, PartialEq, Eq)]
+pub struct DependencyCountMismatch {
+    #[diesel(sql_type = BigInt)]
+    pub id: i64,
+    #[diesel(sql_type = BigInt)]
+    pub prod_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub dev_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub peer_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub optional_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub expected_prod_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub expected_dev_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub expected_peer_freq_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub expected_optional_freq_count: i64,
+}
+
+/// Recounts how many normal versions have each dependency, and returns the dependencies whose freq
+/// counts are off, ordered by id. Only the given dependencies are checked, or all of them if
+/// `dep_ids` is `None`. Either way this scans the whole `versions` table once.
+pub fn query_dependency_count_mismatches<R>(
+    conn: &mut R,
+    dep_ids: Option<&[i64]>,
+) -> Vec<DependencyCountMismatch>
+where
+    R: QueryRunner,
+{
+    let query = diesel::sql_query(
+        "WITH uses AS ( \
+             SELECT u.dep_id, sum(u.prod)::BIGINT AS prod, sum(u.dev)::BIGINT AS dev, \
+                 sum(u.peer)::BIGINT AS peer, sum(u.opt)::BIGINT AS opt \
+             FROM versions v, \
+             LATERAL ( \
+                 SELECT unnest(v.prod_dependencies), 1, 0, 0, 0 \
+                 UNION ALL SELECT unnest(v.dev_dependencies), 0, 1, 0, 0 \
+                 UNION ALL SELECT unnest(v.peer_dependencies), 0, 0, 1, 0 \
+                 UNION ALL SELECT unnest(v.optional_dependencies), 0, 0, 0, 1 \
+             ) AS u(dep_id, prod, dev, peer, opt) \
+             WHERE v.current_version_state_type = 'normal' \
+                 AND ($1 IS NULL OR (v.prod_dependencies || v.dev_dependencies \
+                     || v.peer_dependencies || v.optional_dependencies) && $1) \
+             GROUP BY u.dep_id \
+         ) \
+         SELECT d.id, \
+             d.prod_freq_count, d.dev_freq_count, d.peer_freq_count, d.optional_freq_count, \
+             coalesce(uses.prod, 0) AS expected_prod_freq_count, \
+             coalesce(uses.dev, 0) AS expected_dev_freq_count, \
+             coalesce(uses.peer, 0) AS expected_peer_freq_count, \
+             coalesce(uses.opt, 0) AS expected_optional_freq_count \
+         FROM dependencies d LEFT JOIN uses ON uses.dep_id = d.id \
+         WHERE ($1 IS NULL OR d.id = ANY($1)) \
+             AND (d.prod_freq_count, d.dev_freq_count, d.peer_freq_count, d.optional_freq_count) \
+                 IS DISTINCT FROM (coalesce(uses.prod, 0), coalesce(uses.dev, 0), \
+                     coalesce(uses.peer, 0), coalesce(uses.opt, 0)) \
+         ORDER BY d.id",
+    )
+    .bind::<Nullable<Array<BigInt>>, _>(dep_ids.map(|ids| ids.to_vec()));
+
+    conn.load(query)
+        .unwrap_or_else(|e| panic!("Error recounting dependencies, {:?}", e))
+}
+
+/// A dependency whose `dst_package_id_if_exists` isn't the id of the package it is on.
+#[derive(QueryableByName, Debug, PartialEq, Eq)]
+pub struct DependencyDstMismatch {
+    #[diesel(sql_type = BigInt)]
+    pub id: i64,
+    #[diesel(sql_type = Text)]
+    pub dst_package_name: String,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    pub dst_package_id_if_exists: Option<i64>,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    pub expected_dst_package_id: Option<i64>,
+}
+
+/// Returns the dependencies that don't refer to the package they are on by id, or that refer to
+/// a package when there is none with their name, ordered by id. Only the given dependencies are
+/// checked, or all of them if `dep_ids` is `None`.
+pub fn query_dependency_dst_mismatches<R>(
+    conn: &mut R,
+    dep_ids: Option<&[i64]>,
+) -> Vec<DependencyDstMismatch>
+where
+    R: QueryRunner,
+{
+    let query = diesel::sql_query(
+        "SELECT d.id, d.dst_package_name, d.dst_package_id_if_exists, \
+             p.id AS expected_dst_package_id \
+         FROM dependencies d LEFT JOIN packages p ON p.name = d.dst_package_name \
+         WHERE ($1 IS NULL OR d.id = ANY($1)) \
+             AND d.dst_package_id_if_exists IS DISTINCT FROM p.id \
+         ORDER BY d.id",
+    )
+    .bind::<Nullable<Array<BigInt>>, _>(dep_ids.map(|ids| ids.to_vec()));
+
+    conn.load(query)
+        .unwrap_or_else(|e| panic!("Error checking dependency destinations, {:?}", e))
+}
@@ -11,7 +11,7 @@ use super::sql::InternalDiffLogVersionStateElem;
 use super::InternalDiffLogPackageState;
 use super::InternalDiffLogVersionState;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 enum FlushOp {
     Nothing,
     Create,
@@ -22,6 +22,9 @@ pub struct DiffStateManager {
     local_state: HashMap<String, (Option<InternalDiffLogPackageState>, FlushOp)>,
 }
 
+/// The local state of a single package, as saved by `DiffStateManager::save_package`.
+pub struct SavedPackageState(Option<InternalDiffLogPackageState>, FlushOp);
+
 impl DiffStateManager {
     pub fn new() -> DiffStateManager {
         DiffStateManager {
@@ -39,27 +42,68 @@ impl DiffStateManager {
             .entry(package_name_str)
             .or_insert_with_key(|k| {
                 let sql_row = sql::lookup_package(k, conn);
-                let loaded_state = sql_row.map(|r| InternalDiffLogPackageState {
-                    package_pack_hash: Some(r.package_only_packument_hash),
-                    versions: r
-                        .versions
-                        .into_iter()
-                        .map(|elem| {
-                            (
-                                elem.v,
-                                InternalDiffLogVersionState {
-                                    version_pack_hash: elem.pack_hash,
-                                    deleted: elem.deleted,
-                                },
-                            )
-                        })
-                        .collect(),
-                });
-                (loaded_state, FlushOp::Nothing)
+                (sql_row.map(row_to_state), FlushOp::Nothing)
             });
         x.0.as_ref()
     }
 
+    /// Loads the state of all the given packages with a single query, so that
+    /// they can later be looked up with `lookup_prefetched_package` without
+    /// needing a DB connection. Packages already in the local state are not reloaded.
+    pub fn prefetch_packages<R: QueryRunner>(&mut self, package_names: Vec<String>, conn: &mut R) {
+        let to_load: Vec<String> = package_names
+            .into_iter()
+            .filter(|name| !self.local_state.contains_key(name))
+            .collect();
+
+        if to_load.is_empty() {
+            return;
+        }
+
+        let mut loaded: HashMap<String, InternalDiffLogPackageState> =
+            sql::lookup_packages(&to_load, conn)
+                .into_iter()
+                .map(|r| (r.package_name.clone(), row_to_state(r)))
+                .collect();
+
+        for name in to_load {
+            let state = loaded.remove(&name);
+            self.local_state.insert(name, (state, FlushOp::Nothing));
+        }
+    }
+
+    /// Looks up a package which must have been loaded previously, either by
+    /// `prefetch_packages` or `lookup_package`.
+    pub fn lookup_prefetched_package(
+        &self,
+        package_name_str: &str,
+    ) -> Option<&InternalDiffLogPackageState> {
+        self.local_state
+            .get(package_name_str)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Programming error: package {} was not prefetched",
+                    package_name_str
+                )
+            })
+            .0
+            .as_ref()
+    }
+
+    /// Saves the local state of a package, so that it can be put back with `restore_package`
+    /// if applying a change to it fails part way through. Returns `None` if the package
+    /// has not been loaded.
+    pub fn save_package(&self, package_name_str: &str) -> Option<SavedPackageState> {
+        self.local_state
+            .get(package_name_str)
+            .map(|(state, flush)| SavedPackageState(state.clone(), flush.clone()))
+    }
+
+    pub fn restore_package(&mut self, package_name_str: String, saved: SavedPackageState) {
+        self.local_state
+            .insert(package_name_str, (saved.0, saved.1));
+    }
+
     pub fn apply_diff_entry(&mut self, entry: &NewDiffLogEntryWithHash) {
         let hash = entry.hash.as_ref();
         let package = &entry.entry.package_name;
@@ -161,6 +205,25 @@ impl DiffStateManager {
     }
 }
 
+fn row_to_state(r: InternalDiffLogStateRow) -> InternalDiffLogPackageState {
+    InternalDiffLogPackageState {
+        package_pack_hash: Some(r.package_only_packument_hash),
+        versions: r
+            .versions
+            .into_iter()
+            .map(|elem| {
+                (
+                    elem.v,
+                    InternalDiffLogVersionState {
+                        version_pack_hash: elem.pack_hash,
+                        deleted: elem.deleted,
+                    },
+                )
+            })
+            .collect(),
+    }
+}
+
 impl Default for DiffStateManager {
     fn default() -> Self {
         Self::new()
@@ -481,4 +544,79 @@ mod tests {
             assert_eq!(manager.lookup_package(pack2.clone(), conn), None);
         });
     }
+
+    #[test]
+    fn test_diff_log_internal_state_manager_prefetch_packages() {
+        let pack1 = "react".to_string();
+        let pack2 = "lodash".to_string();
+
+        let pack1_pack = PackageOnlyPackument::Normal {
+            latest: None,
+            created: Utc::now(),
+            modified: Utc::now(),
+            other_dist_tags: Map::new(),
+            extra_version_times: BTreeMap::new(),
+        };
+        let (_, hash1, _) = pack1_pack.serialize_and_hash();
+
+        testing::using_test_db(|conn| {
+            let mut manager = DiffStateManager::new();
+
+            sql::create_packages(
+                vec![InternalDiffLogStateRow {
+                    package_name: pack1.clone(),
+                    package_only_packument_hash: hash1.clone(),
+                    versions: vec![],
+                }],
+                conn,
+            );
+
+            manager.prefetch_packages(vec![pack1.clone(), pack2.clone()], conn);
+
+            assert_eq!(
+                manager.lookup_prefetched_package(&pack1),
+                Some(&InternalDiffLogPackageState {
+                    package_pack_hash: Some(hash1),
+                    versions: BTreeMap::new()
+                })
+            );
+            assert_eq!(manager.lookup_prefetched_package(&pack2), None);
+        });
+    }
+
+    #[test]
+    fn test_diff_log_internal_state_manager_restore_package() {
+        let pack1 = "react".to_string();
+
+        let pack1_pack = PackageOnlyPackument::Normal {
+            latest: None,
+            created: Utc::now(),
+            modified: Utc::now(),
+            other_dist_tags: Map::new(),
+            extra_version_times: BTreeMap::new(),
+        };
+
+        testing::using_test_db(|conn| {
+            let mut manager = DiffStateManager::new();
+            manager.prefetch_packages(vec![pack1.clone()], conn);
+
+            let saved = manager.save_package(&pack1).unwrap();
+            manager.apply_diff_entry(&NewDiffLogEntryWithHash {
+                entry: NewDiffLogEntry {
+                    seq: 0,
+                    package_name: pack1.clone(),
+                    instr: DiffLogInstruction::CreatePackage(pack1_pack),
+                },
+                hash: None,
+            });
+            assert!(manager.lookup_prefetched_package(&pack1).is_some());
+
+            manager.restore_package(pack1.clone(), saved);
+            assert_eq!(manager.lookup_prefetched_package(&pack1), None);
+
+            // Nothing should be written, since the package is back to its original state
+            manager.flush_to_db(conn);
+            assert_eq!(sql::lookup_package(&pack1, conn), None);
+        });
+    }
 }
@@ -87,6 +87,16 @@ pub(crate) fn lookup_package<R: QueryRunner>(
         .unwrap_or_else(|e| panic!("Error fetching row: {}", e))
 }
 
+pub(crate) fn lookup_packages<R: QueryRunner>(
+    package_names: &[String],
+    conn: &mut R,
+) -> Vec<InternalDiffLogStateRow> {
+    use schema::internal_diff_log_state::dsl::*;
+
+    conn.load(internal_diff_log_state.filter(package_name.eq_any(package_names)))
+        .unwrap_or_else(|e| panic!("Error fetching rows: {}", e))
+}
+
 pub(crate) fn update_packages<R: QueryRunner>(rows: Vec<InternalDiffLogStateRow>, conn: &mut R) {
     for r in rows {
         conn.execute(diesel::update(&r).set(&r))
@@ -94,6 +104,15 @@ pub(crate) fn update_packages<R: QueryRunner>(rows: Vec<InternalDiffLogStateRow>
     }
 }
 
+pub(crate) fn delete_packages<R: QueryRunner>(package_names: &[String], conn: &mut R) {
+    use schema::internal_diff_log_state::dsl::*;
+
+    conn.execute(diesel::delete(
+        internal_diff_log_state.filter(package_name.eq_any(package_names)),
+    ))
+    .unwrap_or_else(|e| panic!("Error deleting rows: {}", e));
+}
+
 pub mod testing {
     // use super::schema;
     // use super::InternalDiffLogStateRow;
@@ -1,4 +1,7 @@
 pub mod internal_diff_log_state;
+pub mod quarantine;
+mod reconstruct;
+mod rewind;
 
 use crate::connection::DbConnection;
 use crate::connection::QueryRunner;
@@ -9,7 +12,12 @@ use crate::packument::VersionOnlyPackument;
 
 use super::schema;
 use super::schema::diff_log;
+
 use diesel::prelude::*;
+pub use reconstruct::reconstruct;
+pub use reconstruct::ReconstructedPackument;
+pub use reconstruct::ReconstructionPoint;
+pub use rewind::rewind_diff_log;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
@@ -180,7 +188,7 @@ pub fn query_diff_entries_after_seq(
             v,
             version_packument,
         ))
-        .order(id);
+        .order((seq, id));
 
     let rows: Vec<DiffLogRow> = conn.load(join_query).unwrap_or_else(|err| {
         panic!(
@@ -207,6 +215,181 @@ pub fn query_num_changes_after_seq_in_diff_log(after_seq: i64, conn: &mut DbConn
     })
 }
 
+/// Loads all diff entries of a single package in order, optionally only up to (and including) a seq.
+pub fn query_diff_entries_for_package<R: QueryRunner>(
+    package: &str,
+    up_to_seq: Option<i64>,
+    conn: &mut R,
+) -> Vec<DiffLogEntry> {
+    use schema::diff_log::dsl::*;
+
+    let mut query = diff_log
+        .filter(package_name.eq(package))
+        .select((
+            id,
+            seq,
+            package_name,
+            dt,
+            package_only_packument,
+            v,
+            version_packument,
+        ))
+        .order((seq, id))
+        .into_boxed();
+
+    if let Some(up_to) = up_to_seq {
+        query = query.filter(seq.le(up_to));
+    }
+
+    let rows: Vec<DiffLogRow> = conn.load(query).unwrap_or_else(|err| {
+        panic!(
+            "Error querying DB for diff_log of package {}:\n{}",
+            package, err
+        )
+    });
+
+    rows.into_iter().map(|e| e.into()).collect()
+}
+
+pub fn query_diff_entry_by_id<R: QueryRunner>(entry_id: i64, conn: &mut R) -> Option<DiffLogEntry> {
+    use schema::diff_log::dsl::*;
+
+    let row: Option<DiffLogRow> = conn
+        .first(diff_log.filter(id.eq(entry_id)).select((
+            id,
+            seq,
+            package_name,
+            dt,
+            package_only_packument,
+            v,
+            version_packument,
+        )))
+        .optional()
+        .unwrap_or_else(|err| panic!("Error querying DB for diff entry {}:\n{}", entry_id, err));
+
+    row.map(|e| e.into())
+}
+
+pub fn query_first_seq_for_package<R: QueryRunner>(package: &str, conn: &mut R) -> Option<i64> {
+    use diesel::dsl::min;
+    use schema::diff_log::dsl::*;
+
+    conn.first(diff_log.filter(package_name.eq(package)).select(min(seq)))
+        .unwrap_or_else(|err| {
+            panic!(
+                "Error querying DB for first seq of package {}:\n{}",
+                package, err
+            )
+        })
+}
+
+/// Checks whether any diff entries for the package were generated by a change after `after_seq`.
+pub fn query_package_has_diff_entries_after_seq<R: QueryRunner>(
+    package: &str,
+    after_seq: i64,
+    conn: &mut R,
+) -> bool {
+    use schema::diff_log::dsl::*;
+
+    conn.first::<_, i64>(
+        diff_log
+            .filter(package_name.eq(package).and(seq.gt(after_seq)))
+            .select(id),
+    )
+    .optional()
+    .unwrap_or_else(|err| {
+        panic!(
+            "Error querying DB for diff_log of package {} after seq {}:\n{}",
+            package, after_seq, err
+        )
+    })
+    .is_some()
+}
+
+/// The names of the packages with diff entries generated by changes after `after_seq`.
+pub fn query_packages_with_diff_entries_after_seq<R: QueryRunner>(
+    after_seq: i64,
+    conn: &mut R,
+) -> Vec<String> {
+    use schema::diff_log::dsl::*;
+
+    conn.load(
+        diff_log
+            .filter(seq.gt(after_seq))
+            .select(package_name)
+            .distinct(),
+    )
+    .unwrap_or_else(|err| {
+        panic!(
+            "Error querying DB for packages in diff_log after seq {}:\n{}",
+            after_seq, err
+        )
+    })
+}
+
+/// The names of the packages with diff entries generated by changes after `after_seq`, up to and
+/// including `up_to_seq`.
+pub fn query_packages_with_diff_entries_in_seq_range<R: QueryRunner>(
+    after_seq: i64,
+    up_to_seq: i64,
+    conn: &mut R,
+) -> Vec<String> {
+    use schema::diff_log::dsl::*;
+
+    conn.load(
+        diff_log
+            .filter(seq.gt(after_seq).and(seq.le(up_to_seq)))
+            .select(package_name)
+            .distinct(),
+    )
+    .unwrap_or_else(|err| {
+        panic!(
+            "Error querying DB for packages in diff_log after seq {} up to seq {}:\n{}",
+            after_seq, up_to_seq, err
+        )
+    })
+}
+
+/// The packages created by diff entries up to and including `up_to_seq` which have no row in
+/// the `packages` table.
+pub fn query_created_packages_without_rows<R: QueryRunner>(
+    up_to_seq: i64,
+    conn: &mut R,
+) -> Vec<String> {
+    use diesel::dsl::{exists, not};
+    use schema::diff_log::dsl::*;
+    use schema::packages;
+
+    conn.load(
+        diff_log
+            .filter(dt.eq(DiffTypeEnum::CreatePackage))
+            .filter(seq.le(up_to_seq))
+            .filter(not(exists(
+                packages::table.filter(packages::name.eq(package_name)),
+            )))
+            .select(package_name)
+            .distinct(),
+    )
+    .unwrap_or_else(|err| {
+        panic!(
+            "Error querying DB for created packages without rows up to seq {}:\n{}",
+            up_to_seq, err
+        )
+    })
+}
+
+pub fn delete_diff_entries_after_seq<R: QueryRunner>(after_seq: i64, conn: &mut R) -> usize {
+    use schema::diff_log::dsl::*;
+
+    conn.execute(diesel::delete(diff_log.filter(seq.gt(after_seq))))
+        .unwrap_or_else(|err| {
+            panic!(
+                "Error deleting diff_log entries after seq {}:\n{}",
+                after_seq, err
+            )
+        })
+}
+
 const INSERT_CHUNK_SIZE: usize = 2048;
 
 pub fn insert_diff_log_entries<R: QueryRunner>(
@@ -0,0 +1,143 @@
+use chrono::DateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+
+use crate::connection::QueryRunner;
+use crate::schema::diff_log_quarantine;
+
+/// A change that panicked while being processed by the diff log builder.
+/// It is skipped by the builder until it is successfully retried.
+#[derive(Queryable, Debug, PartialEq, Eq, Clone)]
+pub struct QuarantinedChange {
+    pub seq: i64,
+    pub package_name: Option<String>,
+    pub panic_message: String,
+    pub json_path: Option<String>,
+    pub quarantined_time: DateTime<Utc>,
+    pub num_retries: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = diff_log_quarantine)]
+pub struct NewQuarantinedChange {
+    pub seq: i64,
+    pub package_name: Option<String>,
+    pub panic_message: String,
+    pub json_path: Option<String>,
+    pub quarantined_time: DateTime<Utc>,
+}
+
+/// Inserts the failed changes into the quarantine. If a change is already quarantined
+/// (i.e. it failed again during a retry), its message and path are replaced and its retry count is bumped.
+pub fn quarantine_changes<R: QueryRunner>(changes: Vec<NewQuarantinedChange>, conn: &mut R) {
+    use crate::schema::diff_log_quarantine::dsl::*;
+
+    if changes.is_empty() {
+        return;
+    }
+    tracing::debug!(num_changes = changes.len(), "Quarantining changes");
+
+    conn.execute(
+        diesel::insert_into(diff_log_quarantine)
+            .values(changes)
+            .on_conflict(seq)
+            .do_update()
+            .set((
+                panic_message.eq(excluded(panic_message)),
+                json_path.eq(excluded(json_path)),
+                quarantined_time.eq(excluded(quarantined_time)),
+                num_retries.eq(num_retries + 1),
+            )),
+    )
+    .unwrap_or_else(|e| panic!("Error quarantining changes: {}", e));
+}
+
+pub fn query_quarantined_changes_after_seq<R: QueryRunner>(
+    after_seq: i64,
+    limit_size: i64,
+    conn: &mut R,
+) -> Vec<QuarantinedChange> {
+    use crate::schema::diff_log_quarantine::dsl::*;
+
+    conn.load(
+        diff_log_quarantine
+            .filter(seq.gt(after_seq))
+            .order(seq)
+            .limit(limit_size),
+    )
+    .unwrap_or_else(|e| {
+        panic!(
+            "Error querying quarantined changes after seq {}: {}",
+            after_seq, e
+        )
+    })
+}
+
+pub fn query_num_quarantined_changes<R: QueryRunner>(conn: &mut R) -> i64 {
+    use crate::schema::diff_log_quarantine::dsl::*;
+
+    conn.first(diff_log_quarantine.count())
+        .unwrap_or_else(|e| panic!("Error counting quarantined changes: {}", e))
+}
+
+pub fn remove_quarantined_changes<R: QueryRunner>(seqs: &[i64], conn: &mut R) {
+    use crate::schema::diff_log_quarantine::dsl::*;
+
+    conn.execute(diesel::delete(diff_log_quarantine.filter(seq.eq_any(seqs))))
+        .unwrap_or_else(|e| panic!("Error removing quarantined changes: {}", e));
+}
+
+pub fn remove_quarantined_changes_after_seq<R: QueryRunner>(after_seq: i64, conn: &mut R) {
+    use crate::schema::diff_log_quarantine::dsl::*;
+
+    let num_removed = conn
+        .execute(diesel::delete(
+            diff_log_quarantine.filter(seq.gt(after_seq)),
+        ))
+        .unwrap_or_else(|e| panic!("Error removing quarantined changes: {}", e));
+    tracing::debug!(after_seq, num_removed, "Removed quarantined changes");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn new_quarantined(seq: i64, message: &str) -> NewQuarantinedChange {
+        NewQuarantinedChange {
+            seq,
+            package_name: Some("react".into()),
+            panic_message: message.into(),
+            json_path: Some("/versions/1.0.0/dist".into()),
+            quarantined_time: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_quarantine_insert_retry_remove() {
+        testing::using_test_db(|conn| {
+            assert_eq!(query_num_quarantined_changes(conn), 0);
+
+            quarantine_changes(
+                vec![new_quarantined(5, "first"), new_quarantined(3, "other")],
+                conn,
+            );
+            assert_eq!(query_num_quarantined_changes(conn), 2);
+
+            let qs = query_quarantined_changes_after_seq(0, 10, conn);
+            assert_eq!(qs.iter().map(|q| q.seq).collect::<Vec<_>>(), vec![3, 5]);
+            assert!(qs.iter().all(|q| q.num_retries == 0));
+
+            // Failing again updates the existing row
+            quarantine_changes(vec![new_quarantined(5, "second")], conn);
+            let qs = query_quarantined_changes_after_seq(3, 10, conn);
+            assert_eq!(qs.len(), 1);
+            assert_eq!(qs[0].panic_message, "second");
+            assert_eq!(qs[0].num_retries, 1);
+
+            remove_quarantined_changes(&[3, 5], conn);
+            assert_eq!(query_num_quarantined_changes(conn), 0);
+        });
+    }
+}
@@ -0,0 +1,323 @@
+use chrono::DateTime;
+use chrono::Utc;
+
+use super::query_diff_entries_for_package;
+use super::query_first_seq_for_package;
+use super::DiffLogInstruction;
+use crate::change_log;
+use crate::connection::QueryRunner;
+use crate::packument::AllVersionPackuments;
+use crate::packument::PackageOnlyPackument;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconstructionPoint {
+    /// Replay all diff entries with a seq less than or equal to this one.
+    Seq(i64),
+    /// Replay all diff entries for changes received by this time, and then hide
+    /// everything that was published after it. The second step is what makes this
+    /// work for times before we started following the registry, since the first
+    /// change of a package contains its entire history.
+    Time(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconstructedPackument {
+    pub package: PackageOnlyPackument,
+    pub versions: AllVersionPackuments,
+    /// Versions which had been deleted by the reconstruction point, with their last known data.
+    pub deleted_versions: AllVersionPackuments,
+    /// The seq of the last diff entry that was applied.
+    pub last_seq: i64,
+}
+
+/// Reconstructs the packument of `package` as it was at the given point, by replaying the diff log.
+/// Returns `None` if the package didn't exist yet at that point.
+pub fn reconstruct<R: QueryRunner>(
+    package: &str,
+    at: ReconstructionPoint,
+    conn: &mut R,
+) -> Option<ReconstructedPackument> {
+    let up_to_seq = match at {
+        ReconstructionPoint::Seq(s) => Some(s),
+        ReconstructionPoint::Time(t) => change_log::query_seq_received_as_of(t, conn),
+    };
+
+    let mut entries = query_diff_entries_for_package(package, up_to_seq, conn);
+
+    // If the package was first received after the given time, we still use its first change,
+    // which will be restricted to what was published by then below.
+    if entries.is_empty() && matches!(at, ReconstructionPoint::Time(_)) {
+        if let Some(first_seq) = query_first_seq_for_package(package, conn) {
+            entries = query_diff_entries_for_package(package, Some(first_seq), conn);
+        }
+    }
+    let mut state = replay(entries.into_iter().map(|e| (e.seq, e.instr)))?;
+
+    if let ReconstructionPoint::Time(t) = at {
+        state = restrict_to_published_by(state, t)?;
+    }
+
+    Some(state)
+}
+
+fn replay<I>(entries: I) -> Option<ReconstructedPackument>
+where
+    I: IntoIterator<Item = (i64, DiffLogInstruction)>,
+{
+    let mut state: Option<ReconstructedPackument> = None;
+
+    for (seq, instr) in entries {
+        if let DiffLogInstruction::CreatePackage(pack) = instr {
+            // A package is only created once, so it can't exist yet.
+            assert!(
+                state.is_none(),
+                "CreatePackage on existing package at seq {}",
+                seq
+            );
+            state = Some(ReconstructedPackument {
+                package: pack,
+                versions: AllVersionPackuments::new(),
+                deleted_versions: AllVersionPackuments::new(),
+                last_seq: seq,
+            });
+            continue;
+        }
+
+        let s = state
+            .as_mut()
+            .unwrap_or_else(|| panic!("diff entry before CreatePackage at seq {}", seq));
+        s.last_seq = seq;
+
+        match instr {
+            DiffLogInstruction::CreatePackage(_) => unreachable!(),
+            DiffLogInstruction::UpdatePackage(pack) => s.package = pack,
+            DiffLogInstruction::PatchPackageReferences => {}
+            DiffLogInstruction::CreateVersion(v, data)
+            | DiffLogInstruction::UpdateVersion(v, data) => {
+                // An update of a deleted version means it has come back.
+                s.deleted_versions.remove(&v);
+                s.versions.insert(v, data);
+            }
+            DiffLogInstruction::DeleteVersion(v) => {
+                let data = s
+                    .versions
+                    .remove(&v)
+                    .unwrap_or_else(|| panic!("deleting missing version {} at seq {}", v, seq));
+                s.deleted_versions.insert(v, data);
+            }
+        }
+    }
+
+    state
+}
+
+fn restrict_to_published_by(
+    mut state: ReconstructedPackument,
+    t: DateTime<Utc>,
+) -> Option<ReconstructedPackument> {
+    state.versions.retain(|_, data| data.time <= t);
+    state.deleted_versions.retain(|_, data| data.time <= t);
+
+    let latest_published = state
+        .versions
+        .iter()
+        .filter(|(v, _)| v.prerelease.is_empty())
+        .max_by_key(|(_, data)| data.time)
+        .map(|(v, _)| v.clone());
+    let last_version_time = state.versions.values().map(|data| data.time).max();
+
+    match &mut state.package {
+        PackageOnlyPackument::Normal {
+            latest,
+            created,
+            modified,
+            extra_version_times,
+            ..
+        } => {
+            if *created > t {
+                return None;
+            }
+            let latest_still_published = match latest {
+                Some(l) => state.versions.contains_key(l),
+                None => false,
+            };
+            if !latest_still_published {
+                *latest = latest_published;
+            }
+            restrict_modified(modified, *created, last_version_time, t);
+            extra_version_times.retain(|_, vt| *vt <= t);
+        }
+        PackageOnlyPackument::Unpublished {
+            created,
+            modified,
+            extra_version_times,
+            ..
+        } => {
+            if *created > t {
+                return None;
+            }
+            restrict_modified(modified, *created, last_version_time, t);
+            extra_version_times.retain(|_, vt| *vt <= t);
+        }
+        PackageOnlyPackument::MissingData | PackageOnlyPackument::Deleted => {}
+    }
+
+    Some(state)
+}
+
+fn restrict_modified(
+    modified: &mut DateTime<Utc>,
+    created: DateTime<Utc>,
+    last_version_time: Option<DateTime<Utc>>,
+    t: DateTime<Utc>,
+) {
+    if *modified > t {
+        *modified = last_version_time.map_or(created, |lvt| lvt.max(created));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::TimeZone;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::custom_types::Semver;
+    use crate::diff_log::insert_diff_log_entries;
+    use crate::diff_log::NewDiffLogEntry;
+    use crate::packument::VersionOnlyPackument;
+    use crate::testing;
+    use crate::testing::package_data_at;
+
+    fn version_data(time: DateTime<Utc>) -> VersionOnlyPackument {
+        VersionOnlyPackument {
+            time,
+            ..testing::version_data()
+        }
+    }
+
+    fn entry(seq: i64, instr: DiffLogInstruction) -> NewDiffLogEntry {
+        NewDiffLogEntry {
+            seq,
+            package_name: "react".into(),
+            instr,
+        }
+    }
+
+    #[test]
+    fn test_reconstruct_at_seq() {
+        let t0 = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let v1 = Semver::new_testing_semver(1);
+        let v3 = Semver::new_testing_semver(3);
+
+        let entries = vec![
+            entry(
+                10,
+                DiffLogInstruction::CreatePackage(package_data_at(None, t0, t0)),
+            ),
+            entry(
+                10,
+                DiffLogInstruction::CreateVersion(v1.clone(), version_data(t0)),
+            ),
+            entry(
+                10,
+                DiffLogInstruction::UpdatePackage(package_data_at(Some(v1.clone()), t0, t0)),
+            ),
+            entry(10, DiffLogInstruction::PatchPackageReferences),
+            entry(
+                20,
+                DiffLogInstruction::CreateVersion(v3.clone(), version_data(t0 + Duration::days(1))),
+            ),
+            entry(30, DiffLogInstruction::DeleteVersion(v1.clone())),
+        ];
+
+        testing::using_test_db(|conn| {
+            for seq in [10, 20, 30] {
+                change_log::insert_change(conn, seq, Value::Null, t0);
+            }
+            insert_diff_log_entries(entries, conn);
+
+            assert_eq!(
+                reconstruct("react", ReconstructionPoint::Seq(5), conn),
+                None
+            );
+            assert_eq!(
+                reconstruct("lodash", ReconstructionPoint::Seq(30), conn),
+                None
+            );
+
+            let at_10 = reconstruct("react", ReconstructionPoint::Seq(10), conn).unwrap();
+            assert_eq!(at_10.last_seq, 10);
+            assert_eq!(at_10.package, package_data_at(Some(v1.clone()), t0, t0));
+            assert_eq!(at_10.versions.keys().collect::<Vec<_>>(), vec![&v1]);
+            assert!(at_10.deleted_versions.is_empty());
+
+            let at_25 = reconstruct("react", ReconstructionPoint::Seq(25), conn).unwrap();
+            assert_eq!(at_25.last_seq, 20);
+            assert_eq!(at_25.versions.keys().collect::<Vec<_>>(), vec![&v1, &v3]);
+
+            let at_30 = reconstruct("react", ReconstructionPoint::Seq(30), conn).unwrap();
+            assert_eq!(at_30.versions.keys().collect::<Vec<_>>(), vec![&v3]);
+            assert_eq!(at_30.deleted_versions.keys().collect::<Vec<_>>(), vec![&v1]);
+        });
+    }
+
+    #[test]
+    fn test_reconstruct_at_time_before_following() {
+        // The package's first change already contains its whole history,
+        // so asking for a time before it was received must filter by publish time.
+        let t0 = Utc.with_ymd_and_hms(2015, 1, 1, 0, 0, 0).unwrap();
+        let t1 = t0 + Duration::days(100);
+        let t2 = t0 + Duration::days(200);
+        let received = t0 + Duration::days(1000);
+        let v1 = Semver::new_testing_semver(1);
+        let v3 = Semver::new_testing_semver(3);
+
+        let entries = vec![
+            entry(
+                10,
+                DiffLogInstruction::CreatePackage(package_data_at(None, t0, t2)),
+            ),
+            entry(
+                10,
+                DiffLogInstruction::CreateVersion(v1.clone(), version_data(t1)),
+            ),
+            entry(
+                10,
+                DiffLogInstruction::CreateVersion(v3.clone(), version_data(t2)),
+            ),
+            entry(
+                10,
+                DiffLogInstruction::UpdatePackage(package_data_at(Some(v3.clone()), t0, t2)),
+            ),
+            entry(10, DiffLogInstruction::PatchPackageReferences),
+        ];
+
+        testing::using_test_db(|conn| {
+            change_log::insert_change(conn, 10, Value::Null, received);
+            insert_diff_log_entries(entries, conn);
+
+            let now = reconstruct("react", ReconstructionPoint::Time(received), conn).unwrap();
+            assert_eq!(now.package, package_data_at(Some(v3.clone()), t0, t2));
+
+            let between = reconstruct(
+                "react",
+                ReconstructionPoint::Time(t1 + Duration::days(1)),
+                conn,
+            )
+            .unwrap();
+            assert_eq!(between.versions.keys().collect::<Vec<_>>(), vec![&v1]);
+            assert_eq!(between.package, package_data_at(Some(v1.clone()), t0, t1));
+
+            assert_eq!(
+                reconstruct(
+                    "react",
+                    ReconstructionPoint::Time(t0 - Duration::days(1)),
+                    conn
+                ),
+                None
+            );
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+
+use super::delete_diff_entries_after_seq;
+use super::internal_diff_log_state::sql;
+use super::internal_diff_log_state::sql::InternalDiffLogStateRow;
+use super::internal_diff_log_state::sql::InternalDiffLogVersionStateElem;
+use super::quarantine::remove_quarantined_changes_after_seq;
+use super::query_diff_entries_for_package;
+use super::query_packages_with_diff_entries_after_seq;
+use super::DiffLogEntry;
+use super::DiffLogInstruction;
+use crate::connection::QueryRunner;
+use crate::custom_types::Semver;
+use crate::internal_state;
+
+/// Rewinds the diff log to how it was right after processing the change `to_seq`, so that the
+/// changes after it are diffed again: deletes the diff entries and quarantined changes after
+/// `to_seq`, and recomputes the internal state of the packages they were for from the entries
+/// that are left. Returns the number of packages that were rewound.
+///
+/// The relational tables are built from the diff log, so they must already be rewound to `to_seq`.
+pub fn rewind_diff_log<R: QueryRunner>(to_seq: i64, conn: &mut R) -> usize {
+    let package_names = query_packages_with_diff_entries_after_seq(to_seq, conn);
+    let existing: HashSet<String> = sql::lookup_packages(&package_names, conn)
+        .into_iter()
+        .map(|r| r.package_name)
+        .collect();
+
+    let mut to_create = vec![];
+    let mut to_update = vec![];
+    let mut to_delete = vec![];
+    for name in &package_names {
+        let entries = query_diff_entries_for_package(name, Some(to_seq), conn);
+        match replay_state(name, entries) {
+            Some(row) if existing.contains(name) => to_update.push(row),
+            Some(row) => to_create.push(row),
+            None => to_delete.push(name.clone()),
+        }
+    }
+
+    tracing::info!(
+        to_seq,
+        num_created = to_create.len(),
+        num_updated = to_update.len(),
+        num_deleted = to_delete.len(),
+        "Rewinding the internal state of the diff log"
+    );
+    sql::create_packages(to_create, conn);
+    sql::update_packages(to_update, conn);
+    sql::delete_packages(&to_delete, conn);
+
+    delete_diff_entries_after_seq(to_seq, conn);
+    remove_quarantined_changes_after_seq(to_seq, conn);
+    internal_state::set_diff_log_processed_seq(to_seq, conn);
+
+    package_names.len()
+}
+
+/// The internal state that generating `entries` left the package in. This hashes the packuments
+/// of the entries the same way `diff_log_builder` does, and applies them like
+/// `DiffStateManager::apply_diff_entry`.
+fn replay_state(package_name: &str, entries: Vec<DiffLogEntry>) -> Option<InternalDiffLogStateRow> {
+    let mut package_hash = None;
+    let mut versions: BTreeMap<Semver, InternalDiffLogVersionStateElem> = BTreeMap::new();
+
+    for entry in entries {
+        match entry.instr {
+            DiffLogInstruction::CreatePackage(pack) | DiffLogInstruction::UpdatePackage(pack) => {
+                let (_, hash, _) = pack.serialize_and_hash();
+                package_hash = Some(hash);
+            }
+            DiffLogInstruction::CreateVersion(v, data)
+            | DiffLogInstruction::UpdateVersion(v, data) => {
+                // An update doesn't change whether the version is deleted.
+                let (_, hash, _) = data.serialize_and_hash();
+                versions
+                    .entry(v.clone())
+                    .and_modify(|state| state.pack_hash = hash.clone())
+                    .or_insert(InternalDiffLogVersionStateElem {
+                        v,
+                        pack_hash: hash,
+                        deleted: false,
+                    });
+            }
+            DiffLogInstruction::DeleteVersion(v) => {
+                versions
+                    .get_mut(&v)
+                    .unwrap_or_else(|| {
+                        panic!("deleting missing version {} at seq {}", v, entry.seq)
+                    })
+                    .deleted = true;
+            }
+            DiffLogInstruction::PatchPackageReferences => {}
+        }
+    }
+
+    package_hash.map(|hash| InternalDiffLogStateRow {
+        package_name: package_name.to_string(),
+        package_only_packument_hash: hash,
+        versions: versions.into_values().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff_log::insert_diff_log_entries;
+    use crate::diff_log::internal_diff_log_state::manager::DiffStateManager;
+    use crate::diff_log::internal_diff_log_state::sql::testing::get_all_packages;
+    use crate::diff_log::quarantine::{
+        quarantine_changes, query_num_quarantined_changes, NewQuarantinedChange,
+    };
+    use crate::diff_log::testing::get_all_diff_logs;
+    use crate::diff_log::{NewDiffLogEntry, NewDiffLogEntryWithHash};
+    use crate::packument::VersionOnlyPackument;
+    use crate::testing;
+    use crate::testing::package_data;
+    use chrono::Utc;
+
+    /// Versions are told apart by their tarball url.
+    fn version_data(tarball_url: &str) -> VersionOnlyPackument {
+        let mut data = testing::version_data();
+        data.dist.tarball_url = tarball_url.into();
+        data
+    }
+
+    /// Builds the diff log and its internal state like `diff_log_builder` does.
+    fn build<R: QueryRunner>(entries: Vec<(i64, &str, DiffLogInstruction)>, conn: &mut R) {
+        let mut manager = DiffStateManager::new();
+        let mut new_entries = vec![];
+        for (seq, package_name, instr) in entries {
+            manager.lookup_package(package_name.to_string(), conn);
+            let hash = match &instr {
+                DiffLogInstruction::CreatePackage(pack)
+                | DiffLogInstruction::UpdatePackage(pack) => Some(pack.serialize_and_hash().1),
+                DiffLogInstruction::CreateVersion(_, data)
+                | DiffLogInstruction::UpdateVersion(_, data) => Some(data.serialize_and_hash().1),
+                _ => None,
+            };
+            let entry = NewDiffLogEntryWithHash {
+                entry: NewDiffLogEntry {
+                    seq,
+                    package_name: package_name.to_string(),
+                    instr,
+                },
+                hash,
+            };
+            manager.apply_diff_entry(&entry);
+            new_entries.push(entry.entry);
+        }
+        manager.flush_to_db(conn);
+        insert_diff_log_entries(new_entries, conn);
+    }
+
+    #[test]
+    fn test_rewind_diff_log() {
+        let v1 = Semver::new_testing_semver(1);
+        let v2 = Semver::new_testing_semver(2);
+
+        testing::using_test_db(|conn| {
+            build(
+                vec![
+                    (
+                        10,
+                        "react",
+                        DiffLogInstruction::CreatePackage(package_data(None)),
+                    ),
+                    (
+                        10,
+                        "react",
+                        DiffLogInstruction::CreateVersion(v1.clone(), version_data("a")),
+                    ),
+                    (
+                        10,
+                        "react",
+                        DiffLogInstruction::UpdatePackage(package_data(Some(v1.clone()))),
+                    ),
+                    (10, "react", DiffLogInstruction::PatchPackageReferences),
+                ],
+                conn,
+            );
+            internal_state::set_diff_log_processed_seq(10, conn);
+            let state_at_10 = get_all_packages(conn);
+            let entries_at_10 = get_all_diff_logs(conn);
+
+            build(
+                vec![
+                    (
+                        20,
+                        "react",
+                        DiffLogInstruction::CreateVersion(v2.clone(), version_data("b")),
+                    ),
+                    (
+                        20,
+                        "react",
+                        DiffLogInstruction::UpdateVersion(v1.clone(), version_data("c")),
+                    ),
+                    (
+                        20,
+                        "react",
+                        DiffLogInstruction::UpdatePackage(package_data(Some(v2.clone()))),
+                    ),
+                    (
+                        20,
+                        "lodash",
+                        DiffLogInstruction::CreatePackage(package_data(None)),
+                    ),
+                    (20, "lodash", DiffLogInstruction::PatchPackageReferences),
+                    (30, "react", DiffLogInstruction::DeleteVersion(v1.clone())),
+                ],
+                conn,
+            );
+            quarantine_changes(
+                vec![NewQuarantinedChange {
+                    seq: 25,
+                    package_name: Some("left-pad".into()),
+                    panic_message: "oops".into(),
+                    json_path: None,
+                    quarantined_time: Utc::now(),
+                }],
+                conn,
+            );
+            internal_state::set_diff_log_processed_seq(30, conn);
+            assert_ne!(get_all_packages(conn), state_at_10);
+
+            assert_eq!(rewind_diff_log(10, conn), 2);
+
+            assert_eq!(get_all_packages(conn), state_at_10);
+            assert_eq!(get_all_diff_logs(conn), entries_at_10);
+            assert_eq!(query_num_quarantined_changes(conn), 0);
+            assert_eq!(internal_state::query_diff_log_processed_seq(conn), Some(10));
+        });
+    }
+
+    #[test]
+    fn test_replay_state_keeps_deleted_versions() {
+        let v1 = Semver::new_testing_semver(1);
+        let entries = vec![
+            DiffLogInstruction::CreatePackage(package_data(None)),
+            DiffLogInstruction::CreateVersion(v1.clone(), version_data("a")),
+            DiffLogInstruction::DeleteVersion(v1.clone()),
+            DiffLogInstruction::UpdateVersion(v1.clone(), version_data("b")),
+        ];
+        let entries = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, instr)| DiffLogEntry {
+                id: i as i64,
+                seq: 10,
+                package_name: "react".into(),
+                instr,
+            })
+            .collect();
+
+        let row = replay_state("react", entries).unwrap();
+        assert_eq!(
+            row.package_only_packument_hash,
+            package_data(None).serialize_and_hash().1
+        );
+        assert_eq!(
+            row.versions,
+            vec![InternalDiffLogVersionStateElem {
+                v: v1,
+                pack_hash: version_data("b").serialize_and_hash().1,
+                deleted: true,
+            }]
+        );
+        assert_eq!(replay_state("react", vec![]), None);
+    }
+}
@@ -1,89 +1,186 @@
-use std::collections::HashSet;
-
 use chrono::NaiveDate;
-use redis::Commands;
+use diesel::sql_types::{BigInt, Date, Integer};
 
-use super::schema::download_metrics;
-use crate::{connection::QueryRunner, custom_types::DownloadCount, packages::Package};
+use super::schema::{download_count_gaps, download_counts_daily, download_metrics};
+use crate::{connection::QueryRunner, custom_types::DownloadCount};
 use diesel::prelude::*;
 
-#[derive(Insertable, Clone, Queryable, Debug)]
-#[diesel(table_name = download_metrics)]
+/// The download counts of a package over a range of days, as fetched from the API.
+#[derive(Clone, Debug)]
 pub struct DownloadMetric {
     pub package_id: i64,
-    pub download_counts: Vec<DownloadCount>,
+    /// The first day fetched.
+    pub start_date: NaiveDate,
+    /// The last day fetched.
     pub latest_date: NaiveDate,
+    /// The days with nonzero counts. The other days in the range had no downloads, unless
+    /// they're in `gaps`.
+    pub counts: Vec<DailyDownloadCount>,
+    pub gaps: Vec<DownloadCountGap>,
 }
 
-impl DownloadMetric {
-    pub fn new(
-        package_id: i64,
-        download_counts: Vec<DownloadCount>,
-        latest_date: NaiveDate,
-    ) -> DownloadMetric {
-        DownloadMetric {
-            package_id,
-            download_counts,
-            latest_date,
-        }
-    }
+#[derive(Insertable, Queryable, Clone, Debug, PartialEq, Eq)]
+#[diesel(table_name = download_counts_daily)]
+pub struct DailyDownloadCount {
+    pub package_id: i64,
+    pub day: NaiveDate,
+    pub count: i64,
+}
+
+/// An inclusive range of days we don't know the download counts of.
+#[derive(Insertable, Queryable, Clone, Debug, PartialEq, Eq)]
+#[diesel(table_name = download_count_gaps)]
+pub struct DownloadCountGap {
+    pub package_id: i64,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
 }
 
 #[derive(Debug, Clone, Queryable)]
 pub struct QueriedDownloadMetric {
+    pub id: i64,
+    pub package_id: i64,
+    pub latest_date: NaiveDate,
+}
+
+/// A row of `download_metrics`, with the legacy weekly counts.
+#[derive(Debug, Clone, Queryable)]
+pub struct FullDownloadMetric {
     pub id: i64,
     pub package_id: i64,
     pub download_counts: Vec<DownloadCount>,
     pub latest_date: NaiveDate,
 }
 
-// impl<ST, SB: diesel::backend::Backend> Queryable<ST, SB> for QueriedDownloadMetric
-// where
-//     (i64, i64, Vec<DownloadCount>, i64, NaiveDate): diesel::deserialize::FromSqlRow<ST, SB>,
-// {
-//     type Row = (i64, i64, Vec<DownloadCount>, i64, NaiveDate);
-
-//     fn build(row: Self::Row) -> Self {
-//         QueriedDownloadMetric {
-//             id: row.0,
-//             package_id: row.1,
-//             download_counts: row.2,
-//             total_downloads: row.3,
-//             latest_date: row.4,
-//         }
-//     }
-// }
-
-impl From<QueriedDownloadMetric> for DownloadMetric {
-    fn from(qdm: QueriedDownloadMetric) -> Self {
-        DownloadMetric {
-            package_id: qdm.package_id,
-            download_counts: qdm.download_counts,
-            latest_date: qdm.latest_date,
-        }
-    }
+/// The granularities of the download count rollup views.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollup {
+    /// Weeks starting on Monday.
+    Weekly,
+    Monthly,
+}
+
+/// The downloads of a package in a week or month.
+#[derive(QueryableByName, Debug, Clone, PartialEq, Eq)]
+pub struct RolledUpDownloadCount {
+    /// The first day of the week or month.
+    #[diesel(sql_type = Date)]
+    pub period: NaiveDate,
+    /// A lower bound if `missing_days` isn't zero.
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    #[diesel(sql_type = Integer)]
+    pub missing_days: i32,
 }
 
-pub fn insert_download_metric<R: QueryRunner>(conn: &mut R, metrics: DownloadMetric) {
+const INSERT_CHUNK_SIZE: usize = 4096;
+
+/// Inserts the download metric of a package that doesn't have one yet. Should be run in a
+/// transaction.
+pub fn insert_download_metric<R: QueryRunner>(conn: &mut R, metric: DownloadMetric) {
     use super::schema::download_metrics::dsl::*;
 
-    conn.execute(
-        diesel::insert_into(download_metrics)
-            .values(metrics)
-            .on_conflict_do_nothing(),
-    )
-    .unwrap_or_else(|e| panic!("Error inserting download metric, {:?}", e));
+    let inserted = conn
+        .execute(
+            diesel::insert_into(download_metrics)
+                .values((
+                    package_id.eq(metric.package_id),
+                    latest_date.eq(metric.latest_date),
+                ))
+                .on_conflict_do_nothing(),
+        )
+        .unwrap_or_else(|e| panic!("Error inserting download metric, {:?}", e));
+    if inserted > 0 {
+        replace_download_counts(conn, &metric);
+    }
 }
 
+/// Updates a download metric with newly fetched counts, which replace what we had for the days
+/// they cover. Should be run in a transaction.
 pub fn update_metric_by_id<R: QueryRunner>(conn: &mut R, metric_id: i64, metric: DownloadMetric) {
     use super::schema::download_metrics::dsl::*;
 
     conn.execute(diesel::update(download_metrics.find(metric_id)).set((
         package_id.eq(metric.package_id),
-        download_counts.eq(metric.download_counts),
         latest_date.eq(metric.latest_date),
     )))
     .unwrap_or_else(|e| panic!("Error updating download metric, {:?}", e));
+    replace_download_counts(conn, &metric);
+}
+
+/// Empties the legacy weekly counts of a download metric, once its daily counts are fetched.
+pub fn clear_weekly_download_counts<R: QueryRunner>(conn: &mut R, metric_id: i64) {
+    use super::schema::download_metrics::dsl::*;
+
+    conn.execute(
+        diesel::update(download_metrics.find(metric_id))
+            .set(download_counts.eq(Vec::<DownloadCount>::new())),
+    )
+    .unwrap_or_else(|e| panic!("Error clearing weekly download counts, {:?}", e));
+}
+
+/// Replaces the daily counts and gaps of a package for the days from `metric.start_date` through
+/// `metric.latest_date`, trimming older gaps that overlap them.
+fn replace_download_counts<R: QueryRunner>(conn: &mut R, metric: &DownloadMetric) {
+    let (from, to) = (metric.start_date, metric.latest_date);
+
+    conn.execute(
+        diesel::delete(download_counts_daily::table)
+            .filter(download_counts_daily::package_id.eq(metric.package_id))
+            .filter(download_counts_daily::day.between(from, to)),
+    )
+    .unwrap_or_else(|e| panic!("Error deleting daily download counts, {:?}", e));
+
+    let overlapping = diesel::delete(download_count_gaps::table)
+        .filter(download_count_gaps::package_id.eq(metric.package_id))
+        .filter(download_count_gaps::start_date.le(to))
+        .filter(download_count_gaps::end_date.ge(from));
+    let old_gaps: Vec<DownloadCountGap> = conn
+        .load(overlapping.returning(download_count_gaps::all_columns))
+        .unwrap_or_else(|e| panic!("Error deleting download count gaps, {:?}", e));
+
+    let mut gaps = metric.gaps.clone();
+    for gap in old_gaps {
+        if gap.start_date < from {
+            gaps.push(DownloadCountGap {
+                end_date: from.pred_opt().unwrap(),
+                ..gap.clone()
+            });
+        }
+        if gap.end_date > to {
+            gaps.push(DownloadCountGap {
+                start_date: to.succ_opt().unwrap(),
+                ..gap
+            });
+        }
+    }
+
+    for chunk in metric.counts.chunks(INSERT_CHUNK_SIZE) {
+        conn.execute(diesel::insert_into(download_counts_daily::table).values(chunk))
+            .unwrap_or_else(|e| panic!("Error inserting daily download counts, {:?}", e));
+    }
+    for chunk in gaps.chunks(INSERT_CHUNK_SIZE) {
+        conn.execute(diesel::insert_into(download_count_gaps::table).values(chunk))
+            .unwrap_or_else(|e| panic!("Error inserting download count gaps, {:?}", e));
+    }
+}
+
+/// Creates the yearly partitions of `download_counts_daily` from `from_year` through `to_year`
+/// that don't exist yet. Counts can only be inserted for days in a partition, so this is called
+/// once at startup, for the years that will be fetched.
+pub fn create_daily_partitions<R: QueryRunner>(conn: &mut R, from_year: i32, to_year: i32) {
+    for year in from_year..=to_year {
+        tracing::debug!(
+            year,
+            "Creating the daily download counts partition, if missing"
+        );
+        conn.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS download_counts_daily_{year} PARTITION OF download_counts_daily \
+             FOR VALUES FROM ('{year}-01-01') TO ('{}-01-01')",
+            year + 1
+        ))
+        .unwrap_or_else(|e| panic!("Error creating download counts partition, {:?}", e));
+    }
 }
 
 /// Queries all download metrics with latest date being less than or equal the given date.
@@ -96,7 +193,32 @@ pub fn query_metric_latest_less_than<R: QueryRunner>(
     use super::schema::download_metrics::dsl::*;
 
     conn.load::<_, QueriedDownloadMetric>(
-        download_metrics.filter(latest_date.le(date)).limit(limit),
+        download_metrics
+            .select((id, package_id, latest_date))
+            .filter(latest_date.le(date))
+            .limit(limit),
+    )
+    .unwrap_or_else(|e| panic!("Error querying download metrics, {:?}", e))
+}
+
+/// Queries download metrics with ids greater than `after_id` that still have legacy weekly
+/// counts, ordered by id. The query is limited to the given limit.
+pub fn query_metrics_with_weekly_counts<R: QueryRunner>(
+    conn: &mut R,
+    after_id: i64,
+    limit: i64,
+) -> Vec<QueriedDownloadMetric> {
+    use super::schema::download_metrics::dsl::*;
+
+    conn.load::<_, QueriedDownloadMetric>(
+        download_metrics
+            .select((id, package_id, latest_date))
+            .filter(id.gt(after_id))
+            .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+                "cardinality(download_counts) > 0",
+            ))
+            .order(id)
+            .limit(limit),
     )
     .unwrap_or_else(|e| panic!("Error querying download metrics, {:?}", e))
 }
@@ -110,56 +232,204 @@ pub fn has_metrics_for_package_id<R: QueryRunner>(conn: &mut R, p_id: i64) -> bo
         .is_some()
 }
 
-/// Queries redis for the list of packages that have been rate-limited
-pub fn query_rate_limited_packages<R: QueryRunner>(conn: &mut R) -> Vec<Package> {
-    let mut con = conn.get_dl_redis();
+/// The nonzero daily download counts of a package, ordered by day.
+pub fn query_daily_download_counts<R: QueryRunner>(
+    conn: &mut R,
+    p_id: i64,
+) -> Vec<DailyDownloadCount> {
+    use super::schema::download_counts_daily::dsl::*;
+
+    conn.load(download_counts_daily.filter(package_id.eq(p_id)).order(day))
+        .unwrap_or_else(|e| panic!("Error querying daily download counts, {:?}", e))
+}
+
+/// The days we don't know the download counts of for a package, ordered by day.
+pub fn query_download_count_gaps<R: QueryRunner>(conn: &mut R, p_id: i64) -> Vec<DownloadCountGap> {
+    use super::schema::download_count_gaps::dsl::*;
 
-    let data: HashSet<String> = con.smembers("rate-limited").unwrap();
+    conn.load(
+        download_count_gaps
+            .filter(package_id.eq(p_id))
+            .order(start_date),
+    )
+    .unwrap_or_else(|e| panic!("Error querying download count gaps, {:?}", e))
+}
+
+/// Queries up to `limit` download metrics with ids greater than `after_id`, ordered by id.
+pub fn query_download_metrics_after_id<R: QueryRunner>(
+    conn: &mut R,
+    after_id: i64,
+    limit: i64,
+) -> Vec<FullDownloadMetric> {
+    use super::schema::download_metrics::dsl::*;
 
-    data.into_iter()
-        .map(|s| serde_json::from_str::<Package>(&s).unwrap())
-        .collect()
+    conn.load(
+        download_metrics
+            .filter(id.gt(after_id))
+            .order(id)
+            .limit(limit),
+    )
+    .unwrap_or_else(|e| panic!("Error querying download metrics, {:?}", e))
 }
 
-/// Removes a package from the rate-limited packages set in redis
-pub fn remove_rate_limited_package<R: QueryRunner>(conn: &mut R, package: &Package) {
-    let mut con = conn.get_dl_redis();
+/// The nonzero daily download counts of the given packages, ordered by package and day.
+pub fn query_daily_download_counts_by_package_ids<R: QueryRunner>(
+    conn: &mut R,
+    p_ids: &[i64],
+) -> Vec<DailyDownloadCount> {
+    use super::schema::download_counts_daily::dsl::*;
 
-    let _: () = con
-        .srem("rate-limited", serde_json::to_string(package).unwrap())
-        .unwrap();
+    conn.load(
+        download_counts_daily
+            .filter(package_id.eq_any(p_ids))
+            .order((package_id, day)),
+    )
+    .unwrap_or_else(|e| panic!("Error querying daily download counts, {:?}", e))
 }
 
-/// Removes a list of packages from the rate-limited packages set in redis
-pub fn remove_rate_limited_packages<R: QueryRunner>(conn: &mut R, package: &Vec<Package>) {
-    let mut con = conn.get_dl_redis();
+/// The download count gaps of the given packages, ordered by package and day.
+pub fn query_download_count_gaps_by_package_ids<R: QueryRunner>(
+    conn: &mut R,
+    p_ids: &[i64],
+) -> Vec<DownloadCountGap> {
+    use super::schema::download_count_gaps::dsl::*;
 
-    for p in package {
-        let _: () = con
-            .srem("rate-limited", serde_json::to_string(p).unwrap())
-            .unwrap();
-    }
+    conn.load(
+        download_count_gaps
+            .filter(package_id.eq_any(p_ids))
+            .order((package_id, start_date)),
+    )
+    .unwrap_or_else(|e| panic!("Error querying download count gaps, {:?}", e))
 }
 
-/// Adds a package to the set of rate-limited packages
-pub fn add_rate_limited_package<R: QueryRunner>(conn: &mut R, package: &Package) {
-    let mut con = conn.get_dl_redis();
+/// The oldest latest date of all download metrics, and how many metrics have a latest date
+/// before `stale_before`.
+pub fn query_latest_date_stats<R: QueryRunner>(
+    conn: &mut R,
+    stale_before: NaiveDate,
+) -> (Option<NaiveDate>, i64) {
+    use super::schema::download_metrics::dsl::*;
+    use diesel::dsl::{count_star, min};
 
-    let data = serde_json::to_string(&package).unwrap();
+    let oldest = conn
+        .first(download_metrics.select(min(latest_date)))
+        .unwrap_or_else(|e| panic!("Error querying download metrics, {:?}", e));
+    let num_stale = conn
+        .first(
+            download_metrics
+                .filter(latest_date.lt(stale_before))
+                .select(count_star()),
+        )
+        .unwrap_or_else(|e| panic!("Error querying download metrics, {:?}", e));
+    (oldest, num_stale)
+}
+
+/// The weekly or monthly download counts of a package, ordered by period. Periods without
+/// downloads or gaps are left out.
+pub fn query_rolled_up_download_counts<R: QueryRunner>(
+    conn: &mut R,
+    p_id: i64,
+    rollup: Rollup,
+) -> Vec<RolledUpDownloadCount> {
+    let (view, period) = match rollup {
+        Rollup::Weekly => ("download_counts_weekly", "week"),
+        Rollup::Monthly => ("download_counts_monthly", "month"),
+    };
+    let query = diesel::sql_query(format!(
+        "SELECT {period} AS period, count, missing_days FROM {view} \
+         WHERE package_id = $1 ORDER BY period"
+    ))
+    .bind::<BigInt, _>(p_id);
 
-    let _: () = con.sadd("rate-limited", data).unwrap();
+    conn.load(query)
+        .unwrap_or_else(|e| panic!("Error querying download count rollups, {:?}", e))
 }
 
-/// Adds a list of packages to the set of rate-limited packages
-pub fn add_rate_limited_packages<R: QueryRunner>(conn: &mut R, packages: &[Package]) {
-    let mut con = conn.get_dl_redis();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn jan(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 1, d).unwrap()
+    }
+
+    fn metric(from: u32, to: u32, counts: &[(u32, i64)], gaps: &[(u32, u32)]) -> DownloadMetric {
+        DownloadMetric {
+            package_id: 1,
+            start_date: jan(from),
+            latest_date: jan(to),
+            counts: counts
+                .iter()
+                .map(|(d, count)| DailyDownloadCount {
+                    package_id: 1,
+                    day: jan(*d),
+                    count: *count,
+                })
+                .collect(),
+            gaps: gaps
+                .iter()
+                .map(|(from, to)| DownloadCountGap {
+                    package_id: 1,
+                    start_date: jan(*from),
+                    end_date: jan(*to),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_download_counts_and_gaps() {
+        testing::using_test_db(|conn| {
+            // Jan 3 2022 is a Monday
+            insert_download_metric(conn, metric(1, 10, &[(3, 5), (4, 7), (10, 1)], &[(6, 8)]));
+            let metric_id = query_metric_latest_less_than(conn, jan(10), 1)[0].id;
 
-    let data: Vec<String> = packages
-        .iter()
-        .map(|p| serde_json::to_string(&p).unwrap())
-        .collect();
+            assert_eq!(
+                query_rolled_up_download_counts(conn, 1, Rollup::Weekly),
+                vec![
+                    RolledUpDownloadCount {
+                        period: jan(3),
+                        count: 12,
+                        missing_days: 3
+                    },
+                    RolledUpDownloadCount {
+                        period: jan(10),
+                        count: 1,
+                        missing_days: 0
+                    },
+                ]
+            );
 
-    for d in data {
-        let _: () = con.sadd("rate-limited", d).unwrap();
+            // fetching again replaces the overlapping days, and fills in part of the gap
+            update_metric_by_id(
+                conn,
+                metric_id,
+                metric(7, 12, &[(7, 2), (12, 4)], &[(11, 11)]),
+            );
+            assert_eq!(
+                query_daily_download_counts(conn, 1)
+                    .into_iter()
+                    .map(|c| (c.day, c.count))
+                    .collect::<Vec<_>>(),
+                vec![(jan(3), 5), (jan(4), 7), (jan(7), 2), (jan(12), 4)]
+            );
+            assert_eq!(
+                query_download_count_gaps(conn, 1)
+                    .into_iter()
+                    .map(|g| (g.start_date, g.end_date))
+                    .collect::<Vec<_>>(),
+                vec![(jan(6), jan(6)), (jan(11), jan(11))]
+            );
+            assert_eq!(
+                query_rolled_up_download_counts(conn, 1, Rollup::Monthly),
+                vec![RolledUpDownloadCount {
+                    period: jan(1),
+                    count: 18,
+                    missing_days: 2
+                }]
+            );
+            assert_eq!(query_metric_latest_less_than(conn, jan(11), 10).len(), 0);
+        });
     }
 }
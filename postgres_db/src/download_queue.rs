@@ -89,7 +89,7 @@ impl DownloadTask {
 
 const ENQUEUE_CHUNK_SIZE: usize = 2048;
 
-pub fn enqueue_downloads(the_downloads: Vec<DownloadTask>, conn: &mut DbConnection) -> usize {
+pub fn enqueue_downloads<R: QueryRunner>(the_downloads: Vec<DownloadTask>, conn: &mut R) -> usize {
     let mut chunk_iter = the_downloads.chunks_exact(ENQUEUE_CHUNK_SIZE);
     let mut modify_count = 0;
     for chunk in &mut chunk_iter {
@@ -101,7 +101,7 @@ pub fn enqueue_downloads(the_downloads: Vec<DownloadTask>, conn: &mut DbConnecti
     modify_count
 }
 
-fn enqueue_chunk(conn: &mut DbConnection, chunk: &[DownloadTask]) -> usize {
+fn enqueue_chunk<R: QueryRunner>(conn: &mut R, chunk: &[DownloadTask]) -> usize {
     use schema::download_tasks::dsl::*;
 
     if chunk.len() > ENQUEUE_CHUNK_SIZE {
@@ -232,6 +232,20 @@ pub fn get_total_tasks_num(conn: &mut DbConnection, retry_failed: bool) -> i64 {
     }
 }
 
+/// The number of tasks that haven't been downloaded or failed, and the queue time of the
+/// oldest one.
+pub fn query_pending_tasks_stats<R: QueryRunner>(conn: &mut R) -> (i64, Option<DateTime<Utc>>) {
+    use diesel::dsl::{count_star, min};
+    use schema::download_tasks::dsl::*;
+
+    conn.first(
+        download_tasks
+            .filter(failed.is_null())
+            .select((count_star(), min(queue_time))),
+    )
+    .expect("Failed to query pending tasks")
+}
+
 pub fn load_chunk_init(conn: &mut DbConnection, retry_failed: bool) -> Vec<DownloadTask> {
     use schema::download_tasks::dsl::*;
     if retry_failed {
@@ -278,7 +292,7 @@ pub fn load_chunk_next(
 }
 
 pub fn update_from_tarballs(conn: &mut DbConnection, tarballs: &Vec<DownloadedTarball>) {
-    println!("[MAIN] Updating {} tarballs", tarballs.len());
+    tracing::info!(num_tarballs = tarballs.len(), "Updating tarballs");
 
     // insert all the tarballs from download_queue in the db
     {
@@ -26,7 +26,7 @@ pub struct DownloadedTarball {
     pub tgz_local_path: Option<String>,
     pub blob_storage_key: Option<String>,
 
-    pub num_bytes: Option<i64>,
+    pub num_bytes: i64,
 }
 
 impl DownloadedTarball {
@@ -34,7 +34,7 @@ impl DownloadedTarball {
         task: &DownloadTask,
         tgz_local_path: Option<String>,
         blob_storage_key: Option<String>,
-        num_bytes: Option<i64>,
+        num_bytes: i64,
     ) -> Self {
         Self {
             tarball_url: task.url.clone(),
@@ -54,7 +54,7 @@ impl DownloadedTarball {
     /// Creates the downloaded tarball struct from the given download task and local path (full
     /// path to file). Sets the time of download to now.
     pub fn from_task(task: &DownloadTask, local_path: String, num_bytes: i64) -> DownloadedTarball {
-        Self::from_task_help(task, Some(local_path), None, Some(num_bytes))
+        Self::from_task_help(task, Some(local_path), None, num_bytes)
     }
 
     /// Creates the downloaded tarball struct from the given download task and blob storage key.
@@ -62,14 +62,14 @@ impl DownloadedTarball {
     pub fn from_task_blob(
         task: &DownloadTask,
         blob_key: String,
-        num_bytes: Option<i64>,
+        num_bytes: i64,
     ) -> DownloadedTarball {
         Self::from_task_help(task, None, Some(blob_key), num_bytes)
     }
 }
 
-pub fn get_downloaded_urls_matching_tasks(
-    conn: &mut DbConnection,
+pub fn get_downloaded_urls_matching_tasks<R: QueryRunner>(
+    conn: &mut R,
     chunk: &[DownloadTask],
 ) -> Vec<String> {
     use schema::downloaded_tarballs::dsl::*;
@@ -106,7 +106,7 @@ pub fn query_first_tarball_by_url(conn: &mut DbConnection) -> Option<DownloadedT
         .pop()
 }
 
-pub fn get_tarball_by_url(conn: &mut DbConnection, url: &str) -> Option<DownloadedTarball> {
+pub fn get_tarball_by_url<R: QueryRunner>(conn: &mut R, url: &str) -> Option<DownloadedTarball> {
     use schema::downloaded_tarballs::dsl::*;
 
     let query = downloaded_tarballs.filter(tarball_url.eq(url));
@@ -125,6 +125,23 @@ pub fn num_total_downloaded_tarballs(conn: &mut DbConnection) -> i64 {
         .unwrap()
 }
 
+/// The number of tarballs with urls after `after_url` (or all of them, if it's `None`), and
+/// when the oldest of them was downloaded.
+pub fn query_tarballs_after_url_stats<R: QueryRunner>(
+    conn: &mut R,
+    after_url: Option<&str>,
+) -> (i64, Option<DateTime<Utc>>) {
+    use diesel::dsl::{count_star, min};
+    use schema::downloaded_tarballs::dsl::*;
+
+    let query = downloaded_tarballs.select((count_star(), min(downloaded_at)));
+    let res = match after_url {
+        Some(after_url) => conn.first(query.filter(tarball_url.gt(after_url))),
+        None => conn.first(query),
+    };
+    res.expect("Error querying tarballs after url")
+}
+
 pub fn set_blob_storage_key(conn: &mut DbConnection, tb_url: &str, blob_key: &str) {
     use schema::downloaded_tarballs::dsl::*;
 
@@ -0,0 +1,178 @@
+use super::schema::version_exposures;
+use super::schema::version_vulnerabilities;
+use crate::connection::QueryRunner;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+
+#[derive(Insertable, Queryable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = version_vulnerabilities)]
+pub struct VersionVulnerability {
+    pub version_id: i64,
+    pub ghsa_id: String,
+}
+
+/// A window during which installing a version would install one affected by an advisory.
+#[derive(Insertable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = version_exposures)]
+pub struct VersionExposure {
+    pub version_id: i64,
+    pub ghsa_id: String,
+    pub exposed_from: DateTime<Utc>,
+    pub exposed_until: Option<DateTime<Utc>>,
+    pub depth: i32,
+    /// The versions installed along the way, ending with the affected one.
+    pub path: Vec<i64>,
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct VersionExposureRow {
+    pub id: i64,
+    pub version_id: i64,
+    pub ghsa_id: String,
+    pub exposed_from: DateTime<Utc>,
+    pub exposed_until: Option<DateTime<Utc>>,
+    pub depth: i32,
+    pub path: Vec<i64>,
+}
+
+const INSERT_CHUNK_SIZE: usize = 1024;
+
+/// Replaces everything we know about which versions an advisory affects.
+pub fn replace_ghsa_exposures<R>(
+    conn: &mut R,
+    the_ghsa_id: &str,
+    affected: Vec<VersionVulnerability>,
+    exposures: Vec<VersionExposure>,
+) where
+    R: QueryRunner,
+{
+    conn.execute(
+        diesel::delete(version_vulnerabilities::table)
+            .filter(version_vulnerabilities::ghsa_id.eq(the_ghsa_id)),
+    )
+    .expect("Failed to delete old version vulnerabilities");
+    conn.execute(
+        diesel::delete(version_exposures::table).filter(version_exposures::ghsa_id.eq(the_ghsa_id)),
+    )
+    .expect("Failed to delete old version exposures");
+
+    for chunk in affected.chunks(INSERT_CHUNK_SIZE) {
+        conn.execute(diesel::insert_into(version_vulnerabilities::table).values(chunk))
+            .expect("Failed to insert version vulnerabilities");
+    }
+    for chunk in exposures.chunks(INSERT_CHUNK_SIZE) {
+        conn.execute(diesel::insert_into(version_exposures::table).values(chunk))
+            .expect("Failed to insert version exposures");
+    }
+}
+
+/// The advisories that directly affect a version.
+pub fn query_version_vulnerabilities<R>(conn: &mut R, the_version_id: i64) -> Vec<String>
+where
+    R: QueryRunner,
+{
+    use super::schema::version_vulnerabilities::dsl::*;
+
+    conn.load(
+        version_vulnerabilities
+            .select(ghsa_id)
+            .filter(version_id.eq(the_version_id))
+            .order(ghsa_id),
+    )
+    .expect("Failed to query version vulnerabilities")
+}
+
+/// The exposure windows of a version, ordered by advisory and time. If `at` is given, only the
+/// windows containing it.
+pub fn query_version_exposures<R>(
+    conn: &mut R,
+    the_version_id: i64,
+    at: Option<DateTime<Utc>>,
+) -> Vec<VersionExposureRow>
+where
+    R: QueryRunner,
+{
+    use super::schema::version_exposures::dsl::*;
+
+    let mut query = version_exposures
+        .filter(version_id.eq(the_version_id))
+        .order((ghsa_id, exposed_from))
+        .into_boxed();
+
+    if let Some(at) = at {
+        query = query
+            .filter(exposed_from.le(at))
+            .filter(exposed_until.is_null().or(exposed_until.gt(at)));
+    }
+
+    conn.load(query).expect("Failed to query version exposures")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 1, d, 0, 0, 0).unwrap()
+    }
+
+    fn exposure(ghsa: &str, from: u32, until: Option<u32>, path: Vec<i64>) -> VersionExposure {
+        VersionExposure {
+            version_id: 1,
+            ghsa_id: ghsa.into(),
+            exposed_from: day(from),
+            exposed_until: until.map(day),
+            depth: path.len() as i32,
+            path,
+        }
+    }
+
+    #[test]
+    fn test_replace_and_query_exposures() {
+        testing::using_test_db(|conn| {
+            let affected = |ghsa: &str| VersionVulnerability {
+                version_id: 3,
+                ghsa_id: ghsa.into(),
+            };
+            replace_ghsa_exposures(
+                conn,
+                "GHSA-1",
+                vec![affected("GHSA-1")],
+                vec![
+                    exposure("GHSA-1", 1, Some(5), vec![2, 3]),
+                    exposure("GHSA-1", 5, None, vec![3]),
+                ],
+            );
+            replace_ghsa_exposures(
+                conn,
+                "GHSA-2",
+                vec![affected("GHSA-2")],
+                vec![exposure("GHSA-2", 2, Some(3), vec![3])],
+            );
+
+            assert_eq!(
+                query_version_vulnerabilities(conn, 3),
+                vec!["GHSA-1", "GHSA-2"]
+            );
+            assert_eq!(query_version_exposures(conn, 1, None).len(), 3);
+            let mut at = |d: u32| {
+                query_version_exposures(conn, 1, Some(day(d)))
+                    .into_iter()
+                    .map(|e| (e.ghsa_id, e.path))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                at(2),
+                vec![("GHSA-1".into(), vec![2, 3]), ("GHSA-2".into(), vec![3])]
+            );
+            assert_eq!(at(5), vec![("GHSA-1".into(), vec![3])]);
+
+            // replacing one advisory leaves the others alone
+            replace_ghsa_exposures(conn, "GHSA-1", vec![], vec![]);
+            assert_eq!(query_version_vulnerabilities(conn, 3), vec!["GHSA-2"]);
+            assert_eq!(query_version_exposures(conn, 1, None).len(), 1);
+        });
+    }
+}
@@ -2,14 +2,19 @@ use super::connection::DbConnection;
 use super::schema;
 use super::schema::cwes;
 use super::schema::ghsa;
+use super::schema::ghsa_aliases;
 use super::schema::ghsa_cwe_relation;
+use super::schema::ghsa_history;
 use super::schema::vulnerabilities;
 use crate::connection::QueryRunner;
-use crate::custom_types::Semver;
+use crate::custom_types::{Semver, VersionConstraint};
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::upsert::excluded;
 use diesel::Queryable;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 
 #[derive(Queryable, Insertable, Debug, Clone)]
 #[diesel(table_name = ghsa)]
@@ -24,6 +29,8 @@ pub struct Ghsa {
     pub refs: Vec<String>,
     pub cvss_score: Option<f32>,
     pub cvss_vector: Option<String>,
+    /// Where the advisory came from, e.g. `github` or `osv`.
+    pub source: String,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -36,12 +43,14 @@ pub struct GhsaVulnerability {
     pub vulnerable_version_upper_bound: Option<Semver>,
     pub vulnerable_version_upper_bound_inclusive: bool,
     pub first_patched_version: Option<Semver>,
+    /// The exact vulnerable versions, which the bounds only approximate.
+    pub vulnerable_version_constraint: VersionConstraint,
 }
 
 #[derive(Queryable, Debug, Clone)]
 #[diesel(table_name = vulnerabilities)]
 struct GhsaVulnerabilityRow {
-    _id: i64,
+    id: i64,
     ghsa_id: String,
     package_name: String,
     vulnerable_version_lower_bound: Option<Semver>,
@@ -49,6 +58,122 @@ struct GhsaVulnerabilityRow {
     vulnerable_version_upper_bound: Option<Semver>,
     vulnerable_version_upper_bound_inclusive: bool,
     first_patched_version: Option<Semver>,
+    vulnerable_version_constraint: VersionConstraint,
+}
+
+impl From<GhsaVulnerabilityRow> for GhsaVulnerability {
+    fn from(row: GhsaVulnerabilityRow) -> Self {
+        GhsaVulnerability {
+            ghsa_id: row.ghsa_id,
+            package_name: row.package_name,
+            vulnerable_version_lower_bound: row.vulnerable_version_lower_bound,
+            vulnerable_version_lower_bound_inclusive: row.vulnerable_version_lower_bound_inclusive,
+            vulnerable_version_upper_bound: row.vulnerable_version_upper_bound,
+            vulnerable_version_upper_bound_inclusive: row.vulnerable_version_upper_bound_inclusive,
+            first_patched_version: row.first_patched_version,
+            vulnerable_version_constraint: row.vulnerable_version_constraint,
+        }
+    }
+}
+
+/// A vulnerable range of an advisory, as its source gave it.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GhsaHistoryVulnerability {
+    pub package_name: String,
+    pub vulnerable_version_range: String,
+    pub first_patched_version: Option<String>,
+}
+
+/// A revision of an advisory to record in `ghsa_history`.
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = ghsa_history)]
+pub struct GhsaRevision {
+    pub ghsa_id: String,
+    pub observed_at: DateTime<Utc>,
+    pub severity: String,
+    pub description: String,
+    pub summary: String,
+    pub withdrawn_at: Option<DateTime<Utc>>,
+    pub published_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub refs: Vec<String>,
+    pub cvss_score: Option<f32>,
+    pub cvss_vector: Option<String>,
+    pub cwe_ids: Vec<String>,
+    pub vulnerabilities: Value,
+    pub revision_hash: String,
+    pub source: String,
+}
+
+impl GhsaRevision {
+    pub fn new(
+        advisory: &Ghsa,
+        mut cwe_ids: Vec<String>,
+        mut vulns: Vec<GhsaHistoryVulnerability>,
+        observed_at: DateTime<Utc>,
+    ) -> GhsaRevision {
+        // sources don't promise any order, so we sort to not see reorderings as changes
+        cwe_ids.sort();
+        cwe_ids.dedup();
+        vulns.sort();
+        let vulnerabilities = serde_json::to_value(vulns).unwrap();
+
+        let content = json!([
+            advisory.id,
+            advisory.severity,
+            advisory.description,
+            advisory.summary,
+            advisory.withdrawn_at,
+            advisory.published_at,
+            advisory.updated_at,
+            advisory.refs,
+            advisory.cvss_score,
+            advisory.cvss_vector,
+            cwe_ids,
+            vulnerabilities,
+        ]);
+        let mut hasher = Sha256::new();
+        hasher.update(content.to_string());
+        let revision_hash = format!("{:x}", hasher.finalize());
+
+        GhsaRevision {
+            ghsa_id: advisory.id.clone(),
+            observed_at,
+            severity: advisory.severity.clone(),
+            description: advisory.description.clone(),
+            summary: advisory.summary.clone(),
+            withdrawn_at: advisory.withdrawn_at,
+            published_at: advisory.published_at,
+            updated_at: advisory.updated_at,
+            refs: advisory.refs.clone(),
+            cvss_score: advisory.cvss_score,
+            cvss_vector: advisory.cvss_vector.clone(),
+            cwe_ids,
+            vulnerabilities,
+            revision_hash,
+            source: advisory.source.clone(),
+        }
+    }
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct GhsaHistoryRow {
+    pub id: i64,
+    pub ghsa_id: String,
+    pub observed_at: DateTime<Utc>,
+    pub severity: String,
+    pub description: String,
+    pub summary: String,
+    pub withdrawn_at: Option<DateTime<Utc>>,
+    pub published_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub refs: Vec<String>,
+    pub cvss_score: Option<f32>,
+    pub cvss_vector: Option<String>,
+    pub cwe_ids: Vec<String>,
+    pub vulnerabilities: Value,
+    pub revision_hash: String,
+    pub source: String,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -66,6 +191,14 @@ pub struct GhsaCweRelation {
     pub cwe_id: String,
 }
 
+#[derive(Insertable, Queryable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = ghsa_aliases)]
+pub struct GhsaAlias {
+    pub ghsa_id: String,
+    pub alias: String,
+    pub source: String,
+}
+
 pub fn insert_ghsa<R>(conn: &mut R, advisory: Ghsa, vulns: Vec<GhsaVulnerability>)
 where
     R: QueryRunner,
@@ -88,6 +221,7 @@ where
             refs.eq(excluded(refs)),
             cvss_score.eq(excluded(cvss_score)),
             cvss_vector.eq(excluded(cvss_vector)),
+            source.eq(excluded(source)),
         ));
 
     conn.execute(insert_ghsa_query)
@@ -108,6 +242,80 @@ where
         .expect("Failed to insert vulnerabilities");
 }
 
+/// Appends `revision` to `ghsa_history`, unless the last revision of the advisory recorded from
+/// the same source is the same. Returns whether it was appended.
+pub fn insert_ghsa_revision<R>(conn: &mut R, revision: GhsaRevision) -> bool
+where
+    R: QueryRunner,
+{
+    use schema::ghsa_history::dsl::*;
+
+    let last_hash: Option<String> = conn
+        .first(
+            ghsa_history
+                .select(revision_hash)
+                .filter(ghsa_id.eq(&revision.ghsa_id))
+                .filter(source.eq(&revision.source))
+                .order(id.desc()),
+        )
+        .optional()
+        .expect("Failed to query ghsa history");
+
+    if last_hash.as_ref() == Some(&revision.revision_hash) {
+        return false;
+    }
+
+    conn.execute(diesel::insert_into(ghsa_history).values(revision))
+        .expect("Failed to insert ghsa revision");
+    true
+}
+
+/// All recorded revisions of an advisory from all sources, oldest first.
+pub fn query_ghsa_history<R>(conn: &mut R, the_ghsa_id: &str) -> Vec<GhsaHistoryRow>
+where
+    R: QueryRunner,
+{
+    use schema::ghsa_history::dsl::*;
+
+    conn.load(ghsa_history.filter(ghsa_id.eq(the_ghsa_id)).order(id))
+        .expect("Failed to query ghsa history")
+}
+
+/// The latest `updated_at` of the advisories we have from GitHub, which is where incremental
+/// scraping resumes. Advisories from other sources are imported separately, so they're left out.
+pub fn query_latest_ghsa_updated_at<R>(conn: &mut R) -> Option<DateTime<Utc>>
+where
+    R: QueryRunner,
+{
+    use diesel::dsl::max;
+    use schema::ghsa::dsl::*;
+
+    conn.get_result(ghsa.filter(source.eq("github")).select(max(updated_at)))
+        .expect("Failed to query latest ghsa update")
+}
+
+/// The source of an advisory, if we have it.
+pub fn query_ghsa_source<R>(conn: &mut R, the_ghsa_id: &str) -> Option<String>
+where
+    R: QueryRunner,
+{
+    use schema::ghsa::dsl::*;
+
+    conn.first(ghsa.select(source).filter(id.eq(the_ghsa_id)))
+        .optional()
+        .expect("Failed to query ghsa source")
+}
+
+pub fn query_ghsa_ids<R>(conn: &mut R) -> Vec<String>
+where
+    R: QueryRunner,
+{
+    use schema::ghsa::dsl::*;
+
+    conn.load(ghsa.select(id))
+        .expect("Failed to query ghsa ids")
+}
+
 pub fn query_ghsa_by_id(conn: &mut DbConnection, ghsa_id: &str) -> (Ghsa, Vec<GhsaVulnerability>) {
     use schema::ghsa::dsl::*;
 
@@ -126,22 +334,45 @@ pub fn query_ghsa_by_id(conn: &mut DbConnection, ghsa_id: &str) -> (Ghsa, Vec<Gh
             )
         });
 
-    let vulns = vuln_rows
-        .into_iter()
-        .map(|row| GhsaVulnerability {
-            ghsa_id: row.ghsa_id,
-            package_name: row.package_name,
-            vulnerable_version_lower_bound: row.vulnerable_version_lower_bound,
-            vulnerable_version_lower_bound_inclusive: row.vulnerable_version_lower_bound_inclusive,
-            vulnerable_version_upper_bound: row.vulnerable_version_upper_bound,
-            vulnerable_version_upper_bound_inclusive: row.vulnerable_version_upper_bound_inclusive,
-            first_patched_version: row.first_patched_version,
-        })
-        .collect();
+    let vulns = vuln_rows.into_iter().map(GhsaVulnerability::from).collect();
 
     (adv, vulns)
 }
 
+/// Returns up to `limit` advisories with an id greater than `after_id`, ordered by id.
+pub fn query_ghsa_after_id<R>(conn: &mut R, after_id: &str, limit: i64) -> Vec<Ghsa>
+where
+    R: QueryRunner,
+{
+    use schema::ghsa::dsl::*;
+
+    conn.load(ghsa.filter(id.gt(after_id)).order(id).limit(limit))
+        .expect("Failed to query ghsa")
+}
+
+/// Returns up to `limit` `(id, vulnerability)` pairs with an id greater than `after_id`, ordered
+/// by id.
+pub fn query_vulnerabilities_after_id<R>(
+    conn: &mut R,
+    after_id: i64,
+    limit: i64,
+) -> Vec<(i64, GhsaVulnerability)>
+where
+    R: QueryRunner,
+{
+    use schema::vulnerabilities::dsl::*;
+
+    let rows: Vec<GhsaVulnerabilityRow> = conn
+        .load(
+            vulnerabilities
+                .filter(id.gt(after_id))
+                .order(id)
+                .limit(limit),
+        )
+        .expect("Failed to query vulnerabilities");
+    rows.into_iter().map(|row| (row.id, row.into())).collect()
+}
+
 const INSERT_CHUNK_SIZE: usize = 256;
 
 pub fn insert_cwes<R>(conn: &mut R, cwes_to_insert: Vec<Cwe>)
@@ -175,6 +406,33 @@ where
         .expect("Failed to insert cwes");
 }
 
+/// Inserts CWEs we only know the ids of, without touching the ones we already have.
+pub fn insert_missing_cwes<R>(conn: &mut R, cwe_ids: Vec<String>)
+where
+    R: QueryRunner,
+{
+    use schema::cwes::dsl::*;
+
+    let cwes_to_insert: Vec<_> = cwe_ids
+        .into_iter()
+        .map(|cwe_id| Cwe {
+            id: cwe_id,
+            name: String::new(),
+            description: String::new(),
+        })
+        .collect();
+
+    for chunk in cwes_to_insert.chunks(INSERT_CHUNK_SIZE) {
+        let insert_cwes_query = diesel::insert_into(cwes)
+            .values(chunk)
+            .on_conflict(id)
+            .do_nothing();
+
+        conn.execute(insert_cwes_query)
+            .expect("Failed to insert cwes");
+    }
+}
+
 pub fn associate_ghsa_to_cwe<R>(conn: &mut R, assoc: Vec<GhsaCweRelation>)
 where
     R: QueryRunner,
@@ -201,3 +459,226 @@ where
 
     conn.execute(insert_rels).expect("Failed to insert rels");
 }
+
+/// Replaces the aliases of an advisory reported by `the_source`.
+pub fn set_ghsa_aliases<R>(conn: &mut R, the_ghsa_id: &str, the_source: &str, aliases: Vec<String>)
+where
+    R: QueryRunner,
+{
+    use schema::ghsa_aliases::dsl::*;
+
+    let delete_old_query = diesel::delete(ghsa_aliases)
+        .filter(ghsa_id.eq(the_ghsa_id))
+        .filter(source.eq(the_source));
+
+    conn.execute(delete_old_query)
+        .expect("Failed to delete old ghsa aliases");
+
+    let mut rows: Vec<_> = aliases
+        .into_iter()
+        .filter(|a| a != the_ghsa_id)
+        .map(|a| GhsaAlias {
+            ghsa_id: the_ghsa_id.to_owned(),
+            alias: a,
+            source: the_source.to_owned(),
+        })
+        .collect();
+    rows.sort_by(|a, b| a.alias.cmp(&b.alias));
+    rows.dedup();
+
+    conn.execute(diesel::insert_into(ghsa_aliases).values(rows))
+        .expect("Failed to insert ghsa aliases");
+}
+
+/// The advisories known under `the_alias`, as any source reports it, along with the advisory
+/// with that id itself if we have it.
+pub fn query_ghsa_ids_by_alias<R>(conn: &mut R, the_alias: &str) -> Vec<String>
+where
+    R: QueryRunner,
+{
+    use schema::ghsa_aliases::dsl::*;
+
+    let mut ids: Vec<String> = conn
+        .load(ghsa_aliases.select(ghsa_id).filter(alias.eq(the_alias)))
+        .expect("Failed to query ghsa aliases");
+    if query_ghsa_source(conn, the_alias).is_some() {
+        ids.push(the_alias.to_owned());
+    }
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::testing;
+
+    fn advisory(severity: &str) -> Ghsa {
+        Ghsa {
+            id: "GHSA-xxxx-xxxx-xxxx".into(),
+            severity: severity.into(),
+            description: "description".into(),
+            summary: "summary".into(),
+            withdrawn_at: None,
+            published_at: Utc::now(),
+            updated_at: Utc::now(),
+            refs: vec!["https://example.com".into()],
+            cvss_score: Some(7.5),
+            cvss_vector: None,
+            source: "github".into(),
+        }
+    }
+
+    fn vuln(range: &str) -> GhsaHistoryVulnerability {
+        GhsaHistoryVulnerability {
+            package_name: "lodash".into(),
+            vulnerable_version_range: range.into(),
+            first_patched_version: None,
+        }
+    }
+
+    #[test]
+    fn test_ghsa_history_only_records_changes() {
+        testing::using_test_db(|conn| {
+            let high = advisory("HIGH");
+            let revision = |a: &Ghsa, cwes: &[&str], vulns: Vec<GhsaHistoryVulnerability>| {
+                let cwes = cwes.iter().map(|c| c.to_string()).collect();
+                GhsaRevision::new(a, cwes, vulns, Utc::now())
+            };
+
+            assert!(insert_ghsa_revision(
+                conn,
+                revision(
+                    &high,
+                    &["CWE-1", "CWE-2"],
+                    vec![vuln("< 1.0"), vuln("= 2.0.0")]
+                )
+            ));
+            // reordering isn't a change
+            assert!(!insert_ghsa_revision(
+                conn,
+                revision(
+                    &high,
+                    &["CWE-2", "CWE-1"],
+                    vec![vuln("= 2.0.0"), vuln("< 1.0")]
+                )
+            ));
+            assert!(insert_ghsa_revision(
+                conn,
+                revision(&high, &["CWE-1", "CWE-2"], vec![vuln("< 1.1")])
+            ));
+            assert!(insert_ghsa_revision(
+                conn,
+                revision(&advisory("LOW"), &["CWE-1", "CWE-2"], vec![vuln("< 1.1")])
+            ));
+            // going back to an older revision is a change
+            assert!(insert_ghsa_revision(
+                conn,
+                revision(&high, &["CWE-1", "CWE-2"], vec![vuln("< 1.1")])
+            ));
+
+            let history = query_ghsa_history(conn, &high.id);
+            assert_eq!(
+                history
+                    .iter()
+                    .map(|h| h.severity.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["HIGH", "HIGH", "LOW", "HIGH"]
+            );
+            assert_eq!(
+                history[0].vulnerabilities,
+                json!([
+                    {
+                        "package_name": "lodash",
+                        "vulnerable_version_range": "< 1.0",
+                        "first_patched_version": null,
+                    },
+                    {
+                        "package_name": "lodash",
+                        "vulnerable_version_range": "= 2.0.0",
+                        "first_patched_version": null,
+                    },
+                ])
+            );
+        });
+    }
+
+    #[test]
+    fn test_ghsa_history_is_per_source() {
+        testing::using_test_db(|conn| {
+            let github = advisory("HIGH");
+            let osv = Ghsa {
+                source: "osv".into(),
+                ..github.clone()
+            };
+            let revision = |a: &Ghsa| GhsaRevision::new(a, vec![], vec![vuln("< 1.0")], Utc::now());
+
+            assert!(insert_ghsa_revision(conn, revision(&github)));
+            assert!(insert_ghsa_revision(conn, revision(&osv)));
+            assert!(!insert_ghsa_revision(conn, revision(&github)));
+            assert!(!insert_ghsa_revision(conn, revision(&osv)));
+
+            let history = query_ghsa_history(conn, &github.id);
+            assert_eq!(
+                history
+                    .iter()
+                    .map(|h| h.source.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["github", "osv"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_latest_ghsa_updated_at_is_from_github() {
+        testing::using_test_db(|conn| {
+            assert_eq!(query_latest_ghsa_updated_at(conn), None);
+
+            let github_updated = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap();
+            let github = Ghsa {
+                updated_at: github_updated,
+                ..advisory("HIGH")
+            };
+            let osv = Ghsa {
+                id: "GHSA-yyyy-yyyy-yyyy".into(),
+                updated_at: Utc.with_ymd_and_hms(2022, 6, 1, 0, 0, 0).unwrap(),
+                source: "osv".into(),
+                ..advisory("HIGH")
+            };
+            insert_ghsa(conn, osv, vec![]);
+            assert_eq!(query_latest_ghsa_updated_at(conn), None);
+
+            insert_ghsa(conn, github, vec![]);
+            assert_eq!(query_latest_ghsa_updated_at(conn), Some(github_updated));
+        });
+    }
+
+    #[test]
+    fn test_ghsa_aliases() {
+        testing::using_test_db(|conn| {
+            let adv = advisory("HIGH");
+            insert_ghsa(conn, adv.clone(), vec![]);
+            assert_eq!(query_ghsa_source(conn, &adv.id), Some("github".into()));
+
+            let aliases = |a: &[&str]| a.iter().map(|a| a.to_string()).collect();
+            set_ghsa_aliases(conn, &adv.id, "github", aliases(&["CVE-2020-1", &adv.id]));
+            set_ghsa_aliases(conn, &adv.id, "osv", aliases(&["CVE-2020-1", "CVE-2020-2"]));
+            // replaces the previous aliases from the same source
+            set_ghsa_aliases(conn, &adv.id, "osv", aliases(&["CVE-2020-2"]));
+
+            assert_eq!(
+                query_ghsa_ids_by_alias(conn, "CVE-2020-1"),
+                vec![adv.id.clone()]
+            );
+            assert_eq!(
+                query_ghsa_ids_by_alias(conn, "CVE-2020-2"),
+                vec![adv.id.clone()]
+            );
+            assert_eq!(query_ghsa_ids_by_alias(conn, &adv.id), vec![adv.id.clone()]);
+            assert!(query_ghsa_ids_by_alias(conn, "CVE-2020-3").is_empty());
+        });
+    }
+}
@@ -19,6 +19,26 @@ pub fn set_relational_processed_seq<R: QueryRunner>(seq: i64, conn: &mut R) {
     set_key_value_int_state("relational_processed_seq", seq, conn);
 }
 
+/// The seqs the relational tables were rewound to, oldest first.
+pub fn query_relational_rewinds<R: QueryRunner>(conn: &mut R) -> Vec<i64> {
+    query_key_value_string_state("relational_rewinds", conn)
+        .map(|rewinds| {
+            rewinds
+                .split(',')
+                .map(|seq| seq.parse().expect("Invalid seq in relational_rewinds"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn add_relational_rewind<R: QueryRunner>(to_seq: i64, conn: &mut R) {
+    tracing::info!(to_seq, "Recording a rewind of the relational tables");
+    let mut rewinds = query_relational_rewinds(conn);
+    rewinds.push(to_seq);
+    let rewinds: Vec<String> = rewinds.iter().map(i64::to_string).collect();
+    set_key_value_string_state("relational_rewinds", rewinds.join(","), conn);
+}
+
 pub fn query_queued_downloads_seq<R: QueryRunner>(conn: &mut R) -> Option<i64> {
     query_key_value_int_state("queued_downloads_seq", conn)
 }
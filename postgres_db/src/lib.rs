@@ -8,10 +8,14 @@ pub mod diff_analysis;
 pub mod diff_log;
 pub mod download_queue;
 pub mod download_tarball;
+pub mod exposures;
 pub mod ghsa;
 pub mod internal_state;
+pub mod notifications;
 pub mod packages;
 pub mod packument;
+pub mod replay;
+pub mod rewind;
 #[allow(unused_imports)]
 pub mod schema;
 pub mod versions;
@@ -22,5 +26,4 @@ pub mod download_metrics;
 
 pub mod serde_non_string_key_serialization;
 
-#[cfg(test)]
 pub mod testing;
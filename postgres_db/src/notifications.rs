@@ -0,0 +1,116 @@
+//! Change notifications between the pipeline stages, over Postgres `LISTEN`/`NOTIFY`. Each stage
+//! notifies its channel when it advances, and stages running as daemons listen on the channel of
+//! the stage upstream of them.
+
+use std::time::Duration;
+
+use diesel::sql_types::Text;
+use postgres::fallible_iterator::FallibleIterator;
+
+use crate::connection::QueryRunner;
+
+/// New changes were inserted into `change_log`.
+pub const CHANGE_LOG_CHANNEL: &str = "change_log_advanced";
+/// The diff log was built through a new seq.
+pub const DIFF_LOG_CHANNEL: &str = "diff_log_advanced";
+/// The relational tables were built through a new seq.
+pub const RELATIONAL_CHANNEL: &str = "relational_advanced";
+/// Downloads were queued through a new seq.
+pub const QUEUED_DOWNLOADS_CHANNEL: &str = "queued_downloads_advanced";
+
+/// Notifies `channel`. In a transaction, listeners only get it once the transaction commits.
+pub fn notify<R: QueryRunner>(conn: &mut R, channel: &str, payload: &str) {
+    let query = diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(channel)
+        .bind::<Text, _>(payload);
+    conn.execute(query)
+        .unwrap_or_else(|e| panic!("Error notifying {}, {:?}", channel, e));
+}
+
+/// A connection listening on some channels. Diesel can't receive notifications, so this is a
+/// separate connection.
+pub struct Listener {
+    client: postgres::Client,
+}
+
+impl Listener {
+    /// Listens on `channels` with a new connection to `DATABASE_URL`.
+    pub fn connect(channels: &[&str]) -> Listener {
+        use dotenv::dotenv;
+        use std::env;
+
+        dotenv().expect("failed to load .env");
+
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        Listener::connect_to(&database_url, channels)
+    }
+
+    pub fn connect_to(database_url: &str, channels: &[&str]) -> Listener {
+        let mut client = postgres::Client::connect(database_url, postgres::NoTls)
+            .unwrap_or_else(|e| panic!("Error connecting to {}, {:?}", database_url, e));
+        for channel in channels {
+            client
+                .batch_execute(&format!("LISTEN \"{}\"", channel))
+                .unwrap_or_else(|e| panic!("Error listening on {}, {:?}", channel, e));
+        }
+        Listener { client }
+    }
+
+    /// Waits up to `timeout` for a notification, returning whether there was one. Any other
+    /// notifications that already arrived are dropped, since a single run of a stage catches up
+    /// with all of them.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        let mut notifications = self.client.notifications();
+        let notified = notifications
+            .timeout_iter(timeout)
+            .next()
+            .expect("Error waiting for notifications")
+            .is_some();
+        let mut pending = notifications.iter();
+        while pending
+            .next()
+            .expect("Error reading notifications")
+            .is_some()
+        {}
+        notified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{notify, Listener, DIFF_LOG_CHANNEL};
+    use crate::testing::using_test_db;
+
+    #[test]
+    fn test_notifications_arrive_on_commit() {
+        using_test_db(|conn| {
+            let url = std::env::var("TESTING_DATABASE_URL").unwrap();
+            let mut listener = Listener::connect_to(&url, &[DIFF_LOG_CHANNEL]);
+            let timeout = Duration::from_millis(200);
+
+            assert!(!listener.wait(timeout));
+
+            conn.run_psql_transaction(|mut trans_conn| {
+                notify(&mut trans_conn, DIFF_LOG_CHANNEL, "1");
+                Ok(((), false))
+            })
+            .unwrap();
+            assert!(!listener.wait(timeout));
+
+            conn.run_psql_transaction(|mut trans_conn| {
+                notify(&mut trans_conn, DIFF_LOG_CHANNEL, "2");
+                notify(&mut trans_conn, DIFF_LOG_CHANNEL, "3");
+                Ok(((), true))
+            })
+            .unwrap();
+            assert!(listener.wait(timeout));
+            // both were handled by the first wait
+            assert!(!listener.wait(timeout));
+
+            notify(conn, "some_other_channel", "4");
+            assert!(!listener.wait(timeout));
+        });
+    }
+}
@@ -42,6 +42,22 @@ pub struct NewPackage {
     pub unpublished_data: Option<Value>,
 }
 
+impl From<Package> for NewPackage {
+    fn from(package: Package) -> Self {
+        NewPackage {
+            name: package.name,
+            current_package_state_type: package.current_package_state_type,
+            package_state_history: package.package_state_history,
+            dist_tag_latest_version: package.dist_tag_latest_version,
+            created: package.created,
+            modified: package.modified,
+            other_dist_tags: package.other_dist_tags,
+            other_time_data: package.other_time_data,
+            unpublished_data: package.unpublished_data,
+        }
+    }
+}
+
 #[derive(Debug, AsChangeset)]
 #[diesel(table_name = packages)]
 pub struct PackageUpdate {
@@ -172,11 +188,64 @@ pub fn query_next_pkg_id<R: QueryRunner>(conn: &mut R, pkg_id: i64) -> Option<i6
     conn.first(query).optional().expect("Error loading package")
 }
 
+/// Gets up to `limit` package ids greater than the given id, in order.
+pub fn query_package_ids_after<R: QueryRunner>(
+    conn: &mut R,
+    after_id: i64,
+    limit: i64,
+) -> Vec<i64> {
+    use super::schema::packages::dsl::*;
+
+    let query = packages
+        .filter(id.gt(after_id))
+        .order(id.asc())
+        .select(id)
+        .limit(limit);
+
+    conn.load(query).expect("Error loading package ids")
+}
+
+/// Gets up to `limit` packages with an id greater than the given id, in order.
+pub fn query_packages_after_id<R: QueryRunner>(
+    conn: &mut R,
+    after_id: i64,
+    limit: i64,
+) -> Vec<Package> {
+    use super::schema::packages::dsl::*;
+
+    let query = packages
+        .filter(id.gt(after_id))
+        .order(id.asc())
+        .limit(limit);
+
+    conn.load(query).expect("Error loading packages")
+}
+
+/// Gets the ids of `n` random packages. This scans the whole table.
+pub fn query_random_package_ids<R: QueryRunner>(conn: &mut R, n: i64) -> Vec<i64> {
+    use super::schema::packages::dsl::*;
+
+    let query = packages
+        .select(id)
+        .order(diesel::dsl::sql::<diesel::sql_types::Double>("random()"))
+        .limit(n);
+
+    conn.load(query).expect("Error sampling package ids")
+}
+
 pub fn update_package<R: QueryRunner>(conn: &mut R, package_id: i64, update: PackageUpdate) {
     let query = diesel::update(packages::table.filter(packages::id.eq(package_id))).set(update);
     conn.execute(query).expect("Error updating package");
 }
 
+/// Deletes a package row. Its versions, and its own `dist_tag_latest_version`, must already be gone,
+/// and dependencies on it must not refer to it by id anymore.
+pub fn delete_package<R: QueryRunner>(conn: &mut R, package_id: i64) {
+    let query = diesel::delete(packages::table.filter(packages::id.eq(package_id)));
+    let rows = conn.execute(query).expect("Error deleting package");
+    assert_eq!(rows, 1);
+}
+
 pub fn get_package<R: QueryRunner>(conn: &mut R, package_id: i64) -> Package {
     maybe_get_package(conn, package_id)
         .unwrap_or_else(|| panic!("Package with id {} not found", package_id))
@@ -193,6 +262,15 @@ pub fn get_package_by_name<R: QueryRunner>(conn: &mut R, package_name: &str) ->
         .expect("Error getting package by name")
 }
 
+/// Gets the packages with the given names, in no particular order. Missing names are skipped.
+pub fn get_packages_by_names<R: QueryRunner>(
+    conn: &mut R,
+    package_names: &[String],
+) -> Vec<Package> {
+    let query = packages::table.filter(packages::name.eq_any(package_names));
+    conn.load(query).expect("Error getting packages by names")
+}
+
 pub fn maybe_get_package_id_by_name<R: QueryRunner>(
     conn: &mut R,
     package_name: &str,
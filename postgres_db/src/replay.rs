@@ -0,0 +1,301 @@
+//! Replaying the diff entries of a package in memory, to get the rows the `EntryProcessor` of
+//! relational_db_builder should have built from them. The package rows are built by the same
+//! functions the `EntryProcessor` uses.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{
+    custom_types::{
+        PackageStateTimePoint, PackageStateType, Semver, VersionStateTimePoint, VersionStateType,
+    },
+    diff_log::{DiffLogEntry, DiffLogInstruction},
+    packages::{NewPackage, Package},
+    packument::{PackageOnlyPackument, VersionOnlyPackument},
+    serde_non_string_key_serialization::BTreeMapSerializedAsString,
+};
+
+pub struct ReplayedPackage {
+    /// `None` if the entries don't create the package.
+    pub package: Option<Package>,
+    pub versions: BTreeMap<Semver, ReplayedVersion>,
+}
+
+pub struct ReplayedVersion {
+    pub version_state_history: Vec<VersionStateTimePoint>,
+    /// The packument the version was created with. Only its `extra_metadata` may change later.
+    pub created_with: VersionOnlyPackument,
+    /// The last packument of the version.
+    pub last: VersionOnlyPackument,
+}
+
+impl ReplayedVersion {
+    pub fn current_state(&self) -> &VersionStateType {
+        &self
+            .version_state_history
+            .last()
+            .expect("Replayed versions have a state")
+            .state
+    }
+}
+
+/// Replays the diff `entries` of a package, in order, like `EntryProcessor` processes them.
+///
+/// The package row keeps the id of `current`, its row in the `packages` table, and `version_id`
+/// looks up the ids of versions that become the latest version.
+pub fn replay_package<F>(
+    current: &Package,
+    entries: Vec<DiffLogEntry>,
+    mut version_id: F,
+) -> ReplayedPackage
+where
+    F: FnMut(Semver) -> i64,
+{
+    let mut package: Option<Package> = None;
+    let mut versions: BTreeMap<Semver, ReplayedVersion> = BTreeMap::new();
+
+    for entry in entries {
+        match entry.instr {
+            DiffLogInstruction::CreatePackage(data) => {
+                let mut created = current.clone();
+                let diff = created.diff(created_package_row(
+                    current.name.clone(),
+                    data,
+                    entry.seq,
+                    entry.id,
+                ));
+                created.apply_diff(&diff);
+                package = Some(created);
+            }
+            DiffLogInstruction::UpdatePackage(data) => {
+                let old = package.as_mut().unwrap_or_else(|| {
+                    panic!(
+                        "UpdatePackage before CreatePackage of {} at seq {}",
+                        current.name, entry.seq
+                    )
+                });
+                let new_row = updated_package_row(old, data, entry.seq, entry.id, &mut version_id);
+                let diff = old.diff(new_row);
+                old.apply_diff(&diff);
+            }
+            DiffLogInstruction::CreateVersion(v, data) => {
+                let history = vec![VersionStateTimePoint {
+                    state: VersionStateType::Normal,
+                    seq: entry.seq,
+                    diff_entry_id: entry.id,
+                    estimated_time: Some(data.time),
+                }];
+                versions.insert(
+                    v,
+                    ReplayedVersion {
+                        version_state_history: history,
+                        created_with: data.clone(),
+                        last: data,
+                    },
+                );
+            }
+            DiffLogInstruction::UpdateVersion(v, data) => {
+                let version = versions.get_mut(&v).unwrap_or_else(|| {
+                    panic!("updating missing version {} at seq {}", v, entry.seq)
+                });
+                if version.current_state() != &VersionStateType::Normal {
+                    version.version_state_history.push(VersionStateTimePoint {
+                        state: VersionStateType::Normal,
+                        seq: entry.seq,
+                        diff_entry_id: entry.id,
+                        estimated_time: None,
+                    });
+                }
+                version.last = data;
+            }
+            DiffLogInstruction::DeleteVersion(v) => {
+                let version = versions.get_mut(&v).unwrap_or_else(|| {
+                    panic!("deleting missing version {} at seq {}", v, entry.seq)
+                });
+                version.version_state_history.push(VersionStateTimePoint {
+                    state: VersionStateType::Deleted,
+                    seq: entry.seq,
+                    diff_entry_id: entry.id,
+                    estimated_time: None,
+                });
+            }
+            DiffLogInstruction::PatchPackageReferences => {}
+        }
+    }
+
+    ReplayedPackage { package, versions }
+}
+
+/// The row of a package created by `data`.
+pub fn created_package_row(
+    package: String,
+    data: PackageOnlyPackument,
+    seq: i64,
+    diff_entry_id: i64,
+) -> NewPackage {
+    match data {
+        PackageOnlyPackument::Normal {
+            latest,
+            created,
+            modified,
+            other_dist_tags,
+            extra_version_times,
+        } => {
+            assert_eq!(latest, None);
+            NewPackage {
+                name: package,
+                current_package_state_type: PackageStateType::Normal,
+                package_state_history: vec![PackageStateTimePoint {
+                    state: PackageStateType::Normal,
+                    seq,
+                    diff_entry_id,
+                    estimated_time: Some(created),
+                }],
+                dist_tag_latest_version: None,
+                created: Some(created),
+                modified: Some(modified),
+                other_dist_tags: Some(Value::Object(other_dist_tags)),
+                other_time_data: Some(
+                    serde_json::to_value(BTreeMapSerializedAsString::new(extra_version_times))
+                        .unwrap(),
+                ),
+                unpublished_data: None,
+            }
+        }
+        PackageOnlyPackument::Unpublished {
+            created,
+            modified,
+            unpublished_blob,
+            extra_version_times,
+        } => NewPackage {
+            name: package,
+            current_package_state_type: PackageStateType::Unpublished,
+            package_state_history: vec![PackageStateTimePoint {
+                state: PackageStateType::Unpublished,
+                seq,
+                diff_entry_id,
+                estimated_time: Some(created),
+            }],
+            dist_tag_latest_version: None,
+            created: Some(created),
+            modified: Some(modified),
+            other_dist_tags: None,
+            other_time_data: Some(
+                serde_json::to_value(BTreeMapSerializedAsString::new(extra_version_times)).unwrap(),
+            ),
+            unpublished_data: Some(unpublished_blob),
+        },
+        // Maybe we want to treat these separately?
+        PackageOnlyPackument::Deleted | PackageOnlyPackument::MissingData => NewPackage {
+            name: package,
+            current_package_state_type: PackageStateType::Deleted,
+            package_state_history: vec![PackageStateTimePoint {
+                state: PackageStateType::Deleted,
+                seq,
+                diff_entry_id,
+                estimated_time: None, // TODO: try to estimate a seq time based on other nearby seqs?
+            }],
+            dist_tag_latest_version: None,
+            created: None,
+            modified: None,
+            other_dist_tags: None,
+            other_time_data: None,
+            unpublished_data: None,
+        },
+    }
+}
+
+/// The row of `old_package` updated by `data`. `latest_id` looks up the id of the latest version.
+pub fn updated_package_row<F>(
+    old_package: &Package,
+    data: PackageOnlyPackument,
+    seq: i64,
+    diff_entry_id: i64,
+    latest_id: F,
+) -> NewPackage
+where
+    F: FnOnce(Semver) -> i64,
+{
+    match data {
+        PackageOnlyPackument::Normal {
+            latest,
+            created,
+            modified,
+            other_dist_tags,
+            extra_version_times,
+        } => {
+            NewPackage {
+                name: old_package.name.clone(),
+                current_package_state_type: PackageStateType::Normal,
+                package_state_history: snoc(
+                    old_package.package_state_history.clone(),
+                    PackageStateTimePoint {
+                        state: PackageStateType::Normal,
+                        seq,
+                        diff_entry_id,
+                        estimated_time: Some(modified), // TODO ???
+                    },
+                ),
+                dist_tag_latest_version: latest.map(latest_id),
+                created: Some(created),
+                modified: Some(modified),
+                other_dist_tags: Some(Value::Object(other_dist_tags)),
+                other_time_data: Some(
+                    serde_json::to_value(BTreeMapSerializedAsString::new(extra_version_times))
+                        .unwrap(),
+                ),
+                unpublished_data: None,
+            }
+        }
+        PackageOnlyPackument::Unpublished {
+            created,
+            modified,
+            unpublished_blob,
+            extra_version_times,
+        } => NewPackage {
+            name: old_package.name.clone(),
+            current_package_state_type: PackageStateType::Unpublished,
+            package_state_history: snoc(
+                old_package.package_state_history.clone(),
+                PackageStateTimePoint {
+                    state: PackageStateType::Unpublished,
+                    seq,
+                    diff_entry_id,
+                    estimated_time: Some(modified), // TODO ???
+                },
+            ),
+            dist_tag_latest_version: old_package.dist_tag_latest_version,
+            created: Some(created),
+            modified: Some(modified),
+            other_dist_tags: old_package.other_dist_tags.clone(),
+            other_time_data: Some(serde_json::to_value(extra_version_times).unwrap()),
+            unpublished_data: Some(unpublished_blob),
+        },
+        // Maybe we want to treat these separately?
+        PackageOnlyPackument::Deleted | PackageOnlyPackument::MissingData => NewPackage {
+            name: old_package.name.clone(),
+            current_package_state_type: PackageStateType::Deleted,
+            package_state_history: snoc(
+                old_package.package_state_history.clone(),
+                PackageStateTimePoint {
+                    state: PackageStateType::Deleted,
+                    seq,
+                    diff_entry_id,
+                    estimated_time: None, // TODO ???
+                },
+            ),
+            dist_tag_latest_version: old_package.dist_tag_latest_version,
+            created: old_package.created,
+            modified: old_package.modified,
+            other_dist_tags: old_package.other_dist_tags.clone(),
+            other_time_data: old_package.other_time_data.clone(),
+            unpublished_data: old_package.unpublished_data.clone(),
+        },
+    }
+}
+
+fn snoc<T>(mut vec: Vec<T>, item: T) -> Vec<T> {
+    vec.push(item);
+    vec
+}
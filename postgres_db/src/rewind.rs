@@ -0,0 +1,206 @@
+//! Rewinding the relational tables to an earlier seq, e.g. after fixing a bug in the
+//! `EntryProcessor` of relational_db_builder, so that only the diff entries after it have to be
+//! processed again.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{
+    connection::QueryRunner,
+    custom_types::{Semver, VersionStateType},
+    dependencies::{self, DependencyType},
+    diff_log, internal_state,
+    packages::{self, NewPackage},
+    replay::{replay_package, ReplayedVersion},
+    versions::{self, Version},
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RewindSummary {
+    /// Packages that existed at the seq, and were changed after it.
+    pub num_packages_rewound: usize,
+    /// Packages that were created after the seq.
+    pub num_packages_deleted: usize,
+    /// Versions that were created after the seq, including those of deleted packages.
+    pub num_versions_deleted: usize,
+}
+
+/// Rewinds `packages`, `versions` and `dependencies` to how they were right after processing the
+/// diff entries up to and including seq `to_seq`, and sets the relational processed seq to it.
+/// The rewind is recorded in `internal_state::query_relational_rewinds`, so that delta exports
+/// of the tables from before it can be refused.
+///
+/// Rather than rebuilding the tables, this inverts the diff entries after `to_seq` one package at
+/// a time, so rows that existed at `to_seq` keep their ids:
+/// - Packages and versions created after `to_seq` are deleted, and dependencies on the deleted
+///   packages don't refer to them by id anymore.
+/// - The state histories of the other packages and versions are cut off at `to_seq`. The rest of
+///   a package is rebuilt from its diff entries up to `to_seq`, like `EntryProcessor` built it,
+///   and the `extra_metadata` of a version is taken from its last packument up to `to_seq`.
+/// - The freq counts of dependencies are decremented for versions that were deleted or created
+///   after `to_seq`, and incremented for versions that were un-deleted after it.
+///
+/// Dependency rows that were first inserted after `to_seq` are left with zero counts, and are
+/// reused once the diff entries are processed again.
+pub fn rewind_relational_tables<R: QueryRunner>(conn: &mut R, to_seq: i64) -> RewindSummary {
+    let processed_seq = internal_state::query_relational_processed_seq(conn).unwrap_or(0);
+    assert!(
+        to_seq <= processed_seq,
+        "Can't rewind the relational tables to seq {}, they are only at seq {}",
+        to_seq,
+        processed_seq
+    );
+
+    tracing::info!(to_seq, processed_seq, "Rewinding the relational tables");
+    let mut summary = RewindSummary::default();
+    // This includes packages whose entries after `to_seq` haven't been processed yet, which are
+    // no-ops below if none of them were.
+    for package_name in diff_log::query_packages_with_diff_entries_after_seq(to_seq, conn) {
+        rewind_package(conn, &package_name, to_seq, &mut summary);
+    }
+
+    if to_seq < processed_seq {
+        internal_state::add_relational_rewind(to_seq, conn);
+    }
+    internal_state::set_relational_processed_seq(to_seq, conn);
+    tracing::info!(
+        num_packages_rewound = summary.num_packages_rewound,
+        num_packages_deleted = summary.num_packages_deleted,
+        num_versions_deleted = summary.num_versions_deleted,
+        "Rewound the relational tables"
+    );
+    summary
+}
+
+fn rewind_package<R: QueryRunner>(
+    conn: &mut R,
+    package_name: &str,
+    to_seq: i64,
+    summary: &mut RewindSummary,
+) {
+    let _span = tracing::debug_span!("rewind_package", package = package_name).entered();
+    let package = match packages::maybe_get_package_id_by_name(conn, package_name) {
+        Some(package_id) => packages::get_package(conn, package_id),
+        None => return,
+    };
+    let package_versions = versions::get_versions_by_package_id(conn, package.id);
+
+    let entries = diff_log::query_diff_entries_for_package(package_name, Some(to_seq), conn);
+    let replayed = replay_package(&package, entries, |latest| {
+        versions::get_version_id_by_semver(conn, package.id, latest)
+    });
+
+    match replayed.package {
+        Some(package_at_seq) => {
+            // The package is rewound before its versions are deleted, since its latest version
+            // might be one of them.
+            let diff = package.diff(NewPackage::from(package_at_seq));
+            packages::update_package(conn, package.id, diff);
+
+            let mut to_delete = vec![];
+            for version in package_versions {
+                rewind_version(conn, version, to_seq, &replayed.versions, &mut to_delete);
+            }
+            tracing::debug!(
+                num_versions_deleted = to_delete.len(),
+                "Rewound the package"
+            );
+            summary.num_versions_deleted += to_delete.len();
+            versions::delete_version_rows(conn, &to_delete);
+            summary.num_packages_rewound += 1;
+        }
+        None => {
+            let mut no_latest = NewPackage::from(package.clone());
+            no_latest.dist_tag_latest_version = None;
+            packages::update_package(conn, package.id, package.diff(no_latest));
+
+            let version_ids: Vec<i64> = package_versions.iter().map(|v| v.id).collect();
+            for version in &package_versions {
+                if version.current_version_state_type == VersionStateType::Normal {
+                    count_dependencies(conn, version, false);
+                }
+            }
+            versions::delete_version_rows(conn, &version_ids);
+            dependencies::clear_deps_dst_pack(conn, package.id);
+            packages::delete_package(conn, package.id);
+
+            tracing::debug!(
+                num_versions_deleted = version_ids.len(),
+                "Deleted the package, it was created after the seq"
+            );
+            summary.num_versions_deleted += version_ids.len();
+            summary.num_packages_deleted += 1;
+        }
+    }
+}
+
+/// Rewinds a version of a package that existed at `to_seq`, or adds it to `to_delete` if it was
+/// created after `to_seq`. `replayed` has the versions as of `to_seq`.
+fn rewind_version<R: QueryRunner>(
+    conn: &mut R,
+    mut version: Version,
+    to_seq: i64,
+    replayed: &BTreeMap<Semver, ReplayedVersion>,
+    to_delete: &mut Vec<i64>,
+) {
+    let (history, undone): (Vec<_>, Vec<_>) = std::mem::take(&mut version.version_state_history)
+        .into_iter()
+        .partition(|point| point.seq <= to_seq);
+
+    // The dependencies of a version are counted while it is normal.
+    let counted_now = version.current_version_state_type == VersionStateType::Normal;
+    let counted_then =
+        matches!(history.last(), Some(point) if point.state == VersionStateType::Normal);
+    match (counted_now, counted_then) {
+        (true, false) => count_dependencies(conn, &version, false),
+        (false, true) => count_dependencies(conn, &version, true),
+        _ => {}
+    }
+
+    let state_then = match history.last() {
+        Some(point) => point.state.clone(),
+        None => {
+            to_delete.push(version.id);
+            return;
+        }
+    };
+    if !undone.is_empty() {
+        versions::set_version_state(conn, version.id, state_then, history);
+    }
+
+    let data = &replayed
+        .get(&version.semver)
+        .unwrap_or_else(|| {
+            panic!(
+                "Version {} of package id {} has no packument up to the seq",
+                version.semver, version.package_id
+            )
+        })
+        .last;
+    let extra_metadata_then = Value::Object(data.extra_metadata.clone().into_iter().collect());
+    if version.extra_metadata != extra_metadata_then {
+        versions::set_version_extra_metadata(conn, version.id, extra_metadata_then);
+    }
+}
+
+/// Counts the dependencies of a version once more, or once less, like `EntryProcessor` does when
+/// it un-deletes and deletes versions.
+fn count_dependencies<R: QueryRunner>(conn: &mut R, version: &Version, add: bool) {
+    let dep_lists = [
+        (&version.prod_dependencies, DependencyType::Prod),
+        (&version.dev_dependencies, DependencyType::Dev),
+        (&version.peer_dependencies, DependencyType::Peer),
+        (&version.optional_dependencies, DependencyType::Optional),
+    ];
+    for (dep_ids, dep_type) in dep_lists {
+        for dep in dependencies::get_dependencies_by_ids(conn, dep_ids) {
+            let change = if add {
+                dep.mark_as_add(dep_type)
+            } else {
+                dep.mark_as_delete(dep_type)
+            };
+            dependencies::insert_dependency_inc_counts(conn, change.as_new());
+        }
+    }
+}
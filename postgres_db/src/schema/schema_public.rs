@@ -1,6 +1,10 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "constraint_conjuncts_struct"))]
+    pub struct ConstraintConjunctsStruct;
+
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "diff_type"))]
     pub struct DiffType;
@@ -93,6 +97,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    diff_log_quarantine (seq) {
+        seq -> Int8,
+        package_name -> Nullable<Text>,
+        panic_message -> Text,
+        json_path -> Nullable<Text>,
+        quarantined_time -> Timestamptz,
+        num_retries -> Int4,
+    }
+}
+
+diesel::table! {
+    download_count_gaps (package_id, start_date) {
+        package_id -> Int8,
+        start_date -> Date,
+        end_date -> Date,
+    }
+}
+
+diesel::table! {
+    download_counts_daily (package_id, day) {
+        package_id -> Int8,
+        day -> Date,
+        count -> Int8,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::DownloadCountStruct;
@@ -135,7 +166,7 @@ diesel::table! {
         npm_signature -> Nullable<Text>,
         tgz_local_path -> Nullable<Text>,
         blob_storage_key -> Nullable<Text>,
-        num_bytes -> Nullable<Int8>,
+        num_bytes -> Int8,
     }
 }
 
@@ -151,6 +182,15 @@ diesel::table! {
         refs -> Array<Text>,
         cvss_score -> Nullable<Float4>,
         cvss_vector -> Nullable<Text>,
+        source -> Text,
+    }
+}
+
+diesel::table! {
+    ghsa_aliases (ghsa_id, alias, source) {
+        ghsa_id -> Text,
+        alias -> Text,
+        source -> Text,
     }
 }
 
@@ -161,6 +201,27 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    ghsa_history (id) {
+        id -> Int8,
+        ghsa_id -> Text,
+        observed_at -> Timestamptz,
+        severity -> Text,
+        description -> Text,
+        summary -> Text,
+        withdrawn_at -> Nullable<Timestamptz>,
+        published_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        refs -> Array<Text>,
+        cvss_score -> Nullable<Float4>,
+        cvss_vector -> Nullable<Text>,
+        cwe_ids -> Array<Text>,
+        vulnerabilities -> Jsonb,
+        revision_hash -> Text,
+        source -> Text,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::InternalDiffLogVersionState;
@@ -199,6 +260,25 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    version_exposures (id) {
+        id -> Int8,
+        version_id -> Int8,
+        ghsa_id -> Text,
+        exposed_from -> Timestamptz,
+        exposed_until -> Nullable<Timestamptz>,
+        depth -> Int4,
+        path -> Array<Int8>,
+    }
+}
+
+diesel::table! {
+    version_vulnerabilities (version_id, ghsa_id) {
+        version_id -> Int8,
+        ghsa_id -> Text,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::SemverStruct;
@@ -227,6 +307,7 @@ diesel::table! {
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::SemverStruct;
+    use crate::custom_types::sql_types::ConstraintConjunctsSql;
 
     vulnerabilities (id) {
         id -> Int8,
@@ -237,11 +318,13 @@ diesel::table! {
         vulnerable_version_upper_bound -> Nullable<SemverStruct>,
         vulnerable_version_upper_bound_inclusive -> Bool,
         first_patched_version -> Nullable<SemverStruct>,
+        vulnerable_version_constraint -> Array<ConstraintConjunctsSql>,
     }
 }
 
 diesel::joinable!(dependencies -> packages (dst_package_id_if_exists));
 diesel::joinable!(diff_log -> change_log (seq));
+diesel::joinable!(ghsa_aliases -> ghsa (ghsa_id));
 diesel::joinable!(vulnerabilities -> ghsa (ghsa_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
@@ -249,14 +332,21 @@ diesel::allow_tables_to_appear_in_same_query!(
     cwes,
     dependencies,
     diff_log,
+    diff_log_quarantine,
+    download_count_gaps,
+    download_counts_daily,
     download_metrics,
     download_tasks,
     downloaded_tarballs,
     ghsa,
+    ghsa_aliases,
     ghsa_cwe_relation,
+    ghsa_history,
     internal_diff_log_state,
     internal_state,
     packages,
+    version_exposures,
+    version_vulnerabilities,
     versions,
     vulnerabilities,
 );
@@ -1,8 +1,13 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeZone, Utc};
 use diesel::sql_query;
+use serde_json::Map;
 
 use crate::connection::DbConnection;
 use crate::connection::QueryRunner;
 use crate::custom_types::{PrereleaseTag, Semver};
+use crate::packument::{Dist, PackageOnlyPackument, VersionOnlyPackument};
 
 pub use crate::connection::testing::using_test_db;
 
@@ -48,3 +53,51 @@ pub fn using_temp_table<F>(
     let drop_query = sql_query(format!("DROP TABLE {}", table_name));
     conn.execute(drop_query).unwrap();
 }
+
+/// The time of the packuments below, 2020-01-01.
+pub fn testing_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// The packument of a normal package created and modified at `testing_time()`.
+pub fn package_data(latest: Option<Semver>) -> PackageOnlyPackument {
+    package_data_at(latest, testing_time(), testing_time())
+}
+
+pub fn package_data_at(
+    latest: Option<Semver>,
+    created: DateTime<Utc>,
+    modified: DateTime<Utc>,
+) -> PackageOnlyPackument {
+    PackageOnlyPackument::Normal {
+        latest,
+        created,
+        modified,
+        other_dist_tags: Map::new(),
+        extra_version_times: BTreeMap::new(),
+    }
+}
+
+/// The packument of a version without dependencies, published at `testing_time()`. Tests set the
+/// fields they care about with struct update syntax.
+pub fn version_data() -> VersionOnlyPackument {
+    VersionOnlyPackument {
+        prod_dependencies: vec![],
+        dev_dependencies: vec![],
+        peer_dependencies: vec![],
+        optional_dependencies: vec![],
+        dist: Dist {
+            tarball_url: "stuff".into(),
+            shasum: None,
+            unpacked_size: None,
+            file_count: None,
+            integrity: None,
+            signature0_sig: None,
+            signature0_keyid: None,
+            npm_signature: None,
+        },
+        repository: None,
+        time: testing_time(),
+        extra_metadata: BTreeMap::new(),
+    }
+}
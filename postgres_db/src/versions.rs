@@ -54,6 +54,29 @@ pub struct NewVersion {
     pub optional_dependencies: Vec<i64>, // this is a list of version ids
 }
 
+/// The columns of a version that say what it depends on, and when it existed.
+#[derive(Queryable, Debug)]
+pub struct VersionDependencies {
+    pub id: i64,
+    pub package_id: i64,
+    pub created: DateTime<Utc>,
+    pub version_state_history: Vec<VersionStateTimePoint>,
+    pub prod_dependencies: Vec<i64>,
+    pub dev_dependencies: Vec<i64>,
+    pub peer_dependencies: Vec<i64>,
+    pub optional_dependencies: Vec<i64>,
+}
+
+/// The columns of a version that say when it existed.
+#[derive(Queryable, Debug, Clone)]
+pub struct VersionRelease {
+    pub id: i64,
+    pub package_id: i64,
+    pub semver: Semver,
+    pub created: DateTime<Utc>,
+    pub version_state_history: Vec<VersionStateTimePoint>,
+}
+
 pub fn get_version_by_id<R: QueryRunner>(conn: &mut R, version_id: i64) -> Version {
     let query = versions::table.filter(versions::id.eq(version_id));
     conn.get_result(query).expect("Error getting package")
@@ -64,6 +87,15 @@ pub fn get_versions_by_package_id<R: QueryRunner>(conn: &mut R, package_id: i64)
     conn.get_results(query).expect("Error getting package")
 }
 
+pub fn get_versions_by_package_ids<R: QueryRunner>(
+    conn: &mut R,
+    package_ids: &[i64],
+) -> Vec<Version> {
+    let query = versions::table.filter(versions::package_id.eq_any(package_ids));
+    conn.load(query)
+        .expect("Error getting versions by package ids")
+}
+
 // TODO[perf]: memoize this?
 pub fn get_version_id_by_semver<R: QueryRunner>(conn: &mut R, package_id: i64, v: Semver) -> i64 {
     let query = versions::table
@@ -120,6 +152,107 @@ pub fn get_version_times<R: QueryRunner>(
 //     }
 // }
 
+/// Returns up to `limit` versions with an id greater than `after_id`, ordered by id.
+pub fn query_versions_after_id<R>(conn: &mut R, after_id: i64, limit: i64) -> Vec<Version>
+where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    let query = versions.filter(id.gt(after_id)).order(id).limit(limit);
+    conn.load(query).expect("Error querying versions")
+}
+
+/// Returns up to `limit` versions with an id greater than `after_id`, ordered by id.
+pub fn query_version_dependencies_after_id<R>(
+    conn: &mut R,
+    after_id: i64,
+    limit: i64,
+) -> Vec<VersionDependencies>
+where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    let query = versions
+        .select((
+            id,
+            package_id,
+            created,
+            version_state_history,
+            prod_dependencies,
+            dev_dependencies,
+            peer_dependencies,
+            optional_dependencies,
+        ))
+        .filter(id.gt(after_id))
+        .order(id)
+        .limit(limit);
+    conn.load(query)
+        .expect("Error querying version dependencies")
+}
+
+pub fn get_version_dependencies_by_package_ids<R>(
+    conn: &mut R,
+    package_ids: &[i64],
+) -> Vec<VersionDependencies>
+where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    let query = versions
+        .select((
+            id,
+            package_id,
+            created,
+            version_state_history,
+            prod_dependencies,
+            dev_dependencies,
+            peer_dependencies,
+            optional_dependencies,
+        ))
+        .filter(package_id.eq_any(package_ids));
+    conn.load(query)
+        .expect("Error getting version dependencies")
+}
+
+pub fn get_version_releases_by_package_ids<R>(
+    conn: &mut R,
+    package_ids: &[i64],
+) -> Vec<VersionRelease>
+where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    let query = versions
+        .select((id, package_id, semver, created, version_state_history))
+        .filter(package_id.eq_any(package_ids));
+    conn.load(query).expect("Error getting version releases")
+}
+
+/// Gets the ids of the packages that have a version with any kind of dependency in `dep_ids`.
+pub fn get_package_ids_with_any_dependency<R>(conn: &mut R, dep_ids: &[i64]) -> Vec<i64>
+where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    let query = versions
+        .select(package_id)
+        .filter(
+            prod_dependencies
+                .overlaps_with(dep_ids)
+                .or(dev_dependencies.overlaps_with(dep_ids))
+                .or(peer_dependencies.overlaps_with(dep_ids))
+                .or(optional_dependencies.overlaps_with(dep_ids)),
+        )
+        .distinct();
+    conn.load(query)
+        .expect("Error getting packages with dependencies")
+}
+
 pub fn insert_new_version<R>(conn: &mut R, new_version: NewVersion) -> i64
 where
     R: QueryRunner,
@@ -209,6 +342,40 @@ pub fn undelete_to_normal_version<R>(
         .expect("Error undeleting version");
 }
 
+/// Overwrites the state and state history of a version, without touching its dependency counts.
+pub fn set_version_state<R>(
+    conn: &mut R,
+    version_id: i64,
+    state: VersionStateType,
+    history: Vec<VersionStateTimePoint>,
+) where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    let update_query = diesel::update(versions.find(version_id)).set((
+        current_version_state_type.eq(state),
+        version_state_history.eq(history),
+    ));
+
+    assert_eq!(
+        conn.execute(update_query)
+            .expect("Error setting version state"),
+        1
+    );
+}
+
+/// Deletes version rows, without touching their dependency counts.
+pub fn delete_version_rows<R>(conn: &mut R, version_ids: &[i64])
+where
+    R: QueryRunner,
+{
+    use super::schema::versions::dsl::*;
+
+    conn.execute(diesel::delete(versions.filter(id.eq_any(version_ids))))
+        .expect("Error deleting version rows");
+}
+
 // pub fn delete_versions_not_in(conn: &mut DbConnection, pkg_id: i64, vers: Vec<&Semver>) {
 //     use super::schema::versions::dsl::*;
 
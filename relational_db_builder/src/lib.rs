@@ -1,16 +1,19 @@
 mod relational_db_accessor;
+#[cfg(test)]
+mod rewind;
+#[cfg(test)]
+mod testing;
+pub mod verify;
 
 use std::collections::HashSet;
 
 use postgres_db::{
     connection::QueryRunner,
-    custom_types::{
-        PackageStateTimePoint, PackageStateType, Semver, VersionStateTimePoint, VersionStateType,
-    },
+    custom_types::{Semver, VersionStateTimePoint, VersionStateType},
     dependencies::{Dependency, DependencyType, NewDependency},
     diff_log::DiffLogInstruction,
-    packages::NewPackage,
     packument::{PackageOnlyPackument, Spec, VersionOnlyPackument},
+    replay::{created_package_row, updated_package_row},
     versions::NewVersion,
 };
 use relational_db_accessor::RelationalDbAccessor;
@@ -45,6 +48,8 @@ impl EntryProcessor {
     ) where
         R: QueryRunner,
     {
+        let _span =
+            tracing::info_span!("diff_entry", seq, diff_entry_id, package = %package).entered();
         match instr {
             DiffLogInstruction::CreatePackage(data) => {
                 self.create_package(conn, package, data, seq, diff_entry_id)
@@ -74,6 +79,11 @@ impl EntryProcessor {
         self.db.flush_caches(conn);
     }
 
+    /// Forgets the cached rows, after the transaction that wrote them was rolled back.
+    pub fn clear_caches(&mut self) {
+        self.db.clear_caches();
+    }
+
     fn create_package<R>(
         &mut self,
         conn: &mut R,
@@ -84,82 +94,7 @@ impl EntryProcessor {
     ) where
         R: QueryRunner,
     {
-        let new_package = match data {
-            PackageOnlyPackument::Normal {
-                latest,
-                created,
-                modified,
-                other_dist_tags,
-                extra_version_times,
-            } => {
-                assert_eq!(latest, None);
-                NewPackage {
-                    name: package,
-                    current_package_state_type: PackageStateType::Normal,
-                    package_state_history: vec![PackageStateTimePoint {
-                        state: PackageStateType::Normal,
-                        seq,
-                        diff_entry_id,
-                        estimated_time: Some(created),
-                    }],
-                    dist_tag_latest_version: None,
-                    created: Some(created),
-                    modified: Some(modified),
-                    other_dist_tags: Some(Value::Object(other_dist_tags)),
-                    other_time_data: Some(
-                        serde_json::to_value(
-                            postgres_db::serde_non_string_key_serialization::BTreeMapSerializedAsString::new(extra_version_times),
-                        )
-                        .unwrap()
-                    ),
-                    unpublished_data: None,
-                }
-            }
-            PackageOnlyPackument::Unpublished {
-                created,
-                modified,
-                unpublished_blob,
-                extra_version_times,
-            } => NewPackage {
-                name: package,
-                current_package_state_type: PackageStateType::Unpublished,
-                package_state_history: vec![PackageStateTimePoint {
-                    state: PackageStateType::Unpublished,
-                    seq,
-                    diff_entry_id,
-                    estimated_time: Some(created),
-                }],
-                dist_tag_latest_version: None,
-                created: Some(created),
-                modified: Some(modified),
-                other_dist_tags: None,
-                other_time_data: Some(
-                    serde_json::to_value(
-                        postgres_db::serde_non_string_key_serialization::BTreeMapSerializedAsString::new(extra_version_times),
-                    )
-                    .unwrap(),
-                ),
-                unpublished_data: Some(unpublished_blob),
-            },
-            // Maybe we want to treat these separately?
-            PackageOnlyPackument::Deleted | PackageOnlyPackument::MissingData => NewPackage {
-                name: package,
-                current_package_state_type: PackageStateType::Deleted,
-                package_state_history: vec![PackageStateTimePoint {
-                    state: PackageStateType::Deleted,
-                    seq,
-                    diff_entry_id,
-                    estimated_time: None, // TODO: try to estimate a seq time based on other nearby seqs?
-                }],
-                dist_tag_latest_version: None,
-                created: None,
-                modified: None,
-                other_dist_tags: None,
-                other_time_data: None,
-                unpublished_data: None,
-            },
-        };
-
+        let new_package = created_package_row(package, data, seq, diff_entry_id);
         self.db.insert_new_package(conn, new_package);
     }
 
@@ -177,91 +112,13 @@ impl EntryProcessor {
         // `old_package` before calling `update_package`.
         let (diff, package_id) = {
             let old_package = self.db.get_package_by_name(conn, &package_name);
-            let old_history = old_package.package_state_history.clone();
             let package_id = old_package.id;
 
-            let new_package = match data {
-                PackageOnlyPackument::Normal {
-                    latest,
-                    created,
-                    modified,
-                    other_dist_tags,
-                    extra_version_times,
-                } => {
-                    let latest_id = latest.map(|latest_semver| {
-                        self.db
-                            .get_version_id_by_semver(conn, package_id, latest_semver)
-                    });
-                    NewPackage {
-                        name: package_name.clone(),
-                        current_package_state_type: PackageStateType::Normal,
-                        package_state_history: snoc(
-                            old_history,
-                            PackageStateTimePoint {
-                                state: PackageStateType::Normal,
-                                seq,
-                                diff_entry_id,
-                                estimated_time: Some(modified), // TODO ???
-                            },
-                        ),
-                        dist_tag_latest_version: latest_id,
-                        created: Some(created),
-                        modified: Some(modified),
-                        other_dist_tags: Some(Value::Object(other_dist_tags)),
-                        other_time_data: Some(
-                            serde_json::to_value(
-                                postgres_db::serde_non_string_key_serialization::BTreeMapSerializedAsString::new(extra_version_times),
-                            )
-                            .unwrap()
-                        ),
-                        unpublished_data: None,
-                    }
-                }
-                PackageOnlyPackument::Unpublished {
-                    created,
-                    modified,
-                    unpublished_blob,
-                    extra_version_times,
-                } => NewPackage {
-                    name: package_name.clone(),
-                    current_package_state_type: PackageStateType::Unpublished,
-                    package_state_history: snoc(
-                        old_history,
-                        PackageStateTimePoint {
-                            state: PackageStateType::Unpublished,
-                            seq,
-                            diff_entry_id,
-                            estimated_time: Some(modified), // TODO ???
-                        },
-                    ),
-                    dist_tag_latest_version: old_package.dist_tag_latest_version,
-                    created: Some(created),
-                    modified: Some(modified),
-                    other_dist_tags: old_package.other_dist_tags.clone(),
-                    other_time_data: Some(serde_json::to_value(extra_version_times).unwrap()),
-                    unpublished_data: Some(unpublished_blob),
-                },
-                // Maybe we want to treat these separately?
-                PackageOnlyPackument::Deleted | PackageOnlyPackument::MissingData => NewPackage {
-                    name: package_name.clone(),
-                    current_package_state_type: PackageStateType::Deleted,
-                    package_state_history: snoc(
-                        old_history,
-                        PackageStateTimePoint {
-                            state: PackageStateType::Deleted,
-                            seq,
-                            diff_entry_id,
-                            estimated_time: None, // TODO ???
-                        },
-                    ),
-                    dist_tag_latest_version: old_package.dist_tag_latest_version,
-                    created: old_package.created,
-                    modified: old_package.modified,
-                    other_dist_tags: old_package.other_dist_tags.clone(),
-                    other_time_data: old_package.other_time_data.clone(),
-                    unpublished_data: old_package.unpublished_data.clone(),
-                },
-            };
+            let new_package =
+                updated_package_row(&old_package, data, seq, diff_entry_id, |latest_semver| {
+                    self.db
+                        .get_version_id_by_semver(conn, package_id, latest_semver)
+                });
 
             (old_package.diff(new_package), package_id)
         };
@@ -523,9 +380,12 @@ impl EntryProcessor {
 
         // If time changed, we ignore that, because thats B.S., and print a warning
         if current_data.created != new_pack_data.time {
-            println!(
-                "Warning: time changed for {}@{} in diff entry id {}, seq id {}",
-                package_name, version, diff_entry_id, seq
+            tracing::warn!(
+                package = %package_name,
+                version = %version,
+                diff_entry_id,
+                seq,
+                "Time changed"
             );
         }
 
@@ -631,11 +491,6 @@ impl EntryProcessor {
     }
 }
 
-fn snoc<T>(mut vec: Vec<T>, item: T) -> Vec<T> {
-    vec.push(item);
-    vec
-}
-
 fn assert_unique<T, X>(xs: T)
 where
     T: Iterator<Item = X>,
@@ -1,199 +1,292 @@
-use kdam::{tqdm, BarExt};
+use kdam::{tqdm, Bar, BarExt};
 use std::any::Any;
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashSet;
 use std::panic::{self, AssertUnwindSafe};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use chrono::Utc;
+use dependency_graph::{DependencyGraph, GraphUpdate};
 use metrics_logging::{
-    MetricsLoggerTrait, RelationalDbBatchCompleteMetrics, RelationalDbEndSessionMetrics,
-    RelationalDbPanicMetrics, RelationalDbStartSessionMetrics,
+    MetricsLogger, MetricsLoggerTrait, RelationalDbBatchCompleteMetrics,
+    RelationalDbEndSessionMetrics, RelationalDbPanicMetrics, RelationalDbStartSessionMetrics,
 };
-use postgres_db::connection::{DbConnection, QueryRunner};
+use pipeline_stage::{
+    PageSize, PageStats, PipelineStage, Runner, SessionStart, SessionSummary, StageError,
+    DAEMON_POLL_INTERVAL,
+};
+use postgres_db::connection::{DbConnection, DbConnectionInTransaction, QueryRunner};
 use postgres_db::diff_log::DiffLogEntry;
 use postgres_db::diff_log::{self, DiffLogInstruction};
 use postgres_db::internal_state;
+use postgres_db::notifications::{Listener, DIFF_LOG_CHANNEL, RELATIONAL_CHANNEL};
 
 use relational_db_builder::EntryProcessor;
-use utils::check_no_concurrent_processes;
+use utils::{check_no_concurrent_processes, panic_as_string};
 
 const TARGET_PAGE_SIZE_NUM_ENTRIES: i64 = 8000;
 
 fn main() {
+    let _tracing = utils::init_tracing("relational_db_builder");
     check_no_concurrent_processes("relational_db_builder");
 
+    let mut args: Vec<String> = std::env::args().collect();
+    let dry_run = pipeline_stage::take_flag(&mut args, "--dry-run");
+    let daemon = pipeline_stage::take_flag(&mut args, "--daemon");
+    if args.len() != 1 {
+        eprintln!("Usage: {} [--dry-run | --daemon]", args[0]);
+        std::process::exit(1);
+    }
+
     let mut conn = DbConnection::connect();
-    let mut metrics_logger = metrics_logging::new_metrics_logger(false);
-
-    let mut processed_up_to_seq =
-        internal_state::query_relational_processed_seq(&mut conn).unwrap_or(0);
-
-    println!("Initial queries:");
-    println!("query_num_changes_after_seq");
-    let num_changes_total = postgres_db::diff_log::query_num_changes_after_seq_in_diff_log(
-        processed_up_to_seq,
-        &mut conn,
-    );
-    println!("query_num_diff_entries_after_seq");
-    let num_entries_total =
-        diff_log::query_num_diff_entries_after_seq(processed_up_to_seq, &mut conn);
-    println!("Initial queries DONE!");
-
-    let mut num_changes_so_far = 0;
-    let mut num_entries_so_far = 0;
-
-    let session_start_time = Utc::now();
-    let session_start_seq_exclusive = processed_up_to_seq;
-
-    metrics_logger.log_relational_db_builder_start_session(RelationalDbStartSessionMetrics {
-        session_start_time,
-        session_start_seq_exclusive,
-        session_num_seqs: num_changes_total,
-        session_num_diff_entries: num_entries_total,
-    });
-
-    let mut entry_processor = EntryProcessor::new();
-
-    let mut batches_pb = tqdm!(
-        total = num_entries_total.try_into().unwrap(),
-        desc = "All entries",
-        position = 0
-    );
-
-    // Initially we start the page size at 256
-    let mut current_page_size_seqs = 256;
-    let history_size = 4;
-    let mut batch_size_history: VecDeque<(i64, i64)> = VecDeque::new();
-
-    let mut num_loops = 0;
-
-    // TODO: Extract this into function (duplicated in download_queuer/src/main.rs)
-    loop {
-        let batch_start = Instant::now();
-        let batch_start_time = Utc::now();
-
-        if num_loops <= 3 {
-            println!(
-                "Starting read from diff_log, page size = {}",
-                current_page_size_seqs
-            );
-        }
+    let metrics_logger = MetricsLogger::from_env_or_exit();
+
+    let mut stage = RelationalDbStage {
+        entry_processor: EntryProcessor::new(),
+        dependency_graph_path: std::env::var("DEPENDENCY_GRAPH_PATH").ok(),
+        dependency_graph: None,
+        graph_has_uncommitted_page: false,
+        num_changes_total: 0,
+        num_entries_total: 0,
+        batches_pb: None,
+    };
+
+    // Pages are sized by seq, and start at 256 seqs
+    let page_size = PageSize::Adaptive {
+        initial: 256,
+        target_items: TARGET_PAGE_SIZE_NUM_ENTRIES,
+        min: 16,
+        max: 16384,
+        history: 4,
+    };
+    let mut runner = Runner::new(page_size, metrics_logger)
+        .dry_run(dry_run)
+        .stop_on_signals();
+    if daemon {
+        let mut listener = Listener::connect(&[DIFF_LOG_CHANNEL]);
+        runner.run_daemon(&mut stage, &mut conn, &mut listener, DAEMON_POLL_INTERVAL);
+    } else {
+        runner.run(&mut stage, &mut conn);
+    }
+}
 
-        let entries = diff_log::query_diff_entries_after_seq(
-            processed_up_to_seq,
-            current_page_size_seqs,
-            &mut conn,
-        );
+struct RelationalDbStage {
+    entry_processor: EntryProcessor,
+    dependency_graph_path: Option<String>,
+    dependency_graph: Option<DependencyGraph>,
+    /// Whether a page was applied to the graph since the last commit.
+    graph_has_uncommitted_page: bool,
+    num_changes_total: i64,
+    num_entries_total: i64,
+    batches_pb: Option<Bar>,
+}
 
-        if num_loops <= 3 {
-            println!(
-                "Completed read from diff_log, page size = {}",
-                current_page_size_seqs
-            );
-        }
+impl PipelineStage for RelationalDbStage {
+    type Item = DiffLogEntry;
+    type Cursor = i64;
+    type Output = ProcessEntrySuccessMetrics;
+
+    fn name(&self) -> &'static str {
+        "relational_db_builder"
+    }
+
+    fn load_cursor(&mut self, conn: &mut DbConnection) -> i64 {
+        internal_state::query_relational_processed_seq(conn).unwrap_or(0)
+    }
+
+    fn save_cursor(&mut self, conn: &mut DbConnectionInTransaction, cursor: &i64) {
+        internal_state::set_relational_processed_seq(*cursor, conn);
+    }
+
+    fn query_page(
+        &mut self,
+        conn: &mut DbConnection,
+        cursor: &i64,
+        page_size: i64,
+    ) -> Vec<DiffLogEntry> {
+        diff_log::query_diff_entries_after_seq(*cursor, page_size, conn)
+    }
+
+    fn advance(&self, _cursor: &i64, page: &[DiffLogEntry]) -> i64 {
+        page.last().unwrap().seq
+    }
+
+    fn notify_channel(&self) -> Option<&'static str> {
+        Some(RELATIONAL_CHANNEL)
+    }
+
+    fn page_units(&self, page: &[DiffLogEntry]) -> i64 {
+        let unique_seqs: HashSet<_> = page.iter().map(|entry| entry.seq).collect();
+        unique_seqs.len() as i64
+    }
 
-        let unique_seqs: HashSet<_> = entries.iter().map(|entry| entry.seq).collect();
-        let num_changes = unique_seqs.len() as i64;
+    fn process_page(
+        &mut self,
+        conn: &mut DbConnectionInTransaction,
+        page: Vec<DiffLogEntry>,
+    ) -> Result<ProcessEntrySuccessMetrics, StageError> {
+        let mut graph_update = GraphUpdate::default();
+        if self.dependency_graph.is_some() {
+            for e in &page {
+                graph_update.note_entry(&e.package_name, &e.instr);
+            }
+        }
 
-        let read_duration = batch_start.elapsed();
+        let res =
+            process_entries(&mut self.entry_processor, conn, page).map_err(|err| StageError {
+                seq: Some(err.seq),
+                item_id: Some(err.entry_id),
+                message: err.message,
+                payload: err.err,
+            })?;
+        if let Some(graph) = &mut self.dependency_graph {
+            self.graph_has_uncommitted_page = true;
+            graph_update.apply(conn, graph);
+        }
+        Ok(res)
+    }
 
-        let num_entries = entries.len() as i64;
-        num_entries_so_far += num_entries;
-        if num_entries == 0 {
-            break;
+    fn page_rolled_back(&mut self, conn: &mut DbConnection) {
+        self.entry_processor.clear_caches();
+        if self.graph_has_uncommitted_page {
+            self.graph_has_uncommitted_page = false;
+            let processed_seq = internal_state::query_relational_processed_seq(conn).unwrap_or(0);
+            self.dependency_graph = self
+                .dependency_graph_path
+                .as_ref()
+                .map(|path| load_dependency_graph(conn, path, processed_seq));
         }
+    }
 
-        let first_seq_in_page = entries.first().unwrap().seq;
-        let last_seq_in_page = entries.last().unwrap().seq;
-
-        let process_entries_metrics = conn
-            .run_psql_transaction(|mut trans_conn| {
-                match process_entries(&mut entry_processor, &mut trans_conn, entries) {
-                    Ok(res) => {
-                        internal_state::set_relational_processed_seq(
-                            last_seq_in_page,
-                            &mut trans_conn,
-                        );
-                        Ok((res, true))
-                    }
-                    Err(err) => {
-                        metrics_logger.log_relational_db_builder_panic(RelationalDbPanicMetrics {
-                            panic_time: Utc::now(),
-                            panic_on_seq_id: err.seq,
-                            panic_on_diff_entry_id: err.entry_id,
-                            panic_message: err.message,
-                        });
-                        std::panic::resume_unwind(err.err);
-                    }
-                }
-            })
-            .unwrap();
-
-        num_changes_so_far += num_changes;
-
-        processed_up_to_seq = last_seq_in_page;
-
-        let batch_total_duration = batch_start.elapsed();
-
-        metrics_logger.log_relational_db_builder_batch_complete_metrics(
-            RelationalDbBatchCompleteMetrics {
-                batch_start_time,
-                batch_start_seq_inclusive: first_seq_in_page,
-                batch_end_seq_inclusive: last_seq_in_page,
-                batch_num_processed_seqs: num_changes,
-                batch_num_processed_diff_entries: num_entries,
-                batch_bytes_read: process_entries_metrics.read_bytes as i64,
-                batch_bytes_written: process_entries_metrics.write_bytes as i64,
-                batch_reading_duration: chrono::Duration::from_std(read_duration).unwrap(),
-                batch_writing_duration: chrono::Duration::from_std(
-                    process_entries_metrics.write_duration,
-                )
-                .unwrap(),
-                batch_total_duration: chrono::Duration::from_std(batch_total_duration).unwrap(),
-                session_num_seqs: num_changes_total,
-                session_num_diff_entries: num_entries_total,
-                session_num_seqs_processed_so_far: num_changes_so_far,
-                session_num_diff_entries_processed_so_far: num_entries_so_far,
-                session_start_time,
-            },
+    fn start_session(
+        &mut self,
+        conn: &mut DbConnection,
+        logger: &mut MetricsLogger,
+        session: &SessionStart<i64>,
+    ) {
+        tracing::info!("Running initial queries");
+        self.num_changes_total =
+            diff_log::query_num_changes_after_seq_in_diff_log(session.cursor, conn);
+        self.num_entries_total = diff_log::query_num_diff_entries_after_seq(session.cursor, conn);
+        tracing::info!(
+            num_changes = self.num_changes_total,
+            num_entries = self.num_entries_total,
+            "Initial queries done"
         );
 
-        batches_pb.update(num_entries.try_into().unwrap());
+        logger.log_relational_db_builder_start_session(RelationalDbStartSessionMetrics {
+            session_start_time: session.start_time,
+            session_start_seq_exclusive: session.cursor,
+            session_num_seqs: self.num_changes_total,
+            session_num_diff_entries: self.num_entries_total,
+        });
+
+        // as a daemon, the graph is kept up to date in memory between sessions
+        if self.dependency_graph.is_none() {
+            self.dependency_graph = self
+                .dependency_graph_path
+                .as_ref()
+                .map(|path| load_dependency_graph(conn, path, session.cursor));
+        }
+
+        self.batches_pb = Some(tqdm!(
+            total = self.num_entries_total.try_into().unwrap(),
+            desc = "All entries",
+            position = 0
+        ));
+    }
 
-        batch_size_history.push_front((num_entries, num_changes));
-        if batch_size_history.len() == history_size + 1 {
-            batch_size_history.pop_back();
+    fn page_completed(
+        &mut self,
+        logger: &mut MetricsLogger,
+        page: &PageStats<i64>,
+        output: &ProcessEntrySuccessMetrics,
+    ) {
+        self.graph_has_uncommitted_page = false;
+        logger.log_relational_db_builder_batch_complete_metrics(RelationalDbBatchCompleteMetrics {
+            batch_start_time: page.start_time,
+            batch_start_seq_inclusive: page.first,
+            batch_end_seq_inclusive: page.last,
+            batch_num_processed_seqs: page.num_units,
+            batch_num_processed_diff_entries: page.num_items,
+            batch_bytes_read: output.read_bytes as i64,
+            batch_bytes_written: output.write_bytes as i64,
+            batch_reading_duration: chrono::Duration::from_std(page.read_duration).unwrap(),
+            batch_writing_duration: chrono::Duration::from_std(output.write_duration).unwrap(),
+            batch_total_duration: chrono::Duration::from_std(page.total_duration).unwrap(),
+            session_num_seqs: self.num_changes_total,
+            session_num_diff_entries: self.num_entries_total,
+            session_num_seqs_processed_so_far: page.session_units_so_far,
+            session_num_diff_entries_processed_so_far: page.session_items_so_far,
+            session_start_time: page.session_start_time,
+        });
+
+        if let Some(pb) = &mut self.batches_pb {
+            pb.update(page.num_items.try_into().unwrap());
         }
+    }
 
-        if batch_size_history.len() == history_size {
-            let entries_sum = batch_size_history
-                .iter()
-                .fold(0, |acc, (n_entries, _n_changes)| acc + n_entries);
+    fn page_failed(&mut self, logger: &mut MetricsLogger, first: &i64, err: &StageError) {
+        logger.log_relational_db_builder_panic(RelationalDbPanicMetrics {
+            panic_time: Utc::now(),
+            panic_on_seq_id: err.seq.unwrap_or(*first),
+            // the panic wasn't in an entry, e.g. it was while flushing the caches
+            panic_on_diff_entry_id: err.item_id.unwrap_or(-1),
+            panic_message: err.message.clone(),
+        });
+    }
 
-            let changes_sum = batch_size_history
-                .iter()
-                .fold(0, |acc, (_n_entries, n_changes)| acc + n_changes);
+    fn end_session(
+        &mut self,
+        _conn: &mut DbConnection,
+        logger: &mut MetricsLogger,
+        session: &SessionSummary<i64>,
+    ) {
+        logger.log_relational_db_builder_end_session(RelationalDbEndSessionMetrics {
+            session_start_time: session.start_time,
+            session_start_seq_exclusive: session.start,
+            session_num_seqs: session.num_units,
+            session_num_diff_entries: session.num_items,
+            session_end_time: session.end_time,
+            session_end_seq_inclusive: session.end,
+            session_total_duration: session.end_time - session.start_time,
+        })
+    }
 
-            let ratio_est = (entries_sum as f64) / (changes_sum as f64);
-            let page_size_seq_est = ((TARGET_PAGE_SIZE_NUM_ENTRIES as f64) / ratio_est) as i64;
-            current_page_size_seqs = page_size_seq_est.max(16).min(16384);
+    fn shut_down(&mut self, _conn: &mut DbConnection, last_session: &SessionSummary<i64>) {
+        if last_session.dry_run {
+            tracing::info!("Dry run, not saving the dependency graph");
+        } else if let (Some(path), Some(graph)) =
+            (&self.dependency_graph_path, &mut self.dependency_graph)
+        {
+            tracing::info!(path = %path, "Saving dependency graph");
+            graph
+                .save(path, last_session.end)
+                .expect("Failed to save the dependency graph");
         }
-
-        num_loops += 1;
     }
+}
 
-    let session_end_time = Utc::now();
-    let session_total_duration = session_end_time - session_start_time;
-
-    metrics_logger.log_relational_db_builder_end_session(RelationalDbEndSessionMetrics {
-        session_start_time,
-        session_start_seq_exclusive,
-        session_num_seqs: num_changes_so_far,
-        session_num_diff_entries: num_entries_so_far,
-        session_end_time,
-        session_end_seq_inclusive: processed_up_to_seq,
-        session_total_duration,
-    })
+/// Loads the saved dependency graph if it is up to date with the relational tables, and otherwise
+/// builds it from them.
+fn load_dependency_graph(
+    conn: &mut DbConnection,
+    path: &str,
+    processed_up_to_seq: i64,
+) -> DependencyGraph {
+    match DependencyGraph::load(path) {
+        Ok((graph, through_seq)) if through_seq == processed_up_to_seq => {
+            tracing::info!(through_seq, "Loaded dependency graph");
+            return graph;
+        }
+        Ok((_, through_seq)) => tracing::warn!(
+            through_seq,
+            processed_up_to_seq,
+            "Saved dependency graph is behind the relational tables"
+        ),
+        Err(err) => tracing::warn!(path = %path, error = %err, "Could not load dependency graph"),
+    }
+    tracing::info!("Building dependency graph");
+    DependencyGraph::build(conn)
 }
 
 pub fn process_entries<R>(
@@ -261,32 +354,3 @@ pub struct ProcessEntrySuccessMetrics {
     pub write_bytes: usize,
     pub write_duration: Duration,
 }
-
-fn panic_as_string(p: &Box<dyn Any + Send>) -> String {
-    fn swap<A, B>(x: Result<A, B>) -> Result<B, A> {
-        match x {
-            Ok(a) => Err(a),
-            Err(b) => Ok(b),
-        }
-    }
-
-    fn panic_as_string_result_err(p: &Box<dyn Any + Send>) -> Result<(), String> {
-        swap(
-            p.as_ref()
-                .downcast_ref::<&str>()
-                .map(|s| s.to_string())
-                .ok_or(p),
-        )?;
-
-        swap(
-            p.as_ref()
-                .downcast_ref::<String>()
-                .map(|s| s.to_string())
-                .ok_or(p),
-        )?;
-
-        Err("Unknown panic type".to_string())
-    }
-
-    panic_as_string_result_err(p).unwrap_err()
-}
@@ -0,0 +1,75 @@
+use postgres_db::connection::DbConnection;
+use postgres_db::diff_log;
+use postgres_db::internal_state;
+use postgres_db::rewind::rewind_relational_tables;
+use utils::check_no_concurrent_processes;
+
+const USAGE: &str = "<seq> [--diff-log] [--dry-run]";
+
+/// Rewinds the relational tables, and with `--diff-log` also the diff log, to how they were right
+/// after processing seq `<seq>`. Running `relational_db_builder` (and `diff_log_builder`) again then
+/// only reprocesses the changes after it.
+fn main() {
+    let _tracing = utils::init_tracing("rewind_relational_db");
+    let mut args: Vec<String> = std::env::args().collect();
+    let dry_run = pipeline_stage::take_flag(&mut args, "--dry-run");
+    let rewind_diff_log = pipeline_stage::take_flag(&mut args, "--diff-log");
+    if args.len() != 2 {
+        exit_with_usage(&args[0]);
+    }
+    let to_seq: i64 = args[1]
+        .parse()
+        .unwrap_or_else(|_| exit_with_usage(&args[0]));
+
+    check_no_concurrent_processes("relational_db_builder");
+    if rewind_diff_log {
+        check_no_concurrent_processes("diff_log_builder");
+    }
+
+    let mut conn = DbConnection::connect();
+
+    let relational_seq = internal_state::query_relational_processed_seq(&mut conn).unwrap_or(0);
+    let diff_log_seq = internal_state::query_diff_log_processed_seq(&mut conn).unwrap_or(0);
+    if to_seq > relational_seq || (rewind_diff_log && to_seq > diff_log_seq) {
+        eprintln!(
+            "Can't rewind to seq {}: the relational tables are at seq {} and the diff log is at seq {}",
+            to_seq, relational_seq, diff_log_seq
+        );
+        std::process::exit(1);
+    }
+
+    let (summary, num_diff_log_packages) = conn
+        .run_psql_transaction(|mut trans_conn| {
+            let summary = rewind_relational_tables(&mut trans_conn, to_seq);
+            let num_diff_log_packages = if rewind_diff_log {
+                Some(diff_log::rewind_diff_log(to_seq, &mut trans_conn))
+            } else {
+                None
+            };
+            Ok(((summary, num_diff_log_packages), !dry_run))
+        })
+        .expect("Failed to rewind");
+
+    println!(
+        "Rewound the relational tables from seq {} to seq {}: {} packages rewound, {} packages and {} versions deleted",
+        relational_seq,
+        to_seq,
+        summary.num_packages_rewound,
+        summary.num_packages_deleted,
+        summary.num_versions_deleted
+    );
+    if let Some(n) = num_diff_log_packages {
+        println!(
+            "Rewound the diff log from seq {} to seq {}: {} packages rewound",
+            diff_log_seq, to_seq, n
+        );
+    }
+    if dry_run {
+        println!("Dry run, rolled back");
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
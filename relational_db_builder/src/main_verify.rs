@@ -0,0 +1,124 @@
+use postgres_db::connection::DbConnection;
+use postgres_db::packages;
+use relational_db_builder::verify::Verifier;
+use utils::check_no_concurrent_processes;
+
+const USAGE: &str =
+    "[--sample <n> | --package <name>...] [--no-change-log] [--repair-sql <file>] [--verbose]";
+
+const PAGE_SIZE: i64 = 1024;
+const PROGRESS_EVERY: usize = 100_000;
+
+/// Checks the relational tables against the diff log, and the diff log against the change log.
+/// Checks every package by default, `<n>` random packages with `--sample`, or the given packages
+/// with `--package`. Exits with status 2 if anything doesn't match.
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let program = &args[0];
+
+    let mut sample = None;
+    let mut package_names = vec![];
+    let mut check_change_log = true;
+    let mut repair_sql_path = None;
+    let mut verbose = false;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--sample" => {
+                let n = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                sample = Some(
+                    n.parse::<i64>()
+                        .unwrap_or_else(|_| exit_with_usage(program)),
+                );
+            }
+            "--package" => {
+                let name = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                package_names.push(name.clone());
+            }
+            "--no-change-log" => check_change_log = false,
+            "--repair-sql" => {
+                let path = rest.next().unwrap_or_else(|| exit_with_usage(program));
+                repair_sql_path = Some(path.clone());
+            }
+            "--verbose" => verbose = true,
+            _ => exit_with_usage(program),
+        }
+    }
+    if sample.is_some() && !package_names.is_empty() {
+        exit_with_usage(program);
+    }
+
+    let _tracing = utils::init_tracing("verify");
+    check_no_concurrent_processes("relational_db_builder");
+
+    let mut conn = DbConnection::connect();
+    let mut verifier = Verifier::new(&mut conn, check_change_log);
+
+    let full_scan = sample.is_none() && package_names.is_empty();
+    if let Some(n) = sample {
+        for package_id in packages::query_random_package_ids(&mut conn, n) {
+            verifier.check_package(&mut conn, package_id);
+        }
+    } else if !package_names.is_empty() {
+        for name in &package_names {
+            verifier.check_package_by_name(&mut conn, name);
+        }
+    } else {
+        let mut after_id = 0;
+        loop {
+            let page = packages::query_package_ids_after(&mut conn, after_id, PAGE_SIZE);
+            let last_id = match page.last() {
+                Some(id) => *id,
+                None => break,
+            };
+            for package_id in page {
+                verifier.check_package(&mut conn, package_id);
+                if verifier.num_packages_checked() % PROGRESS_EVERY == 0 {
+                    tracing::info!(
+                        num_packages = verifier.num_packages_checked(),
+                        num_discrepancies = verifier.num_discrepancies(),
+                        "Checked packages"
+                    );
+                }
+            }
+            after_id = last_id;
+        }
+        verifier.check_missing_packages(&mut conn);
+    }
+
+    let report = verifier.finish(&mut conn, full_scan);
+
+    if verbose {
+        for d in &report.discrepancies {
+            println!("[{}] {}", d.category(), d);
+        }
+    }
+    let num_dependencies = match report.num_dependencies_checked {
+        Some(n) => n.to_string(),
+        None => "all".to_string(),
+    };
+    println!(
+        "Checked {} packages and {} dependencies at seq {}: {} discrepancies",
+        report.num_packages_checked,
+        num_dependencies,
+        report.seq,
+        report.discrepancies.len()
+    );
+    for (category, count) in report.counts_by_category() {
+        println!("  {}: {}", category, count);
+    }
+
+    if let Some(path) = repair_sql_path {
+        std::fs::write(&path, report.repair_sql()).expect("Failed to write the repair SQL");
+        println!("Wrote repair SQL to {}", path);
+    }
+
+    if !report.discrepancies.is_empty() {
+        std::process::exit(2);
+    }
+}
+
+fn exit_with_usage(program: &str) -> ! {
+    eprintln!("Usage: {} {}", program, USAGE);
+    std::process::exit(1);
+}
@@ -302,6 +302,16 @@ impl RelationalDbAccessor {
         );
     }
 
+    /// Forgets everything in the caches without flushing it, for when the writes they reflect
+    /// were rolled back.
+    pub fn clear_caches(&mut self) {
+        self.package_id_cache.clear();
+        self.package_data_cache.clear();
+        self.version_id_cache.clear();
+        self.dependency_states_cache.clear();
+        self.need_flush_set.clear();
+    }
+
     pub fn flush_caches<R>(&mut self, conn: &mut R)
     where
         R: QueryRunner,
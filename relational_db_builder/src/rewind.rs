@@ -0,0 +1,120 @@
+//! Tests of rewinding the relational tables with `postgres_db::rewind`, which need the
+//! `EntryProcessor` to build them first.
+
+use postgres_db::{
+    connection::{testing::using_test_db, DbConnection},
+    dependencies,
+    diff_log::{self, DiffLogInstruction},
+    internal_state, packages,
+    rewind::{rewind_relational_tables, RewindSummary},
+    versions,
+};
+
+use crate::testing::{entry, package_data, process, semver, version_data};
+
+/// The rows of the test packages, their versions, and the dependencies of those.
+fn dump(conn: &mut DbConnection) -> Vec<String> {
+    let mut dump = vec![];
+    let mut dep_ids = vec![];
+    for name in ["lodash", "react"] {
+        if let Some(id) = packages::maybe_get_package_id_by_name(conn, name) {
+            dump.push(format!("{:?}", packages::get_package(conn, id)));
+            let mut package_versions = versions::get_versions_by_package_id(conn, id);
+            package_versions.sort_by_key(|v| v.id);
+            for v in package_versions {
+                dep_ids.extend(&v.prod_dependencies);
+                dump.push(format!("{:?}", v));
+            }
+        }
+    }
+    let mut deps = dependencies::get_dependencies_by_ids(conn, &dep_ids);
+    deps.sort_by_key(|d| d.id);
+    dump.extend(deps.iter().map(|d| format!("{:?}", d)));
+    dump
+}
+
+#[test]
+fn test_rewind_relational_tables() {
+    use DiffLogInstruction::*;
+
+    using_test_db(|conn| {
+        process(
+            conn,
+            vec![
+                entry(10, "lodash", CreatePackage(package_data(None))),
+                entry(
+                    10,
+                    "lodash",
+                    CreateVersion(semver(1), version_data(&["react"], None)),
+                ),
+                entry(
+                    10,
+                    "lodash",
+                    CreateVersion(semver(3), version_data(&["react"], None)),
+                ),
+                entry(10, "lodash", UpdatePackage(package_data(Some(1)))),
+                entry(10, "lodash", PatchPackageReferences),
+                entry(10, "lodash", DeleteVersion(semver(3))),
+            ],
+        );
+        let at_10 = dump(conn);
+
+        process(
+            conn,
+            vec![
+                entry(20, "react", CreatePackage(package_data(None))),
+                entry(
+                    20,
+                    "react",
+                    CreateVersion(semver(1), version_data(&[], None)),
+                ),
+                entry(20, "react", PatchPackageReferences),
+                entry(
+                    20,
+                    "lodash",
+                    CreateVersion(semver(2), version_data(&["react"], None)),
+                ),
+                entry(
+                    20,
+                    "lodash",
+                    UpdateVersion(semver(1), version_data(&["react"], Some("hi"))),
+                ),
+                entry(
+                    20,
+                    "lodash",
+                    UpdateVersion(semver(3), version_data(&["react"], None)),
+                ),
+                entry(20, "lodash", UpdatePackage(package_data(Some(2)))),
+            ],
+        );
+        process(conn, vec![entry(30, "lodash", DeleteVersion(semver(1)))]);
+        assert_ne!(dump(conn), at_10);
+
+        let summary = rewind_relational_tables(conn, 10);
+        assert_eq!(
+            summary,
+            RewindSummary {
+                num_packages_rewound: 1,
+                num_packages_deleted: 1,
+                num_versions_deleted: 2,
+            }
+        );
+        assert_eq!(dump(conn), at_10);
+        assert_eq!(
+            internal_state::query_relational_processed_seq(conn),
+            Some(10)
+        );
+        assert_eq!(internal_state::query_relational_rewinds(conn), vec![10]);
+
+        // The diff entries after the seq can be processed again.
+        diff_log::delete_diff_entries_after_seq(10, conn);
+        process(
+            conn,
+            vec![entry(20, "react", CreatePackage(package_data(None)))],
+        );
+        assert_eq!(
+            internal_state::query_relational_processed_seq(conn),
+            Some(20)
+        );
+    });
+}
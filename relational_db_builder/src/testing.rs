@@ -0,0 +1,71 @@
+//! Diff entries for tests, and processing them like the relational_db_builder.
+
+use std::collections::BTreeMap;
+
+use postgres_db::{
+    connection::DbConnection,
+    custom_types::{ParsedSpec, Semver},
+    diff_log::{self, insert_diff_log_entries, DiffLogInstruction, NewDiffLogEntry},
+    internal_state,
+    packument::{PackageOnlyPackument, Spec, VersionOnlyPackument},
+    testing,
+};
+use serde_json::json;
+
+use crate::EntryProcessor;
+
+pub fn semver(major: i64) -> Semver {
+    Semver {
+        major,
+        minor: 0,
+        bug: 0,
+        prerelease: vec![],
+        build: vec![],
+    }
+}
+
+pub fn version_data(prod_dependencies: &[&str], extra: Option<&str>) -> VersionOnlyPackument {
+    VersionOnlyPackument {
+        prod_dependencies: prod_dependencies
+            .iter()
+            .map(|dst| {
+                let spec = Spec {
+                    raw: json!("latest"),
+                    parsed: ParsedSpec::Tag("latest".into()),
+                };
+                (dst.to_string(), spec)
+            })
+            .collect(),
+        extra_metadata: extra
+            .map(|e| BTreeMap::from([("description".to_string(), json!(e))]))
+            .unwrap_or_default(),
+        ..testing::version_data()
+    }
+}
+
+pub fn package_data(latest: Option<i64>) -> PackageOnlyPackument {
+    testing::package_data(latest.map(semver))
+}
+
+pub fn entry(seq: i64, package_name: &str, instr: DiffLogInstruction) -> NewDiffLogEntry {
+    NewDiffLogEntry {
+        seq,
+        package_name: package_name.into(),
+        instr,
+    }
+}
+
+/// Adds the entries to the diff log, and processes them like the relational_db_builder.
+pub fn process(conn: &mut DbConnection, entries: Vec<NewDiffLogEntry>) {
+    let after_seq = internal_state::query_relational_processed_seq(conn).unwrap_or(0);
+    insert_diff_log_entries(entries, conn);
+
+    let mut processor = EntryProcessor::new();
+    let entries = diff_log::query_diff_entries_after_seq(after_seq, 1000, conn);
+    let last_seq = entries.last().unwrap().seq;
+    for e in entries {
+        processor.process_entry(conn, e.package_name, e.instr, e.seq, e.id);
+    }
+    processor.flush_caches(conn);
+    internal_state::set_relational_processed_seq(last_seq, conn);
+}
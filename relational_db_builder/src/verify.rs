@@ -0,0 +1,815 @@
+//! Checking that the relational tables match the diff log, and that the diff log matches the
+//! change log, to catch drift between the stages of the pipeline.
+//!
+//! The expected rows of a package are recomputed by replaying its diff entries in memory, the
+//! expected freq counts and destinations of dependencies by recounting them in SQL, and the last
+//! change of a package is compared against the diff log reconstructed at its seq.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Debug;
+
+use postgres_db::{
+    change_log,
+    connection::QueryRunner,
+    custom_types::Semver,
+    dependencies::{self, Dependency},
+    diff_log::{self, DiffLogInstruction, ReconstructionPoint},
+    packages::{self, Package},
+    packument::{Spec, VersionOnlyPackument},
+    replay::{replay_package, ReplayedVersion},
+    versions::{self, Version},
+};
+use serde_json::Value;
+
+/// Stands in for the id of a version that should be the latest version of a package, but has no
+/// row. It never equals a real id, and the missing version is reported on its own.
+const MISSING_VERSION_ID: i64 = -1;
+
+/// How many versions to list when a change and the diff log disagree on them.
+const MAX_LISTED_VERSIONS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// The diff log creates the package, but it has no row.
+    MissingPackage {
+        package: String,
+    },
+    /// The package has a row, but the diff log doesn't create it.
+    ExtraPackage {
+        package: String,
+        package_id: i64,
+    },
+    PackageField {
+        package: String,
+        package_id: i64,
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+    MissingVersion {
+        package: String,
+        version: Semver,
+    },
+    ExtraVersion {
+        package: String,
+        version: Semver,
+        version_id: i64,
+    },
+    VersionField {
+        package: String,
+        version: Semver,
+        version_id: i64,
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+    /// The prod, dev, peer and optional freq counts of a dependency.
+    DependencyCounts {
+        dependency_id: i64,
+        expected: [i64; 4],
+        actual: [i64; 4],
+    },
+    DependencyDst {
+        dependency_id: i64,
+        dst_package_name: String,
+        expected: Option<i64>,
+        actual: Option<i64>,
+    },
+    /// The diff log reconstructed at `seq` doesn't match the change at `seq`.
+    ChangeLog {
+        package: String,
+        seq: i64,
+        detail: String,
+    },
+}
+
+impl Discrepancy {
+    pub fn category(&self) -> &'static str {
+        match self {
+            Discrepancy::MissingPackage { .. } => "missing_package",
+            Discrepancy::ExtraPackage { .. } => "extra_package",
+            Discrepancy::PackageField { .. } => "package_field",
+            Discrepancy::MissingVersion { .. } => "missing_version",
+            Discrepancy::ExtraVersion { .. } => "extra_version",
+            Discrepancy::VersionField { .. } => "version_field",
+            Discrepancy::DependencyCounts { .. } => "dependency_counts",
+            Discrepancy::DependencyDst { .. } => "dependency_dst",
+            Discrepancy::ChangeLog { .. } => "change_log",
+        }
+    }
+
+    /// A statement that fixes the discrepancy in place, if it is a column that doesn't depend on
+    /// anything else. The others have to be fixed by rewinding and reprocessing.
+    pub fn repair_sql(&self) -> Option<String> {
+        match self {
+            Discrepancy::VersionField {
+                version_id,
+                field: "extra_metadata",
+                expected,
+                ..
+            } => Some(format!(
+                "UPDATE versions SET extra_metadata = '{}'::jsonb WHERE id = {};",
+                expected.replace('\'', "''"),
+                version_id
+            )),
+            Discrepancy::DependencyCounts {
+                dependency_id,
+                expected: [prod, dev, peer, optional],
+                ..
+            } => Some(format!(
+                "UPDATE dependencies SET prod_freq_count = {}, dev_freq_count = {}, \
+                 peer_freq_count = {}, optional_freq_count = {} WHERE id = {};",
+                prod, dev, peer, optional, dependency_id
+            )),
+            Discrepancy::DependencyDst {
+                dependency_id,
+                expected,
+                ..
+            } => Some(format!(
+                "UPDATE dependencies SET dst_package_id_if_exists = {} WHERE id = {};",
+                expected.map_or("NULL".to_string(), |id| id.to_string()),
+                dependency_id
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::MissingPackage { package } => {
+                write!(f, "{}: created in the diff log, but has no row", package)
+            }
+            Discrepancy::ExtraPackage {
+                package,
+                package_id,
+            } => write!(
+                f,
+                "{} (id {}): has a row, but isn't created in the diff log",
+                package, package_id
+            ),
+            Discrepancy::PackageField {
+                package,
+                package_id,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} (id {}): {} is {}, expected {}",
+                package, package_id, field, actual, expected
+            ),
+            Discrepancy::MissingVersion { package, version } => write!(
+                f,
+                "{}@{}: created in the diff log, but has no row",
+                package, version
+            ),
+            Discrepancy::ExtraVersion {
+                package,
+                version,
+                version_id,
+            } => write!(
+                f,
+                "{}@{} (id {}): has a row, but isn't created in the diff log",
+                package, version, version_id
+            ),
+            Discrepancy::VersionField {
+                package,
+                version,
+                version_id,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}@{} (id {}): {} is {}, expected {}",
+                package, version, version_id, field, actual, expected
+            ),
+            Discrepancy::DependencyCounts {
+                dependency_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "dependency {}: freq counts (prod, dev, peer, optional) are {:?}, expected {:?}",
+                dependency_id, actual, expected
+            ),
+            Discrepancy::DependencyDst {
+                dependency_id,
+                dst_package_name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "dependency {} on {}: dst_package_id_if_exists is {:?}, expected {:?}",
+                dependency_id, dst_package_name, actual, expected
+            ),
+            Discrepancy::ChangeLog {
+                package,
+                seq,
+                detail,
+            } => write!(
+                f,
+                "{}: the diff log doesn't match the change at seq {}: {}",
+                package, seq, detail
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// The relational processed seq the tables were checked at.
+    pub seq: i64,
+    pub num_packages_checked: usize,
+    /// `None` if all dependencies were checked.
+    pub num_dependencies_checked: Option<usize>,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl VerifyReport {
+    pub fn counts_by_category(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for d in &self.discrepancies {
+            *counts.entry(d.category()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// A script that repairs what can be repaired in place, in one transaction, and lists the
+    /// rest in comments.
+    pub fn repair_sql(&self) -> String {
+        let mut sql = format!(
+            "-- Repairs for {} discrepancies found at seq {}.\nBEGIN;\n",
+            self.discrepancies.len(),
+            self.seq
+        );
+        let mut num_unrepaired = 0;
+        for d in &self.discrepancies {
+            match d.repair_sql() {
+                Some(statement) => sql.push_str(&statement),
+                None => {
+                    num_unrepaired += 1;
+                    sql.push_str("-- Not repairable in place: ");
+                    sql.push_str(&d.to_string().replace('\n', " "));
+                }
+            }
+            sql.push('\n');
+        }
+        sql.push_str("COMMIT;\n");
+        if num_unrepaired > 0 {
+            sql.push_str(&format!(
+                "-- {} discrepancies weren't repaired. Rewind the relational tables to a seq before \
+                 they appeared with rewind_relational_db, adding --diff-log for change_log ones, \
+                 and process the diff log again.\n",
+                num_unrepaired
+            ));
+        }
+        sql
+    }
+}
+
+/// Checks packages one at a time, remembering the dependencies of their versions, whose freq
+/// counts and destinations are checked at the end.
+///
+/// The relational tables must not change while this runs, or it finds discrepancies that aren't
+/// there.
+pub struct Verifier {
+    check_change_log: bool,
+    dependency_ids: BTreeSet<i64>,
+    report: VerifyReport,
+}
+
+impl Verifier {
+    /// Checks against the diff entries up to the relational processed seq. With
+    /// `check_change_log`, the diff log of each package is checked against its last change too.
+    pub fn new<R: QueryRunner>(conn: &mut R, check_change_log: bool) -> Verifier {
+        let seq = postgres_db::internal_state::query_relational_processed_seq(conn).unwrap_or(0);
+        Verifier {
+            check_change_log,
+            dependency_ids: BTreeSet::new(),
+            report: VerifyReport {
+                seq,
+                ..VerifyReport::default()
+            },
+        }
+    }
+
+    pub fn num_packages_checked(&self) -> usize {
+        self.report.num_packages_checked
+    }
+
+    pub fn num_discrepancies(&self) -> usize {
+        self.report.discrepancies.len()
+    }
+
+    /// Checks a package by name, whether or not it has a row.
+    pub fn check_package_by_name<R: QueryRunner>(&mut self, conn: &mut R, package_name: &str) {
+        match packages::maybe_get_package_id_by_name(conn, package_name) {
+            Some(package_id) => self.check_package(conn, package_id),
+            None => {
+                let entries = diff_log::query_diff_entries_for_package(
+                    package_name,
+                    Some(self.report.seq),
+                    conn,
+                );
+                let created = entries
+                    .iter()
+                    .any(|e| matches!(e.instr, DiffLogInstruction::CreatePackage(_)));
+                if created {
+                    self.report.discrepancies.push(Discrepancy::MissingPackage {
+                        package: package_name.to_string(),
+                    });
+                }
+                self.report.num_packages_checked += 1;
+            }
+        }
+    }
+
+    /// Checks the package with the given id, and its versions.
+    pub fn check_package<R: QueryRunner>(&mut self, conn: &mut R, package_id: i64) {
+        let package = packages::get_package(conn, package_id);
+        let entries =
+            diff_log::query_diff_entries_for_package(&package.name, Some(self.report.seq), conn);
+        let last_seq = entries.last().map(|e| e.seq);
+        let actual_versions = versions::get_versions_by_package_id(conn, package.id);
+        let version_ids: BTreeMap<Semver, i64> = actual_versions
+            .iter()
+            .map(|v| (v.semver.clone(), v.id))
+            .collect();
+
+        let replayed = replay_package(&package, entries, |v| {
+            version_ids.get(&v).copied().unwrap_or(MISSING_VERSION_ID)
+        });
+        self.report.num_packages_checked += 1;
+
+        let expected_package = match replayed.package {
+            Some(p) => p,
+            None => {
+                self.report.discrepancies.push(Discrepancy::ExtraPackage {
+                    package: package.name.clone(),
+                    package_id: package.id,
+                });
+                return;
+            }
+        };
+        self.check_package_fields(&expected_package, &package);
+
+        let mut expected_versions = replayed.versions;
+        for version in actual_versions {
+            self.dependency_ids.extend(
+                version
+                    .prod_dependencies
+                    .iter()
+                    .chain(&version.dev_dependencies)
+                    .chain(&version.peer_dependencies)
+                    .chain(&version.optional_dependencies),
+            );
+            match expected_versions.remove(&version.semver) {
+                Some(expected) => self.check_version(conn, &package.name, &expected, &version),
+                None => self.report.discrepancies.push(Discrepancy::ExtraVersion {
+                    package: package.name.clone(),
+                    version: version.semver,
+                    version_id: version.id,
+                }),
+            }
+        }
+        for version in expected_versions.into_keys() {
+            self.report.discrepancies.push(Discrepancy::MissingVersion {
+                package: package.name.clone(),
+                version,
+            });
+        }
+
+        if let (true, Some(seq)) = (self.check_change_log, last_seq) {
+            self.check_change(conn, &package.name, seq);
+        }
+    }
+
+    /// Reports the packages that the diff log creates but that have no row. Only needed when
+    /// checking every package row, since those are missed otherwise.
+    pub fn check_missing_packages<R: QueryRunner>(&mut self, conn: &mut R) {
+        for package in diff_log::query_created_packages_without_rows(self.report.seq, conn) {
+            self.report
+                .discrepancies
+                .push(Discrepancy::MissingPackage { package });
+        }
+    }
+
+    /// Checks the freq counts and destinations of the dependencies of the checked versions, or of
+    /// all dependencies with `all_dependencies`, and returns the report.
+    pub fn finish<R: QueryRunner>(mut self, conn: &mut R, all_dependencies: bool) -> VerifyReport {
+        let dep_ids: Vec<i64> = self.dependency_ids.iter().copied().collect();
+        let dep_ids = if all_dependencies {
+            None
+        } else {
+            Some(dep_ids.as_slice())
+        };
+        self.report.num_dependencies_checked = dep_ids.map(|ids| ids.len());
+        if matches!(dep_ids, Some(ids) if ids.is_empty()) {
+            // The queries scan all versions even for a few dependencies.
+            return self.report;
+        }
+
+        for m in dependencies::query_dependency_count_mismatches(conn, dep_ids) {
+            self.report
+                .discrepancies
+                .push(Discrepancy::DependencyCounts {
+                    dependency_id: m.id,
+                    expected: [
+                        m.expected_prod_freq_count,
+                        m.expected_dev_freq_count,
+                        m.expected_peer_freq_count,
+                        m.expected_optional_freq_count,
+                    ],
+                    actual: [
+                        m.prod_freq_count,
+                        m.dev_freq_count,
+                        m.peer_freq_count,
+                        m.optional_freq_count,
+                    ],
+                });
+        }
+        for m in dependencies::query_dependency_dst_mismatches(conn, dep_ids) {
+            self.report.discrepancies.push(Discrepancy::DependencyDst {
+                dependency_id: m.id,
+                dst_package_name: m.dst_package_name,
+                expected: m.expected_dst_package_id,
+                actual: m.dst_package_id_if_exists,
+            });
+        }
+        self.report
+    }
+
+    fn check_package_fields(&mut self, expected: &Package, actual: &Package) {
+        let mismatches = [
+            mismatch(
+                "current_package_state_type",
+                &expected.current_package_state_type,
+                &actual.current_package_state_type,
+            ),
+            mismatch(
+                "package_state_history",
+                &expected.package_state_history,
+                &actual.package_state_history,
+            ),
+            mismatch(
+                "dist_tag_latest_version",
+                &expected.dist_tag_latest_version,
+                &actual.dist_tag_latest_version,
+            ),
+            mismatch("created", &expected.created, &actual.created),
+            mismatch("modified", &expected.modified, &actual.modified),
+            mismatch(
+                "other_dist_tags",
+                &expected.other_dist_tags,
+                &actual.other_dist_tags,
+            ),
+            mismatch(
+                "other_time_data",
+                &expected.other_time_data,
+                &actual.other_time_data,
+            ),
+            mismatch(
+                "unpublished_data",
+                &expected.unpublished_data,
+                &actual.unpublished_data,
+            ),
+        ];
+        for (field, expected_text, actual_text) in mismatches.into_iter().flatten() {
+            self.report.discrepancies.push(Discrepancy::PackageField {
+                package: actual.name.clone(),
+                package_id: actual.id,
+                field,
+                expected: expected_text,
+                actual: actual_text,
+            });
+        }
+    }
+
+    fn check_version<R: QueryRunner>(
+        &mut self,
+        conn: &mut R,
+        package_name: &str,
+        expected: &ReplayedVersion,
+        actual: &Version,
+    ) {
+        let created_with = &expected.created_with;
+        let extra_metadata =
+            Value::Object(expected.last.extra_metadata.clone().into_iter().collect());
+        let deps = dependencies::get_dependencies_by_ids(
+            conn,
+            &[
+                actual.prod_dependencies.as_slice(),
+                &actual.dev_dependencies,
+                &actual.peer_dependencies,
+                &actual.optional_dependencies,
+            ]
+            .concat(),
+        );
+        let deps: BTreeMap<i64, Dependency> = deps.into_iter().map(|d| (d.id, d)).collect();
+
+        let mismatches = [
+            mismatch(
+                "current_version_state_type",
+                expected.current_state(),
+                &actual.current_version_state_type,
+            ),
+            mismatch(
+                "version_state_history",
+                &expected.version_state_history,
+                &actual.version_state_history,
+            ),
+            mismatch(
+                "tarball_url",
+                &created_with.dist.tarball_url,
+                &actual.tarball_url,
+            ),
+            mismatch("created", &created_with.time, &actual.created),
+            mismatch(
+                "repository_raw",
+                &created_with.repository.as_ref().map(|r| &r.raw),
+                &actual.repository_raw.as_ref(),
+            ),
+            mismatch(
+                "repository_parsed",
+                &created_with.repository.as_ref().map(|r| &r.info),
+                &actual.repository_parsed.as_ref(),
+            ),
+            json_mismatch("extra_metadata", &extra_metadata, &actual.extra_metadata),
+            mismatch(
+                "prod_dependencies",
+                &expected_deps(&created_with.prod_dependencies),
+                &actual_deps(&actual.prod_dependencies, &deps),
+            ),
+            mismatch(
+                "dev_dependencies",
+                &expected_deps(&created_with.dev_dependencies),
+                &actual_deps(&actual.dev_dependencies, &deps),
+            ),
+            mismatch(
+                "peer_dependencies",
+                &expected_deps(&created_with.peer_dependencies),
+                &actual_deps(&actual.peer_dependencies, &deps),
+            ),
+            mismatch(
+                "optional_dependencies",
+                &expected_deps(&created_with.optional_dependencies),
+                &actual_deps(&actual.optional_dependencies, &deps),
+            ),
+        ];
+        for (field, expected_text, actual_text) in mismatches.into_iter().flatten() {
+            self.report.discrepancies.push(Discrepancy::VersionField {
+                package: package_name.to_string(),
+                version: actual.semver.clone(),
+                version_id: actual.id,
+                field,
+                expected: expected_text,
+                actual: actual_text,
+            });
+        }
+    }
+
+    /// Checks the diff log of a package reconstructed at `seq`, the last seq it has entries for,
+    /// against the change at `seq`. Skipped if the change log doesn't have the change anymore.
+    fn check_change<R: QueryRunner>(&mut self, conn: &mut R, package_name: &str, seq: i64) {
+        let change = match change_log::query_changes_by_seqs(&[seq], conn).pop() {
+            Some(change) => change,
+            None => return,
+        };
+        let mut report = |detail: String| {
+            self.report.discrepancies.push(Discrepancy::ChangeLog {
+                package: package_name.to_string(),
+                seq,
+                detail,
+            })
+        };
+
+        let (change_package, change_versions) = match diff_log_builder::deserialize_change(change) {
+            Some((name, package, versions)) if name == package_name => (package, versions),
+            Some((name, _, _)) => return report(format!("the change is for {}", name)),
+            None => return report("the change isn't for a package".to_string()),
+        };
+        let reconstructed =
+            match diff_log::reconstruct(package_name, ReconstructionPoint::Seq(seq), conn) {
+                Some(r) => r,
+                None => return report("the diff log doesn't create the package".to_string()),
+            };
+
+        if reconstructed.package.serialize_and_hash().1 != change_package.serialize_and_hash().1 {
+            report("the package data differs".to_string());
+        }
+
+        let hash = |data: &VersionOnlyPackument| data.serialize_and_hash().1;
+        let all_versions: BTreeSet<&Semver> = reconstructed
+            .versions
+            .keys()
+            .chain(change_versions.keys())
+            .collect();
+        let differing: Vec<String> = all_versions
+            .into_iter()
+            .filter_map(
+                |v| match (reconstructed.versions.get(v), change_versions.get(v)) {
+                    (Some(a), Some(b)) if hash(a) == hash(b) => None,
+                    (Some(_), Some(_)) => Some(format!("{} (data differs)", v)),
+                    (Some(_), None) => Some(format!("{} (not in the change)", v)),
+                    (None, _) if reconstructed.deleted_versions.contains_key(v) => {
+                        Some(format!("{} (deleted in the diff log)", v))
+                    }
+                    (None, _) => Some(format!("{} (not in the diff log)", v)),
+                },
+            )
+            .collect();
+        if !differing.is_empty() {
+            let mut listed = differing[..differing.len().min(MAX_LISTED_VERSIONS)].join(", ");
+            if differing.len() > MAX_LISTED_VERSIONS {
+                listed.push_str(&format!(
+                    ", and {} more",
+                    differing.len() - MAX_LISTED_VERSIONS
+                ));
+            }
+            report(format!("{} versions differ: {}", differing.len(), listed));
+        }
+    }
+}
+
+/// Returns the field and its expected and actual values, if they differ.
+fn mismatch<T: PartialEq + Debug>(
+    field: &'static str,
+    expected: &T,
+    actual: &T,
+) -> Option<(&'static str, String, String)> {
+    if expected == actual {
+        None
+    } else {
+        Some((field, format!("{:?}", expected), format!("{:?}", actual)))
+    }
+}
+
+/// Like [`mismatch`], with the values as JSON.
+fn json_mismatch(
+    field: &'static str,
+    expected: &Value,
+    actual: &Value,
+) -> Option<(&'static str, String, String)> {
+    if expected == actual {
+        None
+    } else {
+        Some((field, expected.to_string(), actual.to_string()))
+    }
+}
+
+/// The package names and raw specs of dependencies, which identify their rows.
+fn expected_deps(deps: &[(String, Spec)]) -> Vec<(String, Value)> {
+    deps.iter()
+        .map(|(dst, spec)| (dst.clone(), spec.raw.clone()))
+        .collect()
+}
+
+fn actual_deps(dep_ids: &[i64], deps: &BTreeMap<i64, Dependency>) -> Vec<(String, Value)> {
+    dep_ids
+        .iter()
+        .map(|id| match deps.get(id) {
+            Some(dep) => (dep.dst_package_name.clone(), dep.raw_spec.clone()),
+            None => (format!("<missing dependency {}>", id), Value::Null),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres_db::{
+        connection::testing::using_test_db,
+        diff_log::{insert_diff_log_entries, DiffLogInstruction::*},
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{entry, package_data, process, semver, version_data};
+
+    fn verify_all<R: QueryRunner>(conn: &mut R) -> VerifyReport {
+        let mut verifier = Verifier::new(conn, true);
+        for package_id in packages::query_package_ids_after(conn, 0, 100) {
+            verifier.check_package(conn, package_id);
+        }
+        verifier.check_missing_packages(conn);
+        verifier.finish(conn, true)
+    }
+
+    #[test]
+    fn test_verify() {
+        using_test_db(|conn| {
+            process(
+                conn,
+                vec![
+                    entry(10, "lodash", CreatePackage(package_data(None))),
+                    entry(
+                        10,
+                        "lodash",
+                        CreateVersion(semver(1), version_data(&["react"], None)),
+                    ),
+                    entry(10, "lodash", UpdatePackage(package_data(Some(1)))),
+                    entry(10, "lodash", PatchPackageReferences),
+                    entry(20, "react", CreatePackage(package_data(None))),
+                    entry(20, "react", PatchPackageReferences),
+                    entry(
+                        20,
+                        "lodash",
+                        UpdateVersion(semver(1), version_data(&["react"], Some("hi"))),
+                    ),
+                    entry(
+                        20,
+                        "lodash",
+                        CreateVersion(semver(2), version_data(&["react"], None)),
+                    ),
+                    entry(20, "lodash", DeleteVersion(semver(2))),
+                ],
+            );
+
+            let report = verify_all(conn);
+            assert_eq!(report.seq, 20);
+            assert_eq!(report.num_packages_checked, 2);
+            assert_eq!(report.discrepancies, vec![]);
+
+            // Drift in each stage.
+            let lodash_id = packages::maybe_get_package_id_by_name(conn, "lodash").unwrap();
+            let v1_id = versions::get_version_id_by_semver(conn, lodash_id, semver(1));
+            let dep_id = versions::get_version_by_id(conn, v1_id).prod_dependencies[0];
+            versions::set_version_extra_metadata(conn, v1_id, json!({}));
+            dependencies::set_dependency_counts(conn, dep_id, (5, 0, 0, 0));
+            insert_diff_log_entries(
+                vec![entry(20, "left-pad", CreatePackage(package_data(None)))],
+                conn,
+            );
+
+            let report = verify_all(conn);
+            assert_eq!(
+                report.discrepancies,
+                vec![
+                    Discrepancy::VersionField {
+                        package: "lodash".into(),
+                        version: semver(1),
+                        version_id: v1_id,
+                        field: "extra_metadata",
+                        expected: r#"{"description":"hi"}"#.into(),
+                        actual: "{}".into(),
+                    },
+                    Discrepancy::MissingPackage {
+                        package: "left-pad".into(),
+                    },
+                    Discrepancy::DependencyCounts {
+                        dependency_id: dep_id,
+                        expected: [1, 0, 0, 0],
+                        actual: [5, 0, 0, 0],
+                    },
+                ]
+            );
+            assert_eq!(
+                report.counts_by_category(),
+                BTreeMap::from([
+                    ("dependency_counts", 1),
+                    ("missing_package", 1),
+                    ("version_field", 1),
+                ])
+            );
+            assert_eq!(
+                report.discrepancies[0].repair_sql(),
+                Some(format!(
+                    r#"UPDATE versions SET extra_metadata = '{{"description":"hi"}}'::jsonb WHERE id = {};"#,
+                    v1_id
+                ))
+            );
+            assert_eq!(report.discrepancies[1].repair_sql(), None);
+            assert!(report
+                .repair_sql()
+                .contains("-- 1 discrepancies weren't repaired."));
+        });
+    }
+
+    #[test]
+    fn test_verify_named_packages() {
+        using_test_db(|conn| {
+            insert_diff_log_entries(
+                vec![entry(10, "left-pad", CreatePackage(package_data(None)))],
+                conn,
+            );
+            postgres_db::internal_state::set_relational_processed_seq(10, conn);
+
+            let mut verifier = Verifier::new(conn, false);
+            verifier.check_package_by_name(conn, "left-pad");
+            verifier.check_package_by_name(conn, "nonexistent");
+            let report = verifier.finish(conn, false);
+
+            assert_eq!(report.num_packages_checked, 2);
+            assert_eq!(report.num_dependencies_checked, Some(0));
+            assert_eq!(
+                report.discrepancies,
+                vec![Discrepancy::MissingPackage {
+                    package: "left-pad".into()
+                }]
+            );
+        });
+    }
+}
@@ -1,7 +1,9 @@
 use std::time::Duration;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use semver_spec_serialization::{parse_semver, parse_spec_via_node};
+#[cfg(feature = "node-parser")]
+use semver_spec_serialization::parse_spec_via_node;
+use semver_spec_serialization::{parse_semver, parse_spec};
 
 pub fn bench_parse_semver(c: &mut Criterion) {
     c.bench_function("parse semver 1.2.3-alpha.1+build.56", |b| {
@@ -9,16 +11,31 @@ pub fn bench_parse_semver(c: &mut Criterion) {
     });
 }
 
+pub fn bench_parse_spec(c: &mut Criterion) {
+    c.bench_function("parse spec: ^1.2.3", |b| {
+        b.iter(|| parse_spec(black_box("^1.2.3")))
+    });
+}
+
+#[cfg(feature = "node-parser")]
 pub fn bench_parse_spec_via_node(c: &mut Criterion) {
     c.bench_function("parse spec via node: ^1.2.3", |b| {
         b.iter(|| parse_spec_via_node(black_box("^1.2.3")))
     });
 }
 
+#[cfg(not(feature = "node-parser"))]
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(20));
+    targets = bench_parse_semver, bench_parse_spec
+}
+
+#[cfg(feature = "node-parser")]
 criterion_group! {
     name = benches;
     config = Criterion::default().measurement_time(Duration::from_secs(20));
-    targets = bench_parse_semver, bench_parse_spec_via_node
+    targets = bench_parse_semver, bench_parse_spec, bench_parse_spec_via_node
 }
 
 criterion_main!(benches);
@@ -2,7 +2,10 @@ use std::process::Command;
 
 // build script's entry point
 fn main() {
-    npm_install();
+    // only the node parser needs js_parser's dependencies installed
+    if std::env::var_os("CARGO_FEATURE_NODE_PARSER").is_some() {
+        npm_install();
+    }
 }
 
 fn npm_install() {
@@ -0,0 +1,71 @@
+//! Checks that `parse_spec` agrees with the node parser on every distinct raw spec
+//! in the `dependencies` table, and prints the ones where they differ.
+
+use std::collections::HashSet;
+
+use postgres_db::connection::DbConnection;
+use postgres_db::custom_types::ParsedSpec;
+use postgres_db::dependencies;
+use semver_spec_serialization::{parse_spec, parse_spec_via_node};
+use serde_json::Value;
+
+const PAGE_SIZE: i64 = 4096;
+
+fn main() {
+    let mut conn = DbConnection::connect();
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut num_mismatches = 0;
+    let mut last_id = 0;
+
+    loop {
+        let page = dependencies::query_raw_specs_after_id(&mut conn, last_id, PAGE_SIZE);
+        if page.is_empty() {
+            break;
+        }
+        last_id = page.last().unwrap().0;
+
+        for (id, raw_spec) in page {
+            // non-string specs are never parsed, so there is nothing to compare
+            let raw_spec = match raw_spec {
+                Value::String(s) => s,
+                _ => continue,
+            };
+            if !seen.insert(raw_spec.clone()) {
+                continue;
+            }
+
+            let native = parse_spec(&raw_spec);
+            let node = parse_spec_via_node(&raw_spec)
+                .unwrap_or_else(|e| panic!("Node failed to parse {:?}: {:?}", raw_spec, e));
+
+            // the exact error messages don't matter, only that both reject the spec
+            let agree = match (&native, &node) {
+                (ParsedSpec::Invalid(_), ParsedSpec::Invalid(_)) => true,
+                (a, b) => a == b,
+            };
+            if !agree {
+                num_mismatches += 1;
+                println!("mismatch for {:?} (dependency id {}):", raw_spec, id);
+                println!("    native: {:?}", native);
+                println!("    node:   {:?}", node);
+            }
+        }
+
+        println!(
+            "compared {} distinct specs up to dependency id {}, {} mismatches so far",
+            seen.len(),
+            last_id,
+            num_mismatches
+        );
+    }
+
+    println!(
+        "done: {} distinct specs, {} mismatches",
+        seen.len(),
+        num_mismatches
+    );
+    if num_mismatches > 0 {
+        std::process::exit(1);
+    }
+}
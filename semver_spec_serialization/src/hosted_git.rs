@@ -0,0 +1,458 @@
+//! A port of the parts of hosted-git-info (5.0.0) that npm-package-arg uses to recognize
+//! git specs for known hosts (e.g. `user/repo` or `github:user/repo#tag`), and to produce
+//! their normalized `saveSpec`.
+
+use url::Url;
+
+use crate::js_compat;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HostType {
+    Github,
+    Bitbucket,
+    Gitlab,
+    Gist,
+    Sourcehut,
+}
+
+impl HostType {
+    fn from_shortcut(protocol: &str) -> Option<HostType> {
+        match protocol {
+            "github:" => Some(HostType::Github),
+            "bitbucket:" => Some(HostType::Bitbucket),
+            "gitlab:" => Some(HostType::Gitlab),
+            "gist:" => Some(HostType::Gist),
+            "sourcehut:" => Some(HostType::Sourcehut),
+            _ => None,
+        }
+    }
+
+    fn from_domain(domain: &str) -> Option<HostType> {
+        match domain {
+            "github.com" => Some(HostType::Github),
+            "bitbucket.org" => Some(HostType::Bitbucket),
+            "gitlab.com" => Some(HostType::Gitlab),
+            "gist.github.com" => Some(HostType::Gist),
+            "git.sr.ht" => Some(HostType::Sourcehut),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            HostType::Github => "github",
+            HostType::Bitbucket => "bitbucket",
+            HostType::Gitlab => "gitlab",
+            HostType::Gist => "gist",
+            HostType::Sourcehut => "sourcehut",
+        }
+    }
+
+    fn domain(self) -> &'static str {
+        match self {
+            HostType::Github => "github.com",
+            HostType::Bitbucket => "bitbucket.org",
+            HostType::Gitlab => "gitlab.com",
+            HostType::Gist => "gist.github.com",
+            HostType::Sourcehut => "git.sr.ht",
+        }
+    }
+
+    fn protocols(self) -> &'static [&'static str] {
+        match self {
+            HostType::Github => &["git:", "http:", "git+ssh:", "git+https:", "ssh:", "https:"],
+            HostType::Bitbucket | HostType::Gitlab => &["git+ssh:", "git+https:", "ssh:", "https:"],
+            HostType::Gist => &["git:", "git+ssh:", "git+https:", "ssh:", "https:"],
+            HostType::Sourcehut => &["git+ssh:", "https:"],
+        }
+    }
+}
+
+const KNOWN_PROTOCOLS: &[&str] = &[
+    "github:",
+    "bitbucket:",
+    "gitlab:",
+    "gist:",
+    "sourcehut:",
+    "http:",
+    "https:",
+    "git:",
+    "git+ssh:",
+    "git+https:",
+    "ssh:",
+];
+
+const AUTH_PROTOCOLS: &[&str] = &["git:", "https:", "git+https:", "http:", "git+http:"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Representation {
+    Shortcut,
+    SshUrl,
+    Https,
+    Git,
+}
+
+/// A git repository on one of the hosts known to hosted-git-info.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HostedGit {
+    host: HostType,
+    user: Option<String>,
+    auth: Option<String>,
+    project: String,
+    pub(crate) committish: Option<String>,
+    default_representation: Representation,
+}
+
+/// The segments returned by a host's `extract` function, before decoding.
+struct Segments<'a> {
+    user: Option<&'a str>,
+    project: &'a str,
+    committish: Option<&'a str>,
+}
+
+/// `HostedGit.fromUrl(giturl)`
+pub(crate) fn from_url(giturl: &str) -> Option<HostedGit> {
+    if giturl.is_empty() {
+        return None;
+    }
+
+    let url = if is_github_shorthand(giturl) {
+        format!("github:{}", giturl)
+    } else {
+        correct_protocol(giturl)
+    };
+    let parsed = parse_git_url(&url)?;
+
+    let protocol = format!("{}:", parsed.scheme());
+    let hostname = parsed.host_str().unwrap_or("");
+    let shortcut = HostType::from_shortcut(&protocol);
+    let host = shortcut
+        .or_else(|| HostType::from_domain(hostname.strip_prefix("www.").unwrap_or(hostname)))?;
+
+    let username = parsed.username();
+    let password = parsed.password().unwrap_or("");
+    let auth = if AUTH_PROTOCOLS.contains(&protocol.as_str())
+        && (!username.is_empty() || !password.is_empty())
+    {
+        if password.is_empty() {
+            Some(username.to_owned())
+        } else {
+            Some(format!("{}:{}", username, password))
+        }
+    } else {
+        None
+    };
+
+    let hash = match parsed.fragment() {
+        Some(f) if !f.is_empty() => Some(f),
+        _ => None,
+    };
+
+    if shortcut.is_some() {
+        let mut pathname = parsed.path();
+        pathname = pathname.strip_prefix('/').unwrap_or(pathname);
+        // we ignore auth for shortcuts, so just trim it out
+        if let Some(first_at) = pathname.find('@') {
+            pathname = &pathname[first_at + 1..];
+        }
+
+        let (user, project) = match pathname.rfind('/') {
+            Some(last_slash) => {
+                let user = js_compat::decode_uri_component(&pathname[..last_slash])?;
+                let project = js_compat::decode_uri_component(&pathname[last_slash + 1..])?;
+                // we want nulls only, never empty strings
+                (Some(user).filter(|u| !u.is_empty()), project)
+            }
+            None => (None, js_compat::decode_uri_component(pathname)?),
+        };
+        let project = strip_dot_git(&project).to_owned();
+
+        let committish = match hash {
+            Some(h) => Some(js_compat::decode_uri_component(h)?),
+            None => None,
+        };
+
+        Some(HostedGit {
+            host,
+            user,
+            auth,
+            project,
+            committish,
+            default_representation: Representation::Shortcut,
+        })
+    } else {
+        if !host.protocols().contains(&protocol.as_str()) {
+            return None;
+        }
+
+        let segments = extract(host, parsed.path(), hash.unwrap_or(""))?;
+
+        let user = match segments.user {
+            Some(u) if !u.is_empty() => Some(js_compat::decode_uri_component(u)?),
+            _ => None,
+        };
+        let project = js_compat::decode_uri_component(segments.project)?;
+        // decodeURIComponent(undefined) is the string "undefined"
+        let committish =
+            js_compat::decode_uri_component(segments.committish.unwrap_or("undefined"))?;
+
+        let default_representation = match protocol.as_str() {
+            "git+ssh:" | "ssh:" => Representation::SshUrl,
+            "git+https:" | "https:" => Representation::Https,
+            "git:" => Representation::Git,
+            // there is no `http` representation, so `toString` falls back to `sshurl`
+            _ => Representation::SshUrl,
+        };
+
+        Some(HostedGit {
+            host,
+            user,
+            auth,
+            project,
+            committish: Some(committish).filter(|c| !c.is_empty()),
+            default_representation,
+        })
+    }
+}
+
+impl std::fmt::Display for HostedGit {
+    /// `hosted.toString({ noGitPlus: false, noCommittish: false })`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let domain = self.host.domain();
+        let user = self.user.as_deref().unwrap_or("null");
+        let project = &self.project;
+        let is_gist = self.host == HostType::Gist;
+
+        match self.default_representation {
+            Representation::Shortcut if is_gist => write!(f, "{}:{}", self.host.name(), project)?,
+            Representation::Shortcut => write!(f, "{}:{}/{}", self.host.name(), user, project)?,
+            Representation::SshUrl if is_gist => {
+                write!(f, "git+ssh://git@{}/{}.git", domain, project)?
+            }
+            Representation::SshUrl => {
+                write!(f, "git+ssh://git@{}/{}/{}.git", domain, user, project)?
+            }
+            Representation::Https if is_gist => {
+                write!(f, "git+https://{}/{}.git", domain, project)?
+            }
+            Representation::Https if self.host == HostType::Sourcehut => {
+                write!(f, "https://{}/{}/{}.git", domain, user, project)?
+            }
+            Representation::Https => {
+                write!(f, "git+https://")?;
+                if let Some(auth) = &self.auth {
+                    write!(f, "{}@", auth)?;
+                }
+                write!(f, "{}/{}/{}.git", domain, user, project)?
+            }
+            Representation::Git if is_gist => write!(f, "git://{}/{}.git", domain, project)?,
+            Representation::Git => {
+                // only github and gist accept the git: protocol
+                write!(f, "git://")?;
+                if let Some(auth) = &self.auth {
+                    write!(f, "{}@", auth)?;
+                }
+                write!(f, "{}/{}/{}.git", domain, user, project)?
+            }
+        }
+
+        if let Some(c) = &self.committish {
+            write!(f, "#{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+fn strip_dot_git(project: &str) -> &str {
+    project.strip_suffix(".git").unwrap_or(project)
+}
+
+/// The host-specific `extract` functions, which pull the user, project and committish out of a URL.
+fn extract<'a>(host: HostType, pathname: &'a str, hash: &'a str) -> Option<Segments<'a>> {
+    let mut parts = pathname.split('/').skip(1);
+    match host {
+        HostType::Github => {
+            let user = parts.next();
+            let project = parts.next();
+            let kind = parts.next();
+            let mut committish = parts.next();
+            match kind {
+                Some(t) if !t.is_empty() && t != "tree" => return None,
+                Some(t) if !t.is_empty() => {}
+                _ => committish = Some(hash),
+            }
+            let project = strip_dot_git(project.unwrap_or(""));
+            let user = user.filter(|u| !u.is_empty())?;
+            if project.is_empty() {
+                return None;
+            }
+            Some(Segments {
+                user: Some(user),
+                project,
+                committish,
+            })
+        }
+        HostType::Bitbucket | HostType::Sourcehut => {
+            let user = parts.next();
+            let project = parts.next();
+            let aux = parts.next();
+            let tarball_path = if host == HostType::Bitbucket {
+                "get"
+            } else {
+                "archive"
+            };
+            if aux == Some(tarball_path) {
+                return None;
+            }
+            let project = strip_dot_git(project.unwrap_or(""));
+            let user = user.filter(|u| !u.is_empty())?;
+            if project.is_empty() {
+                return None;
+            }
+            Some(Segments {
+                user: Some(user),
+                project,
+                committish: Some(hash),
+            })
+        }
+        HostType::Gitlab => {
+            let path = pathname.get(1..).unwrap_or("");
+            if path.contains("/-/") || path.contains("/archive.tar.gz") {
+                return None;
+            }
+            let (user, project) = match path.rfind('/') {
+                Some(i) => (&path[..i], &path[i + 1..]),
+                None => ("", path),
+            };
+            let project = strip_dot_git(project);
+            if user.is_empty() || project.is_empty() {
+                return None;
+            }
+            Some(Segments {
+                user: Some(user),
+                project,
+                committish: Some(hash),
+            })
+        }
+        HostType::Gist => {
+            let user = parts.next();
+            let project = parts.next();
+            let aux = parts.next();
+            if aux == Some("raw") {
+                return None;
+            }
+            let (user, project) = match project.filter(|p| !p.is_empty()) {
+                Some(p) => (user, p),
+                None => (None, user.filter(|u| !u.is_empty())?),
+            };
+            Some(Segments {
+                user,
+                project: strip_dot_git(project),
+                committish: Some(hash),
+            })
+        }
+    }
+}
+
+/// Looks for github shorthand inputs, such as `npm/cli`.
+fn is_github_shorthand(arg: &str) -> bool {
+    // it cannot contain whitespace before the first #
+    // it cannot start with a / because that's probably an absolute file path
+    // but it must include a slash since repos are username/repository
+    // it cannot start with a . because that's probably a relative file path
+    // it cannot start with an @ because that's a scoped package if it passes the other tests
+    // it cannot contain a : before a # because that tells us that there's a protocol
+    // a second / may not exist before a #
+    let first_hash = arg.find('#');
+    let first_slash = arg.find('/');
+    let second_slash = first_slash.and_then(|i| arg[i + 1..].find('/').map(|j| i + 1 + j));
+    let first_colon = arg.find(':');
+    let first_space = arg.find(js_compat::is_js_whitespace);
+    let first_at = arg.find('@');
+
+    let only_after_hash = |i: Option<usize>| match (i, first_hash) {
+        (None, _) => true,
+        (Some(i), Some(h)) => i > h,
+        (Some(_), None) => false,
+    };
+
+    let has_slash = matches!(first_slash, Some(i) if i > 0);
+    // if a # is found, what we really want to know is that the character
+    // immediately before # is not a /
+    let does_not_end_with_slash = match first_hash {
+        Some(h) => h == 0 || arg.as_bytes()[h - 1] != b'/',
+        None => !arg.ends_with('/'),
+    };
+    let does_not_start_with_dot = !arg.starts_with('.');
+
+    only_after_hash(first_space)
+        && has_slash
+        && does_not_end_with_slash
+        && does_not_start_with_dot
+        && only_after_hash(first_at)
+        && only_after_hash(first_colon)
+        && only_after_hash(second_slash)
+}
+
+/// Accepts input like `git:github.com:user/repo` and inserts the `//` after the first `:`
+fn correct_protocol(arg: &str) -> String {
+    let first_colon = arg.find(':');
+    let proto = match first_colon {
+        Some(i) => &arg[..i + 1],
+        None => "",
+    };
+    if KNOWN_PROTOCOLS.contains(&proto) {
+        return arg.to_owned();
+    }
+
+    if let Some(first_at) = arg.find('@') {
+        return match first_colon {
+            Some(c) if first_at < c => arg.to_owned(),
+            _ => format!("git+ssh://{}", arg),
+        };
+    }
+
+    let after_colon = first_colon.map_or(0, |c| c + 1);
+    if arg.find("//") == Some(after_colon) {
+        return arg.to_owned();
+    }
+
+    format!("{}//{}", &arg[..after_colon], &arg[after_colon..])
+}
+
+fn last_index_of_before(s: &str, c: char, before: char) -> Option<usize> {
+    let end = s.find(before).map_or(s.len(), |i| i + 1);
+    s[..end.min(s.len())].rfind(c)
+}
+
+/// Attempts to correct an scp style url so that it will parse with `new URL()`
+fn correct_url(giturl: &str) -> String {
+    let mut giturl = giturl.to_owned();
+    // ignore @ that come after the first hash since the denotes the start
+    // of a committish which can contain @ characters
+    let first_at = last_index_of_before(&giturl, '@', '#');
+    // ignore colons that come after the hash since that could include colons such as:
+    // git@github.com:user/package-2#semver:^1.0.0
+    let last_colon_before_hash = last_index_of_before(&giturl, ':', '#');
+
+    if let Some(colon) = last_colon_before_hash {
+        if first_at.is_none_or(|at| colon > at) {
+            // the last : comes after the first @ (or there is no @),
+            // so we replace it with a / to create a valid path
+            giturl = format!("{}/{}", &giturl[..colon], &giturl[colon + 1..]);
+        }
+    }
+
+    if last_index_of_before(&giturl, ':', '#').is_none() && !giturl.contains("//") {
+        // we have no : at all, so we prepend a protocol
+        giturl = format!("git+ssh://{}", giturl);
+    }
+
+    giturl
+}
+
+fn parse_git_url(giturl: &str) -> Option<Url> {
+    Url::parse(giturl)
+        .or_else(|_| Url::parse(&correct_url(giturl)))
+        .ok()
+}
@@ -0,0 +1,85 @@
+//! Small helpers that behave like the JavaScript builtins used by node-semver,
+//! npm-package-arg and hosted-git-info, which differ from their Rust equivalents
+//! in edge cases (e.g. which characters count as whitespace).
+
+/// The characters matched by `\s` in a (non-unicode) JavaScript regex,
+/// for use inside a character class.
+pub(crate) const WS_CHARS: &str =
+    r"\t\n\x0B\x0C\r \xA0\x{1680}\x{2000}-\x{200A}\x{2028}\x{2029}\x{202F}\x{205F}\x{3000}\x{FEFF}";
+
+pub(crate) fn is_js_whitespace(c: char) -> bool {
+    matches!(
+        c,
+        '\t' | '\n' | '\x0B' | '\x0C' | '\r' | ' ' | '\u{A0}' | '\u{1680}' | '\u{2000}'
+            ..='\u{200A}'
+                | '\u{2028}'
+                | '\u{2029}'
+                | '\u{202F}'
+                | '\u{205F}'
+                | '\u{3000}'
+                | '\u{FEFF}'
+    )
+}
+
+/// `String.prototype.trim`
+pub(crate) fn trim(s: &str) -> &str {
+    s.trim_matches(is_js_whitespace)
+}
+
+/// Returns true if `encodeURIComponent(s) === s`
+pub(crate) fn is_uri_component_safe(s: &str) -> bool {
+    s.chars().all(|c| {
+        c.is_ascii_alphanumeric()
+            || matches!(c, '-' | '_' | '.' | '!' | '~' | '*' | '\'' | '(' | ')')
+    })
+}
+
+/// `decodeURIComponent`, which returns `None` where JavaScript would throw a `URIError`.
+pub(crate) fn decode_uri_component(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// `Number(digits) + 1`, formatted the way JavaScript would print it.
+/// Only the exact value up to `Number.MAX_SAFE_INTEGER` matters to us,
+/// since anything larger is rejected later on by node-semver.
+pub(crate) fn increment(digits: &str) -> String {
+    match parse_safe_integer(digits) {
+        Some(n) => (n + 1).to_string(),
+        None => {
+            let trimmed = digits.trim_start_matches('0');
+            if trimmed.len() > 21 {
+                // JavaScript switches to exponential notation, which doesn't parse as a version
+                format!("{}e+{}", &trimmed[..1], trimmed.len() - 1)
+            } else {
+                trimmed.to_owned()
+            }
+        }
+    }
+}
+
+pub(crate) const MAX_SAFE_INTEGER: u64 = 9007199254740991;
+
+/// `+digits`, if the result is at most `Number.MAX_SAFE_INTEGER`.
+pub(crate) fn parse_safe_integer(digits: &str) -> Option<i64> {
+    let trimmed = digits.trim_start_matches('0');
+    if trimmed.is_empty() {
+        return Some(0);
+    }
+    match trimmed.parse::<u64>() {
+        Ok(n) if n <= MAX_SAFE_INTEGER => Some(n as i64),
+        _ => None,
+    }
+}
@@ -1,10 +1,7 @@
 #[macro_use]
 extern crate lazy_static;
 
-use std::io::{BufRead, BufReader, Write};
-use std::string::FromUtf8Error;
-use std::sync::Mutex;
-use std::{num::ParseIntError, os::unix::net::UnixStream};
+use std::num::ParseIntError;
 
 use cached::proc_macro::cached;
 
@@ -12,45 +9,21 @@ use lazy_regex::regex;
 
 use postgres_db::custom_types::{ParsedSpec, PrereleaseTag, Semver};
 
-lazy_static! {
-    static ref SOCK_PATH: String = {
-        let tmpdir = std::env::temp_dir();
-        format!(
-            "{}/specsrv-{}.sock",
-            tmpdir.to_str().unwrap(),
-            std::process::id()
-        )
-    };
-    static ref SPEC_PROC_CHILD: Mutex<std::process::Child> = {
-        use std::process::Command;
-        use std::process::Stdio;
-
-        let mut js_dir = std::env::current_dir().unwrap();
-        if js_dir.ends_with("semver_spec_serialization/") {
-            js_dir.push("js_parser");
-        } else if js_dir.ends_with("relational_db_builder/")
-            || js_dir.ends_with("diff_log_builder/")
-            || js_dir.ends_with("historic_npm_registry/")
-        {
-            js_dir.push("..");
-            js_dir.push("semver_spec_serialization");
-            js_dir.push("js_parser");
-        } else {
-            js_dir.push("semver_spec_serialization");
-            js_dir.push("js_parser");
-        }
-
-        let child = Command::new("node")
-            .arg(js_dir)
-            .arg(SOCK_PATH.to_string())
-            .arg(std::process::id().to_string())
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Couldn't spawn spec parsing daemon");
-        Mutex::new(child)
-    };
-}
+mod hosted_git;
+mod js_compat;
+#[cfg(feature = "node-parser")]
+mod node;
+mod range;
+mod satisfies;
+mod spec;
+
+#[cfg(feature = "node-parser")]
+pub use node::{parse_spec_via_node, parse_spec_via_node_cached, ParseSpecError};
+pub use range::{parse_advisory_range, parse_range, parse_range_with_options, ParseRangeError};
+pub use satisfies::{
+    compare_versions, intersects, is_subset, max_satisfying, min_satisfying, satisfies,
+};
+pub use spec::parse_spec;
 
 #[derive(Debug)]
 pub enum ParseSemverError {
@@ -126,83 +99,7 @@ pub fn parse_semver(v_str: &str) -> Result<Semver, ParseSemverError> {
     })
 }
 
-#[derive(Debug)]
-pub enum ParseSpecError {
-    UnknownType(String),
-    Other(String),
-    Encoding(FromUtf8Error),
-    JsonParsing(serde_json::Error),
-    IO(std::io::Error),
-}
-
-impl From<serde_json::Error> for ParseSpecError {
-    fn from(err: serde_json::Error) -> Self {
-        Self::JsonParsing(err)
-    }
-}
-
-impl From<FromUtf8Error> for ParseSpecError {
-    fn from(err: FromUtf8Error) -> Self {
-        Self::Encoding(err)
-    }
-}
-
-impl From<std::io::Error> for ParseSpecError {
-    fn from(err: std::io::Error) -> Self {
-        Self::IO(err)
-    }
-}
-
-pub fn parse_spec_via_node(s: &str) -> Result<ParsedSpec, ParseSpecError> {
-    // edge case for empty string, which cannot be transmitted via socket
-    if s.is_empty() {
-        return Ok(ParsedSpec::Tag("latest".to_string()));
-    }
-
-    let mut stream = {
-        // hack to evaluate the daemon in the lazy static
-        let mut lock = SPEC_PROC_CHILD.lock().unwrap();
-        let _hack = lock.id();
-
-        let stream_res = UnixStream::connect(SOCK_PATH.to_string());
-        match stream_res {
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                // this might happen if the daemon didn't start up in time.
-                // if this was a real error, it will throw again.
-                // although, this error should never happen in practice.
-                let stdout = lock.stdout.take().ok_or_else(|| {
-                    ParseSpecError::Other(
-                        "Couldn't retrieve stdout from parsing daemon".to_string(),
-                    )
-                })?;
-
-                // read stdout and wait for the "Listening" string
-                let mut buf = String::new();
-                let mut reader = BufReader::new(stdout);
-                reader.read_line(&mut buf)?;
-
-                UnixStream::connect(SOCK_PATH.to_string())
-            }
-            _ => stream_res,
-        }?
-    };
-
-    stream.write_all(s.as_bytes())?;
-    let mut res = String::new();
-    let mut reader = BufReader::new(stream);
-    reader.read_line(&mut res)?;
-    // println!("json = {}", res);
-    let parsed: ParsedSpec = serde_json::from_str(&res)?;
-
-    Ok(parsed)
-}
-
-#[cached(
-    size = 500_000,
-    result = true,
-    key = "String",
-    convert = r#"{ String::from(s) }"#
-)]
-pub fn parse_spec_via_node_cached(s: &str) -> Result<ParsedSpec, ParseSpecError> {
-    parse_spec_via_node(s)
+#[cached(size = 500_000, key = "String", convert = r#"{ String::from(s) }"#)]
+pub fn parse_spec_cached(s: &str) -> ParsedSpec {
+    parse_spec(s)
 }
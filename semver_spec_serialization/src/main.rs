@@ -1,4 +1,4 @@
-use semver_spec_serialization::parse_spec_via_node;
+use semver_spec_serialization::parse_spec;
 
 // TODO: delete when done
 pub fn main() {
@@ -10,7 +10,7 @@ pub fn main() {
         if input == "quit" {
             break;
         }
-        let spec = parse_spec_via_node(input);
+        let spec = parse_spec(input);
         println!("{:?}", spec);
     }
 }
@@ -0,0 +1,120 @@
+//! Parsing specs by sending them to a node daemon running `js_parser`, which uses
+//! npm-package-arg and node-semver directly. This is slow, and needs node and npm installed,
+//! so it is only kept around to check `parse_spec` against.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::string::FromUtf8Error;
+use std::sync::Mutex;
+
+use cached::proc_macro::cached;
+
+use postgres_db::custom_types::ParsedSpec;
+
+lazy_static! {
+    static ref SOCK_PATH: String = {
+        let tmpdir = std::env::temp_dir();
+        format!(
+            "{}/specsrv-{}.sock",
+            tmpdir.to_str().unwrap(),
+            std::process::id()
+        )
+    };
+    static ref SPEC_PROC_CHILD: Mutex<std::process::Child> = {
+        use std::process::Command;
+        use std::process::Stdio;
+
+        let js_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/js_parser");
+
+        let child = Command::new("node")
+            .arg(js_dir)
+            .arg(SOCK_PATH.to_string())
+            .arg(std::process::id().to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Couldn't spawn spec parsing daemon");
+        Mutex::new(child)
+    };
+}
+
+#[derive(Debug)]
+pub enum ParseSpecError {
+    UnknownType(String),
+    Other(String),
+    Encoding(FromUtf8Error),
+    JsonParsing(serde_json::Error),
+    IO(std::io::Error),
+}
+
+impl From<serde_json::Error> for ParseSpecError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JsonParsing(err)
+    }
+}
+
+impl From<FromUtf8Error> for ParseSpecError {
+    fn from(err: FromUtf8Error) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+impl From<std::io::Error> for ParseSpecError {
+    fn from(err: std::io::Error) -> Self {
+        Self::IO(err)
+    }
+}
+
+pub fn parse_spec_via_node(s: &str) -> Result<ParsedSpec, ParseSpecError> {
+    // edge case for empty string, which cannot be transmitted via socket
+    if s.is_empty() {
+        return Ok(ParsedSpec::Tag("latest".to_string()));
+    }
+
+    let mut stream = {
+        // hack to evaluate the daemon in the lazy static
+        let mut lock = SPEC_PROC_CHILD.lock().unwrap();
+        let _hack = lock.id();
+
+        let stream_res = UnixStream::connect(SOCK_PATH.to_string());
+        match stream_res {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // this might happen if the daemon didn't start up in time.
+                // if this was a real error, it will throw again.
+                // although, this error should never happen in practice.
+                let stdout = lock.stdout.take().ok_or_else(|| {
+                    ParseSpecError::Other(
+                        "Couldn't retrieve stdout from parsing daemon".to_string(),
+                    )
+                })?;
+
+                // read stdout and wait for the "Listening" string
+                let mut buf = String::new();
+                let mut reader = BufReader::new(stdout);
+                reader.read_line(&mut buf)?;
+
+                UnixStream::connect(SOCK_PATH.to_string())
+            }
+            _ => stream_res,
+        }?
+    };
+
+    stream.write_all(s.as_bytes())?;
+    let mut res = String::new();
+    let mut reader = BufReader::new(stream);
+    reader.read_line(&mut res)?;
+    // println!("json = {}", res);
+    let parsed: ParsedSpec = serde_json::from_str(&res)?;
+
+    Ok(parsed)
+}
+
+#[cached(
+    size = 500_000,
+    result = true,
+    key = "String",
+    convert = r#"{ String::from(s) }"#
+)]
+pub fn parse_spec_via_node_cached(s: &str) -> Result<ParsedSpec, ParseSpecError> {
+    parse_spec_via_node(s)
+}
@@ -0,0 +1,607 @@
+//! A port of node-semver's (7.3.7) `Range` parsing in loose mode, which is what
+//! npm-package-arg uses. Like node-semver, ranges are desugared by rewriting the
+//! string with a series of regexes, and the results are then parsed as comparators.
+//! The regexes and rewrites follow `internal/re.js` and `classes/range.js` closely,
+//! since any deviation changes which specs are ranges and which are tags.
+
+use regex::{Captures, Regex};
+
+use postgres_db::custom_types::{PrereleaseTag, Semver, VersionComparator, VersionConstraint};
+
+use crate::js_compat::{self, WS_CHARS};
+
+/// node-semver's `MAX_LENGTH`
+const MAX_VERSION_LENGTH: usize = 256;
+
+const PRERELEASE_IDENTIFIER: &str = "(?:0|[1-9][0-9]*|[0-9]*[a-zA-Z-][a-zA-Z0-9-]*)";
+const PRERELEASE_IDENTIFIER_LOOSE: &str = "(?:[0-9]+|[0-9]*[a-zA-Z-][a-zA-Z0-9-]*)";
+const BUILD: &str = r"(?:\+([0-9A-Za-z-]+(?:\.[0-9A-Za-z-]+)*))";
+const GTLT: &str = "((?:<|>)?=?)";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseRangeError {
+    Range(String),
+    Comparator(String),
+    Version(String),
+}
+
+impl std::fmt::Display for ParseRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseRangeError::Range(r) => write!(f, "Invalid SemVer Range: {}", r),
+            ParseRangeError::Comparator(c) => write!(f, "Invalid comparator: {}", c),
+            ParseRangeError::Version(v) => write!(f, "Invalid Version: {}", v),
+        }
+    }
+}
+
+lazy_static! {
+    static ref WS: String = format!("[{}]", WS_CHARS);
+    static ref PRERELEASE: String = format!(r"(?:-({pi}(?:\.{pi})*))", pi = PRERELEASE_IDENTIFIER);
+    static ref PRERELEASE_LOOSE: String =
+        format!(r"(?:-?({pi}(?:\.{pi})*))", pi = PRERELEASE_IDENTIFIER_LOOSE);
+    static ref LOOSE_PLAIN: String = format!(
+        r"[v={ws}]*([0-9]+)\.([0-9]+)\.([0-9]+){pre}?{build}?",
+        ws = WS_CHARS,
+        pre = *PRERELEASE_LOOSE,
+        build = BUILD
+    );
+    static ref XRANGE_PLAIN: String = xrange_plain("0|[1-9][0-9]*|x|X|\\*", &PRERELEASE);
+    static ref XRANGE_PLAIN_LOOSE: String = xrange_plain("[0-9]+|x|X|\\*", &PRERELEASE_LOOSE);
+    static ref LOOSE: Regex = Regex::new(&format!("^{}$", *LOOSE_PLAIN)).unwrap();
+    static ref XRANGE_LOOSE: Regex =
+        Regex::new(&format!("^{}{}*{}$", GTLT, *WS, *XRANGE_PLAIN_LOOSE)).unwrap();
+    static ref TILDE_TRIM: Regex = Regex::new(&format!("({ws}*)(?:~>?){ws}+", ws = *WS)).unwrap();
+    static ref TILDE_LOOSE: Regex =
+        Regex::new(&format!("^(?:~>?){}$", *XRANGE_PLAIN_LOOSE)).unwrap();
+    static ref CARET_TRIM: Regex = Regex::new(&format!(r"({ws}*)(?:\^){ws}+", ws = *WS)).unwrap();
+    static ref CARET_LOOSE: Regex =
+        Regex::new(&format!(r"^(?:\^){}$", *XRANGE_PLAIN_LOOSE)).unwrap();
+    static ref COMPARATOR_LOOSE: Regex =
+        Regex::new(&format!("^{}{}*({})$|^$", GTLT, *WS, *LOOSE_PLAIN)).unwrap();
+    static ref COMPARATOR_TRIM: Regex = Regex::new(&format!(
+        "({ws}*){gtlt}{ws}*({loose}|{xrange})",
+        ws = *WS,
+        gtlt = GTLT,
+        loose = *LOOSE_PLAIN,
+        xrange = *XRANGE_PLAIN
+    ))
+    .unwrap();
+    static ref HYPHEN_RANGE_LOOSE: Regex = Regex::new(&format!(
+        "^{ws}*({x}){ws}+-{ws}+({x}){ws}*$",
+        ws = *WS,
+        x = *XRANGE_PLAIN_LOOSE
+    ))
+    .unwrap();
+    static ref STAR: Regex = Regex::new(&format!(r"(<|>)?=?{}*\*", *WS)).unwrap();
+    static ref GTE0: Regex = Regex::new(&format!(r"^{ws}*>={ws}*0\.0\.0{ws}*$", ws = *WS)).unwrap();
+    static ref GTE0_PRE: Regex =
+        Regex::new(&format!(r"^{ws}*>={ws}*0\.0\.0-0{ws}*$", ws = *WS)).unwrap();
+    static ref OR: Regex = Regex::new(&format!(r"{ws}*\|\|{ws}*", ws = *WS)).unwrap();
+    static ref WS_RUN: Regex = Regex::new(&format!("{}+", *WS)).unwrap();
+}
+
+fn xrange_plain(identifier: &str, prerelease: &str) -> String {
+    format!(
+        r"[v={ws}]*({id})(?:\.({id})(?:\.({id})(?:{pre})?{build}?)?)?",
+        ws = WS_CHARS,
+        id = identifier,
+        pre = prerelease,
+        build = BUILD
+    )
+}
+
+/// A parsed comparator. The operator is kept as a string, and `value` is used for de-duplication,
+/// exactly as node-semver does.
+#[derive(Debug, Clone)]
+struct Comparator {
+    operator: &'static str,
+    semver: Option<Semver>,
+    value: String,
+}
+
+impl Comparator {
+    fn parse(comp: &str) -> Result<Comparator, ParseRangeError> {
+        let m = COMPARATOR_LOOSE
+            .captures(comp)
+            .ok_or_else(|| ParseRangeError::Comparator(comp.to_owned()))?;
+
+        let operator = match m.get(1).map(|x| x.as_str()) {
+            Some("<") => "<",
+            Some("<=") => "<=",
+            Some(">") => ">",
+            Some(">=") => ">=",
+            _ => "",
+        };
+
+        let semver = match m.get(2).map(|x| x.as_str()) {
+            None | Some("") => None,
+            Some(v) => Some(parse_semver_loose(v)?),
+        };
+
+        let value = match &semver {
+            None => String::new(),
+            Some(v) => format!("{}{}", operator, format_version(v)),
+        };
+
+        Ok(Comparator {
+            operator,
+            semver,
+            value,
+        })
+    }
+
+    fn is_null_set(&self) -> bool {
+        self.value == "<0.0.0-0"
+    }
+
+    fn is_any(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    fn into_version_comparator(self) -> VersionComparator {
+        match (self.operator, self.semver) {
+            (_, None) => VersionComparator::Any,
+            ("", Some(v)) => VersionComparator::Eq(v),
+            (">", Some(v)) => VersionComparator::Gt(v),
+            (">=", Some(v)) => VersionComparator::Gte(v),
+            ("<", Some(v)) => VersionComparator::Lt(v),
+            ("<=", Some(v)) => VersionComparator::Lte(v),
+            (op, _) => unreachable!("unknown comparator op: {}", op),
+        }
+    }
+}
+
+/// `new SemVer(version, { loose: true })`
+fn parse_semver_loose(version: &str) -> Result<Semver, ParseRangeError> {
+    let invalid = || ParseRangeError::Version(version.to_owned());
+
+    // JavaScript string lengths count UTF-16 code units
+    if version.encode_utf16().count() > MAX_VERSION_LENGTH {
+        return Err(invalid());
+    }
+
+    let m = LOOSE
+        .captures(js_compat::trim(version))
+        .ok_or_else(invalid)?;
+
+    let major = js_compat::parse_safe_integer(&m[1]).ok_or_else(invalid)?;
+    let minor = js_compat::parse_safe_integer(&m[2]).ok_or_else(invalid)?;
+    let bug = js_compat::parse_safe_integer(&m[3]).ok_or_else(invalid)?;
+
+    let prerelease = match m.get(4) {
+        None => vec![],
+        Some(pre) => pre
+            .as_str()
+            .split('.')
+            .map(|id| {
+                let num = if id.bytes().all(|b| b.is_ascii_digit()) {
+                    js_compat::parse_safe_integer(id)
+                        .filter(|n| (*n as u64) < js_compat::MAX_SAFE_INTEGER)
+                } else {
+                    None
+                };
+                match num {
+                    Some(n) => PrereleaseTag::Int(n),
+                    None => PrereleaseTag::String(id.to_owned()),
+                }
+            })
+            .collect(),
+    };
+
+    let build = match m.get(5) {
+        None => vec![],
+        Some(b) => b.as_str().split('.').map(|s| s.to_owned()).collect(),
+    };
+
+    Ok(Semver {
+        major,
+        minor,
+        bug,
+        prerelease,
+        build,
+    })
+}
+
+/// `SemVer.prototype.format`, which leaves out the build metadata
+fn format_version(v: &Semver) -> String {
+    let mut s = format!("{}.{}.{}", v.major, v.minor, v.bug);
+    if !v.prerelease.is_empty() {
+        let pre: Vec<String> = v.prerelease.iter().map(|t| t.to_string()).collect();
+        s.push('-');
+        s.push_str(&pre.join("."));
+    }
+    s
+}
+
+fn is_x(id: Option<&str>) -> bool {
+    match id {
+        None | Some("") | Some("x") | Some("X") | Some("*") => true,
+        Some(_) => false,
+    }
+}
+
+fn split_ws(s: &str) -> Vec<&str> {
+    WS_RUN.split(s).collect()
+}
+
+/// Parses a range the same way as `new semver.Range(range, { loose: true })`.
+pub fn parse_range(range: &str) -> Result<VersionConstraint, ParseRangeError> {
+    parse_range_with_options(range, false)
+}
+
+/// Parses a range the same way as `new semver.Range(range, { loose: true, includePrerelease })`.
+/// With `include_prerelease`, the lower bounds of partial versions are lowered to their smallest
+/// prerelease, so `1.x` becomes `>=1.0.0-0 <2.0.0-0` instead of `>=1.0.0 <2.0.0-0`.
+pub fn parse_range_with_options(
+    range: &str,
+    include_prerelease: bool,
+) -> Result<VersionConstraint, ParseRangeError> {
+    let mut set: Vec<Vec<Comparator>> = Vec::new();
+    for r in OR.split(range) {
+        let comps = parse_conjunction(js_compat::trim(r), include_prerelease)?;
+        // throw out any comparator lists that are empty, they were invalid in loose mode
+        if !comps.is_empty() {
+            set.push(comps);
+        }
+    }
+
+    if set.is_empty() {
+        return Err(ParseRangeError::Range(range.to_owned()));
+    }
+
+    // if we have any that are not the null set, throw out null sets
+    if set.len() > 1 {
+        let first = set[0].clone();
+        set.retain(|c| !c[0].is_null_set());
+        if set.is_empty() {
+            set = vec![first];
+        } else if set.len() > 1 {
+            // if we have any that are *, then the range is just *
+            if let Some(any) = set.iter().find(|c| c.len() == 1 && c[0].is_any()) {
+                set = vec![any.clone()];
+            }
+        }
+    }
+
+    Ok(VersionConstraint(
+        set.into_iter()
+            .map(|conjuncts| {
+                conjuncts
+                    .into_iter()
+                    .map(Comparator::into_version_comparator)
+                    .collect()
+            })
+            .collect(),
+    ))
+}
+
+/// Parses a vulnerable version range of a security advisory. Advisory ranges (from GHSA and OSV)
+/// separate comparators with commas, e.g. `>= 1.0.0, < 1.2`, which is the npm range
+/// `>= 1.0.0 < 1.2`. Partial versions get their npm meaning, so `< 1.2` becomes `<1.2.0-0`.
+pub fn parse_advisory_range(range: &str) -> Result<VersionConstraint, ParseRangeError> {
+    parse_range(&range.replace(',', " "))
+}
+
+/// `Range.prototype.parseRange`
+fn parse_conjunction(
+    range: &str,
+    include_prerelease: bool,
+) -> Result<Vec<Comparator>, ParseRangeError> {
+    let range = js_compat::trim(range);
+
+    // `1.2.3 - 1.2.4` => `>=1.2.3 <=1.2.4`
+    let range =
+        HYPHEN_RANGE_LOOSE.replace(range, |c: &Captures| hyphen_replace(c, include_prerelease));
+    // `> 1.2.3 < 1.2.5` => `>1.2.3 <1.2.5`
+    let range = COMPARATOR_TRIM.replace_all(&range, "${1}${2}${3}");
+    // `~ 1.2.3` => `~1.2.3`
+    let range = TILDE_TRIM.replace_all(&range, "${1}~");
+    // `^ 1.2.3` => `^1.2.3`
+    let range = CARET_TRIM.replace_all(&range, "${1}^");
+    // normalize spaces
+    let range = split_ws(&range).join(" ");
+
+    let desugared: Vec<String> = range
+        .split(' ')
+        .map(|comp| parse_comparator(comp, include_prerelease))
+        .collect();
+    let desugared = desugared.join(" ");
+
+    // >=0.0.0 is equivalent to *, and so is >=0.0.0-0 when prereleases are included
+    let gte0: &Regex = if include_prerelease { &GTE0_PRE } else { &GTE0 };
+    let parsed = split_ws(&desugared)
+        .into_iter()
+        .map(|comp| gte0.replace(js_compat::trim(comp), ""))
+        // in loose mode, throw out any that are not valid comparators
+        .filter(|comp| COMPARATOR_LOOSE.is_match(comp))
+        .map(|comp| Comparator::parse(&comp))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut comparators: Vec<Comparator> = Vec::new();
+    for comp in parsed {
+        // if any comparators are the null set, then replace with JUST null set
+        if comp.is_null_set() {
+            return Ok(vec![comp]);
+        }
+        // don't include the same comparator more than once, the last one wins
+        match comparators.iter_mut().find(|c| c.value == comp.value) {
+            Some(existing) => *existing = comp,
+            None => comparators.push(comp),
+        }
+    }
+
+    // if more than one comparator, remove any * comparators
+    if comparators.len() > 1 {
+        comparators.retain(|c| !c.is_any());
+    }
+
+    Ok(comparators)
+}
+
+/// Turns a (possibly) tilde, caret, x-range or star comparator into primitive comparators.
+fn parse_comparator(comp: &str, include_prerelease: bool) -> String {
+    let comp = replace_each(js_compat::trim(comp), |c| {
+        replace_caret(c, include_prerelease)
+    });
+    let comp = replace_each(js_compat::trim(&comp), replace_tilde);
+    let comp = replace_each(&comp, |c| {
+        replace_xrange(js_compat::trim(c), include_prerelease)
+    });
+    // Because * is AND-ed with everything else in the comparator,
+    // and '' means "any version", just remove the *s entirely.
+    STAR.replace(js_compat::trim(&comp), "").into_owned()
+}
+
+fn replace_each<F>(comp: &str, f: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    split_ws(comp)
+        .into_iter()
+        .map(f)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn caps<'t>(c: &Captures<'t>, i: usize) -> Option<&'t str> {
+    c.get(i).map(|m| m.as_str())
+}
+
+// ~, ~> --> * (any, kinda silly)
+// ~2, ~2.x, ~2.x.x, ~>2, ~>2.x ~>2.x.x --> >=2.0.0 <3.0.0-0
+// ~2.0, ~2.0.x, ~>2.0, ~>2.0.x --> >=2.0.0 <2.1.0-0
+// ~1.2, ~1.2.x, ~>1.2, ~>1.2.x --> >=1.2.0 <1.3.0-0
+// ~1.2.3, ~>1.2.3 --> >=1.2.3 <1.3.0-0
+// ~1.2.0, ~>1.2.0 --> >=1.2.0 <1.3.0-0
+fn replace_tilde(comp: &str) -> String {
+    TILDE_LOOSE
+        .replace(comp, |c: &Captures| {
+            let (maj, min, pat, pr) = (caps(c, 1), caps(c, 2), caps(c, 3), caps(c, 4));
+            if is_x(maj) {
+                String::new()
+            } else if is_x(min) {
+                let maj = maj.unwrap();
+                format!(">={}.0.0 <{}.0.0-0", maj, js_compat::increment(maj))
+            } else if is_x(pat) {
+                let (maj, min) = (maj.unwrap(), min.unwrap());
+                format!(
+                    ">={}.{}.0 <{}.{}.0-0",
+                    maj,
+                    min,
+                    maj,
+                    js_compat::increment(min)
+                )
+            } else {
+                let (maj, min, pat) = (maj.unwrap(), min.unwrap(), pat.unwrap());
+                let pr = match pr {
+                    Some(pr) if !pr.is_empty() => format!("-{}", pr),
+                    _ => String::new(),
+                };
+                format!(
+                    ">={}.{}.{}{} <{}.{}.0-0",
+                    maj,
+                    min,
+                    pat,
+                    pr,
+                    maj,
+                    js_compat::increment(min)
+                )
+            }
+        })
+        .into_owned()
+}
+
+// ^ --> * (any, kinda silly)
+// ^2, ^2.x, ^2.x.x --> >=2.0.0 <3.0.0-0
+// ^2.0, ^2.0.x --> >=2.0.0 <3.0.0-0
+// ^1.2, ^1.2.x --> >=1.2.0 <2.0.0-0
+// ^1.2.3 --> >=1.2.3 <2.0.0-0
+// ^1.2.0 --> >=1.2.0 <2.0.0-0
+// ^0.0.1 --> >=0.0.1 <0.0.2-0
+// ^0.1.0 --> >=0.1.0 <0.2.0-0
+// With include_prerelease, partial versions start at their smallest prerelease: ^2 --> >=2.0.0-0
+fn replace_caret(comp: &str, include_prerelease: bool) -> String {
+    let z = if include_prerelease { "-0" } else { "" };
+    CARET_LOOSE
+        .replace(comp, |c: &Captures| {
+            let (maj, min, pat, pr) = (caps(c, 1), caps(c, 2), caps(c, 3), caps(c, 4));
+            if is_x(maj) {
+                String::new()
+            } else if is_x(min) {
+                let maj = maj.unwrap();
+                format!(">={}.0.0{} <{}.0.0-0", maj, z, js_compat::increment(maj))
+            } else if is_x(pat) {
+                let (maj, min) = (maj.unwrap(), min.unwrap());
+                if maj == "0" {
+                    format!(
+                        ">={}.{}.0{} <{}.{}.0-0",
+                        maj,
+                        min,
+                        z,
+                        maj,
+                        js_compat::increment(min)
+                    )
+                } else {
+                    format!(
+                        ">={}.{}.0{} <{}.0.0-0",
+                        maj,
+                        min,
+                        z,
+                        js_compat::increment(maj)
+                    )
+                }
+            } else {
+                let (maj, min, pat) = (maj.unwrap(), min.unwrap(), pat.unwrap());
+                let from = match pr {
+                    Some(pr) if !pr.is_empty() => format!(">={}.{}.{}-{}", maj, min, pat, pr),
+                    _ => format!(">={}.{}.{}", maj, min, pat),
+                };
+                let to = if maj == "0" {
+                    if min == "0" {
+                        format!("<{}.{}.{}-0", maj, min, js_compat::increment(pat))
+                    } else {
+                        format!("<{}.{}.0-0", maj, js_compat::increment(min))
+                    }
+                } else {
+                    format!("<{}.0.0-0", js_compat::increment(maj))
+                };
+                format!("{} {}", from, to)
+            }
+        })
+        .into_owned()
+}
+
+fn replace_xrange(comp: &str, include_prerelease: bool) -> String {
+    XRANGE_LOOSE
+        .replace(comp, |c: &Captures| {
+            let whole = &c[0];
+            let mut gtlt = caps(c, 1).unwrap_or("");
+            let (maj, min, pat) = (caps(c, 2), caps(c, 3), caps(c, 4));
+
+            let x_maj = is_x(maj);
+            let x_min = x_maj || is_x(min);
+            let x_pat = x_min || is_x(pat);
+            let any_x = x_pat;
+
+            if gtlt == "=" && any_x {
+                gtlt = "";
+            }
+            let pr = if include_prerelease { "-0" } else { "" };
+
+            if x_maj {
+                if gtlt == ">" || gtlt == "<" {
+                    // nothing is allowed
+                    "<0.0.0-0".to_owned()
+                } else {
+                    // nothing is forbidden
+                    "*".to_owned()
+                }
+            } else if !gtlt.is_empty() && any_x {
+                // we know patch is an x, because we have any x at all.
+                // replace X with 0
+                let mut maj = maj.unwrap().to_owned();
+                let mut min = if x_min {
+                    "0".to_owned()
+                } else {
+                    min.unwrap().to_owned()
+                };
+                let pat = "0";
+
+                if gtlt == ">" {
+                    // >1 => >=2.0.0
+                    // >1.2 => >=1.3.0
+                    gtlt = ">=";
+                    if x_min {
+                        maj = js_compat::increment(&maj);
+                        min = "0".to_owned();
+                    } else {
+                        min = js_compat::increment(&min);
+                    }
+                } else if gtlt == "<=" {
+                    // <=0.7.x is actually <0.8.0, since any 0.7.x should
+                    // pass.  Similarly, <=7.x is actually <8.0.0, etc.
+                    gtlt = "<";
+                    if x_min {
+                        maj = js_compat::increment(&maj);
+                    } else {
+                        min = js_compat::increment(&min);
+                    }
+                }
+
+                let pr = if gtlt == "<" { "-0" } else { pr };
+                format!("{}{}.{}.{}{}", gtlt, maj, min, pat, pr)
+            } else if x_min {
+                let maj = maj.unwrap();
+                format!(">={}.0.0{} <{}.0.0-0", maj, pr, js_compat::increment(maj))
+            } else if x_pat {
+                let (maj, min) = (maj.unwrap(), min.unwrap());
+                format!(
+                    ">={}.{}.0{} <{}.{}.0-0",
+                    maj,
+                    min,
+                    pr,
+                    maj,
+                    js_compat::increment(min)
+                )
+            } else {
+                whole.to_owned()
+            }
+        })
+        .into_owned()
+}
+
+// 1.2 - 3.4.5 => >=1.2.0 <=3.4.5
+// 1.2.3 - 3.4 => >=1.2.0 <3.5.0-0 Any 3.4.x will do
+// 1.2 - 3.4 => >=1.2.0 <3.5.0-0
+// With include_prerelease, 1.2.3 - 3.4.5 => >=1.2.3-0 <3.4.6-0
+fn hyphen_replace(c: &Captures, include_prerelease: bool) -> String {
+    let (from, f_maj, f_min, f_pat, f_pr) =
+        (caps(c, 1), caps(c, 2), caps(c, 3), caps(c, 4), caps(c, 5));
+    let (to, t_maj, t_min, t_pat, t_pr) =
+        (caps(c, 7), caps(c, 8), caps(c, 9), caps(c, 10), caps(c, 11));
+    let z = if include_prerelease { "-0" } else { "" };
+
+    let from = if is_x(f_maj) {
+        String::new()
+    } else if is_x(f_min) {
+        format!(">={}.0.0{}", f_maj.unwrap(), z)
+    } else if is_x(f_pat) {
+        format!(">={}.{}.0{}", f_maj.unwrap(), f_min.unwrap(), z)
+    } else if f_pr.is_some_and(|pr| !pr.is_empty()) {
+        format!(">={}", from.unwrap())
+    } else {
+        format!(">={}{}", from.unwrap(), z)
+    };
+
+    let to = if is_x(t_maj) {
+        String::new()
+    } else if is_x(t_min) {
+        format!("<{}.0.0-0", js_compat::increment(t_maj.unwrap()))
+    } else if is_x(t_pat) {
+        format!(
+            "<{}.{}.0-0",
+            t_maj.unwrap(),
+            js_compat::increment(t_min.unwrap())
+        )
+    } else if let Some(pr) = t_pr.filter(|pr| !pr.is_empty()) {
+        format!(
+            "<={}.{}.{}-{}",
+            t_maj.unwrap(),
+            t_min.unwrap(),
+            t_pat.unwrap(),
+            pr
+        )
+    } else if include_prerelease {
+        format!(
+            "<{}.{}.{}-0",
+            t_maj.unwrap(),
+            t_min.unwrap(),
+            js_compat::increment(t_pat.unwrap())
+        )
+    } else {
+        format!("<={}", to.unwrap())
+    };
+
+    js_compat::trim(&format!("{} {}", from, to)).to_owned()
+}
@@ -0,0 +1,438 @@
+//! Evaluating `VersionConstraint`s the way node-semver does: `satisfies`, `maxSatisfying`
+//! and `minSatisfying`, plus checking whether two constraints intersect or one is a subset
+//! of the other.
+//!
+//! Without `include_prerelease`, a prerelease version only satisfies a conjunction if one of its
+//! comparators has a prerelease on the same `major.minor.patch` tuple. Passing `include_prerelease`
+//! here only relaxes that rule: like node-semver, ranges such as `1.x` only include the prereleases
+//! of their lower bound (`>=1.0.0-0`) if they were parsed with the same option, so parse them with
+//! `parse_range_with_options(range, true)` to get node-semver's results.
+//!
+//! `intersects` and `is_subset` are exact: they treat each conjunction as an interval in npm's
+//! version order, restricted to release versions and to the prereleases it allows.
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use postgres_db::custom_types::{PrereleaseTag, Semver, VersionComparator, VersionConstraint};
+
+/// Compares two versions by npm's precedence rules. Build metadata is ignored.
+pub fn compare_versions(a: &Semver, b: &Semver) -> Ordering {
+    a.major
+        .cmp(&b.major)
+        .then(a.minor.cmp(&b.minor))
+        .then(a.bug.cmp(&b.bug))
+        .then_with(|| compare_prerelease(&a.prerelease, &b.prerelease))
+}
+
+fn compare_prerelease(a: &[PrereleaseTag], b: &[PrereleaseTag]) -> Ordering {
+    // a version without a prerelease is greater than one with
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        (false, false) => {}
+    }
+
+    for (x, y) in a.iter().zip(b.iter()) {
+        match compare_identifiers(x, y) {
+            Ordering::Equal => continue,
+            ord => return ord,
+        }
+    }
+    // a shorter prerelease is smaller, if all preceding identifiers are equal
+    a.len().cmp(&b.len())
+}
+
+fn compare_identifiers(a: &PrereleaseTag, b: &PrereleaseTag) -> Ordering {
+    // numeric identifiers too large for a JavaScript number are stored as strings
+    fn as_numeric(t: &PrereleaseTag) -> Option<(usize, &str)> {
+        match t {
+            PrereleaseTag::Int(_) => None,
+            PrereleaseTag::String(s) if !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) => {
+                let digits = s.trim_start_matches('0');
+                Some((digits.len(), digits))
+            }
+            PrereleaseTag::String(_) => None,
+        }
+    }
+
+    match (a, b) {
+        (PrereleaseTag::Int(x), PrereleaseTag::Int(y)) => x.cmp(y),
+        // numeric identifiers are smaller than alphanumeric ones, and any numeric string is too large to be an Int
+        (PrereleaseTag::Int(_), PrereleaseTag::String(_)) => Ordering::Less,
+        (PrereleaseTag::String(_), PrereleaseTag::Int(_)) => Ordering::Greater,
+        (PrereleaseTag::String(x), PrereleaseTag::String(y)) => {
+            match (as_numeric(a), as_numeric(b)) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => x.cmp(y),
+            }
+        }
+    }
+}
+
+fn test_comparator(comp: &VersionComparator, v: &Semver) -> bool {
+    match comp {
+        VersionComparator::Any => true,
+        VersionComparator::Eq(c) => compare_versions(v, c) == Ordering::Equal,
+        VersionComparator::Gt(c) => compare_versions(v, c) == Ordering::Greater,
+        VersionComparator::Gte(c) => compare_versions(v, c) != Ordering::Less,
+        VersionComparator::Lt(c) => compare_versions(v, c) == Ordering::Less,
+        VersionComparator::Lte(c) => compare_versions(v, c) != Ordering::Greater,
+    }
+}
+
+fn comparator_version(comp: &VersionComparator) -> Option<&Semver> {
+    match comp {
+        VersionComparator::Any => None,
+        VersionComparator::Eq(v)
+        | VersionComparator::Gt(v)
+        | VersionComparator::Gte(v)
+        | VersionComparator::Lt(v)
+        | VersionComparator::Lte(v) => Some(v),
+    }
+}
+
+fn same_tuple(a: &Semver, b: &Semver) -> bool {
+    a.major == b.major && a.minor == b.minor && a.bug == b.bug
+}
+
+/// node-semver's `testSet`
+fn test_conjunction(conj: &[VersionComparator], v: &Semver, include_prerelease: bool) -> bool {
+    if !conj.iter().all(|c| test_comparator(c, v)) {
+        return false;
+    }
+
+    if !v.prerelease.is_empty() && !include_prerelease {
+        // Find the set of versions that are allowed to have prereleases.
+        // For example, ^1.2.3-pr.1 desugars to >=1.2.3-pr.1 <2.0.0
+        // That should allow `1.2.3-pr.2` to pass.
+        // However, `1.2.4-alpha.notready` should NOT be allowed,
+        // even though it's within the range set by the comparators.
+        return conj
+            .iter()
+            .filter_map(comparator_version)
+            .any(|allowed| !allowed.prerelease.is_empty() && same_tuple(allowed, v));
+    }
+
+    true
+}
+
+/// Returns true if `v` satisfies `constraint`, like `semver.satisfies(v, range)`. The constraint
+/// should be parsed with the same `include_prerelease`, see the module docs.
+pub fn satisfies(constraint: &VersionConstraint, v: &Semver, include_prerelease: bool) -> bool {
+    constraint
+        .0
+        .iter()
+        .any(|conj| test_conjunction(conj, v, include_prerelease))
+}
+
+/// Returns the greatest of `versions` that satisfies `constraint`, like `semver.maxSatisfying`.
+/// If several versions are equal (i.e. they only differ in build metadata), the first one is returned.
+pub fn max_satisfying<'a, I>(
+    versions: I,
+    constraint: &VersionConstraint,
+    include_prerelease: bool,
+) -> Option<&'a Semver>
+where
+    I: IntoIterator<Item = &'a Semver>,
+{
+    best_satisfying(versions, constraint, include_prerelease, Ordering::Greater)
+}
+
+/// Returns the least of `versions` that satisfies `constraint`, like `semver.minSatisfying`.
+/// If several versions are equal (i.e. they only differ in build metadata), the first one is returned.
+pub fn min_satisfying<'a, I>(
+    versions: I,
+    constraint: &VersionConstraint,
+    include_prerelease: bool,
+) -> Option<&'a Semver>
+where
+    I: IntoIterator<Item = &'a Semver>,
+{
+    best_satisfying(versions, constraint, include_prerelease, Ordering::Less)
+}
+
+fn best_satisfying<'a, I>(
+    versions: I,
+    constraint: &VersionConstraint,
+    include_prerelease: bool,
+    better: Ordering,
+) -> Option<&'a Semver>
+where
+    I: IntoIterator<Item = &'a Semver>,
+{
+    let mut best: Option<&'a Semver> = None;
+    for v in versions {
+        if !satisfies(constraint, v, include_prerelease) {
+            continue;
+        }
+        match best {
+            Some(b) if compare_versions(v, b) != better => {}
+            _ => best = Some(v),
+        }
+    }
+    best
+}
+
+/// Returns true if some version satisfies both `a` and `b`.
+pub fn intersects(a: &VersionConstraint, b: &VersionConstraint, include_prerelease: bool) -> bool {
+    a.0.iter().any(|conj_a| {
+        b.0.iter().any(|conj_b| {
+            let interval = Interval::of_conjunction(conj_a.iter().chain(conj_b.iter()));
+            if include_prerelease {
+                return !interval.is_empty();
+            }
+            if !interval.release_versions().is_empty() {
+                return true;
+            }
+            let tuples_b = prerelease_tuples(conj_b);
+            prerelease_tuples(conj_a)
+                .intersection(&tuples_b)
+                .any(|t| !interval.prereleases_of(*t).is_empty())
+        })
+    })
+}
+
+/// Returns true if every version that satisfies `sub` also satisfies `dom`.
+pub fn is_subset(
+    sub: &VersionConstraint,
+    dom: &VersionConstraint,
+    include_prerelease: bool,
+) -> bool {
+    let dom_conjs: Vec<(Interval, BTreeSet<Tuple>)> = dom
+        .0
+        .iter()
+        .map(|conj| (Interval::of_conjunction(conj), prerelease_tuples(conj)))
+        .collect();
+
+    sub.0.iter().all(|conj| {
+        let interval = Interval::of_conjunction(conj);
+
+        if include_prerelease {
+            let pieces = dom_conjs.iter().map(|(i, _)| i.clone()).collect();
+            return covers(interval, pieces, successor);
+        }
+
+        let release_pieces = dom_conjs
+            .iter()
+            .map(|(i, _)| i.release_versions())
+            .collect();
+        if !covers(
+            interval.release_versions(),
+            release_pieces,
+            release_successor,
+        ) {
+            return false;
+        }
+
+        prerelease_tuples(conj).into_iter().all(|t| {
+            let pieces = dom_conjs
+                .iter()
+                .filter(|(_, tuples)| tuples.contains(&t))
+                .map(|(i, _)| i.prereleases_of(t))
+                .collect();
+            covers(interval.prereleases_of(t), pieces, successor)
+        })
+    })
+}
+
+type Tuple = (i64, i64, i64);
+
+/// The tuples on which a conjunction allows prereleases.
+fn prerelease_tuples(conj: &[VersionComparator]) -> BTreeSet<Tuple> {
+    conj.iter()
+        .filter_map(comparator_version)
+        .filter(|v| !v.prerelease.is_empty())
+        .map(|v| (v.major, v.minor, v.bug))
+        .collect()
+}
+
+fn release(t: Tuple) -> Semver {
+    Semver {
+        major: t.0,
+        minor: t.1,
+        bug: t.2,
+        prerelease: vec![],
+        build: vec![],
+    }
+}
+
+fn smallest_prerelease(t: Tuple) -> Semver {
+    Semver {
+        prerelease: vec![PrereleaseTag::Int(0)],
+        ..release(t)
+    }
+}
+
+/// The smallest version greater than `v`.
+fn successor(v: &Semver) -> Semver {
+    if v.prerelease.is_empty() {
+        // the next version after 1.2.3 is 1.2.4-0
+        smallest_prerelease((v.major, v.minor, v.bug + 1))
+    } else {
+        // the next version after 1.2.3-alpha is 1.2.3-alpha.0
+        let mut prerelease = v.prerelease.clone();
+        prerelease.push(PrereleaseTag::Int(0));
+        Semver {
+            prerelease,
+            build: vec![],
+            ..v.clone()
+        }
+    }
+}
+
+/// The smallest release version greater than the release `v`.
+fn release_successor(v: &Semver) -> Semver {
+    release((v.major, v.minor, v.bug + 1))
+}
+
+/// A set of versions between an inclusive lower bound and an optional upper bound,
+/// in npm's version order. Versions never have build metadata, since it doesn't affect ordering.
+#[derive(Debug, Clone)]
+struct Interval {
+    lower: Semver,
+    /// `None` if unbounded, otherwise the bound and whether it is inclusive.
+    upper: Option<(Semver, bool)>,
+}
+
+impl Interval {
+    fn of_conjunction<'a, I>(comparators: I) -> Interval
+    where
+        I: IntoIterator<Item = &'a VersionComparator>,
+    {
+        // 0.0.0-0 is the smallest version
+        let mut lower = smallest_prerelease((0, 0, 0));
+        let mut upper: Option<(Semver, bool)> = None;
+
+        for comp in comparators {
+            let (lower_bound, upper_bound) = match comp {
+                VersionComparator::Any => (None, None),
+                VersionComparator::Eq(v) => {
+                    (Some(without_build(v)), Some((without_build(v), true)))
+                }
+                VersionComparator::Gt(v) => (Some(successor(&without_build(v))), None),
+                VersionComparator::Gte(v) => (Some(without_build(v)), None),
+                VersionComparator::Lt(v) => (None, Some((without_build(v), false))),
+                VersionComparator::Lte(v) => (None, Some((without_build(v), true))),
+            };
+
+            if let Some(l) = lower_bound {
+                if compare_versions(&l, &lower) == Ordering::Greater {
+                    lower = l;
+                }
+            }
+            if let Some(u) = upper_bound {
+                if upper.as_ref().is_none_or(|cur| upper_is_tighter(&u, cur)) {
+                    upper = Some(u);
+                }
+            }
+        }
+
+        Interval { lower, upper }
+    }
+
+    fn is_below_upper(&self, v: &Semver) -> bool {
+        match &self.upper {
+            None => true,
+            Some((u, inclusive)) => match compare_versions(v, u) {
+                Ordering::Less => true,
+                Ordering::Equal => *inclusive,
+                Ordering::Greater => false,
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.is_below_upper(&self.lower)
+    }
+
+    /// The release versions in this interval, with bounds that are releases.
+    fn release_versions(&self) -> Interval {
+        let lower = release((self.lower.major, self.lower.minor, self.lower.bug));
+        let upper = self.upper.as_ref().map(|(u, inclusive)| {
+            // below 1.2.3-alpha means below 1.2.3
+            (
+                release((u.major, u.minor, u.bug)),
+                *inclusive && u.prerelease.is_empty(),
+            )
+        });
+        Interval { lower, upper }
+    }
+
+    /// The prereleases of the tuple `t` in this interval.
+    fn prereleases_of(&self, t: Tuple) -> Interval {
+        let mut restricted = Interval::of_conjunction(&[
+            VersionComparator::Gte(smallest_prerelease(t)),
+            VersionComparator::Lt(release(t)),
+        ]);
+        if compare_versions(&self.lower, &restricted.lower) == Ordering::Greater {
+            restricted.lower = self.lower.clone();
+        }
+        if let Some(u) = &self.upper {
+            if upper_is_tighter(u, restricted.upper.as_ref().unwrap()) {
+                restricted.upper = Some(u.clone());
+            }
+        }
+        restricted
+    }
+
+    /// Returns true if the upper bound of `self` is at most that of `other`.
+    fn upper_within(&self, other: &Interval) -> bool {
+        match (&self.upper, &other.upper) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(a), Some(b)) => !upper_is_tighter(b, a),
+        }
+    }
+}
+
+fn without_build(v: &Semver) -> Semver {
+    Semver {
+        build: vec![],
+        ..v.clone()
+    }
+}
+
+/// Returns true if the upper bound `a` excludes more versions than `b`.
+fn upper_is_tighter(a: &(Semver, bool), b: &(Semver, bool)) -> bool {
+    match compare_versions(&a.0, &b.0) {
+        Ordering::Less => true,
+        Ordering::Equal => !a.1 && b.1,
+        Ordering::Greater => false,
+    }
+}
+
+/// Returns true if `target` is contained in the union of `pieces`.
+/// All the intervals must live in a space where `succ` gives the next version.
+fn covers(target: Interval, pieces: Vec<Interval>, succ: fn(&Semver) -> Semver) -> bool {
+    // the smallest version in target that we don't know to be covered yet
+    let mut next = target.lower.clone();
+    // in a discrete space, the next version after a piece may already be past the end of target
+    while target.is_below_upper(&next) {
+        // of the pieces containing `next`, pick the one that reaches the furthest
+        let best = pieces
+            .iter()
+            .filter(|p| {
+                compare_versions(&p.lower, &next) != Ordering::Greater && p.is_below_upper(&next)
+            })
+            .reduce(|a, b| if b.upper_within(a) { a } else { b });
+
+        let best = match best {
+            None => return false,
+            Some(b) => b,
+        };
+        if target.upper_within(best) {
+            return true;
+        }
+
+        next = match &best.upper {
+            None => unreachable!(),
+            Some((u, true)) => succ(u),
+            Some((u, false)) => u.clone(),
+        };
+    }
+    true
+}
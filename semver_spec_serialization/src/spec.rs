@@ -0,0 +1,335 @@
+//! A port of npm-package-arg's (9.1.0) `resolve`, plus the mapping from its results
+//! to `ParsedSpec` that `js_parser/index.js` does. Errors are turned into
+//! `ParsedSpec::Invalid` with the same `"{code}: {message}"` format as the daemon.
+
+use lazy_regex::regex_is_match;
+
+use postgres_db::custom_types::{AliasSubspec, ParsedSpec, VersionConstraint};
+
+use crate::hosted_git;
+use crate::js_compat;
+use crate::range::parse_range;
+
+/// An error thrown by npm-package-arg. `code` is `undefined` for some of them.
+struct NpaError {
+    code: Option<&'static str>,
+    message: String,
+}
+
+impl NpaError {
+    fn new<S: Into<String>>(message: S) -> NpaError {
+        NpaError {
+            code: None,
+            message: message.into(),
+        }
+    }
+
+    fn with_code<S: Into<String>>(code: &'static str, message: S) -> NpaError {
+        NpaError {
+            code: Some(code),
+            message: message.into(),
+        }
+    }
+
+    fn into_invalid(self) -> ParsedSpec {
+        ParsedSpec::Invalid(format!(
+            "{}: {}",
+            self.code.unwrap_or("undefined"),
+            self.message
+        ))
+    }
+}
+
+/// A registry spec, which is all an alias may point to.
+enum RegistrySpec {
+    Range(VersionConstraint),
+    Tag(String),
+}
+
+enum NpaResult {
+    Registry {
+        name: Option<String>,
+        spec: RegistrySpec,
+    },
+    Alias {
+        sub_name: Option<String>,
+        sub_spec: RegistrySpec,
+    },
+    Git(String),
+    Remote(String),
+    File(String),
+    Directory(String),
+}
+
+/// Parses a dependency spec (the right hand side of an entry in `dependencies`)
+/// the same way that `npa.resolve(name, spec)` does.
+pub fn parse_spec(s: &str) -> ParsedSpec {
+    match resolve(js_compat::trim(s)) {
+        Ok(NpaResult::Registry { spec, .. }) => registry_to_parsed(spec),
+        Ok(NpaResult::Alias {
+            sub_name: None,
+            sub_spec,
+        }) => registry_to_parsed(sub_spec),
+        Ok(NpaResult::Alias {
+            sub_name: Some(sub_name),
+            sub_spec,
+        }) => {
+            let sub = match sub_spec {
+                RegistrySpec::Range(r) => AliasSubspec::Range(r),
+                RegistrySpec::Tag(t) => AliasSubspec::Tag(t),
+            };
+            ParsedSpec::Alias(sub_name, None, sub)
+        }
+        Ok(NpaResult::Git(s)) => ParsedSpec::Git(s),
+        Ok(NpaResult::Remote(s)) => ParsedSpec::Remote(s),
+        Ok(NpaResult::File(s)) => ParsedSpec::File(s),
+        Ok(NpaResult::Directory(s)) => ParsedSpec::Directory(s),
+        Err(e) => e.into_invalid(),
+    }
+}
+
+fn registry_to_parsed(spec: RegistrySpec) -> ParsedSpec {
+    match spec {
+        RegistrySpec::Range(r) => ParsedSpec::Range(r),
+        RegistrySpec::Tag(t) => ParsedSpec::Tag(t),
+    }
+}
+
+fn is_filespec(spec: &str) -> bool {
+    regex_is_match!(r"^(?:[.]|~[/]|[/]|[a-zA-Z]:)", spec) || regex_is_match!(r"^file:"i, spec)
+}
+
+fn is_filename(spec: &str) -> bool {
+    regex_is_match!(r"[.](?:tgz|tar.gz|tar)$"i, spec)
+}
+
+fn is_url(spec: &str) -> bool {
+    regex_is_match!(r"^(?:git[+])?[a-z]+:"i, spec)
+}
+
+fn is_git(spec: &str) -> bool {
+    regex_is_match!(r"^[^@]+@[^:.]+\.[^:]+:.+$"i, spec)
+}
+
+/// `npa.resolve(name, spec)`, for a name that is known to be valid.
+fn resolve(spec: &str) -> Result<NpaResult, NpaError> {
+    resolve_named(None, spec)
+}
+
+fn resolve_named(name: Option<String>, spec: &str) -> Result<NpaResult, NpaError> {
+    if let Some(n) = &name {
+        validate_package_name(n)?;
+    }
+
+    if is_filespec(spec) {
+        return Ok(from_file(spec));
+    } else if regex_is_match!(r"^npm:"i, spec) {
+        return from_alias(&spec[4..]);
+    }
+
+    if let Some(hosted) = hosted_git::from_url(spec) {
+        check_git_committish(hosted.committish.as_deref())?;
+        Ok(NpaResult::Git(hosted.to_string()))
+    } else if is_url(spec) {
+        from_url(spec)
+    } else if spec.contains('/') || is_filename(spec) {
+        Ok(from_file(spec))
+    } else {
+        from_registry(name, spec)
+    }
+}
+
+/// `npa(arg)`, which splits `arg` into a name and spec before resolving them.
+fn npa(arg: &str) -> Result<NpaResult, NpaError> {
+    let name_ends_at = match arg.strip_prefix('@') {
+        Some(rest) => rest.find('@').map(|i| i + 1),
+        None => arg.find('@'),
+    };
+    let name_ends_at = name_ends_at.filter(|i| *i > 0);
+    let name_part = match name_ends_at {
+        Some(i) => &arg[..i],
+        None => arg,
+    };
+
+    if is_url(arg) {
+        resolve_named(None, arg)
+    } else if is_git(arg) {
+        resolve_named(None, &format!("git+ssh://{}", arg))
+    } else if !name_part.starts_with('@') && (name_part.contains('/') || is_filename(name_part)) {
+        resolve_named(None, arg)
+    } else if let Some(i) = name_ends_at {
+        resolve_named(Some(name_part.to_owned()), &arg[i + 1..])
+    } else if validate_package_name(arg).is_ok() {
+        resolve_named(Some(arg.to_owned()), "")
+    } else {
+        resolve_named(None, arg)
+    }
+}
+
+fn from_file(spec: &str) -> NpaResult {
+    let path = spec.strip_prefix("file:").unwrap_or(spec).to_owned();
+    if is_filename(spec) {
+        NpaResult::File(path)
+    } else {
+        NpaResult::Directory(path)
+    }
+}
+
+fn from_alias(rest: &str) -> Result<NpaResult, NpaError> {
+    match npa(rest)? {
+        NpaResult::Alias { .. } => Err(NpaError::new("nested aliases not supported")),
+        NpaResult::Registry { name, spec } => Ok(NpaResult::Alias {
+            sub_name: name,
+            sub_spec: spec,
+        }),
+        _ => Err(NpaError::new("aliases only work for registry deps")),
+    }
+}
+
+fn from_url(spec: &str) -> Result<NpaResult, NpaError> {
+    // what `url.parse` considers the protocol, given that `spec` matches `is_url`
+    let protocol = spec[..spec.find(':').unwrap() + 1].to_ascii_lowercase();
+    match protocol.as_str() {
+        "git:" | "git+http:" | "git+https:" | "git+rsync:" | "git+ftp:" | "git+file:"
+        | "git+ssh:" => {
+            let committish = spec.find('#').map(|i| &spec[i + 1..]);
+            check_git_committish(committish)?;
+            Ok(NpaResult::Git(spec.to_owned()))
+        }
+        "http:" | "https:" => Ok(NpaResult::Remote(spec.to_owned())),
+        _ => Err(NpaError::with_code(
+            "EUNSUPPORTEDPROTOCOL",
+            format!("Unsupported URL Type \"{}\": {}", protocol, spec),
+        )),
+    }
+}
+
+/// `setGitCommittish`, which parses `::` separated committish, `semver:` and `path:` items.
+/// We only need to know whether it throws.
+fn check_git_committish(committish: Option<&str>) -> Result<(), NpaError> {
+    let committish = match committish {
+        Some(c) if !c.is_empty() => c,
+        _ => return Ok(()),
+    };
+
+    // JavaScript truthiness, so empty strings count as unset
+    let mut has_committish = false;
+    let mut has_range = false;
+    let mut has_subdir = false;
+
+    for part in committish.split("::") {
+        let (name, value) = match part.split_once(':') {
+            None => {
+                if has_range {
+                    return Err(NpaError::new(
+                        "cannot override existing semver range with a committish",
+                    ));
+                }
+                if has_committish {
+                    return Err(NpaError::new(
+                        "cannot override existing committish with a second committish",
+                    ));
+                }
+                has_committish = !part.is_empty();
+                continue;
+            }
+            Some((name, rest)) => (name, rest.split(':').next().unwrap()),
+        };
+
+        if name == "semver" {
+            if has_committish {
+                return Err(NpaError::new(
+                    "cannot override existing committish with a semver range",
+                ));
+            }
+            if has_range {
+                return Err(NpaError::new(
+                    "cannot override existing semver range with a second semver range",
+                ));
+            }
+            let range = js_compat::decode_uri_component(value)
+                .ok_or_else(|| NpaError::new("URI malformed"))?;
+            has_range = !range.is_empty();
+        } else if name == "path" {
+            if has_subdir {
+                return Err(NpaError::new(
+                    "cannot override existing path with a second path",
+                ));
+            }
+            has_subdir = true;
+        }
+        // other keys are ignored with a warning
+    }
+    Ok(())
+}
+
+fn from_registry(name: Option<String>, raw_spec: &str) -> Result<NpaResult, NpaError> {
+    let spec = if raw_spec.is_empty() {
+        "latest"
+    } else {
+        js_compat::trim(raw_spec)
+    };
+
+    let spec = match parse_range(spec) {
+        Ok(r) => RegistrySpec::Range(r),
+        Err(_) => {
+            if !js_compat::is_uri_component_safe(spec) {
+                return Err(NpaError::with_code(
+                    "EINVALIDTAGNAME",
+                    format!(
+                        "Invalid tag name \"{}\": Tags may not have any characters that encodeURIComponent encodes.",
+                        spec
+                    ),
+                ));
+            }
+            RegistrySpec::Tag(spec.to_owned())
+        }
+    };
+
+    Ok(NpaResult::Registry { name, spec })
+}
+
+/// The errors (but not warnings) of validate-npm-package-name (4.0.0).
+fn validate_package_name(name: &str) -> Result<(), NpaError> {
+    let mut errors = vec![];
+
+    if name.is_empty() {
+        errors.push("name length must be greater than zero");
+    }
+    if name.starts_with('.') {
+        errors.push("name cannot start with a period");
+    }
+    if name.starts_with('_') {
+        errors.push("name cannot start with an underscore");
+    }
+    if js_compat::trim(name) != name {
+        errors.push("name cannot contain leading or trailing spaces");
+    }
+    let lower = name.to_lowercase();
+    if lower == "node_modules" {
+        errors.push("node_modules is a blacklisted name");
+    }
+    if lower == "favicon.ico" {
+        errors.push("favicon.ico is a blacklisted name");
+    }
+    if !js_compat::is_uri_component_safe(name) {
+        let scoped_ok = lazy_regex::regex_captures!(r"^(?:@([^/]+?)[/])?([^/]+?)$", name)
+            .is_some_and(|(_, user, pkg)| {
+                !user.is_empty()
+                    && js_compat::is_uri_component_safe(user)
+                    && js_compat::is_uri_component_safe(pkg)
+            });
+        if !scoped_ok {
+            errors.push("name can only contain URL-friendly characters");
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(NpaError::with_code(
+            "EINVALIDPACKAGENAME",
+            format!("Invalid package name \"{}\": {}", name, errors.join("; ")),
+        ))
+    }
+}
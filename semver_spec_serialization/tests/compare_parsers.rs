@@ -0,0 +1,68 @@
+//! Differential tests of `parse_spec` against the node parser, on specs built from
+//! the characters that matter to range parsing.
+#![cfg(feature = "node-parser")]
+
+use postgres_db::custom_types::ParsedSpec;
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+use semver_spec_serialization::{parse_spec, parse_spec_via_node};
+
+const PIECES: &[&str] = &[
+    "0",
+    "1",
+    "2",
+    "01",
+    "12345678901234567890",
+    ".",
+    "x",
+    "X",
+    "*",
+    "^",
+    "~",
+    "~>",
+    ">",
+    "<",
+    "=",
+    "-",
+    " ",
+    " - ",
+    " || ",
+    "v",
+    "a",
+    "rc",
+    "+",
+    "@",
+    "/",
+    ":",
+    "#",
+    "npm:",
+];
+
+#[derive(Debug, Clone)]
+struct RangeLikeSpec(String);
+
+impl Arbitrary for RangeLikeSpec {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let len = usize::arbitrary(g) % 12;
+        RangeLikeSpec(
+            (0..len)
+                .map(|_| *g.choose(PIECES).unwrap())
+                .collect::<String>(),
+        )
+    }
+}
+
+fn parsers_agree(spec: RangeLikeSpec) -> bool {
+    let native = parse_spec(&spec.0);
+    let node = parse_spec_via_node(&spec.0).unwrap();
+    match (&native, &node) {
+        (ParsedSpec::Invalid(_), ParsedSpec::Invalid(_)) => true,
+        (a, b) => a == b,
+    }
+}
+
+#[test]
+fn test_parse_spec_agrees_with_node() {
+    QuickCheck::new()
+        .tests(5000)
+        .quickcheck(parsers_agree as fn(RangeLikeSpec) -> bool);
+}
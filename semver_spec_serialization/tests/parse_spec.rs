@@ -4,6 +4,8 @@ extern crate lazy_static;
 use postgres_db::custom_types::{
     AliasSubspec, ParsedSpec, PrereleaseTag, Semver, VersionComparator, VersionConstraint,
 };
+use semver_spec_serialization::parse_spec;
+#[cfg(feature = "node-parser")]
 use semver_spec_serialization::parse_spec_via_node;
 
 lazy_static! {
@@ -222,6 +224,26 @@ lazy_static! {
     ];
 }
 
+#[test]
+fn test_parse_spec_success_cases() {
+    for (input, answer) in SUCCESS_CASES.iter() {
+        println!("testing {}", input);
+        assert_eq!(parse_spec(input), *answer)
+    }
+}
+
+#[test]
+fn test_parse_spec_invalid_cases() {
+    for (input, err_contains) in INVALID_CASES.iter() {
+        println!("testing {}", input);
+        match parse_spec(input) {
+            ParsedSpec::Invalid(err) => assert!(err.contains(err_contains)),
+            _ => panic!(),
+        }
+    }
+}
+
+#[cfg(feature = "node-parser")]
 #[test]
 fn test_parse_spec_via_node_success_cases() {
     for (input, answer) in SUCCESS_CASES.iter() {
@@ -230,6 +252,7 @@ fn test_parse_spec_via_node_success_cases() {
     }
 }
 
+#[cfg(feature = "node-parser")]
 #[test]
 fn test_parse_spec_via_node_invalid_cases() {
     for (input, err_contains) in INVALID_CASES.iter() {
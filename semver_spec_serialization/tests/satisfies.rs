@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+
+use postgres_db::custom_types::{Semver, VersionConstraint};
+
+use test_case::test_case;
+
+use semver_spec_serialization::{
+    compare_versions, intersects, is_subset, max_satisfying, min_satisfying, parse_range,
+    parse_range_with_options, parse_semver, satisfies,
+};
+
+#[test_case("^1.2.3", "1.5.0", false, true)]
+#[test_case("^1.2.3", "2.0.0", false, false)]
+#[test_case("^1.2.3", "1.5.0-beta", false, false ; "prerelease on other tuple")]
+#[test_case("^1.2.3", "1.5.0-beta", true, true ; "prerelease on other tuple with include_prerelease")]
+#[test_case("^1.2.3-beta.1", "1.2.3-beta.2", false, true ; "prerelease on same tuple")]
+#[test_case("^1.2.3-beta.1", "1.2.4-beta", false, false)]
+#[test_case("^1.2.3", "2.0.0-0", true, false)]
+#[test_case("*", "1.0.0-rc.1", false, false)]
+#[test_case("*", "1.0.0-rc.1", true, true)]
+#[test_case("~1.2", "1.3.0", false, false)]
+#[test_case("1.2.3 - 2.3.4", "2.3.4+build", false, true)]
+#[test_case("1.2.3", "1.2.3+build.5", false, true)]
+#[test_case(">=1.0.0 <1.1.0 || >=2.0.0", "2.5.0", false, true)]
+#[test_case(">=1.0.0 <1.1.0 || >=2.0.0", "1.5.0", false, false)]
+// the answers of node-semver's `satisfies(v, range, { includePrerelease })`
+#[test_case("1.x", "1.0.0-beta", false, false ; "x-range below lower bound")]
+#[test_case("1.x", "1.0.0-beta", true, true ; "x-range lowered with include_prerelease")]
+#[test_case("1.2.x", "1.2.0-beta", true, true ; "patch x-range lowered with include_prerelease")]
+#[test_case("1.x", "2.0.0-0", true, false ; "x-range upper bound")]
+#[test_case(">=1.x", "1.0.0-beta", true, true ; "x-range comparator lowered")]
+#[test_case(">1", "2.0.0-beta", false, false ; "greater than major")]
+#[test_case(">1", "2.0.0-beta", true, true ; "greater than major with include_prerelease")]
+#[test_case("^1", "1.0.0-beta", true, true ; "partial caret lowered")]
+#[test_case("^1.2", "1.2.0-beta", true, true ; "partial caret with minor lowered")]
+#[test_case("^0.2", "0.2.0-beta", true, true ; "partial caret on major zero lowered")]
+#[test_case("^1.2.3", "1.2.3-beta", true, false ; "full caret not lowered")]
+#[test_case("1.2 - 2", "1.2.0-beta", true, true ; "partial hyphen range lowered")]
+#[test_case("1.2.3 - 2", "1.2.3-beta", true, true ; "full hyphen range lowered")]
+#[test_case("*", "0.0.0-beta", true, true ; "star with include_prerelease")]
+#[test_case(">=0.0.0", "0.0.0-beta", true, false ; "explicit lower bound not lowered")]
+fn test_satisfies(range: &str, v: &str, include_prerelease: bool, answer: bool) {
+    assert_eq!(
+        satisfies(
+            &range_with(range, include_prerelease),
+            &version(v),
+            include_prerelease
+        ),
+        answer
+    )
+}
+
+#[test]
+fn test_compare_versions() {
+    // the example from the semver spec
+    let ordered = [
+        "1.0.0-alpha",
+        "1.0.0-alpha.1",
+        "1.0.0-alpha.beta",
+        "1.0.0-beta",
+        "1.0.0-beta.2",
+        "1.0.0-beta.11",
+        "1.0.0-rc.1",
+        "1.0.0",
+        "1.0.1",
+        "1.1.0",
+        "2.0.0",
+    ];
+    for (i, a) in ordered.iter().enumerate() {
+        for (j, b) in ordered.iter().enumerate() {
+            assert_eq!(compare_versions(&version(a), &version(b)), i.cmp(&j));
+        }
+    }
+    assert_eq!(
+        compare_versions(&version("1.0.0+a"), &version("1.0.0+b")),
+        Ordering::Equal
+    );
+}
+
+#[test_case("^1.2.3", Some("1.3.0"), Some("1.2.3"))]
+#[test_case("~1.2.3", Some("1.2.4"), Some("1.2.3"))]
+#[test_case(">=1.3.0-beta", Some("2.0.0"), Some("1.3.0-beta"))]
+#[test_case("<1.0.0", None, None)]
+fn test_max_min_satisfying(range: &str, max: Option<&str>, min: Option<&str>) {
+    let versions: Vec<Semver> = ["1.2.3", "1.2.4", "1.3.0-beta", "1.3.0", "2.0.0"]
+        .iter()
+        .map(|v| version(v))
+        .collect();
+    let constraint = range_of(range);
+
+    assert_eq!(
+        max_satisfying(&versions, &constraint, false),
+        max.map(version).as_ref()
+    );
+    assert_eq!(
+        min_satisfying(&versions, &constraint, false),
+        min.map(version).as_ref()
+    );
+}
+
+#[test]
+fn test_max_satisfying_keeps_first_of_equal_versions() {
+    let versions = vec![version("1.0.0+a"), version("1.0.0+b")];
+    let best = max_satisfying(&versions, &range_of("1.x"), false).unwrap();
+    assert!(std::ptr::eq(best, &versions[0]));
+}
+
+#[test_case("^1.2.3", "~1.5.0", false, true)]
+#[test_case("^1.2.3", "^2.0.0", false, false)]
+#[test_case(">1.0.0 <1.0.1", "1.0.1-beta", false, false ; "prerelease only allowed by one side")]
+#[test_case(">1.0.0 <1.0.1", "1.0.1-beta", true, true ; "prerelease with include_prerelease")]
+#[test_case(">=1.2.3-alpha <1.2.3", "1.2.3-beta", false, true ; "prerelease allowed by both sides")]
+#[test_case("<=1.0.0 || >=3.0.0", "2.x || 3.1.x", false, true)]
+#[test_case("<1.0.0", "1.x", false, false)]
+fn test_intersects(a: &str, b: &str, include_prerelease: bool, answer: bool) {
+    let (a, b) = (
+        range_with(a, include_prerelease),
+        range_with(b, include_prerelease),
+    );
+    assert_eq!(intersects(&a, &b, include_prerelease), answer);
+    assert_eq!(intersects(&b, &a, include_prerelease), answer);
+}
+
+#[test_case("~1.2.3", "^1.2.0", false, true)]
+#[test_case("^1.2.0", "~1.2.3", false, false)]
+#[test_case("~1.2.3", "1.2.3", false, false)]
+#[test_case("1.2.3", "1.2.3 || 2.x", false, true)]
+#[test_case("1.2.x || 1.3.x", ">=1.2.0 <1.4.0", false, true)]
+#[test_case(">=1.2.0 <1.4.0", "1.2.x || 1.3.x", false, true ; "covered by a union")]
+#[test_case(">=1.2.0 <1.4.0", "1.2.x || 1.3.1 - 1.3.9", false, false ; "gap in a union")]
+#[test_case("^1.2.3", "^1.2.3-beta", false, true)]
+#[test_case("^1.2.3-beta", "^1.2.3", false, false ; "prereleases not in superset")]
+#[test_case("^1.2.3-beta", "^1.2.3", true, false ; "prereleases not in superset with include_prerelease")]
+#[test_case(">=1.0.0 <2.0.0", "1.x", false, true)]
+#[test_case(">=1.0.0 <2.0.0", "1.x", true, false ; "prereleases of next major with include_prerelease")]
+#[test_case("<1.0.0", ">=0.0.0 <1.0.0", false, true)]
+fn test_is_subset(sub: &str, dom: &str, include_prerelease: bool, answer: bool) {
+    assert_eq!(
+        is_subset(
+            &range_with(sub, include_prerelease),
+            &range_with(dom, include_prerelease),
+            include_prerelease
+        ),
+        answer
+    )
+}
+
+fn range_of(s: &str) -> VersionConstraint {
+    parse_range(s).unwrap()
+}
+
+fn range_with(s: &str, include_prerelease: bool) -> VersionConstraint {
+    parse_range_with_options(s, include_prerelease).unwrap()
+}
+
+fn version(s: &str) -> Semver {
+    parse_semver(s).unwrap()
+}
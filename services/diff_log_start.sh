@@ -0,0 +1,3 @@
+#!/bin/bash
+cd /zfs-raidz1/federico/npm-follower
+cargo run --release --bin diff_log_builder -- --daemon
@@ -1,3 +1,3 @@
 #!/bin/bash
 cd /zfs-raidz1/federico/npm-follower
-cargo run --release --bin relational_db_builder
+cargo run --release --bin relational_db_builder -- --daemon
@@ -1,96 +1,186 @@
 use std::sync::Arc;
+use std::time::Instant;
 
 use blob_idx_server::{
     errors::{BlobError, ClientError},
     http::{JobType, SubmitJobRequest},
 };
+use metrics_logging::{EventKind, MetricsEvent, MetricsLogger, MetricsLoggerTrait};
+use pipeline_stage::{PageSize, PageStats, PipelineStage, Runner, SessionStart, StageError};
 use postgres_db::{
-    connection::DbConnection,
+    connection::{DbConnection, DbConnectionInTransaction},
     download_tarball::{self, DownloadedTarball},
     internal_state,
 };
 use tokio::sync::{mpsc, Mutex};
+use tracing::Instrument;
 
 const PAGE_SIZE: i64 = 256;
 
+/// The component name of the transfer workers' metrics. The queuing of pages is logged as
+/// `tarball_transfer`.
+const WORKER_COMPONENT: &str = "tarball_transfer_workers";
+
 #[tokio::main]
 async fn main() {
+    let _tracing = utils::init_tracing("tarball_transfer");
     utils::check_no_concurrent_processes("tarball_transfer");
     dotenvy::dotenv().ok();
     let mut conn = DbConnection::connect();
 
-    let args = std::env::args().collect::<Vec<_>>();
+    let mut args = std::env::args().collect::<Vec<_>>();
+    let dry_run = pipeline_stage::take_flag(&mut args, "--dry-run");
     if args.len() != 2 {
-        panic!("Usage: tarball_transfer <num_workers>");
+        panic!("Usage: tarball_transfer [--dry-run] <num_workers>");
     }
     let num_workers = args[1].parse::<usize>().unwrap();
+    let metrics_logger = MetricsLogger::from_env_or_exit();
 
     let mut workers = Vec::new();
     let (tb_tx, tb_rx) = mpsc::channel(num_workers);
     let (db_tx, db_rx) = mpsc::channel(num_workers);
     let tb_rx = Arc::new(tokio::sync::Mutex::new(tb_rx));
     for id in 0..num_workers {
-        let spawned = spawn_transfer_worker(tb_rx.clone(), db_tx.clone(), id);
+        let spawned =
+            spawn_transfer_worker(tb_rx.clone(), db_tx.clone(), id, metrics_logger.clone());
         workers.push(spawned);
     }
 
     let db_worker_conn = DbConnection::connect(); // double the connections, double the fun
     let db_worker = spawn_db_worker(db_rx, db_worker_conn);
 
-    let mut first_ever_tb = Some(
-        download_tarball::query_first_tarball_by_url(&mut conn)
-            .expect("Error querying first tarball by url"),
-    );
-    let (mut last_url, mut queued_up_to) = internal_state::query_tarball_transfer_last(&mut conn)
-        .unwrap_or((first_ever_tb.as_ref().unwrap().tarball_url.to_string(), 0));
+    // the runner blocks, so it runs on its own thread and hands pages to the workers through tb_tx
+    tokio::task::spawn_blocking(move || {
+        let mut stage = TarballTransferStage {
+            tb_tx,
+            first_ever_tb: None,
+            num_tarballs_total: 0,
+            dry_run,
+        };
+        Runner::new(PageSize::Fixed(PAGE_SIZE), metrics_logger)
+            .dry_run(dry_run)
+            .stop_on_signals()
+            .run(&mut stage, &mut conn);
+    })
+    .await
+    .unwrap();
 
-    let num_tarballs_total = internal_state::query_queued_downloads_seq(&mut conn).unwrap_or(0);
-    let mut _num_tarballs_so_far = 0;
+    // the stage dropped tb_tx, so closing db_tx signals the workers to exit. wait for them to exit
+    drop(db_tx);
+    for worker in workers {
+        worker.await.unwrap();
+    }
+    db_worker.await.unwrap();
+}
 
-    loop {
-        println!(
-            "Fetching seq > {}, starting url = {}, page size = {} ({:.1}%)",
-            queued_up_to,
-            last_url,
-            PAGE_SIZE,
-            100.0 * (queued_up_to as f64) / (num_tarballs_total as f64)
-        );
-        let mut tarballs =
-            download_tarball::query_tarballs_after_url(&mut conn, &last_url, PAGE_SIZE);
-        if queued_up_to == 0 {
+struct TarballTransferStage {
+    tb_tx: mpsc::Sender<Vec<DownloadedTarball>>,
+    /// Pages are queried after a url, so the very first tarball has to be added to the first page.
+    first_ever_tb: Option<DownloadedTarball>,
+    num_tarballs_total: i64,
+    dry_run: bool,
+}
+
+impl PipelineStage for TarballTransferStage {
+    type Item = DownloadedTarball;
+    /// The last url queued, and the number of tarballs queued so far.
+    type Cursor = (String, i64);
+    type Output = ();
+
+    fn name(&self) -> &'static str {
+        "tarball_transfer"
+    }
+
+    fn load_cursor(&mut self, conn: &mut DbConnection) -> (String, i64) {
+        let first_ever_tb = download_tarball::query_first_tarball_by_url(conn)
+            .expect("Error querying first tarball by url");
+        let cursor = internal_state::query_tarball_transfer_last(conn)
+            .unwrap_or((first_ever_tb.tarball_url.to_string(), 0));
+        self.first_ever_tb = Some(first_ever_tb);
+        cursor
+    }
+
+    fn save_cursor(&mut self, conn: &mut DbConnectionInTransaction, cursor: &(String, i64)) {
+        let (last_url, queued_up_to) = cursor;
+        internal_state::set_tarball_transfer_last(last_url.clone(), *queued_up_to, conn);
+    }
+
+    fn query_page(
+        &mut self,
+        conn: &mut DbConnection,
+        cursor: &(String, i64),
+        page_size: i64,
+    ) -> Vec<DownloadedTarball> {
+        let (last_url, queued_up_to) = cursor;
+        let mut tarballs = download_tarball::query_tarballs_after_url(conn, last_url, page_size);
+        if *queued_up_to == 0 {
             // first time, add the first tarball
-            tarballs.insert(0, std::mem::take(&mut first_ever_tb).unwrap());
-        }
-        let num_tarballs = tarballs.len() as i64;
-        _num_tarballs_so_far += num_tarballs;
-        if num_tarballs == 0 {
-            break;
+            if let Some(first_ever_tb) = self.first_ever_tb.take() {
+                tarballs.insert(0, first_ever_tb);
+            }
         }
+        tarballs
+    }
 
-        let last_url_in_page = tarballs.last().unwrap().tarball_url.to_string();
+    fn advance(&self, cursor: &(String, i64), page: &[DownloadedTarball]) -> (String, i64) {
+        (
+            page.last().unwrap().tarball_url.to_string(),
+            cursor.1 + page.len() as i64,
+        )
+    }
 
-        tb_tx.send(tarballs).await.unwrap();
-        last_url = last_url_in_page;
-        queued_up_to += num_tarballs;
-        internal_state::set_tarball_transfer_last(last_url.clone(), queued_up_to, &mut conn);
+    fn process_page(
+        &mut self,
+        _conn: &mut DbConnectionInTransaction,
+        page: Vec<DownloadedTarball>,
+    ) -> Result<(), StageError> {
+        if self.dry_run {
+            tracing::info!(
+                num_tarballs = page.len(),
+                "Dry run, not transferring tarballs"
+            );
+        } else {
+            self.tb_tx
+                .blocking_send(page)
+                .expect("transfer workers exited");
+        }
+        Ok(())
     }
 
-    // close channels to signal workers to exit and wait for them to exit
-    drop(tb_tx);
-    drop(db_tx);
-    for worker in workers {
-        worker.await.unwrap();
+    fn start_session(
+        &mut self,
+        conn: &mut DbConnection,
+        _logger: &mut MetricsLogger,
+        _session: &SessionStart<(String, i64)>,
+    ) {
+        self.num_tarballs_total = internal_state::query_queued_downloads_seq(conn).unwrap_or(0);
+    }
+
+    fn page_completed(
+        &mut self,
+        _logger: &mut MetricsLogger,
+        page: &PageStats<(String, i64)>,
+        _output: &(),
+    ) {
+        let (last_url, queued_up_to) = &page.last;
+        tracing::info!(
+            url = %last_url,
+            "Queued tarballs ({:.1}%)",
+            100.0 * (*queued_up_to as f64) / (self.num_tarballs_total as f64)
+        );
     }
-    db_worker.await.unwrap();
 }
 
 pub fn spawn_transfer_worker(
     rx: Arc<Mutex<mpsc::Receiver<Vec<DownloadedTarball>>>>, // if we close this channel, the workers will exit
     db_tx: mpsc::Sender<Vec<(String, String)>>,
     worker_id: usize,
+    mut logger: MetricsLogger,
 ) -> tokio::task::JoinHandle<()> {
-    tokio::task::spawn(async move {
-        println!("Spawned transfer worker {}", worker_id);
+    let span = tracing::info_span!("transfer_worker", worker = worker_id);
+    tokio::task::spawn(
+        async move {
+        tracing::info!("Spawned transfer worker");
         let discovery_scp = std::env::var("DISCOVERY_SCP").expect("DISCOVERY_SCP not set");
         let blob_api_url = std::env::var("BLOB_API_URL").expect("BLOB_API_URL not set");
         let blob_api_key = std::env::var("BLOB_API_KEY").expect("BLOB_API_KEY not set");
@@ -108,7 +198,8 @@ pub fn spawn_transfer_worker(
                     match $e {
                         Ok(v) => v,
                         Err(e) => {
-                            eprintln!("[{}] Error: {}", worker_id, e);
+                            tracing::error!(error = %e, "Transfer failed, retrying");
+                            log_worker_error(&mut logger, e.to_string());
                             retry = true;
                             continue 'o;
                         }
@@ -123,17 +214,14 @@ pub fn spawn_transfer_worker(
                     match rx.recv().await {
                         Some(t) => t,
                         None => {
-                            println!("Transfer worker {} exiting", worker_id);
+                            tracing::info!("Transfer worker exiting");
                             return;
                         }
                     }
                 };
             }
-            println!(
-                "[{}] Got {} tarballs to transfer",
-                worker_id,
-                tarballs.len()
-            );
+            tracing::info!(num_tarballs = tarballs.len(), "Got tarballs to transfer");
+            let transfer_start = Instant::now();
 
             // firsly, move the files (locally) to a tmp dir
             let mut tmp_dir = std::env::temp_dir();
@@ -145,10 +233,7 @@ pub fn spawn_transfer_worker(
             let mut processed_tarballs = Vec::new();
             for tarball in &tarballs {
                 if tarball.tgz_local_path.is_none() {
-                    println!(
-                        "[{}] Tarball {} has no local path, skipping",
-                        worker_id, tarball.tarball_url
-                    );
+                    tracing::warn!(url = %tarball.tarball_url, "Tarball has no local path, skipping");
                     continue;
                 }
                 let local_path = tarball.tgz_local_path.as_ref().unwrap();
@@ -156,10 +241,7 @@ pub fn spawn_transfer_worker(
                 let local_path = std::path::PathBuf::from(local_path);
                 let filename = local_path.file_name();
                 if filename.is_none() {
-                    println!(
-                        "[{}] Tarball {} has no filename, skipping",
-                        worker_id, tarball.tarball_url
-                    );
+                    tracing::warn!(url = %tarball.tarball_url, "Tarball has no filename, skipping");
                     continue;
                 }
                 let filename = filename.unwrap().to_string_lossy().to_string();
@@ -183,7 +265,7 @@ pub fn spawn_transfer_worker(
                 remote_dir
             );
 
-            println!("[{}] Running command: {}", worker_id, cmd);
+            tracing::debug!(cmd = %cmd, "Running command");
             let output = unwrap_or_retry!(
                 tokio::process::Command::new("bash")
                     .arg("-c")
@@ -193,11 +275,14 @@ pub fn spawn_transfer_worker(
             );
 
             if !output.status.success() {
-                eprintln!(
-                    "[{}] Command failed with status {:?}: {}",
-                    worker_id,
-                    output.status.code(),
-                    String::from_utf8_lossy(&output.stderr)
+                tracing::error!(
+                    status = ?output.status.code(),
+                    stderr = %String::from_utf8_lossy(&output.stderr),
+                    "rsync failed, retrying"
+                );
+                log_worker_error(
+                    &mut logger,
+                    format!("rsync failed with status {:?}", output.status.code()),
                 );
                 retry = true;
                 continue;
@@ -226,7 +311,7 @@ pub fn spawn_transfer_worker(
                             // success
                             break;
                         }
-                        println!("[{}] Got response: {}", worker_id, txt);
+                        tracing::debug!(response = %txt, "Got response");
                         let obj: serde_json::Value = serde_json::from_str(&txt).unwrap();
                         let err: Result<ClientError, _> =
                             serde_json::from_value(obj["error"].clone());
@@ -248,25 +333,44 @@ pub fn spawn_transfer_worker(
                                 }
                             }
                             _ => {
-                                eprintln!("[{}] Response Error: {:?}", worker_id, txt);
+                                tracing::error!(response = %txt, "Storing tarballs failed, retrying");
+                                log_worker_error(&mut logger, txt);
                                 retry = true;
                                 continue 'o;
                             }
                         }
                     }
                     Err(e) => {
-                        eprintln!("[{}] Error sending request to job: {}", worker_id, e);
+                        tracing::error!(error = %e, "Error sending request to job, retrying");
+                        log_worker_error(&mut logger, e.to_string());
                         retry = true;
                         continue 'o;
                     }
                 }
             }
 
+            logger.log_event(
+                MetricsEvent::new(WORKER_COMPONENT, EventKind::Progress)
+                    .count("tarballs", processed_tarballs.len() as u64)
+                    .count(
+                        "skipped",
+                        (tarballs.len() - processed_tarballs.len()) as u64,
+                    )
+                    .duration("duration", transfer_start.elapsed()),
+            );
+
             if (db_tx.send(processed_tarballs).await).is_err() {
                 return;
             }
         }
-    })
+    }
+        .instrument(span),
+    )
+}
+
+fn log_worker_error(logger: &mut MetricsLogger, message: String) {
+    logger
+        .log_event(MetricsEvent::new(WORKER_COMPONENT, EventKind::Error).text("message", message));
 }
 
 pub fn spawn_db_worker(
@@ -274,17 +378,18 @@ pub fn spawn_db_worker(
     mut conn: DbConnection,
 ) -> tokio::task::JoinHandle<()> {
     tokio::task::spawn(async move {
-        println!("Spawned db worker");
+        tracing::info!("Spawned db worker");
         loop {
             let tarballs = match rx.recv().await {
                 Some(t) => t,
                 None => {
-                    println!("DB worker exiting");
+                    tracing::info!("DB worker exiting");
                     return;
                 }
             };
-            println!("Got {} tarballs to edit", tarballs.len());
+            tracing::info!(num_tarballs = tarballs.len(), "Got tarballs to edit");
             for (url, name) in tarballs {
+                tracing::debug!(url = %url, blob_key = %name, "Setting blob storage key");
                 download_tarball::set_blob_storage_key(&mut conn, &url, &name);
             }
         }
@@ -1,5 +1,9 @@
 use serde_json::{Map, Value};
 
+mod logging;
+
+pub use logging::{init_tracing, init_tracing_stderr, TracingGuard};
+
 pub fn check_no_concurrent_processes(name: &str) {
     use std::collections::HashSet;
     use std::process::Command;
@@ -33,6 +37,36 @@ pub fn check_no_concurrent_processes(name: &str) {
     }
 }
 
+/// Extracts the message from a panic payload caught by `catch_unwind`.
+pub fn panic_as_string(p: &Box<dyn std::any::Any + Send>) -> String {
+    fn swap<A, B>(x: Result<A, B>) -> Result<B, A> {
+        match x {
+            Ok(a) => Err(a),
+            Err(b) => Ok(b),
+        }
+    }
+
+    fn panic_as_string_result_err(p: &Box<dyn std::any::Any + Send>) -> Result<(), String> {
+        swap(
+            p.as_ref()
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .ok_or(p),
+        )?;
+
+        swap(
+            p.as_ref()
+                .downcast_ref::<String>()
+                .map(|s| s.to_string())
+                .ok_or(p),
+        )?;
+
+        Err("Unknown panic type".to_string())
+    }
+
+    panic_as_string_result_err(p).unwrap_err()
+}
+
 pub trait RemoveInto {
     fn remove_key<T>(&mut self, key: &'static str) -> Option<Result<T, serde_json::Error>>
     where
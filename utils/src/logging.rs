@@ -0,0 +1,79 @@
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Flushes the spans not yet exported to the OTLP collector when dropped.
+pub struct TracingGuard {
+    otlp: bool,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if self.otlp {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Sets up `tracing` for a binary, configured by these environment variables (or `.env`):
+/// - `RUST_LOG` filters what is logged, e.g. `info,postgres_db=debug`. The default is `info`.
+/// - `LOG_FORMAT` is `full` (the default), `pretty` (multi-line) or `json` (one object per line,
+///   with the fields of the current span and all its parents).
+/// - `ENABLE_OTLP_EXPORT=true` also exports spans to an OpenTelemetry collector, at
+///   `OTEL_EXPORTER_OTLP_ENDPOINT` (default `http://localhost:4318`, OTLP over HTTP).
+///
+/// Keep the returned guard until the end of `main`, so that the last spans get exported.
+pub fn init_tracing(service_name: &'static str) -> TracingGuard {
+    init(service_name, BoxMakeWriter::new(std::io::stdout))
+}
+
+/// Like `init_tracing`, but logs to stderr, for binaries whose stdout is their output.
+pub fn init_tracing_stderr(service_name: &'static str) -> TracingGuard {
+    init(service_name, BoxMakeWriter::new(std::io::stderr))
+}
+
+fn init(service_name: &'static str, writer: BoxMakeWriter) -> TracingGuard {
+    dotenv::dotenv().ok();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let fmt_layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    let fmt_layer = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => fmt_layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+        Ok("pretty") => fmt_layer.pretty().boxed(),
+        Err(_) | Ok("full") => fmt_layer.boxed(),
+        Ok(other) => panic!("invalid LOG_FORMAT: {}", other),
+    };
+
+    let otlp = std::env::var("ENABLE_OTLP_EXPORT")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    let otlp_layer = if otlp {
+        let resource = Resource::new(vec![KeyValue::new("service.name", service_name)]);
+        // the batches are exported from a thread of their own, so this works without a runtime
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().http())
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(opentelemetry::runtime::TokioCurrentThread)
+            .expect("failed to set up OTLP export");
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
+    } else {
+        None
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otlp_layer)
+        .init();
+
+    TracingGuard { otlp }
+}